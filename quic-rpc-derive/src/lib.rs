@@ -27,6 +27,7 @@ fn generate_rpc_impls(
     pat: &str,
     mut args: RpcArgs,
     service_name: &Ident,
+    method_name: &str,
     request_type: &Type,
     attr_span: Span,
 ) -> syn::Result<TokenStream2> {
@@ -41,12 +42,22 @@ fn generate_rpc_impls(
         }
         SERVER_STREAMING => {
             let response = args.get("response", pat, attr_span)?;
+            // optional path to a fn that returns the slow consumer policy
+            let slow_consumer = args.get_optional("slow_consumer").map(|policy| {
+                quote! {
+                    fn slow_consumer() -> ::quic_rpc::pattern::server_streaming::SlowConsumer<Self::Response> {
+                        #policy()
+                    }
+                }
+            });
             quote! {
                 impl ::quic_rpc::message::Msg<#service_name> for #request_type {
                     type Pattern = ::quic_rpc::pattern::server_streaming::ServerStreaming;
+                    const NAME: &'static str = #method_name;
                 }
                 impl ::quic_rpc::pattern::server_streaming::ServerStreamingMsg<#service_name> for #request_type {
                     type Response = #response;
+                    #slow_consumer
                 }
             }
         }
@@ -56,6 +67,7 @@ fn generate_rpc_impls(
             quote! {
                 impl ::quic_rpc::message::Msg<#service_name> for #request_type {
                     type Pattern = ::quic_rpc::pattern::bidi_streaming::BidiStreaming;
+                    const NAME: &'static str = #method_name;
                 }
                 impl ::quic_rpc::pattern::bidi_streaming::BidiStreamingMsg<#service_name> for #request_type {
                     type Update = #update;
@@ -69,6 +81,7 @@ fn generate_rpc_impls(
             quote! {
                 impl ::quic_rpc::message::Msg<#service_name> for #request_type {
                     type Pattern = ::quic_rpc::pattern::client_streaming::ClientStreaming;
+                    const NAME: &'static str = #method_name;
                 }
                 impl ::quic_rpc::pattern::client_streaming::ClientStreamingMsg<#service_name> for #request_type {
                     type Update = #update;
@@ -83,6 +96,7 @@ fn generate_rpc_impls(
             quote! {
                 impl ::quic_rpc::message::Msg<#service_name> for #request_type {
                     type Pattern = ::quic_rpc::pattern::try_server_streaming::TryServerStreaming;
+                    const NAME: &'static str = #method_name;
                 }
                 impl ::quic_rpc::pattern::try_server_streaming::TryServerStreamingMsg<#service_name> for #request_type {
                     type CreateError = #create_error;
@@ -161,7 +175,15 @@ pub fn rpc_requests(attr: TokenStream, item: TokenStream) -> TokenStream {
                 Err(e) => return e.to_compile_error().into(),
             };
 
-            match generate_rpc_impls(ident, args, &service_name, request_type, attr.span()) {
+            let method_name = format!("{}.{}", service_name, variant.ident);
+            match generate_rpc_impls(
+                ident,
+                args,
+                &service_name,
+                &method_name,
+                request_type,
+                attr.span(),
+            ) {
                 Ok(impls) => additional_items.extend(impls),
                 Err(e) => return e.to_compile_error().into(),
             }
@@ -177,6 +199,72 @@ pub fn rpc_requests(attr: TokenStream, item: TokenStream) -> TokenStream {
     output.into()
 }
 
+/// Generate `From` and `TryFrom` conversions between an enum and the types of its variants
+///
+/// Each variant must have exactly one unnamed field, and the field types must be
+/// unique. The error of the `TryFrom` conversion is the original enum value.
+///
+/// This replaces deriving `derive_more::From` and `derive_more::TryInto` on the
+/// request and response enums of a service.
+#[proc_macro_derive(RpcConversions)]
+pub fn rpc_conversions(item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as DeriveInput);
+    match generate_conversions(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn generate_conversions(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Enum(data_enum) = &input.data else {
+        return Err(syn::Error::new(
+            input.span(),
+            "RpcConversions can only be derived for enums",
+        ));
+    };
+    let enum_name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let mut types = HashSet::new();
+    let mut impls = Vec::new();
+    for variant in &data_enum.variants {
+        let ty = match &variant.fields {
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 => &fields.unnamed[0].ty,
+            _ => {
+                return Err(syn::Error::new(
+                    variant.span(),
+                    "Each variant must have exactly one unnamed field",
+                ))
+            }
+        };
+        if !types.insert(ty.to_token_stream().to_string()) {
+            return Err(syn::Error::new(
+                variant.span(),
+                "Each variant must have a unique type",
+            ));
+        }
+        let variant_name = &variant.ident;
+        impls.push(quote! {
+            impl #impl_generics ::std::convert::From<#ty> for #enum_name #ty_generics #where_clause {
+                fn from(value: #ty) -> Self {
+                    Self::#variant_name(value)
+                }
+            }
+            impl #impl_generics ::std::convert::TryFrom<#enum_name #ty_generics> for #ty #where_clause {
+                type Error = #enum_name #ty_generics;
+
+                fn try_from(value: #enum_name #ty_generics) -> ::std::result::Result<Self, Self::Error> {
+                    match value {
+                        #enum_name::#variant_name(value) => ::std::result::Result::Ok(value),
+                        #[allow(unreachable_patterns)]
+                        value => ::std::result::Result::Err(value),
+                    }
+                }
+            }
+        });
+    }
+    Ok(quote! { #(#impls)* })
+}
+
 struct RpcArgs {
     types: BTreeMap<String, Type>,
 }
@@ -189,6 +277,11 @@ impl RpcArgs {
             .ok_or_else(|| syn::Error::new(span, format!("{kind} requires a {key} type")))
     }
 
+    /// Get and remove a type from the map, if it exists
+    fn get_optional(&mut self, key: &str) -> Option<Type> {
+        self.types.remove(key)
+    }
+
     /// Fail if there are any unknown arguments remaining
     fn check_empty(&self, span: Span) -> syn::Result<()> {
         if self.types.is_empty() {
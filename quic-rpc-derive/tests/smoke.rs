@@ -12,6 +12,9 @@ fn simple() {
     #[derive(Debug, Serialize, Deserialize)]
     struct ClientStreamingRequest;
 
+    #[derive(Debug, Serialize, Deserialize)]
+    struct SlowRequest;
+
     #[derive(Debug, Serialize, Deserialize)]
     struct BidiStreamingRequest;
 
@@ -40,6 +43,8 @@ fn simple() {
         Rpc(RpcRequest),
         #[server_streaming(response=Response2)]
         ServerStreaming(ServerStreamingRequest),
+        #[server_streaming(response = Response2, slow_consumer = drop_oldest)]
+        Slow(SlowRequest),
         #[bidi_streaming(update= Update1, response = Response3)]
         BidiStreaming(BidiStreamingRequest),
         #[client_streaming(update = Update2, response = Response4)]
@@ -65,6 +70,83 @@ fn simple() {
     }
 
     let _ = Service;
+
+    fn drop_oldest() -> SlowConsumer<Response2> {
+        SlowConsumer::DropOldest {
+            buffer: 16,
+            lagged: |_| Response2,
+        }
+    }
+
+    use quic_rpc::pattern::server_streaming::{ServerStreamingMsg, SlowConsumer};
+    assert!(matches!(
+        <SlowRequest as ServerStreamingMsg<Service>>::slow_consumer(),
+        SlowConsumer::DropOldest { buffer: 16, .. }
+    ));
+    assert!(matches!(
+        <ServerStreamingRequest as ServerStreamingMsg<Service>>::slow_consumer(),
+        SlowConsumer::Block
+    ));
+
+    use quic_rpc::message::{method_name, Msg};
+    // rpc messages keep the default name, and fall back to the type name
+    assert_eq!(
+        method_name::<Service, RpcRequest>(),
+        std::any::type_name::<RpcRequest>()
+    );
+    assert_eq!(
+        method_name::<Service, ServerStreamingRequest>(),
+        "Service.ServerStreaming"
+    );
+    assert_eq!(
+        <BidiStreamingRequest as Msg<Service>>::NAME,
+        "Service.BidiStreaming"
+    );
+}
+
+#[test]
+fn conversions() {
+    use quic_rpc_derive::RpcConversions;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Ping(u64);
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Update;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Pong(u64);
+
+    #[rpc_requests(Service)]
+    #[derive(Debug, PartialEq, Serialize, Deserialize, RpcConversions)]
+    enum Request {
+        #[rpc(response = Pong)]
+        Ping(Ping),
+        Update(Update),
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize, RpcConversions)]
+    enum Response {
+        Pong(Pong),
+    }
+
+    #[derive(Debug, Clone)]
+    struct Service;
+
+    impl quic_rpc::Service for Service {
+        type Req = Request;
+        type Res = Response;
+    }
+
+    let _ = Service;
+
+    assert_eq!(Request::from(Ping(1)), Request::Ping(Ping(1)));
+    assert_eq!(Ping::try_from(Request::Ping(Ping(1))), Ok(Ping(1)));
+    assert_eq!(
+        Ping::try_from(Request::Update(Update)),
+        Err(Request::Update(Update))
+    );
+    assert_eq!(Pong::try_from(Response::from(Pong(2))), Ok(Pong(2)));
 }
 
 /// Use
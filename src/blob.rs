@@ -0,0 +1,175 @@
+//! A byte payload for bulk data.
+//!
+//! Streaming file contents or other large binary data as `Vec<u8>` is slow,
+//! since serde treats a `Vec<u8>` as a sequence and handles every byte on its
+//! own. [Blob] wraps [Bytes] and is serialized as a single byte string instead,
+//! which postcard writes with a single copy into the frame.
+//!
+//! With the memory transport, messages are not serialized at all, so a blob
+//! is passed through without copying the data.
+//!
+//! For postcard, a blob has the same encoding as a `Vec<u8>`, so a field can be
+//! changed from one to the other without breaking the wire protocol.
+//!
+//! # Example
+//!
+//! Use it as the item type of a streaming pattern:
+//! ```
+//! # use quic_rpc::{blob::Blob, message::{Msg, ServerStreaming, ServerStreamingMsg}, Service};
+//! # use serde::{Deserialize, Serialize};
+//! # #[derive(Debug, Clone)]
+//! # struct FileService;
+//! # #[derive(Debug, Serialize, Deserialize)]
+//! # struct Read(String);
+//! # impl From<Read> for Request { fn from(r: Read) -> Self { Request::Read(r) } }
+//! # impl TryFrom<Request> for Read { type Error = (); fn try_from(r: Request) -> Result<Self, ()> { let Request::Read(r) = r; Ok(r) } }
+//! # #[derive(Debug, Serialize, Deserialize)]
+//! # enum Request { Read(Read) }
+//! # #[derive(Debug, Serialize, Deserialize)]
+//! # enum Response { Chunk(Blob) }
+//! # impl From<Blob> for Response { fn from(b: Blob) -> Self { Response::Chunk(b) } }
+//! # impl TryFrom<Response> for Blob { type Error = (); fn try_from(r: Response) -> Result<Self, ()> { let Response::Chunk(b) = r; Ok(b) } }
+//! # impl Service for FileService { type Req = Request; type Res = Response; }
+//! impl Msg<FileService> for Read {
+//!     type Pattern = ServerStreaming;
+//! }
+//!
+//! impl ServerStreamingMsg<FileService> for Read {
+//!     type Response = Blob;
+//! }
+//! ```
+use std::{fmt, ops::Deref};
+
+use bytes::Bytes;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Binary data that is serialized as a byte string
+#[derive(Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Blob(Bytes);
+
+impl Blob {
+    /// Create a blob from bytes
+    pub fn new(data: impl Into<Bytes>) -> Self {
+        Self(data.into())
+    }
+
+    /// The data of the blob
+    pub fn into_bytes(self) -> Bytes {
+        self.0
+    }
+}
+
+impl fmt::Debug for Blob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Blob({} bytes)", self.0.len())
+    }
+}
+
+impl Deref for Blob {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for Blob {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<Bytes> for Blob {
+    fn from(data: Bytes) -> Self {
+        Self(data)
+    }
+}
+
+impl From<Vec<u8>> for Blob {
+    fn from(data: Vec<u8>) -> Self {
+        Self(data.into())
+    }
+}
+
+impl From<&'static [u8]> for Blob {
+    fn from(data: &'static [u8]) -> Self {
+        Self(Bytes::from_static(data))
+    }
+}
+
+impl From<Blob> for Bytes {
+    fn from(blob: Blob) -> Self {
+        blob.0
+    }
+}
+
+impl Serialize for Blob {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Blob {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_bytes(BlobVisitor)
+    }
+}
+
+struct BlobVisitor;
+
+impl<'de> de::Visitor<'de> for BlobVisitor {
+    type Value = Blob;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a byte string")
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Blob, E> {
+        Ok(Blob(Bytes::copy_from_slice(v)))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Blob, E> {
+        Ok(Blob(v.into()))
+    }
+
+    // self describing formats may encode bytes as a sequence
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Blob, A::Error> {
+        let mut data = Vec::with_capacity(seq.size_hint().unwrap_or_default().min(4096));
+        while let Some(byte) = seq.next_element()? {
+            data.push(byte);
+        }
+        Ok(Blob(data.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn postcard_encoding() {
+        let data = (0..=255u8).collect::<Vec<_>>();
+        let encoded = postcard::to_stdvec(&Blob::from(data.clone())).unwrap();
+        assert_eq!(encoded, postcard::to_stdvec(&data).unwrap());
+        let decoded: Blob = postcard::from_bytes(&encoded).unwrap();
+        assert_eq!(&*decoded, &data[..]);
+    }
+
+    #[cfg(feature = "flume-transport")]
+    #[tokio::test]
+    async fn memory_transport_does_not_copy() -> anyhow::Result<()> {
+        use futures_lite::StreamExt;
+        use futures_util::SinkExt;
+
+        use crate::transport::{flume, Connector, Listener};
+
+        let (listener, connector) = flume::channel::<Blob, Blob>(8);
+        let blob = Blob::from(vec![1u8; 1024 * 1024]);
+        let (mut send, _recv) = connector.open().await?;
+        send.send(blob.clone()).await?;
+        let (_send, mut recv) = listener.accept().await?;
+        let received = recv.next().await.transpose()?.expect("a blob");
+        assert_eq!(received.as_ptr(), blob.as_ptr());
+        Ok(())
+    }
+}
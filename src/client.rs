@@ -2,18 +2,25 @@
 //!
 //! The main entry point is [RpcClient].
 use std::{
-    fmt::Debug,
+    fmt::{self, Debug},
+    future::Future,
     marker::PhantomData,
     pin::Pin,
-    task::{Context, Poll},
+    task::{ready, Context, Poll},
+    time::{Duration, Instant},
 };
 
 use futures_lite::Stream;
 use futures_sink::Sink;
 use pin_project::pin_project;
+use tokio::sync::{oneshot, watch};
 
 use crate::{
-    transport::{boxed::BoxableConnector, mapped::MappedConnector, StreamTypes},
+    middleware::{ClientMiddleware, MiddlewareConnector},
+    transport::{
+        boxed::BoxableConnector, limit::LimitedConnector, mapped::MappedConnector,
+        ConnectionErrors, StreamTypes,
+    },
     Connector, Service,
 };
 
@@ -60,6 +67,8 @@ pub type BoxStreamSync<'a, T> = Pin<Box<dyn Stream<Item = T> + Send + Sync + 'a>
 #[derive(Debug)]
 pub struct RpcClient<S, C = BoxedConnector<S>> {
     pub(crate) source: C,
+    pub(crate) default_timeout: Option<Duration>,
+    pub(crate) retry: Option<RetryPolicy>,
     pub(crate) _p: PhantomData<S>,
 }
 
@@ -67,6 +76,57 @@ impl<S, C: Clone> Clone for RpcClient<S, C> {
     fn clone(&self) -> Self {
         Self {
             source: self.source.clone(),
+            default_timeout: self.default_timeout,
+            retry: self.retry,
+            _p: PhantomData,
+        }
+    }
+}
+
+/// Builder for a [RpcClient], see [RpcClient::builder]
+#[derive(Debug)]
+pub struct RpcClientBuilder<S, C> {
+    source: C,
+    default_timeout: Option<Duration>,
+    _p: PhantomData<S>,
+}
+
+impl<S, C> RpcClientBuilder<S, C>
+where
+    S: Service,
+    C: Connector<S>,
+{
+    /// Set a timeout for every call that has no timeout of its own
+    ///
+    /// The timeout applies to every call pattern:
+    ///
+    /// - [rpc](RpcClient::rpc), [multi_rpc](RpcClient::multi_rpc) and
+    ///   [notify](RpcClient::notify): the whole call.
+    /// - [try_server_streaming](RpcClient::try_server_streaming): opening the
+    ///   substream, sending the request and receiving the response that the
+    ///   stream was created.
+    /// - all other streaming patterns: opening the substream and sending the
+    ///   request.
+    ///
+    /// The items of a stream are never subject to the timeout. The `*_timeout`
+    /// methods of the client override it per call, and for the server streaming
+    /// and bidi patterns also cover the first response.
+    #[cfg(feature = "rt")]
+    #[cfg_attr(
+        quicrpc_docsrs,
+        doc(cfg(any(feature = "rt-tokio", feature = "rt-smol", feature = "rt-async-std")))
+    )]
+    pub fn default_timeout(mut self, timeout: Duration) -> Self {
+        self.default_timeout = Some(timeout);
+        self
+    }
+
+    /// Create the client
+    pub fn build(self) -> RpcClient<S, C> {
+        RpcClient {
+            source: self.source,
+            default_timeout: self.default_timeout,
+            retry: None,
             _p: PhantomData,
         }
     }
@@ -76,9 +136,111 @@ impl<S, C: Clone> Clone for RpcClient<S, C> {
 /// that support it, [crate::message::ClientStreaming] and [crate::message::BidiStreaming].
 #[pin_project]
 #[derive(Debug)]
-pub struct UpdateSink<C, T>(#[pin] pub C::SendSink, PhantomData<T>)
+pub struct UpdateSink<C, T>
 where
-    C: StreamTypes;
+    C: StreamTypes,
+{
+    /// The sink of the substream
+    #[pin]
+    pub sink: C::SendSink,
+    /// Acknowledgements from the server, if tracked
+    credits: Option<Credits>,
+    /// The response of a client streaming call, if it can reject updates
+    responded: Option<Responded<C::SendError>>,
+    /// Sent by [UpdateSink::finish], see [Msg::end_of_updates](crate::message::Msg::end_of_updates)
+    end: Option<C::Out>,
+    _p: PhantomData<T>,
+}
+
+/// Error when sending an update using an [UpdateSink]
+///
+/// The sink of a bidi streaming call only fails with [UpdateError::Send], since
+/// the server may respond while it still reads updates.
+#[derive(Debug)]
+pub enum UpdateError<C: ConnectionErrors> {
+    /// Unable to send the update
+    Send(C::SendError),
+    /// The server already sent its response, so it does not read any more updates
+    ///
+    /// This happens when the server rejects a client streaming call early, e.g.
+    /// because a quota is exceeded. The response has the details.
+    Rejected,
+}
+
+impl<C: ConnectionErrors> fmt::Display for UpdateError<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl<C: ConnectionErrors> std::error::Error for UpdateError<C> {}
+
+/// Acknowledgement state of an [UpdateSink]
+#[derive(Debug)]
+struct Credits {
+    sent: u64,
+    acked: watch::Receiver<u64>,
+}
+
+/// Whether the server sent the response of a client streaming call
+#[derive(Debug)]
+pub(crate) struct Responded<E> {
+    /// Fires when the response arrives, and is dropped if there is none
+    rx: Option<oneshot::Receiver<()>>,
+    rejected: bool,
+    /// The response is read by a task, so a failed send can wait for it
+    spawned: bool,
+    /// A send failed, and waits for the response to tell if it was rejected
+    failed: Option<E>,
+}
+
+impl<E> Responded<E> {
+    /// Track the response with the given receiver
+    ///
+    /// If the response is not read by a task, a failed send does not wait for
+    /// the response, since it may never be read.
+    pub(crate) fn new(rx: oneshot::Receiver<()>, spawned: bool) -> Self {
+        Self {
+            rx: Some(rx),
+            rejected: false,
+            spawned,
+            failed: None,
+        }
+    }
+
+    /// Check without waiting if the response arrived
+    fn is_rejected(&mut self) -> bool {
+        if let Some(rx) = &mut self.rx {
+            match rx.try_recv() {
+                Ok(()) => {
+                    self.rejected = true;
+                    self.rx = None;
+                }
+                Err(oneshot::error::TryRecvError::Closed) => self.rx = None,
+                Err(oneshot::error::TryRecvError::Empty) => {}
+            }
+        }
+        self.rejected
+    }
+
+    /// Wait for the response after a failed send
+    ///
+    /// Returns `None` if the send failed because the server responded, and the
+    /// cause otherwise.
+    fn poll_failed(&mut self, cx: &mut Context<'_>) -> Poll<Option<E>> {
+        match &mut self.rx {
+            Some(rx) if self.spawned => {
+                self.rejected = ready!(Pin::new(rx).poll(cx)).is_ok();
+                self.rx = None;
+            }
+            _ => {
+                self.is_rejected();
+            }
+        }
+        let cause = self.failed.take();
+        Poll::Ready(if self.rejected { None } else { cause })
+    }
+}
 
 impl<C, T> UpdateSink<C, T>
 where
@@ -87,32 +249,398 @@ where
 {
     /// Create a new update sink
     pub fn new(sink: C::SendSink) -> Self {
-        Self(sink, PhantomData)
+        Self {
+            sink,
+            credits: None,
+            responded: None,
+            end: None,
+            _p: PhantomData,
+        }
+    }
+
+    /// Create a new update sink that tracks acknowledgements from the server
+    #[cfg(feature = "rt")]
+    pub(crate) fn with_acks(sink: C::SendSink, acked: watch::Receiver<u64>) -> Self {
+        Self {
+            credits: Some(Credits { sent: 0, acked }),
+            ..Self::new(sink)
+        }
+    }
+
+    /// Send the given update in [UpdateSink::finish], see [Msg::end_of_updates](crate::message::Msg::end_of_updates)
+    pub(crate) fn end_with(mut self, end: Option<C::Out>) -> Self {
+        self.end = end;
+        self
+    }
+
+    /// Fail with [UpdateError::Rejected] once the response of a client streaming
+    /// call arrives
+    pub(crate) fn rejected_by(mut self, responded: Responded<C::SendError>) -> Self {
+        self.responded = Some(responded);
+        self
+    }
+
+    /// Number of updates the server acknowledged as processed
+    ///
+    /// This is `None` unless the sink was created by
+    /// [RpcClient::client_streaming_acked].
+    pub fn acked(&self) -> Option<u64> {
+        self.credits.as_ref().map(|credits| *credits.acked.borrow())
+    }
+
+    /// Number of updates that were sent but not yet acknowledged
+    ///
+    /// This is `None` unless the sink was created by
+    /// [RpcClient::client_streaming_acked].
+    pub fn unacked(&self) -> Option<u64> {
+        self.credits
+            .as_ref()
+            .map(|credits| credits.sent.saturating_sub(*credits.acked.borrow()))
+    }
+
+    /// Wait until the server acknowledged at least `n` updates
+    ///
+    /// Returns the number of acknowledged updates, or `None` if the sink does not
+    /// track acknowledgements or the server will not send any more of them.
+    pub async fn wait_acked(&mut self, n: u64) -> Option<u64> {
+        let credits = self.credits.as_mut()?;
+        let acked = credits.acked.wait_for(|acked| *acked >= n).await.ok()?;
+        Some(*acked)
+    }
+
+    /// Finish sending updates
+    ///
+    /// This flushes the pending updates and closes the send side of the
+    /// substream, so the update stream on the server side ends with `None` after
+    /// the last update. Unlike dropping the sink, this waits until the updates are
+    /// handed to the transport, and returns the error if that fails.
+    ///
+    /// If the message has an [end of updates](crate::message::Msg::end_of_updates)
+    /// marker, it is sent first, so the server can tell this from a dropped sink.
+    pub async fn finish(mut self) -> Result<(), UpdateError<C>> {
+        if let Some(end) = self.end.take() {
+            futures_util::future::poll_fn(|cx| Pin::new(&mut self).poll_ready(cx)).await?;
+            Pin::new(&mut self).start_send_out(end)?;
+        }
+        futures_util::SinkExt::close(&mut self).await
+    }
+
+    /// Only flush the sink according to the given policy
+    ///
+    /// See [AutoFlush] for details.
+    pub fn auto_flush(self, policy: FlushPolicy) -> AutoFlush<Self> {
+        AutoFlush::new(self, policy)
+    }
+}
+
+/// When and how often to retry an idempotent call, see [RpcClient::rpc_retry]
+///
+/// The delay before a retry starts at `initial` and doubles with every failed
+/// attempt, up to `max`. A random jitter of up to half the delay is subtracted, so
+/// that many clients that failed at the same time do not retry at the same time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Maximum number of retries after the first attempt
+    pub max_retries: u32,
+    /// Delay before the first retry
+    pub initial: Duration,
+    /// Maximum delay
+    pub max: Duration,
+    /// Also retry when the server closed the substream without a response
+    ///
+    /// The server may have handled the request in this case, which is fine for
+    /// idempotent requests, but may still be unwanted for expensive ones.
+    pub retry_early_close: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(10),
+            retry_early_close: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The delay before retry number `retry`, starting at 1, without jitter
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 1u32 << retry.saturating_sub(1).min(31);
+        self.initial.saturating_mul(factor).min(self.max)
+    }
+
+    /// The delay before retry number `retry`, with jitter
+    #[cfg(feature = "rt")]
+    pub(crate) fn delay(&self, retry: u32) -> Duration {
+        use std::hash::{BuildHasher, Hasher};
+
+        let backoff = self.backoff(retry);
+        // RandomState is randomly seeded, which is good enough for jitter
+        let random = std::collections::hash_map::RandomState::new()
+            .build_hasher()
+            .finish();
+        let jitter = (random % 1024) as f64 / 1024.0 * 0.5;
+        backoff.mul_f64(1.0 - jitter)
+    }
+}
+
+/// When to flush an [AutoFlush] sink
+///
+/// The default policy flushes on every flush call, just like the inner sink.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FlushPolicy {
+    max_items: Option<usize>,
+    max_delay: Option<Duration>,
+}
+
+impl FlushPolicy {
+    /// Flush once this many items have been sent since the last flush
+    pub fn max_items(mut self, max_items: usize) -> Self {
+        self.max_items = Some(max_items);
+        self
+    }
+
+    /// Flush once the oldest unflushed item is older than this
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = Some(max_delay);
+        self
+    }
+}
+
+/// A sink that decouples flushing from sending
+///
+/// A flush of this sink, e.g. as part of [send](futures_util::SinkExt::send), only
+/// flushes the inner sink if the [FlushPolicy] says so. Otherwise the items stay
+/// buffered in the inner sink. Closing the sink always flushes.
+///
+/// With a runtime feature, a timer is started when the first item is buffered.
+/// When it fires, the task that last used the sink is woken, and the next
+/// [poll_ready](Sink::poll_ready) or [poll_flush](Sink::poll_flush) flushes the
+/// inner sink. Without a runtime, the delay is only checked when the sink is used.
+#[pin_project]
+pub struct AutoFlush<S> {
+    #[pin]
+    inner: S,
+    policy: FlushPolicy,
+    unflushed: usize,
+    oldest: Option<Instant>,
+    #[cfg(feature = "rt")]
+    timer: Option<Pin<Box<dyn Future<Output = ()> + Send + Sync>>>,
+}
+
+impl<S: fmt::Debug> fmt::Debug for AutoFlush<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AutoFlush")
+            .field("inner", &self.inner)
+            .field("policy", &self.policy)
+            .field("unflushed", &self.unflushed)
+            .field("oldest", &self.oldest)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S> AutoFlush<S> {
+    /// Wrap a sink with the given flush policy
+    pub fn new(inner: S, policy: FlushPolicy) -> Self {
+        Self {
+            inner,
+            policy,
+            unflushed: 0,
+            oldest: None,
+            #[cfg(feature = "rt")]
+            timer: None,
+        }
+    }
+
+    /// Number of items sent since the last flush of the inner sink
+    pub fn unflushed(&self) -> usize {
+        self.unflushed
+    }
+
+    /// Get the inner sink
+    ///
+    /// Items that have not been flushed yet stay buffered in the inner sink.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Whether the oldest unflushed item is older than the maximum delay
+    ///
+    /// With a runtime, this polls the timer, so the task is woken once it fires.
+    fn delay_elapsed(self: Pin<&mut Self>, cx: &mut Context<'_>) -> bool {
+        let this = self.project();
+        #[cfg(feature = "rt")]
+        if let Some(timer) = this.timer {
+            return timer.as_mut().poll(cx).is_ready();
+        }
+        let _ = cx;
+        this.policy
+            .max_delay
+            .zip(*this.oldest)
+            .is_some_and(|(delay, oldest)| oldest.elapsed() >= delay)
+    }
+
+    fn flush_inner<T>(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>>
+    where
+        S: Sink<T>,
+    {
+        let this = self.project();
+        let res = this.inner.poll_flush(cx);
+        if res.is_ready() {
+            *this.unflushed = 0;
+            *this.oldest = None;
+            #[cfg(feature = "rt")]
+            {
+                *this.timer = None;
+            }
+        }
+        res
     }
 }
 
+impl<S: Sink<T>, T> Sink<T> for AutoFlush<S> {
+    type Error = S::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if self.as_mut().delay_elapsed(cx) {
+            ready!(self.as_mut().flush_inner(cx))?;
+        }
+        self.project().inner.poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        let this = self.project();
+        *this.unflushed += 1;
+        if this.oldest.is_none() {
+            *this.oldest = Some(Instant::now());
+            #[cfg(feature = "rt")]
+            if let Some(delay) = this.policy.max_delay {
+                *this.timer = Some(Box::pin(crate::rt::sleep(delay)));
+            }
+        }
+        this.inner.start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let due = match (self.policy.max_items, self.policy.max_delay) {
+            (None, None) => true,
+            (max_items, _) => {
+                max_items.is_some_and(|n| self.unflushed >= n) || self.as_mut().delay_elapsed(cx)
+            }
+        };
+        if !due {
+            return Poll::Ready(Ok(()));
+        }
+        self.flush_inner(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_close(cx)
+    }
+}
+
+impl<C: StreamTypes, T> UpdateSink<C, T> {
+    /// Fail if a send is still waiting for the response, or, if `before_send` is
+    /// set, if the server responded
+    fn poll_rejected(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        before_send: bool,
+    ) -> Poll<Result<(), UpdateError<C>>> {
+        let Some(responded) = self.project().responded else {
+            return Poll::Ready(Ok(()));
+        };
+        if responded.failed.is_some() {
+            return responded.poll_failed(cx).map(|cause| Err(error(cause)));
+        }
+        if before_send && responded.is_rejected() {
+            return Poll::Ready(Err(UpdateError::Rejected));
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Map the result of the inner sink, waiting for the response if it failed
+    ///
+    /// When the server responds early, it stops reading updates, so sending them
+    /// fails. In that case the send fails with [UpdateError::Rejected].
+    fn poll_map(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        res: Result<(), C::SendError>,
+    ) -> Poll<Result<(), UpdateError<C>>> {
+        let cause = match res {
+            Ok(()) => return Poll::Ready(Ok(())),
+            Err(cause) => cause,
+        };
+        match self.project().responded {
+            Some(responded) => {
+                responded.failed = Some(cause);
+                responded.poll_failed(cx).map(|cause| Err(error(cause)))
+            }
+            None => Poll::Ready(Err(UpdateError::Send(cause))),
+        }
+    }
+
+    /// Send an item on the inner sink, failing if the server responded
+    fn start_send_out(self: Pin<&mut Self>, item: C::Out) -> Result<(), UpdateError<C>> {
+        let this = self.project();
+        if let Some(responded) = this.responded {
+            if responded.is_rejected() {
+                return Err(UpdateError::Rejected);
+            }
+        }
+        match this.sink.start_send(item) {
+            Ok(()) => Ok(()),
+            // the next flush waits for the response to tell why the send failed
+            Err(cause) => match this.responded {
+                Some(responded) => {
+                    responded.failed = Some(cause);
+                    Ok(())
+                }
+                None => Err(UpdateError::Send(cause)),
+            },
+        }
+    }
+}
+
+/// [UpdateError::Send] with the cause, or [UpdateError::Rejected] without one
+fn error<C: ConnectionErrors>(cause: Option<C::SendError>) -> UpdateError<C> {
+    cause.map_or(UpdateError::Rejected, UpdateError::Send)
+}
+
 impl<C, T> Sink<T> for UpdateSink<C, T>
 where
     C: StreamTypes,
     T: Into<C::Out>,
 {
-    type Error = C::SendError;
+    type Error = UpdateError<C>;
 
-    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.project().0.poll_ready(cx)
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        ready!(self.as_mut().poll_rejected(cx, true))?;
+        let res = ready!(self.as_mut().project().sink.poll_ready(cx));
+        self.poll_map(cx, res)
     }
 
-    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
-        let req = item.into();
-        self.project().0.start_send(req)
+    fn start_send(mut self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        self.as_mut().start_send_out(item.into())?;
+        if let Some(credits) = self.project().credits {
+            credits.sent += 1;
+        }
+        Ok(())
     }
 
-    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.project().0.poll_flush(cx)
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        ready!(self.as_mut().poll_rejected(cx, false))?;
+        let res = ready!(self.as_mut().project().sink.poll_flush(cx));
+        self.poll_map(cx, res)
     }
 
-    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.project().0.poll_close(cx)
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        ready!(self.as_mut().poll_rejected(cx, false))?;
+        let res = ready!(self.as_mut().project().sink.poll_close(cx));
+        self.poll_map(cx, res)
     }
 }
 
@@ -128,8 +656,14 @@ where
     ///
     /// You can get a client for a nested service by calling [map](RpcClient::map).
     pub fn new(source: C) -> Self {
-        Self {
+        Self::builder(source).build()
+    }
+
+    /// Create a builder for a rpc client, to configure e.g. a default timeout
+    pub fn builder(source: C) -> RpcClientBuilder<S, C> {
+        RpcClientBuilder {
             source,
+            default_timeout: None,
             _p: PhantomData,
         }
     }
@@ -160,7 +694,12 @@ where
         S::Req: From<SNext::Req>,
         SNext::Res: TryFrom<S::Res>,
     {
-        RpcClient::new(self.source.map::<SNext::Res, SNext::Req>())
+        RpcClient {
+            source: self.source.map::<SNext::Res, SNext::Req>(),
+            default_timeout: self.default_timeout,
+            retry: self.retry,
+            _p: PhantomData,
+        }
     }
 
     /// box
@@ -168,7 +707,80 @@ where
     where
         C: BoxableConnector<S::Res, S::Req>,
     {
-        RpcClient::new(self.source.boxed())
+        RpcClient {
+            source: self.source.boxed(),
+            default_timeout: self.default_timeout,
+            retry: self.retry,
+            _p: PhantomData,
+        }
+    }
+
+    /// Install a middleware that is called for every message of this client
+    ///
+    /// See [middleware](crate::middleware) for details.
+    pub fn with_middleware(
+        self,
+        middleware: impl ClientMiddleware<S>,
+    ) -> RpcClient<S, MiddlewareConnector<S, C>> {
+        RpcClient {
+            source: MiddlewareConnector::new(self.source, middleware),
+            default_timeout: self.default_timeout,
+            retry: self.retry,
+            _p: PhantomData,
+        }
+    }
+
+    /// Limit the number of calls that have a substream open at the same time
+    ///
+    /// Further calls wait until a call of this client or one of its clones is done.
+    /// If `max_queued` is set and that many calls already wait, calls fail with
+    /// [OpenError::Overloaded](crate::transport::limit::OpenError::Overloaded)
+    /// instead, see [LimitedConnector::with_max_queued].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the limit is zero.
+    pub fn with_concurrency_limit(
+        self,
+        limit: usize,
+        max_queued: Option<usize>,
+    ) -> RpcClient<S, LimitedConnector<C>> {
+        let mut source = LimitedConnector::new(self.source, limit);
+        if let Some(max_queued) = max_queued {
+            source = source.with_max_queued(max_queued);
+        }
+        RpcClient {
+            source,
+            default_timeout: self.default_timeout,
+            retry: self.retry,
+            _p: PhantomData,
+        }
+    }
+
+    /// Retry idempotent calls with the given policy, see [RpcClient::rpc_retry]
+    #[cfg(feature = "rt")]
+    #[cfg_attr(
+        quicrpc_docsrs,
+        doc(cfg(any(feature = "rt-tokio", feature = "rt-smol", feature = "rt-async-std")))
+    )]
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Run a call with the given timeout, or else the default timeout
+    ///
+    /// Returns `None` if the timeout elapsed first.
+    pub(crate) async fn timed<F: Future>(
+        &self,
+        timeout: Option<Duration>,
+        future: F,
+    ) -> Option<F::Output> {
+        match timeout.or(self.default_timeout) {
+            #[cfg(feature = "rt")]
+            Some(timeout) => crate::rt::timeout(timeout, future).await,
+            _ => Some(future.await),
+        }
     }
 }
 
@@ -182,6 +794,167 @@ where
     }
 }
 
+type OpenResult<C> = Result<
+    (<C as StreamTypes>::SendSink, <C as StreamTypes>::RecvStream),
+    <C as ConnectionErrors>::OpenError,
+>;
+
+/// A poll based client, for custom event loops and FFI layers
+///
+/// This is a lower level alternative to [RpcClient] that does not require async
+/// functions. Channels are opened using [PollClient::poll_open], and carry the
+/// raw request and response types of the service, so it is up to the caller to
+/// follow the interaction pattern of the first request.
+///
+/// Create it using [RpcClient::into_poll]. `F` is the future that opens a channel
+/// on the connector. It is stored in the client, so the client has to be pinned
+/// to open channels, and neither opening nor sending and receiving on a channel
+/// allocates.
+#[pin_project]
+pub struct PollClient<S: Service, C: Connector<S>, F> {
+    source: C,
+    open: fn(C) -> F,
+    #[pin]
+    opening: Option<F>,
+    _p: PhantomData<S>,
+}
+
+impl<S: Service, C: Connector<S>, F> fmt::Debug for PollClient<S, C, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PollClient")
+            .field("source", &self.source)
+            .field("opening", &self.opening.is_some())
+            .finish()
+    }
+}
+
+impl<S, C, F> PollClient<S, C, F>
+where
+    S: Service,
+    C: Connector<S>,
+    F: Future<Output = OpenResult<C>>,
+{
+    /// Poll for a new channel
+    ///
+    /// Only one channel can be opened at a time. Once this returns
+    /// [Poll::Ready], the next call starts opening another channel.
+    pub fn poll_open(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<PollChannel<S, C>, C::OpenError>> {
+        let mut this = self.project();
+        if this.opening.is_none() {
+            let open = (this.open)(this.source.clone());
+            this.opening.set(Some(open));
+        }
+        let opening = this.opening.as_mut().as_pin_mut().expect("set above");
+        let res = ready!(opening.poll(cx));
+        this.opening.set(None);
+        Poll::Ready(res.map(|(send, recv)| PollChannel {
+            send: Some(send),
+            recv,
+            _p: PhantomData,
+        }))
+    }
+
+    /// Get the underlying connector
+    pub fn into_inner(self) -> C {
+        self.source
+    }
+}
+
+impl<S: Service, C: Connector<S>> RpcClient<S, C> {
+    /// Convert into a poll based client, see [PollClient]
+    pub fn into_poll(self) -> PollClient<S, C, impl Future<Output = OpenResult<C>> + Send> {
+        PollClient {
+            source: self.source,
+            open: |source: C| async move { source.open().await },
+            opening: None,
+            _p: PhantomData,
+        }
+    }
+}
+
+/// A channel opened by [PollClient::poll_open]
+pub struct PollChannel<S: Service, C: Connector<S>> {
+    send: Option<C::SendSink>,
+    recv: C::RecvStream,
+    _p: PhantomData<S>,
+}
+
+impl<S: Service, C: Connector<S>> fmt::Debug for PollChannel<S, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PollChannel")
+            .field("closed", &self.send.is_none())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S: Service, C: Connector<S>> PollChannel<S, C> {
+    fn send(&mut self) -> Pin<&mut C::SendSink> {
+        Pin::new(
+            self.send
+                .as_mut()
+                .expect("send side of the channel is closed"),
+        )
+    }
+
+    /// Poll to send a message and flush it
+    ///
+    /// The message is taken out of `msg` as soon as the channel accepts it. Keep
+    /// calling this until it returns [Poll::Ready], which means that the message
+    /// was flushed. With `None`, this only flushes the channel.
+    ///
+    /// # Panics
+    ///
+    /// If the send side was closed using [PollChannel::poll_close].
+    pub fn poll_send(
+        &mut self,
+        cx: &mut Context<'_>,
+        msg: &mut Option<S::Req>,
+    ) -> Poll<Result<(), C::SendError>> {
+        let mut send = self.send();
+        if msg.is_some() {
+            ready!(send.as_mut().poll_ready(cx))?;
+            if let Some(msg) = msg.take() {
+                send.as_mut().start_send(msg)?;
+            }
+        }
+        send.poll_flush(cx)
+    }
+
+    /// Poll to receive the next message
+    ///
+    /// Returns `None` when the server closed the channel.
+    pub fn poll_recv(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<S::Res, C::RecvError>>> {
+        Pin::new(&mut self.recv).poll_next(cx)
+    }
+
+    /// Poll to close the send side of the channel
+    ///
+    /// This tells the server that there are no more updates, e.g. to finish a
+    /// client streaming request. The receive side stays open.
+    pub fn poll_close(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), C::SendError>> {
+        if self.send.is_none() {
+            return Poll::Ready(Ok(()));
+        }
+        let res = ready!(self.send().poll_close(cx));
+        // some transports only signal the end of the stream when the sink is dropped
+        self.send = None;
+        Poll::Ready(res)
+    }
+
+    /// Get the underlying send sink and receive stream
+    ///
+    /// The send sink is `None` if it was closed using [PollChannel::poll_close].
+    pub fn into_inner(self) -> (Option<C::SendSink>, C::RecvStream) {
+        (self.send, self.recv)
+    }
+}
+
 /// Wrap a stream with an additional item that is kept alive until the stream is dropped
 #[pin_project]
 pub(crate) struct DeferDrop<S: Stream, X>(#[pin] pub S, pub X);
@@ -193,3 +966,17 @@ impl<S: Stream, X> Stream for DeferDrop<S, X> {
         self.project().0.poll_next(cx)
     }
 }
+
+/// A receive stream with a response that was already received put back in front
+pub(crate) type Peeked<R> = futures_lite::stream::Chain<
+    futures_lite::stream::Iter<std::option::IntoIter<<R as Stream>::Item>>,
+    R,
+>;
+
+/// Wait for the first response if `wait` is set, and put it back in front
+pub(crate) async fn first_response<R: Stream + Unpin>(mut recv: R, wait: bool) -> Peeked<R> {
+    use futures_lite::StreamExt;
+
+    let first = if wait { recv.next().await } else { None };
+    futures_lite::stream::iter(first).chain(recv)
+}
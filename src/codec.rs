@@ -0,0 +1,133 @@
+//! Encoding of messages for the QUIC transports.
+//!
+//! The [quinn](crate::transport::quinn) and [iroh](crate::transport::iroh)
+//! transports send every message as a length prefixed frame, see [Framing],
+//! and datagrams as they are. The content of frames and datagrams is produced
+//! by a [Codec].
+//!
+//! The default is [PostcardCodec], which is compact and fast, but not widely
+//! implemented outside of rust. To talk to peers written in other languages,
+//! implement [Codec] for a format they understand, and select it with
+//! `with_codec` on both the listener and the connector. Both sides of a
+//! connection have to use the same codec.
+//!
+//! # Changing the codec of a running system
+//!
+//! There is no handshake frame to negotiate the codec, because QUIC already
+//! negotiates the application protocol during the TLS handshake, using ALPN.
+//! Give the new codec or protocol version its own ALPN, and serve both on one
+//! endpoint: [QuinnListener::from_shared_endpoint] takes the connections with
+//! the new ALPN, and hands all others to a listener for the old protocol created
+//! with [QuinnListener::handle_connections]. Once all servers accept both, the
+//! clients can switch to the new ALPN and codec.
+//!
+//! For iroh, register a [protocol handler](crate::transport::iroh::IrohListener::protocol_handler)
+//! for each ALPN.
+//!
+//! [QuinnListener::from_shared_endpoint]: crate::transport::quinn::QuinnListener::from_shared_endpoint
+//! [QuinnListener::handle_connections]: crate::transport::quinn::QuinnListener::handle_connections
+//!
+//! # Example
+//!
+//! A codec that encodes messages as JSON:
+//! ```
+//! # use quic_rpc::codec::Codec;
+//! # use bytes::{BufMut, BytesMut};
+//! # use serde::{de::DeserializeOwned, Serialize};
+//! #[derive(Debug, Default, Clone, Copy)]
+//! struct JsonCodec;
+//!
+//! impl Codec for JsonCodec {
+//!     fn serialize<T: Serialize>(&self, item: &T, dst: &mut BytesMut) -> std::io::Result<()> {
+//!         Ok(serde_json::to_writer(dst.writer(), item)?)
+//!     }
+//!
+//!     fn deserialize<T: DeserializeOwned>(&self, src: &[u8]) -> std::io::Result<T> {
+//!         Ok(serde_json::from_slice(src)?)
+//!     }
+//! }
+//! ```
+use std::{fmt, io};
+
+use bytes::{BufMut, BytesMut};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Serialization format of messages
+///
+/// A codec is created using [Default] for every substream, so it should be
+/// cheap to create and must not depend on any configuration.
+pub trait Codec: fmt::Debug + Default + Clone + Send + Sync + Unpin + 'static {
+    /// Append the encoding of `item` to `dst`
+    fn serialize<T: Serialize>(&self, item: &T, dst: &mut BytesMut) -> io::Result<()>;
+
+    /// Decode a message from a single frame or datagram
+    ///
+    /// `src` contains exactly one message, without the length prefix.
+    fn deserialize<T: DeserializeOwned>(&self, src: &[u8]) -> io::Result<T>;
+}
+
+/// The default codec, using [postcard]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PostcardCodec;
+
+impl Codec for PostcardCodec {
+    fn serialize<T: Serialize>(&self, item: &T, dst: &mut BytesMut) -> io::Result<()> {
+        postcard::to_io(item, dst.writer())
+            .map(|_| ())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    fn deserialize<T: DeserializeOwned>(&self, src: &[u8]) -> io::Result<T> {
+        postcard::from_bytes(src).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
+
+/// How frames are delimited on a substream
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Framing {
+    /// A 4 byte big endian length prefix
+    #[default]
+    Fixed,
+    /// An unsigned LEB128 varint length prefix, like protobuf uses
+    ///
+    /// Frames shorter than 128 bytes only need a single byte of prefix, which
+    /// saves a few bytes for every small message.
+    Varint,
+}
+
+/// A message is larger than the maximum frame length
+///
+/// The quinn and iroh transports report this as the inner error of an
+/// [io::Error], use [MessageTooLarge::downcast] to get it. When sending, the
+/// message is not sent and the substream can still be used. When receiving, the
+/// substream can not be read any further.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageTooLarge {
+    /// Length of the encoded message, if known
+    ///
+    /// With [Framing::Fixed], the length of a received message is not known.
+    pub size: Option<usize>,
+    /// The maximum frame length
+    pub max: usize,
+}
+
+impl MessageTooLarge {
+    /// Get the [MessageTooLarge] error from an io error, if that is what it is
+    pub fn downcast(err: &io::Error) -> Option<&Self> {
+        err.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for MessageTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl std::error::Error for MessageTooLarge {}
+
+impl From<MessageTooLarge> for io::Error {
+    fn from(err: MessageTooLarge) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
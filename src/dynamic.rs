@@ -0,0 +1,214 @@
+//! String keyed dynamic dispatch
+//!
+//! Normally the set of requests for a service is defined at compile time, as an enum
+//! that is shared between client and server. This module provides an alternative where
+//! requests carry a method name and a postcard encoded payload, and the server
+//! registers handlers by name at runtime.
+//!
+//! This is useful for loosely coupled plugin services that can not share a request
+//! enum at compile time. All calls use the [Rpc](crate::pattern::rpc::Rpc) pattern.
+use std::{collections::BTreeMap, error, fmt, result, sync::Arc};
+
+use futures_lite::Future;
+use futures_util::{future::BoxFuture, FutureExt};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{message::RpcMsg, pattern::rpc, Connector, RpcClient, Service};
+
+/// A service with string keyed methods
+#[derive(Debug, Clone, Copy)]
+pub struct DynService;
+
+impl Service for DynService {
+    type Req = DynRequest;
+    type Res = DynResponse;
+}
+
+/// A request for a method identified by name
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DynRequest {
+    /// The name of the method
+    pub method: String,
+    /// The postcard encoded request
+    pub payload: Vec<u8>,
+}
+
+/// The response to a [DynRequest], a postcard encoded response or an error
+pub type DynResponse = result::Result<Vec<u8>, DynError>;
+
+impl RpcMsg<DynService> for DynRequest {
+    type Response = DynResponse;
+}
+
+/// Error when handling a [DynRequest] on the server side
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DynError {
+    /// No handler is registered for the method
+    UnknownMethod(String),
+    /// The payload could not be decoded by the handler
+    Decode(String),
+    /// The response could not be encoded
+    Encode(String),
+}
+
+impl fmt::Display for DynError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl error::Error for DynError {}
+
+type Handler = Box<dyn Fn(Vec<u8>) -> BoxFuture<'static, DynResponse> + Send + Sync + 'static>;
+
+/// A set of handlers, keyed by method name
+///
+/// Use [Router::handle] as the handler for incoming [DynRequest]s:
+///
+/// ```ignore
+/// let (req, chan) = server.accept().await?.read_first().await?;
+/// chan.rpc(req, router.clone(), Router::handle).await?;
+/// ```
+#[derive(Clone, Default)]
+pub struct Router {
+    handlers: Arc<BTreeMap<String, Handler>>,
+}
+
+impl fmt::Debug for Router {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Router")
+            .field("methods", &self.handlers.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl Router {
+    /// Create a new router without any handlers
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a handler for the given method name
+    ///
+    /// An existing handler with the same name is replaced.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the router has already been cloned.
+    pub fn register<Req, Res, F, Fut>(mut self, method: impl Into<String>, f: F) -> Self
+    where
+        Req: DeserializeOwned + Send + 'static,
+        Res: Serialize + Send + 'static,
+        F: Fn(Req) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Res> + Send + 'static,
+    {
+        let f = Arc::new(f);
+        let handler: Handler = Box::new(move |payload| {
+            let f = f.clone();
+            async move {
+                let req: Req =
+                    postcard::from_bytes(&payload).map_err(|e| DynError::Decode(e.to_string()))?;
+                let res = f(req).await;
+                postcard::to_stdvec(&res).map_err(|e| DynError::Encode(e.to_string()))
+            }
+            .boxed()
+        });
+        Arc::get_mut(&mut self.handlers)
+            .expect("router must not be cloned while registering handlers")
+            .insert(method.into(), handler);
+        self
+    }
+
+    /// The names of all registered methods
+    pub fn methods(&self) -> impl Iterator<Item = &str> {
+        self.handlers.keys().map(|x| x.as_str())
+    }
+
+    /// Handle a request by dispatching it to the handler registered for its method
+    pub async fn handle(self, req: DynRequest) -> DynResponse {
+        let Some(handler) = self.handlers.get(&req.method) else {
+            return Err(DynError::UnknownMethod(req.method));
+        };
+        handler(req.payload).await
+    }
+}
+
+/// Client error for a dynamic call
+#[derive(Debug)]
+pub enum Error<C: crate::transport::ConnectionErrors> {
+    /// The request could not be encoded
+    Encode(postcard::Error),
+    /// The response could not be decoded
+    Decode(postcard::Error),
+    /// The rpc call failed
+    Rpc(rpc::Error<C>),
+    /// The server was unable to handle the request
+    Remote(DynError),
+}
+
+impl<C: crate::transport::ConnectionErrors> fmt::Display for Error<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl<C: crate::transport::ConnectionErrors> error::Error for Error<C> {}
+
+impl<C: Connector<DynService>> RpcClient<DynService, C> {
+    /// Call the method with the given name
+    pub async fn call<Req, Res>(&self, method: &str, req: &Req) -> result::Result<Res, Error<C>>
+    where
+        Req: Serialize,
+        Res: DeserializeOwned,
+    {
+        let payload = postcard::to_stdvec(req).map_err(Error::Encode)?;
+        let req = DynRequest {
+            method: method.to_string(),
+            payload,
+        };
+        let res = self
+            .rpc(req)
+            .await
+            .map_err(Error::Rpc)?
+            .map_err(Error::Remote)?;
+        postcard::from_bytes(&res).map_err(Error::Decode)
+    }
+}
+
+#[cfg(all(test, feature = "flume-transport"))]
+mod tests {
+    use super::*;
+    use crate::{transport::flume, RpcServer};
+
+    #[tokio::test]
+    async fn dynamic_dispatch() -> anyhow::Result<()> {
+        let router = Router::new()
+            .register("math.sqr", |x: u64| async move { x * x })
+            .register(
+                "greet",
+                |name: String| async move { format!("hello {name}") },
+            );
+        assert_eq!(router.methods().collect::<Vec<_>>(), ["greet", "math.sqr"]);
+        let (server, client) = flume::channel(1);
+        let server = RpcServer::<DynService, _>::new(server);
+        let _handle = tokio::spawn(async move {
+            loop {
+                let (req, chan) = server.accept().await?.read_first().await?;
+                chan.rpc(req, router.clone(), Router::handle).await?;
+            }
+            #[allow(unreachable_code)]
+            anyhow::Ok(())
+        });
+        let client = RpcClient::<DynService, _>::new(client);
+        let res: u64 = client.call("math.sqr", &12u64).await?;
+        assert_eq!(res, 144);
+        let res: String = client.call("greet", &"world").await?;
+        assert_eq!(res, "hello world");
+        let res = client.call::<_, u64>("math.cube", &3u64).await;
+        assert!(matches!(
+            res,
+            Err(Error::Remote(DynError::UnknownMethod(_)))
+        ));
+        Ok(())
+    }
+}
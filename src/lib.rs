@@ -90,6 +90,13 @@
 //! # }
 //! ```
 //!
+//! # Protocol crates
+//!
+//! A crate that only defines the messages of a service does not need the network
+//! transports, or a runtime. Depend on quic-rpc with `default-features = false`
+//! and `features = ["core"]` to only build the service, message and pattern
+//! traits, the map layer and the memory transport.
+//!
 //! # Features
 #![doc = document_features::document_features!()]
 #![deny(missing_docs)]
@@ -98,9 +105,33 @@
 use std::fmt::{Debug, Display};
 
 use serde::{de::DeserializeOwned, Serialize};
+#[cfg(feature = "blob")]
+#[cfg_attr(quicrpc_docsrs, doc(cfg(feature = "blob")))]
+pub mod blob;
 pub mod client;
+#[cfg(any(feature = "quinn-transport", feature = "iroh-transport"))]
+#[cfg_attr(
+    quicrpc_docsrs,
+    doc(cfg(any(feature = "quinn-transport", feature = "iroh-transport")))
+)]
+pub mod codec;
+#[cfg(feature = "dynamic")]
+#[cfg_attr(quicrpc_docsrs, doc(cfg(feature = "dynamic")))]
+pub mod dynamic;
 pub mod message;
+pub mod middleware;
+pub mod pubsub;
 pub mod server;
+#[cfg(feature = "rt")]
+#[cfg_attr(
+    quicrpc_docsrs,
+    doc(cfg(any(feature = "rt-tokio", feature = "rt-smol", feature = "rt-async-std")))
+)]
+pub mod session;
+pub mod stream_util;
+#[cfg(feature = "test-vectors")]
+#[cfg_attr(quicrpc_docsrs, doc(cfg(feature = "test-vectors")))]
+pub mod test_vectors;
 pub mod transport;
 pub use client::RpcClient;
 pub use server::RpcServer;
@@ -109,6 +140,8 @@ pub use server::RpcServer;
 mod macros;
 
 pub mod pattern;
+#[cfg(feature = "rt")]
+mod rt;
 
 /// Requirements for a RPC message
 ///
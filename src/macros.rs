@@ -245,6 +245,107 @@ macro_rules! __request_enum {
     };
 }
 
+/// Declare a service struct and its request and response types.
+///
+/// In the short form, the request and response enums are defined elsewhere:
+///
+/// ```ignore
+/// declare_service!(pub TestService, TestRequest, TestResponse);
+/// ```
+///
+/// This is equivalent to:
+/// ```ignore
+/// #[derive(Debug, Clone, Copy, Default)]
+/// pub struct TestService;
+///
+/// impl Service for TestService {
+///     type Req = TestRequest;
+///     type Res = TestResponse;
+/// }
+/// ```
+///
+/// Alternatively, the request and response enums are generated from a list of
+/// message types, with one variant per type, named after the type. This also
+/// generates the `From` and `TryFrom` conversions between the enums and the message
+/// types:
+///
+/// ```
+/// # use quic_rpc::{declare_rpc, declare_service};
+/// # use serde::{Deserialize, Serialize};
+/// #[derive(Debug, Serialize, Deserialize)]
+/// pub struct Ping;
+/// #[derive(Debug, Serialize, Deserialize)]
+/// pub struct Pong;
+///
+/// declare_service!(pub PingService, PingRequest { Ping }, PingResponse { Pong });
+/// declare_rpc!(PingService, Ping, Pong);
+///
+/// let req: PingRequest = Ping.into();
+/// assert!(Ping::try_from(req).is_ok());
+/// ```
+#[macro_export]
+macro_rules! declare_service {
+    (
+        $(#[$attr:meta])*
+        $vis:vis $service:ident,
+        $request:ident { $($req:ident),* $(,)? },
+        $response:ident { $($res:ident),* $(,)? } $(,)?
+    ) => {
+        $crate::__wrapper_enum!($vis $request, concat!("Request messages for ", stringify!($service)), $($req),*);
+        $crate::__wrapper_enum!($vis $response, concat!("Response messages for ", stringify!($service)), $($res),*);
+        $crate::declare_service!($(#[$attr])* $vis $service, $request, $response);
+    };
+    ($(#[$attr:meta])* $vis:vis $service:ident, $request:ty, $response:ty $(,)?) => {
+        #[doc = concat!("RPC service ", stringify!($service))]
+        $(#[$attr])*
+        #[derive(
+            ::std::clone::Clone,
+            ::std::marker::Copy,
+            ::std::fmt::Debug,
+            ::std::default::Default,
+        )]
+        $vis struct $service;
+
+        impl $crate::Service for $service {
+            type Req = $request;
+            type Res = $response;
+        }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __wrapper_enum {
+    ($vis:vis $name:ident, $doc:expr, $($variant:ident),*) => {
+        #[doc = $doc]
+        #[allow(clippy::enum_variant_names)]
+        #[derive(::std::fmt::Debug, ::serde::Serialize, ::serde::Deserialize)]
+        $vis enum $name {
+            $($variant($variant),)*
+        }
+
+        $(
+            impl ::std::convert::From<$variant> for $name {
+                fn from(value: $variant) -> Self {
+                    Self::$variant(value)
+                }
+            }
+
+            impl ::std::convert::TryFrom<$name> for $variant {
+                type Error = $name;
+
+                fn try_from(value: $name) -> ::std::result::Result<Self, $name> {
+                    match value {
+                        $name::$variant(value) => ::std::result::Result::Ok(value),
+                        #[allow(unreachable_patterns)]
+                        value => ::std::result::Result::Err(value),
+                    }
+                }
+            }
+        )*
+    };
+}
+
 /// Declare a message to be a rpc message for a service.
 ///
 /// Example:
@@ -288,6 +389,7 @@ macro_rules! declare_server_streaming {
     ($service:ident, $m_input:ident, $m_output:ident) => {
         impl $crate::message::Msg<$service> for $m_input {
             type Pattern = $crate::message::ServerStreaming;
+            const NAME: &'static str = concat!(stringify!($service), ".", stringify!($m_input));
         }
         impl $crate::message::ServerStreamingMsg<$service> for $m_input {
             type Response = $m_output;
@@ -318,6 +420,7 @@ macro_rules! declare_client_streaming {
     ($service:ident, $m_input:ident, $m_update:ident, $m_output:ident) => {
         impl $crate::message::Msg<$service> for $m_input {
             type Pattern = $crate::message::ClientStreaming;
+            const NAME: &'static str = concat!(stringify!($service), ".", stringify!($m_input));
         }
         impl $crate::message::ClientStreamingMsg<$service> for $m_input {
             type Update = $m_update;
@@ -349,6 +452,7 @@ macro_rules! declare_bidi_streaming {
     ($service:ident, $m_input:ident, $m_update:ident, $m_output:ident) => {
         impl $crate::message::Msg<$service> for $m_input {
             type Pattern = $crate::message::BidiStreaming;
+            const NAME: &'static str = concat!(stringify!($service), ".", stringify!($m_input));
         }
         impl $crate::message::BidiStreamingMsg<$service> for $m_input {
             type Update = $m_update;
@@ -368,6 +472,7 @@ macro_rules! __rpc_message {
     ($service:ident, ServerStreaming, $m_input:ident, _, $m_output:ident) => {
         impl $crate::message::Msg<$service> for $m_input {
             type Pattern = $crate::message::ServerStreaming;
+            const NAME: &'static str = concat!(stringify!($service), ".", stringify!($m_input));
         }
         impl $crate::message::ServerStreamingMsg<$service> for $m_input {
             type Response = $m_output;
@@ -376,6 +481,7 @@ macro_rules! __rpc_message {
     ($service:ident, ClientStreaming, $m_input:ident, $m_update:ident, $m_output:ident) => {
         impl $crate::message::Msg<$service> for $m_input {
             type Pattern = $crate::message::ClientStreaming;
+            const NAME: &'static str = concat!(stringify!($service), ".", stringify!($m_input));
         }
         impl $crate::message::ClientStreamingMsg<$service> for $m_input {
             type Response = $m_output;
@@ -385,6 +491,7 @@ macro_rules! __rpc_message {
     ($service:ident, BidiStreaming, $m_input:ident, $m_update:ident, $m_output:ident) => {
         impl $crate::message::Msg<$service> for $m_input {
             type Pattern = $crate::message::BidiStreaming;
+            const NAME: &'static str = concat!(stringify!($service), ".", stringify!($m_input));
         }
         impl $crate::message::BidiStreamingMsg<$service> for $m_input {
             type Response = $m_output;
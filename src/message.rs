@@ -5,9 +5,10 @@ use std::fmt::Debug;
 
 pub use crate::pattern::{
     bidi_streaming::{BidiStreaming, BidiStreamingMsg},
-    client_streaming::{ClientStreaming, ClientStreamingMsg},
-    rpc::{Rpc, RpcMsg},
-    server_streaming::{ServerStreaming, ServerStreamingMsg},
+    client_streaming::{AckedClientStreamingMsg, ClientStreaming, ClientStreamingMsg},
+    notify::{Notify, NotifyMsg},
+    rpc::{IdempotentMsg, Rpc, RpcMsg},
+    server_streaming::{CreditedServerStreamingMsg, ServerStreaming, ServerStreamingMsg},
 };
 use crate::Service;
 
@@ -17,12 +18,81 @@ use crate::Service;
 pub trait Msg<S: Service>: Into<S::Req> + TryFrom<S::Req> + Send + 'static {
     /// The interaction pattern for this message with this service.
     type Pattern: InteractionPattern;
+
+    /// The name of the method, e.g. `"ComputeService.Sqr"`.
+    ///
+    /// This is used for tracing, so you can see which method is being called. It is set
+    /// automatically by the `rpc_requests` macro in `quic-rpc-derive`. If it is empty,
+    /// the type name of the message is used instead. This is always the case for rpc
+    /// messages, which get their [Msg] impl from [RpcMsg].
+    const NAME: &'static str = "";
+
+    /// Whether the message may be sent as an unreliable datagram.
+    ///
+    /// This is only a declaration of eligibility. Patterns that carry small, self
+    /// contained messages check it using [use_datagram], and send the message on a
+    /// regular substream if the transport does not support datagrams or the
+    /// message does not fit into a single datagram.
+    const DATAGRAM: bool = false;
+
+    /// The update that marks the end of the updates of a streaming call
+    ///
+    /// Most transports can not tell a client that finished sending updates from
+    /// one that dropped the [UpdateSink](crate::client::UpdateSink) or crashed. If
+    /// this returns an update, [UpdateSink::finish](crate::client::UpdateSink::finish)
+    /// sends it before closing, and the [UpdateStream](crate::server::UpdateStream)
+    /// on the server ends with `None` when it arrives. If the updates end without
+    /// it, the call fails with [RpcServerError::UpdatesAborted](crate::server::RpcServerError::UpdatesAborted).
+    ///
+    /// The default is `None`, so the updates end when the client closes its side.
+    fn end_of_updates() -> Option<S::Req> {
+        None
+    }
+
+    /// Whether the update is the one returned by [Msg::end_of_updates]
+    fn is_end_of_updates(_update: &S::Req) -> bool {
+        false
+    }
+}
+
+/// The name of the method for message `M`, see [Msg::NAME].
+pub fn method_name<S: Service, M: Msg<S>>() -> &'static str {
+    if M::NAME.is_empty() {
+        std::any::type_name::<M>()
+    } else {
+        M::NAME
+    }
+}
+
+/// Whether to send message `M` as a datagram instead of on a substream.
+///
+/// `encoded_len` is the size of the encoded message, and `max_datagram_size` the
+/// maximum datagram size of the connection, or `None` if the connection does not
+/// support datagrams. Messages that are too large fall back to a substream.
+pub fn use_datagram<S: Service, M: Msg<S>>(
+    encoded_len: usize,
+    max_datagram_size: Option<usize>,
+) -> bool {
+    M::DATAGRAM && max_datagram_size.is_some_and(|max| encoded_len <= max)
+}
+
+/// The check for the end of the updates of message `M`, if it has a marker.
+pub(crate) fn end_of_updates<S: Service, M: Msg<S>>() -> Option<fn(&S::Req) -> bool> {
+    M::end_of_updates()
+        .is_some()
+        .then_some(M::is_end_of_updates as fn(&S::Req) -> bool)
+}
+
+/// A span to instrument the handling of a message of type `M`.
+pub(crate) fn method_span<S: Service, M: Msg<S>>() -> tracing::Span {
+    tracing::debug_span!("rpc", method = method_name::<S, M>())
 }
 
 /// Trait defining interaction pattern.
 ///
-/// Currently there are 4 patterns:
+/// Currently there are 5 patterns:
 /// - [Rpc]: 1 request, 1 response
+/// - [Notify]: 1 request, no response
 /// - [ClientStreaming]: 1 request, stream of updates, 1 response
 /// - [ServerStreaming]: 1 request, stream of responses
 /// - [BidiStreaming]: 1 request, stream of updates, stream of responses
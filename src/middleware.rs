@@ -0,0 +1,570 @@
+//! Middleware that sees every message of a service
+//!
+//! A [ClientMiddleware] is installed on a client with
+//! [RpcClient::with_middleware](crate::RpcClient::with_middleware). It is called for
+//! every request and update the client sends, and every response it receives, on
+//! any interaction pattern. Use it to add auth tokens, logging or metrics in one
+//! place instead of at every call site.
+//!
+//! A [ServerMiddleware] is installed on a server with
+//! [RpcServer::with_middleware](crate::RpcServer::with_middleware). It sees the
+//! first request of every substream before it is dispatched, and can reject it,
+//! e.g. because the client is not authorized or the server is overloaded. It is
+//! also called for every response the server sends. The first request is checked
+//! when it is read with [Accepting::read_first](crate::server::Accepting::read_first),
+//! not when the substream is accepted. Notifications are checked when they are
+//! received with [RpcServer::recv_notification](crate::RpcServer::recv_notification)
+//! or [RpcServer::recv_datagram](crate::RpcServer::recv_datagram), or by
+//! [RpcServer::accept_loop](crate::RpcServer::accept_loop).
+//!
+//! Middleware is installed by wrapping the connector or listener, so installing
+//! several middlewares nests them: the middleware installed last sees outgoing
+//! messages first and incoming messages last.
+use std::{
+    fmt,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures_lite::Stream;
+use futures_sink::Sink;
+use futures_util::SinkExt;
+use pin_project::pin_project;
+use tracing::warn;
+
+use crate::{
+    transport::{
+        meta::{MetaOpen, Metadata},
+        ConnectionErrors, Connector, Listener, LocalAddr, StreamTypes,
+    },
+    Service,
+};
+
+/// Hooks that are called for the messages of a client
+///
+/// All hooks have an empty default implementation. They are called from the poll
+/// functions of the substreams, so they should not block.
+pub trait ClientMiddleware<S: Service>: fmt::Debug + Send + Sync + 'static {
+    /// Called when a substream is opened with metadata
+    ///
+    /// This is only called for calls that carry metadata, like
+    /// [RpcClient::rpc_with_meta](crate::RpcClient::rpc_with_meta).
+    fn on_metadata(&self, _meta: &mut Metadata) {}
+
+    /// Called before a request or update is sent
+    fn before_send(&self, _req: &mut S::Req) {}
+
+    /// Called after a response is received
+    fn after_receive(&self, _res: &mut S::Res) {}
+}
+
+/// Hooks that are called for the messages of a server
+///
+/// All hooks have an empty default implementation. They are called from the poll
+/// functions of the substreams and from
+/// [Accepting::read_first](crate::server::Accepting::read_first), so they should
+/// not block.
+pub trait ServerMiddleware<S: Service>: fmt::Debug + Send + Sync + 'static {
+    /// Called with the first request of a substream, before it is dispatched
+    ///
+    /// Return a [Rejection] to drop the substream instead.
+    fn on_request(&self, _req: &mut S::Req) -> Result<(), Rejection<S::Res>> {
+        Ok(())
+    }
+
+    /// Called before a response is sent
+    fn before_send(&self, _res: &mut S::Res) {}
+}
+
+/// A request that was rejected by a [ServerMiddleware]
+#[derive(Debug)]
+pub struct Rejection<R> {
+    /// Why the request was rejected, for logging
+    pub reason: String,
+    /// The response sent to the client before the substream is closed
+    ///
+    /// Usually an error variant of the response enum, so that the client gets a
+    /// proper error instead of an early close.
+    pub response: Option<R>,
+}
+
+impl<R> Rejection<R> {
+    /// Reject a request, with a response for the client
+    pub fn new(reason: impl Into<String>, response: impl Into<R>) -> Self {
+        Self {
+            reason: reason.into(),
+            response: Some(response.into()),
+        }
+    }
+}
+
+/// A connector that calls a [ClientMiddleware] for the substreams it opens
+pub struct MiddlewareConnector<S: Service, C> {
+    inner: C,
+    middleware: Arc<dyn ClientMiddleware<S>>,
+}
+
+impl<S: Service, C> MiddlewareConnector<S, C> {
+    /// Wrap a connector
+    pub fn new(inner: C, middleware: impl ClientMiddleware<S>) -> Self {
+        Self {
+            inner,
+            middleware: Arc::new(middleware),
+        }
+    }
+
+    fn wrap<Si, St>(&self, (send, recv): (Si, St)) -> (SendSink<S, Si>, RecvStream<S, St>) {
+        let send = SendSink {
+            inner: send,
+            middleware: self.middleware.clone(),
+        };
+        let recv = RecvStream {
+            inner: recv,
+            middleware: self.middleware.clone(),
+        };
+        (send, recv)
+    }
+}
+
+impl<S: Service, C: Clone> Clone for MiddlewareConnector<S, C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            middleware: self.middleware.clone(),
+        }
+    }
+}
+
+impl<S: Service, C: fmt::Debug> fmt::Debug for MiddlewareConnector<S, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MiddlewareConnector")
+            .field("inner", &self.inner)
+            .field("middleware", &self.middleware)
+            .finish()
+    }
+}
+
+impl<S: Service, C: ConnectionErrors> ConnectionErrors for MiddlewareConnector<S, C> {
+    type SendError = C::SendError;
+    type RecvError = C::RecvError;
+    type OpenError = C::OpenError;
+    type AcceptError = C::AcceptError;
+}
+
+impl<S, C> StreamTypes for MiddlewareConnector<S, C>
+where
+    S: Service,
+    C: StreamTypes<In = S::Res, Out = S::Req>,
+{
+    type In = S::Res;
+    type Out = S::Req;
+    type RecvStream = RecvStream<S, C::RecvStream>;
+    type SendSink = SendSink<S, C::SendSink>;
+}
+
+impl<S, C> Connector for MiddlewareConnector<S, C>
+where
+    S: Service,
+    C: Connector<In = S::Res, Out = S::Req>,
+{
+    async fn open(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        Ok(self.wrap(self.inner.open().await?))
+    }
+
+    async fn open_with_priority(
+        &self,
+        priority: i32,
+    ) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        Ok(self.wrap(self.inner.open_with_priority(priority).await?))
+    }
+
+    async fn open_uni(&self) -> Result<Self::SendSink, Self::OpenError> {
+        Ok(SendSink {
+            inner: self.inner.open_uni().await?,
+            middleware: self.middleware.clone(),
+        })
+    }
+
+    /// Send a message as a datagram, or on a unidirectional substream
+    ///
+    /// The hook has run once the message is handed to the inner connector, so a
+    /// message the inner connector can't send as a datagram is not handed back,
+    /// where it would be hooked again when sent on a substream. It is sent on a
+    /// substream of the inner connector instead. If that substream can't be
+    /// opened, the message is dropped, like a lost datagram.
+    async fn send_datagram(
+        &self,
+        mut msg: Self::Out,
+    ) -> Result<Option<Self::Out>, Self::SendError> {
+        self.middleware.before_send(&mut msg);
+        let Some(msg) = self.inner.send_datagram(msg).await? else {
+            return Ok(None);
+        };
+        let mut send = match self.inner.open_uni().await {
+            Ok(send) => send,
+            Err(cause) => {
+                warn!("dropping datagram, unable to open a substream: {cause}");
+                return Ok(None);
+            }
+        };
+        send.send(msg).await?;
+        send.close().await?;
+        Ok(None)
+    }
+}
+
+impl<S, C> MetaOpen for MiddlewareConnector<S, C>
+where
+    S: Service,
+    C: MetaOpen<In = S::Res, Out = S::Req>,
+{
+    async fn open_with_meta(
+        &self,
+        mut meta: Metadata,
+    ) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        self.middleware.on_metadata(&mut meta);
+        Ok(self.wrap(self.inner.open_with_meta(meta).await?))
+    }
+}
+
+/// A listener that calls [ServerMiddleware::before_send] for the substreams it accepts
+///
+/// Substreams are returned as soon as they are accepted. The first request is
+/// checked with [ServerMiddleware::on_request] in
+/// [Accepting::read_first](crate::server::Accepting::read_first), so that a client
+/// that opens a substream and sends nothing does not hold up other clients.
+pub struct MiddlewareListener<S: Service, C> {
+    inner: C,
+    middleware: Arc<dyn ServerMiddleware<S>>,
+}
+
+impl<S: Service, C> MiddlewareListener<S, C> {
+    /// Wrap a listener
+    pub fn new(inner: C, middleware: impl ServerMiddleware<S>) -> Self {
+        Self::from_arc(inner, Arc::new(middleware))
+    }
+
+    pub(crate) fn from_arc(inner: C, middleware: Arc<dyn ServerMiddleware<S>>) -> Self {
+        Self { inner, middleware }
+    }
+
+    fn wrap<Si>(&self, send: Si) -> ServerSendSink<S, Si> {
+        ServerSendSink {
+            inner: send,
+            middleware: self.middleware.clone(),
+        }
+    }
+}
+
+impl<S: Service, C: Clone> Clone for MiddlewareListener<S, C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            middleware: self.middleware.clone(),
+        }
+    }
+}
+
+impl<S: Service, C: fmt::Debug> fmt::Debug for MiddlewareListener<S, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MiddlewareListener")
+            .field("inner", &self.inner)
+            .field("middleware", &self.middleware)
+            .finish()
+    }
+}
+
+impl<S: Service, C: ConnectionErrors> ConnectionErrors for MiddlewareListener<S, C> {
+    type SendError = C::SendError;
+    type RecvError = C::RecvError;
+    type OpenError = C::OpenError;
+    type AcceptError = C::AcceptError;
+}
+
+impl<S, C> StreamTypes for MiddlewareListener<S, C>
+where
+    S: Service,
+    C: StreamTypes<In = S::Req, Out = S::Res>,
+{
+    type In = S::Req;
+    type Out = S::Res;
+    type RecvStream = C::RecvStream;
+    type SendSink = ServerSendSink<S, C::SendSink>;
+}
+
+impl<S, C> Listener for MiddlewareListener<S, C>
+where
+    S: Service,
+    C: Listener<In = S::Req, Out = S::Res>,
+{
+    async fn accept(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::AcceptError> {
+        let (send, recv) = self.inner.accept().await?;
+        Ok((self.wrap(send), recv))
+    }
+
+    fn local_addr(&self) -> &[LocalAddr] {
+        self.inner.local_addr()
+    }
+
+    async fn accept_uni(&self) -> Result<Self::RecvStream, Self::AcceptError> {
+        self.inner.accept_uni().await
+    }
+
+    async fn recv_datagram(&self) -> Result<Self::In, Self::RecvError> {
+        self.inner.recv_datagram().await
+    }
+}
+
+/// A send sink that calls [ServerMiddleware::before_send] for every message
+#[pin_project]
+pub struct ServerSendSink<S: Service, Si> {
+    #[pin]
+    inner: Si,
+    middleware: Arc<dyn ServerMiddleware<S>>,
+}
+
+impl<S: Service, Si: fmt::Debug> fmt::Debug for ServerSendSink<S, Si> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServerSendSink")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S: Service, Si: Sink<S::Res>> Sink<S::Res> for ServerSendSink<S, Si> {
+    type Error = Si::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, mut item: S::Res) -> Result<(), Self::Error> {
+        let this = self.project();
+        this.middleware.before_send(&mut item);
+        this.inner.start_send(item)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_close(cx)
+    }
+}
+
+/// A send sink that calls [ClientMiddleware::before_send] for every message
+#[pin_project]
+pub struct SendSink<S: Service, Si> {
+    #[pin]
+    inner: Si,
+    middleware: Arc<dyn ClientMiddleware<S>>,
+}
+
+impl<S: Service, Si: fmt::Debug> fmt::Debug for SendSink<S, Si> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SendSink")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S: Service, Si: Sink<S::Req>> Sink<S::Req> for SendSink<S, Si> {
+    type Error = Si::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, mut item: S::Req) -> Result<(), Self::Error> {
+        let this = self.project();
+        this.middleware.before_send(&mut item);
+        this.inner.start_send(item)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_close(cx)
+    }
+}
+
+/// A receive stream that calls [ClientMiddleware::after_receive] for every message
+#[pin_project]
+pub struct RecvStream<S: Service, St> {
+    #[pin]
+    inner: St,
+    middleware: Arc<dyn ClientMiddleware<S>>,
+}
+
+impl<S: Service, St: fmt::Debug> fmt::Debug for RecvStream<S, St> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RecvStream")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S, St, E> Stream for RecvStream<S, St>
+where
+    S: Service,
+    St: Stream<Item = Result<S::Res, E>>,
+{
+    type Item = St::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let mut res = this.inner.poll_next(cx);
+        if let Poll::Ready(Some(Ok(item))) = &mut res {
+            this.middleware.after_receive(item);
+        }
+        res
+    }
+}
+
+#[cfg(all(test, feature = "flume-transport"))]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use futures_lite::StreamExt;
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::{message::RpcMsg, server::RpcServerError, transport::flume, RpcClient, RpcServer};
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Echo(u64);
+
+    #[derive(Debug, Clone)]
+    struct EchoService;
+
+    impl Service for EchoService {
+        type Req = Echo;
+        type Res = Echo;
+    }
+
+    impl RpcMsg<EchoService> for Echo {
+        type Response = Echo;
+    }
+
+    /// Adds to every request, and counts the responses
+    #[derive(Debug)]
+    struct Add(u64, Arc<AtomicU64>);
+
+    impl ClientMiddleware<EchoService> for Add {
+        fn before_send(&self, req: &mut Echo) {
+            req.0 += self.0;
+        }
+
+        fn after_receive(&self, _res: &mut Echo) {
+            self.1.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[tokio::test]
+    async fn client_middleware() -> anyhow::Result<()> {
+        let (listener, connector) = flume::channel(1);
+        let server = RpcServer::<EchoService, _>::new(listener);
+        let _server = tokio::spawn(async move {
+            loop {
+                let (req, chan) = server.accept().await?.read_first().await?;
+                chan.rpc(req, (), |_, req| async move { req }).await?;
+            }
+            #[allow(unreachable_code)]
+            anyhow::Ok(())
+        });
+        let received = Arc::new(AtomicU64::new(0));
+        let client = RpcClient::<EchoService, _>::new(connector)
+            .with_middleware(Add(1, received.clone()))
+            .with_middleware(Add(10, received.clone()));
+        let Echo(res) = client.rpc(Echo(100)).await?;
+        assert_eq!(res, 111);
+        assert_eq!(received.load(Ordering::Relaxed), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn datagram_fallback() -> anyhow::Result<()> {
+        let (listener, connector) = flume::channel::<Echo, Echo>(1);
+        let connector = MiddlewareConnector::new(connector, Add(1, Default::default()));
+        // flume has no datagrams, so the message goes on a substream, hooked once
+        assert!(connector.send_datagram(Echo(100)).await?.is_none());
+        let (_, mut recv) = listener.accept().await?;
+        assert!(matches!(recv.next().await, Some(Ok(Echo(101)))));
+        Ok(())
+    }
+
+    /// Rejects odd requests, and doubles every response
+    #[derive(Debug)]
+    struct EvenOnly;
+
+    impl ServerMiddleware<EchoService> for EvenOnly {
+        fn on_request(&self, req: &mut Echo) -> Result<(), Rejection<Echo>> {
+            if req.0 % 2 == 1 {
+                return Err(Rejection::new("odd", Echo(0)));
+            }
+            Ok(())
+        }
+
+        fn before_send(&self, res: &mut Echo) {
+            res.0 *= 2;
+        }
+    }
+
+    #[tokio::test]
+    async fn server_middleware() -> anyhow::Result<()> {
+        let (listener, connector) = flume::channel(1);
+        let server = RpcServer::<EchoService, _>::new(listener).with_middleware(EvenOnly);
+        let handled = Arc::new(AtomicU64::new(0));
+        let _server = tokio::spawn({
+            let handled = handled.clone();
+            async move {
+                loop {
+                    let (req, chan) = match server.accept().await?.read_first().await {
+                        Ok(first) => first,
+                        Err(RpcServerError::Rejected(_)) => continue,
+                        Err(cause) => return Err(cause.into()),
+                    };
+                    handled.fetch_add(1, Ordering::Relaxed);
+                    chan.rpc(req, (), |_, req| async move { req }).await?;
+                }
+                #[allow(unreachable_code)]
+                anyhow::Ok(())
+            }
+        });
+        let client = RpcClient::<EchoService, _>::new(connector);
+        let Echo(res) = client.rpc(Echo(3)).await?;
+        assert_eq!(res, 0);
+        let Echo(res) = client.rpc(Echo(4)).await?;
+        assert_eq!(res, 8);
+        assert_eq!(handled.load(Ordering::Relaxed), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn idle_substream() -> anyhow::Result<()> {
+        let (listener, connector) = flume::channel(1);
+        let server = RpcServer::<EchoService, _>::new(listener).with_middleware(EvenOnly);
+        let _server = tokio::spawn(async move {
+            loop {
+                let accepting = server.accept().await?;
+                tokio::spawn(async move {
+                    let (req, chan) = accepting.read_first().await?;
+                    chan.rpc(req, (), |_, req| async move { req }).await?;
+                    anyhow::Ok(())
+                });
+            }
+            #[allow(unreachable_code)]
+            anyhow::Ok(())
+        });
+        // a substream on which nothing is sent does not hold up other clients
+        let _idle = connector.open().await?;
+        let client = RpcClient::<EchoService, _>::new(connector);
+        let Echo(res) = client.rpc(Echo(4)).await?;
+        assert_eq!(res, 8);
+        Ok(())
+    }
+}
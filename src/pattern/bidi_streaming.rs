@@ -8,10 +8,11 @@ use std::{
 
 use futures_lite::{Stream, StreamExt};
 use futures_util::{FutureExt, SinkExt};
+use tracing::Instrument;
 
 use crate::{
-    client::{BoxStreamSync, UpdateSink},
-    message::{InteractionPattern, Msg},
+    client::{first_response, BoxStreamSync, UpdateSink},
+    message::{end_of_updates, method_span, InteractionPattern, Msg},
     server::{race2, RpcChannel, RpcServerError, UpdateStream},
     transport::{ConnectionErrors, Connector, StreamTypes},
     RpcClient, Service,
@@ -46,6 +47,9 @@ pub enum Error<C: ConnectionErrors> {
     Open(C::OpenError),
     /// Unable to send the request to the server
     Send(C::SendError),
+    /// The request was not sent, or the first response not received, within the
+    /// timeout, see [RpcClient::bidi_timeout]
+    Timeout,
 }
 
 impl<C: ConnectionErrors> fmt::Display for Error<C> {
@@ -89,19 +93,73 @@ where
         ),
         Error<C>,
     >
+    where
+        M: BidiStreamingMsg<S>,
+    {
+        self.timed(None, self.bidi_inner(msg, false))
+            .await
+            .unwrap_or(Err(Error::Timeout))
+    }
+
+    async fn bidi_inner<M>(
+        &self,
+        msg: M,
+        wait: bool,
+    ) -> result::Result<
+        (
+            UpdateSink<C, M::Update>,
+            BoxStreamSync<'static, result::Result<M::Response, ItemError<C>>>,
+        ),
+        Error<C>,
+    >
     where
         M: BidiStreamingMsg<S>,
     {
         let msg = msg.into();
         let (mut send, recv) = self.source.open().await.map_err(Error::Open)?;
         send.send(msg).await.map_err(Error::<C>::Send)?;
-        let send = UpdateSink::new(send);
+        let recv = first_response(recv, wait).await;
+        let send = UpdateSink::new(send).end_with(M::end_of_updates());
         let recv = Box::pin(recv.map(move |x| match x {
             Ok(msg) => M::Response::try_from(msg).map_err(|_| ItemError::DowncastError),
             Err(e) => Err(ItemError::RecvError(e)),
         }));
         Ok((send, recv))
     }
+
+    /// Bidi call that fails with [Error::Timeout] if the first response is not
+    /// received within the given timeout
+    ///
+    /// The timeout covers opening the substream, sending the request and
+    /// receiving the first response, not the updates or the responses after that.
+    /// It overrides the default timeout of the client.
+    ///
+    /// Updates can only be sent once this returns, so a call where the server
+    /// waits for an update before it responds always times out. Use
+    /// [RpcClient::bidi] for those.
+    #[cfg(feature = "rt")]
+    #[cfg_attr(
+        quicrpc_docsrs,
+        doc(cfg(any(feature = "rt-tokio", feature = "rt-smol", feature = "rt-async-std")))
+    )]
+    pub async fn bidi_timeout<M>(
+        &self,
+        msg: M,
+        timeout: std::time::Duration,
+    ) -> result::Result<
+        (
+            UpdateSink<C, M::Update>,
+            BoxStreamSync<'static, result::Result<M::Response, ItemError<C>>>,
+        ),
+        Error<C>,
+    >
+    where
+        M: BidiStreamingMsg<S>,
+    {
+        self.timed(Some(timeout), self.bidi_inner(msg, true))
+            .await
+            .unwrap_or(Err(Error::Timeout))
+    }
 }
 
 impl<C, S> RpcChannel<S, C>
@@ -126,7 +184,7 @@ where
     {
         let Self { mut send, recv, .. } = self;
         // downcast the updates
-        let (updates, read_error) = UpdateStream::new(recv);
+        let (updates, read_error) = UpdateStream::new(recv, end_of_updates::<S, M>());
         // get the response
         let responses = f(target, req, updates);
         race2(read_error.map(Err), async move {
@@ -141,6 +199,7 @@ where
             }
             Ok(())
         })
+        .instrument(method_span::<S, M>())
         .await
     }
 }
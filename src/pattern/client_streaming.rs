@@ -3,15 +3,21 @@
 use std::{
     error,
     fmt::{self, Debug},
+    num::NonZeroU64,
+    pin::Pin,
     result,
+    task::{ready, Context, Poll},
 };
 
-use futures_lite::{future::Boxed, Future, StreamExt};
+use futures_lite::{future::Boxed, Future, Stream, StreamExt};
 use futures_util::{FutureExt, SinkExt, TryFutureExt};
+use pin_project::pin_project;
+use tokio::sync::{oneshot, watch};
+use tracing::Instrument;
 
 use crate::{
-    client::UpdateSink,
-    message::{InteractionPattern, Msg},
+    client::{Responded, UpdateSink},
+    message::{end_of_updates, method_span, InteractionPattern, Msg},
     server::{race2, RpcChannel, RpcServerError, UpdateStream},
     transport::{ConnectionErrors, StreamTypes},
     Connector, RpcClient, Service,
@@ -39,6 +45,29 @@ pub trait ClientStreamingMsg<S: Service>: Msg<S, Pattern = ClientStreaming> {
     type Response: Into<S::Res> + TryFrom<S::Res> + Send + 'static;
 }
 
+/// A client streaming message where the server acknowledges processed updates
+///
+/// Acknowledgements are sent as responses on the same substream as the final
+/// response, so they must be distinguishable from it. An acknowledgement carries
+/// the total number of updates the server processed so far. On the client side,
+/// it is exposed by [UpdateSink::acked] and [UpdateSink::wait_acked], which can be
+/// used for application level flow control or to resume an interrupted upload.
+pub trait AckedClientStreamingMsg<S: Service>: ClientStreamingMsg<S> {
+    /// Create the acknowledgement for the given number of processed updates
+    fn ack(processed: u64) -> S::Res;
+
+    /// Get the number of processed updates if the response is an acknowledgement
+    fn processed(res: &S::Res) -> Option<u64>;
+
+    /// Acknowledge automatically after every `n` processed updates
+    ///
+    /// An update counts as processed when the handler asks for the next one. The
+    /// default is `None`, so updates are only acknowledged by [AckedUpdateStream::ack].
+    fn ack_every() -> Option<NonZeroU64> {
+        None
+    }
+}
+
 /// Server error when accepting a client streaming request
 #[derive(Debug)]
 pub enum Error<C: ConnectionErrors> {
@@ -46,6 +75,8 @@ pub enum Error<C: ConnectionErrors> {
     Open(C::OpenError),
     /// Unable to send the request to the server
     Send(C::SendError),
+    /// The request was not sent within the timeout, see [RpcClient::client_streaming_timeout]
+    Timeout,
 }
 
 impl<C: ConnectionErrors> fmt::Display for Error<C> {
@@ -81,6 +112,13 @@ where
     C: Connector<S>,
 {
     /// Call to the server that allows the client to stream, single response
+    ///
+    /// If the server responds before it read all updates, sending more updates
+    /// fails with [UpdateError::Rejected](crate::client::UpdateError::Rejected).
+    /// With a runtime feature, the response is read by a separate task, so this
+    /// also works when the response future is only awaited after all updates were
+    /// sent. Without one, it is only noticed once the response future was polled.
+    /// Dropping the response future stops the task.
     pub async fn client_streaming<M>(
         &self,
         msg: M,
@@ -91,24 +129,137 @@ where
         ),
         Error<C>,
     >
+    where
+        M: ClientStreamingMsg<S>,
+    {
+        self.timed(None, self.client_streaming_inner(msg))
+            .await
+            .unwrap_or(Err(Error::Timeout))
+    }
+
+    async fn client_streaming_inner<M>(
+        &self,
+        msg: M,
+    ) -> result::Result<
+        (
+            UpdateSink<C, M::Update>,
+            Boxed<result::Result<M::Response, ItemError<C>>>,
+        ),
+        Error<C>,
+    >
     where
         M: ClientStreamingMsg<S>,
     {
         let msg = msg.into();
         let (mut send, mut recv) = self.source.open().await.map_err(Error::Open)?;
         send.send(msg).map_err(Error::Send).await?;
-        let send = UpdateSink::<C, M::Update>::new(send);
+        let (responded_tx, responded_rx) = oneshot::channel();
         let recv = async move {
             let item = recv.next().await.ok_or(ItemError::EarlyClose)?;
-
-            match item {
-                Ok(msg) => M::Response::try_from(msg).map_err(|_| ItemError::DowncastError),
-                Err(e) => Err(ItemError::RecvError(e)),
-            }
-        }
-        .boxed();
+            let msg = item.map_err(ItemError::RecvError)?;
+            responded_tx.send(()).ok();
+            M::Response::try_from(msg).map_err(|_| ItemError::DowncastError)
+        };
+        // read the response in a task, so that the update sink notices an early
+        // response even if the response future is only polled after the upload
+        #[cfg(feature = "rt")]
+        let (recv, spawned) = (crate::rt::spawn(recv).boxed(), true);
+        #[cfg(not(feature = "rt"))]
+        let (recv, spawned) = (recv.boxed(), false);
+        let send = UpdateSink::<C, M::Update>::new(send)
+            .end_with(M::end_of_updates())
+            .rejected_by(Responded::new(responded_rx, spawned));
         Ok((send, recv))
     }
+
+    /// Client streaming call that fails with [Error::Timeout] if the request can
+    /// not be sent within the given timeout
+    ///
+    /// The timeout covers opening the substream and sending the request, not the
+    /// updates or the response. It overrides the default timeout of the client.
+    #[cfg(feature = "rt")]
+    #[cfg_attr(
+        quicrpc_docsrs,
+        doc(cfg(any(feature = "rt-tokio", feature = "rt-smol", feature = "rt-async-std")))
+    )]
+    pub async fn client_streaming_timeout<M>(
+        &self,
+        msg: M,
+        timeout: std::time::Duration,
+    ) -> result::Result<
+        (
+            UpdateSink<C, M::Update>,
+            Boxed<result::Result<M::Response, ItemError<C>>>,
+        ),
+        Error<C>,
+    >
+    where
+        M: ClientStreamingMsg<S>,
+    {
+        self.timed(Some(timeout), self.client_streaming_inner(msg))
+            .await
+            .unwrap_or(Err(Error::Timeout))
+    }
+}
+
+impl<S, C> RpcClient<S, C>
+where
+    S: Service,
+    C: Connector<S>,
+{
+    /// Client streaming call where the server acknowledges processed updates
+    ///
+    /// The responses are read by a separate task, so acknowledgements arrive while
+    /// the updates are sent. Dropping the response future stops the task, after
+    /// which [UpdateSink::wait_acked] no longer makes progress.
+    #[cfg(feature = "rt")]
+    #[cfg_attr(
+        quicrpc_docsrs,
+        doc(cfg(any(feature = "rt-tokio", feature = "rt-smol", feature = "rt-async-std")))
+    )]
+    pub async fn client_streaming_acked<M>(
+        &self,
+        msg: M,
+    ) -> result::Result<
+        (
+            UpdateSink<C, M::Update>,
+            Boxed<result::Result<M::Response, ItemError<C>>>,
+        ),
+        Error<C>,
+    >
+    where
+        M: AckedClientStreamingMsg<S>,
+    {
+        self.timed(None, async move {
+            let msg = msg.into();
+            let (mut send, mut recv) = self.source.open().await.map_err(Error::Open)?;
+            send.send(msg).map_err(Error::Send).await?;
+            let (acked_tx, acked_rx) = watch::channel(0);
+            let (responded_tx, responded_rx) = oneshot::channel();
+            let send = UpdateSink::<C, M::Update>::with_acks(send, acked_rx)
+                .end_with(M::end_of_updates())
+                .rejected_by(Responded::new(responded_rx, true));
+            let recv = crate::rt::spawn(async move {
+                loop {
+                    let item = recv.next().await.ok_or(ItemError::EarlyClose)?;
+                    let msg = item.map_err(ItemError::RecvError)?;
+                    match M::processed(&msg) {
+                        Some(processed) => {
+                            acked_tx.send_replace(processed);
+                        }
+                        None => {
+                            responded_tx.send(()).ok();
+                            break M::Response::try_from(msg).map_err(|_| ItemError::DowncastError);
+                        }
+                    }
+                }
+            })
+            .boxed();
+            Ok((send, recv))
+        })
+        .await
+        .unwrap_or(Err(Error::Timeout))
+    }
 }
 
 impl<S, C> RpcChannel<S, C>
@@ -132,7 +283,7 @@ where
         T: Send + 'static,
     {
         let Self { mut send, recv, .. } = self;
-        let (updates, read_error) = UpdateStream::new(recv);
+        let (updates, read_error) = UpdateStream::new(recv, end_of_updates::<S, M>());
         race2(read_error.map(Err), async move {
             // get the response
             let res = f(target, req, updates).await;
@@ -141,6 +292,129 @@ where
             // send it and return the error if any
             send.send(res).await.map_err(RpcServerError::SendError)
         })
+        .instrument(method_span::<S, M>())
+        .await
+    }
+
+    /// handle the message M using the given function on the target object,
+    /// acknowledging processed updates to the client
+    ///
+    /// See [AckedClientStreamingMsg] for when updates are acknowledged.
+    pub async fn client_streaming_acked<M, F, Fut, T>(
+        self,
+        req: M,
+        target: T,
+        f: F,
+    ) -> result::Result<(), RpcServerError<C>>
+    where
+        M: AckedClientStreamingMsg<S>,
+        F: FnOnce(T, M, AckedUpdateStream<C, M::Update>) -> Fut + Send + 'static,
+        Fut: Future<Output = M::Response> + Send + 'static,
+        T: Send + 'static,
+    {
+        let Self { mut send, recv, .. } = self;
+        let (updates, read_error) = UpdateStream::new(recv, end_of_updates::<S, M>());
+        let (acked_tx, mut acked_rx) = watch::channel(0);
+        let updates = AckedUpdateStream {
+            inner: updates,
+            received: 0,
+            acked: acked_tx,
+            every: M::ack_every(),
+        };
+        race2(read_error.map(Err), async move {
+            let res = f(target, req, updates);
+            tokio::pin!(res);
+            // forward acknowledgements until the handler is done or drops the updates
+            let res = loop {
+                tokio::select! {
+                    biased;
+                    res = &mut res => break res,
+                    changed = acked_rx.changed() => match changed {
+                        Ok(()) => {
+                            let processed = *acked_rx.borrow_and_update();
+                            send.send(M::ack(processed))
+                                .await
+                                .map_err(RpcServerError::SendError)?;
+                        }
+                        Err(_) => break res.await,
+                    }
+                }
+            };
+            // turn into a S::Res so we can send it
+            let res = res.into();
+            // send it and return the error if any
+            send.send(res).await.map_err(RpcServerError::SendError)
+        })
+        .instrument(method_span::<S, M>())
         .await
     }
 }
+
+/// A stream of updates that acknowledges processed updates to the client
+///
+/// See [AckedClientStreamingMsg] for details.
+#[pin_project]
+pub struct AckedUpdateStream<C, T>
+where
+    C: StreamTypes,
+{
+    #[pin]
+    inner: UpdateStream<C, T>,
+    received: u64,
+    acked: watch::Sender<u64>,
+    every: Option<NonZeroU64>,
+}
+
+impl<C: StreamTypes, T> fmt::Debug for AckedUpdateStream<C, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AckedUpdateStream")
+            .field("received", &self.received)
+            .field("acked", &*self.acked.borrow())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<C, T> AckedUpdateStream<C, T>
+where
+    C: StreamTypes,
+{
+    /// Acknowledge all updates that were received so far
+    pub fn ack(&self) {
+        self.ack_processed(self.received);
+    }
+
+    /// Acknowledge the given number of processed updates
+    ///
+    /// Acknowledgements never go backwards, so a smaller number than a previous
+    /// acknowledgement is ignored.
+    pub fn ack_processed(&self, processed: u64) {
+        self.acked.send_if_modified(|acked| {
+            let modified = processed > *acked;
+            *acked = (*acked).max(processed);
+            modified
+        });
+    }
+}
+
+impl<C, T> Stream for AckedUpdateStream<C, T>
+where
+    C: StreamTypes,
+    T: TryFrom<C::In>,
+{
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        // the handler asks for the next update, so the previous ones are processed
+        if let Some(every) = this.every {
+            if *this.received - *this.acked.borrow() >= every.get() {
+                this.acked.send_replace(*this.received);
+            }
+        }
+        let item = ready!(this.inner.poll_next(cx));
+        if item.is_some() {
+            *this.received += 1;
+        }
+        Poll::Ready(item)
+    }
+}
@@ -6,6 +6,8 @@
 //! Each pattern defines different associated message types for the interaction.
 pub mod bidi_streaming;
 pub mod client_streaming;
+pub mod multi;
+pub mod notify;
 pub mod rpc;
 pub mod server_streaming;
 pub mod try_server_streaming;
@@ -0,0 +1,282 @@
+//! Multi interaction pattern.
+//!
+//! A message with this pattern can be sent either as a rpc call with a single
+//! response, or as a server streaming request. The client selects which one it
+//! wants at the call site.
+//!
+//! The selected [Mode] travels in a [Request] envelope around the message, so the
+//! request enum of the service has a variant for `Request<M>` instead of for the
+//! message itself, and the message does not need a field for the mode.
+//!
+//! The server decides at runtime which modes it supports, and advertises them to
+//! clients that select another one, see [MultiMsg::unsupported].
+
+use std::{
+    error,
+    fmt::{self, Debug},
+    result,
+};
+
+use futures_lite::{Future, Stream, StreamExt};
+use futures_util::{FutureExt, SinkExt};
+use serde::{Deserialize, Serialize};
+use tracing::Instrument;
+
+use crate::{
+    client::{BoxStreamSync, DeferDrop},
+    message::{method_span, InteractionPattern, Msg},
+    pattern::server_streaming::ItemError,
+    server::{race2, RpcChannel, RpcServerError},
+    transport::{ConnectionErrors, StreamTypes},
+    Connector, RpcClient, Service,
+};
+
+/// Multi interaction pattern
+///
+/// There is one request, and either a single response or a stream of responses,
+/// depending on the [Mode] selected by the client.
+#[derive(Debug, Clone, Copy)]
+pub struct Multi;
+impl InteractionPattern for Multi {}
+
+/// The mode of a multi pattern request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Mode {
+    /// A single response
+    Rpc,
+    /// A stream of responses
+    ServerStreaming,
+}
+
+/// A multi pattern message, together with the mode the client selected
+///
+/// This is what is sent to the server, so it is the type that implements [Msg]
+/// with the [Multi] pattern.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Request<M> {
+    /// The mode the client selected
+    pub mode: Mode,
+    /// The message
+    pub msg: M,
+}
+
+/// Defines the response types for a multi pattern message.
+///
+/// The message is sent in a [Request], which needs to implement [Msg] with the
+/// [Multi] pattern.
+pub trait MultiMsg<S: Service>: Send + Sized + 'static {
+    /// The type of the response when called as a rpc
+    type Response: Into<S::Res> + TryFrom<S::Res> + Send + 'static;
+    /// The type of the items when called as a server streaming request
+    type Item: Into<S::Res> + TryFrom<S::Res> + Send + 'static;
+
+    /// Create the response that tells the client that the server does not
+    /// support the selected mode, with the modes it does support
+    ///
+    /// The server sends it instead of the responses, and the client fails with
+    /// [Error::Unsupported]. The default is `None`, then the server closes the
+    /// substream without a response.
+    fn unsupported(supported: &[Mode]) -> Option<S::Res> {
+        let _ = supported;
+        None
+    }
+
+    /// Get the modes the server supports if the response tells that it does not
+    /// support the selected one
+    ///
+    /// This must recognize the responses created by [MultiMsg::unsupported].
+    fn is_unsupported(res: &S::Res) -> Option<Vec<Mode>> {
+        let _ = res;
+        None
+    }
+}
+
+/// Client error for a multi pattern request
+#[derive(Debug)]
+pub enum Error<C: ConnectionErrors> {
+    /// The server does not support the selected mode for this message, see
+    /// [MultiMsg::unsupported]
+    Unsupported {
+        /// The mode the client selected
+        mode: Mode,
+        /// The modes the server supports
+        supported: Vec<Mode>,
+    },
+    /// Unable to open a substream at all
+    Open(C::OpenError),
+    /// Unable to send the request to the server
+    Send(C::SendError),
+    /// Server closed the stream before sending a response
+    EarlyClose,
+    /// Unable to receive the response from the server
+    RecvError(C::RecvError),
+    /// Unexpected response from the server
+    DowncastError,
+    /// The call did not complete within the timeout, see
+    /// [RpcClientBuilder::default_timeout](crate::client::RpcClientBuilder::default_timeout)
+    Timeout,
+}
+
+impl<C: ConnectionErrors> fmt::Display for Error<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl<C: ConnectionErrors> error::Error for Error<C> {}
+
+impl<S, C> RpcClient<S, C>
+where
+    S: Service,
+    C: Connector<S>,
+{
+    /// Call a multi pattern message as a rpc, single request, single response
+    pub async fn multi_rpc<M>(&self, msg: M) -> result::Result<M::Response, Error<C>>
+    where
+        M: MultiMsg<S>,
+        Request<M>: Msg<S, Pattern = Multi>,
+    {
+        let msg = Request {
+            mode: Mode::Rpc,
+            msg,
+        };
+        let msg = msg.into();
+        self.timed(None, async move {
+            let (mut send, mut recv) = self.source.open().await.map_err(Error::Open)?;
+            send.send(msg).await.map_err(Error::<C>::Send)?;
+            let res = recv
+                .next()
+                .await
+                .ok_or(Error::<C>::EarlyClose)?
+                .map_err(Error::<C>::RecvError)?;
+            // keep send alive until we have the answer
+            drop(send);
+            if let Some(supported) = M::is_unsupported(&res) {
+                return Err(Error::Unsupported {
+                    mode: Mode::Rpc,
+                    supported,
+                });
+            }
+            M::Response::try_from(res).map_err(|_| Error::DowncastError)
+        })
+        .await
+        .unwrap_or(Err(Error::Timeout))
+    }
+
+    /// Call a multi pattern message as a server streaming request
+    pub async fn multi_server_streaming<M>(
+        &self,
+        msg: M,
+    ) -> result::Result<BoxStreamSync<'static, result::Result<M::Item, ItemError<C>>>, Error<C>>
+    where
+        M: MultiMsg<S>,
+        Request<M>: Msg<S, Pattern = Multi>,
+    {
+        let msg = Request {
+            mode: Mode::ServerStreaming,
+            msg,
+        };
+        let msg = msg.into();
+        // if the server can reject the mode, the rejection is the first response
+        let can_reject = M::unsupported(&[]).is_some();
+        let (send, recv, first) = self
+            .timed(None, async move {
+                let (mut send, mut recv) = self.source.open().await.map_err(Error::Open)?;
+                send.send(msg).await.map_err(Error::<C>::Send)?;
+                let first = match can_reject {
+                    true => recv.next().await,
+                    false => None,
+                };
+                Ok((send, recv, first))
+            })
+            .await
+            .unwrap_or(Err(Error::Timeout))?;
+        if let Some(supported) = first
+            .as_ref()
+            .and_then(|res| res.as_ref().ok())
+            .and_then(M::is_unsupported)
+        {
+            return Err(Error::Unsupported {
+                mode: Mode::ServerStreaming,
+                supported,
+            });
+        }
+        let recv = futures_lite::stream::iter(first).chain(recv);
+        let recv = recv.map(move |x| match x {
+            Ok(msg) => M::Item::try_from(msg).map_err(|_| ItemError::DowncastError),
+            Err(e) => Err(ItemError::RecvError(e)),
+        });
+        // keep send alive so the request on the server side does not get cancelled
+        let recv = Box::pin(DeferDrop(recv, send));
+        Ok(recv)
+    }
+}
+
+impl<S, C> RpcChannel<S, C>
+where
+    S: Service,
+    C: StreamTypes<In = S::Req, Out = S::Res>,
+{
+    /// handle the multi pattern message `M` using the given functions on the target object
+    ///
+    /// `rpc` is used if the client selected [Mode::Rpc], `stream` if the client selected
+    /// [Mode::ServerStreaming]. Requests in a mode that is not in `supported` are
+    /// rejected with the response created by [MultiMsg::unsupported], which tells
+    /// the client the supported modes. If the message has none, the substream is
+    /// closed and this fails with [RpcServerError::UnexpectedStartMessage].
+    ///
+    /// If you want to support concurrent requests, you need to spawn this on a tokio task yourself.
+    pub async fn multi<M, F1, Fut, F2, Str, T>(
+        self,
+        req: Request<M>,
+        supported: &[Mode],
+        target: T,
+        rpc: F1,
+        stream: F2,
+    ) -> result::Result<(), RpcServerError<C>>
+    where
+        M: MultiMsg<S>,
+        Request<M>: Msg<S, Pattern = Multi>,
+        F1: FnOnce(T, M) -> Fut,
+        Fut: Future<Output = M::Response>,
+        F2: FnOnce(T, M) -> Str + Send + 'static,
+        Str: Stream<Item = M::Item> + Send + 'static,
+        T: Send + 'static,
+    {
+        let Request { mode, msg } = req;
+        let Self {
+            mut send, mut recv, ..
+        } = self;
+        if !supported.contains(&mode) {
+            let Some(res) = M::unsupported(supported) else {
+                return Err(RpcServerError::UnexpectedStartMessage);
+            };
+            return send.send(res).await.map_err(RpcServerError::SendError);
+        }
+        // cancel if we get an update, no matter what it is
+        let cancel = recv
+            .next()
+            .map(|_| RpcServerError::UnexpectedUpdateMessage::<C>);
+        // race the computation and the cancellation
+        race2(cancel.map(Err), async move {
+            match mode {
+                Mode::Rpc => {
+                    let res = rpc(target, msg).await.into();
+                    send.send(res).await.map_err(RpcServerError::SendError)
+                }
+                Mode::ServerStreaming => {
+                    let responses = stream(target, msg);
+                    tokio::pin!(responses);
+                    while let Some(response) = responses.next().await {
+                        send.send(response.into())
+                            .await
+                            .map_err(RpcServerError::SendError)?;
+                    }
+                    Ok(())
+                }
+            }
+        })
+        .instrument(method_span::<S, Request<M>>())
+        .await
+    }
+}
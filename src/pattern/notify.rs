@@ -0,0 +1,158 @@
+//! Notify interaction pattern.
+//!
+//! A notification is a single message from the client to the server, without a
+//! response. Messages that set [Msg::DATAGRAM] are sent as unreliable datagrams
+//! if the transport supports them, which avoids the cost of opening a substream
+//! and of retransmissions. This is useful for high frequency updates where only
+//! the latest value matters, like game state or metrics ticks.
+//!
+//! Other notifications are sent on a unidirectional substream, so there is no
+//! return stream and no round trip. On transports with unidirectional substreams,
+//! like quinn and iroh, they arrive at [RpcServer::recv_notification]. On other
+//! transports, they arrive like any other request and are handled with
+//! [RpcChannel::notify]. Notifications that are sent as datagrams arrive at
+//! [RpcServer::recv_datagram].
+//!
+//! [RpcServer::accept_loop] receives both kinds of notifications as well, and
+//! passes them to the handler installed with
+//! [RpcServer::with_notification_handler].
+
+use std::{error, fmt, result};
+
+use futures_lite::Future;
+use futures_util::SinkExt;
+use tracing::Instrument;
+
+use crate::{
+    message::{method_span, InteractionPattern, Msg},
+    server::{RpcChannel, RpcServerError},
+    transport::{self, ConnectionErrors, StreamTypes},
+    Connector, Listener, RpcClient, RpcServer, Service,
+};
+
+/// Notify interaction pattern
+///
+/// There is only one request and no response.
+#[derive(Debug, Clone, Copy)]
+pub struct Notify;
+impl InteractionPattern for Notify {}
+
+/// A notification message, without a response
+pub trait NotifyMsg<S: Service>: Msg<S, Pattern = Notify> {}
+
+/// Client error when sending a notification
+#[derive(Debug)]
+pub enum Error<C: ConnectionErrors> {
+    /// Unable to open a substream at all
+    Open(C::OpenError),
+    /// Unable to send the notification to the server
+    Send(C::SendError),
+    /// The notification was not sent within the timeout, see
+    /// [RpcClientBuilder::default_timeout](crate::client::RpcClientBuilder::default_timeout)
+    Timeout,
+}
+
+impl<C: ConnectionErrors> fmt::Display for Error<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl<C: ConnectionErrors> error::Error for Error<C> {}
+
+impl<S, C> RpcClient<S, C>
+where
+    S: Service,
+    C: Connector<S>,
+{
+    /// Send a notification to the server, without waiting for it to be handled
+    ///
+    /// If the message may be sent as a datagram, see [Msg::DATAGRAM], and the
+    /// transport supports datagrams, it is sent as a single datagram. Delivery of
+    /// datagrams is not guaranteed. Otherwise the message is sent on its own
+    /// unidirectional substream, which is closed right away.
+    pub async fn notify<M>(&self, msg: M) -> result::Result<(), Error<C>>
+    where
+        M: NotifyMsg<S>,
+    {
+        let mut msg: S::Req = msg.into();
+        self.timed(None, async move {
+            if M::DATAGRAM {
+                match transport::Connector::send_datagram(&self.source, msg)
+                    .await
+                    .map_err(Error::Send)?
+                {
+                    None => return Ok(()),
+                    Some(returned) => msg = returned,
+                }
+            }
+            let mut send = self.source.open_uni().await.map_err(Error::Open)?;
+            send.send(msg).await.map_err(Error::<C>::Send)?;
+            send.close().await.map_err(Error::<C>::Send)?;
+            Ok(())
+        })
+        .await
+        .unwrap_or(Err(Error::Timeout))
+    }
+}
+
+impl<S, C> RpcChannel<S, C>
+where
+    S: Service,
+    C: StreamTypes<In = S::Req, Out = S::Res>,
+{
+    /// handle the notification of type `M` using the given function on the target object
+    ///
+    /// Nothing is sent back to the client.
+    pub async fn notify<M, F, Fut, T>(
+        self,
+        req: M,
+        target: T,
+        f: F,
+    ) -> result::Result<(), RpcServerError<C>>
+    where
+        M: NotifyMsg<S>,
+        F: FnOnce(T, M) -> Fut,
+        Fut: Future<Output = ()>,
+        T: Send + 'static,
+    {
+        f(target, req).instrument(method_span::<S, M>()).await;
+        Ok(())
+    }
+}
+
+impl<S: Service, C: Listener<S>> RpcServer<S, C> {
+    /// Receive the next notification that was sent on a unidirectional substream
+    ///
+    /// For transports without unidirectional substreams, this never completes,
+    /// and notifications arrive at [RpcServer::accept] instead.
+    ///
+    /// This reads one substream at a time, so a client that opens a substream and
+    /// does not send on it holds up the notifications of other clients.
+    /// [RpcServer::accept_loop] reads them concurrently.
+    ///
+    /// Returns [RpcServerError::Rejected] if a middleware installed with
+    /// [RpcServer::with_middleware] rejects the notification.
+    pub async fn recv_notification(&self) -> result::Result<S::Req, RpcServerError<C>> {
+        let recv = transport::Listener::accept_uni(self.as_ref())
+            .await
+            .map_err(RpcServerError::Accept)?;
+        self.read_notification(recv).await
+    }
+
+    /// Receive the next notification that was sent as a datagram
+    ///
+    /// Datagrams from all connections of the listener are queued until they are
+    /// received here, and dropped if the queue is full. For transports without
+    /// datagram support, this never completes.
+    ///
+    /// Returns [RpcServerError::Rejected] if a middleware installed with
+    /// [RpcServer::with_middleware] rejects the notification.
+    pub async fn recv_datagram(&self) -> result::Result<S::Req, RpcServerError<C>> {
+        let mut msg = transport::Listener::recv_datagram(self.as_ref())
+            .await
+            .map_err(RpcServerError::RecvError)?;
+        self.check_notification(&mut msg)?;
+        Ok(msg)
+    }
+}
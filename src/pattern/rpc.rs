@@ -1,4 +1,18 @@
 //! RPC interaction pattern.
+//!
+//! # Fallible responses
+//!
+//! There is no separate pattern for requests that can fail. Set the response of
+//! the [RpcMsg] to a [Result], with a serializable error type, and the error
+//! travels over the wire like any other response:
+//!
+//! - The handler returns the [Result]. Use [RpcChannel::rpc_map_err] to write it
+//!   with a more convenient error type, like `anyhow::Error`.
+//! - [RpcClient::rpc] returns the [Result] inside the network [Result], and
+//!   [RpcClient::try_rpc] merges both into a [TryError].
+//!
+//! For the server streaming pattern, see
+//! [TryServerStreamingMsg](crate::pattern::try_server_streaming::TryServerStreamingMsg).
 
 use std::{
     error,
@@ -8,11 +22,15 @@ use std::{
 
 use futures_lite::{Future, StreamExt};
 use futures_util::{FutureExt, SinkExt};
+use tracing::Instrument;
 
 use crate::{
-    message::{InteractionPattern, Msg},
+    message::{method_span, InteractionPattern, Msg},
     server::{race2, RpcChannel, RpcServerError},
-    transport::{ConnectionErrors, StreamTypes},
+    transport::{
+        meta::{MetaOpen, Metadata},
+        ConnectionErrors, StreamTypes,
+    },
     Connector, RpcClient, Service,
 };
 
@@ -27,7 +45,8 @@ impl InteractionPattern for Rpc {}
 ///
 /// Since this is the most common interaction pattern, this also implements [Msg] for you
 /// automatically, with the interaction pattern set to [Rpc]. This is to reduce boilerplate
-/// when defining rpc messages.
+/// when defining rpc messages. The [Msg::NAME] and [Msg::DATAGRAM] of rpc messages are
+/// the defaults, so their [method name](crate::message::method_name) is their type name.
 pub trait RpcMsg<S: Service>: Msg<S, Pattern = Rpc> {
     /// The type for the response
     ///
@@ -39,6 +58,13 @@ pub trait RpcMsg<S: Service>: Msg<S, Pattern = Rpc> {
 impl<T: RpcMsg<S>, S: Service> Msg<S> for T {
     type Pattern = Rpc;
 }
+/// Marker for rpc messages that can safely be handled more than once
+///
+/// Only these messages can be retried, see [RpcClient::rpc_retry]. A request is
+/// idempotent if handling it twice has the same effect as handling it once, e.g.
+/// a lookup or setting a value.
+pub trait IdempotentMsg<S: Service>: RpcMsg<S> + Clone {}
+
 /// Client error. All client DSL methods return a `Result` with this error type.
 #[derive(Debug)]
 pub enum Error<C: ConnectionErrors> {
@@ -52,6 +78,8 @@ pub enum Error<C: ConnectionErrors> {
     RecvError(C::RecvError),
     /// Unexpected response from the server
     DowncastError,
+    /// The call did not complete within the timeout, see [RpcClient::rpc_timeout]
+    Timeout,
 }
 
 impl<C: ConnectionErrors> fmt::Display for Error<C> {
@@ -62,19 +90,212 @@ impl<C: ConnectionErrors> fmt::Display for Error<C> {
 
 impl<C: ConnectionErrors> error::Error for Error<C> {}
 
+/// Client error for a rpc call with a fallible response.
+///
+/// This combines the network errors of [Error] with the application error that was
+/// returned by the handler on the server side. See [RpcClient::try_rpc].
+#[derive(Debug)]
+pub enum TryError<C: ConnectionErrors, E> {
+    /// Unable to open a substream at all
+    Open(C::OpenError),
+    /// Unable to send the request to the server
+    Send(C::SendError),
+    /// Server closed the stream before sending a response
+    EarlyClose,
+    /// Unable to receive the response from the server
+    RecvError(C::RecvError),
+    /// Unexpected response from the server
+    DowncastError,
+    /// The call did not complete within the timeout
+    Timeout,
+    /// The handler on the server side returned an error
+    Application(E),
+}
+
+impl<C: ConnectionErrors, E> From<Error<C>> for TryError<C, E> {
+    fn from(e: Error<C>) -> Self {
+        match e {
+            Error::Open(e) => Self::Open(e),
+            Error::Send(e) => Self::Send(e),
+            Error::EarlyClose => Self::EarlyClose,
+            Error::RecvError(e) => Self::RecvError(e),
+            Error::DowncastError => Self::DowncastError,
+            Error::Timeout => Self::Timeout,
+        }
+    }
+}
+
+impl<C: ConnectionErrors, E: Debug> fmt::Display for TryError<C, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl<C: ConnectionErrors, E: Debug> error::Error for TryError<C, E> {}
+
 impl<S, C> RpcClient<S, C>
 where
     S: Service,
     C: Connector<S>,
 {
     /// RPC call to the server, single request, single response
+    ///
+    /// Fails with [Error::Timeout] if the client has a
+    /// [default timeout](crate::client::RpcClientBuilder::default_timeout) and there
+    /// is no response within it.
     pub async fn rpc<M>(&self, msg: M) -> result::Result<M::Response, Error<C>>
     where
         M: RpcMsg<S>,
     {
-        let msg = msg.into();
-        let (mut send, mut recv) = self.source.open().await.map_err(Error::Open)?;
-        send.send(msg).await.map_err(Error::<C>::Send)?;
+        self.timed(None, self.rpc_inner(msg))
+            .await
+            .unwrap_or(Err(Error::Timeout))
+    }
+
+    /// Send a batch of RPC calls and wait for all responses
+    ///
+    /// At most `concurrency` calls, but at least one, are in flight at the same
+    /// time. The results are in the same order as the requests, and a failed call
+    /// does not affect the others.
+    pub async fn rpc_batch<M>(
+        &self,
+        msgs: Vec<M>,
+        concurrency: usize,
+    ) -> Vec<result::Result<M::Response, Error<C>>>
+    where
+        M: RpcMsg<S>,
+    {
+        let calls = futures_lite::stream::iter(msgs).map(|msg| self.rpc(msg));
+        futures_util::StreamExt::buffered(calls, concurrency.max(1))
+            .collect()
+            .await
+    }
+
+    /// RPC call to the server that fails with [Error::Timeout] if there is no
+    /// response within the given timeout
+    ///
+    /// The timeout covers the whole call, including opening the substream. It
+    /// overrides the default timeout of the client.
+    #[cfg(feature = "rt")]
+    #[cfg_attr(
+        quicrpc_docsrs,
+        doc(cfg(any(feature = "rt-tokio", feature = "rt-smol", feature = "rt-async-std")))
+    )]
+    pub async fn rpc_timeout<M>(
+        &self,
+        msg: M,
+        timeout: std::time::Duration,
+    ) -> result::Result<M::Response, Error<C>>
+    where
+        M: RpcMsg<S>,
+    {
+        self.timed(Some(timeout), self.rpc_inner(msg))
+            .await
+            .unwrap_or(Err(Error::Timeout))
+    }
+
+    /// RPC call to the server for an idempotent message, that is retried as
+    /// configured with [RpcClient::with_retry]
+    ///
+    /// Calls that failed to open a substream or to send the request are retried,
+    /// and calls where the server closed the substream without a response if the
+    /// [RetryPolicy](crate::client::RetryPolicy) says so. Without a policy, this is
+    /// the same as [RpcClient::rpc].
+    #[cfg(feature = "rt")]
+    #[cfg_attr(
+        quicrpc_docsrs,
+        doc(cfg(any(feature = "rt-tokio", feature = "rt-smol", feature = "rt-async-std")))
+    )]
+    pub async fn rpc_retry<M>(&self, msg: M) -> result::Result<M::Response, Error<C>>
+    where
+        M: IdempotentMsg<S>,
+    {
+        let mut retries = 0;
+        loop {
+            let err = match self.rpc(msg.clone()).await {
+                Ok(res) => return Ok(res),
+                Err(err) => err,
+            };
+            let Some(policy) = self.retry.filter(|policy| retries < policy.max_retries) else {
+                return Err(err);
+            };
+            match err {
+                Error::Open(_) | Error::Send(_) => {}
+                Error::EarlyClose if policy.retry_early_close => {}
+                err => return Err(err),
+            }
+            retries += 1;
+            tracing::debug!("retrying rpc call after error: {err}");
+            crate::rt::sleep(policy.delay(retries)).await;
+        }
+    }
+
+    /// RPC call to the server that carries [Metadata] along with the request
+    ///
+    /// This requires a connector that can send metadata, see [transport::meta](crate::transport::meta).
+    /// The server reads it with [RpcChannel::metadata].
+    pub async fn rpc_with_meta<M>(
+        &self,
+        msg: M,
+        meta: Metadata,
+    ) -> result::Result<M::Response, Error<C>>
+    where
+        M: RpcMsg<S>,
+        C: MetaOpen,
+    {
+        let call = async move {
+            let channel = self
+                .source
+                .open_with_meta(meta)
+                .await
+                .map_err(Error::Open)?;
+            Self::rpc_on(channel, msg).await
+        };
+        self.timed(None, call).await.unwrap_or(Err(Error::Timeout))
+    }
+
+    /// RPC call to the server on a channel with the given priority
+    ///
+    /// On the quinn and iroh transports, the priority is mapped to the QUIC stream
+    /// priority, so the request of a latency sensitive call is not queued behind the
+    /// data of bulk transfers on the same connection. The default priority is 0, and
+    /// higher values are sent first. Other transports ignore the priority, see
+    /// [Connector::open_with_priority](crate::transport::Connector::open_with_priority).
+    pub async fn rpc_with_priority<M>(
+        &self,
+        msg: M,
+        priority: i32,
+    ) -> result::Result<M::Response, Error<C>>
+    where
+        M: RpcMsg<S>,
+    {
+        let call = async move {
+            let channel = self
+                .source
+                .open_with_priority(priority)
+                .await
+                .map_err(Error::Open)?;
+            Self::rpc_on(channel, msg).await
+        };
+        self.timed(None, call).await.unwrap_or(Err(Error::Timeout))
+    }
+
+    async fn rpc_inner<M>(&self, msg: M) -> result::Result<M::Response, Error<C>>
+    where
+        M: RpcMsg<S>,
+    {
+        let channel = self.source.open().await.map_err(Error::Open)?;
+        Self::rpc_on(channel, msg).await
+    }
+
+    async fn rpc_on<M>(
+        (mut send, mut recv): (C::SendSink, C::RecvStream),
+        msg: M,
+    ) -> result::Result<M::Response, Error<C>>
+    where
+        M: RpcMsg<S>,
+    {
+        send.send(msg.into()).await.map_err(Error::<C>::Send)?;
         let res = recv
             .next()
             .await
@@ -84,6 +305,48 @@ where
         drop(send);
         M::Response::try_from(res).map_err(|_| Error::DowncastError)
     }
+
+    /// RPC call to the server that hands back the channel after the response
+    ///
+    /// This is useful to negotiate something with a typed request and then continue
+    /// on the same channel with a different protocol, e.g. a bulk transfer. The
+    /// server handles the request with [RpcChannel::rpc_raw].
+    ///
+    /// For the quinn and iroh transports, the send and receive side can be turned
+    /// into the raw QUIC streams with `into_inner` and `into_parts`.
+    pub async fn rpc_raw<M>(
+        &self,
+        msg: M,
+    ) -> result::Result<(M::Response, C::SendSink, C::RecvStream), Error<C>>
+    where
+        M: RpcMsg<S>,
+    {
+        let call = async move {
+            let msg = msg.into();
+            let (mut send, mut recv) = self.source.open().await.map_err(Error::Open)?;
+            send.send(msg).await.map_err(Error::<C>::Send)?;
+            let res = recv
+                .next()
+                .await
+                .ok_or(Error::<C>::EarlyClose)?
+                .map_err(Error::<C>::RecvError)?;
+            let res = M::Response::try_from(res).map_err(|_| Error::DowncastError)?;
+            Ok((res, send, recv))
+        };
+        self.timed(None, call).await.unwrap_or(Err(Error::Timeout))
+    }
+
+    /// RPC call to the server for a message with a fallible response
+    ///
+    /// This is available for messages where the response is a [Result]. An error returned
+    /// by the handler on the server side is serialized and surfaced as
+    /// [TryError::Application], so it can be handled separately from network errors.
+    pub async fn try_rpc<M, R, E>(&self, msg: M) -> result::Result<R, TryError<C, E>>
+    where
+        M: RpcMsg<S, Response = result::Result<R, E>>,
+    {
+        self.rpc(msg).await?.map_err(TryError::Application)
+    }
 }
 
 impl<S, C> RpcChannel<S, C>
@@ -122,9 +385,36 @@ where
             // send it and return the error if any
             send.send(res).await.map_err(RpcServerError::SendError)
         })
+        .instrument(method_span::<S, M>())
         .await
     }
 
+    /// handle the message of type `M` like [Self::rpc], and hand back the channel
+    /// once the response is sent
+    ///
+    /// Unlike [Self::rpc], anything the client sends after the request does not
+    /// cancel the handler, since the client may already continue with a different
+    /// protocol. See [RpcClient::rpc_raw] for the client side.
+    pub async fn rpc_raw<M, F, Fut, T>(
+        self,
+        req: M,
+        target: T,
+        f: F,
+    ) -> result::Result<(C::SendSink, C::RecvStream), RpcServerError<C>>
+    where
+        M: RpcMsg<S>,
+        F: FnOnce(T, M) -> Fut,
+        Fut: Future<Output = M::Response>,
+        T: Send + 'static,
+    {
+        let (mut send, recv) = self.into_raw();
+        let res = f(target, req).instrument(method_span::<S, M>()).await;
+        send.send(res.into())
+            .await
+            .map_err(RpcServerError::SendError)?;
+        Ok((send, recv))
+    }
+
     /// A rpc call that also maps the error from the user type to the wire type
     ///
     /// This is useful if you want to write your function with a convenient error type like anyhow::Error,
@@ -1,17 +1,25 @@
 //! Server streaming interaction pattern.
 
 use std::{
+    collections::VecDeque,
     error,
     fmt::{self, Debug},
+    future::poll_fn,
+    pin::Pin,
     result,
+    task::{ready, Context, Poll},
 };
 
+use futures_sink::Sink;
+
 use futures_lite::{Stream, StreamExt};
 use futures_util::{FutureExt, SinkExt, TryFutureExt};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
 
 use crate::{
-    client::{BoxStreamSync, DeferDrop},
-    message::{InteractionPattern, Msg},
+    client::{first_response, BoxStreamSync, DeferDrop},
+    message::{method_span, InteractionPattern, Msg},
     server::{race2, RpcChannel, RpcServerError},
     transport::{ConnectionErrors, Connector, StreamTypes},
     RpcClient, Service,
@@ -30,6 +38,58 @@ pub trait ServerStreamingMsg<S: Service>: Msg<S, Pattern = ServerStreaming> {
     ///
     /// For requests that can produce errors, this can be set to [Result<T, E>](std::result::Result).
     type Response: Into<S::Res> + TryFrom<S::Res> + Send + 'static;
+
+    /// What to do when the client does not keep up with the responses
+    ///
+    /// The default is [SlowConsumer::Block].
+    fn slow_consumer() -> SlowConsumer<Self::Response> {
+        SlowConsumer::Block
+    }
+}
+
+/// Policy for a server streaming request when the client can not keep up
+///
+/// A client is too slow when the transport does not accept responses as fast as
+/// the handler produces them.
+#[derive(Debug)]
+pub enum SlowConsumer<T> {
+    /// Stop polling the response stream until the client catches up
+    Block,
+    /// Queue up to `buffer` responses, then drop the oldest queued response
+    ///
+    /// When responses were dropped, the response created by `lagged` from the
+    /// number of dropped responses is sent before the next queued response.
+    DropOldest {
+        /// Maximum number of queued responses
+        buffer: usize,
+        /// Create the lag notice
+        lagged: fn(u64) -> T,
+    },
+    /// Queue up to `buffer` responses, then terminate the stream
+    ///
+    /// The queued responses are discarded, and the response created by `too_slow`
+    /// is sent as the last response. The handler fails with
+    /// [RpcServerError::SlowConsumer].
+    Terminate {
+        /// Maximum number of queued responses
+        buffer: usize,
+        /// Create the error response
+        too_slow: fn() -> T,
+    },
+}
+
+/// A server streaming message where the client grants credits for responses
+///
+/// The server only sends as many responses as the client requested using
+/// [CreditedStream::request], so a slow consumer never has more than the
+/// requested responses buffered. Credits are sent as requests on the substream
+/// of the call, so they must be distinguishable from other requests.
+pub trait CreditedServerStreamingMsg<S: Service>: ServerStreamingMsg<S> {
+    /// Create the request that grants the given number of credits
+    fn credit(n: u64) -> S::Req;
+
+    /// Get the number of granted credits if the request grants credits
+    fn credits(req: &S::Req) -> Option<u64>;
 }
 
 /// Server error when accepting a server streaming request
@@ -39,6 +99,9 @@ pub enum Error<C: ConnectionErrors> {
     Open(C::OpenError),
     /// Unable to send the request to the server
     Send(C::SendError),
+    /// The request was not sent, or the first response not received, within the
+    /// timeout, see [RpcClient::server_streaming_timeout]
+    Timeout,
 }
 
 impl<S: Connector> fmt::Display for Error<S> {
@@ -76,20 +139,119 @@ where
         &self,
         msg: M,
     ) -> result::Result<BoxStreamSync<'static, result::Result<M::Response, ItemError<C>>>, Error<C>>
+    where
+        M: ServerStreamingMsg<S>,
+    {
+        self.timed(None, self.server_streaming_inner(msg, false))
+            .await
+            .unwrap_or(Err(Error::Timeout))
+    }
+
+    async fn server_streaming_inner<M>(
+        &self,
+        msg: M,
+        wait: bool,
+    ) -> result::Result<BoxStreamSync<'static, result::Result<M::Response, ItemError<C>>>, Error<C>>
     where
         M: ServerStreamingMsg<S>,
     {
         let msg = msg.into();
         let (mut send, recv) = self.source.open().await.map_err(Error::Open)?;
         send.send(msg).map_err(Error::<C>::Send).await?;
+        let recv = first_response(recv, wait).await;
         let recv = recv.map(move |x| match x {
             Ok(msg) => M::Response::try_from(msg).map_err(|_| ItemError::DowncastError),
             Err(e) => Err(ItemError::RecvError(e)),
         });
-        // keep send alive so the request on the server side does not get cancelled
+        // keep send alive so the request on the server side does not get cancelled.
+        // dropping the stream closes the send side, which cancels the request.
         let recv = Box::pin(DeferDrop(recv, send));
         Ok(recv)
     }
+
+    /// Server streaming call that fails with [Error::Timeout] if the first
+    /// response is not received within the given timeout
+    ///
+    /// The timeout covers opening the substream, sending the request and
+    /// receiving the first response, not the responses after that. It overrides
+    /// the default timeout of the client.
+    #[cfg(feature = "rt")]
+    #[cfg_attr(
+        quicrpc_docsrs,
+        doc(cfg(any(feature = "rt-tokio", feature = "rt-smol", feature = "rt-async-std")))
+    )]
+    pub async fn server_streaming_timeout<M>(
+        &self,
+        msg: M,
+        timeout: std::time::Duration,
+    ) -> result::Result<BoxStreamSync<'static, result::Result<M::Response, ItemError<C>>>, Error<C>>
+    where
+        M: ServerStreamingMsg<S>,
+    {
+        self.timed(Some(timeout), self.server_streaming_inner(msg, true))
+            .await
+            .unwrap_or(Err(Error::Timeout))
+    }
+
+    /// Server streaming call where responses are only sent after they were
+    /// requested using [CreditedStream::request]
+    ///
+    /// No responses are requested initially.
+    pub async fn server_streaming_credited<M>(
+        &self,
+        msg: M,
+    ) -> result::Result<CreditedStream<C, M::Response>, Error<C>>
+    where
+        M: CreditedServerStreamingMsg<S>,
+    {
+        self.timed(None, async move {
+            let msg = msg.into();
+            let (mut send, recv) = self.source.open().await.map_err(Error::Open)?;
+            send.send(msg).map_err(Error::<C>::Send).await?;
+            let recv = Box::pin(recv.map(move |x| match x {
+                Ok(msg) => M::Response::try_from(msg).map_err(|_| ItemError::DowncastError),
+                Err(e) => Err(ItemError::RecvError(e)),
+            }));
+            Ok(CreditedStream {
+                send,
+                recv,
+                credit: M::credit,
+            })
+        })
+        .await
+        .unwrap_or(Err(Error::Timeout))
+    }
+}
+
+/// Responses of a credited server streaming call, see
+/// [RpcClient::server_streaming_credited]
+///
+/// Dropping the stream cancels the request on the server side.
+pub struct CreditedStream<C: StreamTypes, T> {
+    send: C::SendSink,
+    recv: BoxStreamSync<'static, result::Result<T, ItemError<C>>>,
+    credit: fn(u64) -> C::Out,
+}
+
+impl<C: StreamTypes, T> fmt::Debug for CreditedStream<C, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CreditedStream").finish_non_exhaustive()
+    }
+}
+
+impl<C: StreamTypes, T> CreditedStream<C, T> {
+    /// Request `n` more responses from the server
+    pub async fn request(&mut self, n: u64) -> result::Result<(), C::SendError> {
+        self.send.send((self.credit)(n)).await
+    }
+}
+
+impl<C: StreamTypes, T> Stream for CreditedStream<C, T> {
+    type Item = result::Result<T, ItemError<C>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.recv.as_mut().poll_next(cx)
+    }
 }
 
 impl<S, C> RpcChannel<S, C>
@@ -111,29 +273,173 @@ where
         F: FnOnce(T, M) -> Str + Send + 'static,
         Str: Stream<Item = M::Response> + Send + 'static,
         T: Send + 'static,
+    {
+        self.server_streaming_cancellable(req, target, move |target, req, _| f(target, req))
+            .await
+    }
+
+    /// handle the message M using the given function on the target object,
+    /// passing a token that is cancelled when the client goes away
+    ///
+    /// The token is cancelled whenever the request ends with an error, most
+    /// commonly because the client dropped the response stream. The response
+    /// stream is dropped at the same time, so the token is only useful for work
+    /// that outlives it, e.g. tasks spawned by the handler.
+    pub async fn server_streaming_cancellable<M, F, Str, T>(
+        self,
+        req: M,
+        target: T,
+        f: F,
+    ) -> result::Result<(), RpcServerError<C>>
+    where
+        M: ServerStreamingMsg<S>,
+        F: FnOnce(T, M, CancellationToken) -> Str + Send + 'static,
+        Str: Stream<Item = M::Response> + Send + 'static,
+        T: Send + 'static,
     {
         let Self {
             mut send, mut recv, ..
         } = self;
+        let token = CancellationToken::new();
         // cancel if we get an update, no matter what it is
         let cancel = recv
             .next()
             .map(|_| RpcServerError::UnexpectedUpdateMessage::<C>);
         // race the computation and the cancellation
-        race2(cancel.map(Err), async move {
+        let handler_token = token.clone();
+        let res = race2(cancel.map(Err), async move {
             // get the response
-            let responses = f(target, req);
+            let responses = f(target, req, handler_token);
             tokio::pin!(responses);
-            while let Some(response) = responses.next().await {
-                // turn into a S::Res so we can send it
-                let response = response.into();
-                // send it and return the error if any
-                send.send(response)
+            let (buffer, lagged, too_slow) = match M::slow_consumer() {
+                SlowConsumer::Block => {
+                    while let Some(response) = responses.next().await {
+                        // turn into a S::Res so we can send it
+                        let response = response.into();
+                        // send it and return the error if any
+                        send.send(response)
+                            .await
+                            .map_err(RpcServerError::SendError)?;
+                    }
+                    return Ok(());
+                }
+                SlowConsumer::DropOldest { buffer, lagged } => (buffer.max(1), Some(lagged), None),
+                SlowConsumer::Terminate { buffer, too_slow } => {
+                    (buffer.max(1), None, Some(too_slow))
+                }
+            };
+            let mut queue = VecDeque::new();
+            let mut dropped = 0u64;
+            let mut done = false;
+            // returns true if the queue overflowed and the stream must be terminated
+            let overflow = poll_fn(|cx| {
+                // queue all responses that are available
+                while !done {
+                    match responses.as_mut().poll_next(cx) {
+                        Poll::Ready(Some(response)) => {
+                            if queue.len() >= buffer {
+                                if too_slow.is_some() {
+                                    return Poll::Ready(Ok(true));
+                                }
+                                queue.pop_front();
+                                dropped += 1;
+                            }
+                            queue.push_back(response);
+                        }
+                        Poll::Ready(None) => done = true,
+                        Poll::Pending => break,
+                    }
+                }
+                // send as many of them as the transport accepts
+                let mut send = Pin::new(&mut send);
+                while dropped > 0 || !queue.is_empty() {
+                    ready!(send.as_mut().poll_ready(cx))?;
+                    let response = match lagged.filter(|_| dropped > 0) {
+                        Some(lagged) => lagged(std::mem::take(&mut dropped)),
+                        None => queue.pop_front().expect("queue is not empty"),
+                    };
+                    send.as_mut().start_send(response.into())?;
+                }
+                ready!(send.poll_flush(cx))?;
+                if done {
+                    Poll::Ready(Ok(false))
+                } else {
+                    Poll::Pending
+                }
+            })
+            .await
+            .map_err(RpcServerError::SendError)?;
+            if let (true, Some(too_slow)) = (overflow, too_slow) {
+                send.send(too_slow().into())
                     .await
                     .map_err(RpcServerError::SendError)?;
+                return Err(RpcServerError::SlowConsumer);
             }
             Ok(())
         })
+        .instrument(method_span::<S, M>())
+        .await;
+        if res.is_err() {
+            token.cancel();
+        }
+        res
+    }
+
+    /// handle the message M using the given function on the target object,
+    /// sending responses only as far as the client granted credits
+    ///
+    /// The response stream is polled at most one response ahead of the credits, so
+    /// that the end of the stream is seen even when the client has no credits left.
+    /// See [CreditedServerStreamingMsg] for details.
+    pub async fn server_streaming_credited<M, F, Str, T>(
+        self,
+        req: M,
+        target: T,
+        f: F,
+    ) -> result::Result<(), RpcServerError<C>>
+    where
+        M: CreditedServerStreamingMsg<S>,
+        F: FnOnce(T, M) -> Str + Send + 'static,
+        Str: Stream<Item = M::Response> + Send + 'static,
+        T: Send + 'static,
+    {
+        let Self {
+            mut send, mut recv, ..
+        } = self;
+        async move {
+            let responses = f(target, req);
+            tokio::pin!(responses);
+            let mut credits = 0u64;
+            // the next response, read ahead while there are no credits
+            let mut next: Option<M::Response> = None;
+            loop {
+                if credits > 0 {
+                    if let Some(response) = next.take() {
+                        credits -= 1;
+                        send.send(response.into())
+                            .await
+                            .map_err(RpcServerError::SendError)?;
+                    }
+                }
+                tokio::select! {
+                    biased;
+                    update = recv.next() => match update {
+                        Some(Ok(update)) => match M::credits(&update) {
+                            Some(n) => credits = credits.saturating_add(n),
+                            None => return Err(RpcServerError::UnexpectedUpdateMessage),
+                        },
+                        Some(Err(cause)) => return Err(RpcServerError::RecvError(cause)),
+                        // the client dropped the stream
+                        None => return Err(RpcServerError::UnexpectedUpdateMessage),
+                    },
+                    response = responses.next(), if next.is_none() => match response {
+                        Some(response) => next = Some(response),
+                        None => return Ok(()),
+                    },
+                }
+            }
+        }
+        .instrument(method_span::<S, M>())
         .await
     }
 }
@@ -9,10 +9,11 @@ use std::{
 use futures_lite::{Future, Stream, StreamExt};
 use futures_util::{FutureExt, SinkExt, TryFutureExt};
 use serde::{Deserialize, Serialize};
+use tracing::Instrument;
 
 use crate::{
     client::{BoxStreamSync, DeferDrop},
-    message::{InteractionPattern, Msg},
+    message::{method_span, InteractionPattern, Msg},
     server::{race2, RpcChannel, RpcServerError},
     transport::{self, ConnectionErrors, StreamTypes},
     Connector, RpcClient, Service,
@@ -66,6 +67,9 @@ pub enum Error<C: transport::Connector, E: Debug> {
     Downcast,
     /// Application error
     Application(E),
+    /// The stream was not created within the timeout, see
+    /// [RpcClient::try_server_streaming_timeout]
+    Timeout,
 }
 
 impl<S: transport::Connector, E: Debug> fmt::Display for Error<S, E> {
@@ -164,6 +168,7 @@ where
             }
             Ok(())
         })
+        .instrument(method_span::<S, M>())
         .await
     }
 }
@@ -181,6 +186,53 @@ where
         BoxStreamSync<'static, Result<M::Item, ItemError<C, M::ItemError>>>,
         Error<C, M::CreateError>,
     >
+    where
+        M: TryServerStreamingMsg<S>,
+        Result<M::Item, M::ItemError>: Into<S::Res> + TryFrom<S::Res>,
+        Result<StreamCreated, M::CreateError>: Into<S::Res> + TryFrom<S::Res>,
+    {
+        self.timed(None, self.try_server_streaming_inner(msg))
+            .await
+            .unwrap_or(Err(Error::Timeout))
+    }
+
+    /// Try server streaming call that fails with [Error::Timeout] if the stream
+    /// is not created within the given timeout
+    ///
+    /// The timeout covers opening the substream, sending the request and
+    /// receiving the first response, which tells whether the stream was created.
+    /// The items of the stream are not covered. It overrides the default timeout
+    /// of the client.
+    #[cfg(feature = "rt")]
+    #[cfg_attr(
+        quicrpc_docsrs,
+        doc(cfg(any(feature = "rt-tokio", feature = "rt-smol", feature = "rt-async-std")))
+    )]
+    pub async fn try_server_streaming_timeout<M>(
+        &self,
+        msg: M,
+        timeout: std::time::Duration,
+    ) -> result::Result<
+        BoxStreamSync<'static, Result<M::Item, ItemError<C, M::ItemError>>>,
+        Error<C, M::CreateError>,
+    >
+    where
+        M: TryServerStreamingMsg<S>,
+        Result<M::Item, M::ItemError>: Into<S::Res> + TryFrom<S::Res>,
+        Result<StreamCreated, M::CreateError>: Into<S::Res> + TryFrom<S::Res>,
+    {
+        self.timed(Some(timeout), self.try_server_streaming_inner(msg))
+            .await
+            .unwrap_or(Err(Error::Timeout))
+    }
+
+    async fn try_server_streaming_inner<M>(
+        &self,
+        msg: M,
+    ) -> result::Result<
+        BoxStreamSync<'static, Result<M::Item, ItemError<C, M::ItemError>>>,
+        Error<C, M::CreateError>,
+    >
     where
         M: TryServerStreamingMsg<S>,
         Result<M::Item, M::ItemError>: Into<S::Res> + TryFrom<S::Res>,
@@ -0,0 +1,403 @@
+//! Topic based publish/subscribe on top of a streaming substream.
+//!
+//! A [Broadcaster] is shared between server handlers. Anything published into
+//! it is delivered to all current subscribers of the topic. The [Subscription]
+//! interaction pattern lets a client subscribe to and unsubscribe from topics
+//! while the substream is open, and receive the published items as a stream of
+//! responses.
+//!
+//! Publishing never waits for subscribers. Each subscriber has a bounded buffer
+//! of items per topic. If a subscriber does not keep up, the oldest items are
+//! dropped for this subscriber only, and it is told how many items it missed
+//! using [SubscriptionMsg::lagged].
+//!
+//! On the wire, subscribing and unsubscribing are updates of type
+//! [SubscriptionUpdate], so the request type of the service must be convertible
+//! from and into it.
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug},
+    hash::Hash,
+    result,
+    sync::{Arc, Mutex},
+};
+
+use futures_lite::StreamExt;
+use futures_util::{
+    stream::{self, BoxStream, SelectAll},
+    SinkExt,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+use crate::{
+    client::{BoxStreamSync, UpdateError, UpdateSink},
+    message::{method_span, InteractionPattern, Msg},
+    pattern::bidi_streaming::{Error, ItemError},
+    server::{RpcChannel, RpcServerError},
+    transport::StreamTypes,
+    Connector, RpcClient, Service,
+};
+
+/// Subscription interaction pattern
+///
+/// After the initial request, the client sends [SubscriptionUpdate]s to change
+/// the set of topics, and the server sends the items published to them.
+#[derive(Debug, Clone, Copy)]
+pub struct Subscription;
+impl InteractionPattern for Subscription {}
+
+/// Defines the topic and item types for a subscription message.
+pub trait SubscriptionMsg<S: Service>: Msg<S, Pattern = Subscription> {
+    /// The type for topics
+    type Topic: Clone + Eq + Hash + Send + Sync + 'static;
+
+    /// The type for published items
+    type Item: Into<S::Res> + TryFrom<S::Res> + Clone + Send + Sync + 'static;
+
+    /// Create the item that is sent when a subscriber missed items
+    ///
+    /// The default is to skip the missed items silently.
+    fn lagged(_missed: u64) -> Option<Self::Item> {
+        None
+    }
+}
+
+/// Update sent by the client to change the subscribed topics
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SubscriptionUpdate<K> {
+    /// Start receiving items published to the topic
+    Subscribe(K),
+    /// Stop receiving items published to the topic
+    Unsubscribe(K),
+}
+
+/// Publishes items to the subscribers of a topic
+///
+/// Cloning a broadcaster is cheap, all clones share the same topics.
+pub struct Broadcaster<T, K = String> {
+    topics: Arc<Mutex<HashMap<K, broadcast::Sender<T>>>>,
+    capacity: usize,
+}
+
+impl<T, K> Clone for Broadcaster<T, K> {
+    fn clone(&self) -> Self {
+        Self {
+            topics: self.topics.clone(),
+            capacity: self.capacity,
+        }
+    }
+}
+
+impl<T, K> Debug for Broadcaster<T, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Broadcaster")
+            .field("topics", &self.topics.lock().unwrap().len())
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+impl<T, K> Broadcaster<T, K>
+where
+    T: Clone,
+    K: Eq + Hash,
+{
+    /// Create a broadcaster that buffers up to `capacity` items per subscriber
+    /// and topic
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            topics: Default::default(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Publish an item to all current subscribers of the topic
+    ///
+    /// Returns the number of subscribers the item was published to.
+    pub fn publish(&self, topic: &K, item: T) -> usize {
+        let mut topics = self.topics.lock().unwrap();
+        let Some(sender) = topics.get(topic) else {
+            return 0;
+        };
+        match sender.send(item) {
+            Ok(n) => n,
+            Err(_) => {
+                // the last subscriber is gone
+                topics.remove(topic);
+                0
+            }
+        }
+    }
+
+    /// Number of current subscribers of the topic
+    pub fn subscribers(&self, topic: &K) -> usize {
+        let topics = self.topics.lock().unwrap();
+        topics
+            .get(topic)
+            .map_or(0, |sender| sender.receiver_count())
+    }
+
+    /// Subscribe to a topic directly, without going through a client
+    pub fn subscribe(&self, topic: K) -> broadcast::Receiver<T> {
+        let mut topics = self.topics.lock().unwrap();
+        topics
+            .entry(topic)
+            .or_insert_with(|| broadcast::channel(self.capacity).0)
+            .subscribe()
+    }
+}
+
+/// Sink to change the subscribed topics of a [Subscription]
+pub struct SubscriptionSink<C: StreamTypes, K>(UpdateSink<C, SubscriptionUpdate<K>>);
+
+impl<C: StreamTypes, K> Debug for SubscriptionSink<C, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SubscriptionSink").finish_non_exhaustive()
+    }
+}
+
+impl<C, K> SubscriptionSink<C, K>
+where
+    C: StreamTypes,
+    SubscriptionUpdate<K>: Into<C::Out>,
+{
+    /// Start receiving items published to the topic
+    ///
+    /// Subscribing to a topic that is already subscribed has no effect.
+    pub async fn subscribe(&mut self, topic: K) -> result::Result<(), UpdateError<C>> {
+        self.0.send(SubscriptionUpdate::Subscribe(topic)).await
+    }
+
+    /// Stop receiving items published to the topic
+    ///
+    /// Items that were already sent may still arrive after this returns.
+    pub async fn unsubscribe(&mut self, topic: K) -> result::Result<(), UpdateError<C>> {
+        self.0.send(SubscriptionUpdate::Unsubscribe(topic)).await
+    }
+}
+
+impl<S, C> RpcClient<S, C>
+where
+    S: Service,
+    C: Connector<S>,
+{
+    /// Open a subscription
+    ///
+    /// No topics are subscribed initially. Dropping the sink ends the
+    /// subscription, after which the item stream ends.
+    pub async fn subscription<M>(
+        &self,
+        msg: M,
+    ) -> result::Result<
+        (
+            SubscriptionSink<C, M::Topic>,
+            BoxStreamSync<'static, result::Result<M::Item, ItemError<C>>>,
+        ),
+        Error<C>,
+    >
+    where
+        M: SubscriptionMsg<S>,
+        SubscriptionUpdate<M::Topic>: Into<S::Req>,
+    {
+        self.timed(None, async move {
+            let msg = msg.into();
+            let (mut send, recv) = self.source.open().await.map_err(Error::Open)?;
+            send.send(msg).await.map_err(Error::<C>::Send)?;
+            let send = SubscriptionSink(UpdateSink::new(send));
+            let recv: BoxStreamSync<'static, _> = Box::pin(recv.map(move |x| match x {
+                Ok(msg) => M::Item::try_from(msg).map_err(|_| ItemError::DowncastError),
+                Err(e) => Err(ItemError::RecvError(e)),
+            }));
+            Ok((send, recv))
+        })
+        .await
+        .unwrap_or(Err(Error::Timeout))
+    }
+}
+
+impl<S, C> RpcChannel<S, C>
+where
+    S: Service,
+    C: StreamTypes<In = S::Req, Out = S::Res>,
+{
+    /// Serve a subscription from the given broadcaster
+    ///
+    /// This runs until the client stops sending updates. If you want to support
+    /// concurrent requests, you need to spawn this on a tokio task yourself.
+    pub async fn subscription<M>(
+        self,
+        _req: M,
+        broadcaster: Broadcaster<M::Item, M::Topic>,
+    ) -> result::Result<(), RpcServerError<C>>
+    where
+        M: SubscriptionMsg<S>,
+        SubscriptionUpdate<M::Topic>: TryFrom<S::Req>,
+    {
+        let Self {
+            mut send, mut recv, ..
+        } = self;
+        let mut topics = HashMap::<M::Topic, CancellationToken>::new();
+        let mut items = SelectAll::new();
+        async move {
+            loop {
+                tokio::select! {
+                    update = recv.next() => {
+                        let update = match update {
+                            Some(Ok(update)) => update,
+                            Some(Err(cause)) => return Err(RpcServerError::RecvError(cause)),
+                            None => return Ok(()),
+                        };
+                        match SubscriptionUpdate::try_from(update) {
+                            Ok(SubscriptionUpdate::Subscribe(topic)) => {
+                                if topics.contains_key(&topic) {
+                                    continue;
+                                }
+                                let token = CancellationToken::new();
+                                let receiver = broadcaster.subscribe(topic.clone());
+                                items.push(topic_stream(receiver, token.clone()));
+                                topics.insert(topic, token);
+                            }
+                            Ok(SubscriptionUpdate::Unsubscribe(topic)) => {
+                                if let Some(token) = topics.remove(&topic) {
+                                    token.cancel();
+                                }
+                            }
+                            Err(_) => return Err(RpcServerError::UnexpectedUpdateMessage),
+                        }
+                    }
+                    Some(item) = items.next() => {
+                        let item = match item {
+                            Ok(item) => item,
+                            Err(missed) => match M::lagged(missed) {
+                                Some(item) => item,
+                                None => continue,
+                            },
+                        };
+                        send.send(item.into())
+                            .await
+                            .map_err(RpcServerError::SendError)?;
+                    }
+                }
+            }
+        }
+        .instrument(method_span::<S, M>())
+        .await
+    }
+}
+
+/// Items of a single topic until the token is cancelled, or the number of
+/// missed items
+fn topic_stream<T>(
+    receiver: broadcast::Receiver<T>,
+    token: CancellationToken,
+) -> BoxStream<'static, result::Result<T, u64>>
+where
+    T: Clone + Send + 'static,
+{
+    Box::pin(stream::unfold(
+        (receiver, token),
+        |(mut receiver, token)| async move {
+            let item = tokio::select! {
+                _ = token.cancelled() => return None,
+                item = receiver.recv() => item,
+            };
+            let item = match item {
+                Ok(item) => Ok(item),
+                Err(RecvError::Lagged(missed)) => Err(missed),
+                Err(RecvError::Closed) => return None,
+            };
+            Some((item, (receiver, token)))
+        },
+    ))
+}
+
+#[cfg(all(test, feature = "flume-transport"))]
+mod tests {
+    use derive_more::{From, TryInto};
+
+    use super::*;
+    use crate::{transport::flume, RpcServer};
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Watch;
+
+    #[derive(Debug, Serialize, Deserialize, From, TryInto)]
+    enum Request {
+        Watch(Watch),
+        Update(SubscriptionUpdate<String>),
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    enum Event {
+        Value(u64),
+        Lagged(u64),
+    }
+
+    #[derive(Debug, Clone)]
+    struct WatchService;
+
+    impl Service for WatchService {
+        type Req = Request;
+        type Res = Event;
+    }
+
+    impl Msg<WatchService> for Watch {
+        type Pattern = Subscription;
+    }
+
+    impl SubscriptionMsg<WatchService> for Watch {
+        type Topic = String;
+        type Item = Event;
+
+        fn lagged(missed: u64) -> Option<Event> {
+            Some(Event::Lagged(missed))
+        }
+    }
+
+    #[tokio::test]
+    async fn subscribe_unsubscribe() -> anyhow::Result<()> {
+        let (listener, connector) = flume::channel(1);
+        let server = RpcServer::<WatchService, _>::new(listener);
+        let broadcaster = Broadcaster::<Event>::new(2);
+        let _server = tokio::spawn({
+            let broadcaster = broadcaster.clone();
+            async move {
+                let (req, chan) = server.accept().await?.read_first().await?;
+                let Request::Watch(req) = req else {
+                    anyhow::bail!("unexpected request");
+                };
+                chan.subscription(req, broadcaster).await?;
+                anyhow::Ok(())
+            }
+        });
+        let client = RpcClient::<WatchService, _>::new(connector);
+        let (mut sink, mut items) = client.subscription(Watch).await?;
+        let a = "a".to_string();
+        let b = "b".to_string();
+        sink.subscribe(a.clone()).await?;
+        sink.subscribe(b.clone()).await?;
+        while broadcaster.subscribers(&b) == 0 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(broadcaster.publish(&a, Event::Value(1)), 1);
+        assert_eq!(items.next().await.transpose()?, Some(Event::Value(1)));
+        sink.unsubscribe(a.clone()).await?;
+        while broadcaster.subscribers(&a) > 0 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(broadcaster.publish(&a, Event::Value(2)), 0);
+        // publishing never blocks, a subscriber that does not keep up lags
+        for i in 0..4 {
+            broadcaster.publish(&b, Event::Value(i));
+        }
+        assert_eq!(items.next().await.transpose()?, Some(Event::Lagged(2)));
+        assert_eq!(items.next().await.transpose()?, Some(Event::Value(2)));
+        assert_eq!(items.next().await.transpose()?, Some(Event::Value(3)));
+        drop(sink);
+        assert!(items.next().await.is_none());
+        Ok(())
+    }
+}
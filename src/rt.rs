@@ -0,0 +1,92 @@
+//! Runtime abstraction
+//!
+//! The only runtime specific things quic-rpc needs are spawning tasks and
+//! sleeping. Everything else, including the memory transport, is runtime agnostic.
+//!
+//! The runtime is selected using features. If several runtime features are
+//! enabled, `rt-tokio` takes precedence over `rt-smol`, which takes precedence
+//! over `rt-async-std`. Without a runtime feature, the apis that need to spawn
+//! tasks are not available.
+#[cfg(not(any(feature = "rt-tokio", feature = "rt-smol", feature = "rt-async-std")))]
+compile_error!(
+    "the enabled features need a runtime, enable one of rt-tokio, rt-smol or rt-async-std"
+);
+
+#[cfg(any(feature = "rt-tokio", feature = "rt-smol", feature = "rt-async-std"))]
+use std::time::Duration;
+
+use futures_lite::Future;
+use futures_util::future::{FutureExt, RemoteHandle};
+
+/// A handle to a spawned task
+///
+/// Awaiting the handle returns the output of the task. Dropping the handle
+/// cancels the task.
+pub(crate) type Task<T> = RemoteHandle<T>;
+
+/// Spawn a task on the selected runtime
+pub(crate) fn spawn<F>(future: F) -> Task<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let (remote, handle) = future.remote_handle();
+    spawn_detached(remote);
+    handle
+}
+
+/// Spawn a task on the selected runtime, without a handle
+#[cfg(feature = "rt-tokio")]
+pub(crate) fn spawn_detached(future: impl Future<Output = ()> + Send + 'static) {
+    tokio::spawn(future);
+}
+
+/// Spawn a task on the selected runtime, without a handle
+#[cfg(all(not(feature = "rt-tokio"), feature = "rt-smol"))]
+pub(crate) fn spawn_detached(future: impl Future<Output = ()> + Send + 'static) {
+    smol::spawn(future).detach();
+}
+
+/// Spawn a task on the selected runtime, without a handle
+#[cfg(all(
+    not(feature = "rt-tokio"),
+    not(feature = "rt-smol"),
+    feature = "rt-async-std"
+))]
+pub(crate) fn spawn_detached(future: impl Future<Output = ()> + Send + 'static) {
+    async_std::task::spawn(future);
+}
+
+/// Sleep for the given duration on the selected runtime
+#[cfg(feature = "rt-tokio")]
+pub(crate) async fn sleep(duration: Duration) {
+    tokio::time::sleep(duration).await;
+}
+
+/// Sleep for the given duration on the selected runtime
+#[cfg(all(not(feature = "rt-tokio"), feature = "rt-smol"))]
+pub(crate) async fn sleep(duration: Duration) {
+    smol::Timer::after(duration).await;
+}
+
+/// Sleep for the given duration on the selected runtime
+#[cfg(all(
+    not(feature = "rt-tokio"),
+    not(feature = "rt-smol"),
+    feature = "rt-async-std"
+))]
+pub(crate) async fn sleep(duration: Duration) {
+    async_std::task::sleep(duration).await;
+}
+
+/// Run a future with a timeout on the selected runtime
+///
+/// Returns `None` if the timeout elapsed first.
+#[cfg(any(feature = "rt-tokio", feature = "rt-smol", feature = "rt-async-std"))]
+pub(crate) async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+    futures_lite::future::or(async { Some(future.await) }, async {
+        sleep(duration).await;
+        None
+    })
+    .await
+}
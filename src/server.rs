@@ -7,18 +7,23 @@ use std::{
     marker::PhantomData,
     pin::Pin,
     result,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     task::{self, Poll},
+    time::Instant,
 };
 
 use futures_lite::{Future, Stream, StreamExt};
 use futures_util::{SinkExt, TryStreamExt};
 use pin_project::pin_project;
-use tokio::{sync::oneshot, task::JoinSet};
+use tokio::sync::oneshot;
+#[cfg(feature = "rt-tokio")]
 use tokio_util::task::AbortOnDropHandle;
-use tracing::{error, warn};
 
 use crate::{
+    middleware::{MiddlewareListener, Rejection, ServerMiddleware},
     transport::{
         self,
         boxed::BoxableListener,
@@ -79,24 +84,113 @@ pub type IrohListener<S> =
 /// `S` is the service type.
 /// `C` is the channel type.
 #[derive(Debug)]
-pub struct RpcServer<S, C = BoxedListener<S>> {
+pub struct RpcServer<S: Service, C = BoxedListener<S>> {
     /// The channel on which new requests arrive.
     ///
     /// Each new request is a receiver and channel pair on which messages for this request
     /// are received and responses sent.
     source: C,
+    stats: Arc<ServerStats>,
+    /// The middlewares that check the first request, in the order they were installed
+    middleware: Vec<Arc<dyn ServerMiddleware<S>>>,
+    /// Handles the notifications received by [RpcServer::accept_loop]
+    #[cfg(feature = "rt")]
+    notification_handler: Option<NotificationHandler<S>>,
     _p: PhantomData<S>,
 }
 
-impl<S, C: Clone> Clone for RpcServer<S, C> {
+impl<S: Service, C: Clone> Clone for RpcServer<S, C> {
     fn clone(&self) -> Self {
         Self {
             source: self.source.clone(),
+            stats: self.stats.clone(),
+            middleware: self.middleware.clone(),
+            #[cfg(feature = "rt")]
+            notification_handler: self.notification_handler.clone(),
             _p: PhantomData,
         }
     }
 }
 
+/// A handler installed with [RpcServer::with_notification_handler]
+#[cfg(feature = "rt")]
+struct NotificationHandler<S: Service>(
+    Arc<dyn Fn(S::Req) -> futures_util::future::BoxFuture<'static, ()> + Send + Sync>,
+);
+
+#[cfg(feature = "rt")]
+impl<S: Service> Clone for NotificationHandler<S> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+#[cfg(feature = "rt")]
+impl<S: Service> Debug for NotificationHandler<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("NotificationHandler").finish()
+    }
+}
+
+/// Counters for the requests accepted by a [RpcServer]
+///
+/// The counters are shared between clones of the server.
+#[derive(Debug, Default)]
+struct ServerStats {
+    accepted: AtomicU64,
+    accept_errors: AtomicU64,
+    early_close: AtomicU64,
+    read_errors: AtomicU64,
+    active_handlers: AtomicU64,
+}
+
+/// Counts a running handler task of the accept loop
+#[cfg(feature = "rt")]
+struct ActiveHandler(Arc<ServerStats>);
+
+#[cfg(feature = "rt")]
+impl ActiveHandler {
+    fn new(stats: Arc<ServerStats>) -> Self {
+        stats.active_handlers.fetch_add(1, Ordering::Relaxed);
+        Self(stats)
+    }
+}
+
+#[cfg(feature = "rt")]
+impl Drop for ActiveHandler {
+    fn drop(&mut self) {
+        self.0.active_handlers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A snapshot of the counters of a [RpcServer], see [RpcServer::stats]
+#[derive(Debug, Clone, Copy)]
+pub struct ServerStatsSnapshot {
+    /// When the snapshot was taken
+    pub at: Instant,
+    /// Number of accepted substreams
+    pub accepted: u64,
+    /// Number of errors of the listener when accepting a substream
+    pub accept_errors: u64,
+    /// Number of substreams that were closed before the first message
+    pub early_close: u64,
+    /// Number of errors when reading the first message
+    pub read_errors: u64,
+    /// Number of handler tasks of the accept loop that are currently running
+    pub active_handlers: u64,
+}
+
+impl ServerStatsSnapshot {
+    /// The rate of accepted substreams per second since an earlier snapshot
+    pub fn accepted_per_second(&self, earlier: &Self) -> f64 {
+        let elapsed = self.at.saturating_duration_since(earlier.at).as_secs_f64();
+        if elapsed == 0.0 {
+            return 0.0;
+        }
+        self.accepted.saturating_sub(earlier.accepted) as f64 / elapsed
+    }
+}
+
 impl<S: Service, C: Listener<S>> RpcServer<S, C> {
     /// Create a new rpc server for a specific service for a [Service] given a compatible
     /// [Listener].
@@ -105,10 +199,101 @@ impl<S: Service, C: Listener<S>> RpcServer<S, C> {
     pub fn new(source: C) -> Self {
         Self {
             source,
+            stats: Default::default(),
+            middleware: Vec::new(),
+            #[cfg(feature = "rt")]
+            notification_handler: None,
+            _p: PhantomData,
+        }
+    }
+
+    /// A snapshot of the accept counters of this server
+    pub fn stats(&self) -> ServerStatsSnapshot {
+        let stats = &self.stats;
+        ServerStatsSnapshot {
+            at: Instant::now(),
+            accepted: stats.accepted.load(Ordering::Relaxed),
+            accept_errors: stats.accept_errors.load(Ordering::Relaxed),
+            early_close: stats.early_close.load(Ordering::Relaxed),
+            read_errors: stats.read_errors.load(Ordering::Relaxed),
+            active_handlers: stats.active_handlers.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Run the installed middlewares on a notification
+    ///
+    /// There is no substream to send the response of a rejection on, so it is dropped.
+    pub(crate) fn check_notification(
+        &self,
+        request: &mut S::Req,
+    ) -> result::Result<(), RpcServerError<C>> {
+        check_request(&self.middleware, request)
+            .map_err(|rejection| RpcServerError::Rejected(rejection.reason))
+    }
+
+    /// Install a middleware that is called for every request of this server
+    ///
+    /// See [middleware](crate::middleware) for details.
+    pub fn with_middleware(
+        self,
+        middleware: impl ServerMiddleware<S>,
+    ) -> RpcServer<S, MiddlewareListener<S, C>> {
+        let middleware: Arc<dyn ServerMiddleware<S>> = Arc::new(middleware);
+        let mut middlewares = self.middleware;
+        middlewares.push(middleware.clone());
+        RpcServer {
+            source: MiddlewareListener::from_arc(self.source, middleware),
+            stats: self.stats,
+            middleware: middlewares,
+            #[cfg(feature = "rt")]
+            notification_handler: self.notification_handler,
             _p: PhantomData,
         }
     }
 
+    /// Install a handler for the notifications that [RpcServer::accept_loop] receives
+    ///
+    /// These are the notifications that arrive on unidirectional substreams or as
+    /// datagrams, see [notify](crate::pattern::notify). Each one is handled in a
+    /// separate task, after the middlewares checked it.
+    #[cfg(feature = "rt")]
+    #[cfg_attr(
+        quicrpc_docsrs,
+        doc(cfg(any(feature = "rt-tokio", feature = "rt-smol", feature = "rt-async-std")))
+    )]
+    pub fn with_notification_handler<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn(S::Req) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        use futures_util::FutureExt;
+
+        self.notification_handler = Some(NotificationHandler(Arc::new(move |req| {
+            handler(req).boxed()
+        })));
+        self
+    }
+
+    /// Read the notification of a unidirectional substream, and run the middlewares on it
+    ///
+    /// The returned future does not borrow the server, so it can run on its own task.
+    pub(crate) fn read_notification(
+        &self,
+        mut recv: C::RecvStream,
+    ) -> impl Future<Output = result::Result<S::Req, RpcServerError<C>>> + Send + 'static {
+        let middleware = self.middleware.clone();
+        async move {
+            let mut msg = match recv.next().await {
+                Some(Ok(msg)) => msg,
+                Some(Err(cause)) => return Err(RpcServerError::RecvError(cause)),
+                None => return Err(RpcServerError::EarlyClose),
+            };
+            check_request(&middleware, &mut msg)
+                .map_err(|rejection| RpcServerError::Rejected(rejection.reason))?;
+            Ok(msg)
+        }
+    }
+
     /// Box the transport for the service.
     ///
     /// The boxed transport is the default for the `C` type parameter, so by boxing we can avoid
@@ -117,7 +302,14 @@ impl<S: Service, C: Listener<S>> RpcServer<S, C> {
     where
         C: BoxableListener<S::Req, S::Res>,
     {
-        RpcServer::new(self.source.boxed())
+        RpcServer {
+            source: self.source.boxed(),
+            stats: self.stats,
+            middleware: self.middleware,
+            #[cfg(feature = "rt")]
+            notification_handler: self.notification_handler,
+            _p: PhantomData,
+        }
     }
 }
 
@@ -129,6 +321,46 @@ impl<S: Service, C: Listener<S>> RpcServer<S, C> {
 /// Sink and stream are independent, so you can take the channel apart and use
 /// them independently.
 ///
+/// The methods to handle a request are specific to the interaction pattern of the
+/// message, so handling a message with the wrong pattern is a compile error:
+///
+/// ```compile_fail,E0277
+/// use quic_rpc::{
+///     message::Msg,
+///     pattern::server_streaming::{ServerStreaming, ServerStreamingMsg},
+///     server::RpcChannel,
+///     Service,
+/// };
+/// use serde::{Deserialize, Serialize};
+///
+/// #[derive(Debug, Clone)]
+/// struct MyService;
+///
+/// impl Service for MyService {
+///     type Req = Count;
+///     type Res = Number;
+/// }
+///
+/// #[derive(Debug, Serialize, Deserialize)]
+/// struct Count;
+///
+/// #[derive(Debug, Serialize, Deserialize)]
+/// struct Number(u64);
+///
+/// impl Msg<MyService> for Count {
+///     type Pattern = ServerStreaming;
+/// }
+///
+/// impl ServerStreamingMsg<MyService> for Count {
+///     type Response = Number;
+/// }
+///
+/// async fn handle(chan: RpcChannel<MyService>, req: Count) {
+///     // Count is a server streaming message, so it can not be handled as a rpc
+///     chan.rpc(req, (), |_, _| async { Number(0) }).await.ok();
+/// }
+/// ```
+///
 /// Type parameters:
 ///
 /// `S` is the service type.
@@ -157,6 +389,15 @@ where
         }
     }
 
+    /// Split this channel into its send and receive side
+    ///
+    /// Use this to leave the RPC protocol after the first request, e.g. to switch to
+    /// a bulk transfer of raw bytes. See [RpcChannel::rpc_raw] to send a typed
+    /// response first.
+    pub fn into_raw(self) -> (C::SendSink, C::RecvStream) {
+        (self.send, self.recv)
+    }
+
     /// Convert this channel into a boxed channel.
     pub fn boxed(self) -> RpcChannel<S, BoxedChannelTypes<S>>
     where
@@ -169,6 +410,25 @@ where
         RpcChannel::new(send, recv)
     }
 
+    /// The identity of the client, as authenticated by the transport
+    ///
+    /// E.g. the client certificate for a quinn listener that requires client
+    /// authentication. Use this to decide per client which requests to allow.
+    pub fn peer_identity(&self) -> Option<&transport::PeerIdentity>
+    where
+        C::RecvStream: transport::PeerInfo,
+    {
+        transport::PeerInfo::peer_identity(&self.recv)
+    }
+
+    /// The metadata the client sent with the request, see [transport::meta]
+    pub fn metadata(&self) -> Option<&transport::meta::Metadata>
+    where
+        C::RecvStream: transport::meta::MetaInfo,
+    {
+        transport::meta::MetaInfo::metadata(&self.recv)
+    }
+
     /// Map this channel's service into an inner service.
     ///
     /// This method is available if the required bounds are upheld:
@@ -195,6 +455,8 @@ where
 pub struct Accepting<S: Service, C: Listener<S>> {
     send: C::SendSink,
     recv: C::RecvStream,
+    stats: Arc<ServerStats>,
+    middleware: Vec<Arc<dyn ServerMiddleware<S>>>,
     _p: PhantomData<S>,
 }
 
@@ -208,28 +470,71 @@ impl<S: Service, C: Listener<S>> Accepting<S, C> {
     ///
     /// Often sink and stream will wrap an an underlying byte stream. In this case you can
     /// call into_inner() on them to get it back to perform byte level reads and writes.
+    ///
+    /// If a [ServerMiddleware] installed with [RpcServer::with_middleware] rejects the
+    /// request, the rejection response is sent and [RpcServerError::Rejected] is returned.
     pub async fn read_first(self) -> result::Result<(S::Req, RpcChannel<S, C>), RpcServerError<C>> {
-        let Accepting { send, mut recv, .. } = self;
+        let Accepting {
+            mut send,
+            mut recv,
+            stats,
+            middleware,
+            ..
+        } = self;
         // get the first message from the client. This will tell us what it wants to do.
-        let request: S::Req = recv
-            .next()
-            .await
+        let mut request: S::Req = match recv.next().await {
+            Some(Ok(request)) => request,
             // no msg => early close
-            .ok_or(RpcServerError::EarlyClose)?
-            // recv error
-            .map_err(RpcServerError::RecvError)?;
+            None => {
+                stats.early_close.fetch_add(1, Ordering::Relaxed);
+                return Err(RpcServerError::EarlyClose);
+            }
+            Some(Err(cause)) => {
+                stats.read_errors.fetch_add(1, Ordering::Relaxed);
+                return Err(RpcServerError::RecvError(cause));
+            }
+        };
+        if let Err(rejection) = check_request(&middleware, &mut request) {
+            if let Some(response) = rejection.response {
+                send.send(response).await.ok();
+            }
+            return Err(RpcServerError::Rejected(rejection.reason));
+        }
         Ok((request, RpcChannel::<S, C>::new(send, recv)))
     }
 }
 
+/// Run the middlewares on the first request of a substream, or on a notification
+pub(crate) fn check_request<S: Service>(
+    middleware: &[Arc<dyn ServerMiddleware<S>>],
+    request: &mut S::Req,
+) -> result::Result<(), Rejection<S::Res>> {
+    for middleware in middleware {
+        if let Err(rejection) = middleware.on_request(request) {
+            tracing::debug!("request rejected: {}", rejection.reason);
+            return Err(rejection);
+        }
+    }
+    Ok(())
+}
+
 impl<S: Service, C: Listener<S>> RpcServer<S, C> {
     /// Accepts a new channel from a client. The result is an [Accepting] object that
     /// can be used to read the first request.
     pub async fn accept(&self) -> result::Result<Accepting<S, C>, RpcServerError<C>> {
-        let (send, recv) = self.source.accept().await.map_err(RpcServerError::Accept)?;
+        let (send, recv) = match self.source.accept().await {
+            Ok(channel) => channel,
+            Err(cause) => {
+                self.stats.accept_errors.fetch_add(1, Ordering::Relaxed);
+                return Err(RpcServerError::Accept(cause));
+            }
+        };
+        self.stats.accepted.fetch_add(1, Ordering::Relaxed);
         Ok(Accepting {
             send,
             recv,
+            stats: self.stats.clone(),
+            middleware: self.middleware.clone(),
             _p: PhantomData,
         })
     }
@@ -243,7 +548,18 @@ impl<S: Service, C: Listener<S>> RpcServer<S, C> {
     ///
     /// Each request will be handled in a separate task.
     ///
+    /// Notifications that arrive on unidirectional substreams or as datagrams are
+    /// received as well, each in a separate task, and passed to the handler that is
+    /// installed with [RpcServer::with_notification_handler]. Without one, they are
+    /// dropped with a warning. So don't use [RpcServer::recv_notification] or
+    /// [RpcServer::recv_datagram] while the accept loop runs.
+    ///
     /// It is the caller's responsibility to poll the returned future to drive the server.
+    #[cfg(feature = "rt")]
+    #[cfg_attr(
+        quicrpc_docsrs,
+        doc(cfg(any(feature = "rt-tokio", feature = "rt-smol", feature = "rt-async-std")))
+    )]
     pub async fn accept_loop<Fun, Fut, E>(self, handler: Fun)
     where
         S: Service,
@@ -252,15 +568,18 @@ impl<S: Service, C: Listener<S>> RpcServer<S, C> {
         Fut: Future<Output = Result<(), E>> + Send + 'static,
         E: Into<anyhow::Error> + 'static,
     {
+        use std::panic::AssertUnwindSafe;
+
+        use futures_util::{stream::FuturesUnordered, FutureExt};
+        use tracing::{error, warn};
+
         let handler = Arc::new(handler);
-        let mut tasks = JoinSet::new();
+        let mut tasks = FuturesUnordered::new();
         loop {
             tokio::select! {
-                Some(res) = tasks.join_next(), if !tasks.is_empty() => {
-                    if let Err(e) = res {
-                        if e.is_panic() {
-                            error!("Panic handling RPC request: {e}");
-                        }
+                Some(res) = tasks.next(), if !tasks.is_empty() => {
+                    if let Err(payload) = res {
+                        error!("Panic handling RPC request: {}", panic_message(&payload));
                     }
                 }
                 req = self.accept() => {
@@ -272,9 +591,12 @@ impl<S: Service, C: Listener<S>> RpcServer<S, C> {
                         }
                     };
                     let handler = handler.clone();
-                    tasks.spawn(async move {
+                    let active = ActiveHandler::new(self.stats.clone());
+                    let task = async move {
+                        let _active = active;
                         let (req, chan) = match req.read_first().await {
                             Ok((req, chan)) => (req, chan),
+                            Err(RpcServerError::Rejected(_)) => return,
                             Err(e) => {
                                 warn!("Error reading first message: {e}");
                                 return;
@@ -283,13 +605,57 @@ impl<S: Service, C: Listener<S>> RpcServer<S, C> {
                         if let Err(cause) = handler(req, chan).await {
                             warn!("Error handling RPC request: {}", cause.into());
                         }
-                    });
+                    };
+                    tasks.push(crate::rt::spawn(AssertUnwindSafe(task).catch_unwind()));
+                }
+                recv = transport::Listener::accept_uni(&self.source) => {
+                    let recv = match recv {
+                        Ok(recv) => recv,
+                        Err(e) => {
+                            warn!("Error accepting notification: {e}");
+                            continue;
+                        }
+                    };
+                    // read on the task, so a substream without a message does not hold up others
+                    let read = self.read_notification(recv);
+                    let notification_handler = self.notification_handler.clone();
+                    let active = ActiveHandler::new(self.stats.clone());
+                    let task = async move {
+                        let _active = active;
+                        match read.await {
+                            Ok(msg) => handle_notification(notification_handler, msg).await,
+                            Err(RpcServerError::Rejected(_)) => {}
+                            Err(e) => warn!("Error reading notification: {e}"),
+                        }
+                    };
+                    tasks.push(crate::rt::spawn(AssertUnwindSafe(task).catch_unwind()));
+                }
+                msg = transport::Listener::recv_datagram(&self.source) => {
+                    let mut msg = match msg {
+                        Ok(msg) => msg,
+                        Err(e) => {
+                            warn!("Error receiving datagram: {e}");
+                            continue;
+                        }
+                    };
+                    if self.check_notification(&mut msg).is_err() {
+                        continue;
+                    }
+                    let notification_handler = self.notification_handler.clone();
+                    let active = ActiveHandler::new(self.stats.clone());
+                    let task = async move {
+                        let _active = active;
+                        handle_notification(notification_handler, msg).await
+                    };
+                    tasks.push(crate::rt::spawn(AssertUnwindSafe(task).catch_unwind()));
                 }
             }
         }
     }
 
     /// Spawn an accept loop and return a handle to the task.
+    #[cfg(feature = "rt-tokio")]
+    #[cfg_attr(quicrpc_docsrs, doc(cfg(feature = "rt-tokio")))]
     pub fn spawn_accept_loop<Fun, Fut, E>(self, handler: Fun) -> AbortOnDropHandle<()>
     where
         S: Service,
@@ -302,6 +668,27 @@ impl<S: Service, C: Listener<S>> RpcServer<S, C> {
     }
 }
 
+/// Pass a notification to the notification handler, if there is one
+#[cfg(feature = "rt")]
+async fn handle_notification<S: Service>(handler: Option<NotificationHandler<S>>, msg: S::Req) {
+    match handler {
+        Some(handler) => (handler.0)(msg).await,
+        None => tracing::warn!("Dropping notification, there is no notification handler"),
+    }
+}
+
+/// Extract the message of a panic payload, if it is a string
+#[cfg(feature = "rt")]
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg
+    } else {
+        "unknown panic"
+    }
+}
+
 impl<S: Service, C: Listener<S>> AsRef<C> for RpcServer<S, C> {
     fn as_ref(&self) -> &C {
         &self.source
@@ -310,14 +697,19 @@ impl<S: Service, C: Listener<S>> AsRef<C> for RpcServer<S, C> {
 
 /// A stream of updates
 ///
-/// If there is any error with receiving or with decoding the updates, the stream will stall and the error will
-/// cause a termination of the RPC call.
+/// The stream ends with `None` when the client finished sending updates, see
+/// [UpdateSink::finish](crate::client::UpdateSink::finish). If there is any error with receiving or
+/// with decoding the updates, the stream will stall and the error will cause a termination of the
+/// RPC call. For messages with an [end of updates](crate::message::Msg::end_of_updates) marker,
+/// updates that end without the marker are an error as well.
 #[pin_project]
 #[derive(Debug)]
 pub struct UpdateStream<C, T>(
     #[pin] C::RecvStream,
     Option<oneshot::Sender<RpcServerError<C>>>,
     PhantomData<T>,
+    Option<fn(&C::In) -> bool>,
+    bool,
 )
 where
     C: StreamTypes;
@@ -327,10 +719,19 @@ where
     C: StreamTypes,
     T: TryFrom<C::In>,
 {
-    pub(crate) fn new(recv: C::RecvStream) -> (Self, UnwrapToPending<RpcServerError<C>>) {
+    /// Create a stream of updates, ending at the update for which `is_end` is true
+    ///
+    /// Without `is_end`, the updates end when the client closes its side.
+    pub(crate) fn new(
+        recv: C::RecvStream,
+        is_end: Option<fn(&C::In) -> bool>,
+    ) -> (Self, UnwrapToPending<RpcServerError<C>>) {
         let (error_send, error_recv) = oneshot::channel();
         let error_recv = UnwrapToPending(error_recv);
-        (Self(recv, Some(error_send), PhantomData), error_recv)
+        (
+            Self(recv, Some(error_send), PhantomData, is_end, false),
+            error_recv,
+        )
     }
 }
 
@@ -343,7 +744,14 @@ where
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Option<Self::Item>> {
         let mut this = self.project();
+        if *this.4 {
+            return Poll::Ready(None);
+        }
         match Pin::new(&mut this.0).poll_next(cx) {
+            Poll::Ready(Some(Ok(msg))) if this.3.is_some_and(|is_end| is_end(&msg)) => {
+                *this.4 = true;
+                Poll::Ready(None)
+            }
             Poll::Ready(Some(msg)) => match msg {
                 Ok(msg) => {
                     let msg = T::try_from(msg).map_err(|_cause| ());
@@ -366,6 +774,13 @@ where
                     Poll::Pending
                 }
             },
+            Poll::Ready(None) if this.3.is_some() => {
+                // the client dropped the update sink instead of finishing it
+                if let Some(tx) = this.1.take() {
+                    let _ = tx.send(RpcServerError::UpdatesAborted);
+                }
+                Poll::Pending
+            }
             Poll::Ready(None) => Poll::Ready(None),
             Poll::Pending => Poll::Pending,
         }
@@ -386,6 +801,15 @@ pub enum RpcServerError<C: ConnectionErrors> {
     SendError(C::SendError),
     /// Got an unexpected update message, e.g. a request message or a non-matching update message
     UnexpectedUpdateMessage,
+    /// The client did not keep up with the responses of a server streaming request
+    ///
+    /// See [SlowConsumer::Terminate](crate::pattern::server_streaming::SlowConsumer::Terminate).
+    SlowConsumer,
+    /// The first request was rejected by a [ServerMiddleware], with the given reason
+    Rejected(String),
+    /// The updates ended without the [end of updates](crate::message::Msg::end_of_updates)
+    /// marker, e.g. because the client dropped the update sink
+    UpdatesAborted,
 }
 
 impl<In: RpcMessage, Out: RpcMessage, C: ConnectionErrors>
@@ -397,6 +821,9 @@ impl<In: RpcMessage, Out: RpcMessage, C: ConnectionErrors>
             RpcServerError::EarlyClose => RpcServerError::EarlyClose,
             RpcServerError::UnexpectedStartMessage => RpcServerError::UnexpectedStartMessage,
             RpcServerError::UnexpectedUpdateMessage => RpcServerError::UnexpectedUpdateMessage,
+            RpcServerError::SlowConsumer => RpcServerError::SlowConsumer,
+            RpcServerError::Rejected(reason) => RpcServerError::Rejected(reason),
+            RpcServerError::UpdatesAborted => RpcServerError::UpdatesAborted,
             RpcServerError::SendError(x) => RpcServerError::SendError(x),
             RpcServerError::Accept(x) => RpcServerError::Accept(x),
             RpcServerError::RecvError(ErrorOrMapError::Inner(x)) => RpcServerError::RecvError(x),
@@ -420,6 +847,9 @@ impl<C: ConnectionErrors> RpcServerError<C> {
             RpcServerError::EarlyClose => RpcServerError::EarlyClose,
             RpcServerError::UnexpectedStartMessage => RpcServerError::UnexpectedStartMessage,
             RpcServerError::UnexpectedUpdateMessage => RpcServerError::UnexpectedUpdateMessage,
+            RpcServerError::SlowConsumer => RpcServerError::SlowConsumer,
+            RpcServerError::Rejected(reason) => RpcServerError::Rejected(reason),
+            RpcServerError::UpdatesAborted => RpcServerError::UpdatesAborted,
             RpcServerError::SendError(x) => RpcServerError::SendError(x.into()),
             RpcServerError::Accept(x) => RpcServerError::Accept(x.into()),
             RpcServerError::RecvError(x) => RpcServerError::RecvError(x.into()),
@@ -436,6 +866,9 @@ impl<C: ConnectionErrors> fmt::Debug for RpcServerError<C> {
             Self::SendError(arg0) => f.debug_tuple("SendError").field(arg0).finish(),
             Self::UnexpectedStartMessage => f.debug_tuple("UnexpectedStartMessage").finish(),
             Self::UnexpectedUpdateMessage => f.debug_tuple("UnexpectedStartMessage").finish(),
+            Self::SlowConsumer => write!(f, "SlowConsumer"),
+            Self::Rejected(reason) => f.debug_tuple("Rejected").field(reason).finish(),
+            Self::UpdatesAborted => write!(f, "UpdatesAborted"),
         }
     }
 }
@@ -0,0 +1,178 @@
+//! Long lived subscriptions that survive reconnects
+//!
+//! A [Session] wraps a [RpcClient]. Server streaming requests that are registered
+//! through [Session::subscribe] are re-established whenever their stream fails or
+//! ends, e.g. because the underlying connection was lost and the connector had to
+//! reconnect. The request for the next attempt is created from the last item that
+//! was received, so it can carry a resume token.
+use std::{
+    fmt,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures_lite::{Stream, StreamExt};
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+use crate::{message::ServerStreamingMsg, rt, Connector, RpcClient, Service};
+
+/// A client session that re-establishes subscriptions
+#[derive(Debug)]
+pub struct Session<S, C> {
+    client: RpcClient<S, C>,
+    retry_delay: Duration,
+    max_retries: Option<u64>,
+}
+
+impl<S, C: Clone> Clone for Session<S, C> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            retry_delay: self.retry_delay,
+            max_retries: self.max_retries,
+        }
+    }
+}
+
+impl<S, C> Session<S, C>
+where
+    S: Service,
+    C: Connector<S> + Clone,
+{
+    /// Create a new session for the given client
+    ///
+    /// By default, failed subscriptions are retried every second, forever.
+    pub fn new(client: RpcClient<S, C>) -> Self {
+        Self {
+            client,
+            retry_delay: Duration::from_secs(1),
+            max_retries: None,
+        }
+    }
+
+    /// Set the delay between failing to open a subscription and retrying
+    pub fn retry_delay(mut self, delay: Duration) -> Self {
+        self.retry_delay = delay;
+        self
+    }
+
+    /// Set the maximum number of consecutive retries without receiving an item
+    ///
+    /// After that, the subscription ends.
+    pub fn max_retries(mut self, max_retries: u64) -> Self {
+        self.max_retries = Some(max_retries);
+        self
+    }
+
+    /// The underlying client
+    pub fn client(&self) -> &RpcClient<S, C> {
+        &self.client
+    }
+
+    /// Register a subscription
+    ///
+    /// `make_request` is called to create the request for every attempt, with the
+    /// last item that was received so far. It can return `None` to end the subscription.
+    ///
+    /// The subscription is driven by a separate task, which is aborted when the
+    /// returned stream is dropped.
+    pub fn subscribe<M, F>(&self, mut make_request: F) -> Subscription<M::Response>
+    where
+        M: ServerStreamingMsg<S>,
+        M::Response: Clone,
+        F: FnMut(Option<&M::Response>) -> Option<M> + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel(16);
+        let resumed = Arc::new(AtomicU64::new(0));
+        let client = self.client.clone();
+        let retry_delay = self.retry_delay;
+        let max_retries = self.max_retries;
+        let task = rt::spawn({
+            let resumed = resumed.clone();
+            async move {
+                let mut last: Option<M::Response> = None;
+                let mut failures = 0u64;
+                for attempt in 0u64.. {
+                    let Some(req) = make_request(last.as_ref()) else {
+                        break;
+                    };
+                    if attempt > 0 {
+                        resumed.fetch_add(1, Ordering::Relaxed);
+                    }
+                    match client.server_streaming(req).await {
+                        Ok(mut items) => {
+                            while let Some(item) = items.next().await {
+                                match item {
+                                    Ok(item) => {
+                                        failures = 0;
+                                        last = Some(item.clone());
+                                        if tx.send(item).await.is_err() {
+                                            return;
+                                        }
+                                    }
+                                    Err(cause) => {
+                                        debug!("subscription failed: {cause}");
+                                        break;
+                                    }
+                                }
+                            }
+                            // the stream ended, so try again
+                            failures += 1;
+                        }
+                        Err(cause) => {
+                            warn!("unable to open subscription: {cause}");
+                            failures += 1;
+                        }
+                    }
+                    if max_retries.is_some_and(|max| failures > max) {
+                        break;
+                    }
+                    if failures > 1 {
+                        rt::sleep(retry_delay).await;
+                    }
+                }
+            }
+        });
+        Subscription {
+            rx,
+            resumed,
+            _task: task,
+        }
+    }
+}
+
+/// A stream of items of a subscription, returned by [Session::subscribe]
+pub struct Subscription<T> {
+    rx: mpsc::Receiver<T>,
+    resumed: Arc<AtomicU64>,
+    _task: rt::Task<()>,
+}
+
+impl<T> Subscription<T> {
+    /// The number of times the subscription was re-established
+    pub fn resumed(&self) -> u64 {
+        self.resumed.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> fmt::Debug for Subscription<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscription")
+            .field("resumed", &self.resumed())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> Stream for Subscription<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
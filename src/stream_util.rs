@@ -0,0 +1,373 @@
+//! Adapters for client result streams
+//!
+//! The streaming client calls return streams of `Result<T, ItemError>`. The
+//! [ResultStreamExt] trait provides a few adapters that are commonly needed
+//! when consuming such streams.
+#[cfg(feature = "rt")]
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    task::Waker,
+};
+use std::{
+    fmt,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_lite::{Future, Stream, StreamExt};
+use pin_project::pin_project;
+use tokio_util::sync::{CancellationToken, WaitForCancellationFutureOwned};
+
+#[cfg(feature = "rt")]
+use crate::rt;
+
+/// Extension trait for streams of results
+pub trait ResultStreamExt<T, E>: Stream<Item = Result<T, E>> + Sized {
+    /// Convert the error type of the stream into [anyhow::Error]
+    fn into_anyhow(self) -> IntoAnyhow<Self>
+    where
+        E: Into<anyhow::Error>,
+    {
+        IntoAnyhow(self)
+    }
+
+    /// Flatten a stream of nested results into a stream of `anyhow::Result<U>`
+    ///
+    /// This is useful for streams where the items themselves are application level
+    /// results, e.g. `Result<Result<U, AppError>, ItemError>`.
+    fn flatten_err(self) -> FlattenErr<Self> {
+        FlattenErr(self)
+    }
+
+    /// End the stream as soon as the given token is cancelled
+    ///
+    /// Dropping the stream will drop the underlying client stream, which will
+    /// in turn cancel the request on the server side.
+    fn take_until_cancelled(self, token: CancellationToken) -> TakeUntilCancelled<Self> {
+        TakeUntilCancelled {
+            inner: Some(self),
+            cancelled: Box::pin(token.cancelled_owned()),
+        }
+    }
+
+    /// Eagerly read the stream into a buffer of the given capacity
+    ///
+    /// The stream is driven by a separate task, so the remote is never blocked
+    /// by a slow consumer. When the buffer is full, the oldest items are dropped
+    /// and the number of dropped items is reported as a [Buffered::Lagged] item
+    /// in their place.
+    ///
+    /// The task is aborted when the returned stream is dropped.
+    #[cfg(feature = "rt")]
+    #[cfg_attr(
+        quicrpc_docsrs,
+        doc(cfg(any(feature = "rt-tokio", feature = "rt-smol", feature = "rt-async-std")))
+    )]
+    fn buffered_lagging(self, capacity: usize) -> LagBuffered<Result<T, E>>
+    where
+        Self: Send + 'static,
+        T: Send + 'static,
+        E: Send + 'static,
+    {
+        LagBuffered::new(self, capacity)
+    }
+
+    /// Convert into a stream that terminates after the first error
+    ///
+    /// The client streams keep going after an item error, whereas consumers of
+    /// [futures_util::TryStream] usually expect the stream to end at the first error.
+    fn into_try_stream(self) -> IntoTryStream<Self> {
+        IntoTryStream { inner: Some(self) }
+    }
+}
+
+impl<S, T, E> ResultStreamExt<T, E> for S where S: Stream<Item = Result<T, E>> + Sized {}
+
+/// Flatten the result of opening a streaming call and the resulting stream into
+/// a single stream.
+///
+/// If opening the stream failed, the returned stream yields the open error as its only item.
+pub fn flatten_open<S, T, E, E2>(
+    res: Result<S, E2>,
+) -> impl Stream<Item = anyhow::Result<T>> + Send + 'static
+where
+    S: Stream<Item = Result<T, E>> + Send + Unpin + 'static,
+    E: Into<anyhow::Error>,
+    E2: Into<anyhow::Error> + Send + 'static,
+    T: Send + 'static,
+{
+    match res {
+        Ok(stream) => FlattenOpen::Stream(IntoAnyhow(stream)),
+        Err(cause) => FlattenOpen::Error(Some(cause.into())),
+    }
+}
+
+enum FlattenOpen<S> {
+    Stream(IntoAnyhow<S>),
+    Error(Option<anyhow::Error>),
+}
+
+impl<S, T, E> Stream for FlattenOpen<S>
+where
+    S: Stream<Item = Result<T, E>> + Unpin,
+    E: Into<anyhow::Error>,
+{
+    type Item = anyhow::Result<T>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.get_mut() {
+            FlattenOpen::Stream(stream) => Pin::new(stream).poll_next(cx),
+            FlattenOpen::Error(cause) => Poll::Ready(cause.take().map(Err)),
+        }
+    }
+}
+
+/// Stream returned by [ResultStreamExt::into_anyhow]
+#[pin_project]
+#[derive(Debug)]
+pub struct IntoAnyhow<S>(#[pin] S);
+
+impl<S, T, E> Stream for IntoAnyhow<S>
+where
+    S: Stream<Item = Result<T, E>>,
+    E: Into<anyhow::Error>,
+{
+    type Item = anyhow::Result<T>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.project()
+            .0
+            .poll_next(cx)
+            .map(|x| x.map(|x| x.map_err(Into::into)))
+    }
+}
+
+/// Stream returned by [ResultStreamExt::flatten_err]
+#[pin_project]
+#[derive(Debug)]
+pub struct FlattenErr<S>(#[pin] S);
+
+impl<S, U, E1, E2> Stream for FlattenErr<S>
+where
+    S: Stream<Item = Result<Result<U, E2>, E1>>,
+    E1: Into<anyhow::Error>,
+    E2: Into<anyhow::Error>,
+{
+    type Item = anyhow::Result<U>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.project().0.poll_next(cx).map(|x| {
+            x.map(|x| match x {
+                Ok(Ok(x)) => Ok(x),
+                Ok(Err(cause)) => Err(cause.into()),
+                Err(cause) => Err(cause.into()),
+            })
+        })
+    }
+}
+
+/// Stream returned by [ResultStreamExt::take_until_cancelled]
+pub struct TakeUntilCancelled<S> {
+    inner: Option<S>,
+    cancelled: Pin<Box<WaitForCancellationFutureOwned>>,
+}
+
+impl<S> fmt::Debug for TakeUntilCancelled<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TakeUntilCancelled").finish()
+    }
+}
+
+impl<S: Stream + Unpin> Stream for TakeUntilCancelled<S> {
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.inner.is_none() {
+            return Poll::Ready(None);
+        }
+        if self.cancelled.as_mut().poll(cx).is_ready() {
+            // drop the inner stream right away, so the remote gets notified
+            self.inner = None;
+            return Poll::Ready(None);
+        }
+        match self.inner.as_mut() {
+            Some(inner) => inner.poll_next(cx),
+            None => Poll::Ready(None),
+        }
+    }
+}
+
+/// Stream returned by [ResultStreamExt::into_try_stream]
+#[derive(Debug)]
+pub struct IntoTryStream<S> {
+    inner: Option<S>,
+}
+
+impl<S, T, E> Stream for IntoTryStream<S>
+where
+    S: Stream<Item = Result<T, E>> + Unpin,
+{
+    type Item = Result<T, E>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let Some(inner) = self.inner.as_mut() else {
+            return Poll::Ready(None);
+        };
+        let res = inner.poll_next(cx);
+        if let Poll::Ready(Some(Err(_))) | Poll::Ready(None) = &res {
+            self.inner = None;
+        }
+        res
+    }
+}
+
+/// An item of a [LagBuffered] stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Buffered<T> {
+    /// An item from the underlying stream
+    Item(T),
+    /// The consumer was too slow, and this many items were dropped
+    Lagged(u64),
+}
+
+#[cfg(feature = "rt")]
+#[derive(Debug)]
+struct LagState<T> {
+    items: VecDeque<T>,
+    lagged: u64,
+    done: bool,
+    waker: Option<Waker>,
+}
+
+/// Stream returned by [ResultStreamExt::buffered_lagging]
+#[cfg(feature = "rt")]
+#[cfg_attr(
+    quicrpc_docsrs,
+    doc(cfg(any(feature = "rt-tokio", feature = "rt-smol", feature = "rt-async-std")))
+)]
+pub struct LagBuffered<T> {
+    state: Arc<Mutex<LagState<T>>>,
+    _task: rt::Task<()>,
+}
+
+#[cfg(feature = "rt")]
+impl<T> fmt::Debug for LagBuffered<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LagBuffered").finish()
+    }
+}
+
+#[cfg(feature = "rt")]
+impl<T: Send + 'static> LagBuffered<T> {
+    fn new(stream: impl Stream<Item = T> + Send + 'static, capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        let state = Arc::new(Mutex::new(LagState {
+            items: VecDeque::with_capacity(capacity),
+            lagged: 0,
+            done: false,
+            waker: None,
+        }));
+        let task = rt::spawn({
+            let state = state.clone();
+            async move {
+                tokio::pin!(stream);
+                while let Some(item) = stream.next().await {
+                    let mut state = state.lock().unwrap();
+                    if state.items.len() == capacity {
+                        state.items.pop_front();
+                        state.lagged += 1;
+                    }
+                    state.items.push_back(item);
+                    if let Some(waker) = state.waker.take() {
+                        waker.wake();
+                    }
+                }
+                let mut state = state.lock().unwrap();
+                state.done = true;
+                if let Some(waker) = state.waker.take() {
+                    waker.wake();
+                }
+            }
+        });
+        Self { state, _task: task }
+    }
+}
+
+#[cfg(feature = "rt")]
+impl<T> Stream for LagBuffered<T> {
+    type Item = Buffered<T>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut state = self.state.lock().unwrap();
+        if state.lagged > 0 {
+            let lagged = std::mem::take(&mut state.lagged);
+            return Poll::Ready(Some(Buffered::Lagged(lagged)));
+        }
+        if let Some(item) = state.items.pop_front() {
+            return Poll::Ready(Some(Buffered::Item(item)));
+        }
+        if state.done {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::{stream, StreamExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn flatten_and_try_stream() {
+        let items: Vec<Result<Result<u64, std::io::Error>, std::io::Error>> =
+            vec![Ok(Ok(1)), Ok(Err(std::io::Error::other("app"))), Ok(Ok(2))];
+        let res: Vec<_> = stream::iter(items).flatten_err().collect().await;
+        assert_eq!(res.len(), 3);
+        assert!(res[1].is_err());
+
+        let items: Vec<Result<u64, std::io::Error>> =
+            vec![Ok(1), Err(std::io::Error::other("boom")), Ok(2)];
+        let res: Vec<_> = stream::iter(items).into_try_stream().collect().await;
+        assert_eq!(res.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn take_until_cancelled() {
+        let token = CancellationToken::new();
+        let mut s = stream::repeat(Ok::<_, String>(1u64)).take_until_cancelled(token.clone());
+        assert!(s.next().await.is_some());
+        token.cancel();
+        assert!(s.next().await.is_none());
+    }
+
+    #[cfg(feature = "rt-tokio")]
+    #[tokio::test]
+    async fn buffered_lagging() {
+        // fires when the task polls past the last item, which it only does after
+        // pushing all items into the buffer
+        let (filled_tx, filled_rx) = tokio::sync::oneshot::channel();
+        let mut filled_tx = Some(filled_tx);
+        let filled = stream::poll_fn(move |_| {
+            if let Some(tx) = filled_tx.take() {
+                tx.send(()).ok();
+            }
+            Poll::Ready(None)
+        });
+        let items = stream::iter((0..10u64).map(Ok::<_, String>)).chain(filled);
+        let mut s = items.buffered_lagging(4);
+        filled_rx.await.unwrap();
+        assert_eq!(s.next().await, Some(Buffered::Lagged(6)));
+        let rest: Vec<_> = s
+            .map(|x| match x {
+                Buffered::Item(x) => x.unwrap(),
+                Buffered::Lagged(_) => panic!("unexpected lag"),
+            })
+            .collect()
+            .await;
+        assert_eq!(rest, vec![6, 7, 8, 9]);
+    }
+}
@@ -0,0 +1,320 @@
+//! Conformance vectors for implementations of the wire protocol in other languages.
+//!
+//! On the wire, every message of a substream is encoded using postcard, and
+//! prefixed with its length as a 4 byte big endian integer. [TestVectors]
+//! collects example requests and responses of a [Service] and writes these
+//! frames to disk, so that other implementations can check that they encode
+//! and decode the same bytes.
+//!
+//! Rust can not enumerate the variants of an enum, so there must be one
+//! example per variant that should be covered.
+//!
+//! To pin the encoding of messages in the test suite of a service, use
+//! [assert_wire_roundtrip!](crate::assert_wire_roundtrip) or
+//! [assert_wire_roundtrip_all].
+//!
+//! # Example
+//! ```no_run
+//! # use quic_rpc::{test_vectors::TestVectors, Service};
+//! # use serde::{Deserialize, Serialize};
+//! # #[derive(Debug, Serialize, Deserialize)]
+//! # enum Request { Ping(u64) }
+//! # #[derive(Debug, Serialize, Deserialize)]
+//! # enum Response { Pong(u64) }
+//! # #[derive(Debug, Clone)]
+//! # struct PingService;
+//! # impl Service for PingService { type Req = Request; type Res = Response; }
+//! TestVectors::<PingService>::new()
+//!     .request("ping", Request::Ping(42))
+//!     .response("pong", Response::Pong(42))
+//!     .write_to("vectors")?;
+//! # std::io::Result::Ok(())
+//! ```
+use std::{
+    fmt::{self, Write},
+    fs, io,
+    path::Path,
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::Service;
+
+/// Encode a message as a frame, as it is sent on a substream
+pub fn encode_frame<T: Serialize>(msg: &T) -> Result<Vec<u8>, postcard::Error> {
+    let payload = postcard::to_stdvec(msg)?;
+    let len = u32::try_from(payload.len()).map_err(|_| postcard::Error::SerializeBufferFull)?;
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(&payload);
+    Ok(frame)
+}
+
+/// Decode a frame, as it is received on a substream
+///
+/// Fails if the length prefix does not match the length of the frame, or if the
+/// payload is not a complete encoding of a `T`.
+pub fn decode_frame<T: DeserializeOwned>(frame: &[u8]) -> Result<T, postcard::Error> {
+    if frame.len() < 4 {
+        return Err(postcard::Error::DeserializeUnexpectedEnd);
+    }
+    let (len, payload) = frame.split_at(4);
+    if u32::from_be_bytes(len.try_into().unwrap()) as usize != payload.len() {
+        return Err(postcard::Error::DeserializeBadEncoding);
+    }
+    let (msg, rest) = postcard::take_from_bytes(payload)?;
+    if !rest.is_empty() {
+        return Err(postcard::Error::DeserializeBadEncoding);
+    }
+    Ok(msg)
+}
+
+/// Assert that a message survives being encoded and decoded as a frame
+///
+/// Returns the encoded frame, so it can be compared with a known good value.
+/// Panics if the message can not be encoded or decoded, or if the decoded
+/// message is not equal to the original.
+#[track_caller]
+pub fn assert_wire_roundtrip<T>(msg: &T) -> Vec<u8>
+where
+    T: Serialize + DeserializeOwned + PartialEq + fmt::Debug,
+{
+    let frame = match encode_frame(msg) {
+        Ok(frame) => frame,
+        Err(cause) => panic!("unable to encode {msg:?}: {cause}"),
+    };
+    let decoded: T = match decode_frame(&frame) {
+        Ok(decoded) => decoded,
+        Err(cause) => panic!("unable to decode {msg:?} from {frame:02x?}: {cause}"),
+    };
+    assert_eq!(&decoded, msg, "message changed in a wire round trip");
+    frame
+}
+
+/// Assert that all messages survive being encoded and decoded as a frame
+///
+/// See [assert_wire_roundtrip].
+#[track_caller]
+pub fn assert_wire_roundtrip_all<T>(msgs: impl IntoIterator<Item = T>)
+where
+    T: Serialize + DeserializeOwned + PartialEq + fmt::Debug,
+{
+    for msg in msgs {
+        assert_wire_roundtrip(&msg);
+    }
+}
+
+/// Assert that a message survives being encoded and decoded as a frame
+///
+/// With a second argument, also assert that the encoded frame, including the
+/// length prefix, is equal to the given bytes. This pins the wire encoding, so
+/// that accidental changes to a request or response enum, like reordering its
+/// variants, make the test fail.
+///
+/// # Example
+/// ```
+/// # use serde::{Deserialize, Serialize};
+/// #[derive(Debug, PartialEq, Serialize, Deserialize)]
+/// enum Request {
+///     Ping(u64),
+/// }
+///
+/// quic_rpc::assert_wire_roundtrip!(Request::Ping(300));
+/// quic_rpc::assert_wire_roundtrip!(Request::Ping(300), [0, 0, 0, 3, 0, 0xac, 0x02]);
+/// ```
+#[macro_export]
+macro_rules! assert_wire_roundtrip {
+    ($msg:expr $(,)?) => {
+        $crate::test_vectors::assert_wire_roundtrip(&$msg);
+    };
+    ($msg:expr, $frame:expr $(,)?) => {
+        let frame = $crate::test_vectors::assert_wire_roundtrip(&$msg);
+        assert_eq!(
+            frame.as_slice(),
+            &$frame[..],
+            "wire encoding of {} changed",
+            stringify!($msg)
+        );
+    };
+}
+
+/// Direction of a test vector
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// A request, sent from the client to the server
+    Request,
+    /// A response, sent from the server to the client
+    Response,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Request => write!(f, "request"),
+            Self::Response => write!(f, "response"),
+        }
+    }
+}
+
+/// A single encoded example message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestVector {
+    /// Name of the vector, used as file name
+    pub name: String,
+    /// Whether this is a request or a response
+    pub kind: Kind,
+    /// Debug representation of the message
+    pub debug: String,
+    /// The encoded frame, including the length prefix
+    pub frame: Vec<u8>,
+}
+
+/// A collection of test vectors for a service
+#[derive(Debug)]
+pub struct TestVectors<S> {
+    vectors: Vec<TestVector>,
+    _s: std::marker::PhantomData<S>,
+}
+
+impl<S: Service> Default for TestVectors<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: Service> TestVectors<S> {
+    /// Create an empty collection
+    pub fn new() -> Self {
+        Self {
+            vectors: Vec::new(),
+            _s: std::marker::PhantomData,
+        }
+    }
+
+    /// Add an example request
+    ///
+    /// Panics if the request can not be encoded, or if the name is used twice.
+    pub fn request(self, name: impl Into<String>, req: impl Into<S::Req>) -> Self {
+        self.push(name.into(), Kind::Request, &req.into())
+    }
+
+    /// Add an example response
+    ///
+    /// Panics if the response can not be encoded, or if the name is used twice.
+    pub fn response(self, name: impl Into<String>, res: impl Into<S::Res>) -> Self {
+        self.push(name.into(), Kind::Response, &res.into())
+    }
+
+    fn push<T: Serialize + fmt::Debug>(mut self, name: String, kind: Kind, msg: &T) -> Self {
+        assert!(
+            !self
+                .vectors
+                .iter()
+                .any(|v| v.name == name && v.kind == kind),
+            "duplicate {kind} test vector {name}"
+        );
+        let frame = encode_frame(msg).expect("unable to encode test vector");
+        self.vectors.push(TestVector {
+            name,
+            kind,
+            debug: format!("{msg:?}"),
+            frame,
+        });
+        self
+    }
+
+    /// The collected test vectors
+    pub fn vectors(&self) -> &[TestVector] {
+        &self.vectors
+    }
+
+    /// Write the test vectors to the given directory
+    ///
+    /// Each frame is written to `<kind>/<name>.bin`. An `index.txt` lists one
+    /// vector per line, with the kind, name, hex encoded frame and debug
+    /// representation separated by tabs.
+    pub fn write_to(&self, dir: impl AsRef<Path>) -> io::Result<()> {
+        let dir = dir.as_ref();
+        let mut index = String::new();
+        for kind in [Kind::Request, Kind::Response] {
+            fs::create_dir_all(dir.join(kind.to_string()))?;
+        }
+        for vector in &self.vectors {
+            let path = dir
+                .join(vector.kind.to_string())
+                .join(format!("{}.bin", vector.name));
+            fs::write(path, &vector.frame)?;
+            write!(index, "{}\t{}\t", vector.kind, vector.name).unwrap();
+            for byte in &vector.frame {
+                write!(index, "{byte:02x}").unwrap();
+            }
+            writeln!(index, "\t{}", vector.debug.replace(['\t', '\n'], " ")).unwrap();
+        }
+        fs::write(dir.join("index.txt"), index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Clone)]
+    struct PingService;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Request {
+        Ping(u64),
+        Hello(String),
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    enum Response {
+        Pong(u64),
+    }
+
+    impl Service for PingService {
+        type Req = Request;
+        type Res = Response;
+    }
+
+    #[test]
+    fn write_vectors() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        TestVectors::<PingService>::new()
+            .request("ping", Request::Ping(300))
+            .request("hello", Request::Hello("hi".into()))
+            .response("pong", Response::Pong(300))
+            .write_to(dir.path())?;
+        // variant 0, then 300 as a varint
+        assert_eq!(
+            fs::read(dir.path().join("request/ping.bin"))?,
+            [0, 0, 0, 3, 0, 0xac, 0x02]
+        );
+        // variant 1, then the length prefixed string
+        assert_eq!(
+            fs::read(dir.path().join("request/hello.bin"))?,
+            [0, 0, 0, 4, 1, 2, b'h', b'i']
+        );
+        let index = fs::read_to_string(dir.path().join("index.txt"))?;
+        assert_eq!(index.lines().count(), 3);
+        assert!(index.contains("response\tpong\t0000000300ac02\tPong(300)"));
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip() {
+        crate::assert_wire_roundtrip!(Request::Ping(300), [0, 0, 0, 3, 0, 0xac, 0x02]);
+        assert_wire_roundtrip_all([Request::Ping(0), Request::Hello("hi".into())]);
+        // trailing bytes and a wrong length prefix are rejected
+        assert!(decode_frame::<Request>(&[0, 0, 0, 3, 0, 1, 2]).is_err());
+        assert!(decode_frame::<Request>(&[0, 0, 0, 2, 0, 1, 2]).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "wire encoding of Request::Ping(1) changed")]
+    fn roundtrip_changed() {
+        crate::assert_wire_roundtrip!(Request::Ping(1), [0, 0, 0, 2, 0, 2]);
+    }
+}
@@ -0,0 +1,279 @@
+//! Transport wrapper that measures backpressure on send sinks.
+//!
+//! The wrappers record how often and for how long the send sinks of all substreams
+//! block in `poll_ready` or `poll_flush`. A sink blocks when the network or the remote can not
+//! keep up, so if handlers are slow while the send sinks rarely block, the
+//! cause is local.
+//!
+//! Stats are shared between clones of a wrapper, and all substreams opened or
+//! accepted through it. Wrap separate connectors to get separate stats.
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use futures_sink::Sink;
+use pin_project::pin_project;
+
+use super::{ConnectionErrors, Connector, Listener, LocalAddr, StreamTypes};
+
+/// Counters for backpressure on send sinks
+#[derive(Debug, Default)]
+pub struct BackpressureStats {
+    sent: AtomicU64,
+    blocked: AtomicU64,
+    blocked_nanos: AtomicU64,
+    max_blocked_nanos: AtomicU64,
+}
+
+impl BackpressureStats {
+    /// A snapshot of the current values
+    pub fn snapshot(&self) -> BackpressureSnapshot {
+        BackpressureSnapshot {
+            sent: self.sent.load(Ordering::Relaxed),
+            blocked: self.blocked.load(Ordering::Relaxed),
+            blocked_time: Duration::from_nanos(self.blocked_nanos.load(Ordering::Relaxed)),
+            max_blocked_time: Duration::from_nanos(self.max_blocked_nanos.load(Ordering::Relaxed)),
+        }
+    }
+
+    fn record_blocked(&self, elapsed: Duration) {
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        self.blocked.fetch_add(1, Ordering::Relaxed);
+        self.blocked_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.max_blocked_nanos.fetch_max(nanos, Ordering::Relaxed);
+    }
+}
+
+/// A snapshot of [BackpressureStats]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BackpressureSnapshot {
+    /// Number of messages sent
+    pub sent: u64,
+    /// Number of times the sink did not become ready or flushed immediately
+    pub blocked: u64,
+    /// Total time spent waiting for the sink
+    pub blocked_time: Duration,
+    /// Longest single wait for the sink
+    pub max_blocked_time: Duration,
+}
+
+/// A send sink that records how long `poll_ready` and `poll_flush` block
+#[pin_project]
+#[derive(Debug)]
+pub struct SendSink<S> {
+    #[pin]
+    inner: S,
+    blocked_since: Option<Instant>,
+    stats: Arc<BackpressureStats>,
+}
+
+impl<S> SendSink<S> {
+    /// Wrap a send sink, recording into the given stats
+    pub fn new(inner: S, stats: Arc<BackpressureStats>) -> Self {
+        Self {
+            inner,
+            blocked_since: None,
+            stats,
+        }
+    }
+
+    /// Get the inner sink
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+/// Start or stop measuring a blocked period
+fn record(pending: bool, blocked_since: &mut Option<Instant>, stats: &BackpressureStats) {
+    if pending {
+        blocked_since.get_or_insert_with(Instant::now);
+    } else if let Some(start) = blocked_since.take() {
+        stats.record_blocked(start.elapsed());
+    }
+}
+
+impl<S: Sink<T>, T> Sink<T> for SendSink<S> {
+    type Error = S::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        let res = this.inner.poll_ready(cx);
+        record(res.is_pending(), this.blocked_since, this.stats);
+        res
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        let this = self.project();
+        this.stats.sent.fetch_add(1, Ordering::Relaxed);
+        this.inner.start_send(item)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        let res = this.inner.poll_flush(cx);
+        record(res.is_pending(), this.blocked_since, this.stats);
+        res
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_close(cx)
+    }
+}
+
+/// A connector that records backpressure on all substreams it opens
+#[derive(Debug, Clone)]
+pub struct BackpressureConnector<C> {
+    inner: C,
+    stats: Arc<BackpressureStats>,
+}
+
+impl<C> BackpressureConnector<C> {
+    /// Wrap a connector
+    pub fn new(inner: C) -> Self {
+        Self {
+            inner,
+            stats: Default::default(),
+        }
+    }
+
+    /// The backpressure stats of all substreams opened by this connector
+    pub fn stats(&self) -> &Arc<BackpressureStats> {
+        &self.stats
+    }
+}
+
+impl<C: ConnectionErrors> ConnectionErrors for BackpressureConnector<C> {
+    type SendError = C::SendError;
+    type RecvError = C::RecvError;
+    type OpenError = C::OpenError;
+    type AcceptError = C::AcceptError;
+}
+
+impl<C: StreamTypes> StreamTypes for BackpressureConnector<C> {
+    type In = C::In;
+    type Out = C::Out;
+    type RecvStream = C::RecvStream;
+    type SendSink = SendSink<C::SendSink>;
+}
+
+impl<C: Connector> Connector for BackpressureConnector<C> {
+    async fn open(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        let (send, recv) = self.inner.open().await?;
+        Ok((SendSink::new(send, self.stats.clone()), recv))
+    }
+
+    async fn open_with_priority(
+        &self,
+        priority: i32,
+    ) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        let (send, recv) = self.inner.open_with_priority(priority).await?;
+        Ok((SendSink::new(send, self.stats.clone()), recv))
+    }
+
+    async fn open_uni(&self) -> Result<Self::SendSink, Self::OpenError> {
+        let send = self.inner.open_uni().await?;
+        Ok(SendSink::new(send, self.stats.clone()))
+    }
+
+    async fn send_datagram(&self, msg: Self::Out) -> Result<Option<Self::Out>, Self::SendError> {
+        self.inner.send_datagram(msg).await
+    }
+}
+
+/// A listener that records backpressure on all substreams it accepts
+#[derive(Debug, Clone)]
+pub struct BackpressureListener<C> {
+    inner: C,
+    stats: Arc<BackpressureStats>,
+}
+
+impl<C> BackpressureListener<C> {
+    /// Wrap a listener
+    pub fn new(inner: C) -> Self {
+        Self {
+            inner,
+            stats: Default::default(),
+        }
+    }
+
+    /// The backpressure stats of all substreams accepted by this listener
+    pub fn stats(&self) -> &Arc<BackpressureStats> {
+        &self.stats
+    }
+}
+
+impl<C: ConnectionErrors> ConnectionErrors for BackpressureListener<C> {
+    type SendError = C::SendError;
+    type RecvError = C::RecvError;
+    type OpenError = C::OpenError;
+    type AcceptError = C::AcceptError;
+}
+
+impl<C: StreamTypes> StreamTypes for BackpressureListener<C> {
+    type In = C::In;
+    type Out = C::Out;
+    type RecvStream = C::RecvStream;
+    type SendSink = SendSink<C::SendSink>;
+}
+
+impl<C: Listener> Listener for BackpressureListener<C> {
+    async fn accept(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::AcceptError> {
+        let (send, recv) = self.inner.accept().await?;
+        Ok((SendSink::new(send, self.stats.clone()), recv))
+    }
+
+    fn local_addr(&self) -> &[LocalAddr] {
+        self.inner.local_addr()
+    }
+
+    async fn accept_uni(&self) -> Result<Self::RecvStream, Self::AcceptError> {
+        self.inner.accept_uni().await
+    }
+
+    async fn recv_datagram(&self) -> Result<Self::In, Self::RecvError> {
+        self.inner.recv_datagram().await
+    }
+}
+
+#[cfg(all(test, feature = "flume-transport"))]
+mod tests {
+    use futures_lite::StreamExt;
+    use futures_util::SinkExt;
+
+    use super::*;
+    use crate::transport::flume;
+
+    #[tokio::test]
+    async fn records_blocked_sends() -> anyhow::Result<()> {
+        let (listener, connector) = flume::channel::<u64, u64>(1);
+        let connector = BackpressureConnector::new(connector);
+        let listener = BackpressureListener::new(listener);
+        let server = tokio::spawn(async move {
+            let (_send, mut recv) = listener.accept().await?;
+            // be a slow reader
+            while let Some(item) = recv.next().await {
+                item?;
+                tokio::time::sleep(Duration::from_micros(100)).await;
+            }
+            anyhow::Ok(())
+        });
+        let (mut send, _recv) = connector.open().await?;
+        // more than the buffer of the flume substream
+        for i in 0..200 {
+            send.send(i).await?;
+        }
+        drop(send);
+        server.await??;
+        let stats = connector.stats().snapshot();
+        assert_eq!(stats.sent, 200);
+        assert!(stats.blocked > 0);
+        assert!(stats.blocked_time >= stats.max_blocked_time);
+        Ok(())
+    }
+}
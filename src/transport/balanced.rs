@@ -0,0 +1,287 @@
+//! Transport that distributes channels over connections to replica servers
+//!
+//! A [BalancedConnection] holds a connector per replica of a stateless service,
+//! and opens channels on them in round-robin order. A connector on which opening
+//! a channel failed [max_failures](BalancedConnection::with_max_failures) times in
+//! a row is ejected, and skipped for the [eject
+//! duration](BalancedConnection::with_eject_duration). After that, the next open
+//! on it probes the connector again: if it succeeds, the connector is healthy
+//! again, if it fails, it is ejected right away.
+//!
+//! Only failures to open a channel count. A replica that accepts channels but
+//! fails the calls on them stays in rotation.
+use std::{
+    fmt,
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use super::{ConnectionErrors, Connector, StreamTypes};
+
+/// Default number of failed opens in a row after which a connector is ejected
+pub const DEFAULT_MAX_FAILURES: u32 = 3;
+
+/// Default time for which an ejected connector is skipped
+pub const DEFAULT_EJECT_DURATION: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Health {
+    /// Failed opens in a row
+    failures: u32,
+    /// The connector is skipped until then
+    ejected_until: Option<Instant>,
+}
+
+/// A connector that distributes channels over multiple connectors
+pub struct BalancedConnection<C> {
+    connections: Arc<[C]>,
+    health: Arc<Mutex<Vec<Health>>>,
+    next: Arc<AtomicUsize>,
+    max_failures: u32,
+    eject_for: Duration,
+}
+
+impl<C: Connector> BalancedConnection<C> {
+    /// Create a balanced connection over the given connectors
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no connectors.
+    pub fn new(connections: impl IntoIterator<Item = C>) -> Self {
+        let connections: Arc<[C]> = connections.into_iter().collect();
+        assert!(!connections.is_empty(), "no connections");
+        Self {
+            health: Arc::new(Mutex::new(vec![Health::default(); connections.len()])),
+            connections,
+            next: Default::default(),
+            max_failures: DEFAULT_MAX_FAILURES,
+            eject_for: DEFAULT_EJECT_DURATION,
+        }
+    }
+
+    /// Set the number of failed opens in a row after which a connector is ejected
+    ///
+    /// The default is [DEFAULT_MAX_FAILURES].
+    pub fn with_max_failures(mut self, value: u32) -> Self {
+        self.max_failures = value.max(1);
+        self
+    }
+
+    /// Set the time for which an ejected connector is skipped
+    ///
+    /// The default is [DEFAULT_EJECT_DURATION].
+    pub fn with_eject_duration(mut self, value: Duration) -> Self {
+        self.eject_for = value;
+        self
+    }
+
+    /// The connectors the channels are distributed over
+    pub fn connections(&self) -> &[C] {
+        &self.connections
+    }
+
+    /// Whether the connector with the given index is currently ejected
+    pub fn is_ejected(&self, index: usize) -> bool {
+        let health = self.health.lock().unwrap();
+        health[index]
+            .ejected_until
+            .is_some_and(|until| Instant::now() < until)
+    }
+
+    /// The indices of the connectors to try, in order
+    ///
+    /// Ejected connectors are skipped, unless all connectors are ejected. Then they
+    /// are all tried, so that the connection recovers as soon as a replica does.
+    fn candidates(&self) -> Vec<usize> {
+        let n = self.connections.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed) % n;
+        let order = (0..n).map(|i| (start + i) % n);
+        let now = Instant::now();
+        let health = self.health.lock().unwrap();
+        let healthy = order
+            .clone()
+            .filter(|&i| health[i].ejected_until.map_or(true, |until| until <= now))
+            .collect::<Vec<_>>();
+        if healthy.is_empty() {
+            order.collect()
+        } else {
+            healthy
+        }
+    }
+
+    fn record(&self, index: usize, ok: bool) {
+        let mut health = self.health.lock().unwrap();
+        let health = &mut health[index];
+        if ok {
+            *health = Health::default();
+            return;
+        }
+        health.failures = health.failures.saturating_add(1);
+        if health.failures >= self.max_failures {
+            tracing::debug!(
+                "ejecting connection {index} after {} failures",
+                health.failures
+            );
+            health.ejected_until = Some(Instant::now() + self.eject_for);
+        }
+    }
+
+    /// Open on the healthy connectors in turn, until opening succeeds on one
+    async fn open_by<T, F, Fut>(&self, f: F) -> Result<T, C::OpenError>
+    where
+        F: Fn(C) -> Fut,
+        Fut: Future<Output = Result<T, C::OpenError>>,
+    {
+        let mut last_err = None;
+        for index in self.candidates() {
+            match f(self.connections[index].clone()).await {
+                Ok(res) => {
+                    self.record(index, true);
+                    return Ok(res);
+                }
+                Err(cause) => {
+                    tracing::debug!("open failed on connection {index}: {cause}");
+                    self.record(index, false);
+                    last_err = Some(cause);
+                }
+            }
+        }
+        Err(last_err.expect("there is at least one connection"))
+    }
+}
+
+impl<C> Clone for BalancedConnection<C> {
+    fn clone(&self) -> Self {
+        Self {
+            connections: self.connections.clone(),
+            health: self.health.clone(),
+            next: self.next.clone(),
+            max_failures: self.max_failures,
+            eject_for: self.eject_for,
+        }
+    }
+}
+
+impl<C: fmt::Debug> fmt::Debug for BalancedConnection<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BalancedConnection")
+            .field("connections", &self.connections)
+            .field("max_failures", &self.max_failures)
+            .field("eject_for", &self.eject_for)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<C: ConnectionErrors> ConnectionErrors for BalancedConnection<C> {
+    type SendError = C::SendError;
+    type RecvError = C::RecvError;
+    type OpenError = C::OpenError;
+    type AcceptError = C::AcceptError;
+}
+
+impl<C: StreamTypes> StreamTypes for BalancedConnection<C> {
+    type In = C::In;
+    type Out = C::Out;
+    type RecvStream = C::RecvStream;
+    type SendSink = C::SendSink;
+}
+
+impl<C: Connector> Connector for BalancedConnection<C> {
+    /// Open a channel on the next healthy connector
+    ///
+    /// If opening fails, the next healthy connector is tried. The error of the
+    /// last connector is returned if opening fails on all of them.
+    async fn open(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        self.open_by(|connector| async move { connector.open().await })
+            .await
+    }
+
+    /// Open a channel with a priority hint on the next healthy connector
+    ///
+    /// Connectors are tried in the same way as for [open](Self::open).
+    async fn open_with_priority(
+        &self,
+        priority: i32,
+    ) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        self.open_by(|connector| async move { connector.open_with_priority(priority).await })
+            .await
+    }
+
+    /// Open a unidirectional channel on the next healthy connector
+    ///
+    /// Connectors are tried in the same way as for [open](Self::open).
+    async fn open_uni(&self) -> Result<Self::SendSink, Self::OpenError> {
+        self.open_by(|connector| async move { connector.open_uni().await })
+            .await
+    }
+
+    /// Send a datagram on the next healthy connector
+    ///
+    /// If that connector does not support datagrams, the message is returned.
+    async fn send_datagram(&self, msg: Self::Out) -> Result<Option<Self::Out>, Self::SendError> {
+        let index = self.candidates()[0];
+        self.connections[index].send_datagram(msg).await
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "flume-transport")]
+mod tests {
+    use super::*;
+    use crate::transport::{flume, Listener};
+
+    #[tokio::test]
+    async fn round_robin_and_eject() {
+        let (l0, c0) = flume::channel::<(), ()>(8);
+        let (l1, c1) = flume::channel::<(), ()>(8);
+        let (l2, c2) = flume::channel::<(), ()>(8);
+        let connection = BalancedConnection::new([c0, c1, c2])
+            .with_max_failures(2)
+            .with_eject_duration(Duration::from_millis(50));
+        // opens are distributed evenly
+        for _ in 0..2 {
+            connection.open().await.unwrap();
+        }
+        connection.clone().open().await.unwrap();
+        for listener in [&l0, &l1, &l2] {
+            listener.accept().await.unwrap();
+        }
+
+        // a dead replica is skipped, and ejected after repeated failures
+        drop(l1);
+        for _ in 0..6 {
+            connection.open().await.unwrap();
+        }
+        assert!(connection.is_ejected(1));
+        assert!(!connection.is_ejected(0) && !connection.is_ejected(2));
+        // opens that failed on the dead replica went to the next one
+        for (listener, n) in [(&l0, 2), (&l2, 4)] {
+            for _ in 0..n {
+                listener.accept().await.unwrap();
+            }
+        }
+
+        // after the eject duration, a failed probe ejects it right away
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(!connection.is_ejected(1));
+        for _ in 0..3 {
+            connection.open().await.unwrap();
+        }
+        assert!(connection.is_ejected(1));
+    }
+
+    #[tokio::test]
+    async fn all_failed() {
+        let (_, c0) = flume::channel::<(), ()>(1);
+        let (_, c1) = flume::channel::<(), ()>(1);
+        let connection = BalancedConnection::new([c0, c1]).with_max_failures(1);
+        assert!(connection.open().await.is_err());
+        assert!(connection.is_ejected(0) && connection.is_ejected(1));
+        // ejected connectors are still tried if there is nothing else
+        assert!(connection.open().await.is_err());
+    }
+}
@@ -4,6 +4,7 @@ use std::{
     fmt::Debug,
     future::Future,
     pin::Pin,
+    sync::{Arc, RwLock},
     task::{Context, Poll},
 };
 
@@ -12,7 +13,7 @@ use futures_sink::Sink;
 use futures_util::{future::BoxFuture, SinkExt, Stream, StreamExt, TryStreamExt};
 use pin_project::pin_project;
 
-use super::{ConnectionErrors, StreamTypes};
+use super::{ConnectionErrors, PeerIdentity, PeerInfo, StreamTypes};
 use crate::RpcMessage;
 type BoxedFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + Sync + 'a>>;
 
@@ -61,7 +62,7 @@ impl<T: RpcMessage> Sink<T> for SendSink<T> {
         match self.project().0 {
             #[cfg(feature = "flume-transport")]
             SendSinkInner::Direct(sink) => sink.start_send_unpin(item).map_err(anyhow::Error::from),
-            SendSinkInner::Boxed(sink) => sink.start_send_unpin(item).map_err(anyhow::Error::from),
+            SendSinkInner::Boxed(sink) => sink.start_send_unpin(item),
         }
     }
 
@@ -99,20 +100,32 @@ enum RecvStreamInner<T: RpcMessage> {
 /// For local channels, this is a thin wrapper around a flume receive stream.
 /// For network channels, this contains a boxed stream, since it is reasonable
 #[pin_project]
-pub struct RecvStream<T: RpcMessage>(RecvStreamInner<T>);
+pub struct RecvStream<T: RpcMessage>(RecvStreamInner<T>, Option<PeerIdentity>);
 
 impl<T: RpcMessage> RecvStream<T> {
     /// Create a new receive stream from a boxed stream
     pub fn boxed(
         stream: impl Stream<Item = Result<T, anyhow::Error>> + Send + Sync + 'static,
     ) -> Self {
-        Self(RecvStreamInner::Boxed(Box::pin(stream)))
+        Self(RecvStreamInner::Boxed(Box::pin(stream)), None)
     }
 
     /// Create a new receive stream from a direct flume receive stream
     #[cfg(feature = "flume-transport")]
     pub(crate) fn direct(stream: ::flume::r#async::RecvStream<'static, T>) -> Self {
-        Self(RecvStreamInner::Direct(stream))
+        Self(RecvStreamInner::Direct(stream), None)
+    }
+
+    /// Keep the identity of the remote, since the boxed stream hides it
+    pub fn with_peer_identity(mut self, peer: Option<PeerIdentity>) -> Self {
+        self.1 = peer;
+        self
+    }
+}
+
+impl<T: RpcMessage> PeerInfo for RecvStream<T> {
+    fn peer_identity(&self) -> Option<&PeerIdentity> {
+        self.1.as_ref()
     }
 }
 
@@ -220,7 +233,32 @@ pub trait BoxableConnector<In: RpcMessage, Out: RpcMessage>: Debug + Send + Sync
     fn clone_box(&self) -> Box<dyn BoxableConnector<In, Out>>;
 
     /// Open a channel to the remote che
-    fn open_boxed(&self) -> OpenFuture<In, Out>;
+    fn open_boxed(&self) -> OpenFuture<'_, In, Out>;
+
+    /// Open a channel with a priority hint, see [Connector::open_with_priority](super::Connector::open_with_priority)
+    ///
+    /// The default implementation ignores the priority.
+    fn open_with_priority_boxed(&self, priority: i32) -> OpenFuture<'_, In, Out> {
+        let _ = priority;
+        self.open_boxed()
+    }
+
+    /// Open a unidirectional channel, see [Connector::open_uni](super::Connector::open_uni)
+    ///
+    /// The default implementation drops the receive side of a bidirectional channel.
+    fn open_uni_boxed(&self) -> BoxFuture<'_, anyhow::Result<SendSink<Out>>> {
+        Box::pin(async move {
+            let (send, _recv) = self.open_boxed().await?;
+            Ok(send)
+        })
+    }
+
+    /// Send a message as a datagram, see [Connector::send_datagram](super::Connector::send_datagram)
+    ///
+    /// The default implementation does not support datagrams.
+    fn send_datagram_boxed(&self, msg: Out) -> BoxFuture<'_, anyhow::Result<Option<Out>>> {
+        Box::pin(futures_lite::future::ready(Ok(Some(msg))))
+    }
 }
 
 /// A boxed connector
@@ -258,6 +296,95 @@ impl<In: RpcMessage, Out: RpcMessage> super::Connector for BoxedConnector<In, Ou
     async fn open(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
         self.0.open_boxed().await
     }
+
+    async fn open_with_priority(
+        &self,
+        priority: i32,
+    ) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        self.0.open_with_priority_boxed(priority).await
+    }
+
+    async fn open_uni(&self) -> anyhow::Result<Self::SendSink> {
+        self.0.open_uni_boxed().await
+    }
+
+    async fn send_datagram(&self, msg: Out) -> anyhow::Result<Option<Out>> {
+        self.0.send_datagram_boxed(msg).await
+    }
+}
+
+/// A connector whose transport can be replaced at runtime
+///
+/// All clones share the same transport, so e.g. every handle to an
+/// [RpcClient](crate::RpcClient) moves to the new transport when it is replaced
+/// with [Self::set_transport]. Channels that are already open keep using the
+/// transport they were opened on.
+#[derive(Debug)]
+pub struct DynamicConnector<In, Out>(Arc<RwLock<BoxedConnector<In, Out>>>);
+
+impl<In: RpcMessage, Out: RpcMessage> DynamicConnector<In, Out> {
+    /// Create a dynamic connector, initially using the given transport
+    pub fn new(transport: BoxedConnector<In, Out>) -> Self {
+        Self(Arc::new(RwLock::new(transport)))
+    }
+
+    /// Replace the transport that new channels are opened on
+    ///
+    /// Returns the previous transport.
+    pub fn set_transport(&self, transport: BoxedConnector<In, Out>) -> BoxedConnector<In, Out> {
+        std::mem::replace(&mut self.0.write().unwrap(), transport)
+    }
+
+    /// The transport that new channels are opened on
+    pub fn transport(&self) -> BoxedConnector<In, Out> {
+        self.0.read().unwrap().clone()
+    }
+}
+
+impl<In, Out> Clone for DynamicConnector<In, Out> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage> StreamTypes for DynamicConnector<In, Out> {
+    type In = In;
+    type Out = Out;
+    type RecvStream = RecvStream<In>;
+    type SendSink = SendSink<Out>;
+}
+
+impl<In: RpcMessage, Out: RpcMessage> ConnectionErrors for DynamicConnector<In, Out> {
+    type SendError = anyhow::Error;
+    type RecvError = anyhow::Error;
+    type OpenError = anyhow::Error;
+    type AcceptError = anyhow::Error;
+}
+
+impl<In: RpcMessage, Out: RpcMessage> super::Connector for DynamicConnector<In, Out> {
+    async fn open(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        // don't hold the lock while opening
+        let transport = self.transport();
+        transport.0.open_boxed().await
+    }
+
+    async fn open_with_priority(
+        &self,
+        priority: i32,
+    ) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        let transport = self.transport();
+        transport.0.open_with_priority_boxed(priority).await
+    }
+
+    async fn open_uni(&self) -> anyhow::Result<Self::SendSink> {
+        let transport = self.transport();
+        transport.0.open_uni_boxed().await
+    }
+
+    async fn send_datagram(&self, msg: Out) -> anyhow::Result<Option<Out>> {
+        let transport = self.transport();
+        transport.0.send_datagram_boxed(msg).await
+    }
 }
 
 /// Stream types for boxed streams
@@ -294,10 +421,24 @@ pub trait BoxableListener<In: RpcMessage, Out: RpcMessage>: Debug + Send + Sync
     fn clone_box(&self) -> Box<dyn BoxableListener<In, Out>>;
 
     /// Accept a channel from a remote client
-    fn accept_bi_boxed(&self) -> AcceptFuture<In, Out>;
+    fn accept_bi_boxed(&self) -> AcceptFuture<'_, In, Out>;
 
     /// Get the local address
     fn local_addr(&self) -> &[super::LocalAddr];
+
+    /// Accept a unidirectional channel, see [Listener::accept_uni](super::Listener::accept_uni)
+    ///
+    /// The default implementation never completes.
+    fn accept_uni_boxed(&self) -> BoxFuture<'_, anyhow::Result<RecvStream<In>>> {
+        Box::pin(futures_lite::future::pending())
+    }
+
+    /// Receive a datagram, see [Listener::recv_datagram](super::Listener::recv_datagram)
+    ///
+    /// The default implementation never completes.
+    fn recv_datagram_boxed(&self) -> BoxFuture<'_, anyhow::Result<In>> {
+        Box::pin(futures_lite::future::pending())
+    }
 }
 
 /// A boxed listener
@@ -342,26 +483,74 @@ impl<In: RpcMessage, Out: RpcMessage> super::Listener for BoxedListener<In, Out>
     fn local_addr(&self) -> &[super::LocalAddr] {
         self.0.local_addr()
     }
+
+    fn accept_uni(
+        &self,
+    ) -> impl Future<Output = Result<Self::RecvStream, Self::AcceptError>> + Send {
+        self.0.accept_uni_boxed()
+    }
+
+    fn recv_datagram(&self) -> impl Future<Output = Result<Self::In, Self::RecvError>> + Send {
+        self.0.recv_datagram_boxed()
+    }
 }
 impl<In: RpcMessage, Out: RpcMessage> BoxableConnector<In, Out> for BoxedConnector<In, Out> {
     fn clone_box(&self) -> Box<dyn BoxableConnector<In, Out>> {
         Box::new(self.clone())
     }
 
-    fn open_boxed(&self) -> OpenFuture<In, Out> {
+    fn open_boxed(&self) -> OpenFuture<'_, In, Out> {
         OpenFuture::boxed(crate::transport::Connector::open(self))
     }
+
+    fn open_with_priority_boxed(&self, priority: i32) -> OpenFuture<'_, In, Out> {
+        OpenFuture::boxed(crate::transport::Connector::open_with_priority(
+            self, priority,
+        ))
+    }
+
+    fn open_uni_boxed(&self) -> BoxFuture<'_, anyhow::Result<SendSink<Out>>> {
+        Box::pin(crate::transport::Connector::open_uni(self))
+    }
+
+    fn send_datagram_boxed(&self, msg: Out) -> BoxFuture<'_, anyhow::Result<Option<Out>>> {
+        Box::pin(crate::transport::Connector::send_datagram(self, msg))
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage> BoxableConnector<In, Out> for DynamicConnector<In, Out> {
+    fn clone_box(&self) -> Box<dyn BoxableConnector<In, Out>> {
+        Box::new(self.clone())
+    }
+
+    fn open_boxed(&self) -> OpenFuture<'_, In, Out> {
+        OpenFuture::boxed(crate::transport::Connector::open(self))
+    }
+
+    fn open_with_priority_boxed(&self, priority: i32) -> OpenFuture<'_, In, Out> {
+        OpenFuture::boxed(crate::transport::Connector::open_with_priority(
+            self, priority,
+        ))
+    }
+
+    fn open_uni_boxed(&self) -> BoxFuture<'_, anyhow::Result<SendSink<Out>>> {
+        Box::pin(crate::transport::Connector::open_uni(self))
+    }
+
+    fn send_datagram_boxed(&self, msg: Out) -> BoxFuture<'_, anyhow::Result<Option<Out>>> {
+        Box::pin(crate::transport::Connector::send_datagram(self, msg))
+    }
 }
 
 #[cfg(feature = "quinn-transport")]
-impl<In: RpcMessage, Out: RpcMessage> BoxableConnector<In, Out>
-    for super::quinn::QuinnConnector<In, Out>
+impl<In: RpcMessage, Out: RpcMessage, C: crate::codec::Codec> BoxableConnector<In, Out>
+    for super::quinn::QuinnConnector<In, Out, C>
 {
     fn clone_box(&self) -> Box<dyn BoxableConnector<In, Out>> {
         Box::new(self.clone())
     }
 
-    fn open_boxed(&self) -> OpenFuture<In, Out> {
+    fn open_boxed(&self) -> OpenFuture<'_, In, Out> {
         let f = Box::pin(async move {
             let (send, recv) = super::Connector::open(self).await?;
             // map the error types to anyhow
@@ -372,22 +561,45 @@ impl<In: RpcMessage, Out: RpcMessage> BoxableConnector<In, Out>
         });
         OpenFuture::boxed(f)
     }
+
+    fn open_with_priority_boxed(&self, priority: i32) -> OpenFuture<'_, In, Out> {
+        let f = Box::pin(async move {
+            let (send, recv) = super::Connector::open_with_priority(self, priority).await?;
+            let send = send.sink_map_err(anyhow::Error::from);
+            let recv = recv.map_err(anyhow::Error::from);
+            anyhow::Ok((SendSink::boxed(send), RecvStream::boxed(recv)))
+        });
+        OpenFuture::boxed(f)
+    }
+
+    fn open_uni_boxed(&self) -> BoxFuture<'_, anyhow::Result<SendSink<Out>>> {
+        Box::pin(async move {
+            let send = super::Connector::open_uni(self).await?;
+            Ok(SendSink::boxed(send.sink_map_err(anyhow::Error::from)))
+        })
+    }
+
+    fn send_datagram_boxed(&self, msg: Out) -> BoxFuture<'_, anyhow::Result<Option<Out>>> {
+        Box::pin(async move { Ok(super::Connector::send_datagram(self, msg).await?) })
+    }
 }
 
 #[cfg(feature = "quinn-transport")]
-impl<In: RpcMessage, Out: RpcMessage> BoxableListener<In, Out>
-    for super::quinn::QuinnListener<In, Out>
+impl<In: RpcMessage, Out: RpcMessage, C: crate::codec::Codec> BoxableListener<In, Out>
+    for super::quinn::QuinnListener<In, Out, C>
 {
     fn clone_box(&self) -> Box<dyn BoxableListener<In, Out>> {
         Box::new(self.clone())
     }
 
-    fn accept_bi_boxed(&self) -> AcceptFuture<In, Out> {
+    fn accept_bi_boxed(&self) -> AcceptFuture<'_, In, Out> {
         let f = async move {
             let (send, recv) = super::Listener::accept(self).await?;
+            let peer = recv.peer_identity().cloned();
             let send = send.sink_map_err(anyhow::Error::from);
             let recv = recv.map_err(anyhow::Error::from);
-            anyhow::Ok((SendSink::boxed(send), RecvStream::boxed(recv)))
+            let recv = RecvStream::boxed(recv).with_peer_identity(peer);
+            anyhow::Ok((SendSink::boxed(send), recv))
         };
         AcceptFuture::boxed(f)
     }
@@ -395,17 +607,30 @@ impl<In: RpcMessage, Out: RpcMessage> BoxableListener<In, Out>
     fn local_addr(&self) -> &[super::LocalAddr] {
         super::Listener::local_addr(self)
     }
+
+    fn accept_uni_boxed(&self) -> BoxFuture<'_, anyhow::Result<RecvStream<In>>> {
+        Box::pin(async move {
+            let recv = super::Listener::accept_uni(self).await?;
+            let peer = recv.peer_identity().cloned();
+            let recv = recv.map_err(anyhow::Error::from);
+            Ok(RecvStream::boxed(recv).with_peer_identity(peer))
+        })
+    }
+
+    fn recv_datagram_boxed(&self) -> BoxFuture<'_, anyhow::Result<In>> {
+        Box::pin(async move { Ok(super::Listener::recv_datagram(self).await?) })
+    }
 }
 
 #[cfg(feature = "iroh-transport")]
-impl<In: RpcMessage, Out: RpcMessage> BoxableConnector<In, Out>
-    for super::iroh::IrohConnector<In, Out>
+impl<In: RpcMessage, Out: RpcMessage, C: crate::codec::Codec> BoxableConnector<In, Out>
+    for super::iroh::IrohConnector<In, Out, C>
 {
     fn clone_box(&self) -> Box<dyn BoxableConnector<In, Out>> {
         Box::new(self.clone())
     }
 
-    fn open_boxed(&self) -> OpenFuture<In, Out> {
+    fn open_boxed(&self) -> OpenFuture<'_, In, Out> {
         let f = Box::pin(async move {
             let (send, recv) = super::Connector::open(self).await?;
             // map the error types to anyhow
@@ -416,22 +641,45 @@ impl<In: RpcMessage, Out: RpcMessage> BoxableConnector<In, Out>
         });
         OpenFuture::boxed(f)
     }
+
+    fn open_with_priority_boxed(&self, priority: i32) -> OpenFuture<'_, In, Out> {
+        let f = Box::pin(async move {
+            let (send, recv) = super::Connector::open_with_priority(self, priority).await?;
+            let send = send.sink_map_err(anyhow::Error::from);
+            let recv = recv.map_err(anyhow::Error::from);
+            anyhow::Ok((SendSink::boxed(send), RecvStream::boxed(recv)))
+        });
+        OpenFuture::boxed(f)
+    }
+
+    fn open_uni_boxed(&self) -> BoxFuture<'_, anyhow::Result<SendSink<Out>>> {
+        Box::pin(async move {
+            let send = super::Connector::open_uni(self).await?;
+            Ok(SendSink::boxed(send.sink_map_err(anyhow::Error::from)))
+        })
+    }
+
+    fn send_datagram_boxed(&self, msg: Out) -> BoxFuture<'_, anyhow::Result<Option<Out>>> {
+        Box::pin(async move { Ok(super::Connector::send_datagram(self, msg).await?) })
+    }
 }
 
 #[cfg(feature = "iroh-transport")]
-impl<In: RpcMessage, Out: RpcMessage> BoxableListener<In, Out>
-    for super::iroh::IrohListener<In, Out>
+impl<In: RpcMessage, Out: RpcMessage, C: crate::codec::Codec> BoxableListener<In, Out>
+    for super::iroh::IrohListener<In, Out, C>
 {
     fn clone_box(&self) -> Box<dyn BoxableListener<In, Out>> {
         Box::new(self.clone())
     }
 
-    fn accept_bi_boxed(&self) -> AcceptFuture<In, Out> {
+    fn accept_bi_boxed(&self) -> AcceptFuture<'_, In, Out> {
         let f = async move {
             let (send, recv) = super::Listener::accept(self).await?;
+            let peer = recv.peer_identity().cloned();
             let send = send.sink_map_err(anyhow::Error::from);
             let recv = recv.map_err(anyhow::Error::from);
-            anyhow::Ok((SendSink::boxed(send), RecvStream::boxed(recv)))
+            let recv = RecvStream::boxed(recv).with_peer_identity(peer);
+            anyhow::Ok((SendSink::boxed(send), recv))
         };
         AcceptFuture::boxed(f)
     }
@@ -439,6 +687,251 @@ impl<In: RpcMessage, Out: RpcMessage> BoxableListener<In, Out>
     fn local_addr(&self) -> &[super::LocalAddr] {
         super::Listener::local_addr(self)
     }
+
+    fn accept_uni_boxed(&self) -> BoxFuture<'_, anyhow::Result<RecvStream<In>>> {
+        Box::pin(async move {
+            let recv = super::Listener::accept_uni(self).await?;
+            let peer = recv.peer_identity().cloned();
+            let recv = recv.map_err(anyhow::Error::from);
+            Ok(RecvStream::boxed(recv).with_peer_identity(peer))
+        })
+    }
+
+    fn recv_datagram_boxed(&self) -> BoxFuture<'_, anyhow::Result<In>> {
+        Box::pin(async move { Ok(super::Listener::recv_datagram(self).await?) })
+    }
+}
+
+/// Open a channel on a connector and box both halves
+fn open_and_box<C: super::Connector>(connector: &C) -> OpenFuture<'_, C::In, C::Out> {
+    OpenFuture::boxed(async move {
+        let (send, recv) = connector.open().await.map_err(Into::into)?;
+        let send = send.sink_map_err(Into::into);
+        let recv = recv.map_err(Into::into);
+        anyhow::Ok((SendSink::boxed(send), RecvStream::boxed(recv)))
+    })
+}
+
+/// Open a channel with a priority hint on a connector and box both halves
+fn open_with_priority_and_box<C: super::Connector>(
+    connector: &C,
+    priority: i32,
+) -> OpenFuture<'_, C::In, C::Out> {
+    OpenFuture::boxed(async move {
+        let (send, recv) = connector
+            .open_with_priority(priority)
+            .await
+            .map_err(Into::into)?;
+        let send = send.sink_map_err(Into::into);
+        let recv = recv.map_err(Into::into);
+        anyhow::Ok((SendSink::boxed(send), RecvStream::boxed(recv)))
+    })
+}
+
+fn open_uni_and_box<C: super::Connector>(
+    connector: &C,
+) -> BoxFuture<'_, anyhow::Result<SendSink<C::Out>>> {
+    Box::pin(async move {
+        let send = connector.open_uni().await.map_err(Into::into)?;
+        anyhow::Ok(SendSink::boxed(send.sink_map_err(Into::into)))
+    })
+}
+
+fn send_datagram_and_box<C: super::Connector>(
+    connector: &C,
+    msg: C::Out,
+) -> BoxFuture<'_, anyhow::Result<Option<C::Out>>> {
+    Box::pin(async move { connector.send_datagram(msg).await.map_err(Into::into) })
+}
+
+/// Implement [BoxableConnector] and [BoxableListener] for transports with
+/// generic `In` and `Out` types, boxing the sinks and streams
+macro_rules! boxable {
+    ($feature:literal, connector $ty:ty) => {
+        #[cfg(feature = $feature)]
+        impl<In: RpcMessage, Out: RpcMessage> BoxableConnector<In, Out> for $ty {
+            fn clone_box(&self) -> Box<dyn BoxableConnector<In, Out>> {
+                Box::new(self.clone())
+            }
+
+            fn open_boxed(&self) -> OpenFuture<'_, In, Out> {
+                open_and_box(self)
+            }
+
+            fn open_with_priority_boxed(&self, priority: i32) -> OpenFuture<'_, In, Out> {
+                open_with_priority_and_box(self, priority)
+            }
+
+            fn open_uni_boxed(&self) -> BoxFuture<'_, anyhow::Result<SendSink<Out>>> {
+                open_uni_and_box(self)
+            }
+
+            fn send_datagram_boxed(&self, msg: Out) -> BoxFuture<'_, anyhow::Result<Option<Out>>> {
+                send_datagram_and_box(self, msg)
+            }
+        }
+    };
+    ($feature:literal, listener $ty:ty) => {
+        #[cfg(feature = $feature)]
+        impl<In: RpcMessage, Out: RpcMessage> BoxableListener<In, Out> for $ty {
+            fn clone_box(&self) -> Box<dyn BoxableListener<In, Out>> {
+                Box::new(self.clone())
+            }
+
+            fn accept_bi_boxed(&self) -> AcceptFuture<'_, In, Out> {
+                // this needs the concrete type, since accept futures are not
+                // known to be Sync in general
+                AcceptFuture::boxed(async move {
+                    let (send, recv) = super::Listener::accept(self).await?;
+                    let send = send.sink_map_err(anyhow::Error::from);
+                    let recv = recv.map_err(anyhow::Error::from);
+                    anyhow::Ok((SendSink::boxed(send), RecvStream::boxed(recv)))
+                })
+            }
+
+            fn local_addr(&self) -> &[super::LocalAddr] {
+                super::Listener::local_addr(self)
+            }
+
+            fn accept_uni_boxed(&self) -> BoxFuture<'_, anyhow::Result<RecvStream<In>>> {
+                Box::pin(async move {
+                    let recv = super::Listener::accept_uni(self).await?;
+                    anyhow::Ok(RecvStream::boxed(recv.map_err(anyhow::Error::from)))
+                })
+            }
+
+            fn recv_datagram_boxed(&self) -> BoxFuture<'_, anyhow::Result<In>> {
+                Box::pin(async move { Ok(super::Listener::recv_datagram(self).await?) })
+            }
+        }
+    };
+}
+
+boxable!("hyper-transport", connector super::hyper::HyperConnector<In, Out>);
+boxable!("hyper-transport", listener super::hyper::HyperListener<In, Out>);
+boxable!("framed-transport", connector super::framed::FramedConnection<In, Out>);
+boxable!("framed-transport", listener super::framed::FramedConnection<In, Out>);
+boxable!("tcp-transport", connector super::tcp::TcpConnector<In, Out>);
+boxable!("tcp-transport", listener super::tcp::TcpListener<In, Out>);
+boxable!("websocket-transport", connector super::websocket::WebSocketConnector<In, Out>);
+boxable!("websocket-transport", listener super::websocket::WebSocketListener<In, Out>);
+
+impl<A, B> BoxableConnector<A::In, A::Out> for super::combined::CombinedConnector<A, B>
+where
+    A: super::Connector,
+    B: super::Connector<In = A::In, Out = A::Out>,
+{
+    fn clone_box(&self) -> Box<dyn BoxableConnector<A::In, A::Out>> {
+        Box::new(self.clone())
+    }
+
+    fn open_boxed(&self) -> OpenFuture<'_, A::In, A::Out> {
+        open_and_box(self)
+    }
+
+    fn open_with_priority_boxed(&self, priority: i32) -> OpenFuture<'_, A::In, A::Out> {
+        open_with_priority_and_box(self, priority)
+    }
+
+    fn open_uni_boxed(&self) -> BoxFuture<'_, anyhow::Result<SendSink<A::Out>>> {
+        open_uni_and_box(self)
+    }
+
+    fn send_datagram_boxed(&self, msg: A::Out) -> BoxFuture<'_, anyhow::Result<Option<A::Out>>> {
+        send_datagram_and_box(self, msg)
+    }
+}
+
+impl<C: super::Connector> BoxableConnector<C::In, C::Out>
+    for super::reconnecting::ReconnectingConnector<C>
+{
+    fn clone_box(&self) -> Box<dyn BoxableConnector<C::In, C::Out>> {
+        Box::new(self.clone())
+    }
+
+    fn open_boxed(&self) -> OpenFuture<'_, C::In, C::Out> {
+        open_and_box(self)
+    }
+
+    fn open_with_priority_boxed(&self, priority: i32) -> OpenFuture<'_, C::In, C::Out> {
+        open_with_priority_and_box(self, priority)
+    }
+
+    fn open_uni_boxed(&self) -> BoxFuture<'_, anyhow::Result<SendSink<C::Out>>> {
+        open_uni_and_box(self)
+    }
+
+    fn send_datagram_boxed(&self, msg: C::Out) -> BoxFuture<'_, anyhow::Result<Option<C::Out>>> {
+        send_datagram_and_box(self, msg)
+    }
+}
+
+impl<C: super::Connector> BoxableConnector<C::In, C::Out> for super::limit::LimitedConnector<C> {
+    fn clone_box(&self) -> Box<dyn BoxableConnector<C::In, C::Out>> {
+        Box::new(self.clone())
+    }
+
+    fn open_boxed(&self) -> OpenFuture<'_, C::In, C::Out> {
+        open_and_box(self)
+    }
+
+    fn open_with_priority_boxed(&self, priority: i32) -> OpenFuture<'_, C::In, C::Out> {
+        open_with_priority_and_box(self, priority)
+    }
+
+    fn open_uni_boxed(&self) -> BoxFuture<'_, anyhow::Result<SendSink<C::Out>>> {
+        open_uni_and_box(self)
+    }
+
+    fn send_datagram_boxed(&self, msg: C::Out) -> BoxFuture<'_, anyhow::Result<Option<C::Out>>> {
+        send_datagram_and_box(self, msg)
+    }
+}
+
+impl<C: super::Connector> BoxableConnector<C::In, C::Out> for super::circuit::CircuitBreaker<C> {
+    fn clone_box(&self) -> Box<dyn BoxableConnector<C::In, C::Out>> {
+        Box::new(self.clone())
+    }
+
+    fn open_boxed(&self) -> OpenFuture<'_, C::In, C::Out> {
+        open_and_box(self)
+    }
+
+    fn open_with_priority_boxed(&self, priority: i32) -> OpenFuture<'_, C::In, C::Out> {
+        open_with_priority_and_box(self, priority)
+    }
+
+    fn open_uni_boxed(&self) -> BoxFuture<'_, anyhow::Result<SendSink<C::Out>>> {
+        open_uni_and_box(self)
+    }
+
+    fn send_datagram_boxed(&self, msg: C::Out) -> BoxFuture<'_, anyhow::Result<Option<C::Out>>> {
+        send_datagram_and_box(self, msg)
+    }
+}
+
+impl<C: super::Connector> BoxableConnector<C::In, C::Out>
+    for super::balanced::BalancedConnection<C>
+{
+    fn clone_box(&self) -> Box<dyn BoxableConnector<C::In, C::Out>> {
+        Box::new(self.clone())
+    }
+
+    fn open_boxed(&self) -> OpenFuture<'_, C::In, C::Out> {
+        open_and_box(self)
+    }
+
+    fn open_with_priority_boxed(&self, priority: i32) -> OpenFuture<'_, C::In, C::Out> {
+        open_with_priority_and_box(self, priority)
+    }
+
+    fn open_uni_boxed(&self) -> BoxFuture<'_, anyhow::Result<SendSink<C::Out>>> {
+        open_uni_and_box(self)
+    }
+
+    fn send_datagram_boxed(&self, msg: C::Out) -> BoxFuture<'_, anyhow::Result<Option<C::Out>>> {
+        send_datagram_and_box(self, msg)
+    }
 }
 
 #[cfg(feature = "flume-transport")]
@@ -449,7 +942,7 @@ impl<In: RpcMessage, Out: RpcMessage> BoxableConnector<In, Out>
         Box::new(self.clone())
     }
 
-    fn open_boxed(&self) -> OpenFuture<In, Out> {
+    fn open_boxed(&self) -> OpenFuture<'_, In, Out> {
         OpenFuture::direct(super::Connector::open(self))
     }
 }
@@ -462,7 +955,7 @@ impl<In: RpcMessage, Out: RpcMessage> BoxableListener<In, Out>
         Box::new(self.clone())
     }
 
-    fn accept_bi_boxed(&self) -> AcceptFuture<In, Out> {
+    fn accept_bi_boxed(&self) -> AcceptFuture<'_, In, Out> {
         AcceptFuture::direct(super::Listener::accept(self))
     }
 
@@ -486,7 +979,7 @@ where
         Box::new(self.clone())
     }
 
-    fn open_boxed(&self) -> OpenFuture<In, Out> {
+    fn open_boxed(&self) -> OpenFuture<'_, In, Out> {
         let f = Box::pin(async move {
             let (send, recv) = super::Connector::open(self).await.map_err(|e| e.into())?;
             // map the error types to anyhow
@@ -497,12 +990,25 @@ where
         });
         OpenFuture::boxed(f)
     }
+
+    fn open_with_priority_boxed(&self, priority: i32) -> OpenFuture<'_, In, Out> {
+        open_with_priority_and_box(self, priority)
+    }
+
+    fn open_uni_boxed(&self) -> BoxFuture<'_, anyhow::Result<SendSink<Out>>> {
+        open_uni_and_box(self)
+    }
+
+    fn send_datagram_boxed(&self, msg: Out) -> BoxFuture<'_, anyhow::Result<Option<Out>>> {
+        send_datagram_and_box(self, msg)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::Service;
 
+    #[allow(dead_code)]
     #[derive(Debug, Clone)]
     struct FooService;
 
@@ -537,4 +1043,78 @@ mod tests {
             println!("{:?}", res);
         }
     }
+
+    #[cfg(feature = "flume-transport")]
+    #[tokio::test]
+    async fn dynamic_switch() -> anyhow::Result<()> {
+        use crate::transport::{Connector, Listener};
+
+        let (server_a, client_a) = crate::transport::flume::channel::<u64, u64>(1);
+        let (server_b, client_b) = crate::transport::flume::channel::<u64, u64>(1);
+        let client = super::DynamicConnector::new(super::BoxedConnector::new(client_a));
+        let handle = client.clone();
+
+        let _chan = client.open().await?;
+        server_a.accept().await?;
+
+        handle.set_transport(super::BoxedConnector::new(client_b));
+        let _chan = client.open().await?;
+        server_b.accept().await?;
+        Ok(())
+    }
+
+    #[cfg(feature = "flume-transport")]
+    #[tokio::test]
+    async fn forward_priority() -> anyhow::Result<()> {
+        use std::sync::{
+            atomic::{AtomicI32, Ordering},
+            Arc,
+        };
+
+        use crate::transport::{
+            circuit::CircuitBreaker, flume::FlumeConnector, limit::LimitedConnector,
+            ConnectionErrors, Connector, StreamTypes,
+        };
+
+        /// Records the priority of the last open
+        #[derive(Debug, Clone)]
+        struct Prioritized(FlumeConnector<u64, u64>, Arc<AtomicI32>);
+
+        impl ConnectionErrors for Prioritized {
+            type SendError = <FlumeConnector<u64, u64> as ConnectionErrors>::SendError;
+            type RecvError = <FlumeConnector<u64, u64> as ConnectionErrors>::RecvError;
+            type OpenError = <FlumeConnector<u64, u64> as ConnectionErrors>::OpenError;
+            type AcceptError = <FlumeConnector<u64, u64> as ConnectionErrors>::AcceptError;
+        }
+
+        impl StreamTypes for Prioritized {
+            type In = u64;
+            type Out = u64;
+            type RecvStream = <FlumeConnector<u64, u64> as StreamTypes>::RecvStream;
+            type SendSink = <FlumeConnector<u64, u64> as StreamTypes>::SendSink;
+        }
+
+        impl Connector for Prioritized {
+            async fn open(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+                self.open_with_priority(0).await
+            }
+
+            async fn open_with_priority(
+                &self,
+                priority: i32,
+            ) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+                self.1.store(priority, Ordering::Relaxed);
+                self.0.open().await
+            }
+        }
+
+        let (_server, client) = crate::transport::flume::channel::<u64, u64>(8);
+        let priority = Arc::new(AtomicI32::new(0));
+        let connector = Prioritized(client, priority.clone());
+        let connector = CircuitBreaker::new(LimitedConnector::new(connector, 4));
+        let connector = super::BoxedConnector::new(connector);
+        let _chan = connector.open_with_priority(7).await?;
+        assert_eq!(priority.load(Ordering::Relaxed), 7);
+        Ok(())
+    }
 }
@@ -0,0 +1,366 @@
+//! Transport wrapper that limits the bytes buffered across all substreams.
+//!
+//! Every message that is sent counts against a shared [ByteBudget] until the send
+//! sink is flushed, i.e. until the message has been handed off to the underlying
+//! transport. This prevents one bulk transfer to a slow reader from exhausting
+//! memory.
+//!
+//! The typed transports do not know the encoded size of a message, so the size
+//! is computed by a user provided function.
+use std::{
+    fmt,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use futures_sink::Sink;
+use pin_project::{pin_project, pinned_drop};
+
+use super::{ConnectionErrors, Connector, Listener, LocalAddr, StreamTypes};
+
+/// What to do when a send would exceed the budget
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BudgetPolicy {
+    /// Wait in `poll_ready` until the budget is no longer exhausted
+    ///
+    /// A single message can exceed the remaining budget, so the budget can be
+    /// exceeded by at most one message per substream.
+    #[default]
+    Backpressure,
+    /// Fail the send with [SendError::BudgetExceeded]
+    Reject,
+}
+
+#[derive(Debug, Default)]
+struct BudgetState {
+    used: usize,
+    waiters: Vec<Waker>,
+}
+
+/// A byte budget that is shared by all substreams of a connection
+#[derive(Debug)]
+pub struct ByteBudget {
+    capacity: usize,
+    policy: BudgetPolicy,
+    state: Mutex<BudgetState>,
+}
+
+impl ByteBudget {
+    /// Create a new budget with the given capacity in bytes
+    pub fn new(capacity: usize, policy: BudgetPolicy) -> Arc<Self> {
+        Arc::new(Self {
+            capacity,
+            policy,
+            state: Default::default(),
+        })
+    }
+
+    /// The capacity of the budget in bytes
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of bytes that are currently buffered
+    pub fn used(&self) -> usize {
+        self.state.lock().unwrap().used
+    }
+
+    fn release(&self, size: usize) {
+        if size == 0 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.used = state.used.saturating_sub(size);
+        for waker in state.waiters.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+/// Error when sending on a substream with a byte budget
+#[derive(Debug)]
+pub enum SendError<E> {
+    /// Error from the inner sink
+    Inner(E),
+    /// The message does not fit into the remaining budget
+    BudgetExceeded {
+        /// Size of the message
+        size: usize,
+        /// Remaining budget
+        available: usize,
+    },
+}
+
+impl<E: fmt::Display> fmt::Display for SendError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Inner(e) => write!(f, "{e}"),
+            Self::BudgetExceeded { size, available } => write!(
+                f,
+                "byte budget exceeded: message of {size} bytes, {available} bytes available"
+            ),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for SendError<E> {}
+
+type Sizer<T> = Arc<dyn Fn(&T) -> usize + Send + Sync + 'static>;
+
+/// A send sink that accounts the size of sent messages against a [ByteBudget]
+#[pin_project(PinnedDrop)]
+pub struct SendSink<S, T> {
+    #[pin]
+    inner: S,
+    budget: Arc<ByteBudget>,
+    sizer: Sizer<T>,
+    unflushed: usize,
+}
+
+impl<S: fmt::Debug, T> fmt::Debug for SendSink<S, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SendSink")
+            .field("inner", &self.inner)
+            .field("budget", &self.budget)
+            .field("unflushed", &self.unflushed)
+            .finish()
+    }
+}
+
+#[pinned_drop]
+impl<S, T> PinnedDrop for SendSink<S, T> {
+    fn drop(self: Pin<&mut Self>) {
+        let this = self.project();
+        this.budget.release(std::mem::take(this.unflushed));
+    }
+}
+
+impl<S: Sink<T>, T> Sink<T> for SendSink<S, T> {
+    type Error = SendError<S::Error>;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        if this.budget.policy == BudgetPolicy::Backpressure {
+            let mut state = this.budget.state.lock().unwrap();
+            if state.used >= this.budget.capacity {
+                state.waiters.push(cx.waker().clone());
+                return Poll::Pending;
+            }
+        }
+        this.inner.poll_ready(cx).map_err(SendError::Inner)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        let this = self.project();
+        let size = (this.sizer)(&item);
+        {
+            let mut state = this.budget.state.lock().unwrap();
+            let available = this.budget.capacity.saturating_sub(state.used);
+            if this.budget.policy == BudgetPolicy::Reject && size > available {
+                return Err(SendError::BudgetExceeded { size, available });
+            }
+            state.used += size;
+        }
+        *this.unflushed += size;
+        this.inner.start_send(item).map_err(SendError::Inner)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        let res = this.inner.poll_flush(cx);
+        if res.is_ready() {
+            this.budget.release(std::mem::take(this.unflushed));
+        }
+        res.map_err(SendError::Inner)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        let res = this.inner.poll_close(cx);
+        if res.is_ready() {
+            this.budget.release(std::mem::take(this.unflushed));
+        }
+        res.map_err(SendError::Inner)
+    }
+}
+
+macro_rules! budget_wrapper {
+    ($name:ident, $what:literal) => {
+        #[doc = concat!("A ", $what, " that limits the bytes buffered across all its substreams")]
+        pub struct $name<C: StreamTypes> {
+            inner: C,
+            budget: Arc<ByteBudget>,
+            sizer: Sizer<C::Out>,
+        }
+
+        impl<C: StreamTypes> $name<C> {
+            #[doc = concat!("Wrap a ", $what, " with a byte budget")]
+            ///
+            /// `sizer` computes the size of an outgoing message in bytes.
+            pub fn new(
+                inner: C,
+                budget: Arc<ByteBudget>,
+                sizer: impl Fn(&C::Out) -> usize + Send + Sync + 'static,
+            ) -> Self {
+                Self {
+                    inner,
+                    budget,
+                    sizer: Arc::new(sizer),
+                }
+            }
+
+            /// The byte budget
+            pub fn budget(&self) -> &Arc<ByteBudget> {
+                &self.budget
+            }
+        }
+
+        impl<C: StreamTypes + Clone> Clone for $name<C> {
+            fn clone(&self) -> Self {
+                Self {
+                    inner: self.inner.clone(),
+                    budget: self.budget.clone(),
+                    sizer: self.sizer.clone(),
+                }
+            }
+        }
+
+        impl<C: StreamTypes> fmt::Debug for $name<C> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.debug_struct(stringify!($name))
+                    .field("inner", &self.inner)
+                    .field("budget", &self.budget)
+                    .finish()
+            }
+        }
+
+        impl<C: StreamTypes> ConnectionErrors for $name<C> {
+            type SendError = SendError<C::SendError>;
+            type RecvError = C::RecvError;
+            type OpenError = C::OpenError;
+            type AcceptError = C::AcceptError;
+        }
+
+        impl<C: StreamTypes> StreamTypes for $name<C> {
+            type In = C::In;
+            type Out = C::Out;
+            type RecvStream = C::RecvStream;
+            type SendSink = SendSink<C::SendSink, C::Out>;
+        }
+
+        impl<C: StreamTypes> $name<C> {
+            fn wrap(&self, send: C::SendSink) -> SendSink<C::SendSink, C::Out> {
+                SendSink {
+                    inner: send,
+                    budget: self.budget.clone(),
+                    sizer: self.sizer.clone(),
+                    unflushed: 0,
+                }
+            }
+        }
+    };
+}
+
+budget_wrapper!(BudgetConnector, "connector");
+budget_wrapper!(BudgetListener, "listener");
+
+impl<C: Connector> Connector for BudgetConnector<C> {
+    async fn open(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        let (send, recv) = self.inner.open().await?;
+        Ok((self.wrap(send), recv))
+    }
+
+    async fn open_with_priority(
+        &self,
+        priority: i32,
+    ) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        let (send, recv) = self.inner.open_with_priority(priority).await?;
+        Ok((self.wrap(send), recv))
+    }
+
+    async fn open_uni(&self) -> Result<Self::SendSink, Self::OpenError> {
+        Ok(self.wrap(self.inner.open_uni().await?))
+    }
+
+    /// Send a datagram on the inner connector
+    ///
+    /// A datagram is handed off to the transport right away, so it does not count
+    /// against the budget.
+    async fn send_datagram(&self, msg: Self::Out) -> Result<Option<Self::Out>, Self::SendError> {
+        self.inner
+            .send_datagram(msg)
+            .await
+            .map_err(SendError::Inner)
+    }
+}
+
+impl<C: Listener> Listener for BudgetListener<C> {
+    async fn accept(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::AcceptError> {
+        let (send, recv) = self.inner.accept().await?;
+        Ok((self.wrap(send), recv))
+    }
+
+    fn local_addr(&self) -> &[LocalAddr] {
+        self.inner.local_addr()
+    }
+
+    async fn accept_uni(&self) -> Result<Self::RecvStream, Self::AcceptError> {
+        self.inner.accept_uni().await
+    }
+
+    async fn recv_datagram(&self) -> Result<Self::In, Self::RecvError> {
+        self.inner.recv_datagram().await
+    }
+}
+
+#[cfg(all(test, feature = "flume-transport"))]
+mod tests {
+    use std::time::Duration;
+
+    use futures_util::SinkExt;
+
+    use super::*;
+    use crate::transport::flume;
+
+    #[tokio::test]
+    async fn reject() -> anyhow::Result<()> {
+        let (_listener, connector) = flume::channel::<Vec<u8>, u8>(2);
+        let budget = ByteBudget::new(100, BudgetPolicy::Reject);
+        let connector = BudgetConnector::new(connector, budget.clone(), Vec::len);
+        let (mut a, _) = connector.open().await?;
+        let (mut b, _) = connector.open().await?;
+        a.feed(vec![0; 60]).await?;
+        assert_eq!(budget.used(), 60);
+        let res = b.feed(vec![0; 60]).await;
+        assert!(matches!(res, Err(SendError::BudgetExceeded { .. })));
+        a.flush().await?;
+        assert_eq!(budget.used(), 0);
+        b.feed(vec![0; 60]).await?;
+        drop(b);
+        assert_eq!(budget.used(), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn backpressure() -> anyhow::Result<()> {
+        let (_listener, connector) = flume::channel::<Vec<u8>, u8>(2);
+        let budget = ByteBudget::new(100, BudgetPolicy::Backpressure);
+        let connector = BudgetConnector::new(connector, budget.clone(), Vec::len);
+        let (mut a, _) = connector.open().await?;
+        let (mut b, _b) = connector.open().await?;
+        a.feed(vec![0; 120]).await?;
+        let blocked = tokio::spawn(async move {
+            b.feed(vec![0; 10]).await?;
+            anyhow::Ok(b)
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!blocked.is_finished());
+        a.flush().await?;
+        let b = blocked.await??;
+        assert_eq!(budget.used(), 10);
+        drop(b);
+        assert_eq!(budget.used(), 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,334 @@
+//! Transport that fails fast while the remote is down
+//!
+//! A [CircuitBreaker] wraps a connector and keeps track of the outcome of the
+//! last [opens](CircuitBreaker::with_window). The circuit starts out closed, and
+//! channels are opened on the inner connector. When the share of failed opens
+//! reaches the [failure rate](CircuitBreaker::with_failure_rate), the circuit
+//! opens, and opens fail right away with [OpenError::CircuitOpen] instead of each
+//! waiting for the inner connector to give up.
+//!
+//! After the [open duration](CircuitBreaker::with_open_duration), the circuit is
+//! half-open: the next open is a probe on the inner connector, while other opens
+//! still fail fast. If the probe succeeds, the circuit closes, otherwise it opens
+//! again.
+//!
+//! A call on a [RpcClient](crate::RpcClient) that fails fast returns
+//! [Error::Open](crate::pattern::rpc::Error::Open) with [OpenError::CircuitOpen].
+use std::{
+    collections::VecDeque,
+    error, fmt,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::watch;
+
+use super::{ConnectionErrors, Connector, StreamTypes};
+
+/// Default number of recent opens the failure rate is computed over
+pub const DEFAULT_WINDOW: usize = 10;
+
+/// Default share of failed opens at which the circuit opens
+pub const DEFAULT_FAILURE_RATE: f64 = 0.5;
+
+/// Default time the circuit stays open before it probes the remote
+pub const DEFAULT_OPEN_DURATION: Duration = Duration::from_secs(5);
+
+/// The state of a [CircuitBreaker]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Channels are opened on the inner connector
+    Closed,
+    /// Opens fail right away
+    Open,
+    /// A probe is opened on the inner connector, other opens fail right away
+    HalfOpen,
+}
+
+#[derive(Debug)]
+enum State {
+    /// The outcomes of the recent opens, `true` for a failure
+    Closed(VecDeque<bool>),
+    Open {
+        until: Instant,
+    },
+    HalfOpen,
+}
+
+/// A connector that fails fast while opening channels on the inner connector fails
+pub struct CircuitBreaker<C> {
+    inner: C,
+    state: Arc<Mutex<State>>,
+    watch: Arc<watch::Sender<CircuitState>>,
+    window: usize,
+    failure_rate: f64,
+    open_for: Duration,
+}
+
+impl<C: Connector> CircuitBreaker<C> {
+    /// Create a circuit breaker around a connector
+    pub fn new(inner: C) -> Self {
+        Self {
+            inner,
+            state: Arc::new(Mutex::new(State::Closed(VecDeque::new()))),
+            watch: Arc::new(watch::channel(CircuitState::Closed).0),
+            window: DEFAULT_WINDOW,
+            failure_rate: DEFAULT_FAILURE_RATE,
+            open_for: DEFAULT_OPEN_DURATION,
+        }
+    }
+
+    /// Set the number of recent opens the failure rate is computed over
+    ///
+    /// The circuit does not open before this many opens were made. The default is
+    /// [DEFAULT_WINDOW].
+    pub fn with_window(mut self, value: usize) -> Self {
+        self.window = value.max(1);
+        self
+    }
+
+    /// Set the share of failed opens, between 0 and 1, at which the circuit opens
+    ///
+    /// The default is [DEFAULT_FAILURE_RATE].
+    pub fn with_failure_rate(mut self, value: f64) -> Self {
+        self.failure_rate = value;
+        self
+    }
+
+    /// Set the time the circuit stays open before it probes the remote
+    ///
+    /// The default is [DEFAULT_OPEN_DURATION].
+    pub fn with_open_duration(mut self, value: Duration) -> Self {
+        self.open_for = value;
+        self
+    }
+
+    /// Watch the state of the circuit
+    ///
+    /// An open circuit only becomes half-open on the next open, so the state
+    /// stays [CircuitState::Open] while nothing is opened.
+    pub fn state(&self) -> watch::Receiver<CircuitState> {
+        self.watch.subscribe()
+    }
+
+    /// Get back the inner connector
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    fn set(&self, state: &mut State, new: State) {
+        let public = match new {
+            State::Closed(_) => CircuitState::Closed,
+            State::Open { .. } => CircuitState::Open,
+            State::HalfOpen => CircuitState::HalfOpen,
+        };
+        *state = new;
+        self.watch.send_if_modified(|current| {
+            let changed = *current != public;
+            *current = public;
+            changed
+        });
+    }
+
+    /// Decide whether an open may go to the inner connector, and if it is a probe
+    fn admit(&self) -> Option<bool> {
+        let mut state = self.state.lock().unwrap();
+        match &*state {
+            State::Closed(_) => Some(false),
+            State::Open { until } if Instant::now() >= *until => {
+                self.set(&mut state, State::HalfOpen);
+                Some(true)
+            }
+            State::Open { .. } | State::HalfOpen => None,
+        }
+    }
+
+    fn record(&self, probe: bool, failed: bool) {
+        let mut state = self.state.lock().unwrap();
+        let open = State::Open {
+            until: Instant::now() + self.open_for,
+        };
+        match &mut *state {
+            State::HalfOpen if probe => {
+                if failed {
+                    self.set(&mut state, open);
+                } else {
+                    self.set(&mut state, State::Closed(VecDeque::new()));
+                }
+            }
+            State::Closed(outcomes) if !probe => {
+                outcomes.push_back(failed);
+                if outcomes.len() > self.window {
+                    outcomes.pop_front();
+                }
+                let failures = outcomes.iter().filter(|failed| **failed).count();
+                if outcomes.len() == self.window
+                    && failures as f64 >= self.failure_rate * self.window as f64
+                {
+                    tracing::debug!("opening circuit after {failures} failed opens");
+                    self.set(&mut state, open);
+                }
+            }
+            // the outcome of an open that started in a state that is over
+            _ => {}
+        }
+    }
+
+    /// Run an open on the inner connector if the circuit admits it, and record the outcome
+    async fn guarded<T>(
+        &self,
+        open: impl Future<Output = Result<T, C::OpenError>>,
+    ) -> Result<T, OpenError<C>> {
+        let probe = self.admit().ok_or(OpenError::CircuitOpen)?;
+        let mut guard = ProbeGuard {
+            breaker: self,
+            probe,
+        };
+        let res = open.await;
+        self.record(probe, res.is_err());
+        guard.probe = false;
+        res.map_err(OpenError::Open)
+    }
+}
+
+/// Returns the circuit to open if a probe is cancelled, so the next open probes again
+struct ProbeGuard<'a, C> {
+    breaker: &'a CircuitBreaker<C>,
+    probe: bool,
+}
+
+impl<C> Drop for ProbeGuard<'_, C> {
+    fn drop(&mut self) {
+        if !self.probe {
+            return;
+        }
+        let mut state = self.breaker.state.lock().unwrap();
+        if let State::HalfOpen = *state {
+            *state = State::Open {
+                until: Instant::now(),
+            };
+            self.breaker.watch.send_replace(CircuitState::Open);
+        }
+    }
+}
+
+impl<C: Clone> Clone for CircuitBreaker<C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            state: self.state.clone(),
+            watch: self.watch.clone(),
+            window: self.window,
+            failure_rate: self.failure_rate,
+            open_for: self.open_for,
+        }
+    }
+}
+
+impl<C: fmt::Debug> fmt::Debug for CircuitBreaker<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CircuitBreaker")
+            .field("inner", &self.inner)
+            .field("state", &*self.watch.borrow())
+            .finish_non_exhaustive()
+    }
+}
+
+/// OpenError for circuit breakers
+#[derive(Debug)]
+pub enum OpenError<C: ConnectionErrors> {
+    /// The circuit is open, the inner connector was not used
+    CircuitOpen,
+    /// Opening failed on the inner connector
+    Open(C::OpenError),
+}
+
+impl<C: ConnectionErrors> fmt::Display for OpenError<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl<C: ConnectionErrors> error::Error for OpenError<C> {}
+
+impl<C: ConnectionErrors> ConnectionErrors for CircuitBreaker<C> {
+    type SendError = C::SendError;
+    type RecvError = C::RecvError;
+    type OpenError = OpenError<C>;
+    type AcceptError = C::AcceptError;
+}
+
+impl<C: StreamTypes> StreamTypes for CircuitBreaker<C> {
+    type In = C::In;
+    type Out = C::Out;
+    type RecvStream = C::RecvStream;
+    type SendSink = C::SendSink;
+}
+
+impl<C: Connector> Connector for CircuitBreaker<C> {
+    async fn open(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        self.guarded(self.inner.open()).await
+    }
+
+    async fn open_with_priority(
+        &self,
+        priority: i32,
+    ) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        self.guarded(self.inner.open_with_priority(priority)).await
+    }
+
+    async fn open_uni(&self) -> Result<Self::SendSink, Self::OpenError> {
+        self.guarded(self.inner.open_uni()).await
+    }
+
+    /// Send a datagram on the inner connector
+    ///
+    /// Datagrams are sent regardless of the state of the circuit, and do not count
+    /// as opens.
+    async fn send_datagram(&self, msg: Self::Out) -> Result<Option<Self::Out>, Self::SendError> {
+        self.inner.send_datagram(msg).await
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "flume-transport")]
+mod tests {
+    use super::*;
+    use crate::transport::{boxed::DynamicConnector, flume, Listener};
+
+    #[tokio::test]
+    async fn open_and_recover() {
+        let (_, dead) = flume::channel::<(), ()>(1);
+        let connector = DynamicConnector::new(dead.boxed());
+        let breaker = CircuitBreaker::new(connector.clone())
+            .with_window(4)
+            .with_failure_rate(0.5)
+            .with_open_duration(Duration::from_millis(50));
+
+        // the circuit only opens once the window is full
+        for _ in 0..3 {
+            assert!(matches!(breaker.open().await, Err(OpenError::Open(_))));
+        }
+        assert_eq!(*breaker.state().borrow(), CircuitState::Closed);
+        assert!(matches!(breaker.open().await, Err(OpenError::Open(_))));
+        assert_eq!(*breaker.state().borrow(), CircuitState::Open);
+        assert!(matches!(
+            breaker.clone().open().await,
+            Err(OpenError::CircuitOpen)
+        ));
+
+        // a failed probe opens the circuit again
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(matches!(breaker.open().await, Err(OpenError::Open(_))));
+        assert!(matches!(breaker.open().await, Err(OpenError::CircuitOpen)));
+
+        // the remote is back, and a successful probe closes the circuit
+        let (listener, alive) = flume::channel::<(), ()>(8);
+        connector.set_transport(alive.boxed());
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        breaker.open().await.unwrap();
+        listener.accept().await.unwrap();
+        assert_eq!(*breaker.state().borrow(), CircuitState::Closed);
+    }
+}
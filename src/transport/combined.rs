@@ -2,6 +2,7 @@
 use std::{
     error, fmt,
     fmt::Debug,
+    future::Future,
     pin::Pin,
     task::{Context, Poll},
 };
@@ -12,6 +13,34 @@ use pin_project::pin_project;
 
 use super::{ConnectionErrors, Connector, Listener, LocalAddr, StreamTypes};
 
+/// One of the two connections of a [CombinedConnector]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    /// The first connection
+    A,
+    /// The second connection
+    B,
+}
+
+/// How a [CombinedConnector] picks the connection to open a channel on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenPolicy {
+    /// Always use the given side if it is configured, and the other side otherwise
+    Prefer(Side),
+    /// Try `a` first, and `b` if opening on `a` fails
+    Fallback,
+    /// Open on both at the same time, and use whichever succeeds first
+    ///
+    /// The open on the slower side is cancelled.
+    Fastest,
+}
+
+impl Default for OpenPolicy {
+    fn default() -> Self {
+        Self::Prefer(Side::A)
+    }
+}
+
 /// A connection that combines two other connections
 #[derive(Debug, Clone)]
 pub struct CombinedConnector<A, B> {
@@ -19,14 +48,86 @@ pub struct CombinedConnector<A, B> {
     pub a: Option<A>,
     /// Second connection
     pub b: Option<B>,
+    policy: OpenPolicy,
 }
 
 impl<A: Connector, B: Connector<In = A::In, Out = A::Out>> CombinedConnector<A, B> {
     /// Create a combined connection from two other connections
     ///
-    /// It will always use the first connection that is not `None`.
+    /// By default it will always use the first connection that is not `None`,
+    /// see [Self::with_policy].
     pub fn new(a: Option<A>, b: Option<B>) -> Self {
-        Self { a, b }
+        Self {
+            a,
+            b,
+            policy: OpenPolicy::default(),
+        }
+    }
+
+    /// Set the policy for picking the connection to open a channel on
+    pub fn with_policy(mut self, policy: OpenPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// The policy for picking the connection to open a channel on
+    pub fn policy(&self) -> OpenPolicy {
+        self.policy
+    }
+
+    /// Open with `open_a` or `open_b`, depending on the policy
+    async fn open_by<T, FA, FB>(
+        &self,
+        open_a: impl FnOnce(A) -> FA,
+        open_b: impl FnOnce(B) -> FB,
+    ) -> Result<T, OpenError<A, B>>
+    where
+        FA: Future<Output = Result<T, A::OpenError>>,
+        FB: Future<Output = Result<T, B::OpenError>>,
+    {
+        let this = self.clone();
+        match (this.a, this.b, this.policy) {
+            (None, None, _) => Err(OpenError::NoChannel),
+            (Some(a), None, _) => open_a(a).await.map_err(OpenError::A),
+            (None, Some(b), _) => open_b(b).await.map_err(OpenError::B),
+            (Some(a), Some(_), OpenPolicy::Prefer(Side::A)) => {
+                open_a(a).await.map_err(OpenError::A)
+            }
+            (Some(_), Some(b), OpenPolicy::Prefer(Side::B)) => {
+                open_b(b).await.map_err(OpenError::B)
+            }
+            (Some(a), Some(b), OpenPolicy::Fallback) => match open_a(a).await {
+                Ok(res) => Ok(res),
+                Err(a_err) => {
+                    tracing::debug!("opening on a failed, falling back to b: {a_err}");
+                    open_b(b)
+                        .await
+                        .map_err(|b_err| OpenError::Both(a_err, b_err))
+                }
+            },
+            (Some(a), Some(b), OpenPolicy::Fastest) => {
+                let a_fut = open_a(a);
+                let b_fut = open_b(b);
+                tokio::pin!(a_fut, b_fut);
+                let mut a_err = None;
+                let mut b_err = None;
+                loop {
+                    tokio::select! {
+                        res = &mut a_fut, if a_err.is_none() => match res {
+                            Ok(res) => break Ok(res),
+                            Err(cause) => a_err = Some(cause),
+                        },
+                        res = &mut b_fut, if b_err.is_none() => match res {
+                            Ok(res) => break Ok(res),
+                            Err(cause) => b_err = Some(cause),
+                        },
+                    }
+                    if let (Some(_), Some(_)) = (&a_err, &b_err) {
+                        break Err(OpenError::Both(a_err.unwrap(), b_err.unwrap()));
+                    }
+                }
+            }
+        }
     }
 }
 
@@ -169,6 +270,8 @@ pub enum OpenError<A: ConnectionErrors, B: ConnectionErrors> {
     B(B::OpenError),
     /// None of the two channels is configured
     NoChannel,
+    /// Opening failed on both channels
+    Both(A::OpenError, B::OpenError),
 }
 
 impl<A: ConnectionErrors, B: ConnectionErrors> fmt::Display for OpenError<A, B> {
@@ -212,17 +315,63 @@ impl<A: Connector, B: Connector<In = A::In, Out = A::Out>> StreamTypes for Combi
 
 impl<A: Connector, B: Connector<In = A::In, Out = A::Out>> Connector for CombinedConnector<A, B> {
     async fn open(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
-        let this = self.clone();
-        // try a first, then b
-        if let Some(a) = this.a {
-            let (send, recv) = a.open().await.map_err(OpenError::A)?;
-            Ok((SendSink::A(send), RecvStream::A(recv)))
-        } else if let Some(b) = this.b {
-            let (send, recv) = b.open().await.map_err(OpenError::B)?;
-            Ok((SendSink::B(send), RecvStream::B(recv)))
-        } else {
-            Err(OpenError::NoChannel)
+        self.open_by(
+            |a| async move {
+                let (send, recv) = a.open().await?;
+                Ok((SendSink::A(send), RecvStream::A(recv)))
+            },
+            |b| async move {
+                let (send, recv) = b.open().await?;
+                Ok((SendSink::B(send), RecvStream::B(recv)))
+            },
+        )
+        .await
+    }
+
+    async fn open_with_priority(
+        &self,
+        priority: i32,
+    ) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        self.open_by(
+            |a| async move {
+                let (send, recv) = a.open_with_priority(priority).await?;
+                Ok((SendSink::A(send), RecvStream::A(recv)))
+            },
+            |b| async move {
+                let (send, recv) = b.open_with_priority(priority).await?;
+                Ok((SendSink::B(send), RecvStream::B(recv)))
+            },
+        )
+        .await
+    }
+
+    async fn open_uni(&self) -> Result<Self::SendSink, Self::OpenError> {
+        self.open_by(
+            |a| async move { a.open_uni().await.map(SendSink::A) },
+            |b| async move { b.open_uni().await.map(SendSink::B) },
+        )
+        .await
+    }
+
+    /// Send a datagram on the preferred side, or on the other side if the preferred
+    /// side does not support datagrams
+    ///
+    /// The preferred side is `b` for [OpenPolicy::Prefer] with [Side::B], and `a`
+    /// otherwise.
+    async fn send_datagram(&self, msg: Self::Out) -> Result<Option<Self::Out>, Self::SendError> {
+        let mut msg = Some(msg);
+        let prefer_b = self.policy == OpenPolicy::Prefer(Side::B);
+        for side in [prefer_b, !prefer_b] {
+            let Some(m) = msg.take() else {
+                break;
+            };
+            msg = match (side, &self.a, &self.b) {
+                (false, Some(a), _) => a.send_datagram(m).await.map_err(SendError::A)?,
+                (true, _, Some(b)) => b.send_datagram(m).await.map_err(SendError::B)?,
+                _ => Some(m),
+            };
         }
+        Ok(msg)
     }
 }
 
@@ -270,16 +419,74 @@ impl<A: Listener, B: Listener<In = A::In, Out = A::Out>> Listener for CombinedLi
     fn local_addr(&self) -> &[LocalAddr] {
         &self.local_addr
     }
+
+    async fn accept_uni(&self) -> Result<Self::RecvStream, Self::AcceptError> {
+        let a_fut = async {
+            match &self.a {
+                Some(a) => a
+                    .accept_uni()
+                    .await
+                    .map(RecvStream::A)
+                    .map_err(AcceptError::A),
+                None => std::future::pending().await,
+            }
+        };
+        let b_fut = async {
+            match &self.b {
+                Some(b) => b
+                    .accept_uni()
+                    .await
+                    .map(RecvStream::B)
+                    .map_err(AcceptError::B),
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            res = a_fut => res,
+            res = b_fut => res,
+        }
+    }
+
+    async fn recv_datagram(&self) -> Result<Self::In, Self::RecvError> {
+        let a_fut = async {
+            match &self.a {
+                Some(a) => a.recv_datagram().await.map_err(RecvError::A),
+                None => std::future::pending().await,
+            }
+        };
+        let b_fut = async {
+            match &self.b {
+                Some(b) => b.recv_datagram().await.map_err(RecvError::B),
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            res = a_fut => res,
+            res = b_fut => res,
+        }
+    }
 }
 
 #[cfg(test)]
 #[cfg(feature = "flume-transport")]
 mod tests {
     use crate::transport::{
-        combined::{self, OpenError},
+        combined::{self, OpenError, OpenPolicy, RecvStream, Side},
         flume, Connector,
     };
 
+    /// A connector that fails to open, and a working one
+    fn failing_and_working() -> (
+        flume::FlumeConnector<(), ()>,
+        flume::FlumeConnector<(), ()>,
+        flume::FlumeListener<(), ()>,
+    ) {
+        let (listener, failing) = flume::channel(1);
+        drop(listener);
+        let (listener, working) = flume::channel(8);
+        (failing, working, listener)
+    }
+
     #[tokio::test]
     async fn open_empty_channel() {
         let channel = combined::CombinedConnector::<
@@ -289,4 +496,37 @@ mod tests {
         let res = channel.open().await;
         assert!(matches!(res, Err(OpenError::NoChannel)));
     }
+
+    #[tokio::test]
+    async fn policies() {
+        let (failing, working, _listener) = failing_and_working();
+        let connector = combined::CombinedConnector::new(Some(failing), Some(working));
+
+        let res = connector.open().await;
+        assert!(matches!(res, Err(OpenError::A(_))));
+
+        let connector = connector.with_policy(OpenPolicy::Prefer(Side::B));
+        let (_, recv) = connector.open().await.unwrap();
+        assert!(matches!(recv, RecvStream::B(_)));
+
+        let connector = connector.with_policy(OpenPolicy::Fallback);
+        let (_, recv) = connector.open().await.unwrap();
+        assert!(matches!(recv, RecvStream::B(_)));
+
+        let connector = connector.with_policy(OpenPolicy::Fastest);
+        let (_, recv) = connector.open().await.unwrap();
+        assert!(matches!(recv, RecvStream::B(_)));
+    }
+
+    #[tokio::test]
+    async fn both_fail() {
+        let (failing, _, _) = failing_and_working();
+        for policy in [OpenPolicy::Fallback, OpenPolicy::Fastest] {
+            let connector =
+                combined::CombinedConnector::new(Some(failing.clone()), Some(failing.clone()))
+                    .with_policy(policy);
+            let res = connector.open().await;
+            assert!(matches!(res, Err(OpenError::Both(_, _))));
+        }
+    }
 }
@@ -0,0 +1,655 @@
+//! Transport wrapper that compresses large messages.
+//!
+//! [CompressedConnector] and [CompressedListener] compress messages whose
+//! postcard encoding is larger than a threshold. The underlying transport carries
+//! [Frame]s, so this works with any transport, and the services keep their
+//! message types. Small messages are carried as they are, so they are only
+//! encoded once, by the underlying transport.
+//!
+//! The only algorithm is raw deflate, using `miniz_oxide`. zstd is not available
+//! to this crate; [Algorithm] is non exhaustive so it can be added later.
+//!
+//! The algorithm is negotiated once per connector: before its first substream,
+//! the client opens a substream that starts with a [Frame::Negotiate] listing the
+//! algorithms it supports. The server replies with the algorithm it picked, or
+//! none, and all clones of the connector use it from then on. Every other
+//! substream starts with a [Frame::Start] with the negotiated algorithm, so the
+//! server knows it without keeping state per client.
+//!
+//! The listener reads the first frame of new substreams on separate tasks, under
+//! a [timeout](CompressionConfig::start_timeout), so a peer that does not send it
+//! does not block accepting other substreams. Datagrams are never compressed.
+//!
+//! Compression only pays off for messages that are large and repetitive, e.g.
+//! text or json like payloads of a server streaming call.
+use std::{
+    fmt,
+    future::Future,
+    marker::PhantomData,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures_lite::{Stream, StreamExt};
+use futures_util::{future, SinkExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+use super::{
+    boxed::{RecvStream, SendSink},
+    ConnectionErrors, Connector, Listener, LocalAddr, StreamTypes,
+};
+use crate::{rt, RpcMessage};
+
+/// A compression algorithm
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum Algorithm {
+    /// Raw deflate, as in RFC 1951
+    Deflate,
+}
+
+/// A message on the underlying transport of a compressed transport
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Frame<T> {
+    /// Starts the substream that negotiates the algorithm
+    ///
+    /// The client sends the algorithms it supports, in order of preference, and
+    /// the server replies with the one it picked, or none.
+    Negotiate(Vec<Algorithm>),
+    /// Starts every other substream of the client, with the negotiated algorithm
+    Start(Option<Algorithm>),
+    /// A message
+    Plain(T),
+    /// A postcard encoded message, compressed with the negotiated algorithm
+    Compressed(Vec<u8>),
+}
+
+/// Configuration of a compressed connector or listener
+#[derive(Debug, Clone)]
+pub struct CompressionConfig {
+    /// Compress messages whose encoding is at least this many bytes
+    pub threshold: usize,
+    /// Compression level, from 0 (fastest) to 10 (smallest)
+    pub level: u8,
+    /// Fail receiving messages that decompress to more than this many bytes
+    pub max_message_size: usize,
+    /// The algorithms to offer or accept, in order of preference
+    ///
+    /// Empty to never compress sent messages.
+    pub algorithms: Vec<Algorithm>,
+    /// Time a new substream has to send its first frame to a listener
+    ///
+    /// Substreams that take longer are dropped.
+    pub start_timeout: Duration,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 1024,
+            level: 6,
+            max_message_size: 16 * 1024 * 1024,
+            algorithms: vec![Algorithm::Deflate],
+            start_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl CompressionConfig {
+    fn encode<T: Serialize>(
+        &self,
+        algorithm: Option<Algorithm>,
+        msg: T,
+    ) -> anyhow::Result<Frame<T>> {
+        let Some(Algorithm::Deflate) = algorithm else {
+            return Ok(Frame::Plain(msg));
+        };
+        let size: usize =
+            postcard::serialize_with_flavor(&msg, postcard::ser_flavors::Size::default())?;
+        if size < self.threshold {
+            return Ok(Frame::Plain(msg));
+        }
+        let bytes = postcard::to_stdvec(&msg)?;
+        Ok(Frame::Compressed(miniz_oxide::deflate::compress_to_vec(
+            &bytes, self.level,
+        )))
+    }
+
+    fn decode<T: for<'a> Deserialize<'a>>(
+        &self,
+        algorithm: Option<Algorithm>,
+        frame: Frame<T>,
+    ) -> anyhow::Result<T> {
+        let bytes = match (frame, algorithm) {
+            (Frame::Plain(msg), _) => return Ok(msg),
+            (Frame::Compressed(bytes), Some(Algorithm::Deflate)) => {
+                miniz_oxide::inflate::decompress_to_vec_with_limit(&bytes, self.max_message_size)
+                    .map_err(|cause| anyhow::anyhow!("invalid compressed message: {cause:?}"))?
+            }
+            (Frame::Compressed(_), None) => anyhow::bail!("compressed message without algorithm"),
+            (Frame::Negotiate(_) | Frame::Start(_), _) => anyhow::bail!("unexpected start frame"),
+        };
+        Ok(postcard::from_bytes(&bytes)?)
+    }
+
+    /// The first of our algorithms that the client offered
+    fn pick(&self, offered: &[Algorithm]) -> Option<Algorithm> {
+        self.algorithms
+            .iter()
+            .find(|algorithm| offered.contains(algorithm))
+            .copied()
+    }
+}
+
+/// A connector that compresses large messages, if the server supports it
+pub struct CompressedConnector<In, Out, C> {
+    inner: C,
+    config: Arc<CompressionConfig>,
+    /// The algorithm the server picked, once negotiated
+    negotiated: Arc<Mutex<Option<Option<Algorithm>>>>,
+    /// Held while negotiating, so concurrent opens negotiate once
+    negotiating: Arc<tokio::sync::Mutex<()>>,
+    _p: PhantomData<(In, Out)>,
+}
+
+impl<In, Out, C> CompressedConnector<In, Out, C>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    C: Connector<In = Frame<In>, Out = Frame<Out>>,
+{
+    /// Create a new compressed connector
+    pub fn new(inner: C, config: CompressionConfig) -> Self {
+        Self {
+            inner,
+            config: Arc::new(config),
+            negotiated: Default::default(),
+            negotiating: Default::default(),
+            _p: PhantomData,
+        }
+    }
+
+    /// The algorithm the server picked, once known
+    ///
+    /// `Some(None)` if the server does not compress.
+    pub fn negotiated(&self) -> Option<Option<Algorithm>> {
+        *self.negotiated.lock().unwrap()
+    }
+
+    /// Negotiate the algorithm with the server, unless that already happened
+    ///
+    /// Otherwise, this happens when the first substream is opened.
+    pub async fn negotiate(&self) -> anyhow::Result<Option<Algorithm>> {
+        if let Some(algorithm) = self.negotiated() {
+            return Ok(algorithm);
+        }
+        let _guard = self.negotiating.lock().await;
+        // another open may have negotiated while we waited
+        if let Some(algorithm) = self.negotiated() {
+            return Ok(algorithm);
+        }
+        let (mut send, mut recv) = self.inner.open().await.map_err(Into::into)?;
+        send.send(Frame::Negotiate(self.config.algorithms.clone()))
+            .await
+            .map_err(Into::into)?;
+        let algorithm = match recv.next().await {
+            Some(Ok(Frame::Negotiate(picked))) => self.config.pick(&picked),
+            Some(Ok(_)) => anyhow::bail!("unexpected message while negotiating"),
+            Some(Err(cause)) => return Err(cause.into()),
+            None => anyhow::bail!("closed while negotiating"),
+        };
+        *self.negotiated.lock().unwrap() = Some(algorithm);
+        Ok(algorithm)
+    }
+}
+
+impl<In, Out, C: Clone> Clone for CompressedConnector<In, Out, C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            config: self.config.clone(),
+            negotiated: self.negotiated.clone(),
+            negotiating: self.negotiating.clone(),
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<In, Out, C: fmt::Debug> fmt::Debug for CompressedConnector<In, Out, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompressedConnector")
+            .field("inner", &self.inner)
+            .field("config", &self.config)
+            .field("negotiated", &self.negotiated.lock().unwrap())
+            .finish()
+    }
+}
+
+impl<In, Out, C> ConnectionErrors for CompressedConnector<In, Out, C>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    C: ConnectionErrors,
+{
+    type SendError = anyhow::Error;
+    type RecvError = anyhow::Error;
+    type OpenError = anyhow::Error;
+    type AcceptError = anyhow::Error;
+}
+
+impl<In, Out, C> StreamTypes for CompressedConnector<In, Out, C>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    C: StreamTypes<In = Frame<In>, Out = Frame<Out>>,
+{
+    type In = In;
+    type Out = Out;
+    type RecvStream = RecvStream<In>;
+    type SendSink = SendSink<Out>;
+}
+
+impl<In, Out, C> CompressedConnector<In, Out, C>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    C: Connector<In = Frame<In>, Out = Frame<Out>>,
+{
+    /// Start a send side opened on the inner connector
+    async fn start<S>(
+        &self,
+        mut send: S,
+        algorithm: Option<Algorithm>,
+    ) -> anyhow::Result<SendSink<Out>>
+    where
+        S: futures_sink::Sink<Frame<Out>> + Send + Sync + Unpin + 'static,
+        S::Error: Into<anyhow::Error>,
+    {
+        send.send(Frame::Start(algorithm))
+            .await
+            .map_err(Into::into)?;
+        let config = self.config.clone();
+        let send = send
+            .sink_map_err(Into::into)
+            .with(move |msg: Out| future::ready(config.encode(algorithm, msg)));
+        Ok(SendSink::boxed(send))
+    }
+
+    /// Set up a channel opened on the inner connector
+    async fn open_by<F>(
+        &self,
+        open: impl FnOnce() -> F,
+    ) -> anyhow::Result<(SendSink<Out>, RecvStream<In>)>
+    where
+        F: Future<Output = Result<(C::SendSink, C::RecvStream), C::OpenError>>,
+    {
+        let algorithm = self.negotiate().await?;
+        let (send, recv) = open().await.map_err(Into::into)?;
+        let send = self.start(send, algorithm).await?;
+        let config = self.config.clone();
+        let recv = recv.map(move |frame| match frame {
+            Ok(frame) => config.decode(algorithm, frame),
+            Err(cause) => Err(cause.into()),
+        });
+        Ok((send, RecvStream::boxed(recv)))
+    }
+}
+
+impl<In, Out, C> Connector for CompressedConnector<In, Out, C>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    C: Connector<In = Frame<In>, Out = Frame<Out>>,
+{
+    async fn open(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        self.open_by(|| self.inner.open()).await
+    }
+
+    async fn open_with_priority(
+        &self,
+        priority: i32,
+    ) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        self.open_by(|| self.inner.open_with_priority(priority))
+            .await
+    }
+
+    async fn open_uni(&self) -> Result<Self::SendSink, Self::OpenError> {
+        let algorithm = self.negotiate().await?;
+        let send = self.inner.open_uni().await.map_err(Into::into)?;
+        self.start(send, algorithm).await
+    }
+
+    async fn send_datagram(&self, msg: Self::Out) -> Result<Option<Self::Out>, Self::SendError> {
+        let returned = self
+            .inner
+            .send_datagram(Frame::Plain(msg))
+            .await
+            .map_err(Into::into)?;
+        Ok(returned.map(|frame| match frame {
+            Frame::Plain(msg) => msg,
+            _ => unreachable!("the inner connector returns the frame it was given"),
+        }))
+    }
+}
+
+type Channel<In, Out> = (SendSink<Out>, RecvStream<In>);
+
+/// Substreams that are ready to be accepted
+type Queue<T> = Arc<tokio::sync::Mutex<mpsc::Receiver<anyhow::Result<T>>>>;
+
+/// A listener that compresses large messages, if the client supports it
+///
+/// Substreams that negotiate the algorithm are answered, but not returned from
+/// [Listener::accept].
+pub struct CompressedListener<In: RpcMessage, Out: RpcMessage> {
+    bi: Queue<Channel<In, Out>>,
+    uni: Queue<RecvStream<In>>,
+    config: Arc<CompressionConfig>,
+    local_addr: Vec<LocalAddr>,
+    _tasks: Arc<[rt::Task<()>; 2]>,
+}
+
+impl<In, Out> CompressedListener<In, Out>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+{
+    /// Create a new compressed listener
+    ///
+    /// This spawns tasks that accept substreams on the listener. The tasks are
+    /// stopped when the listener and all its clones are dropped.
+    pub fn new<C>(inner: C, config: CompressionConfig) -> Self
+    where
+        C: Listener<In = Frame<In>, Out = Frame<Out>>,
+    {
+        let config = Arc::new(config);
+        let (bi, bi_rx) = mpsc::channel(16);
+        let (uni, uni_rx) = mpsc::channel(16);
+        let local_addr = inner.local_addr().to_vec();
+        let tasks = [
+            rt::spawn(Self::accept_loop(inner.clone(), config.clone(), bi)),
+            rt::spawn(Self::accept_uni_loop(inner, config.clone(), uni)),
+        ];
+        Self {
+            bi: Arc::new(tokio::sync::Mutex::new(bi_rx)),
+            uni: Arc::new(tokio::sync::Mutex::new(uni_rx)),
+            config,
+            local_addr,
+            _tasks: Arc::new(tasks),
+        }
+    }
+
+    /// Read the first frame of a substream
+    ///
+    /// Returns the algorithm of a regular substream. Negotiations are answered on
+    /// the substream they came on, and `None` is returned for them, as for
+    /// substreams that do not start properly.
+    async fn start<S, R, E>(
+        config: &CompressionConfig,
+        send: &mut S,
+        recv: &mut R,
+    ) -> Option<Option<Algorithm>>
+    where
+        S: futures_sink::Sink<Frame<Out>> + Unpin,
+        R: Stream<Item = Result<Frame<In>, E>> + Unpin,
+    {
+        match rt::timeout(config.start_timeout, recv.next()).await {
+            // compressed messages of an algorithm we don't support fail to decode
+            Some(Some(Ok(Frame::Start(algorithm)))) => {
+                Some(algorithm.filter(|algorithm| config.algorithms.contains(algorithm)))
+            }
+            Some(Some(Ok(Frame::Negotiate(offered)))) => {
+                let picked = Frame::Negotiate(Vec::from_iter(config.pick(&offered)));
+                rt::timeout(config.start_timeout, send.send(picked)).await;
+                None
+            }
+            Some(_) => {
+                debug!("invalid start frame");
+                None
+            }
+            None => {
+                debug!("no start frame within {:?}", config.start_timeout);
+                None
+            }
+        }
+    }
+
+    fn decoded<R, E>(
+        config: Arc<CompressionConfig>,
+        algorithm: Option<Algorithm>,
+        recv: R,
+    ) -> RecvStream<In>
+    where
+        R: Stream<Item = Result<Frame<In>, E>> + Send + Sync + Unpin + 'static,
+        E: Into<anyhow::Error>,
+    {
+        RecvStream::boxed(recv.map(move |frame| match frame {
+            Ok(frame) => config.decode(algorithm, frame),
+            Err(cause) => Err(cause.into()),
+        }))
+    }
+
+    async fn accept_loop<C>(
+        inner: C,
+        config: Arc<CompressionConfig>,
+        tx: mpsc::Sender<anyhow::Result<Channel<In, Out>>>,
+    ) where
+        C: Listener<In = Frame<In>, Out = Frame<Out>>,
+    {
+        loop {
+            let (mut send, mut recv) = match inner.accept().await {
+                Ok(channel) => channel,
+                Err(cause) => {
+                    warn!("compressed accept failed: {cause}");
+                    tx.send(Err(cause.into())).await.ok();
+                    break;
+                }
+            };
+            let config = config.clone();
+            let tx = tx.clone();
+            // read the start frame on a separate task, to not block accepting other substreams
+            rt::spawn_detached(async move {
+                let Some(algorithm) = Self::start(&config, &mut send, &mut recv).await else {
+                    return;
+                };
+                let recv = Self::decoded(config.clone(), algorithm, recv);
+                let send = send
+                    .sink_map_err(Into::into)
+                    .with(move |msg: Out| future::ready(config.encode(algorithm, msg)));
+                tx.send(Ok((SendSink::boxed(send), recv))).await.ok();
+            });
+        }
+    }
+
+    async fn accept_uni_loop<C>(
+        inner: C,
+        config: Arc<CompressionConfig>,
+        tx: mpsc::Sender<anyhow::Result<RecvStream<In>>>,
+    ) where
+        C: Listener<In = Frame<In>, Out = Frame<Out>>,
+    {
+        loop {
+            let mut recv = match inner.accept_uni().await {
+                Ok(recv) => recv,
+                Err(cause) => {
+                    warn!("compressed accept failed: {cause}");
+                    tx.send(Err(cause.into())).await.ok();
+                    break;
+                }
+            };
+            let config = config.clone();
+            let tx = tx.clone();
+            rt::spawn_detached(async move {
+                // there is no reply on a unidirectional substream
+                let mut send = futures_util::sink::drain::<Frame<Out>>();
+                let Some(algorithm) = Self::start(&config, &mut send, &mut recv).await else {
+                    return;
+                };
+                tx.send(Ok(Self::decoded(config, algorithm, recv)))
+                    .await
+                    .ok();
+            });
+        }
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage> Clone for CompressedListener<In, Out> {
+    fn clone(&self) -> Self {
+        Self {
+            bi: self.bi.clone(),
+            uni: self.uni.clone(),
+            config: self.config.clone(),
+            local_addr: self.local_addr.clone(),
+            _tasks: self._tasks.clone(),
+        }
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage> fmt::Debug for CompressedListener<In, Out> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompressedListener")
+            .field("config", &self.config)
+            .field("local_addr", &self.local_addr)
+            .finish()
+    }
+}
+
+impl<In, Out> ConnectionErrors for CompressedListener<In, Out>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+{
+    type SendError = anyhow::Error;
+    type RecvError = anyhow::Error;
+    type OpenError = anyhow::Error;
+    type AcceptError = anyhow::Error;
+}
+
+impl<In, Out> StreamTypes for CompressedListener<In, Out>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+{
+    type In = In;
+    type Out = Out;
+    type RecvStream = RecvStream<In>;
+    type SendSink = SendSink<Out>;
+}
+
+impl<In, Out> Listener for CompressedListener<In, Out>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+{
+    async fn accept(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::AcceptError> {
+        self.bi
+            .lock()
+            .await
+            .recv()
+            .await
+            .unwrap_or_else(|| Err(anyhow::anyhow!("compressed listener closed")))
+    }
+
+    fn local_addr(&self) -> &[LocalAddr] {
+        &self.local_addr
+    }
+
+    async fn accept_uni(&self) -> Result<Self::RecvStream, Self::AcceptError> {
+        self.uni
+            .lock()
+            .await
+            .recv()
+            .await
+            .unwrap_or_else(|| Err(anyhow::anyhow!("compressed listener closed")))
+    }
+}
+
+#[cfg(all(test, feature = "flume-transport"))]
+mod tests {
+    use super::*;
+    use crate::transport::flume;
+
+    #[tokio::test]
+    async fn compresses_large_messages() -> anyhow::Result<()> {
+        let (listener, connector) = flume::channel::<Frame<String>, Frame<String>>(8);
+        let (tap_listener, tap_connector) = flume::channel::<Frame<String>, Frame<String>>(8);
+        let listener = CompressedListener::<String, String>::new(listener, Default::default());
+        let connector =
+            CompressedConnector::<String, String, _>::new(connector, Default::default());
+        let large = "abc".repeat(1000);
+
+        let (mut send, mut recv) = connector.open().await?;
+        let (mut server_send, mut server_recv) = listener.accept().await?;
+        assert_eq!(connector.negotiated(), Some(Some(Algorithm::Deflate)));
+        send.send("small".to_string()).await?;
+        assert_eq!(
+            server_recv.next().await.transpose()?.as_deref(),
+            Some("small")
+        );
+        server_send.send(large.clone()).await?;
+        assert_eq!(recv.next().await.transpose()?, Some(large.clone()));
+
+        // check what goes over the wire
+        let raw = CompressedConnector::<String, String, _>::new(tap_connector, Default::default());
+        *raw.negotiated.lock().unwrap() = Some(Some(Algorithm::Deflate));
+        let (mut send, _recv) = raw.open().await?;
+        let (_, mut wire) = tap_listener.accept().await?;
+        send.send("small".to_string()).await?;
+        send.send(large).await?;
+        assert!(matches!(
+            wire.next().await.transpose()?,
+            Some(Frame::Start(Some(Algorithm::Deflate)))
+        ));
+        // small messages are not encoded by the wrapper
+        assert!(
+            matches!(wire.next().await.transpose()?, Some(Frame::Plain(msg)) if msg == "small")
+        );
+        match wire.next().await.transpose()? {
+            Some(Frame::Compressed(bytes)) => assert!(bytes.len() < 100),
+            frame => panic!("unexpected frame {frame:?}"),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn server_without_compression() -> anyhow::Result<()> {
+        let (listener, connector) = flume::channel::<Frame<String>, Frame<String>>(8);
+        let config = CompressionConfig {
+            algorithms: vec![],
+            ..Default::default()
+        };
+        let listener = CompressedListener::<String, String>::new(listener, config);
+        let connector =
+            CompressedConnector::<String, String, _>::new(connector, Default::default());
+        let large = "abc".repeat(1000);
+        let (mut send, mut recv) = connector.open().await?;
+        let (mut server_send, mut server_recv) = listener.accept().await?;
+        server_send.send(large.clone()).await?;
+        assert_eq!(recv.next().await.transpose()?, Some(large.clone()));
+        assert_eq!(connector.negotiated(), Some(None));
+        send.send(large.clone()).await?;
+        assert_eq!(server_recv.next().await.transpose()?, Some(large));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn silent_peer() -> anyhow::Result<()> {
+        let (listener, connector) = flume::channel::<Frame<String>, Frame<String>>(8);
+        let config = CompressionConfig {
+            start_timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let listener = CompressedListener::<String, String>::new(listener, config);
+        // a substream that never sends its start frame does not block other substreams
+        let _silent = connector.open().await?;
+        let connector =
+            CompressedConnector::<String, String, _>::new(connector, Default::default());
+        let (mut send, _recv) = connector.open().await?;
+        send.send("hello".to_string()).await?;
+        let (_, mut recv) = listener.accept().await?;
+        assert_eq!(recv.next().await.transpose()?.as_deref(), Some("hello"));
+        Ok(())
+    }
+}
@@ -0,0 +1,456 @@
+//! Connector wrapper that injects failures
+//!
+//! [FaultyConnector] wraps any connector and makes it misbehave like a bad
+//! network: it fails opening substreams, kills substreams while they are in use,
+//! delays sends, and fails sending or receiving single items. Messages are never
+//! corrupted, a fault always surfaces as an [Error::Injected].
+//!
+//! This is for testing the retry logic of applications. Faults are chosen with a
+//! pseudo random generator with a fixed [seed](Faults::seed), so a test that
+//! opens and uses substreams in the same order sees the same faults every time.
+use std::{
+    error, fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use futures_lite::Stream;
+use futures_sink::Sink;
+use pin_project::pin_project;
+
+use super::{ConnectionErrors, Connector, StreamTypes};
+
+/// Which faults to inject, and how often
+///
+/// Probabilities are between 0.0 (never) and 1.0 (always). The default injects
+/// no faults at all.
+#[derive(Debug, Clone, Default)]
+pub struct Faults {
+    /// Probability that opening a substream fails
+    pub drop_open: f64,
+    /// Probability, per item sent or received, that the substream is killed
+    ///
+    /// A killed substream fails all further sends and ends after returning an
+    /// error from the receive stream.
+    pub kill_stream: f64,
+    /// Probability, per item sent or received, that just this item fails
+    pub error: f64,
+    /// Delay before every send
+    pub send_delay: Option<Duration>,
+    /// Seed of the pseudo random generator
+    pub seed: u64,
+}
+
+/// The kind of an injected fault
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Opening the substream was dropped
+    DroppedOpen,
+    /// The substream was killed
+    KilledStream,
+    /// A single send or receive failed
+    Random,
+}
+
+/// Error of a [FaultyConnector]
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The wrapped connector failed
+    Transport(E),
+    /// The failure was injected
+    Injected(Fault),
+}
+
+impl<E: fmt::Debug> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl<E: fmt::Debug> error::Error for Error<E> {}
+
+#[derive(Debug)]
+struct Shared {
+    faults: Mutex<Faults>,
+    rng: AtomicU64,
+}
+
+impl Shared {
+    fn faults(&self) -> Faults {
+        self.faults.lock().unwrap().clone()
+    }
+
+    /// Returns true with probability `p`
+    fn chance(&self, p: f64) -> bool {
+        if p <= 0.0 {
+            return false;
+        }
+        // splitmix64
+        let mut z = self
+            .rng
+            .fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed)
+            .wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        ((z >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+
+    /// The fault to inject for the next item, if any
+    fn item_fault(&self, killed: &AtomicBool) -> Option<Fault> {
+        if killed.load(Ordering::Relaxed) {
+            return Some(Fault::KilledStream);
+        }
+        let faults = self.faults();
+        if self.chance(faults.kill_stream) {
+            killed.store(true, Ordering::Relaxed);
+            Some(Fault::KilledStream)
+        } else if self.chance(faults.error) {
+            Some(Fault::Random)
+        } else {
+            None
+        }
+    }
+}
+
+/// A connector that injects failures into the substreams of another connector
+///
+/// Clones share the faults and the random generator.
+#[derive(Debug, Clone)]
+pub struct FaultyConnector<C> {
+    inner: C,
+    shared: Arc<Shared>,
+}
+
+impl<C> FaultyConnector<C> {
+    /// Wrap a connector
+    pub fn new(inner: C, faults: Faults) -> Self {
+        Self {
+            inner,
+            shared: Arc::new(Shared {
+                rng: AtomicU64::new(faults.seed),
+                faults: Mutex::new(faults),
+            }),
+        }
+    }
+
+    /// Change the faults, also for substreams that are already open
+    ///
+    /// This does not reset the random generator.
+    pub fn set_faults(&self, faults: Faults) {
+        *self.shared.faults.lock().unwrap() = faults;
+    }
+
+    /// The faults that are currently injected
+    pub fn faults(&self) -> Faults {
+        self.shared.faults()
+    }
+
+    /// The wrapped connector
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+}
+
+/// A send sink that delays or fails sends
+#[pin_project]
+pub struct SendSink<S> {
+    #[pin]
+    inner: S,
+    shared: Arc<Shared>,
+    killed: Arc<AtomicBool>,
+    delay: Option<Pin<Box<dyn Future<Output = ()> + Send + Sync>>>,
+    delayed: bool,
+}
+
+impl<S: fmt::Debug> fmt::Debug for SendSink<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SendSink")
+            .field("inner", &self.inner)
+            .field("killed", &self.killed)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S: Sink<T>, T> Sink<T> for SendSink<S> {
+    type Error = Error<S::Error>;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        if this.killed.load(Ordering::Relaxed) {
+            return Poll::Ready(Err(Error::Injected(Fault::KilledStream)));
+        }
+        if !*this.delayed {
+            if let Some(duration) = this.shared.faults().send_delay {
+                let delay = this
+                    .delay
+                    .get_or_insert_with(|| Box::pin(crate::rt::sleep(duration)));
+                ready!(delay.as_mut().poll(cx));
+                *this.delay = None;
+            }
+            *this.delayed = true;
+        }
+        this.inner.poll_ready(cx).map_err(Error::Transport)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        let this = self.project();
+        *this.delayed = false;
+        if let Some(fault) = this.shared.item_fault(this.killed) {
+            return Err(Error::Injected(fault));
+        }
+        this.inner.start_send(item).map_err(Error::Transport)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project()
+            .inner
+            .poll_flush(cx)
+            .map_err(Error::Transport)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project()
+            .inner
+            .poll_close(cx)
+            .map_err(Error::Transport)
+    }
+}
+
+/// A receive stream that fails items, or ends early
+#[pin_project]
+#[derive(Debug)]
+pub struct RecvStream<S> {
+    #[pin]
+    inner: S,
+    shared: Arc<Shared>,
+    killed: Arc<AtomicBool>,
+    done: bool,
+}
+
+impl<S: Stream<Item = Result<T, E>>, T, E> Stream for RecvStream<S> {
+    type Item = Result<T, Error<E>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        if *this.done {
+            return Poll::Ready(None);
+        }
+        if this.killed.load(Ordering::Relaxed) {
+            *this.done = true;
+            return Poll::Ready(Some(Err(Error::Injected(Fault::KilledStream))));
+        }
+        let Some(item) = ready!(this.inner.poll_next(cx)) else {
+            return Poll::Ready(None);
+        };
+        Poll::Ready(Some(match this.shared.item_fault(this.killed) {
+            Some(fault) => {
+                *this.done = fault == Fault::KilledStream;
+                Err(Error::Injected(fault))
+            }
+            None => item.map_err(Error::Transport),
+        }))
+    }
+}
+
+impl<C: ConnectionErrors> ConnectionErrors for FaultyConnector<C> {
+    type SendError = Error<C::SendError>;
+    type RecvError = Error<C::RecvError>;
+    type OpenError = Error<C::OpenError>;
+    type AcceptError = C::AcceptError;
+}
+
+impl<C: StreamTypes> StreamTypes for FaultyConnector<C> {
+    type In = C::In;
+    type Out = C::Out;
+    type RecvStream = RecvStream<C::RecvStream>;
+    type SendSink = SendSink<C::SendSink>;
+}
+
+impl<C: Connector> FaultyConnector<C> {
+    /// Apply the open faults to an open on the inner connector
+    async fn open_by(
+        &self,
+        open: impl Future<Output = Result<(C::SendSink, C::RecvStream), C::OpenError>>,
+    ) -> Result<(SendSink<C::SendSink>, RecvStream<C::RecvStream>), Error<C::OpenError>> {
+        if self.shared.chance(self.shared.faults().drop_open) {
+            return Err(Error::Injected(Fault::DroppedOpen));
+        }
+        let (send, recv) = open.await.map_err(Error::Transport)?;
+        let killed = Arc::new(AtomicBool::new(false));
+        let send = SendSink {
+            inner: send,
+            shared: self.shared.clone(),
+            killed: killed.clone(),
+            delay: None,
+            delayed: false,
+        };
+        let recv = RecvStream {
+            inner: recv,
+            shared: self.shared.clone(),
+            killed,
+            done: false,
+        };
+        Ok((send, recv))
+    }
+}
+
+impl<C: Connector> Connector for FaultyConnector<C> {
+    async fn open(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        self.open_by(self.inner.open()).await
+    }
+
+    async fn open_with_priority(
+        &self,
+        priority: i32,
+    ) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        self.open_by(self.inner.open_with_priority(priority)).await
+    }
+
+    async fn open_uni(&self) -> Result<Self::SendSink, Self::OpenError> {
+        if self.shared.chance(self.shared.faults().drop_open) {
+            return Err(Error::Injected(Fault::DroppedOpen));
+        }
+        let send = self.inner.open_uni().await.map_err(Error::Transport)?;
+        Ok(SendSink {
+            inner: send,
+            shared: self.shared.clone(),
+            killed: Default::default(),
+            delay: None,
+            delayed: false,
+        })
+    }
+
+    /// Send a datagram on the inner connector
+    ///
+    /// The send delay applies, and the send fails with the `error` probability.
+    async fn send_datagram(&self, msg: Self::Out) -> Result<Option<Self::Out>, Self::SendError> {
+        let faults = self.shared.faults();
+        if let Some(duration) = faults.send_delay {
+            crate::rt::sleep(duration).await;
+        }
+        if self.shared.chance(faults.error) {
+            return Err(Error::Injected(Fault::Random));
+        }
+        self.inner
+            .send_datagram(msg)
+            .await
+            .map_err(Error::Transport)
+    }
+}
+
+#[cfg(all(test, feature = "flume-transport", feature = "rt-tokio"))]
+mod tests {
+    use futures_lite::StreamExt;
+    use futures_util::SinkExt;
+
+    use super::*;
+    use crate::transport::{flume, Listener};
+
+    #[tokio::test]
+    async fn no_faults() -> anyhow::Result<()> {
+        let (listener, connector) = flume::channel::<u64, u64>(8);
+        let connector = FaultyConnector::new(connector, Faults::default());
+        let (mut send, mut recv) = connector.open().await?;
+        let (mut server_send, mut server_recv) = listener.accept().await?;
+        for i in 0..100 {
+            send.send(i).await?;
+            assert_eq!(server_recv.next().await.transpose()?, Some(i));
+            server_send.send(i).await?;
+            assert_eq!(recv.next().await.transpose()?, Some(i));
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn drop_open() {
+        let (_listener, connector) = flume::channel::<u64, u64>(8);
+        let faults = Faults {
+            drop_open: 1.0,
+            ..Default::default()
+        };
+        let connector = FaultyConnector::new(connector, faults);
+        let res = connector.open().await;
+        assert!(matches!(res, Err(Error::Injected(Fault::DroppedOpen))));
+
+        connector.set_faults(Faults::default());
+        connector.open().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn kill_stream() -> anyhow::Result<()> {
+        let (listener, connector) = flume::channel::<u64, u64>(8);
+        let connector = FaultyConnector::new(connector, Faults::default());
+        let (mut send, mut recv) = connector.open().await?;
+        let (mut server_send, _server_recv) = listener.accept().await?;
+        send.send(1).await?;
+
+        connector.set_faults(Faults {
+            kill_stream: 1.0,
+            ..Default::default()
+        });
+        server_send.send(1).await?;
+        server_send.send(2).await?;
+        assert!(matches!(
+            recv.next().await,
+            Some(Err(Error::Injected(Fault::KilledStream)))
+        ));
+        assert!(recv.next().await.is_none());
+
+        // the substream stays dead, also without faults
+        connector.set_faults(Faults::default());
+        assert!(matches!(
+            send.send(2).await,
+            Err(Error::Injected(Fault::KilledStream))
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn random_errors_are_deterministic() -> anyhow::Result<()> {
+        async fn pattern(seed: u64) -> anyhow::Result<Vec<bool>> {
+            let (listener, connector) = flume::channel::<u64, u64>(8);
+            let faults = Faults {
+                error: 0.5,
+                seed,
+                ..Default::default()
+            };
+            let connector = FaultyConnector::new(connector, faults);
+            let (mut send, _recv) = connector.open().await?;
+            let _server = listener.accept().await?;
+            let mut failed = Vec::new();
+            for i in 0..64 {
+                failed.push(send.send(i).await.is_err());
+            }
+            Ok(failed)
+        }
+        let failed = pattern(42).await?;
+        assert!(failed.iter().any(|x| *x) && failed.iter().any(|x| !*x));
+        assert_eq!(failed, pattern(42).await?);
+        assert_ne!(failed, pattern(43).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send_delay() -> anyhow::Result<()> {
+        let (listener, connector) = flume::channel::<u64, u64>(8);
+        let faults = Faults {
+            send_delay: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let connector = FaultyConnector::new(connector, faults);
+        let (mut send, _recv) = connector.open().await?;
+        let _server = listener.accept().await?;
+        let t0 = std::time::Instant::now();
+        send.send(1).await?;
+        send.send(2).await?;
+        assert!(t0.elapsed() >= Duration::from_millis(100));
+        Ok(())
+    }
+}
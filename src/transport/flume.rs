@@ -1,5 +1,9 @@
 //! Memory transport implementation using [flume]
 //!
+//! Messages are moved through the channels as they are, so they are never
+//! serialized. The serde bounds of [RpcMessage] only exist so the same service
+//! can also be used with the network transports.
+//!
 //! [flume]: https://docs.rs/flume/
 use core::fmt;
 use std::{error, fmt::Display, marker::PhantomData, pin::Pin, result, task::Poll};
@@ -0,0 +1,334 @@
+//! Transport over any byte stream
+//!
+//! [FramedConnection] turns anything that implements [AsyncRead] and
+//! [AsyncWrite], like a duplex stream, a serial port, an ssh channel or a vsock,
+//! into a transport. Both sides of the byte stream can open and accept
+//! substreams, so a [FramedConnection] is both a [Connector] and a [Listener].
+//!
+//! The substreams are multiplexed using a [Mux], see the [mux](super::mux) module
+//! for the frame format and flow control. Items are encoded using postcard, like
+//! in the quinn transport, and sent as one data frame each.
+use std::{error, fmt, io, marker::PhantomData, pin::Pin, result, sync::Arc, task::Poll};
+
+use bytes::Bytes;
+use futures_lite::Stream;
+use futures_sink::Sink;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+pub use super::mux::Role;
+use super::mux::{Mux, MuxConfig, MuxRecvStream, MuxSendSink};
+use crate::{
+    transport::{ConnectionErrors, Connector, Listener, LocalAddr, StreamTypes},
+    RpcMessage,
+};
+
+const MAX_FRAME_LENGTH: usize = 1024 * 1024 * 16;
+
+/// A transport over a single byte stream
+pub struct FramedConnection<In: RpcMessage, Out: RpcMessage> {
+    mux: Arc<Mux>,
+    _p: PhantomData<(In, Out)>,
+}
+
+impl<In: RpcMessage, Out: RpcMessage> FramedConnection<In, Out> {
+    /// Multiplex substreams over a byte stream
+    ///
+    /// The two sides of the byte stream must use different roles. The byte stream
+    /// is closed once all clones of the connection and all its substreams are
+    /// dropped.
+    pub fn new<T>(io: T, role: Role) -> Self
+    where
+        T: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        Self::with_config(io, role, MuxConfig::default())
+    }
+
+    /// Multiplex substreams over a byte stream, with a custom configuration
+    ///
+    /// Items larger than the maximum payload size of the configuration can not
+    /// be received.
+    pub fn with_config<T>(io: T, role: Role, config: MuxConfig) -> Self
+    where
+        T: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        Self {
+            mux: Arc::new(Mux::new(io, role, config)),
+            _p: PhantomData,
+        }
+    }
+
+    /// Whether the byte stream is closed, so no new substreams can be opened
+    pub fn is_closed(&self) -> bool {
+        self.mux.is_closed()
+    }
+}
+
+/// Create the two sides of a connection over an in memory byte stream
+///
+/// `max_buf_size` is the maximum number of bytes buffered in each direction.
+/// This is mostly useful for testing.
+pub fn duplex<In: RpcMessage, Out: RpcMessage>(
+    max_buf_size: usize,
+) -> (FramedConnection<In, Out>, FramedConnection<Out, In>) {
+    let (a, b) = tokio::io::duplex(max_buf_size);
+    (
+        FramedConnection::new(a, Role::Dialer),
+        FramedConnection::new(b, Role::Acceptor),
+    )
+}
+
+impl<In: RpcMessage, Out: RpcMessage> Clone for FramedConnection<In, Out> {
+    fn clone(&self) -> Self {
+        Self {
+            mux: self.mux.clone(),
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage> fmt::Debug for FramedConnection<In, Out> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FramedConnection")
+            .field("closed", &self.is_closed())
+            .finish()
+    }
+}
+
+/// Receive stream for multiplexed channels
+pub struct RecvStream<In: RpcMessage> {
+    inner: MuxRecvStream,
+    _p: PhantomData<In>,
+}
+
+impl<In: RpcMessage> RecvStream<In> {
+    pub(crate) fn new(inner: MuxRecvStream) -> Self {
+        Self {
+            inner,
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<In: RpcMessage> fmt::Debug for RecvStream<In> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RecvStream").finish_non_exhaustive()
+    }
+}
+
+impl<In: RpcMessage> Stream for RecvStream<In> {
+    type Item = result::Result<In, RecvError>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.inner).poll_next(cx).map(|item| {
+            item.map(|item| {
+                let data = item.map_err(RecvError::Io)?;
+                postcard::from_bytes(&data).map_err(RecvError::DeserializeError)
+            })
+        })
+    }
+}
+
+/// Send sink for multiplexed channels
+pub struct SendSink<Out: RpcMessage> {
+    inner: MuxSendSink,
+    _p: PhantomData<Out>,
+}
+
+impl<Out: RpcMessage> SendSink<Out> {
+    pub(crate) fn new(inner: MuxSendSink) -> Self {
+        Self {
+            inner,
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<Out: RpcMessage> fmt::Debug for SendSink<Out> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SendSink").finish_non_exhaustive()
+    }
+}
+
+impl<Out: RpcMessage> Sink<Out> for SendSink<Out> {
+    type Error = SendError;
+
+    fn poll_ready(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner)
+            .poll_ready(cx)
+            .map_err(SendError::Io)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Out) -> Result<(), Self::Error> {
+        let data = postcard::to_stdvec(&item).map_err(SendError::SerializeError)?;
+        if data.len() > MAX_FRAME_LENGTH {
+            return Err(SendError::SizeError(data.len()));
+        }
+        Pin::new(&mut self.inner)
+            .start_send(Bytes::from(data))
+            .map_err(SendError::Io)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner)
+            .poll_flush(cx)
+            .map_err(SendError::Io)
+    }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner)
+            .poll_close(cx)
+            .map_err(SendError::Io)
+    }
+}
+
+/// Send error for multiplexed channels
+#[derive(Debug)]
+pub enum SendError {
+    /// Error when postcard serializing the message
+    SerializeError(postcard::Error),
+    /// The message is too large to be sent
+    SizeError(usize),
+    /// The connection has been closed
+    Io(io::Error),
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl error::Error for SendError {}
+
+/// Receive error for multiplexed channels
+#[derive(Debug)]
+pub enum RecvError {
+    /// Error when postcard deserializing the message
+    DeserializeError(postcard::Error),
+    /// The connection was lost before the remote finished the substream
+    Io(io::Error),
+}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl error::Error for RecvError {}
+
+/// Open error for framed channels
+#[derive(Debug)]
+pub enum OpenError {
+    /// The byte stream is closed
+    Io(io::Error),
+}
+
+impl fmt::Display for OpenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl error::Error for OpenError {}
+
+/// Accept error for framed channels
+#[derive(Debug)]
+pub enum AcceptError {
+    /// The byte stream is closed
+    ConnectionClosed,
+}
+
+impl fmt::Display for AcceptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl error::Error for AcceptError {}
+
+impl<In: RpcMessage, Out: RpcMessage> ConnectionErrors for FramedConnection<In, Out> {
+    type SendError = self::SendError;
+    type RecvError = self::RecvError;
+    type OpenError = OpenError;
+    type AcceptError = AcceptError;
+}
+
+impl<In: RpcMessage, Out: RpcMessage> StreamTypes for FramedConnection<In, Out> {
+    type In = In;
+    type Out = Out;
+    type RecvStream = self::RecvStream<In>;
+    type SendSink = self::SendSink<Out>;
+}
+
+impl<In: RpcMessage, Out: RpcMessage> Connector for FramedConnection<In, Out> {
+    async fn open(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        let (send, recv) = self.mux.open().await.map_err(OpenError::Io)?;
+        Ok((SendSink::new(send), RecvStream::new(recv)))
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage> Listener for FramedConnection<In, Out> {
+    fn local_addr(&self) -> &[LocalAddr] {
+        &[LocalAddr::Mem]
+    }
+
+    async fn accept(&self) -> Result<(Self::SendSink, Self::RecvStream), AcceptError> {
+        let (send, recv) = self
+            .mux
+            .accept()
+            .await
+            .ok_or(AcceptError::ConnectionClosed)?;
+        Ok((SendSink::new(send), RecvStream::new(recv)))
+    }
+}
+
+#[cfg(all(test, feature = "flume-transport"))]
+mod tests {
+    use futures_lite::StreamExt;
+    use futures_util::SinkExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn both_sides_open_substreams() -> anyhow::Result<()> {
+        let (a, b) = duplex::<u64, u64>(1024);
+        for (opener, acceptor) in [(&a, &b), (&b, &a)] {
+            let (mut send, mut recv) = opener.open().await?;
+            let (mut server_send, mut server_recv) = acceptor.accept().await?;
+            send.send(1).await?;
+            drop(send);
+            assert_eq!(server_recv.next().await.transpose()?, Some(1));
+            assert!(server_recv.next().await.is_none());
+            server_send.send(2).await?;
+            drop(server_send);
+            assert_eq!(recv.next().await.transpose()?, Some(2));
+            assert!(recv.next().await.is_none());
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn connection_lost() -> anyhow::Result<()> {
+        let (io, remote) = tokio::io::duplex(1024);
+        let a = FramedConnection::<u64, u64>::new(io, Role::Dialer);
+        let (_send, mut recv) = a.open().await?;
+        // the byte stream is closed without the remote finishing the substream
+        drop(remote);
+        assert!(matches!(recv.next().await, Some(Err(RecvError::Io(_)))));
+        assert!(a.is_closed());
+        Ok(())
+    }
+}
@@ -0,0 +1,799 @@
+//! Transport wrapper that exchanges a typed hello message.
+//!
+//! A client starts a session with a hello message, such as version info,
+//! capabilities or an auth proof. The server checks the hello once, and replies
+//! with a welcome message and the id of the new session, or rejects it. Every
+//! later substream of the client starts with the session id instead of the hello.
+//!
+//! On the client side, the hello is exchanged on a substream of its own before
+//! the first substream is opened, or when calling [HandshakeConnector::handshake].
+//! A rejection is surfaced as an open error. The welcome is available afterwards
+//! using [HandshakeConnector::welcome]. All clones of a connector share the session.
+//!
+//! On the server side, the hello of the session is available to handlers using
+//! [HandshakeRecvStream::hello] on the `recv` field of the
+//! [RpcChannel](crate::server::RpcChannel). The handshake and the session id of new
+//! substreams are read on separate tasks, under a [timeout](HandshakeListenerBuilder::hello_timeout),
+//! so a peer that does not send them does not block accepting other substreams.
+//!
+//! The listener keeps a [limited](HandshakeListenerBuilder::max_sessions) number
+//! of sessions, and forgets the oldest one when a new one is started. A substream
+//! of a forgotten session is rejected, and the client starts a new session on the
+//! next open.
+//!
+//! Unidirectional substreams also start with the session id, but there is no
+//! reply, so one with an unknown session is dropped by the server without the
+//! client noticing. Datagrams can not carry the session id, so they are never
+//! sent, and are sent on a unidirectional substream instead.
+use std::{
+    collections::{hash_map::RandomState, HashMap, VecDeque},
+    fmt,
+    future::Future,
+    hash::BuildHasher,
+    marker::PhantomData,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures_lite::{Stream, StreamExt};
+use futures_util::{future, SinkExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+use super::{
+    boxed::{RecvStream, SendSink},
+    ConnectionErrors, Connector, Listener, LocalAddr, StreamTypes,
+};
+use crate::{rt, RpcMessage};
+
+/// Default time a new substream has to send the hello or its session id
+pub const DEFAULT_HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default number of sessions a [HandshakeListener] keeps
+pub const DEFAULT_MAX_SESSIONS: usize = 1024;
+
+/// Defines the messages that are exchanged in a handshake
+pub trait Handshake: fmt::Debug + Clone + Send + Sync + 'static {
+    /// The message sent by the client to start a session
+    type Hello: RpcMessage + Clone;
+    /// The reply of the server if it accepts the hello
+    type Welcome: RpcMessage + Clone;
+}
+
+/// Identifies a session on a [HandshakeListener]
+///
+/// Session ids are random, so a client can not use the session of another
+/// client without having seen its id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SessionId([u64; 2]);
+
+impl SessionId {
+    fn random() -> Self {
+        // the keys of RandomState come from the random number generator of the
+        // OS, so its hashes can not be predicted without knowing them
+        let state = RandomState::new();
+        Self([state.hash_one(0u8), state.hash_one(1u8)])
+    }
+}
+
+/// A message on the underlying transport of a handshake transport
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Frame<T, H> {
+    /// The hello of the client, on the substream that starts a session
+    Hello(H),
+    /// The welcome of the server, with the id of the new session
+    Welcome(SessionId, H),
+    /// The session of a substream, sent by the client before any message
+    Session(SessionId),
+    /// The server rejected the hello or the session, with a reason
+    Reject(String),
+    /// A regular message
+    Msg(T),
+}
+
+/// The id of a session, and the welcome of the server
+type Session<P> = (SessionId, <P as Handshake>::Welcome);
+
+/// A connector that starts a session with a hello message
+pub struct HandshakeConnector<In, Out, C, P: Handshake> {
+    inner: C,
+    hello: P::Hello,
+    session: Arc<Mutex<Option<Session<P>>>>,
+    /// Held while a handshake is running, so concurrent opens start one session
+    handshake: Arc<tokio::sync::Mutex<()>>,
+    _p: PhantomData<(In, Out)>,
+}
+
+impl<In, Out, C, P> HandshakeConnector<In, Out, C, P>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    C: Connector<In = Frame<In, P::Welcome>, Out = Frame<Out, P::Hello>>,
+    P: Handshake,
+{
+    /// Create a new handshake connector that sends the given hello
+    pub fn new(inner: C, hello: P::Hello) -> Self {
+        Self {
+            inner,
+            hello,
+            session: Default::default(),
+            handshake: Default::default(),
+            _p: PhantomData,
+        }
+    }
+
+    /// The welcome message of the server, once the handshake is complete
+    pub fn welcome(&self) -> Option<P::Welcome> {
+        let session = self.session.lock().unwrap();
+        session.as_ref().map(|(_, welcome)| welcome.clone())
+    }
+
+    /// Exchange the hello now, unless there already is a session
+    ///
+    /// Otherwise, this happens when the first substream is opened.
+    pub async fn handshake(&self) -> anyhow::Result<P::Welcome> {
+        self.session().await.map(|(_, welcome)| welcome)
+    }
+
+    fn current(&self) -> Option<Session<P>> {
+        self.session.lock().unwrap().clone()
+    }
+
+    /// The current session, starting a new one if there is none
+    async fn session(&self) -> anyhow::Result<Session<P>> {
+        if let Some(session) = self.current() {
+            return Ok(session);
+        }
+        let _guard = self.handshake.lock().await;
+        // another open may have completed the handshake while we waited
+        if let Some(session) = self.current() {
+            return Ok(session);
+        }
+        let (mut send, mut recv) = self.inner.open().await.map_err(Into::into)?;
+        send.send(Frame::Hello(self.hello.clone()))
+            .await
+            .map_err(Into::into)?;
+        let session = match recv.next().await {
+            Some(Ok(Frame::Welcome(id, welcome))) => (id, welcome),
+            Some(Ok(Frame::Reject(reason))) => anyhow::bail!("handshake rejected: {reason}"),
+            Some(Ok(_)) => anyhow::bail!("unexpected message before welcome"),
+            Some(Err(cause)) => return Err(cause.into()),
+            None => anyhow::bail!("closed during handshake"),
+        };
+        *self.session.lock().unwrap() = Some(session.clone());
+        Ok(session)
+    }
+}
+
+impl<In, Out, C: Clone, P: Handshake> Clone for HandshakeConnector<In, Out, C, P> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            hello: self.hello.clone(),
+            session: self.session.clone(),
+            handshake: self.handshake.clone(),
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<In, Out, C: fmt::Debug, P: Handshake> fmt::Debug for HandshakeConnector<In, Out, C, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HandshakeConnector")
+            .field("inner", &self.inner)
+            .field("hello", &self.hello)
+            .field("session", &self.session.lock().unwrap())
+            .finish()
+    }
+}
+
+impl<In, Out, C, P> ConnectionErrors for HandshakeConnector<In, Out, C, P>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    C: ConnectionErrors,
+    P: Handshake,
+{
+    type SendError = anyhow::Error;
+    type RecvError = anyhow::Error;
+    type OpenError = anyhow::Error;
+    type AcceptError = anyhow::Error;
+}
+
+impl<In, Out, C, P> StreamTypes for HandshakeConnector<In, Out, C, P>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    C: StreamTypes<In = Frame<In, P::Welcome>, Out = Frame<Out, P::Hello>>,
+    P: Handshake,
+{
+    type In = In;
+    type Out = Out;
+    type RecvStream = RecvStream<In>;
+    type SendSink = SendSink<Out>;
+}
+
+impl<In, Out, C, P> HandshakeConnector<In, Out, C, P>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    C: Connector<In = Frame<In, P::Welcome>, Out = Frame<Out, P::Hello>>,
+    P: Handshake,
+{
+    /// Set up a channel opened on the inner connector
+    async fn open_by<F>(
+        &self,
+        open: impl FnOnce() -> F,
+    ) -> anyhow::Result<(SendSink<Out>, RecvStream<In>)>
+    where
+        F: Future<Output = Result<(C::SendSink, C::RecvStream), C::OpenError>>,
+    {
+        let (id, _) = self.session().await?;
+        let (mut send, recv) = open().await.map_err(Into::into)?;
+        send.send(Frame::Session(id)).await.map_err(Into::into)?;
+        let send = send
+            .sink_map_err(Into::into)
+            .with(|msg: Out| future::ready(anyhow::Ok(Frame::Msg(msg))));
+        let session = self.session.clone();
+        let recv = recv.map(move |frame| match frame {
+            Ok(Frame::Msg(msg)) => Ok(msg),
+            Ok(Frame::Reject(reason)) => {
+                // the server forgot the session, the next open starts a new one
+                let mut session = session.lock().unwrap();
+                if session.as_ref().is_some_and(|(current, _)| *current == id) {
+                    *session = None;
+                }
+                Err(anyhow::anyhow!("handshake rejected: {reason}"))
+            }
+            Ok(_) => Err(anyhow::anyhow!("unexpected handshake frame")),
+            Err(cause) => Err(cause.into()),
+        });
+        Ok((SendSink::boxed(send), RecvStream::boxed(recv)))
+    }
+}
+
+impl<In, Out, C, P> Connector for HandshakeConnector<In, Out, C, P>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    C: Connector<In = Frame<In, P::Welcome>, Out = Frame<Out, P::Hello>>,
+    P: Handshake,
+{
+    async fn open(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        self.open_by(|| self.inner.open()).await
+    }
+
+    async fn open_with_priority(
+        &self,
+        priority: i32,
+    ) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        self.open_by(|| self.inner.open_with_priority(priority))
+            .await
+    }
+
+    async fn open_uni(&self) -> Result<Self::SendSink, Self::OpenError> {
+        let (id, _) = self.session().await?;
+        let mut send = self.inner.open_uni().await.map_err(Into::into)?;
+        send.send(Frame::Session(id)).await.map_err(Into::into)?;
+        let send = send
+            .sink_map_err(Into::into)
+            .with(|msg: Out| future::ready(anyhow::Ok(Frame::Msg(msg))));
+        Ok(SendSink::boxed(send))
+    }
+
+    /// Datagrams can not carry the session id, so the message is always returned
+    async fn send_datagram(&self, msg: Self::Out) -> Result<Option<Self::Out>, Self::SendError> {
+        Ok(Some(msg))
+    }
+}
+
+type Check<P> = Arc<
+    dyn Fn(&<P as Handshake>::Hello) -> Result<<P as Handshake>::Welcome, String>
+        + Send
+        + Sync
+        + 'static,
+>;
+
+/// The sessions of a listener, oldest first
+struct Sessions<H> {
+    by_id: HashMap<SessionId, Arc<H>>,
+    order: VecDeque<SessionId>,
+    max: usize,
+}
+
+impl<H> Sessions<H> {
+    fn insert(&mut self, hello: H) -> SessionId {
+        let id = SessionId::random();
+        if self.order.len() >= self.max {
+            if let Some(oldest) = self.order.pop_front() {
+                self.by_id.remove(&oldest);
+            }
+        }
+        self.by_id.insert(id, Arc::new(hello));
+        self.order.push_back(id);
+        id
+    }
+
+    fn get(&self, id: &SessionId) -> Option<Arc<H>> {
+        self.by_id.get(id).cloned()
+    }
+}
+
+/// State shared by the tasks of a [HandshakeListener]
+struct Shared<P: Handshake> {
+    check: Check<P>,
+    sessions: Mutex<Sessions<P::Hello>>,
+    hello_timeout: Duration,
+}
+
+impl<P: Handshake> Shared<P> {
+    /// Read the first frame of a substream, and look up its session
+    ///
+    /// The hello of a new session is answered on the substream it came on. That
+    /// substream, and substreams that are not part of a known session, are
+    /// dropped.
+    async fn setup<In, Out, S, R, E>(&self, send: &mut S, recv: &mut R) -> Option<Arc<P::Hello>>
+    where
+        S: futures_sink::Sink<Frame<Out, P::Welcome>> + Unpin,
+        R: Stream<Item = Result<Frame<In, P::Hello>, E>> + Unpin,
+    {
+        let first = rt::timeout(self.hello_timeout, recv.next()).await;
+        let reply = match first {
+            Some(Some(Ok(Frame::Hello(hello)))) => match (self.check)(&hello) {
+                Ok(welcome) => {
+                    let id = self.sessions.lock().unwrap().insert(hello);
+                    Frame::Welcome(id, welcome)
+                }
+                Err(reason) => {
+                    debug!("handshake rejected: {reason}");
+                    Frame::Reject(reason)
+                }
+            },
+            Some(Some(Ok(Frame::Session(id)))) => match self.sessions.lock().unwrap().get(&id) {
+                Some(hello) => return Some(hello),
+                None => Frame::Reject("unknown session".to_string()),
+            },
+            Some(_) => {
+                debug!("invalid handshake frame");
+                return None;
+            }
+            None => {
+                debug!("no handshake within {:?}", self.hello_timeout);
+                return None;
+            }
+        };
+        rt::timeout(self.hello_timeout, send.send(reply)).await;
+        None
+    }
+}
+
+type Channel<In, Out, H> = (SendSink<Out>, HandshakeRecvStream<In, H>);
+
+/// Substreams that are ready to be accepted
+type Queue<T> = Arc<tokio::sync::Mutex<mpsc::Receiver<anyhow::Result<T>>>>;
+
+/// A listener that checks the hello message of every session
+///
+/// Substreams that start a session, and substreams of unknown sessions, are not
+/// returned from [Listener::accept].
+pub struct HandshakeListener<In: RpcMessage, Out: RpcMessage, P: Handshake> {
+    bi: Queue<Channel<In, Out, P::Hello>>,
+    uni: Queue<HandshakeRecvStream<In, P::Hello>>,
+    local_addr: Vec<LocalAddr>,
+    _tasks: Arc<[rt::Task<()>; 2]>,
+}
+
+impl<In, Out, P> HandshakeListener<In, Out, P>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    P: Handshake,
+{
+    /// Create a new handshake listener
+    ///
+    /// The `check` function is called with the hello of every session, and returns
+    /// either the welcome reply or a reason for rejecting the session.
+    ///
+    /// This spawns tasks that accept substreams on the listener. The tasks are
+    /// stopped when the listener and all its clones are dropped.
+    pub fn new<C>(
+        inner: C,
+        check: impl Fn(&P::Hello) -> Result<P::Welcome, String> + Send + Sync + 'static,
+    ) -> Self
+    where
+        C: Listener<In = Frame<In, P::Hello>, Out = Frame<Out, P::Welcome>>,
+    {
+        HandshakeListenerBuilder::new(check).build(inner)
+    }
+
+    async fn accept_loop<C>(
+        inner: C,
+        shared: Arc<Shared<P>>,
+        tx: mpsc::Sender<anyhow::Result<Channel<In, Out, P::Hello>>>,
+    ) where
+        C: Listener<In = Frame<In, P::Hello>, Out = Frame<Out, P::Welcome>>,
+    {
+        loop {
+            let (mut send, mut recv) = match inner.accept().await {
+                Ok(channel) => channel,
+                Err(cause) => {
+                    warn!("handshake accept failed: {cause}");
+                    tx.send(Err(cause.into())).await.ok();
+                    break;
+                }
+            };
+            let shared = shared.clone();
+            let tx = tx.clone();
+            // read the session on a separate task, to not block accepting other substreams
+            rt::spawn_detached(async move {
+                let Some(hello) = shared.setup(&mut send, &mut recv).await else {
+                    return;
+                };
+                let send = send
+                    .sink_map_err(Into::into)
+                    .with(|msg: Out| future::ready(anyhow::Ok(Frame::Msg(msg))));
+                let recv = HandshakeRecvStream::new(recv, hello);
+                tx.send(Ok((SendSink::boxed(send), recv))).await.ok();
+            });
+        }
+    }
+
+    async fn accept_uni_loop<C>(
+        inner: C,
+        shared: Arc<Shared<P>>,
+        tx: mpsc::Sender<anyhow::Result<HandshakeRecvStream<In, P::Hello>>>,
+    ) where
+        C: Listener<In = Frame<In, P::Hello>, Out = Frame<Out, P::Welcome>>,
+    {
+        loop {
+            let mut recv = match inner.accept_uni().await {
+                Ok(recv) => recv,
+                Err(cause) => {
+                    warn!("handshake accept failed: {cause}");
+                    tx.send(Err(cause.into())).await.ok();
+                    break;
+                }
+            };
+            let shared = shared.clone();
+            let tx = tx.clone();
+            rt::spawn_detached(async move {
+                // there is no reply on a unidirectional substream
+                let mut send = futures_util::sink::drain::<Frame<Out, P::Welcome>>();
+                let Some(hello) = shared.setup(&mut send, &mut recv).await else {
+                    return;
+                };
+                tx.send(Ok(HandshakeRecvStream::new(recv, hello)))
+                    .await
+                    .ok();
+            });
+        }
+    }
+}
+
+/// Builder for a [HandshakeListener]
+pub struct HandshakeListenerBuilder<P: Handshake> {
+    check: Check<P>,
+    hello_timeout: Duration,
+    max_sessions: usize,
+}
+
+impl<P: Handshake> fmt::Debug for HandshakeListenerBuilder<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HandshakeListenerBuilder")
+            .field("hello_timeout", &self.hello_timeout)
+            .field("max_sessions", &self.max_sessions)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<P: Handshake> HandshakeListenerBuilder<P> {
+    /// Create a builder for a handshake listener, see [HandshakeListener::new]
+    pub fn new(
+        check: impl Fn(&P::Hello) -> Result<P::Welcome, String> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            check: Arc::new(check),
+            hello_timeout: DEFAULT_HELLO_TIMEOUT,
+            max_sessions: DEFAULT_MAX_SESSIONS,
+        }
+    }
+
+    /// Set the time a new substream has to send the hello or its session id
+    ///
+    /// Substreams that take longer are dropped. The default is [DEFAULT_HELLO_TIMEOUT].
+    pub fn hello_timeout(mut self, value: Duration) -> Self {
+        self.hello_timeout = value;
+        self
+    }
+
+    /// Set the number of sessions to keep
+    ///
+    /// When a new session is started, the oldest one is forgotten if there are
+    /// already this many. The default is [DEFAULT_MAX_SESSIONS].
+    pub fn max_sessions(mut self, value: usize) -> Self {
+        self.max_sessions = value.max(1);
+        self
+    }
+
+    /// Build the listener, spawning the tasks that accept substreams on `inner`
+    pub fn build<In, Out, C>(self, inner: C) -> HandshakeListener<In, Out, P>
+    where
+        In: RpcMessage,
+        Out: RpcMessage,
+        C: Listener<In = Frame<In, P::Hello>, Out = Frame<Out, P::Welcome>>,
+    {
+        let shared = Arc::new(Shared::<P> {
+            check: self.check,
+            sessions: Mutex::new(Sessions {
+                by_id: HashMap::new(),
+                order: VecDeque::new(),
+                max: self.max_sessions,
+            }),
+            hello_timeout: self.hello_timeout,
+        });
+        let (bi, bi_rx) = mpsc::channel(16);
+        let (uni, uni_rx) = mpsc::channel(16);
+        let local_addr = inner.local_addr().to_vec();
+        let tasks = [
+            rt::spawn(HandshakeListener::accept_loop(
+                inner.clone(),
+                shared.clone(),
+                bi,
+            )),
+            rt::spawn(HandshakeListener::accept_uni_loop(inner, shared, uni)),
+        ];
+        HandshakeListener {
+            bi: Arc::new(tokio::sync::Mutex::new(bi_rx)),
+            uni: Arc::new(tokio::sync::Mutex::new(uni_rx)),
+            local_addr,
+            _tasks: Arc::new(tasks),
+        }
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage, P: Handshake> Clone for HandshakeListener<In, Out, P> {
+    fn clone(&self) -> Self {
+        Self {
+            bi: self.bi.clone(),
+            uni: self.uni.clone(),
+            local_addr: self.local_addr.clone(),
+            _tasks: self._tasks.clone(),
+        }
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage, P: Handshake> fmt::Debug for HandshakeListener<In, Out, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HandshakeListener")
+            .field("local_addr", &self.local_addr)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<In, Out, P> ConnectionErrors for HandshakeListener<In, Out, P>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    P: Handshake,
+{
+    type SendError = anyhow::Error;
+    type RecvError = anyhow::Error;
+    type OpenError = anyhow::Error;
+    type AcceptError = anyhow::Error;
+}
+
+impl<In, Out, P> StreamTypes for HandshakeListener<In, Out, P>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    P: Handshake,
+{
+    type In = In;
+    type Out = Out;
+    type RecvStream = HandshakeRecvStream<In, P::Hello>;
+    type SendSink = SendSink<Out>;
+}
+
+impl<In, Out, P> Listener for HandshakeListener<In, Out, P>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    P: Handshake,
+{
+    async fn accept(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::AcceptError> {
+        self.bi
+            .lock()
+            .await
+            .recv()
+            .await
+            .unwrap_or_else(|| Err(anyhow::anyhow!("handshake listener closed")))
+    }
+
+    fn local_addr(&self) -> &[LocalAddr] {
+        &self.local_addr
+    }
+
+    async fn accept_uni(&self) -> Result<Self::RecvStream, Self::AcceptError> {
+        self.uni
+            .lock()
+            .await
+            .recv()
+            .await
+            .unwrap_or_else(|| Err(anyhow::anyhow!("handshake listener closed")))
+    }
+
+    /// Datagrams are not forwarded, so this never completes
+    ///
+    /// A datagram can not carry the session id, so forwarding the datagrams of the
+    /// inner listener would let any peer skip the handshake. [HandshakeConnector]
+    /// sends datagrams on unidirectional substreams instead, which arrive at
+    /// [Listener::accept_uni].
+    async fn recv_datagram(&self) -> Result<Self::In, Self::RecvError> {
+        future::pending().await
+    }
+}
+
+/// Receive side of a substream accepted by a [HandshakeListener]
+pub struct HandshakeRecvStream<T: RpcMessage, H> {
+    inner: RecvStream<T>,
+    hello: Arc<H>,
+}
+
+impl<T: RpcMessage, H> HandshakeRecvStream<T, H> {
+    fn new<R, E>(recv: R, hello: Arc<H>) -> Self
+    where
+        R: Stream<Item = Result<Frame<T, H>, E>> + Send + Sync + Unpin + 'static,
+        E: Into<anyhow::Error>,
+    {
+        let recv = recv.map(|frame| match frame {
+            Ok(Frame::Msg(msg)) => Ok(msg),
+            Ok(_) => Err(anyhow::anyhow!("unexpected handshake frame")),
+            Err(cause) => Err(cause.into()),
+        });
+        Self {
+            inner: RecvStream::boxed(recv),
+            hello,
+        }
+    }
+
+    /// The hello message that the client sent to start the session of this substream
+    pub fn hello(&self) -> &H {
+        &self.hello
+    }
+}
+
+impl<T: RpcMessage, H: fmt::Debug> fmt::Debug for HandshakeRecvStream<T, H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HandshakeRecvStream")
+            .field("hello", &self.hello)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: RpcMessage, H> Stream for HandshakeRecvStream<T, H> {
+    type Item = anyhow::Result<T>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.inner).poll_next(cx)
+    }
+}
+
+#[cfg(all(test, feature = "flume-transport"))]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::{message::RpcMsg, transport::flume, RpcClient, RpcServer, Service};
+
+    #[derive(Debug, Clone)]
+    struct Versioned;
+
+    impl Handshake for Versioned {
+        type Hello = u32;
+        type Welcome = String;
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Ping;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Pong(u32);
+
+    #[derive(Debug, Clone)]
+    struct PingService;
+
+    impl Service for PingService {
+        type Req = Ping;
+        type Res = Pong;
+    }
+
+    impl RpcMsg<PingService> for Ping {
+        type Response = Pong;
+    }
+
+    /// Serve pings, responding with the version of the session
+    fn serve(
+        listener: HandshakeListener<Ping, Pong, Versioned>,
+    ) -> tokio::task::JoinHandle<anyhow::Result<()>> {
+        let server = RpcServer::<PingService, _>::new(listener);
+        tokio::spawn(async move {
+            loop {
+                let (req, chan) = server.accept().await?.read_first().await?;
+                let version = *chan.recv.hello();
+                chan.rpc(req, (), move |_, _| async move { Pong(version) })
+                    .await?;
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn handshake() -> anyhow::Result<()> {
+        let (listener, connector) = flume::channel(1);
+        let checked = Arc::new(AtomicUsize::new(0));
+        let listener = HandshakeListener::<_, _, Versioned>::new(listener, {
+            let checked = checked.clone();
+            move |version| {
+                checked.fetch_add(1, Ordering::Relaxed);
+                if *version >= 2 {
+                    Ok("welcome".to_string())
+                } else {
+                    Err("version too old".to_string())
+                }
+            }
+        });
+        let _server = serve(listener);
+        let old = HandshakeConnector::<_, _, _, Versioned>::new(connector.clone(), 1);
+        let old = RpcClient::<PingService, _>::new(old);
+        assert!(old.rpc(Ping).await.is_err());
+
+        let connector = HandshakeConnector::<_, _, _, Versioned>::new(connector, 3);
+        let client = RpcClient::<PingService, _>::new(connector.clone());
+        let Pong(version) = client.rpc(Ping).await?;
+        assert_eq!(version, 3);
+        assert_eq!(connector.welcome().as_deref(), Some("welcome"));
+        let Pong(version) = client.rpc(Ping).await?;
+        assert_eq!(version, 3);
+        // one hello per session, not per substream
+        assert_eq!(checked.load(Ordering::Relaxed), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn silent_peer() -> anyhow::Result<()> {
+        let (listener, connector) = flume::channel(1);
+        let listener = HandshakeListenerBuilder::<Versioned>::new(|_| Ok(String::new()))
+            .hello_timeout(Duration::from_millis(50))
+            .build(listener);
+        let _server = serve(listener);
+        // a substream that never sends its session does not block other substreams
+        let _silent = connector.open().await?;
+        let connector = HandshakeConnector::<_, _, _, Versioned>::new(connector, 3);
+        let client = RpcClient::<PingService, _>::new(connector);
+        let Pong(version) = client.rpc(Ping).await?;
+        assert_eq!(version, 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn forgotten_session() -> anyhow::Result<()> {
+        let (listener, connector) = flume::channel(1);
+        let listener = HandshakeListenerBuilder::<Versioned>::new(|_| Ok(String::new()))
+            .max_sessions(1)
+            .build(listener);
+        let _server = serve(listener);
+        let first = HandshakeConnector::<_, _, _, Versioned>::new(connector.clone(), 2);
+        let first = RpcClient::<PingService, _>::new(first);
+        first.rpc(Ping).await?;
+        let second = HandshakeConnector::<_, _, _, Versioned>::new(connector, 3);
+        RpcClient::<PingService, _>::new(second).rpc(Ping).await?;
+        // the session of the first client was replaced, so its next call fails and
+        // the one after that starts a new session
+        assert!(first.rpc(Ping).await.is_err());
+        let Pong(version) = first.rpc(Ping).await?;
+        assert_eq!(version, 2);
+        Ok(())
+    }
+}
@@ -1,9 +1,18 @@
 //! http2 transport using [hyper]
 //!
+//! Every substream is a single http2 request. The items sent by the client are
+//! streamed in the request body and the items sent by the server in the response
+//! body, both as length prefixed postcard frames. Items are forwarded as soon as
+//! their frame is complete, in both directions.
+//!
+//! Opening a substream does not wait for the response headers, so the first item
+//! goes out together with the request headers, without an additional round trip.
+//! If the request fails, the error is returned from the receive stream.
+//!
 //! [hyper]: https://crates.io/crates/hyper/
 use std::{
-    convert::Infallible, error, fmt, io, marker::PhantomData, net::SocketAddr, pin::Pin, result,
-    sync::Arc, task::Poll,
+    convert::Infallible, error, fmt, future::poll_fn, io, marker::PhantomData, net::SocketAddr,
+    pin::Pin, result, sync::Arc, task::Poll, time::Duration,
 };
 
 use bytes::Bytes;
@@ -12,11 +21,18 @@ use futures_lite::{Stream, StreamExt};
 use futures_sink::Sink;
 use hyper::{
     client::{connect::Connect, HttpConnector, ResponseFuture},
-    server::conn::{AddrIncoming, AddrStream},
-    service::{make_service_fn, service_fn},
-    Body, Client, Request, Response, Server, StatusCode, Uri,
+    header::{HeaderName, HeaderValue, InvalidHeaderValue, AUTHORIZATION},
+    server::{
+        accept::Accept,
+        conn::{AddrIncoming, AddrStream, Http},
+    },
+    service::service_fn,
+    Body, Client, HeaderMap, Request, Response, StatusCode, Uri,
+};
+use tokio::{
+    sync::{mpsc, watch, Notify, OwnedSemaphorePermit, Semaphore},
+    task::JoinHandle,
 };
-use tokio::{sync::mpsc, task::JoinHandle};
 use tracing::{debug, event, trace, Level};
 
 use crate::{
@@ -33,6 +49,8 @@ struct HyperConnectionInner {
 /// Hyper based connection to a server
 pub struct HyperConnector<In: RpcMessage, Out: RpcMessage> {
     inner: Arc<HyperConnectionInner>,
+    headers: Arc<HeaderMap>,
+    header_fn: Option<HeaderFn>,
     _p: PhantomData<(In, Out)>,
 }
 
@@ -40,11 +58,27 @@ impl<In: RpcMessage, Out: RpcMessage> Clone for HyperConnector<In, Out> {
     fn clone(&self) -> Self {
         Self {
             inner: self.inner.clone(),
+            headers: self.headers.clone(),
+            header_fn: self.header_fn.clone(),
             _p: PhantomData,
         }
     }
 }
 
+/// Function that adds headers to each request
+type HeaderFn = Arc<dyn Fn(&mut HeaderMap) + Send + Sync + 'static>;
+
+/// Function that decides if a request is accepted, based on its headers
+#[derive(Clone)]
+#[allow(clippy::type_complexity)]
+struct Authorizer(Arc<dyn Fn(&HeaderMap) -> result::Result<(), StatusCode> + Send + Sync>);
+
+impl fmt::Debug for Authorizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Authorizer")
+    }
+}
+
 /// Trait so we don't have to drag around the hyper internals
 trait Requester: Send + Sync + 'static {
     fn request(&self, req: Request<Body>) -> ResponseFuture;
@@ -88,9 +122,37 @@ impl<In: RpcMessage, Out: RpcMessage> HyperConnector<In, Out> {
                 uri,
                 config,
             }),
+            headers: Default::default(),
+            header_fn: None,
             _p: PhantomData,
         }
     }
+
+    /// Add a header that is sent with every request
+    ///
+    /// This can be used for static credentials, e.g. an `Authorization` header with
+    /// a bearer token, see [HyperConnector::with_bearer_token].
+    pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        Arc::make_mut(&mut self.headers).append(name, value);
+        self
+    }
+
+    /// Send an `Authorization: Bearer <token>` header with every request
+    pub fn with_bearer_token(self, token: &str) -> result::Result<Self, InvalidHeaderValue> {
+        let mut value = HeaderValue::try_from(format!("Bearer {token}"))?;
+        value.set_sensitive(true);
+        Ok(self.with_header(AUTHORIZATION, value))
+    }
+
+    /// Call a function to add headers to every request
+    ///
+    /// The function is called each time a substream is opened, after the static
+    /// headers were added. Use this for headers that change, like tracing context
+    /// or tokens that expire.
+    pub fn with_header_fn(mut self, f: impl Fn(&mut HeaderMap) + Send + Sync + 'static) -> Self {
+        self.header_fn = Some(Arc::new(f));
+        self
+    }
 }
 
 impl<In: RpcMessage, Out: RpcMessage> fmt::Debug for HyperConnector<In, Out> {
@@ -98,6 +160,7 @@ impl<In: RpcMessage, Out: RpcMessage> fmt::Debug for HyperConnector<In, Out> {
         f.debug_struct("ClientChannel")
             .field("uri", &self.inner.uri)
             .field("config", &self.inner.config)
+            .field("headers", &self.headers)
             .finish()
     }
 }
@@ -186,8 +249,8 @@ pub struct HyperListener<In: RpcMessage, Out: RpcMessage> {
     /// The local address this server is bound to.
     ///
     /// This is useful when the listen address uses a random port, `:0`, to find out which
-    /// port was bound by the kernel.
-    local_addr: [LocalAddr; 1],
+    /// port was bound by the kernel. Empty if the listener is mounted in another server.
+    local_addr: Vec<LocalAddr>,
     /// Phantom data for service
     _p: PhantomData<(In, Out)>,
 }
@@ -200,92 +263,425 @@ impl<In: RpcMessage, Out: RpcMessage> HyperListener<In, Out> {
 
     /// Creates a server listening on the [`SocketAddr`] with a custom configuration.
     pub fn serve_with_config(addr: &SocketAddr, config: ChannelConfig) -> hyper::Result<Self> {
+        Self::builder().channel_config(config).serve(addr)
+    }
+
+    /// Creates a builder to configure the limits and timeouts of the server.
+    pub fn builder() -> HyperListenerBuilder<In, Out> {
+        HyperListenerBuilder::default()
+    }
+}
+
+/// Builder for a [`HyperListener`]
+///
+/// The defaults limit the resources a single client can use: connections that do
+/// not send a request within 10 seconds are closed, and each connection can have
+/// at most 100 concurrent requests, the same as the default for quinn.
+#[derive(Debug)]
+pub struct HyperListenerBuilder<In: RpcMessage, Out: RpcMessage> {
+    config: ChannelConfig,
+    header_read_timeout: Option<Duration>,
+    max_concurrent_streams: Option<u32>,
+    max_connections: Option<usize>,
+    max_header_list_size: u32,
+    keep_alive_interval: Option<Duration>,
+    keep_alive_timeout: Duration,
+    authorizer: Option<Authorizer>,
+    path: Option<String>,
+    _p: PhantomData<(In, Out)>,
+}
+
+impl<In: RpcMessage, Out: RpcMessage> Default for HyperListenerBuilder<In, Out> {
+    fn default() -> Self {
+        Self {
+            config: ChannelConfig::default(),
+            header_read_timeout: Some(Duration::from_secs(10)),
+            max_concurrent_streams: Some(100),
+            max_connections: None,
+            max_header_list_size: 16 * 1024,
+            keep_alive_interval: None,
+            keep_alive_timeout: Duration::from_secs(20),
+            authorizer: None,
+            path: None,
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage> HyperListenerBuilder<In, Out> {
+    /// Set the channel configuration, which contains the frame and payload size limits.
+    pub fn channel_config(mut self, config: ChannelConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Set the maximum http2 frame size.
+    pub fn max_frame_size(mut self, value: u32) -> result::Result<Self, ChannelConfigError> {
+        self.config = self.config.max_frame_size(value)?;
+        Ok(self)
+    }
+
+    /// Set the maximum size of a single request message.
+    pub fn max_payload_size(mut self, value: usize) -> result::Result<Self, ChannelConfigError> {
+        self.config = self.config.max_payload_size(value)?;
+        Ok(self)
+    }
+
+    /// Set the time a new connection has to send the headers of its first request.
+    ///
+    /// Connections that don't are closed. `None` disables the timeout.
+    pub fn header_read_timeout(mut self, value: Option<Duration>) -> Self {
+        self.header_read_timeout = value;
+        self
+    }
+
+    /// Set the maximum number of concurrent requests per connection.
+    ///
+    /// `None` means no limit.
+    pub fn max_concurrent_streams(mut self, value: Option<u32>) -> Self {
+        self.max_concurrent_streams = value;
+        self
+    }
+
+    /// Set the maximum number of concurrent connections.
+    ///
+    /// Once the limit is reached, new connections are not accepted until an existing
+    /// connection is closed. `None`, the default, means no limit.
+    pub fn max_connections(mut self, value: Option<usize>) -> Self {
+        self.max_connections = value;
+        self
+    }
+
+    /// Set the maximum size of the headers of a request.
+    pub fn max_header_list_size(mut self, value: u32) -> Self {
+        self.max_header_list_size = value;
+        self
+    }
+
+    /// Set the interval of http2 keep-alive pings.
+    ///
+    /// `None`, the default, disables keep-alive pings.
+    pub fn keep_alive_interval(mut self, value: Option<Duration>) -> Self {
+        self.keep_alive_interval = value;
+        self
+    }
+
+    /// Set the time to wait for a keep-alive ping to be acknowledged.
+    ///
+    /// If the ping is not acknowledged in time, the connection is closed. This has
+    /// no effect if keep-alive pings are disabled.
+    pub fn keep_alive_timeout(mut self, value: Duration) -> Self {
+        self.keep_alive_timeout = value;
+        self
+    }
+
+    /// Set a function that decides if a request is accepted, based on its headers.
+    ///
+    /// Requests for which the function returns an error are answered with the
+    /// returned status code, and never show up as a substream of the listener.
+    /// This can be used to check tokens before any rpc traffic is accepted.
+    pub fn authorize(
+        mut self,
+        f: impl Fn(&HeaderMap) -> result::Result<(), StatusCode> + Send + Sync + 'static,
+    ) -> Self {
+        self.authorizer = Some(Authorizer(Arc::new(f)));
+        self
+    }
+
+    /// Only accept requests for the given path, e.g. `/rpc/calc`.
+    ///
+    /// Requests for other paths are answered with 404. By default, requests for all
+    /// paths are accepted. The path must be part of the uri of the connector.
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Creates a listener and a handler for requests, to mount in an existing hyper server.
+    ///
+    /// This does not bind a socket. Requests passed to [HyperHandler::handle] show up
+    /// as substreams of the listener. This way one server can host multiple services,
+    /// each under its own [path](Self::path), next to other routes.
+    ///
+    /// The server must speak http2. The connection level settings of this builder, like
+    /// timeouts and limits, must be configured on the server instead.
+    pub fn handler(self) -> (HyperListener<In, Out>, HyperHandler<In>) {
         let (accept_tx, accept_rx) = flume::bounded(32);
+        let handler = HyperHandler {
+            accept_tx,
+            max_payload_size: self.config.max_payload_size,
+            authorizer: self.authorizer,
+            path: self.path.map(Into::into),
+        };
+        // there is no server task to stop
+        let (stop_tx, _) = mpsc::channel::<()>(1);
+        let listener = HyperListener {
+            channel: accept_rx,
+            config: Arc::new(self.config),
+            stop_tx,
+            local_addr: Vec::new(),
+            _p: PhantomData,
+        };
+        (listener, handler)
+    }
 
-        // The hyper "MakeService" which is called for each connection that is made to the
-        // server.  It creates another Service which handles a single request.
-        let service = make_service_fn(move |socket: &AddrStream| {
-            let remote_addr = socket.remote_addr();
-            event!(Level::TRACE, "Connection from {:?}", remote_addr);
-
-            // Need a new accept_tx to move to the future on every call of this FnMut.
-            let accept_tx = accept_tx.clone();
-            async move {
-                let one_req_service = service_fn(move |req: Request<Body>| {
-                    // This closure is an FnMut as well, so clone accept_tx once more.
-                    Self::handle_one_http2_request(req, accept_tx.clone())
-                });
-                Ok::<_, Infallible>(one_req_service)
-            }
-        });
+    /// Creates a server listening on the [`SocketAddr`].
+    pub fn serve(self, addr: &SocketAddr) -> hyper::Result<HyperListener<In, Out>> {
+        let (accept_tx, accept_rx) = flume::bounded(32);
+        let config = self.config;
 
-        let mut incoming = AddrIncoming::bind(addr)?;
-        incoming.set_nodelay(true);
-        let server = Server::builder(incoming)
-            .http2_only(true)
+        let mut http = Http::new();
+        http.http2_only(true)
             .http2_initial_connection_window_size(Some(config.max_frame_size))
             .http2_initial_stream_window_size(Some(config.max_frame_size))
             .http2_max_frame_size(Some(config.max_frame_size))
             .http2_max_send_buf_size(config.max_frame_size.try_into().unwrap())
-            .serve(service);
-        let local_addr = server.local_addr();
+            .http2_max_concurrent_streams(self.max_concurrent_streams)
+            .http2_max_header_list_size(self.max_header_list_size)
+            .http2_keep_alive_interval(self.keep_alive_interval)
+            .http2_keep_alive_timeout(self.keep_alive_timeout);
+        let conn = ConnectionConfig {
+            http,
+            handler: HyperHandler {
+                accept_tx,
+                max_payload_size: config.max_payload_size,
+                authorizer: self.authorizer,
+                path: self.path.map(Into::into),
+            },
+            header_read_timeout: self.header_read_timeout,
+        };
 
-        let (stop_tx, mut stop_rx) = mpsc::channel::<()>(1);
-        let server = server.with_graceful_shutdown(async move {
-            // If the sender is dropped this will also gracefully terminate the server.
-            stop_rx.recv().await;
-        });
-        tokio::spawn(server);
+        let mut incoming = AddrIncoming::bind(addr)?;
+        incoming.set_nodelay(true);
+        let local_addr = incoming.local_addr();
+        let limit = self.max_connections.map(|n| Arc::new(Semaphore::new(n)));
+
+        let (stop_tx, stop_rx) = mpsc::channel::<()>(1);
+        tokio::spawn(accept_connections(incoming, limit, conn, stop_rx));
 
-        Ok(Self {
+        Ok(HyperListener {
             channel: accept_rx,
             config: Arc::new(config),
             stop_tx,
-            local_addr: [LocalAddr::Socket(local_addr)],
+            local_addr: vec![LocalAddr::Socket(local_addr)],
             _p: PhantomData,
         })
     }
+}
+
+/// Everything needed to serve a single connection
+struct ConnectionConfig<In: RpcMessage> {
+    http: Http,
+    handler: HyperHandler<In>,
+    header_read_timeout: Option<Duration>,
+}
+
+/// Handles http2 requests for a [HyperListener]
+///
+/// Created with [HyperListenerBuilder::handler], for mounting a listener in an
+/// existing hyper server.
+pub struct HyperHandler<In: RpcMessage> {
+    accept_tx: Sender<InternalChannel<In>>,
+    max_payload_size: usize,
+    authorizer: Option<Authorizer>,
+    path: Option<Arc<str>>,
+}
+
+impl<In: RpcMessage> Clone for HyperHandler<In> {
+    fn clone(&self) -> Self {
+        Self {
+            accept_tx: self.accept_tx.clone(),
+            max_payload_size: self.max_payload_size,
+            authorizer: self.authorizer.clone(),
+            path: self.path.clone(),
+        }
+    }
+}
 
-    /// Handles a single HTTP2 request.
+impl<In: RpcMessage> fmt::Debug for HyperHandler<In> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HyperHandler")
+            .field("path", &self.path)
+            .field("authorizer", &self.authorizer)
+            .finish()
+    }
+}
+
+impl<In: RpcMessage> HyperHandler<In> {
+    /// Handle a request, passing it to the listener as a new substream
     ///
-    /// This creates the channels to communicate the (optionally streaming) request and
-    /// response and sends them to the [`ServerChannel`].
-    async fn handle_one_http2_request(
-        req: Request<Body>,
-        accept_tx: Sender<InternalChannel<In>>,
-    ) -> Result<Response<Body>, String> {
-        let (req_tx, req_rx) = flume::bounded::<result::Result<In, RecvError>>(32);
-        let (res_tx, res_rx) = flume::bounded::<io::Result<Bytes>>(32);
-        accept_tx
-            .send_async((req_rx, res_tx))
-            .await
-            .map_err(|_e| "unable to send")?;
+    /// The response body streams the items sent by the server. Requests for a
+    /// different path are answered with 404, and requests that are not authorized
+    /// with the status returned by the authorization function. If the listener was
+    /// dropped, the response is 503.
+    pub async fn handle(&self, req: Request<Body>) -> Response<Body> {
+        let status = match &self.path {
+            Some(path) if req.uri().path() != &**path => Err(StatusCode::NOT_FOUND),
+            _ => match &self.authorizer {
+                Some(authorizer) => (authorizer.0)(req.headers()),
+                None => Ok(()),
+            },
+        };
+        if let Err(status) = status {
+            debug!("Request rejected with status {status}");
+            return status_response(status);
+        }
+        match handle_one_http2_request(req, self.accept_tx.clone(), self.max_payload_size).await {
+            Ok(response) => response,
+            Err(cause) => {
+                debug!("Unable to handle request: {cause}");
+                status_response(StatusCode::SERVICE_UNAVAILABLE)
+            }
+        }
+    }
+}
 
-        spawn_recv_forwarder(req.into_body(), req_tx);
-        // Create a response with the response body channel as the response body
-        let response = Response::builder()
-            .status(StatusCode::OK)
-            .body(Body::wrap_stream(res_rx.into_stream()))
-            .map_err(|_| "unable to set body")?;
-        Ok(response)
+fn status_response(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+/// Accepts connections until the listener is dropped.
+///
+/// Once the listener is dropped, no new connections are accepted and all existing
+/// connections are shut down gracefully.
+async fn accept_connections<In: RpcMessage>(
+    mut incoming: AddrIncoming,
+    limit: Option<Arc<Semaphore>>,
+    conn: ConnectionConfig<In>,
+    mut stop_rx: mpsc::Receiver<()>,
+) {
+    let conn = Arc::new(conn);
+    // Dropping the sender at the end of this fn signals all connections to shut down.
+    let (_shutdown_tx, shutdown_rx) = watch::channel(());
+    loop {
+        let next = async {
+            let permit = match &limit {
+                Some(limit) => Some(limit.clone().acquire_owned().await.ok()?),
+                None => None,
+            };
+            let socket = poll_fn(|cx| Pin::new(&mut incoming).poll_accept(cx)).await?;
+            Some((socket, permit))
+        };
+        let (socket, permit) = tokio::select! {
+            // If the sender is dropped this will also gracefully terminate the server.
+            _ = stop_rx.recv() => break,
+            next = next => match next {
+                Some(next) => next,
+                None => break,
+            },
+        };
+        let socket = match socket {
+            Ok(socket) => socket,
+            Err(cause) => {
+                debug!("Failed to accept connection: {cause}");
+                continue;
+            }
+        };
+        tokio::spawn(serve_connection(
+            socket,
+            conn.clone(),
+            permit,
+            shutdown_rx.clone(),
+        ));
     }
 }
 
-fn try_get_length_prefixed(buf: &[u8]) -> Option<&[u8]> {
+/// Serves a single connection.
+async fn serve_connection<In: RpcMessage>(
+    socket: AddrStream,
+    conn: Arc<ConnectionConfig<In>>,
+    _permit: Option<OwnedSemaphorePermit>,
+    mut shutdown_rx: watch::Receiver<()>,
+) {
+    let remote_addr = socket.remote_addr();
+    event!(Level::TRACE, "Connection from {:?}", remote_addr);
+    let first_request = Arc::new(Notify::new());
+    let service = {
+        let conn = conn.clone();
+        let first_request = first_request.clone();
+        service_fn(move |req: Request<Body>| {
+            first_request.notify_one();
+            let handler = conn.handler.clone();
+            async move { Ok::<_, Infallible>(handler.handle(req).await) }
+        })
+    };
+    let connection = conn.http.serve_connection(socket, service);
+    tokio::pin!(connection);
+    let mut header_read_timeout = conn
+        .header_read_timeout
+        .map(|timeout| Box::pin(tokio::time::sleep(timeout)));
+    let mut shutting_down = false;
+    loop {
+        tokio::select! {
+            res = &mut connection => {
+                if let Err(cause) = res {
+                    debug!("Connection from {remote_addr} failed: {cause}");
+                }
+                break;
+            }
+            _ = first_request.notified(), if header_read_timeout.is_some() => {
+                header_read_timeout = None;
+            }
+            _ = async { header_read_timeout.as_mut().unwrap().await }, if header_read_timeout.is_some() => {
+                debug!("Connection from {remote_addr} did not send a request in time");
+                break;
+            }
+            _ = shutdown_rx.changed(), if !shutting_down => {
+                connection.as_mut().graceful_shutdown();
+                shutting_down = true;
+            }
+        }
+    }
+}
+
+/// Handles a single HTTP2 request.
+///
+/// This creates the channels to communicate the (optionally streaming) request and
+/// response and sends them to the [`HyperListener`].
+async fn handle_one_http2_request<In: RpcMessage>(
+    req: Request<Body>,
+    accept_tx: Sender<InternalChannel<In>>,
+    max_payload_size: usize,
+) -> Result<Response<Body>, String> {
+    let (req_tx, req_rx) = flume::bounded::<result::Result<In, RecvError>>(32);
+    let (res_tx, res_rx) = flume::bounded::<io::Result<Bytes>>(32);
+    accept_tx
+        .send_async((req_rx, res_tx))
+        .await
+        .map_err(|_e| "unable to send")?;
+
+    spawn_recv_forwarder(req.into_body(), req_tx, max_payload_size);
+    // Create a response with the response body channel as the response body
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::wrap_stream(res_rx.into_stream()))
+        .map_err(|_| "unable to set body")?;
+    Ok(response)
+}
+
+/// Get the first frame of the buffer, if it is complete
+///
+/// Fails with the length of the frame if it exceeds the maximum payload size.
+fn try_get_length_prefixed(buf: &[u8], max_payload_size: usize) -> Result<Option<&[u8]>, usize> {
     if buf.len() < 4 {
-        return None;
+        return Ok(None);
     }
     let len = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+    if len > max_payload_size {
+        return Err(len);
+    }
     if buf.len() < 4 + len {
-        return None;
+        return Ok(None);
     }
-    Some(&buf[4..4 + len])
+    Ok(Some(&buf[4..4 + len]))
 }
 
 /// Try forward all frames as deserialized messages from the buffer to the sender.
 ///
 /// On success, returns the number of forwarded bytes.
-/// On forward error, returns the unit error.
+/// On forward error, or if a frame is too large, returns the unit error.
 ///
 /// Deserialization errors don't cause an error, they will be sent.
 /// On error the number of consumed bytes is not returned. There is nothing to do but
@@ -293,9 +689,20 @@ fn try_get_length_prefixed(buf: &[u8]) -> Option<&[u8]> {
 async fn try_forward_all<In: RpcMessage>(
     buffer: &[u8],
     req_tx: &Sender<Result<In, RecvError>>,
+    max_payload_size: usize,
 ) -> result::Result<usize, ()> {
     let mut sent = 0;
-    while let Some(msg) = try_get_length_prefixed(&buffer[sent..]) {
+    loop {
+        let msg = match try_get_length_prefixed(&buffer[sent..], max_payload_size) {
+            Ok(Some(msg)) => msg,
+            Ok(None) => break,
+            Err(len) => {
+                // The frame would have to be buffered in full, so stop reading.
+                debug!("Frame of {len} bytes exceeds the maximum payload size");
+                req_tx.send_async(Err(RecvError::SizeError(len))).await.ok();
+                return Err(());
+            }
+        };
         sent += msg.len() + 4;
         let item = postcard::from_bytes::<In>(msg).map_err(RecvError::DeserializeError);
         if let Err(_cause) = req_tx.send_async(item).await {
@@ -316,6 +723,9 @@ async fn try_forward_all<In: RpcMessage>(
 ///
 /// This task will read chunks from the network, split them into length prefixed
 /// frames, deserialize those frames, and send the result to the flume channel.
+/// Frames are forwarded as soon as they are complete, so at most one frame of at
+/// most `max_payload_size` bytes is buffered. The flume channel is bounded, so a
+/// slow receiver applies backpressure to the remote via http2 flow control.
 ///
 /// If there is a network error or the flume channel closes or the request
 /// stream is simply ended this task will terminate.
@@ -326,6 +736,7 @@ async fn try_forward_all<In: RpcMessage>(
 fn spawn_recv_forwarder<In: RpcMessage>(
     req: Body,
     req_tx: Sender<result::Result<In, RecvError>>,
+    max_payload_size: usize,
 ) -> JoinHandle<result::Result<(), ()>> {
     tokio::spawn(async move {
         let mut stream = req;
@@ -337,7 +748,7 @@ fn spawn_recv_forwarder<In: RpcMessage>(
                     event!(Level::TRACE, "Server got {} bytes", chunk.len());
                     if buf.is_empty() {
                         // try to forward directly from buffer
-                        let sent = try_forward_all(chunk, &req_tx).await?;
+                        let sent = try_forward_all(chunk, &req_tx, max_payload_size).await?;
                         // add just the rest, if any
                         buf.extend_from_slice(&chunk[sent..]);
                     } else {
@@ -353,7 +764,7 @@ fn spawn_recv_forwarder<In: RpcMessage>(
                     break;
                 }
             };
-            let sent = try_forward_all(&buf, &req_tx).await?;
+            let sent = try_forward_all(&buf, &req_tx, max_payload_size).await?;
             // remove the forwarded bytes.
             // Frequently this will be the entire buffer, so no memcpy but just set the size to 0
             buf.drain(..sent);
@@ -469,10 +880,7 @@ impl<Out: RpcMessage> Sink<Out> for SendSink<Out> {
         // figure out what to send and what to return
         let (send, res) = match self.serialize(item) {
             Ok(data) => (Ok(data), Ok(())),
-            Err(cause) => (
-                Err(io::Error::new(io::ErrorKind::Other, cause.to_string())),
-                Err(cause),
-            ),
+            Err(cause) => (Err(io::Error::other(cause.to_string())), Err(cause)),
         };
         // attempt sending
         Pin::new(&mut self.sink)
@@ -526,6 +934,10 @@ pub enum RecvError {
     DeserializeError(postcard::Error),
     /// Hyper network error.
     NetworkError(hyper::Error),
+    /// The remote sent a message that is larger than the maximum payload size.
+    SizeError(usize),
+    /// The server rejected the request with the given status, e.g. because it was not authorized.
+    Rejected(StatusCode),
 }
 
 impl fmt::Display for RecvError {
@@ -594,17 +1006,39 @@ impl<In: RpcMessage, Out: RpcMessage> StreamTypes for HyperConnector<In, Out> {
 impl<In: RpcMessage, Out: RpcMessage> Connector for HyperConnector<In, Out> {
     async fn open(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
         let (out_tx, out_rx) = flume::bounded::<io::Result<Bytes>>(32);
-        let req: Request<Body> = Request::post(&self.inner.uri)
+        let mut req: Request<Body> = Request::post(&self.inner.uri)
             .body(Body::wrap_stream(out_rx.into_stream()))
             .map_err(OpenError::HyperHttp)?;
-        let res = self
-            .inner
-            .client
-            .request(req)
-            .await
-            .map_err(OpenError::Hyper)?;
+        req.headers_mut()
+            .extend(self.headers.iter().map(|(k, v)| (k.clone(), v.clone())));
+        if let Some(header_fn) = &self.header_fn {
+            header_fn(req.headers_mut());
+        }
+        let response = self.inner.client.request(req);
         let (in_tx, in_rx) = flume::bounded::<result::Result<In, RecvError>>(32);
-        spawn_recv_forwarder(res.into_body(), in_tx);
+        let max_payload_size = self.inner.config.max_payload_size;
+        // the request is driven by the connection, so there is no need to wait for the response
+        tokio::spawn(async move {
+            match response.await {
+                Ok(res) if res.status() != StatusCode::OK => {
+                    debug!("http2 request rejected with status {}", res.status());
+                    in_tx
+                        .send_async(Err(RecvError::Rejected(res.status())))
+                        .await
+                        .ok();
+                }
+                Ok(res) => {
+                    spawn_recv_forwarder(res.into_body(), in_tx, max_payload_size);
+                }
+                Err(cause) => {
+                    debug!("http2 request failed: {cause}");
+                    in_tx
+                        .send_async(Err(RecvError::NetworkError(cause)))
+                        .await
+                        .ok();
+                }
+            }
+        });
 
         let out_tx = self::SendSink::new(out_tx, self.inner.config.clone());
         let in_rx = self::RecvStream::new(in_rx);
@@ -0,0 +1,309 @@
+//! Connector wrapper that tracks in-flight requests.
+//!
+//! [InflightConnector] counts the substreams that are being opened, the open
+//! substreams, the requests that are still waiting for the end of their response
+//! stream, and items that were sent but not yet flushed. Applications can use
+//! this for admission control, or to display progress.
+//!
+//! The counts are shared between clones of the connector, and an [RpcClient]
+//! using the connector exposes them via [RpcClient::inflight].
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use futures_lite::Stream;
+use futures_sink::Sink;
+use pin_project::{pin_project, pinned_drop};
+
+use super::{ConnectionErrors, Connector, StreamTypes};
+use crate::{RpcClient, Service};
+
+/// Counters for in-flight requests
+#[derive(Debug, Default)]
+pub struct InflightStats {
+    opening: AtomicU64,
+    open_streams: AtomicU64,
+    in_flight: AtomicU64,
+    queued_sends: AtomicU64,
+}
+
+impl InflightStats {
+    /// A snapshot of the current values
+    pub fn snapshot(&self) -> InflightSnapshot {
+        InflightSnapshot {
+            opening: self.opening.load(Ordering::Relaxed),
+            open_streams: self.open_streams.load(Ordering::Relaxed),
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            queued_sends: self.queued_sends.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A snapshot of [InflightStats]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InflightSnapshot {
+    /// Number of substreams that are currently being opened
+    pub opening: u64,
+    /// Number of open substreams, where the send sink or the receive stream is alive
+    pub open_streams: u64,
+    /// Number of substreams whose receive stream has not ended yet
+    pub in_flight: u64,
+    /// Number of items that were sent, but not yet flushed
+    pub queued_sends: u64,
+}
+
+/// Decrements a counter when dropped
+#[derive(Debug)]
+struct Guard {
+    stats: Arc<InflightStats>,
+    counter: fn(&InflightStats) -> &AtomicU64,
+}
+
+impl Guard {
+    fn new(stats: Arc<InflightStats>, counter: fn(&InflightStats) -> &AtomicU64) -> Self {
+        counter(&stats).fetch_add(1, Ordering::Relaxed);
+        Self { stats, counter }
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        (self.counter)(&self.stats).fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A send sink that counts unflushed items
+#[pin_project(PinnedDrop)]
+#[derive(Debug)]
+pub struct SendSink<S> {
+    #[pin]
+    inner: S,
+    unflushed: u64,
+    stats: Arc<InflightStats>,
+    _stream: Arc<Guard>,
+}
+
+#[pinned_drop]
+impl<S> PinnedDrop for SendSink<S> {
+    fn drop(self: Pin<&mut Self>) {
+        let this = self.project();
+        let unflushed = std::mem::take(this.unflushed);
+        this.stats
+            .queued_sends
+            .fetch_sub(unflushed, Ordering::Relaxed);
+    }
+}
+
+impl<S> SendSink<S> {
+    fn flushed(self: Pin<&mut Self>) {
+        let this = self.project();
+        let unflushed = std::mem::take(this.unflushed);
+        this.stats
+            .queued_sends
+            .fetch_sub(unflushed, Ordering::Relaxed);
+    }
+}
+
+impl<S: Sink<T>, T> Sink<T> for SendSink<S> {
+    type Error = S::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        let this = self.project();
+        this.inner.start_send(item)?;
+        *this.unflushed += 1;
+        this.stats.queued_sends.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let res = self.as_mut().project().inner.poll_flush(cx);
+        if res.is_ready() {
+            self.flushed();
+        }
+        res
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let res = self.as_mut().project().inner.poll_close(cx);
+        if res.is_ready() {
+            self.flushed();
+        }
+        res
+    }
+}
+
+/// A receive stream that counts as in flight until it ends or is dropped
+#[pin_project]
+#[derive(Debug)]
+pub struct RecvStream<S> {
+    #[pin]
+    inner: S,
+    in_flight: Option<Guard>,
+    _stream: Arc<Guard>,
+}
+
+impl<S: Stream> Stream for RecvStream<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let res = this.inner.poll_next(cx);
+        if let Poll::Ready(None) = res {
+            this.in_flight.take();
+        }
+        res
+    }
+}
+
+/// A connector that tracks in-flight requests on all substreams it opens
+#[derive(Debug, Clone)]
+pub struct InflightConnector<C> {
+    inner: C,
+    stats: Arc<InflightStats>,
+}
+
+impl<C> InflightConnector<C> {
+    /// Wrap a connector
+    pub fn new(inner: C) -> Self {
+        Self {
+            inner,
+            stats: Default::default(),
+        }
+    }
+
+    /// The counters of this connector
+    pub fn stats(&self) -> &Arc<InflightStats> {
+        &self.stats
+    }
+}
+
+impl<C: ConnectionErrors> ConnectionErrors for InflightConnector<C> {
+    type SendError = C::SendError;
+    type RecvError = C::RecvError;
+    type OpenError = C::OpenError;
+    type AcceptError = C::AcceptError;
+}
+
+impl<C: StreamTypes> StreamTypes for InflightConnector<C> {
+    type In = C::In;
+    type Out = C::Out;
+    type RecvStream = RecvStream<C::RecvStream>;
+    type SendSink = SendSink<C::SendSink>;
+}
+
+impl<C: Connector> InflightConnector<C> {
+    /// Count an open on the inner connector, and the channel it returns
+    async fn open_by(
+        &self,
+        open: impl Future<Output = Result<(C::SendSink, C::RecvStream), C::OpenError>>,
+    ) -> Result<(SendSink<C::SendSink>, RecvStream<C::RecvStream>), C::OpenError> {
+        let opening = Guard::new(self.stats.clone(), |s| &s.opening);
+        let (send, recv) = open.await?;
+        drop(opening);
+        let stream = Arc::new(Guard::new(self.stats.clone(), |s| &s.open_streams));
+        let send = SendSink {
+            inner: send,
+            unflushed: 0,
+            stats: self.stats.clone(),
+            _stream: stream.clone(),
+        };
+        let recv = RecvStream {
+            inner: recv,
+            in_flight: Some(Guard::new(self.stats.clone(), |s| &s.in_flight)),
+            _stream: stream,
+        };
+        Ok((send, recv))
+    }
+}
+
+impl<C: Connector> Connector for InflightConnector<C> {
+    async fn open(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        self.open_by(self.inner.open()).await
+    }
+
+    async fn open_with_priority(
+        &self,
+        priority: i32,
+    ) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        self.open_by(self.inner.open_with_priority(priority)).await
+    }
+
+    /// Open a unidirectional substream
+    ///
+    /// There is no response, so the substream does not count as in flight.
+    async fn open_uni(&self) -> Result<Self::SendSink, Self::OpenError> {
+        let opening = Guard::new(self.stats.clone(), |s| &s.opening);
+        let send = self.inner.open_uni().await?;
+        drop(opening);
+        Ok(SendSink {
+            inner: send,
+            unflushed: 0,
+            stats: self.stats.clone(),
+            _stream: Arc::new(Guard::new(self.stats.clone(), |s| &s.open_streams)),
+        })
+    }
+
+    async fn send_datagram(&self, msg: Self::Out) -> Result<Option<Self::Out>, Self::SendError> {
+        self.inner.send_datagram(msg).await
+    }
+}
+
+impl<S: Service, C> RpcClient<S, InflightConnector<C>> {
+    /// A snapshot of the in-flight requests of this client
+    pub fn inflight(&self) -> InflightSnapshot {
+        self.source.stats.snapshot()
+    }
+}
+
+#[cfg(all(test, feature = "flume-transport"))]
+mod tests {
+    use futures_lite::StreamExt;
+    use futures_util::SinkExt;
+
+    use super::*;
+    use crate::transport::{flume, Listener};
+
+    #[tokio::test]
+    async fn counts() -> anyhow::Result<()> {
+        let (listener, connector) = flume::channel::<u64, u64>(1);
+        let connector = InflightConnector::new(connector);
+        let stats = connector.stats().clone();
+        let (mut send, mut recv) = connector.open().await?;
+        let (mut server_send, mut server_recv) = listener.accept().await?;
+        assert_eq!(
+            stats.snapshot(),
+            InflightSnapshot {
+                open_streams: 1,
+                in_flight: 1,
+                ..Default::default()
+            }
+        );
+        send.feed(1).await?;
+        send.feed(2).await?;
+        assert_eq!(stats.snapshot().queued_sends, 2);
+        send.flush().await?;
+        assert_eq!(stats.snapshot().queued_sends, 0);
+        drop(send);
+        assert_eq!(server_recv.next().await.transpose()?, Some(1));
+        server_send.send(3).await?;
+        drop(server_send);
+        assert_eq!(recv.next().await.transpose()?, Some(3));
+        assert_eq!(stats.snapshot().in_flight, 1);
+        assert!(recv.next().await.is_none());
+        assert_eq!(stats.snapshot().in_flight, 0);
+        assert_eq!(stats.snapshot().open_streams, 1);
+        drop(recv);
+        assert_eq!(stats.snapshot(), InflightSnapshot::default());
+        Ok(())
+    }
+}
@@ -0,0 +1,402 @@
+//! Transport wrapper that counts substreams, messages, bytes and errors.
+//!
+//! [InstrumentedConnector] and [InstrumentedListener] wrap any connector or
+//! listener and update a shared set of [Metrics] for every substream and every
+//! message. Since this works on the transport level, every service that uses the
+//! wrapped transport is covered, without changes to the handlers.
+//!
+//! Messages are counted with the size of their length prefixed postcard frame,
+//! which is what the network transports put on the wire. This is also reported
+//! for transports that do not serialize, like the memory transport.
+//!
+//! The counters can be read with [Metrics::snapshot] and exported to whatever
+//! metrics system the application uses.
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use futures_lite::Stream;
+use futures_sink::Sink;
+use pin_project::pin_project;
+use serde::Serialize;
+
+use super::{ConnectionErrors, Connector, Listener, LocalAddr, StreamTypes};
+
+/// Counters of an instrumented connector or listener
+#[derive(Debug, Default)]
+pub struct Metrics {
+    streams: AtomicU64,
+    open_errors: AtomicU64,
+    messages_sent: AtomicU64,
+    messages_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    send_errors: AtomicU64,
+    recv_errors: AtomicU64,
+}
+
+impl Metrics {
+    /// A snapshot of the current values
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            streams: self.streams.load(Ordering::Relaxed),
+            open_errors: self.open_errors.load(Ordering::Relaxed),
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            send_errors: self.send_errors.load(Ordering::Relaxed),
+            recv_errors: self.recv_errors.load(Ordering::Relaxed),
+        }
+    }
+
+    fn inc(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+}
+
+/// A snapshot of [Metrics]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// Number of substreams that were opened or accepted
+    pub streams: u64,
+    /// Number of errors when opening or accepting a substream
+    pub open_errors: u64,
+    /// Number of messages sent
+    pub messages_sent: u64,
+    /// Number of messages received
+    pub messages_received: u64,
+    /// Size of the frames of all sent messages
+    pub bytes_sent: u64,
+    /// Size of the frames of all received messages
+    pub bytes_received: u64,
+    /// Number of errors when sending a message
+    pub send_errors: u64,
+    /// Number of errors when receiving a message
+    pub recv_errors: u64,
+}
+
+/// Size of the length prefixed postcard frame of a message
+fn frame_size<T: Serialize>(msg: &T) -> u64 {
+    postcard::experimental::serialized_size(msg)
+        .map(|size| size as u64 + 4)
+        .unwrap_or_default()
+}
+
+/// A send sink that counts sent messages
+#[pin_project]
+#[derive(Debug)]
+pub struct SendSink<S> {
+    #[pin]
+    inner: S,
+    metrics: Arc<Metrics>,
+}
+
+/// Count a failed poll of a send sink
+fn count_send_error<E>(metrics: &Metrics, res: Poll<Result<(), E>>) -> Poll<Result<(), E>> {
+    if let Poll::Ready(Err(_)) = &res {
+        Metrics::inc(&metrics.send_errors, 1);
+    }
+    res
+}
+
+impl<S: Sink<T>, T: Serialize> Sink<T> for SendSink<S> {
+    type Error = S::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        count_send_error(this.metrics, this.inner.poll_ready(cx))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        let this = self.project();
+        let size = frame_size(&item);
+        match this.inner.start_send(item) {
+            Ok(()) => {
+                Metrics::inc(&this.metrics.messages_sent, 1);
+                Metrics::inc(&this.metrics.bytes_sent, size);
+                Ok(())
+            }
+            Err(cause) => {
+                Metrics::inc(&this.metrics.send_errors, 1);
+                Err(cause)
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        count_send_error(this.metrics, this.inner.poll_flush(cx))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        count_send_error(this.metrics, this.inner.poll_close(cx))
+    }
+}
+
+/// A receive stream that counts received messages
+#[pin_project]
+#[derive(Debug)]
+pub struct RecvStream<S> {
+    #[pin]
+    inner: S,
+    metrics: Arc<Metrics>,
+}
+
+impl<S: Stream<Item = Result<T, E>>, T: Serialize, E> Stream for RecvStream<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let res = this.inner.poll_next(cx);
+        match &res {
+            Poll::Ready(Some(Ok(item))) => {
+                Metrics::inc(&this.metrics.messages_received, 1);
+                Metrics::inc(&this.metrics.bytes_received, frame_size(item));
+            }
+            Poll::Ready(Some(Err(_))) => Metrics::inc(&this.metrics.recv_errors, 1),
+            _ => {}
+        }
+        res
+    }
+}
+
+/// Count an opened or accepted substream, or the error
+fn count<T, E>(metrics: &Metrics, res: Result<T, E>) -> Result<T, E> {
+    match &res {
+        Ok(_) => Metrics::inc(&metrics.streams, 1),
+        Err(_) => Metrics::inc(&metrics.open_errors, 1),
+    }
+    res
+}
+
+fn wrap<S, R, E>(
+    metrics: &Arc<Metrics>,
+    res: Result<(S, R), E>,
+) -> Result<(SendSink<S>, RecvStream<R>), E> {
+    let (send, recv) = count(metrics, res)?;
+    let recv = RecvStream {
+        inner: recv,
+        metrics: metrics.clone(),
+    };
+    Ok((wrap_send(metrics, send), recv))
+}
+
+fn wrap_send<S>(metrics: &Arc<Metrics>, send: S) -> SendSink<S> {
+    SendSink {
+        inner: send,
+        metrics: metrics.clone(),
+    }
+}
+
+/// Count a datagram sent on the inner connector
+fn count_datagram<T: Serialize, E>(
+    metrics: &Metrics,
+    size: u64,
+    res: Result<Option<T>, E>,
+) -> Result<Option<T>, E> {
+    match &res {
+        Ok(None) => {
+            Metrics::inc(&metrics.messages_sent, 1);
+            Metrics::inc(&metrics.bytes_sent, size);
+        }
+        // not sent as a datagram, so it is counted on the channel it is sent on
+        Ok(Some(_)) => {}
+        Err(_) => Metrics::inc(&metrics.send_errors, 1),
+    }
+    res
+}
+
+/// A connector that counts substreams and messages
+///
+/// Clones share the metrics.
+#[derive(Debug, Clone)]
+pub struct InstrumentedConnector<C> {
+    inner: C,
+    metrics: Arc<Metrics>,
+}
+
+impl<C> InstrumentedConnector<C> {
+    /// Wrap a connector, with new metrics
+    pub fn new(inner: C) -> Self {
+        Self::with_metrics(inner, Default::default())
+    }
+
+    /// Wrap a connector, counting into existing metrics
+    pub fn with_metrics(inner: C, metrics: Arc<Metrics>) -> Self {
+        Self { inner, metrics }
+    }
+
+    /// The metrics of this connector
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+}
+
+impl<C: ConnectionErrors> ConnectionErrors for InstrumentedConnector<C> {
+    type SendError = C::SendError;
+    type RecvError = C::RecvError;
+    type OpenError = C::OpenError;
+    type AcceptError = C::AcceptError;
+}
+
+impl<C: StreamTypes> StreamTypes for InstrumentedConnector<C> {
+    type In = C::In;
+    type Out = C::Out;
+    type RecvStream = RecvStream<C::RecvStream>;
+    type SendSink = SendSink<C::SendSink>;
+}
+
+impl<C: Connector> Connector for InstrumentedConnector<C> {
+    async fn open(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        wrap(&self.metrics, self.inner.open().await)
+    }
+
+    async fn open_with_priority(
+        &self,
+        priority: i32,
+    ) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        wrap(&self.metrics, self.inner.open_with_priority(priority).await)
+    }
+
+    async fn open_uni(&self) -> Result<Self::SendSink, Self::OpenError> {
+        let send = count(&self.metrics, self.inner.open_uni().await)?;
+        Ok(wrap_send(&self.metrics, send))
+    }
+
+    async fn send_datagram(&self, msg: Self::Out) -> Result<Option<Self::Out>, Self::SendError> {
+        let size = frame_size(&msg);
+        count_datagram(&self.metrics, size, self.inner.send_datagram(msg).await)
+    }
+}
+
+/// A listener that counts substreams and messages
+///
+/// Clones share the metrics.
+#[derive(Debug, Clone)]
+pub struct InstrumentedListener<C> {
+    inner: C,
+    metrics: Arc<Metrics>,
+}
+
+impl<C> InstrumentedListener<C> {
+    /// Wrap a listener, with new metrics
+    pub fn new(inner: C) -> Self {
+        Self::with_metrics(inner, Default::default())
+    }
+
+    /// Wrap a listener, counting into existing metrics
+    pub fn with_metrics(inner: C, metrics: Arc<Metrics>) -> Self {
+        Self { inner, metrics }
+    }
+
+    /// The metrics of this listener
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+}
+
+impl<C: ConnectionErrors> ConnectionErrors for InstrumentedListener<C> {
+    type SendError = C::SendError;
+    type RecvError = C::RecvError;
+    type OpenError = C::OpenError;
+    type AcceptError = C::AcceptError;
+}
+
+impl<C: StreamTypes> StreamTypes for InstrumentedListener<C> {
+    type In = C::In;
+    type Out = C::Out;
+    type RecvStream = RecvStream<C::RecvStream>;
+    type SendSink = SendSink<C::SendSink>;
+}
+
+impl<C: Listener> Listener for InstrumentedListener<C> {
+    async fn accept(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::AcceptError> {
+        wrap(&self.metrics, self.inner.accept().await)
+    }
+
+    fn local_addr(&self) -> &[LocalAddr] {
+        self.inner.local_addr()
+    }
+
+    async fn accept_uni(&self) -> Result<Self::RecvStream, Self::AcceptError> {
+        let recv = count(&self.metrics, self.inner.accept_uni().await)?;
+        Ok(RecvStream {
+            inner: recv,
+            metrics: self.metrics.clone(),
+        })
+    }
+
+    async fn recv_datagram(&self) -> Result<Self::In, Self::RecvError> {
+        let res = self.inner.recv_datagram().await;
+        match &res {
+            Ok(msg) => {
+                Metrics::inc(&self.metrics.messages_received, 1);
+                Metrics::inc(&self.metrics.bytes_received, frame_size(msg));
+            }
+            Err(_) => Metrics::inc(&self.metrics.recv_errors, 1),
+        }
+        res
+    }
+}
+
+#[cfg(all(test, feature = "flume-transport"))]
+mod tests {
+    use futures_lite::StreamExt;
+    use futures_util::SinkExt;
+
+    use super::*;
+    use crate::transport::flume;
+
+    #[tokio::test]
+    async fn counts() -> anyhow::Result<()> {
+        let (listener, connector) = flume::channel::<u64, u64>(1);
+        let connector = InstrumentedConnector::new(connector);
+        let listener = InstrumentedListener::new(listener);
+        let (mut send, mut recv) = connector.open().await?;
+        let (mut server_send, mut server_recv) = listener.accept().await?;
+        send.send(300).await?;
+        assert_eq!(server_recv.next().await.transpose()?, Some(300));
+        server_send.send(1).await?;
+        assert_eq!(recv.next().await.transpose()?, Some(1));
+
+        // 300 is a two byte varint, plus the length prefix
+        assert_eq!(
+            connector.metrics().snapshot(),
+            MetricsSnapshot {
+                streams: 1,
+                messages_sent: 1,
+                bytes_sent: 6,
+                messages_received: 1,
+                bytes_received: 5,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            listener.metrics().snapshot(),
+            MetricsSnapshot {
+                streams: 1,
+                messages_sent: 1,
+                bytes_sent: 5,
+                messages_received: 1,
+                bytes_received: 6,
+                ..Default::default()
+            }
+        );
+
+        // the remote side is gone
+        drop((server_send, server_recv, listener));
+        assert!(send.send(2).await.is_err());
+        assert!(connector.open().await.is_err());
+        let snapshot = connector.metrics().snapshot();
+        assert_eq!(snapshot.send_errors, 1);
+        assert_eq!(snapshot.open_errors, 1);
+        Ok(())
+    }
+}
@@ -11,35 +11,48 @@ use std::{
     pin::{pin, Pin},
     sync::Arc,
     task::{Context, Poll},
+    time::Duration,
 };
 
 use flume::TryRecvError;
-use futures_lite::Stream;
+use futures_lite::{Stream, StreamExt};
 use futures_sink::Sink;
-use iroh::{NodeAddr, NodeId};
+use iroh::{endpoint::ConnectionType, NodeAddr, NodeId};
 use pin_project::pin_project;
 use quinn::Connection;
 use serde::{de::DeserializeOwned, Serialize};
-use tokio::{sync::oneshot, task::yield_now};
+use tokio::{
+    sync::{oneshot, watch, Notify},
+    time::Instant,
+};
+use tokio_util::task::AbortOnDropHandle;
 use tracing::{debug_span, Instrument};
 
 use super::{
-    util::{FramedPostcardRead, FramedPostcardWrite},
+    stream_limit::{self, Accepted, Incoming, StreamLimit, StreamPermit},
+    util::{
+        self, FrameConfig, FramedCodecRead, FramedCodecWrite, OpenRequest, UniReceivers, UniSenders,
+    },
     StreamTypes,
 };
 use crate::{
-    transport::{ConnectionErrors, Connector, Listener, LocalAddr},
+    codec::{Codec, Framing, PostcardCodec},
+    transport::{
+        ConnectionErrors, ConnectionEvent, ConnectionEvents, Connector, Listener, LocalAddr,
+        PeerIdentity, PeerInfo,
+    },
     RpcMessage,
 };
 
-const MAX_FRAME_LENGTH: usize = 1024 * 1024 * 16;
-
 #[derive(Debug)]
 struct ListenerInner {
     endpoint: Option<iroh::Endpoint>,
     task: Option<tokio::task::JoinHandle<()>>,
     local_addr: Vec<LocalAddr>,
-    receiver: flume::Receiver<SocketInner>,
+    receiver: Incoming,
+    /// Datagrams and uni substreams received on any connection, if the listener
+    /// handles connections
+    uni: Option<UniReceivers>,
 }
 
 impl Drop for ListenerInner {
@@ -79,31 +92,43 @@ pub enum AccessControl {
 
 /// A server endpoint using a quinn connection
 #[derive(Debug)]
-pub struct IrohListener<In: RpcMessage, Out: RpcMessage> {
+pub struct IrohListener<In: RpcMessage, Out: RpcMessage, C: Codec = PostcardCodec> {
     inner: Arc<ListenerInner>,
-    _p: PhantomData<(In, Out)>,
+    frames: FrameConfig,
+    _p: PhantomData<(In, Out, C)>,
 }
 
 impl<In: RpcMessage, Out: RpcMessage> IrohListener<In, Out> {
     /// handles RPC requests from a connection
     ///
     /// to cleanly shut down the handler, drop the receiver side of the sender.
-    async fn connection_handler(connection: quinn::Connection, sender: flume::Sender<SocketInner>) {
+    async fn connection_handler(
+        connection: quinn::Connection,
+        sender: flume::Sender<Accepted>,
+        limit: Option<StreamLimit>,
+    ) {
+        let limit = stream_limit::semaphore(limit);
+        let peer = peer_identity(&connection);
         loop {
             tracing::debug!("Awaiting incoming bidi substream on existing connection...");
-            let bidi_stream = match connection.accept_bi().await {
-                Ok(bidi_stream) => bidi_stream,
-                Err(quinn::ConnectionError::ApplicationClosed(e)) => {
-                    tracing::debug!(?e, "Peer closed the connection");
-                    break;
-                }
-                Err(e) => {
-                    tracing::debug!(?e, "Error accepting stream");
-                    break;
-                }
-            };
+            let (bidi_stream, permit) =
+                match stream_limit::accept_bi(&connection, limit.as_ref()).await {
+                    Ok(bidi_stream) => bidi_stream,
+                    Err(quinn::ConnectionError::ApplicationClosed(e)) => {
+                        tracing::debug!(?e, "Peer closed the connection");
+                        break;
+                    }
+                    Err(e) => {
+                        tracing::debug!(?e, "Error accepting stream");
+                        break;
+                    }
+                };
             tracing::debug!("Sending substream to be handled... {}", bidi_stream.0.id());
-            if sender.send_async(bidi_stream).await.is_err() {
+            if sender
+                .send_async((bidi_stream, permit, peer.clone()))
+                .await
+                .is_err()
+            {
                 tracing::debug!("Receiver dropped");
                 break;
             }
@@ -112,8 +137,10 @@ impl<In: RpcMessage, Out: RpcMessage> IrohListener<In, Out> {
 
     async fn endpoint_handler(
         endpoint: iroh::Endpoint,
-        sender: flume::Sender<SocketInner>,
+        sender: flume::Sender<Accepted>,
+        uni: UniSenders,
         allowed_node_ids: BTreeSet<NodeId>,
+        limit: Option<StreamLimit>,
     ) {
         loop {
             tracing::debug!("Waiting for incoming connection...");
@@ -161,7 +188,8 @@ impl<In: RpcMessage, Out: RpcMessage> IrohListener<In, Out> {
             );
 
             tracing::debug!("Spawning connection handler...");
-            tokio::spawn(Self::connection_handler(connection, sender.clone()));
+            uni.spawn(&connection, peer_identity(&connection));
+            tokio::spawn(Self::connection_handler(connection, sender.clone(), limit));
         }
     }
 
@@ -180,6 +208,47 @@ impl<In: RpcMessage, Out: RpcMessage> IrohListener<In, Out> {
     pub fn new_with_access_control(
         endpoint: iroh::Endpoint,
         access_control: AccessControl,
+    ) -> io::Result<Self> {
+        Self::new_inner(endpoint, access_control, None)
+    }
+
+    /// Create a new server channel that accepts connections for any of the given ALPNs
+    ///
+    /// This replaces the ALPNs the endpoint was built with, so one endpoint can
+    /// serve several versions of a protocol, e.g. `quic-rpc/v1` and `quic-rpc/v2`.
+    /// The ALPN a client connected with is part of the [PeerIdentity] of the
+    /// accepted channels, see [RecvStream::alpn], so handlers can route by it.
+    pub fn new_with_alpns(
+        endpoint: iroh::Endpoint,
+        alpns: Vec<Vec<u8>>,
+        access_control: AccessControl,
+    ) -> io::Result<Self> {
+        if alpns.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "at least one ALPN is required",
+            ));
+        }
+        endpoint.set_alpns(alpns).map_err(io::Error::other)?;
+        Self::new_inner(endpoint, access_control, None)
+    }
+
+    /// Create a new server endpoint, with specified access control and a limit on
+    /// the concurrently accepted substreams per connection
+    ///
+    /// See [StreamLimit] for details.
+    pub fn new_with_stream_limit(
+        endpoint: iroh::Endpoint,
+        access_control: AccessControl,
+        limit: StreamLimit,
+    ) -> io::Result<Self> {
+        Self::new_inner(endpoint, access_control, Some(limit))
+    }
+
+    fn new_inner(
+        endpoint: iroh::Endpoint,
+        access_control: AccessControl,
+        limit: Option<StreamLimit>,
     ) -> io::Result<Self> {
         let allowed_node_ids = match access_control {
             AccessControl::Unrestricted => BTreeSet::new(),
@@ -194,10 +263,13 @@ impl<In: RpcMessage, Out: RpcMessage> IrohListener<In, Out> {
 
         let (ipv4_socket_addr, maybe_ipv6_socket_addr) = endpoint.bound_sockets();
         let (sender, receiver) = flume::bounded(16);
+        let (uni, uni_rx) = util::uni_channels();
         let task = tokio::spawn(Self::endpoint_handler(
             endpoint.clone(),
             sender,
+            uni,
             allowed_node_ids,
+            limit,
         ));
 
         Ok(Self {
@@ -207,8 +279,10 @@ impl<In: RpcMessage, Out: RpcMessage> IrohListener<In, Out> {
                 local_addr: once(LocalAddr::Socket(ipv4_socket_addr))
                     .chain(maybe_ipv6_socket_addr.map(LocalAddr::Socket))
                     .collect(),
-                receiver,
+                receiver: Incoming::Accepted(receiver),
+                uni: Some(uni_rx),
             }),
+            frames: FrameConfig::default(),
             _p: PhantomData,
         })
     }
@@ -220,21 +294,78 @@ impl<In: RpcMessage, Out: RpcMessage> IrohListener<In, Out> {
     pub fn handle_connections(
         incoming: flume::Receiver<quinn::Connection>,
         local_addr: SocketAddr,
+    ) -> Self {
+        Self::handle_connections_inner(incoming, vec![LocalAddr::Socket(local_addr)], None)
+    }
+
+    /// Create a new server channel, given just a source of incoming connections,
+    /// with a limit on the concurrently accepted substreams per connection
+    pub fn handle_connections_with_stream_limit(
+        incoming: flume::Receiver<quinn::Connection>,
+        local_addr: SocketAddr,
+        limit: StreamLimit,
+    ) -> Self {
+        Self::handle_connections_inner(incoming, vec![LocalAddr::Socket(local_addr)], Some(limit))
+    }
+
+    /// Create a new server channel, and a protocol handler for an iroh [Router]
+    ///
+    /// Register the returned handler with [RouterBuilder::accept] for the ALPN of
+    /// the service. All connections the router accepts for that ALPN are then served
+    /// by this listener. The router decides which nodes may connect, there is no
+    /// [AccessControl] for connections passed in by the handler.
+    ///
+    /// [Router]: iroh::protocol::Router
+    /// [RouterBuilder::accept]: iroh::protocol::RouterBuilder::accept
+    pub fn protocol_handler(endpoint: &iroh::Endpoint) -> (Self, IrohProtocol) {
+        Self::protocol_handler_inner(endpoint, None)
+    }
+
+    /// Create a new server channel, and a protocol handler for an iroh router, with
+    /// a limit on the concurrently accepted substreams per connection
+    pub fn protocol_handler_with_stream_limit(
+        endpoint: &iroh::Endpoint,
+        limit: StreamLimit,
+    ) -> (Self, IrohProtocol) {
+        Self::protocol_handler_inner(endpoint, Some(limit))
+    }
+
+    fn protocol_handler_inner(
+        endpoint: &iroh::Endpoint,
+        limit: Option<StreamLimit>,
+    ) -> (Self, IrohProtocol) {
+        let (ipv4_socket_addr, maybe_ipv6_socket_addr) = endpoint.bound_sockets();
+        let local_addr = once(LocalAddr::Socket(ipv4_socket_addr))
+            .chain(maybe_ipv6_socket_addr.map(LocalAddr::Socket))
+            .collect();
+        let (sender, incoming) = flume::bounded(16);
+        let listener = Self::handle_connections_inner(incoming, local_addr, limit);
+        (listener, IrohProtocol { sender })
+    }
+
+    fn handle_connections_inner(
+        incoming: flume::Receiver<quinn::Connection>,
+        local_addr: Vec<LocalAddr>,
+        limit: Option<StreamLimit>,
     ) -> Self {
         let (sender, receiver) = flume::bounded(16);
+        let (uni, uni_rx) = util::uni_channels();
         let task = tokio::spawn(async move {
             // just grab all connections and spawn a handler for each one
             while let Ok(connection) = incoming.recv_async().await {
-                tokio::spawn(Self::connection_handler(connection, sender.clone()));
+                uni.spawn(&connection, peer_identity(&connection));
+                tokio::spawn(Self::connection_handler(connection, sender.clone(), limit));
             }
         });
         Self {
             inner: Arc::new(ListenerInner {
                 endpoint: None,
                 task: Some(task),
-                local_addr: vec![LocalAddr::Socket(local_addr)],
-                receiver,
+                local_addr,
+                receiver: Incoming::Accepted(receiver),
+                uni: Some(uni_rx),
             }),
+            frames: FrameConfig::default(),
             _p: PhantomData,
         }
     }
@@ -252,63 +383,159 @@ impl<In: RpcMessage, Out: RpcMessage> IrohListener<In, Out> {
                 endpoint: None,
                 task: None,
                 local_addr: vec![LocalAddr::Socket(local_addr)],
-                receiver,
+                receiver: Incoming::External(receiver),
+                uni: None,
             }),
+            frames: FrameConfig::default(),
+            _p: PhantomData,
+        }
+    }
+}
+
+/// An iroh protocol handler that passes connections to an [IrohListener]
+///
+/// Created with [IrohListener::protocol_handler].
+#[derive(Debug, Clone)]
+pub struct IrohProtocol {
+    sender: flume::Sender<quinn::Connection>,
+}
+
+impl iroh::protocol::ProtocolHandler for IrohProtocol {
+    fn accept(
+        self: Arc<Self>,
+        conn: iroh::endpoint::Connecting,
+    ) -> futures_lite::future::Boxed<anyhow::Result<()>> {
+        Box::pin(async move {
+            let connection = conn.await?;
+            self.sender
+                .send_async(connection)
+                .await
+                .map_err(|_| anyhow::anyhow!("listener dropped"))?;
+            Ok(())
+        })
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage, C: Codec> IrohListener<In, Out, C> {
+    /// Set the maximum length of encoded messages that are sent
+    ///
+    /// Sending a larger message fails with [MessageTooLarge](crate::codec::MessageTooLarge). The default is 16 MiB.
+    pub fn max_send_frame_length(mut self, value: usize) -> Self {
+        self.frames.send = value;
+        self
+    }
+
+    /// Set the maximum length of encoded messages that are received
+    ///
+    /// Receiving a larger message fails with [MessageTooLarge](crate::codec::MessageTooLarge), and ends the
+    /// substream. The default is 16 MiB.
+    pub fn max_recv_frame_length(mut self, value: usize) -> Self {
+        self.frames.recv = value;
+        self
+    }
+
+    /// Set how messages are delimited on the substreams
+    ///
+    /// The connectors have to use the same framing.
+    pub fn framing(mut self, value: Framing) -> Self {
+        self.frames.framing = value;
+        self
+    }
+
+    /// Use a different [Codec] for the messages of this listener
+    ///
+    /// The connectors have to use the same codec.
+    pub fn with_codec<C2: Codec>(self) -> IrohListener<In, Out, C2> {
+        IrohListener {
+            inner: self.inner,
+            frames: self.frames,
             _p: PhantomData,
         }
     }
 }
 
-impl<In: RpcMessage, Out: RpcMessage> Clone for IrohListener<In, Out> {
+impl<In: RpcMessage, Out: RpcMessage, C: Codec> Clone for IrohListener<In, Out, C> {
     fn clone(&self) -> Self {
         Self {
             inner: self.inner.clone(),
+            frames: self.frames,
             _p: PhantomData,
         }
     }
 }
 
-impl<In: RpcMessage, Out: RpcMessage> ConnectionErrors for IrohListener<In, Out> {
+impl<In: RpcMessage, Out: RpcMessage, C: Codec> ConnectionErrors for IrohListener<In, Out, C> {
     type SendError = io::Error;
     type RecvError = io::Error;
     type OpenError = quinn::ConnectionError;
     type AcceptError = quinn::ConnectionError;
 }
 
-impl<In: RpcMessage, Out: RpcMessage> StreamTypes for IrohListener<In, Out> {
+impl<In: RpcMessage, Out: RpcMessage, C: Codec> StreamTypes for IrohListener<In, Out, C> {
     type In = In;
     type Out = Out;
-    type SendSink = SendSink<Out>;
-    type RecvStream = RecvStream<In>;
+    type SendSink = SendSink<Out, C>;
+    type RecvStream = RecvStream<In, C>;
 }
 
-impl<In: RpcMessage, Out: RpcMessage> Listener for IrohListener<In, Out> {
+impl<In: RpcMessage, Out: RpcMessage, C: Codec> Listener for IrohListener<In, Out, C> {
     async fn accept(&self) -> Result<(Self::SendSink, Self::RecvStream), AcceptError> {
-        let (send, recv) = self
+        let ((send, recv), permit, peer) = self
             .inner
             .receiver
-            .recv_async()
+            .recv()
             .await
             .map_err(|_| quinn::ConnectionError::LocallyClosed)?;
 
-        Ok((SendSink::new(send), RecvStream::new(recv)))
+        Ok((
+            SendSink::with_permit(send, self.frames, permit.clone()),
+            RecvStream::accepted(recv, self.frames, permit, peer),
+        ))
     }
 
     fn local_addr(&self) -> &[LocalAddr] {
         &self.inner.local_addr
     }
+
+    async fn accept_uni(&self) -> Result<Self::RecvStream, AcceptError> {
+        let (recv, peer) = util::recv_uni(self.inner.uni.as_ref()).await?;
+        Ok(RecvStream::accepted(recv, self.frames, None, peer))
+    }
+
+    async fn recv_datagram(&self) -> Result<In, io::Error> {
+        util::recv_datagram::<_, C>(self.inner.uni.as_ref()).await
+    }
 }
 
 type SocketInner = (quinn::SendStream, quinn::RecvStream);
 
+/// Status of the connection of a connector, as seen by its connection handler
+#[derive(Debug, Clone)]
+enum ConnectionStatus {
+    /// Connection attempt number `n` is in progress
+    Connecting(u64),
+    /// A connection is established
+    Connected(Connection),
+    /// Connection attempt number `n` failed
+    Failed(u64, Arc<anyhow::Error>),
+}
+
 #[derive(Debug)]
 struct ClientConnectionInner {
     /// The quinn endpoint, we just keep a clone of this for information
     endpoint: Option<iroh::Endpoint>,
+    /// The node this connector connects to, if known
+    node_id: Option<NodeId>,
+    /// The status of the connection
+    status: watch::Receiver<ConnectionStatus>,
+    /// The most recent lifecycle event of the connection
+    events: watch::Receiver<ConnectionEvent>,
     /// The task that handles creating new connections
     task: Option<tokio::task::JoinHandle<()>>,
     /// The channel to send new received connections
-    requests_tx: flume::Sender<oneshot::Sender<anyhow::Result<SocketInner>>>,
+    requests_tx: flume::Sender<OpenRequest<anyhow::Error>>,
+    /// Makes the connection handler redial right away, even while it backs off
+    redial: Arc<Notify>,
 }
 
 impl Drop for ClientConnectionInner {
@@ -339,17 +566,56 @@ impl Drop for ClientConnectionInner {
     }
 }
 
+/// Delays between connection attempts after an attempt failed
+///
+/// The delay starts at `initial` and doubles with every failed attempt, up to
+/// `max`. Channels that are opened while the connector backs off fail right away
+/// with the error of the last attempt, instead of each one dialing the remote again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    /// Delay after the first failed attempt
+    pub initial: Duration,
+    /// Maximum delay
+    pub max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(10),
+        }
+    }
+}
+
+impl Backoff {
+    /// The delay after `failures` consecutive failed attempts
+    fn delay(&self, failures: u32) -> Duration {
+        let factor = 1u32 << failures.saturating_sub(1).min(31);
+        self.initial.saturating_mul(factor).min(self.max)
+    }
+}
+
 /// A connection using an iroh connection
-pub struct IrohConnector<In: RpcMessage, Out: RpcMessage> {
+///
+/// The connector holds at most one connection to the remote node, which is shared
+/// by all its clones. When the connection is lost, it is only redialed once a
+/// new channel is opened, and failed attempts are retried with a [Backoff].
+pub struct IrohConnector<In: RpcMessage, Out: RpcMessage, C: Codec = PostcardCodec> {
     inner: Arc<ClientConnectionInner>,
-    _p: PhantomData<(In, Out)>,
+    frames: FrameConfig,
+    _p: PhantomData<(In, Out, C)>,
 }
 
 impl<In: RpcMessage, Out: RpcMessage> IrohConnector<In, Out> {
     async fn single_connection_handler(
         connection: quinn::Connection,
-        requests_rx: flume::Receiver<oneshot::Sender<anyhow::Result<SocketInner>>>,
+        requests_rx: flume::Receiver<OpenRequest<anyhow::Error>>,
+        incoming: Option<flume::Sender<Accepted>>,
+        events: watch::Sender<ConnectionEvent>,
     ) {
+        let _accept = incoming.map(|incoming| accept_substreams(connection.clone(), incoming));
+        let _watch = watch_connection(connection.clone(), None, events);
         loop {
             tracing::debug!("Awaiting request for new bidi substream...");
             let Ok(request_tx) = requests_rx.recv_async().await else {
@@ -357,26 +623,10 @@ impl<In: RpcMessage, Out: RpcMessage> IrohConnector<In, Out> {
                 return;
             };
 
-            tracing::debug!("Got request for new bidi substream");
-            match connection.open_bi().await {
-                Ok(pair) => {
-                    tracing::debug!("Bidi substream opened");
-                    if request_tx.send(Ok(pair)).is_err() {
-                        tracing::debug!("requester dropped");
-                    }
-                }
-                Err(e) => {
-                    tracing::warn!(?e, "error opening bidi substream");
-                    if request_tx
-                        .send(anyhow::Context::context(
-                            Err(e),
-                            "error opening bidi substream",
-                        ))
-                        .is_err()
-                    {
-                        tracing::debug!("requester dropped");
-                    }
-                }
+            tracing::debug!("Got request for new substream");
+            if let Err((request_tx, e)) = request_tx.open(&connection).await {
+                tracing::warn!(?e, "error opening substream");
+                request_tx.fail(anyhow::Error::new(e).context("error opening substream"));
             }
         }
     }
@@ -387,20 +637,25 @@ impl<In: RpcMessage, Out: RpcMessage> IrohConnector<In, Out> {
     /// All other errors are logged and handled internally.
     /// It will try to keep a connection open at all times.
     async fn reconnect_handler_inner(
-        endpoint: iroh::Endpoint,
-        node_addr: NodeAddr,
-        alpn: Vec<u8>,
-        requests_rx: flume::Receiver<oneshot::Sender<anyhow::Result<SocketInner>>>,
+        reconnect: ReconnectHandler,
+        requests_rx: flume::Receiver<OpenRequest<anyhow::Error>>,
+        incoming: Option<flume::Sender<Accepted>>,
+        status: watch::Sender<ConnectionStatus>,
+        events: watch::Sender<ConnectionEvent>,
+        redial: Arc<Notify>,
     ) {
-        let mut reconnect = pin!(ReconnectHandler {
-            endpoint,
-            state: ConnectionState::NotConnected,
-            node_addr,
-            alpn,
-        });
+        let backoff = reconnect.backoff;
+        let mut reconnect = pin!(reconnect);
 
-        let mut pending_request: Option<oneshot::Sender<anyhow::Result<SocketInner>>> = None;
+        let mut pending_request: Option<OpenRequest<anyhow::Error>> = None;
+        let mut attempt = 0;
         let mut connection: Option<Connection> = None;
+        let mut _accept = None;
+        let mut _watch = None;
+        // consecutive failed attempts, and when the next attempt may start
+        let mut failures = 0;
+        let mut retry_at = None;
+        let mut last_err: Option<Arc<anyhow::Error>> = None;
 
         loop {
             // First we check if there is already a request ready in the channel
@@ -418,25 +673,67 @@ impl<In: RpcMessage, Out: RpcMessage> IrohConnector<In, Out> {
                 };
             }
 
+            // After a failed attempt, we only redial once a request needs the connection
+            if let (false, Some(at)) = (reconnect.connected(), retry_at) {
+                let Some(request) = pending_request.take() else {
+                    tokio::select! {
+                        req = requests_rx.recv_async() => match req {
+                            Ok(req) => pending_request = Some(req),
+                            Err(_) => {
+                                tracing::debug!("client dropped");
+                                break;
+                            }
+                        },
+                        _ = redial.notified() => retry_at = None,
+                    }
+                    continue;
+                };
+                if Instant::now() < at {
+                    // fail fast instead of having every request dial the remote
+                    let e = last_err.as_ref().expect("set on failure");
+                    request.fail(anyhow::anyhow!("{e:#}"));
+                    continue;
+                }
+                pending_request = Some(request);
+            }
+
             // If not connected, we attempt to establish a connection
             if !reconnect.connected() {
                 tracing::trace!("tick: connection result");
+                attempt += 1;
+                status.send_replace(ConnectionStatus::Connecting(attempt));
+                events.send_replace(ConnectionEvent::Connecting);
                 match reconnect.as_mut().await {
                     Ok(new_connection) => {
+                        failures = 0;
+                        retry_at = None;
+                        last_err = None;
+                        status.send_replace(ConnectionStatus::Connected(new_connection.clone()));
+                        events.send_replace(ConnectionEvent::Connected);
+                        _watch = Some(watch_connection(
+                            new_connection.clone(),
+                            Some((reconnect.endpoint.clone(), reconnect.node_addr.node_id)),
+                            events.clone(),
+                        ));
+                        if let Some(incoming) = &incoming {
+                            _accept =
+                                Some(accept_substreams(new_connection.clone(), incoming.clone()));
+                        }
                         connection = Some(new_connection);
                     }
                     Err(e) => {
+                        let e = Arc::new(e);
+                        failures += 1;
+                        let delay = backoff.delay(failures);
+                        tracing::debug!(?delay, "connection attempt {attempt} failed: {e:#}");
+                        retry_at = Some(Instant::now() + delay);
+                        last_err = Some(e.clone());
+                        status.send_replace(ConnectionStatus::Failed(attempt, e.clone()));
+                        events.send_replace(ConnectionEvent::Disconnected(e.clone()));
                         // If there was a pending request, we error it out as we're not connected
                         if let Some(request_ack_tx) = pending_request.take() {
-                            if request_ack_tx.send(Err(e)).is_err() {
-                                tracing::debug!("requester dropped");
-                            }
+                            request_ack_tx.fail(anyhow::anyhow!("{e:#}"));
                         }
-
-                        // Yielding back to the runtime, otherwise this can run on a busy loop
-                        // due to the always ready nature of things, messing up with single thread
-                        // runtime flavor of tokio
-                        yield_now().await;
                     }
                 }
                 // If we didn't have a ready request in the channel, we wait for one
@@ -456,22 +753,14 @@ impl<In: RpcMessage, Out: RpcMessage> IrohConnector<In, Out> {
             // If we have a connection and a pending request, we good, just process it
             if let Some(connection) = connection.as_mut() {
                 if let Some(request) = pending_request.take() {
-                    match connection.open_bi().await {
-                        Ok(pair) => {
-                            tracing::debug!("Bidi substream opened");
-                            if request.send(Ok(pair)).is_err() {
-                                tracing::debug!("requester dropped");
-                            }
-                        }
-                        Err(e) => {
-                            tracing::warn!(?e, "error opening bidi substream");
-                            tracing::warn!("recreating connection");
-                            // NOTE: the connection might be stale, so we recreate the
-                            // connection and set the request as pending instead of
-                            // sending the error as a response
-                            reconnect.set_not_connected();
-                            pending_request = Some(request);
-                        }
+                    if let Err((request, e)) = request.open(connection).await {
+                        tracing::warn!(?e, "error opening substream");
+                        tracing::warn!("recreating connection");
+                        // NOTE: the connection might be stale, so we recreate the
+                        // connection and set the request as pending instead of
+                        // sending the error as a response
+                        reconnect.set_not_connected();
+                        pending_request = Some(request);
                     }
                 }
             }
@@ -479,47 +768,334 @@ impl<In: RpcMessage, Out: RpcMessage> IrohConnector<In, Out> {
     }
 
     async fn reconnect_handler(
-        endpoint: iroh::Endpoint,
-        addr: NodeAddr,
-        alpn: Vec<u8>,
-        requests_rx: flume::Receiver<oneshot::Sender<anyhow::Result<SocketInner>>>,
+        reconnect: ReconnectHandler,
+        requests_rx: flume::Receiver<OpenRequest<anyhow::Error>>,
+        incoming: Option<flume::Sender<Accepted>>,
+        status: watch::Sender<ConnectionStatus>,
+        events: watch::Sender<ConnectionEvent>,
+        redial: Arc<Notify>,
     ) {
-        Self::reconnect_handler_inner(endpoint, addr, alpn, requests_rx).await;
+        Self::reconnect_handler_inner(reconnect, requests_rx, incoming, status, events, redial)
+            .await;
         tracing::info!("Reconnect handler finished");
     }
 
     /// Create a new channel
     pub fn from_connection(connection: quinn::Connection) -> Self {
+        Self::from_connection_inner(connection, None)
+    }
+
+    /// Create a new channel, and a listener for substreams opened by the remote
+    ///
+    /// The listener accepts the substreams that the remote opens on the same
+    /// connection, so the remote can call a service on this side without a
+    /// separate endpoint. The message types of the listener are independent of
+    /// the message types of the connector.
+    ///
+    /// Substreams opened by the remote are queued until they are accepted. The
+    /// listener stops accepting substreams when the connector is dropped.
+    pub fn from_connection_with_listener<In2: RpcMessage, Out2: RpcMessage>(
+        connection: quinn::Connection,
+    ) -> (Self, IrohListener<In2, Out2>) {
+        let (sender, receiver) = flume::bounded(16);
+        let connector = Self::from_connection_inner(connection, Some(sender));
+        (connector, reverse_listener(receiver, Vec::new()))
+    }
+
+    fn from_connection_inner(
+        connection: quinn::Connection,
+        incoming: Option<flume::Sender<Accepted>>,
+    ) -> Self {
         let (requests_tx, requests_rx) = flume::bounded(16);
-        let task = tokio::spawn(Self::single_connection_handler(connection, requests_rx));
+        let node_id = iroh::endpoint::get_remote_node_id(&connection).ok();
+        let (_, status) = watch::channel(ConnectionStatus::Connected(connection.clone()));
+        let (events_tx, events) = watch::channel(ConnectionEvent::Connected);
+        let task = tokio::spawn(Self::single_connection_handler(
+            connection,
+            requests_rx,
+            incoming,
+            events_tx,
+        ));
         Self {
             inner: Arc::new(ClientConnectionInner {
                 endpoint: None,
+                node_id,
+                status,
+                events,
                 task: Some(task),
                 requests_tx,
+                redial: Default::default(),
             }),
+            frames: FrameConfig::default(),
             _p: PhantomData,
         }
     }
 
     /// Create a new channel
     pub fn new(endpoint: iroh::Endpoint, node_addr: impl Into<NodeAddr>, alpn: Vec<u8>) -> Self {
-        let (requests_tx, requests_rx) = flume::bounded(16);
-        let task = tokio::spawn(Self::reconnect_handler(
-            endpoint.clone(),
+        Self::new_inner(endpoint, node_addr.into(), alpn, None, Backoff::default())
+    }
+
+    /// Create a new channel that backs off between failed connection attempts
+    /// as configured, instead of using the [Backoff::default]
+    pub fn new_with_backoff(
+        endpoint: iroh::Endpoint,
+        node_addr: impl Into<NodeAddr>,
+        alpn: Vec<u8>,
+        backoff: Backoff,
+    ) -> Self {
+        Self::new_inner(endpoint, node_addr.into(), alpn, None, backoff)
+    }
+
+    /// Create a new channel, and a listener for substreams opened by the remote
+    ///
+    /// Like [IrohConnector::from_connection_with_listener], but the listener
+    /// follows reconnects. Substreams are accepted on whatever connection is
+    /// currently established.
+    pub fn new_with_listener<In2: RpcMessage, Out2: RpcMessage>(
+        endpoint: iroh::Endpoint,
+        node_addr: impl Into<NodeAddr>,
+        alpn: Vec<u8>,
+    ) -> (Self, IrohListener<In2, Out2>) {
+        let (ipv4_socket_addr, maybe_ipv6_socket_addr) = endpoint.bound_sockets();
+        let local_addr = once(LocalAddr::Socket(ipv4_socket_addr))
+            .chain(maybe_ipv6_socket_addr.map(LocalAddr::Socket))
+            .collect();
+        let (sender, receiver) = flume::bounded(16);
+        let connector = Self::new_inner(
+            endpoint,
             node_addr.into(),
             alpn,
+            Some(sender),
+            Backoff::default(),
+        );
+        (connector, reverse_listener(receiver, local_addr))
+    }
+
+    fn new_inner(
+        endpoint: iroh::Endpoint,
+        node_addr: NodeAddr,
+        alpn: Vec<u8>,
+        incoming: Option<flume::Sender<Accepted>>,
+        backoff: Backoff,
+    ) -> Self {
+        let (requests_tx, requests_rx) = flume::bounded(16);
+        let node_id = Some(node_addr.node_id);
+        let (status_tx, status) = watch::channel(ConnectionStatus::Connecting(0));
+        let (events_tx, events) = watch::channel(ConnectionEvent::Connecting);
+        let redial = Arc::new(Notify::new());
+        let task = tokio::spawn(Self::reconnect_handler(
+            ReconnectHandler {
+                endpoint: endpoint.clone(),
+                state: ConnectionState::NotConnected,
+                node_addr,
+                alpn,
+                backoff,
+            },
             requests_rx,
+            incoming,
+            status_tx,
+            events_tx,
+            redial.clone(),
         ));
         Self {
             inner: Arc::new(ClientConnectionInner {
                 endpoint: Some(endpoint),
+                node_id,
+                status,
+                events,
                 task: Some(task),
                 requests_tx,
+                redial,
             }),
+            frames: FrameConfig::default(),
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage, C: Codec> IrohConnector<In, Out, C> {
+    /// Set the maximum length of encoded messages that are sent
+    ///
+    /// Sending a larger message fails with [MessageTooLarge](crate::codec::MessageTooLarge). The default is 16 MiB.
+    pub fn max_send_frame_length(mut self, value: usize) -> Self {
+        self.frames.send = value;
+        self
+    }
+
+    /// Set the maximum length of encoded messages that are received
+    ///
+    /// Receiving a larger message fails with [MessageTooLarge](crate::codec::MessageTooLarge), and ends the
+    /// substream. The default is 16 MiB.
+    pub fn max_recv_frame_length(mut self, value: usize) -> Self {
+        self.frames.recv = value;
+        self
+    }
+
+    /// Set how messages are delimited on the substreams
+    ///
+    /// The listener has to use the same framing.
+    pub fn framing(mut self, value: Framing) -> Self {
+        self.frames.framing = value;
+        self
+    }
+
+    /// Use a different [Codec] for the messages of this connector
+    ///
+    /// The listener has to use the same codec.
+    pub fn with_codec<C2: Codec>(self) -> IrohConnector<In, Out, C2> {
+        IrohConnector {
+            inner: self.inner,
+            frames: self.frames,
             _p: PhantomData,
         }
     }
+
+    /// Dial the remote node and hold the connection, without opening a substream
+    ///
+    /// Call this at startup so the first RPC does not have to wait for discovery
+    /// and hole punching. `node_addr` can contain more recent addressing information
+    /// than the connector was created with, e.g. direct addresses remembered from a
+    /// previous run, which lets iroh skip discovery. It must be the node this
+    /// connector connects to.
+    ///
+    /// Resolves once a connection is established, or fails with the error of the
+    /// first connection attempt that started after this call. Either way, the
+    /// connector keeps the connection open, or keeps trying to establish it, in the
+    /// background.
+    ///
+    /// iroh does not support 0-RTT for outgoing connections, so the handshake can not
+    /// be skipped. Reconnects reuse the paths iroh already knows about the node.
+    ///
+    /// If the connector is backing off after failed attempts, it redials right away.
+    pub async fn prewarm(&self, node_addr: impl Into<NodeAddr>) -> anyhow::Result<()> {
+        let node_addr = node_addr.into();
+        if let Some(node_id) = self.inner.node_id {
+            anyhow::ensure!(
+                node_addr.node_id == node_id,
+                "connector connects to {node_id}, not {}",
+                node_addr.node_id
+            );
+        }
+        if let Some(endpoint) = &self.inner.endpoint {
+            if !node_addr.info.is_empty() {
+                endpoint.add_node_addr(node_addr)?;
+            }
+        }
+        let mut status = self.inner.status.clone();
+        // attempts up to this one might not have used the new addressing information
+        let started = match &*status.borrow_and_update() {
+            ConnectionStatus::Connected(_) => return Ok(()),
+            ConnectionStatus::Connecting(n) => *n,
+            ConnectionStatus::Failed(n, _) => *n,
+        };
+        self.inner.redial.notify_one();
+        loop {
+            if status.changed().await.is_err() {
+                // The connection handler is gone, so the status is final
+                return match &*status.borrow() {
+                    ConnectionStatus::Connected(_) => Ok(()),
+                    _ => anyhow::bail!("connection handler finished"),
+                };
+            }
+            match &*status.borrow_and_update() {
+                ConnectionStatus::Connected(_) => return Ok(()),
+                ConnectionStatus::Failed(n, e) if *n > started => anyhow::bail!("{e:#}"),
+                _ => {}
+            }
+        }
+    }
+
+    /// Open a raw bidirectional stream on the current connection
+    async fn open_bi(&self) -> anyhow::Result<(quinn::SendStream, quinn::RecvStream)> {
+        let (request_ack_tx, request_ack_rx) = oneshot::channel();
+
+        self.inner
+            .requests_tx
+            .send_async(OpenRequest::Bi(request_ack_tx))
+            .await
+            .map_err(|_| quinn::ConnectionError::LocallyClosed)?;
+
+        request_ack_rx
+            .await
+            .map_err(|_| quinn::ConnectionError::LocallyClosed)?
+    }
+}
+
+/// Accept substreams opened by the remote on a client connection
+///
+/// The task is aborted when the returned handle is dropped, so it does not keep
+/// the connection alive.
+fn accept_substreams(
+    connection: quinn::Connection,
+    incoming: flume::Sender<Accepted>,
+) -> AbortOnDropHandle<()> {
+    AbortOnDropHandle::new(tokio::spawn(IrohListener::<(), ()>::connection_handler(
+        connection, incoming, None,
+    )))
+}
+
+/// Report the lifecycle events of a connection until it is closed
+///
+/// If the endpoint and node id are given, switches between a direct and a relayed
+/// path are reported as well. The task is aborted when the returned handle is
+/// dropped, so a connection that was already replaced does not report its close.
+fn watch_connection(
+    connection: quinn::Connection,
+    path: Option<(iroh::Endpoint, NodeId)>,
+    events: watch::Sender<ConnectionEvent>,
+) -> AbortOnDropHandle<()> {
+    AbortOnDropHandle::new(tokio::spawn(async move {
+        let conn_types = async {
+            let conn_types =
+                path.and_then(|(endpoint, node_id)| endpoint.conn_type_stream(node_id).ok());
+            if let Some(mut conn_types) = conn_types {
+                while let Some(conn_type) = conn_types.next().await {
+                    let relayed = match conn_type {
+                        ConnectionType::Relay(_) => true,
+                        ConnectionType::Direct(_) | ConnectionType::Mixed(..) => false,
+                        ConnectionType::None => continue,
+                    };
+                    events.send_if_modified(|event| match event {
+                        ConnectionEvent::Connected if relayed => {
+                            *event = ConnectionEvent::Relayed;
+                            true
+                        }
+                        ConnectionEvent::Relayed if !relayed => {
+                            *event = ConnectionEvent::Connected;
+                            true
+                        }
+                        _ => false,
+                    });
+                }
+            }
+            std::future::pending::<()>().await
+        };
+        tokio::select! {
+            reason = connection.closed() => {
+                events.send_replace(ConnectionEvent::Disconnected(Arc::new(reason.into())));
+            }
+            _ = conn_types => {}
+        }
+    }))
+}
+
+/// A listener for the substreams accepted by [accept_substreams]
+fn reverse_listener<In: RpcMessage, Out: RpcMessage>(
+    receiver: flume::Receiver<Accepted>,
+    local_addr: Vec<LocalAddr>,
+) -> IrohListener<In, Out> {
+    IrohListener {
+        inner: Arc::new(ListenerInner {
+            endpoint: None,
+            task: None,
+            local_addr,
+            receiver: Incoming::Accepted(receiver),
+            uni: None,
+        }),
+        frames: FrameConfig::default(),
+        _p: PhantomData,
+    }
 }
 
 struct ReconnectHandler {
@@ -527,6 +1103,7 @@ struct ReconnectHandler {
     state: ConnectionState,
     node_addr: NodeAddr,
     alpn: Vec<u8>,
+    backoff: Backoff,
 }
 
 impl ReconnectHandler {
@@ -602,7 +1179,7 @@ impl Future for ReconnectHandler {
     }
 }
 
-impl<In: RpcMessage, Out: RpcMessage> fmt::Debug for IrohConnector<In, Out> {
+impl<In: RpcMessage, Out: RpcMessage, C: Codec> fmt::Debug for IrohConnector<In, Out, C> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("ClientChannel")
             .field("inner", &self.inner)
@@ -610,68 +1187,120 @@ impl<In: RpcMessage, Out: RpcMessage> fmt::Debug for IrohConnector<In, Out> {
     }
 }
 
-impl<In: RpcMessage, Out: RpcMessage> Clone for IrohConnector<In, Out> {
+impl<In: RpcMessage, Out: RpcMessage, C: Codec> Clone for IrohConnector<In, Out, C> {
     fn clone(&self) -> Self {
         Self {
             inner: self.inner.clone(),
+            frames: self.frames,
             _p: PhantomData,
         }
     }
 }
 
-impl<In: RpcMessage, Out: RpcMessage> ConnectionErrors for IrohConnector<In, Out> {
+impl<In: RpcMessage, Out: RpcMessage, C: Codec> ConnectionErrors for IrohConnector<In, Out, C> {
     type SendError = io::Error;
     type RecvError = io::Error;
     type OpenError = anyhow::Error;
     type AcceptError = anyhow::Error;
 }
 
-impl<In: RpcMessage, Out: RpcMessage> StreamTypes for IrohConnector<In, Out> {
+impl<In: RpcMessage, Out: RpcMessage, C: Codec> StreamTypes for IrohConnector<In, Out, C> {
     type In = In;
     type Out = Out;
-    type SendSink = SendSink<Out>;
-    type RecvStream = RecvStream<In>;
+    type SendSink = SendSink<Out, C>;
+    type RecvStream = RecvStream<In, C>;
 }
 
-impl<In: RpcMessage, Out: RpcMessage> Connector for IrohConnector<In, Out> {
+impl<In: RpcMessage, Out: RpcMessage, C: Codec> Connector for IrohConnector<In, Out, C> {
     async fn open(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        let (send, recv) = self.open_bi().await?;
+        Ok((
+            SendSink::new(send, self.frames),
+            RecvStream::new(recv, self.frames),
+        ))
+    }
+
+    async fn open_with_priority(
+        &self,
+        priority: i32,
+    ) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        let (send, recv) = self.open_bi().await?;
+        // the stream can only be closed if the connection is lost, which the first
+        // send will report
+        send.set_priority(priority).ok();
+        Ok((
+            SendSink::new(send, self.frames),
+            RecvStream::new(recv, self.frames),
+        ))
+    }
+
+    async fn open_uni(&self) -> Result<Self::SendSink, Self::OpenError> {
         let (request_ack_tx, request_ack_rx) = oneshot::channel();
 
         self.inner
             .requests_tx
-            .send_async(request_ack_tx)
+            .send_async(OpenRequest::Uni(request_ack_tx))
             .await
             .map_err(|_| quinn::ConnectionError::LocallyClosed)?;
 
-        let (send, recv) = request_ack_rx
+        let send = request_ack_rx
             .await
             .map_err(|_| quinn::ConnectionError::LocallyClosed)??;
 
-        Ok((SendSink::new(send), RecvStream::new(recv)))
+        Ok(SendSink::new(send, self.frames))
+    }
+
+    async fn send_datagram(&self, msg: Out) -> Result<Option<Out>, io::Error> {
+        let connection = match &*self.inner.status.borrow() {
+            ConnectionStatus::Connected(connection) => connection.clone(),
+            _ => return Ok(Some(msg)),
+        };
+        Ok(util::send_datagram::<_, C>(&connection, msg))
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage, C: Codec> ConnectionEvents for IrohConnector<In, Out, C> {
+    fn connection_events(&self) -> watch::Receiver<ConnectionEvent> {
+        self.inner.events.clone()
     }
 }
 
-/// A sink that wraps a quinn SendStream with length delimiting and postcard
+/// A sink that wraps a quinn SendStream with length delimiting and a [Codec]
 ///
 /// If you want to send bytes directly, use [SendSink::into_inner] to get the
 /// underlying [quinn::SendStream].
 #[pin_project]
-pub struct SendSink<Out>(#[pin] FramedPostcardWrite<quinn::SendStream, Out>);
+pub struct SendSink<Out, C: Codec = PostcardCodec>(
+    #[pin] FramedCodecWrite<quinn::SendStream, Out, C>,
+    Option<StreamPermit>,
+);
 
-impl<Out> fmt::Debug for SendSink<Out> {
+impl<Out, C: Codec> fmt::Debug for SendSink<Out, C> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("SendSink").finish()
     }
 }
 
-impl<Out: Serialize> SendSink<Out> {
-    fn new(inner: quinn::SendStream) -> Self {
-        let inner = FramedPostcardWrite::new(inner, MAX_FRAME_LENGTH);
-        Self(inner)
+impl<Out: Serialize, C: Codec> SendSink<Out, C> {
+    fn new(inner: quinn::SendStream, frames: FrameConfig) -> Self {
+        Self::with_permit(inner, frames, None)
+    }
+
+    fn with_permit(
+        inner: quinn::SendStream,
+        FrameConfig {
+            send: max_frame_length,
+            framing,
+            ..
+        }: FrameConfig,
+        permit: Option<StreamPermit>,
+    ) -> Self {
+        let inner = FramedCodecWrite::new(inner, framing, max_frame_length);
+        Self(inner, permit)
     }
 }
 
-impl<Out> SendSink<Out> {
+impl<Out, C: Codec> SendSink<Out, C> {
     /// Get the underlying [quinn::SendStream], which implements
     /// [tokio::io::AsyncWrite] and can be used to send bytes directly.
     pub fn into_inner(self) -> quinn::SendStream {
@@ -679,7 +1308,7 @@ impl<Out> SendSink<Out> {
     }
 }
 
-impl<Out: Serialize> Sink<Out> for SendSink<Out> {
+impl<Out: Serialize, C: Codec> Sink<Out> for SendSink<Out, C> {
     type Error = io::Error;
 
     fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
@@ -699,35 +1328,80 @@ impl<Out: Serialize> Sink<Out> for SendSink<Out> {
     }
 }
 
-/// A stream that wraps a quinn RecvStream with length delimiting and postcard
+/// A stream that wraps a quinn RecvStream with length delimiting and a [Codec]
 ///
 /// If you want to receive bytes directly, use [RecvStream::into_inner] to get
 /// the underlying [quinn::RecvStream].
 #[pin_project]
-pub struct RecvStream<In>(#[pin] FramedPostcardRead<quinn::RecvStream, In>);
+pub struct RecvStream<In, C: Codec = PostcardCodec>(
+    #[pin] FramedCodecRead<quinn::RecvStream, In, C>,
+    Option<StreamPermit>,
+    Option<PeerIdentity>,
+);
 
-impl<In> fmt::Debug for RecvStream<In> {
+impl<In, C: Codec> fmt::Debug for RecvStream<In, C> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("RecvStream").finish()
     }
 }
 
-impl<In: DeserializeOwned> RecvStream<In> {
-    fn new(inner: quinn::RecvStream) -> Self {
-        let inner = FramedPostcardRead::new(inner, MAX_FRAME_LENGTH);
-        Self(inner)
+impl<In: DeserializeOwned, C: Codec> RecvStream<In, C> {
+    fn new(inner: quinn::RecvStream, frames: FrameConfig) -> Self {
+        Self::accepted(inner, frames, None, None)
+    }
+
+    fn accepted(
+        inner: quinn::RecvStream,
+        FrameConfig {
+            recv: max_frame_length,
+            framing,
+            ..
+        }: FrameConfig,
+        permit: Option<StreamPermit>,
+        peer: Option<PeerIdentity>,
+    ) -> Self {
+        let inner = FramedCodecRead::new(inner, framing, max_frame_length);
+        Self(inner, permit, peer)
+    }
+}
+
+impl<In, C: Codec> PeerInfo for RecvStream<In, C> {
+    /// The node id of the remote, for substreams accepted by a listener
+    fn peer_identity(&self) -> Option<&PeerIdentity> {
+        self.2.as_ref()
     }
 }
 
-impl<In> RecvStream<In> {
+impl<In, C: Codec> RecvStream<In, C> {
     /// Get the underlying [quinn::RecvStream], which implements
     /// [tokio::io::AsyncRead] and can be used to receive bytes directly.
+    ///
+    /// Bytes that were already read from the stream but not yet decoded are lost.
+    /// Use [RecvStream::into_parts] if the remote may write raw bytes right after
+    /// its last message.
     pub fn into_inner(self) -> quinn::RecvStream {
         self.0.into_inner()
     }
+
+    /// Get the underlying [quinn::RecvStream], and the bytes that were already
+    /// read from it
+    ///
+    /// The returned bytes come before whatever is read from the stream.
+    pub fn into_parts(self) -> (quinn::RecvStream, bytes::Bytes) {
+        self.0.into_parts()
+    }
+
+    /// The ALPN of the connection, for substreams accepted by a listener
+    pub fn alpn(&self) -> Option<&[u8]> {
+        match self.2.as_ref()? {
+            PeerIdentity::Node { alpn, .. } => Some(alpn),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
 }
 
-impl<In: DeserializeOwned> Stream for RecvStream<In> {
+impl<In: DeserializeOwned, C: Codec> Stream for RecvStream<In, C> {
     type Item = Result<In, io::Error>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
@@ -735,8 +1409,64 @@ impl<In: DeserializeOwned> Stream for RecvStream<In> {
     }
 }
 
+/// Get the node id and ALPN of the remote of an iroh connection, see [PeerIdentity::Node]
+pub fn peer_identity(connection: &Connection) -> Option<PeerIdentity> {
+    let node_id = iroh::endpoint::get_remote_node_id(connection).ok()?;
+    let alpn = connection
+        .handshake_data()?
+        .downcast::<quinn::crypto::rustls::HandshakeData>()
+        .ok()?
+        .protocol?;
+    Some(PeerIdentity::Node { node_id, alpn })
+}
+
 /// Error for open. Currently just an anyhow::Error
 pub type OpenBiError = anyhow::Error;
 
 /// Error for accept. Currently just a quinn::ConnectionError
 pub type AcceptError = quinn::ConnectionError;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::Connector;
+
+    #[test]
+    fn backoff_delay() {
+        let backoff = Backoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(1),
+        };
+        assert_eq!(backoff.delay(1), Duration::from_millis(100));
+        assert_eq!(backoff.delay(2), Duration::from_millis(200));
+        assert_eq!(backoff.delay(4), Duration::from_millis(800));
+        assert_eq!(backoff.delay(5), Duration::from_secs(1));
+        assert_eq!(backoff.delay(1000), Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn no_redial_storm() -> anyhow::Result<()> {
+        let endpoint = iroh::Endpoint::builder().bind().await?;
+        // no addressing information, so every attempt fails right away
+        let node_id = iroh::key::SecretKey::generate().public();
+        let connector = IrohConnector::<u64, u64>::new_with_backoff(
+            endpoint,
+            node_id,
+            b"test".to_vec(),
+            Backoff {
+                initial: Duration::from_secs(10),
+                max: Duration::from_secs(10),
+            },
+        );
+        let opens = (0..32).map(|_| {
+            let connector = connector.clone();
+            tokio::spawn(async move { connector.open().await.is_err() })
+        });
+        for open in futures_util::future::join_all(opens).await {
+            assert!(open?);
+        }
+        let status = connector.inner.status.borrow();
+        assert!(matches!(&*status, ConnectionStatus::Failed(1, _)));
+        Ok(())
+    }
+}
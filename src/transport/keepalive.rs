@@ -0,0 +1,328 @@
+//! Keep-alive frames on idle substreams.
+//!
+//! Long lived substreams, like a server streaming subscription that rarely has
+//! an update, can be closed by NATs or relays that consider them idle. A
+//! [KeepAliveConnector] and a [KeepAliveListener] send a [Frame::KeepAlive] on
+//! the send side of every substream when nothing was sent for the configured
+//! interval. Keep-alive frames are filtered out on the receive side, so user code
+//! never sees them. Both sides of a connection have to use the wrappers.
+use std::{fmt, marker::PhantomData, time::Duration};
+
+use futures_lite::{Stream, StreamExt};
+use futures_sink::Sink;
+use futures_util::SinkExt;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio_util::sync::PollSender;
+
+use super::{
+    boxed::{RecvStream, SendSink},
+    ConnectionErrors, Connector, Listener, LocalAddr, StreamTypes,
+};
+use crate::{rt, RpcMessage};
+
+/// A frame on a substream with keep-alives
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Frame<T> {
+    /// Sent when the substream was idle for the keep-alive interval
+    KeepAlive,
+    /// A message
+    Msg(T),
+}
+
+/// Wrap the send side of a substream, spawning the task that writes it
+fn keep_alive_send<Out, Si>(send: Si, interval: Duration) -> SendSink<Out>
+where
+    Out: RpcMessage,
+    Si: Sink<Frame<Out>> + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(1);
+    rt::spawn_detached(write_loop(send, rx, interval));
+    let send = PollSender::new(tx).sink_map_err(|_| anyhow::anyhow!("keep-alive substream closed"));
+    SendSink::boxed(send)
+}
+
+/// Wrap the receive side of a substream, filtering out keep-alive frames
+fn keep_alive_recv<In, St, E>(recv: St) -> RecvStream<In>
+where
+    In: RpcMessage,
+    St: Stream<Item = Result<Frame<In>, E>> + Send + Sync + 'static,
+    E: Into<anyhow::Error>,
+{
+    let recv = recv.filter_map(|frame| match frame {
+        Ok(Frame::KeepAlive) => None,
+        Ok(Frame::Msg(msg)) => Some(Ok(msg)),
+        Err(cause) => Some(Err(cause.into())),
+    });
+    RecvStream::boxed(recv)
+}
+
+async fn write_loop<Out, Si>(send: Si, mut rx: mpsc::Receiver<Out>, interval: Duration)
+where
+    Si: Sink<Frame<Out>>,
+{
+    let mut send = std::pin::pin!(send);
+    loop {
+        let frame = match rt::timeout(interval, rx.recv()).await {
+            Some(Some(msg)) => Frame::Msg(msg),
+            // the send sink was dropped
+            Some(None) => break,
+            None => Frame::KeepAlive,
+        };
+        if send.send(frame).await.is_err() {
+            return;
+        }
+    }
+    send.close().await.ok();
+}
+
+/// A connector that sends keep-alive frames on idle substreams
+pub struct KeepAliveConnector<In, Out, C> {
+    inner: C,
+    interval: Duration,
+    _p: PhantomData<(In, Out)>,
+}
+
+impl<In, Out, C> KeepAliveConnector<In, Out, C>
+where
+    C: Connector<In = Frame<In>, Out = Frame<Out>>,
+{
+    /// Create a new keep-alive connector with the given interval
+    pub fn new(inner: C, interval: Duration) -> Self {
+        Self {
+            inner,
+            interval,
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<In, Out, C: Clone> Clone for KeepAliveConnector<In, Out, C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            interval: self.interval,
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<In, Out, C: fmt::Debug> fmt::Debug for KeepAliveConnector<In, Out, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeepAliveConnector")
+            .field("inner", &self.inner)
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
+
+impl<In, Out, C> ConnectionErrors for KeepAliveConnector<In, Out, C>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    C: ConnectionErrors,
+{
+    type SendError = anyhow::Error;
+    type RecvError = anyhow::Error;
+    type OpenError = anyhow::Error;
+    type AcceptError = anyhow::Error;
+}
+
+impl<In, Out, C> StreamTypes for KeepAliveConnector<In, Out, C>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    C: StreamTypes<In = Frame<In>, Out = Frame<Out>>,
+{
+    type In = In;
+    type Out = Out;
+    type RecvStream = RecvStream<In>;
+    type SendSink = SendSink<Out>;
+}
+
+impl<In, Out, C> Connector for KeepAliveConnector<In, Out, C>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    C: Connector<In = Frame<In>, Out = Frame<Out>>,
+{
+    async fn open(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        let (send, recv) = self.inner.open().await.map_err(Into::into)?;
+        Ok((keep_alive_send(send, self.interval), keep_alive_recv(recv)))
+    }
+
+    async fn open_with_priority(
+        &self,
+        priority: i32,
+    ) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        let (send, recv) = self
+            .inner
+            .open_with_priority(priority)
+            .await
+            .map_err(Into::into)?;
+        Ok((keep_alive_send(send, self.interval), keep_alive_recv(recv)))
+    }
+
+    async fn open_uni(&self) -> Result<Self::SendSink, Self::OpenError> {
+        let send = self.inner.open_uni().await.map_err(Into::into)?;
+        Ok(keep_alive_send(send, self.interval))
+    }
+
+    async fn send_datagram(&self, msg: Self::Out) -> Result<Option<Self::Out>, Self::SendError> {
+        let returned = self
+            .inner
+            .send_datagram(Frame::Msg(msg))
+            .await
+            .map_err(Into::into)?;
+        Ok(match returned {
+            Some(Frame::Msg(msg)) => Some(msg),
+            Some(Frame::KeepAlive) | None => None,
+        })
+    }
+}
+
+/// A listener that sends keep-alive frames on idle substreams
+pub struct KeepAliveListener<In, Out, L> {
+    inner: L,
+    interval: Duration,
+    _p: PhantomData<(In, Out)>,
+}
+
+impl<In, Out, L> KeepAliveListener<In, Out, L>
+where
+    L: Listener<In = Frame<In>, Out = Frame<Out>>,
+{
+    /// Create a new keep-alive listener with the given interval
+    pub fn new(inner: L, interval: Duration) -> Self {
+        Self {
+            inner,
+            interval,
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<In, Out, L: Clone> Clone for KeepAliveListener<In, Out, L> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            interval: self.interval,
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<In, Out, L: fmt::Debug> fmt::Debug for KeepAliveListener<In, Out, L> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeepAliveListener")
+            .field("inner", &self.inner)
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
+
+impl<In, Out, L> ConnectionErrors for KeepAliveListener<In, Out, L>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    L: ConnectionErrors,
+{
+    type SendError = anyhow::Error;
+    type RecvError = anyhow::Error;
+    type OpenError = anyhow::Error;
+    type AcceptError = anyhow::Error;
+}
+
+impl<In, Out, L> StreamTypes for KeepAliveListener<In, Out, L>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    L: StreamTypes<In = Frame<In>, Out = Frame<Out>>,
+{
+    type In = In;
+    type Out = Out;
+    type RecvStream = RecvStream<In>;
+    type SendSink = SendSink<Out>;
+}
+
+impl<In, Out, L> Listener for KeepAliveListener<In, Out, L>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    L: Listener<In = Frame<In>, Out = Frame<Out>>,
+{
+    async fn accept(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::AcceptError> {
+        let (send, recv) = self.inner.accept().await.map_err(Into::into)?;
+        Ok((keep_alive_send(send, self.interval), keep_alive_recv(recv)))
+    }
+
+    fn local_addr(&self) -> &[LocalAddr] {
+        self.inner.local_addr()
+    }
+
+    async fn accept_uni(&self) -> Result<Self::RecvStream, Self::AcceptError> {
+        let recv = self.inner.accept_uni().await.map_err(Into::into)?;
+        Ok(keep_alive_recv(recv))
+    }
+
+    async fn recv_datagram(&self) -> Result<Self::In, Self::RecvError> {
+        loop {
+            match self.inner.recv_datagram().await.map_err(Into::into)? {
+                Frame::Msg(msg) => return Ok(msg),
+                Frame::KeepAlive => continue,
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "flume-transport"))]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::{transport::flume, RpcClient, RpcServer, Service};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Echo(u64);
+
+    #[derive(Debug, Clone)]
+    struct EchoService;
+
+    impl Service for EchoService {
+        type Req = Echo;
+        type Res = Echo;
+    }
+
+    impl crate::message::RpcMsg<EchoService> for Echo {
+        type Response = Echo;
+    }
+
+    #[tokio::test]
+    async fn idle_substream() -> anyhow::Result<()> {
+        let interval = Duration::from_millis(10);
+        let (listener, connector) = flume::channel::<Frame<Echo>, Frame<Echo>>(1);
+        let connector = KeepAliveConnector::new(connector, interval);
+        // the raw side sees the keep-alive frames of an idle substream
+        let (_send, mut recv) = connector.open().await?;
+        let (mut raw_send, mut raw_recv) = listener.accept().await?;
+        assert_eq!(raw_recv.next().await.transpose()?, Some(Frame::KeepAlive));
+        // the wrapped side filters them out
+        raw_send.send(Frame::KeepAlive).await?;
+        raw_send.send(Frame::Msg(Echo(2))).await?;
+        let Echo(x) = recv.next().await.transpose()?.unwrap();
+        assert_eq!(x, 2);
+        // calls work across the wrappers while idle
+        let listener = KeepAliveListener::new(listener, interval);
+        let client = RpcClient::<EchoService, _>::new(connector);
+        let server = RpcServer::<EchoService, _>::new(listener);
+        let _server = tokio::spawn(async move {
+            let (req, chan) = server.accept().await?.read_first().await?;
+            rt::sleep(interval * 5).await;
+            chan.rpc(req, (), |_, req| async move { req }).await?;
+            anyhow::Ok(())
+        });
+        let Echo(x) = client.rpc(Echo(1)).await?;
+        assert_eq!(x, 1);
+        Ok(())
+    }
+}
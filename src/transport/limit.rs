@@ -0,0 +1,287 @@
+//! Connector wrapper that limits the number of concurrently open substreams.
+//!
+//! Opening a substream for every call, without a limit, fails with stream limit
+//! errors on transports like quinn when the remote allows fewer concurrent
+//! streams than there are calls. A [LimitedConnector] holds at most the given
+//! number of substreams open at the same time. A substream counts until both its
+//! send and its receive side are dropped.
+//!
+//! Further opens wait in a queue until a substream is closed. The queue can be
+//! bounded with [LimitedConnector::with_max_queued], in which case opens fail with
+//! [OpenError::Overloaded] when it is full.
+//!
+//! Use [RpcClient::with_concurrency_limit] to limit the calls of a client.
+use std::{
+    error, fmt,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use futures_lite::Stream;
+use futures_sink::Sink;
+use pin_project::pin_project;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use super::{ConnectionErrors, Connector, StreamTypes};
+use crate::{RpcClient, Service};
+
+/// A send sink that holds one of the permits of a [LimitedConnector]
+#[pin_project]
+#[derive(Debug)]
+pub struct SendSink<S> {
+    #[pin]
+    inner: S,
+    _permit: Arc<OwnedSemaphorePermit>,
+}
+
+impl<S: Sink<T>, T> Sink<T> for SendSink<S> {
+    type Error = S::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        self.project().inner.start_send(item)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_close(cx)
+    }
+}
+
+/// A receive stream that holds one of the permits of a [LimitedConnector]
+#[pin_project]
+#[derive(Debug)]
+pub struct RecvStream<S> {
+    #[pin]
+    inner: S,
+    _permit: Arc<OwnedSemaphorePermit>,
+}
+
+impl<S: Stream> Stream for RecvStream<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.project().inner.poll_next(cx)
+    }
+}
+
+/// Counts an open that waits in the queue, until it is dropped
+struct Queued<'a>(&'a AtomicUsize);
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A connector that limits the number of concurrently open substreams
+#[derive(Debug, Clone)]
+pub struct LimitedConnector<C> {
+    inner: C,
+    permits: Arc<Semaphore>,
+    queued: Arc<AtomicUsize>,
+    max_queued: Option<usize>,
+}
+
+impl<C> LimitedConnector<C> {
+    /// Wrap a connector, allowing at most `limit` open substreams
+    ///
+    /// # Panics
+    ///
+    /// Panics if the limit is zero or larger than [Semaphore::MAX_PERMITS].
+    pub fn new(inner: C, limit: usize) -> Self {
+        assert!(limit > 0, "concurrency limit must not be zero");
+        Self {
+            inner,
+            permits: Arc::new(Semaphore::new(limit)),
+            queued: Default::default(),
+            max_queued: None,
+        }
+    }
+
+    /// Fail opens with [OpenError::Overloaded] if this many opens already wait
+    ///
+    /// By default, the queue is not bounded. With a maximum of 0, opens fail right
+    /// away when the limit is reached.
+    pub fn with_max_queued(mut self, value: usize) -> Self {
+        self.max_queued = Some(value);
+        self
+    }
+
+    /// The number of substreams that can be opened without waiting
+    pub fn available(&self) -> usize {
+        self.permits.available_permits()
+    }
+
+    /// The number of opens that wait for a substream to be closed
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    async fn acquire(&self) -> Result<OwnedSemaphorePermit, Overloaded> {
+        if let Ok(permit) = self.permits.clone().try_acquire_owned() {
+            return Ok(permit);
+        }
+        let queued = self.queued.fetch_add(1, Ordering::Relaxed);
+        let _queued = Queued(&self.queued);
+        if self.max_queued.is_some_and(|max| queued >= max) {
+            return Err(Overloaded);
+        }
+        Ok(self
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("semaphore is never closed"))
+    }
+}
+
+/// The queue of a [LimitedConnector] is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overloaded;
+
+impl fmt::Display for Overloaded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("too many concurrent calls")
+    }
+}
+
+impl error::Error for Overloaded {}
+
+/// Make both sides of a channel hold the permit
+fn hold<S, R>(permit: OwnedSemaphorePermit, (send, recv): (S, R)) -> (SendSink<S>, RecvStream<R>) {
+    let permit = Arc::new(permit);
+    let send = SendSink {
+        inner: send,
+        _permit: permit.clone(),
+    };
+    let recv = RecvStream {
+        inner: recv,
+        _permit: permit,
+    };
+    (send, recv)
+}
+
+/// OpenError for limited connectors
+#[derive(Debug)]
+pub enum OpenError<C: ConnectionErrors> {
+    /// The limit is reached and the queue is full
+    Overloaded(Overloaded),
+    /// Opening failed on the inner connector
+    Open(C::OpenError),
+}
+
+impl<C: ConnectionErrors> fmt::Display for OpenError<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl<C: ConnectionErrors> error::Error for OpenError<C> {}
+
+impl<C: ConnectionErrors> ConnectionErrors for LimitedConnector<C> {
+    type SendError = C::SendError;
+    type RecvError = C::RecvError;
+    type OpenError = OpenError<C>;
+    type AcceptError = C::AcceptError;
+}
+
+impl<C: StreamTypes> StreamTypes for LimitedConnector<C> {
+    type In = C::In;
+    type Out = C::Out;
+    type RecvStream = RecvStream<C::RecvStream>;
+    type SendSink = SendSink<C::SendSink>;
+}
+
+impl<C: Connector> Connector for LimitedConnector<C> {
+    async fn open(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        let permit = self.acquire().await.map_err(OpenError::Overloaded)?;
+        let channel = self.inner.open().await.map_err(OpenError::Open)?;
+        Ok(hold(permit, channel))
+    }
+
+    async fn open_with_priority(
+        &self,
+        priority: i32,
+    ) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        let permit = self.acquire().await.map_err(OpenError::Overloaded)?;
+        let channel = self
+            .inner
+            .open_with_priority(priority)
+            .await
+            .map_err(OpenError::Open)?;
+        Ok(hold(permit, channel))
+    }
+
+    async fn open_uni(&self) -> Result<Self::SendSink, Self::OpenError> {
+        let permit = self.acquire().await.map_err(OpenError::Overloaded)?;
+        let send = self.inner.open_uni().await.map_err(OpenError::Open)?;
+        Ok(SendSink {
+            inner: send,
+            _permit: Arc::new(permit),
+        })
+    }
+
+    /// Send a datagram on the inner connector
+    ///
+    /// Datagrams do not open a substream, so they do not count against the limit.
+    async fn send_datagram(&self, msg: Self::Out) -> Result<Option<Self::Out>, Self::SendError> {
+        self.inner.send_datagram(msg).await
+    }
+}
+
+impl<S: Service, C> RpcClient<S, LimitedConnector<C>> {
+    /// The number of calls of this client that wait for a substream
+    pub fn queued(&self) -> usize {
+        self.source.queued()
+    }
+}
+
+#[cfg(all(test, feature = "flume-transport"))]
+mod tests {
+    use super::*;
+    use crate::transport::flume;
+
+    #[tokio::test]
+    async fn queue_and_overload() -> anyhow::Result<()> {
+        let (_listener, connector) = flume::channel::<u64, u64>(8);
+        let connector = LimitedConnector::new(connector, 2).with_max_queued(1);
+        let first = connector.open().await?;
+        let (send, recv) = connector.open().await?;
+        assert_eq!(connector.available(), 0);
+
+        // the next open waits, and the one after that is rejected
+        let waiting = tokio::spawn({
+            let connector = connector.clone();
+            async move { connector.open().await.map(drop) }
+        });
+        while connector.queued() == 0 {
+            tokio::task::yield_now().await;
+        }
+        assert!(matches!(
+            connector.open().await,
+            Err(OpenError::Overloaded(Overloaded))
+        ));
+
+        // the substream counts until both sides are dropped
+        drop(send);
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+        drop(recv);
+        waiting.await??;
+        assert_eq!(connector.queued(), 0);
+        drop(first);
+        assert_eq!(connector.available(), 2);
+        Ok(())
+    }
+}
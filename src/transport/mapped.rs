@@ -9,7 +9,7 @@ use futures_lite::{Stream, StreamExt};
 use futures_util::SinkExt;
 use pin_project::pin_project;
 
-use super::{ConnectionErrors, Connector, StreamTypes};
+use super::{ConnectionErrors, ConnectionEvent, ConnectionEvents, Connector, StreamTypes};
 use crate::{RpcError, RpcMessage};
 
 /// A connection that maps input and output types
@@ -90,6 +90,50 @@ where
             Ok((MappedSendSink::new(send), MappedRecvStream::new(recv)))
         }
     }
+
+    fn open_with_priority(
+        &self,
+        priority: i32,
+    ) -> impl std::future::Future<Output = Result<(Self::SendSink, Self::RecvStream), Self::OpenError>>
+           + Send {
+        let inner = self.inner.open_with_priority(priority);
+        async move {
+            let (send, recv) = inner.await?;
+            Ok((MappedSendSink::new(send), MappedRecvStream::new(recv)))
+        }
+    }
+
+    fn open_uni(
+        &self,
+    ) -> impl std::future::Future<Output = Result<Self::SendSink, Self::OpenError>> + Send {
+        let inner = self.inner.open_uni();
+        async move { Ok(MappedSendSink::new(inner.await?)) }
+    }
+
+    /// Send a message as a datagram on the inner connector
+    ///
+    /// A message that the inner connector returns can not be mapped back, so it is
+    /// sent on a unidirectional substream of the inner connector instead. If that
+    /// can not be opened, the message is dropped, like a lost datagram.
+    async fn send_datagram(&self, msg: Self::Out) -> Result<Option<Self::Out>, Self::SendError> {
+        let Some(msg) = self.inner.send_datagram(msg.into()).await? else {
+            return Ok(None);
+        };
+        match self.inner.open_uni().await {
+            Ok(mut send) => {
+                send.send(msg).await?;
+                send.close().await?;
+            }
+            Err(cause) => tracing::debug!("dropping datagram: {cause}"),
+        }
+        Ok(None)
+    }
+}
+
+impl<In, Out, C: ConnectionEvents> ConnectionEvents for MappedConnector<In, Out, C> {
+    fn connection_events(&self) -> tokio::sync::watch::Receiver<ConnectionEvent> {
+        self.inner.connection_events()
+    }
 }
 
 /// A combinator that maps a stream of incoming messages to a different type
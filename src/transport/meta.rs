@@ -0,0 +1,502 @@
+//! Transport wrapper that carries per request metadata.
+//!
+//! A substream can start with a [Metadata] frame, with string key value pairs, a
+//! deadline and a trace context. The metadata is sent together with the first
+//! request, so it does not cost an extra round trip, and it does not have to be
+//! part of the request enum of the service.
+//!
+//! On the client side, wrap the connector in a [MetaConnector] and use
+//! [RpcClient::rpc_with_meta](crate::RpcClient::rpc_with_meta). Substreams opened
+//! with [Connector::open] carry no metadata.
+//!
+//! On the server side, wrap the listener in a [MetaListener]. The metadata is
+//! available to handlers using
+//! [RpcChannel::metadata](crate::server::RpcChannel::metadata).
+use std::{
+    collections::BTreeMap,
+    fmt,
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use futures_lite::{Future, Stream};
+use futures_sink::Sink;
+use futures_util::{future, SinkExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    boxed::{RecvStream, SendSink},
+    ConnectionErrors, Connector, Listener, LocalAddr, StreamTypes,
+};
+use crate::RpcMessage;
+
+/// A W3C trace context, to continue a distributed trace on the server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceContext {
+    /// The value of the `traceparent` header
+    pub traceparent: String,
+    /// The value of the `tracestate` header, if any
+    pub tracestate: Option<String>,
+}
+
+/// Metadata of a request
+///
+/// The deadline is sent as the time remaining until the deadline, so it does not
+/// depend on synchronized clocks. On the server side, it is relative to when the
+/// metadata was received.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "WireMetadata", into = "WireMetadata")]
+pub struct Metadata {
+    entries: BTreeMap<String, String>,
+    deadline: Option<Instant>,
+    trace: Option<TraceContext>,
+}
+
+impl Metadata {
+    /// Create empty metadata
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a key value pair
+    pub fn with_entry(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.insert(key, value);
+        self
+    }
+
+    /// Set the deadline of the request
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Set the trace context of the request
+    pub fn with_trace(mut self, trace: TraceContext) -> Self {
+        self.trace = Some(trace);
+        self
+    }
+
+    /// Insert a key value pair, returning the previous value for the key
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) -> Option<String> {
+        self.entries.insert(key.into(), value.into())
+    }
+
+    /// Get the value for a key
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.get(key).map(String::as_str)
+    }
+
+    /// Iterate over all key value pairs, ordered by key
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// The deadline of the request
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// The trace context of the request
+    pub fn trace(&self) -> Option<&TraceContext> {
+        self.trace.as_ref()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct WireMetadata {
+    entries: BTreeMap<String, String>,
+    timeout: Option<Duration>,
+    trace: Option<TraceContext>,
+}
+
+impl From<Metadata> for WireMetadata {
+    fn from(meta: Metadata) -> Self {
+        let now = Instant::now();
+        Self {
+            entries: meta.entries,
+            timeout: meta.deadline.map(|d| d.saturating_duration_since(now)),
+            trace: meta.trace,
+        }
+    }
+}
+
+impl From<WireMetadata> for Metadata {
+    fn from(wire: WireMetadata) -> Self {
+        let now = Instant::now();
+        Self {
+            entries: wire.entries,
+            deadline: wire.timeout.and_then(|t| now.checked_add(t)),
+            trace: wire.trace,
+        }
+    }
+}
+
+/// A message on the underlying transport of a metadata transport
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Frame<T> {
+    /// The metadata of the substream, only allowed as the first frame
+    Meta(Metadata),
+    /// A regular message
+    Msg(T),
+}
+
+/// Connectors that can send [Metadata] when opening a substream
+///
+/// Used by [RpcClient::rpc_with_meta](crate::RpcClient::rpc_with_meta).
+pub trait MetaOpen: Connector {
+    /// Open a channel that starts with the given metadata
+    fn open_with_meta(
+        &self,
+        meta: Metadata,
+    ) -> impl Future<Output = Result<(Self::SendSink, Self::RecvStream), Self::OpenError>> + Send;
+}
+
+/// Receive streams of channels that may carry [Metadata]
+///
+/// Used by [RpcChannel::metadata](crate::server::RpcChannel::metadata).
+pub trait MetaInfo {
+    /// The metadata the client sent for this channel, if any
+    fn metadata(&self) -> Option<&Metadata>;
+}
+
+/// A connector that can send metadata at the start of a substream
+pub struct MetaConnector<In, Out, C> {
+    inner: C,
+    _p: PhantomData<(In, Out)>,
+}
+
+impl<In, Out, C> MetaConnector<In, Out, C>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    C: Connector<In = In, Out = Frame<Out>>,
+{
+    /// Create a new metadata connector
+    pub fn new(inner: C) -> Self {
+        Self {
+            inner,
+            _p: PhantomData,
+        }
+    }
+
+    async fn open_inner(
+        &self,
+        open: impl Future<Output = Result<(C::SendSink, C::RecvStream), C::OpenError>>,
+        meta: Option<Metadata>,
+    ) -> anyhow::Result<(SendSink<Out>, RecvStream<In>)> {
+        let (mut send, recv) = open.await.map_err(Into::into)?;
+        if let Some(meta) = meta {
+            // not flushed, so it goes out together with the first request
+            send.feed(Frame::Meta(meta)).await.map_err(Into::into)?;
+        }
+        let recv = recv.map_err(Into::into);
+        Ok((wrap_send(send), RecvStream::boxed(recv)))
+    }
+}
+
+/// Wrap the send side of a substream, after the metadata was sent
+fn wrap_send<Out, S>(send: S) -> SendSink<Out>
+where
+    Out: RpcMessage,
+    S: Sink<Frame<Out>> + Send + Sync + 'static,
+    S::Error: Into<anyhow::Error>,
+{
+    let send = send
+        .sink_map_err(Into::into)
+        .with(|msg: Out| future::ready(anyhow::Ok(Frame::Msg(msg))));
+    SendSink::boxed(send)
+}
+
+impl<In, Out, C: Clone> Clone for MetaConnector<In, Out, C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<In, Out, C: fmt::Debug> fmt::Debug for MetaConnector<In, Out, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetaConnector")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+impl<In, Out, C> ConnectionErrors for MetaConnector<In, Out, C>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    C: ConnectionErrors,
+{
+    type SendError = anyhow::Error;
+    type RecvError = anyhow::Error;
+    type OpenError = anyhow::Error;
+    type AcceptError = anyhow::Error;
+}
+
+impl<In, Out, C> StreamTypes for MetaConnector<In, Out, C>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    C: StreamTypes<In = In, Out = Frame<Out>>,
+{
+    type In = In;
+    type Out = Out;
+    type RecvStream = RecvStream<In>;
+    type SendSink = SendSink<Out>;
+}
+
+impl<In, Out, C> Connector for MetaConnector<In, Out, C>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    C: Connector<In = In, Out = Frame<Out>>,
+{
+    async fn open(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        self.open_inner(self.inner.open(), None).await
+    }
+
+    async fn open_with_priority(
+        &self,
+        priority: i32,
+    ) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        self.open_inner(self.inner.open_with_priority(priority), None)
+            .await
+    }
+
+    /// Open a unidirectional substream, without metadata
+    async fn open_uni(&self) -> Result<Self::SendSink, Self::OpenError> {
+        let send = self.inner.open_uni().await.map_err(Into::into)?;
+        Ok(wrap_send(send))
+    }
+
+    async fn send_datagram(&self, msg: Self::Out) -> Result<Option<Self::Out>, Self::SendError> {
+        let returned = self
+            .inner
+            .send_datagram(Frame::Msg(msg))
+            .await
+            .map_err(Into::into)?;
+        Ok(match returned {
+            Some(Frame::Msg(msg)) => Some(msg),
+            Some(Frame::Meta(_)) | None => None,
+        })
+    }
+}
+
+impl<In, Out, C> MetaOpen for MetaConnector<In, Out, C>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    C: Connector<In = In, Out = Frame<Out>>,
+{
+    async fn open_with_meta(
+        &self,
+        meta: Metadata,
+    ) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        self.open_inner(self.inner.open(), Some(meta)).await
+    }
+}
+
+/// A listener that reads the metadata at the start of every substream
+pub struct MetaListener<In, Out, C> {
+    inner: C,
+    _p: PhantomData<(In, Out)>,
+}
+
+impl<In, Out, C> MetaListener<In, Out, C>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    C: Listener<In = Frame<In>, Out = Out>,
+{
+    /// Create a new metadata listener
+    pub fn new(inner: C) -> Self {
+        Self {
+            inner,
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<In, Out, C: Clone> Clone for MetaListener<In, Out, C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<In, Out, C: fmt::Debug> fmt::Debug for MetaListener<In, Out, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetaListener")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+impl<In, Out, C> ConnectionErrors for MetaListener<In, Out, C>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    C: ConnectionErrors,
+{
+    type SendError = anyhow::Error;
+    type RecvError = anyhow::Error;
+    type OpenError = anyhow::Error;
+    type AcceptError = anyhow::Error;
+}
+
+impl<In, Out, C> StreamTypes for MetaListener<In, Out, C>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    C: StreamTypes<In = Frame<In>, Out = Out>,
+{
+    type In = In;
+    type Out = Out;
+    type RecvStream = MetaRecvStream<In>;
+    type SendSink = SendSink<Out>;
+}
+
+impl<In, Out, C> Listener for MetaListener<In, Out, C>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    C: Listener<In = Frame<In>, Out = Out>,
+{
+    async fn accept(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::AcceptError> {
+        let (send, recv) = self.inner.accept().await.map_err(Into::into)?;
+        let send = SendSink::boxed(send.sink_map_err(Into::into));
+        let recv = MetaRecvStream {
+            inner: RecvStream::boxed(recv.map_err(Into::into)),
+            meta: None,
+            first: true,
+        };
+        Ok((send, recv))
+    }
+
+    fn local_addr(&self) -> &[LocalAddr] {
+        self.inner.local_addr()
+    }
+
+    async fn accept_uni(&self) -> Result<Self::RecvStream, Self::AcceptError> {
+        let recv = self.inner.accept_uni().await.map_err(Into::into)?;
+        Ok(MetaRecvStream {
+            inner: RecvStream::boxed(recv.map_err(Into::into)),
+            meta: None,
+            first: true,
+        })
+    }
+
+    async fn recv_datagram(&self) -> Result<Self::In, Self::RecvError> {
+        match self.inner.recv_datagram().await.map_err(Into::into)? {
+            Frame::Msg(msg) => Ok(msg),
+            Frame::Meta(_) => anyhow::bail!("unexpected metadata in a datagram"),
+        }
+    }
+}
+
+/// Receive side of a substream accepted by a [MetaListener]
+pub struct MetaRecvStream<T: RpcMessage> {
+    inner: RecvStream<Frame<T>>,
+    meta: Option<Metadata>,
+    first: bool,
+}
+
+impl<T: RpcMessage> MetaInfo for MetaRecvStream<T> {
+    fn metadata(&self) -> Option<&Metadata> {
+        self.meta.as_ref()
+    }
+}
+
+impl<T: RpcMessage> fmt::Debug for MetaRecvStream<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetaRecvStream")
+            .field("meta", &self.meta)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: RpcMessage> Stream for MetaRecvStream<T> {
+    type Item = anyhow::Result<T>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            let frame = std::task::ready!(Pin::new(&mut self.inner).poll_next(cx));
+            let first = std::mem::replace(&mut self.first, false);
+            return Poll::Ready(match frame {
+                Some(Ok(Frame::Msg(msg))) => Some(Ok(msg)),
+                Some(Ok(Frame::Meta(meta))) if first => {
+                    self.meta = Some(meta);
+                    continue;
+                }
+                Some(Ok(Frame::Meta(_))) => Some(Err(anyhow::anyhow!("unexpected metadata frame"))),
+                Some(Err(cause)) => Some(Err(cause)),
+                None => None,
+            });
+        }
+    }
+}
+
+#[cfg(all(test, feature = "flume-transport"))]
+mod tests {
+    use super::*;
+    use crate::{message::RpcMsg, transport::flume, RpcClient, RpcServer, Service};
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Whoami;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Identity(Option<String>, bool);
+
+    #[derive(Debug, Clone)]
+    struct AuthService;
+
+    impl Service for AuthService {
+        type Req = Whoami;
+        type Res = Identity;
+    }
+
+    impl RpcMsg<AuthService> for Whoami {
+        type Response = Identity;
+    }
+
+    #[tokio::test]
+    async fn metadata() -> anyhow::Result<()> {
+        let (listener, connector) = flume::channel(1);
+        let server = RpcServer::<AuthService, _>::new(MetaListener::new(listener));
+        let _server = tokio::spawn(async move {
+            loop {
+                let (req, chan) = server.accept().await?.read_first().await?;
+                let meta = chan.metadata().cloned().unwrap_or_default();
+                let user = meta.get("user").map(ToOwned::to_owned);
+                let has_deadline = meta.deadline().is_some();
+                chan.rpc(
+                    req,
+                    (),
+                    move |_, _| async move { Identity(user, has_deadline) },
+                )
+                .await?;
+            }
+            #[allow(unreachable_code)]
+            anyhow::Ok(())
+        });
+        let client = RpcClient::<AuthService, _>::new(MetaConnector::new(connector));
+        let Identity(user, has_deadline) = client.rpc(Whoami).await?;
+        assert_eq!(user, None);
+        assert!(!has_deadline);
+
+        let meta = Metadata::new()
+            .with_entry("user", "alice")
+            .with_deadline(Instant::now() + Duration::from_secs(10));
+        let Identity(user, has_deadline) = client.rpc_with_meta(Whoami, meta).await?;
+        assert_eq!(user.as_deref(), Some("alice"));
+        assert!(has_deadline);
+        Ok(())
+    }
+}
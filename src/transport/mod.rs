@@ -20,6 +20,7 @@
 use std::{
     fmt::{self, Debug, Display},
     net::SocketAddr,
+    sync::Arc,
 };
 
 use boxed::{BoxableConnector, BoxableListener, BoxedConnector, BoxedListener};
@@ -29,23 +30,100 @@ use mapped::MappedConnector;
 
 use crate::{RpcError, RpcMessage};
 
+pub mod backpressure;
+pub mod balanced;
 pub mod boxed;
+pub mod budget;
+pub mod circuit;
 pub mod combined;
+#[cfg(feature = "compression")]
+#[cfg_attr(quicrpc_docsrs, doc(cfg(feature = "compression")))]
+pub mod compressed;
+#[cfg(feature = "fault-injection")]
+#[cfg_attr(quicrpc_docsrs, doc(cfg(feature = "fault-injection")))]
+pub mod faulty;
 #[cfg(feature = "flume-transport")]
 #[cfg_attr(quicrpc_docsrs, doc(cfg(feature = "flume-transport")))]
 pub mod flume;
+#[cfg(feature = "framed-transport")]
+#[cfg_attr(quicrpc_docsrs, doc(cfg(feature = "framed-transport")))]
+pub mod framed;
+#[cfg(feature = "rt")]
+#[cfg_attr(quicrpc_docsrs, doc(cfg(feature = "rt")))]
+pub mod handshake;
 #[cfg(feature = "hyper-transport")]
 #[cfg_attr(quicrpc_docsrs, doc(cfg(feature = "hyper-transport")))]
 pub mod hyper;
+pub mod inflight;
+#[cfg(feature = "instrumented")]
+#[cfg_attr(quicrpc_docsrs, doc(cfg(feature = "instrumented")))]
+pub mod instrumented;
 #[cfg(feature = "iroh-transport")]
 #[cfg_attr(quicrpc_docsrs, doc(cfg(feature = "iroh-transport")))]
 pub mod iroh;
+#[cfg(feature = "rt")]
+#[cfg_attr(
+    quicrpc_docsrs,
+    doc(cfg(any(feature = "rt-tokio", feature = "rt-smol", feature = "rt-async-std")))
+)]
+pub mod keepalive;
+pub mod limit;
 pub mod mapped;
+pub mod meta;
 pub mod misc;
+#[cfg(feature = "mpsc-transport")]
+#[cfg_attr(quicrpc_docsrs, doc(cfg(feature = "mpsc-transport")))]
+pub mod mpsc;
+#[cfg(feature = "framed-transport")]
+#[cfg_attr(quicrpc_docsrs, doc(cfg(feature = "framed-transport")))]
+pub mod mux;
+#[cfg(feature = "rt")]
+#[cfg_attr(
+    quicrpc_docsrs,
+    doc(cfg(any(feature = "rt-tokio", feature = "rt-smol", feature = "rt-async-std")))
+)]
+pub mod pipeline;
+#[cfg(any(feature = "tcp-transport", feature = "websocket-transport"))]
+#[cfg_attr(
+    quicrpc_docsrs,
+    doc(cfg(any(feature = "tcp-transport", feature = "websocket-transport")))
+)]
+pub mod proxy;
 #[cfg(feature = "quinn-transport")]
 #[cfg_attr(quicrpc_docsrs, doc(cfg(feature = "quinn-transport")))]
 pub mod quinn;
+pub mod reconnecting;
+#[cfg(feature = "stdio-transport")]
+#[cfg_attr(quicrpc_docsrs, doc(cfg(feature = "stdio-transport")))]
+pub mod stdio;
+#[cfg(feature = "tagged-transport")]
+#[cfg_attr(quicrpc_docsrs, doc(cfg(feature = "tagged-transport")))]
+pub mod tagged;
+#[cfg(feature = "debug-tap")]
+#[cfg_attr(quicrpc_docsrs, doc(cfg(feature = "debug-tap")))]
+pub mod tap;
+#[cfg(feature = "tcp-transport")]
+#[cfg_attr(quicrpc_docsrs, doc(cfg(feature = "tcp-transport")))]
+pub mod tcp;
+#[cfg(feature = "testkit")]
+#[cfg_attr(quicrpc_docsrs, doc(cfg(feature = "testkit")))]
+pub mod testkit;
+#[cfg(feature = "rt")]
+#[cfg_attr(
+    quicrpc_docsrs,
+    doc(cfg(any(feature = "rt-tokio", feature = "rt-smol", feature = "rt-async-std")))
+)]
+pub mod throttle;
+#[cfg(feature = "websocket-transport")]
+#[cfg_attr(quicrpc_docsrs, doc(cfg(feature = "websocket-transport")))]
+pub mod websocket;
 
+#[cfg(any(feature = "quinn-transport", feature = "iroh-transport"))]
+#[cfg_attr(
+    quicrpc_docsrs,
+    doc(cfg(any(feature = "quinn-transport", feature = "iroh-transport")))
+)]
+pub mod stream_limit;
 #[cfg(any(feature = "quinn-transport", feature = "iroh-transport"))]
 #[cfg_attr(
     quicrpc_docsrs,
@@ -92,6 +170,54 @@ pub trait Connector: StreamTypes {
         &self,
     ) -> impl Future<Output = Result<(Self::SendSink, Self::RecvStream), Self::OpenError>> + Send;
 
+    /// Open a unidirectional channel to the remote
+    ///
+    /// Use this when the remote does not respond, to avoid the cost of a return
+    /// stream on transports that have unidirectional substreams. The remote accepts
+    /// these with [`Listener::accept_uni`].
+    ///
+    /// The default implementation opens a bidirectional channel and drops its
+    /// receive side, so the remote accepts it with [`Listener::accept`] like any
+    /// other channel.
+    fn open_uni(&self) -> impl Future<Output = Result<Self::SendSink, Self::OpenError>> + Send {
+        async move {
+            let (send, _recv) = self.open().await?;
+            Ok(send)
+        }
+    }
+
+    /// Open a channel to the remote with a priority hint
+    ///
+    /// On transports that multiplex substreams over a single connection, data of
+    /// channels with a higher priority is sent before data of channels with a lower
+    /// priority. The default priority is 0, and the priority only affects the
+    /// sending side of the channel.
+    ///
+    /// The default implementation ignores the priority and calls [`Connector::open`].
+    fn open_with_priority(
+        &self,
+        priority: i32,
+    ) -> impl Future<Output = Result<(Self::SendSink, Self::RecvStream), Self::OpenError>> + Send
+    {
+        let _ = priority;
+        self.open()
+    }
+
+    /// Send a message as an unreliable datagram, without opening a channel
+    ///
+    /// Returns the message if it could not be sent as a datagram, e.g. because the
+    /// transport does not support datagrams or the message is too large, so the
+    /// caller can send it on a channel instead. A datagram that was sent may still
+    /// be lost, duplicated or reordered.
+    ///
+    /// The default implementation does not support datagrams.
+    fn send_datagram(
+        &self,
+        msg: Self::Out,
+    ) -> impl Future<Output = Result<Option<Self::Out>, Self::SendError>> + Send {
+        futures_lite::future::ready(Ok(Some(msg)))
+    }
+
     /// Map the input and output types of this connection
     fn map<In1, Out1>(self) -> MappedConnector<In1, Out1, Self>
     where
@@ -124,6 +250,27 @@ pub trait Listener: StreamTypes {
     /// The local addresses this endpoint is bound to.
     fn local_addr(&self) -> &[LocalAddr];
 
+    /// Accept a unidirectional channel opened with [`Connector::open_uni`] on any
+    /// of the connections we have currently opened.
+    ///
+    /// The default implementation never completes. Transports without
+    /// unidirectional substreams emulate them with bidirectional channels, which
+    /// are returned by [`Listener::accept`].
+    fn accept_uni(
+        &self,
+    ) -> impl Future<Output = Result<Self::RecvStream, Self::AcceptError>> + Send {
+        futures_lite::future::pending()
+    }
+
+    /// Receive the next datagram sent with [`Connector::send_datagram`] on any of
+    /// the connections we have currently opened.
+    ///
+    /// The default implementation never completes, for transports that do not
+    /// support datagrams.
+    fn recv_datagram(&self) -> impl Future<Output = Result<Self::In, Self::RecvError>> + Send {
+        futures_lite::future::pending()
+    }
+
     /// Box the listener
     fn boxed(self) -> BoxedListener<Self::In, Self::Out>
     where
@@ -155,3 +302,82 @@ impl Display for LocalAddr {
         }
     }
 }
+
+/// The identity of the remote side of a channel, as authenticated by the transport
+///
+/// Returned by [PeerInfo::peer_identity].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PeerIdentity {
+    /// The certificate chain the remote presented in the TLS handshake
+    ///
+    /// The certificates are DER encoded, starting with the certificate of the
+    /// remote itself. For a server, this is only available if the TLS config
+    /// requires client certificates.
+    Certificates(Arc<[Vec<u8>]>),
+    /// The node id of an iroh node, and the ALPN the connection was made for
+    #[cfg(feature = "iroh-transport")]
+    #[cfg_attr(quicrpc_docsrs, doc(cfg(feature = "iroh-transport")))]
+    Node {
+        /// The public key the remote authenticated with
+        node_id: ::iroh::NodeId,
+        /// The application protocol of the connection
+        alpn: Vec<u8>,
+    },
+}
+
+/// Receive streams of channels that know who is on the other side
+///
+/// Used by [RpcChannel::peer_identity](crate::server::RpcChannel::peer_identity).
+pub trait PeerInfo {
+    /// The authenticated identity of the remote, if any
+    fn peer_identity(&self) -> Option<&PeerIdentity>;
+}
+
+/// A change in the lifecycle of the connection behind a [Connector]
+///
+/// Returned by [ConnectionEvents::connection_events].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum ConnectionEvent {
+    /// The connector is trying to establish a connection
+    Connecting,
+    /// A connection is established
+    Connected,
+    /// A connection is established, but only reaches the remote through a relay
+    Relayed,
+    /// The connection was lost, or the attempt to establish one failed
+    Disconnected(Arc<anyhow::Error>),
+}
+
+/// Connectors that report the lifecycle of their connection
+///
+/// This lets applications show the status of the connection, instead of
+/// inferring it from failed calls.
+pub trait ConnectionEvents {
+    /// Watch the lifecycle of the connection
+    ///
+    /// The value of the watch is the most recent event, so it also tells the
+    /// current state of the connection.
+    fn connection_events(&self) -> tokio::sync::watch::Receiver<ConnectionEvent>;
+
+    /// A stream of the lifecycle events of the connection
+    ///
+    /// The stream starts with the current state. Events that happen in quick
+    /// succession may be skipped, but the last event is always yielded. The stream
+    /// ends when the connector and all its clones are dropped.
+    fn connection_event_stream(
+        &self,
+    ) -> impl Stream<Item = ConnectionEvent> + Send + Unpin + 'static {
+        let mut events = self.connection_events();
+        events.mark_changed();
+        Box::pin(futures_lite::stream::unfold(
+            events,
+            |mut events| async move {
+                events.changed().await.ok()?;
+                let event = events.borrow_and_update().clone();
+                Some((event, events))
+            },
+        ))
+    }
+}
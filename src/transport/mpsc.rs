@@ -0,0 +1,293 @@
+//! Memory transport implementation using [tokio::sync::mpsc]
+//!
+//! This has the same api as the [flume](super::flume) transport, but only
+//! depends on tokio, which is needed anyway. Since the channels are plain tokio
+//! channels, tasks waiting on them show up in `tokio-console` like any other.
+use std::{error, fmt, pin::Pin, result, sync::Arc, task::Poll};
+
+use futures_lite::{Future, Stream};
+use futures_sink::Sink;
+use tokio::sync::{mpsc, Mutex};
+use tokio_util::sync::PollSender;
+
+use super::StreamTypes;
+use crate::{
+    transport::{ConnectionErrors, Connector, Listener, LocalAddr},
+    RpcMessage,
+};
+
+/// Buffer size of the channels for a single substream
+const SUBSTREAM_BUFFER: usize = 128;
+
+/// Error when receiving from a channel
+///
+/// This type has zero inhabitants, so it is always safe to unwrap a result with this error type.
+#[derive(Debug)]
+pub enum RecvError {}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl error::Error for RecvError {}
+
+/// Sink for memory channels
+pub struct SendSink<T: RpcMessage>(PollSender<T>);
+
+impl<T: RpcMessage> fmt::Debug for SendSink<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SendSink").finish()
+    }
+}
+
+impl<T: RpcMessage> Sink<T> for SendSink<T> {
+    type Error = self::SendError;
+
+    fn poll_ready(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.0)
+            .poll_ready(cx)
+            .map_err(|_| SendError::ReceiverDropped)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        Pin::new(&mut self.0)
+            .start_send(item)
+            .map_err(|_| SendError::ReceiverDropped)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.0)
+            .poll_flush(cx)
+            .map_err(|_| SendError::ReceiverDropped)
+    }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.0)
+            .poll_close(cx)
+            .map_err(|_| SendError::ReceiverDropped)
+    }
+}
+
+/// Stream for memory channels
+pub struct RecvStream<T: RpcMessage>(mpsc::Receiver<T>);
+
+impl<T: RpcMessage> fmt::Debug for RecvStream<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RecvStream").finish()
+    }
+}
+
+impl<T: RpcMessage> Stream for RecvStream<T> {
+    type Item = result::Result<T, self::RecvError>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        self.0.poll_recv(cx).map(|item| item.map(Ok))
+    }
+}
+
+type Socket<In, Out> = (self::SendSink<Out>, self::RecvStream<In>);
+
+/// A tokio mpsc based listener.
+///
+/// Created using [channel].
+pub struct MpscListener<In: RpcMessage, Out: RpcMessage> {
+    // the receiver can not be cloned, so clones of the listener share it
+    stream: Arc<Mutex<mpsc::Receiver<Socket<In, Out>>>>,
+}
+
+impl<In: RpcMessage, Out: RpcMessage> Clone for MpscListener<In, Out> {
+    fn clone(&self) -> Self {
+        Self {
+            stream: self.stream.clone(),
+        }
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage> fmt::Debug for MpscListener<In, Out> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MpscListener").finish()
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage> ConnectionErrors for MpscListener<In, Out> {
+    type SendError = self::SendError;
+    type RecvError = self::RecvError;
+    type OpenError = self::OpenError;
+    type AcceptError = self::AcceptError;
+}
+
+impl<In: RpcMessage, Out: RpcMessage> StreamTypes for MpscListener<In, Out> {
+    type In = In;
+    type Out = Out;
+    type SendSink = SendSink<Out>;
+    type RecvStream = RecvStream<In>;
+}
+
+impl<In: RpcMessage, Out: RpcMessage> Listener for MpscListener<In, Out> {
+    fn accept(&self) -> impl Future<Output = result::Result<Socket<In, Out>, AcceptError>> + Send {
+        let stream = self.stream.clone();
+        async move {
+            stream
+                .lock()
+                .await
+                .recv()
+                .await
+                .ok_or(AcceptError::RemoteDropped)
+        }
+    }
+
+    fn local_addr(&self) -> &[LocalAddr] {
+        &[LocalAddr::Mem]
+    }
+}
+
+/// A tokio mpsc based connector.
+///
+/// Created using [channel].
+pub struct MpscConnector<In: RpcMessage, Out: RpcMessage> {
+    sink: mpsc::Sender<Socket<Out, In>>,
+}
+
+impl<In: RpcMessage, Out: RpcMessage> Clone for MpscConnector<In, Out> {
+    fn clone(&self) -> Self {
+        Self {
+            sink: self.sink.clone(),
+        }
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage> fmt::Debug for MpscConnector<In, Out> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MpscConnector")
+            .field("sink", &self.sink)
+            .finish()
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage> ConnectionErrors for MpscConnector<In, Out> {
+    type SendError = self::SendError;
+    type RecvError = self::RecvError;
+    type OpenError = self::OpenError;
+    type AcceptError = self::AcceptError;
+}
+
+impl<In: RpcMessage, Out: RpcMessage> StreamTypes for MpscConnector<In, Out> {
+    type In = In;
+    type Out = Out;
+    type SendSink = SendSink<Out>;
+    type RecvStream = RecvStream<In>;
+}
+
+impl<In: RpcMessage, Out: RpcMessage> Connector for MpscConnector<In, Out> {
+    fn open(&self) -> impl Future<Output = result::Result<Socket<In, Out>, OpenError>> + Send {
+        let (local_send, remote_recv) = mpsc::channel::<Out>(SUBSTREAM_BUFFER);
+        let (remote_send, local_recv) = mpsc::channel::<In>(SUBSTREAM_BUFFER);
+        let remote_chan = (
+            SendSink(PollSender::new(remote_send)),
+            RecvStream(remote_recv),
+        );
+        let local_chan = (
+            SendSink(PollSender::new(local_send)),
+            RecvStream(local_recv),
+        );
+        let sink = self.sink.clone();
+        async move {
+            sink.send(remote_chan)
+                .await
+                .map_err(|_| OpenError::RemoteDropped)?;
+            Ok(local_chan)
+        }
+    }
+}
+
+/// AcceptError for mpsc channels.
+///
+/// There is not much that can go wrong with mpsc channels.
+#[derive(Debug)]
+pub enum AcceptError {
+    /// The remote side of the channel was dropped
+    RemoteDropped,
+}
+
+impl fmt::Display for AcceptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl error::Error for AcceptError {}
+
+/// SendError for mpsc channels.
+///
+/// There is not much that can go wrong with mpsc channels.
+#[derive(Debug)]
+pub enum SendError {
+    /// Receiver was dropped
+    ReceiverDropped,
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl error::Error for SendError {}
+
+/// OpenError for mpsc channels.
+#[derive(Debug)]
+pub enum OpenError {
+    /// The remote side of the channel was dropped
+    RemoteDropped,
+}
+
+impl fmt::Display for OpenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl error::Error for OpenError {}
+
+/// Create a mpsc listener and a connected mpsc connector.
+///
+/// `buffer` the size of the buffer for new substreams. Keep this at a low value to get
+/// backpressure. Unlike flume, tokio channels need a buffer of at least 1, so 0 is
+/// treated as 1.
+pub fn channel<Req: RpcMessage, Res: RpcMessage>(
+    buffer: usize,
+) -> (MpscListener<Req, Res>, MpscConnector<Res, Req>) {
+    let (sink, stream) = mpsc::channel(buffer.max(1));
+    (
+        MpscListener {
+            stream: Arc::new(Mutex::new(stream)),
+        },
+        MpscConnector { sink },
+    )
+}
+
+#[cfg(all(test, feature = "testkit"))]
+mod tests {
+    use super::*;
+    use crate::transport::testkit;
+
+    #[tokio::test]
+    async fn mpsc_passes() -> anyhow::Result<()> {
+        let (listener, connector) = channel(1);
+        testkit::run(connector, listener).await
+    }
+}
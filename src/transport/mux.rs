@@ -0,0 +1,898 @@
+//! Multiplexing of substreams over a single connection
+//!
+//! Transports without cheap substreams, like tcp, stdio or a serial port, use a
+//! [Mux] to run concurrent requests over one connection. The connection can be a
+//! byte stream, see [Mux::new], or anything that already preserves message
+//! boundaries, like a WebSocket, see [Mux::with_frames].
+//!
+//! Every frame starts with a kind byte and the id of the substream as a 4 byte
+//! big endian integer, followed by the payload. On a byte stream, frames are
+//! prefixed with their length as a 4 byte big endian integer. Substreams opened
+//! by the dialing side have even ids, substreams opened by the accepting side
+//! have odd ids.
+//!
+//! # Checksums
+//!
+//! Byte streams without integrity protection of their own, like a serial port,
+//! can corrupt or lose bytes, and a single corrupted length prefix turns all
+//! following frames into garbage. With [MuxConfig::checksums], every frame is
+//! followed by the CRC-32 of its length prefix and content, as a 4 byte big
+//! endian integer. A frame with a wrong checksum closes the connection, and the
+//! receive streams fail with [ChecksumMismatch]. Both sides have to enable
+//! checksums.
+//!
+//! # Flow control
+//!
+//! Each substream has a window of data frames the sender may send before the
+//! receiver has consumed them. The window starts at [INITIAL_WINDOW] frames, so
+//! a request can be sent without waiting for the remote. The receiver extends
+//! the window to its configured size, and grants more credits as items are
+//! consumed. A receive stream that is not polled therefore only stops its own
+//! sender, not the other substreams of the connection.
+use std::{
+    collections::HashMap,
+    fmt, io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures_lite::{Stream, StreamExt};
+use futures_sink::Sink;
+use futures_util::SinkExt;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::{Decoder, Encoder, Framed, LengthDelimitedCodec};
+use tracing::{debug, trace};
+
+/// The number of data frames a sender may send on a new substream
+///
+/// This is fixed, so the first items of a substream can be sent before the
+/// remote granted any credits.
+pub const INITIAL_WINDOW: u32 = 16;
+
+const OPEN: u8 = 0;
+const DATA: u8 = 1;
+const FINISH: u8 = 2;
+const CREDIT: u8 = 3;
+const STOP: u8 = 4;
+const HEADER_LEN: usize = 5;
+const CHECKSUM_LEN: usize = 4;
+const CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+#[derive(Debug)]
+enum Frame {
+    /// Open a substream, granting credits in addition to the initial window
+    Open(u32, u32),
+    Data(u32, Bytes),
+    Finish(u32),
+    /// Grant credits for more data frames
+    Credit(u32, u32),
+    /// The receive stream is gone, stop sending
+    Stop(u32),
+}
+
+impl Frame {
+    fn encode(self) -> Bytes {
+        let (kind, id, payload) = match self {
+            Self::Open(id, credits) => (OPEN, id, Bytes::copy_from_slice(&credits.to_be_bytes())),
+            Self::Data(id, payload) => (DATA, id, payload),
+            Self::Finish(id) => (FINISH, id, Bytes::new()),
+            Self::Credit(id, credits) => {
+                (CREDIT, id, Bytes::copy_from_slice(&credits.to_be_bytes()))
+            }
+            Self::Stop(id) => (STOP, id, Bytes::new()),
+        };
+        let mut buf = BytesMut::with_capacity(HEADER_LEN + payload.len());
+        buf.put_u8(kind);
+        buf.put_u32(id);
+        buf.put_slice(&payload);
+        buf.freeze()
+    }
+
+    fn decode(mut buf: Bytes) -> io::Result<Self> {
+        if buf.len() < HEADER_LEN {
+            return Err(invalid_data("truncated frame header"));
+        }
+        let kind = buf.get_u8();
+        let id = buf.get_u32();
+        let credits = |mut buf: Bytes| {
+            if buf.len() != 4 {
+                return Err(invalid_data("invalid credits"));
+            }
+            Ok(buf.get_u32())
+        };
+        match kind {
+            OPEN => Ok(Self::Open(id, credits(buf)?)),
+            DATA => Ok(Self::Data(id, buf)),
+            FINISH => Ok(Self::Finish(id)),
+            CREDIT => Ok(Self::Credit(id, credits(buf)?)),
+            STOP => Ok(Self::Stop(id)),
+            _ => Err(invalid_data(format!("unknown frame kind {kind}"))),
+        }
+    }
+}
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// A frame was corrupted on the way, see [MuxConfig::checksums]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChecksumMismatch;
+
+impl fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl std::error::Error for ChecksumMismatch {}
+
+/// Length prefixed frames on a byte stream, optionally with a checksum
+#[derive(Debug)]
+struct FrameCodec {
+    inner: LengthDelimitedCodec,
+    checksums: bool,
+}
+
+impl FrameCodec {
+    fn new(config: &MuxConfig) -> Self {
+        let mut max_frame_length = config.max_payload_size + HEADER_LEN;
+        if config.checksums {
+            max_frame_length += CHECKSUM_LEN;
+        }
+        let inner = LengthDelimitedCodec::builder()
+            .max_frame_length(max_frame_length)
+            .new_codec();
+        Self {
+            inner,
+            checksums: config.checksums,
+        }
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = Bytes;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Bytes>> {
+        if !self.checksums {
+            return Ok(self.inner.decode(src)?.map(BytesMut::freeze));
+        }
+        let Some(prefix) = src.get(..4).map(<[u8]>::to_vec) else {
+            return Ok(None);
+        };
+        let Some(mut frame) = self.inner.decode(src)? else {
+            return Ok(None);
+        };
+        if frame.len() < CHECKSUM_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, ChecksumMismatch));
+        }
+        let checksum = frame.split_off(frame.len() - CHECKSUM_LEN).get_u32();
+        let mut digest = CRC.digest();
+        digest.update(&prefix);
+        digest.update(&frame);
+        if digest.finalize() != checksum {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, ChecksumMismatch));
+        }
+        Ok(Some(frame.freeze()))
+    }
+}
+
+impl Encoder<Bytes> for FrameCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, frame: Bytes, dst: &mut BytesMut) -> io::Result<()> {
+        if !self.checksums {
+            return self.inner.encode(frame, dst);
+        }
+        let start = dst.len();
+        let mut data = BytesMut::with_capacity(frame.len() + CHECKSUM_LEN);
+        data.put_slice(&frame);
+        // room for the checksum, so the length prefix is right
+        data.put_u32(0);
+        self.inner.encode(data.freeze(), dst)?;
+        let end = dst.len() - CHECKSUM_LEN;
+        let checksum = CRC.checksum(&dst[start..end]);
+        dst[end..].copy_from_slice(&checksum.to_be_bytes());
+        Ok(())
+    }
+}
+
+fn connection_closed() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "connection closed")
+}
+
+/// Which side of a connection a [Mux] is on
+///
+/// This decides the ids of the substreams the side opens, so the two sides of a
+/// connection must use different roles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// The side that opened the underlying connection
+    Dialer,
+    /// The side that accepted the underlying connection
+    Acceptor,
+}
+
+/// Configuration of a [Mux]
+#[derive(Debug, Clone, Copy)]
+pub struct MuxConfig {
+    max_payload_size: usize,
+    window: u32,
+    checksums: bool,
+}
+
+impl Default for MuxConfig {
+    fn default() -> Self {
+        Self {
+            max_payload_size: 1024 * 1024 * 16,
+            window: 32,
+            checksums: false,
+        }
+    }
+}
+
+impl MuxConfig {
+    /// The maximum size of the payload of a data frame
+    ///
+    /// This is only used for byte streams, see [Mux::new].
+    pub fn max_payload_size(mut self, value: usize) -> Self {
+        self.max_payload_size = value;
+        self
+    }
+
+    /// The number of data frames per substream that are buffered until the
+    /// receive stream consumes them
+    ///
+    /// Values below [INITIAL_WINDOW] are raised to it.
+    pub fn window(mut self, value: u32) -> Self {
+        self.window = value.max(INITIAL_WINDOW);
+        self
+    }
+
+    /// Whether every frame carries a CRC-32 checksum, see [checksums](self#checksums)
+    ///
+    /// Off by default. This is only used for byte streams, see [Mux::new].
+    pub fn checksums(mut self, value: bool) -> Self {
+        self.checksums = value;
+        self
+    }
+}
+
+#[derive(Debug)]
+enum Item {
+    Data(Bytes),
+    Finish,
+}
+
+/// Send credits of a substream
+#[derive(Debug, Default)]
+struct Credits(Mutex<CreditState>);
+
+#[derive(Debug, Default)]
+struct CreditState {
+    available: u32,
+    stopped: bool,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+impl Credits {
+    fn update(&self, f: impl FnOnce(&mut CreditState)) {
+        let mut state = self.0.lock().unwrap();
+        f(&mut state);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Streams {
+    /// Senders for the receive streams of all open substreams
+    receivers: HashMap<u32, flume::Sender<Item>>,
+    /// Credits for the send sinks of all open substreams
+    senders: HashMap<u32, Arc<Credits>>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    streams: Mutex<Streams>,
+    /// Set once the read side of the connection is gone
+    closed: AtomicBool,
+    /// Set if the connection was closed because of a corrupted frame
+    corrupted: AtomicBool,
+    window: u32,
+}
+
+impl Shared {
+    /// Create a substream, with `credits` in addition to the initial window
+    fn register(
+        self: &Arc<Self>,
+        id: u32,
+        credits: u32,
+        data: flume::Sender<Frame>,
+        control: flume::Sender<Frame>,
+    ) -> io::Result<Substream> {
+        let mut streams = self.streams.lock().unwrap();
+        if self.closed.load(Ordering::Acquire) {
+            return Err(connection_closed());
+        }
+        // one more than the window, so there is always room for the finish
+        let (sender, receiver) = flume::bounded(self.window as usize + 1);
+        streams.receivers.insert(id, sender);
+        let credits = Arc::new(Credits(Mutex::new(CreditState {
+            available: INITIAL_WINDOW.saturating_add(credits),
+            ..Default::default()
+        })));
+        streams.senders.insert(id, credits.clone());
+        let send = MuxSendSink {
+            id,
+            shared: self.clone(),
+            credits,
+            sink: data.into_sink(),
+            finished: false,
+        };
+        let recv = MuxRecvStream {
+            id,
+            shared: self.clone(),
+            recv: receiver.into_stream(),
+            control: Some(control),
+            unreported: 0,
+            done: false,
+        };
+        Ok((send, recv))
+    }
+
+    /// Pass an item to a receive stream, returns false if there is none
+    fn deliver(&self, id: u32, item: Item) -> io::Result<bool> {
+        let mut streams = self.streams.lock().unwrap();
+        let finish = matches!(item, Item::Finish);
+        let Some(sender) = streams.receivers.get(&id) else {
+            trace!("dropping frame for unknown substream {id}");
+            return Ok(false);
+        };
+        match sender.try_send(item) {
+            Ok(()) => {}
+            // the receive stream was dropped in the meantime
+            Err(flume::TrySendError::Disconnected(_)) => {
+                streams.receivers.remove(&id);
+                return Ok(false);
+            }
+            Err(flume::TrySendError::Full(_)) => {
+                return Err(invalid_data(format!(
+                    "remote exceeded the window of substream {id}"
+                )));
+            }
+        }
+        if finish {
+            streams.receivers.remove(&id);
+        }
+        Ok(true)
+    }
+
+    fn credits(&self, id: u32) -> Option<Arc<Credits>> {
+        self.streams.lock().unwrap().senders.get(&id).cloned()
+    }
+
+    fn close(&self) {
+        let mut streams = self.streams.lock().unwrap();
+        self.closed.store(true, Ordering::Release);
+        streams.receivers.clear();
+        for (_, credits) in streams.senders.drain() {
+            credits.update(|state| state.closed = true);
+        }
+    }
+}
+
+/// A substream of a multiplexed connection
+pub type Substream = (MuxSendSink, MuxRecvStream);
+
+/// A connection that is multiplexed into substreams
+///
+/// The connection is closed once the mux and all its substreams are dropped.
+pub struct Mux {
+    shared: Arc<Shared>,
+    /// Open, data and finish frames, in order
+    data: flume::Sender<Frame>,
+    /// Credit and stop frames, which are sent first
+    control: flume::Sender<Frame>,
+    incoming: flume::Receiver<Substream>,
+    next_id: AtomicU32,
+}
+
+impl fmt::Debug for Mux {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mux")
+            .field("closed", &self.is_closed())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Mux {
+    /// Multiplex a byte stream, using length prefixed frames
+    pub fn new<T>(io: T, role: Role, config: MuxConfig) -> Self
+    where
+        T: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let frames = Framed::new(io, FrameCodec::new(&config));
+        Self::with_frames(frames, role, config)
+    }
+
+    /// Multiplex a connection that preserves frame boundaries
+    ///
+    /// Every item of the stream is one frame. The connection is closed when the
+    /// stream ends, and the sink is closed once the mux and all its substreams
+    /// are dropped.
+    pub fn with_frames<F>(frames: F, role: Role, config: MuxConfig) -> Self
+    where
+        F: Stream<Item = io::Result<Bytes>> + Sink<Bytes, Error = io::Error> + Send + 'static,
+    {
+        let (sink, stream) = futures_util::StreamExt::split(frames);
+        let shared = Arc::new(Shared {
+            streams: Default::default(),
+            closed: AtomicBool::new(false),
+            corrupted: AtomicBool::new(false),
+            window: config.window,
+        });
+        let (data, data_rx) = flume::bounded(64);
+        let (control, control_rx) = flume::unbounded();
+        let (incoming_tx, incoming) = flume::bounded(16);
+        tokio::spawn(write_frames(sink, data_rx, control_rx));
+        tokio::spawn(read_frames(
+            stream,
+            shared.clone(),
+            data.downgrade(),
+            control.downgrade(),
+            incoming_tx,
+        ));
+        let first_id = match role {
+            Role::Dialer => 0,
+            Role::Acceptor => 1,
+        };
+        Self {
+            shared,
+            data,
+            control,
+            incoming,
+            next_id: AtomicU32::new(first_id),
+        }
+    }
+
+    /// Open a new substream
+    pub async fn open(&self) -> io::Result<Substream> {
+        let id = self.next_id.fetch_add(2, Ordering::Relaxed);
+        let substream = self
+            .shared
+            .register(id, 0, self.data.clone(), self.control.clone())?;
+        let credits = self.shared.window - INITIAL_WINDOW;
+        self.data
+            .send_async(Frame::Open(id, credits))
+            .await
+            .map_err(|_| connection_closed())?;
+        Ok(substream)
+    }
+
+    /// Accept a substream opened by the remote
+    ///
+    /// Returns `None` once the connection is closed.
+    pub async fn accept(&self) -> Option<Substream> {
+        self.incoming.recv_async().await.ok()
+    }
+
+    /// Whether the connection is closed, so no new substreams can be opened
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.load(Ordering::Acquire) || self.data.is_disconnected()
+    }
+}
+
+async fn write_frames<S>(mut sink: S, data: flume::Receiver<Frame>, control: flume::Receiver<Frame>)
+where
+    S: Sink<Bytes, Error = io::Error> + Unpin,
+{
+    let res = async {
+        let mut data_open = true;
+        let mut control_open = true;
+        // the mux and the substreams hold on to the senders, so this runs until
+        // nobody can send anything anymore
+        while data_open || control_open {
+            let frame = tokio::select! {
+                biased;
+                frame = control.recv_async(), if control_open => frame,
+                frame = data.recv_async(), if data_open => frame,
+            };
+            let Ok(frame) = frame else {
+                data_open &= !data.is_disconnected() || !data.is_empty();
+                control_open &= !control.is_disconnected() || !control.is_empty();
+                continue;
+            };
+            sink.feed(frame.encode()).await?;
+            // write everything that is queued before flushing
+            while let Ok(frame) = control.try_recv().or_else(|_| data.try_recv()) {
+                sink.feed(frame.encode()).await?;
+            }
+            sink.flush().await?;
+        }
+        sink.close().await
+    }
+    .await;
+    if let Err(cause) = res {
+        debug!("error writing frames: {cause}");
+    }
+}
+
+async fn read_frames<S>(
+    mut stream: S,
+    shared: Arc<Shared>,
+    data: flume::WeakSender<Frame>,
+    control: flume::WeakSender<Frame>,
+    incoming: flume::Sender<Substream>,
+) where
+    S: Stream<Item = io::Result<Bytes>> + Unpin,
+{
+    let res = async {
+        while let Some(frame) = stream.next().await {
+            match Frame::decode(frame?)? {
+                Frame::Open(id, credits) => {
+                    let (Some(data), Some(control)) = (data.upgrade(), control.upgrade()) else {
+                        // we are closing, so the substream could not send anything
+                        continue;
+                    };
+                    let substream = shared.register(id, credits, data, control.clone())?;
+                    let extra = shared.window - INITIAL_WINDOW;
+                    if extra > 0 {
+                        control.send(Frame::Credit(id, extra)).ok();
+                    }
+                    // if nobody accepts substreams, dropping it closes it
+                    incoming.send_async(substream).await.ok();
+                }
+                Frame::Data(id, payload) => {
+                    // a stop frame sent before the open frame arrived is ignored,
+                    // so repeat it until the remote stops sending
+                    if !shared.deliver(id, Item::Data(payload))? {
+                        if let Some(control) = control.upgrade() {
+                            control.send(Frame::Stop(id)).ok();
+                        }
+                    }
+                }
+                Frame::Finish(id) => {
+                    shared.deliver(id, Item::Finish)?;
+                }
+                Frame::Credit(id, credits) => {
+                    if let Some(state) = shared.credits(id) {
+                        state.update(|state| {
+                            state.available = state.available.saturating_add(credits)
+                        });
+                    }
+                }
+                Frame::Stop(id) => {
+                    if let Some(state) = shared.credits(id) {
+                        state.update(|state| state.stopped = true);
+                    }
+                }
+            }
+        }
+        io::Result::Ok(())
+    }
+    .await;
+    if let Err(cause) = res {
+        debug!("error reading frames: {cause}");
+        if cause
+            .get_ref()
+            .is_some_and(|inner| inner.is::<ChecksumMismatch>())
+        {
+            shared.corrupted.store(true, Ordering::Release);
+        }
+    }
+    shared.close();
+}
+
+/// The sending half of a substream
+///
+/// Sending waits until the remote granted credits. Sending fails with
+/// [io::ErrorKind::ConnectionReset] once the remote dropped its receive stream.
+pub struct MuxSendSink {
+    id: u32,
+    shared: Arc<Shared>,
+    credits: Arc<Credits>,
+    sink: flume::r#async::SendSink<'static, Frame>,
+    finished: bool,
+}
+
+impl fmt::Debug for MuxSendSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MuxSendSink")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MuxSendSink {
+    /// Mark the substream as finished, so no more credits are tracked for it
+    fn finish(&mut self) {
+        self.finished = true;
+        self.shared.streams.lock().unwrap().senders.remove(&self.id);
+    }
+}
+
+impl Drop for MuxSendSink {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        self.finish();
+        let frame = Frame::Finish(self.id);
+        if let Err(flume::TrySendError::Full(frame)) = self.sink.sender().try_send(frame) {
+            if let Ok(handle) = tokio::runtime::Handle::try_current() {
+                let sender = self.sink.sender().clone();
+                handle.spawn(async move {
+                    sender.send_async(frame).await.ok();
+                });
+            }
+        }
+    }
+}
+
+impl Sink<Bytes> for MuxSendSink {
+    type Error = io::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        {
+            let mut state = self.credits.0.lock().unwrap();
+            if state.stopped {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::ConnectionReset,
+                    "stopped by the remote",
+                )));
+            }
+            if state.closed {
+                return Poll::Ready(Err(connection_closed()));
+            }
+            if state.available == 0 {
+                state.waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+        }
+        Pin::new(&mut self.sink)
+            .poll_ready(cx)
+            .map_err(|_| connection_closed())
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Bytes) -> io::Result<()> {
+        {
+            let mut state = self.credits.0.lock().unwrap();
+            state.available = state.available.saturating_sub(1);
+        }
+        let frame = Frame::Data(self.id, item);
+        Pin::new(&mut self.sink)
+            .start_send(frame)
+            .map_err(|_| connection_closed())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.sink)
+            .poll_flush(cx)
+            .map_err(|_| connection_closed())
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if !self.finished {
+            // finishing does not need credits
+            futures_lite::ready!(Pin::new(&mut self.sink).poll_ready(cx))
+                .map_err(|_| connection_closed())?;
+            let frame = Frame::Finish(self.id);
+            Pin::new(&mut self.sink)
+                .start_send(frame)
+                .map_err(|_| connection_closed())?;
+            self.finish();
+        }
+        self.poll_flush(cx)
+    }
+}
+
+/// The receiving half of a substream
+///
+/// Dropping it before the substream is finished tells the remote to stop
+/// sending.
+pub struct MuxRecvStream {
+    id: u32,
+    shared: Arc<Shared>,
+    recv: flume::r#async::RecvStream<'static, Item>,
+    /// For granting credits, dropped once the substream is done
+    control: Option<flume::Sender<Frame>>,
+    /// Items consumed since credits were last granted
+    unreported: u32,
+    done: bool,
+}
+
+impl fmt::Debug for MuxRecvStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MuxRecvStream")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MuxRecvStream {
+    fn consumed(&mut self) {
+        self.unreported += 1;
+        if self.unreported < self.shared.window / 2 {
+            return;
+        }
+        if let Some(control) = &self.control {
+            let credits = std::mem::take(&mut self.unreported);
+            control.send(Frame::Credit(self.id, credits)).ok();
+        }
+    }
+
+    fn end(&mut self) {
+        self.done = true;
+        self.control = None;
+    }
+}
+
+impl Drop for MuxRecvStream {
+    fn drop(&mut self) {
+        let removed = self
+            .shared
+            .streams
+            .lock()
+            .unwrap()
+            .receivers
+            .remove(&self.id);
+        if let (Some(_), Some(control)) = (removed, &self.control) {
+            control.send(Frame::Stop(self.id)).ok();
+        }
+    }
+}
+
+impl Stream for MuxRecvStream {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        let item = futures_lite::ready!(self.recv.poll_next(cx));
+        Poll::Ready(match item {
+            Some(Item::Data(data)) => {
+                self.consumed();
+                Some(Ok(data))
+            }
+            Some(Item::Finish) => {
+                self.end();
+                None
+            }
+            None => {
+                self.end();
+                Some(Err(if self.shared.corrupted.load(Ordering::Acquire) {
+                    io::Error::new(io::ErrorKind::InvalidData, ChecksumMismatch)
+                } else {
+                    io::Error::new(io::ErrorKind::ConnectionAborted, "connection lost")
+                }))
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn pair(window: u32) -> (Mux, Mux) {
+        let (a, b) = tokio::io::duplex(1024 * 64);
+        let config = MuxConfig::default().window(window);
+        (
+            Mux::new(a, Role::Dialer, config),
+            Mux::new(b, Role::Acceptor, config),
+        )
+    }
+
+    #[tokio::test]
+    async fn slow_receiver_does_not_block_other_substreams() -> anyhow::Result<()> {
+        let (a, b) = pair(INITIAL_WINDOW);
+        let (mut slow, _slow_recv) = a.open().await?;
+        let (_slow_send, _slow_remote) = b.accept().await.unwrap();
+        for i in 0..INITIAL_WINDOW {
+            slow.send(Bytes::from(i.to_string())).await?;
+        }
+        // the window is exhausted, so the next send waits for credits
+        let res = tokio::time::timeout(Duration::from_millis(50), slow.send(Bytes::new())).await;
+        assert!(res.is_err());
+
+        let (mut fast, _fast_recv) = a.open().await?;
+        let (_fast_send, mut fast_remote) = b.accept().await.unwrap();
+        fast.send(Bytes::from_static(b"hello")).await?;
+        assert_eq!(fast_remote.next().await.transpose()?.unwrap(), "hello");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn consuming_grants_credits() -> anyhow::Result<()> {
+        let (a, b) = pair(32);
+        let (mut send, _recv) = a.open().await?;
+        let (_remote_send, mut remote_recv) = b.accept().await.unwrap();
+        let n = 1000u32;
+        let sender = tokio::spawn(async move {
+            for i in 0..n {
+                send.send(Bytes::from(i.to_string())).await?;
+            }
+            io::Result::Ok(())
+        });
+        for i in 0..n {
+            let item = remote_recv.next().await.transpose()?.unwrap();
+            assert_eq!(item, i.to_string());
+        }
+        sender.await??;
+        assert!(remote_recv.next().await.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dropped_receiver_stops_sender() -> anyhow::Result<()> {
+        let (a, b) = pair(INITIAL_WINDOW);
+        let (mut send, _recv) = a.open().await?;
+        let (_remote_send, remote_recv) = b.accept().await.unwrap();
+        drop(remote_recv);
+        let err = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let Err(cause) = send.send(Bytes::new()).await {
+                    break cause;
+                }
+            }
+        })
+        .await?;
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn checksums_roundtrip() -> anyhow::Result<()> {
+        let (a, b) = tokio::io::duplex(1024 * 64);
+        let config = MuxConfig::default().checksums(true);
+        let a = Mux::new(a, Role::Dialer, config);
+        let b = Mux::new(b, Role::Acceptor, config);
+        let (mut send, _recv) = a.open().await?;
+        let (_remote_send, mut remote_recv) = b.accept().await.unwrap();
+        send.send(Bytes::from_static(b"hello")).await?;
+        send.send(Bytes::new()).await?;
+        assert_eq!(remote_recv.next().await.transpose()?.unwrap(), "hello");
+        assert_eq!(remote_recv.next().await.transpose()?.unwrap(), "");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn corrupted_frame_is_detected() -> anyhow::Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        let (mut raw, b) = tokio::io::duplex(1024 * 64);
+        let config = MuxConfig::default().checksums(true);
+        let b = Mux::new(b, Role::Acceptor, config);
+        let mut codec = FrameCodec::new(&config);
+        let mut buf = BytesMut::new();
+        codec.encode(Frame::Open(0, 0).encode(), &mut buf)?;
+        raw.write_all(&buf).await?;
+        let (_remote_send, mut remote_recv) = b.accept().await.unwrap();
+
+        buf.clear();
+        codec.encode(
+            Frame::Data(0, Bytes::from_static(b"hello")).encode(),
+            &mut buf,
+        )?;
+        // flip a bit in the payload
+        let last = buf.len() - CHECKSUM_LEN - 1;
+        buf[last] ^= 1;
+        raw.write_all(&buf).await?;
+        let err = remote_recv.next().await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.get_ref().unwrap().is::<ChecksumMismatch>());
+        Ok(())
+    }
+}
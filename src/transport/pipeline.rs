@@ -0,0 +1,598 @@
+//! Pipelining of requests over a single pooled substream.
+//!
+//! Opening a substream per request is cheap on QUIC, but dominates latency on
+//! transports where it costs a round trip, like a relayed iroh connection or the
+//! hyper transport. A [PipelineConnector] opens one substream of the underlying
+//! connector when it is first used, and runs all substreams opened on it as
+//! virtual substreams over it. A [PipelineListener] splits them up again on the
+//! server side, so handlers do not notice the difference.
+//!
+//! Every message is wrapped in a [Frame] with the id of its virtual substream.
+//! A frame without a message closes the sending side of the virtual substream.
+//! Ids are assigned by the client in increasing order.
+//!
+//! All virtual substreams share the flow control of the pooled substream. A
+//! virtual substream whose receive side is not polled blocks the others once its
+//! buffer of [BUFFER] messages is full, so this is best suited for short
+//! requests. If the pooled substream fails, all its virtual substreams fail,
+//! and the next substream opened on the connector opens a new pooled substream.
+//!
+//! Unidirectional substreams are virtual substreams as well, so the server
+//! accepts them with [Listener::accept]. Datagrams are sent as a [Frame] with id
+//! 0 on the underlying connector, outside of the pooled substream. Virtual
+//! substreams share the priority of the pooled substream, so
+//! [Connector::open_with_priority] ignores the priority.
+use std::{
+    collections::HashMap,
+    fmt,
+    marker::PhantomData,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+};
+
+use futures_lite::{Stream, StreamExt};
+use futures_sink::Sink;
+use futures_util::SinkExt;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, Mutex};
+use tokio_util::sync::PollSender;
+use tracing::{debug, warn};
+
+use super::{
+    boxed::{RecvStream, SendSink},
+    ConnectionErrors, Connector, Listener, LocalAddr, StreamTypes,
+};
+use crate::{rt, RpcMessage};
+
+/// Number of messages buffered per virtual substream and direction
+pub const BUFFER: usize = 16;
+
+/// A message of a virtual substream on the pooled substream
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Frame<T> {
+    /// Id of the virtual substream
+    pub id: u64,
+    /// The message, or `None` if the sender closed the virtual substream
+    pub msg: Option<T>,
+}
+
+/// Receivers of the virtual substreams, or `None` once the pooled substream ended
+type Routes<T> = Arc<std::sync::Mutex<Option<HashMap<u64, mpsc::Sender<anyhow::Result<T>>>>>>;
+
+type Channel<In, Out> = (SendSink<Out>, RecvStream<In>);
+
+/// Where the server side sends new virtual substreams
+type Incoming<In, Out> = mpsc::Sender<Channel<In, Out>>;
+
+/// One side of a pooled substream
+struct Pipe<In, Out> {
+    data: mpsc::Sender<Frame<Out>>,
+    close: mpsc::UnboundedSender<u64>,
+    routes: Routes<In>,
+    next_id: u64,
+}
+
+impl<In: RpcMessage, Out: RpcMessage> Pipe<In, Out> {
+    /// Spawn the task that writes the pooled substream
+    fn new<Si>(send: Si) -> Self
+    where
+        Si: Sink<Frame<Out>> + Send + 'static,
+    {
+        let (data, data_rx) = mpsc::channel(BUFFER);
+        let (close, close_rx) = mpsc::unbounded_channel();
+        rt::spawn_detached(write_loop(send, data_rx, close_rx));
+        Self {
+            data,
+            close,
+            routes: Arc::new(std::sync::Mutex::new(Some(HashMap::new()))),
+            next_id: 0,
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.routes.lock().unwrap().is_none()
+    }
+
+    /// Open a new virtual substream with the next id
+    fn open(&mut self) -> Channel<In, Out> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.open_id(id).0
+    }
+
+    fn open_id(&self, id: u64) -> (Channel<In, Out>, mpsc::Sender<anyhow::Result<In>>) {
+        let (route, rx) = mpsc::channel(BUFFER);
+        if let Some(routes) = self.routes.lock().unwrap().as_mut() {
+            routes.insert(id, route.clone());
+        }
+        let send = VirtualSendSink {
+            id,
+            data: PollSender::new(self.data.clone()),
+            close: self.close.clone(),
+        };
+        let recv = VirtualRecvStream {
+            id,
+            rx,
+            routes: self.routes.clone(),
+        };
+        ((SendSink::boxed(send), RecvStream::boxed(recv)), route)
+    }
+}
+
+async fn write_loop<Out, Si>(
+    send: Si,
+    mut data: mpsc::Receiver<Frame<Out>>,
+    mut close: mpsc::UnboundedReceiver<u64>,
+) where
+    Si: Sink<Frame<Out>>,
+{
+    let mut send = std::pin::pin!(send);
+    loop {
+        // data first, so a virtual substream is only closed after its messages are sent
+        let frame = tokio::select! {
+            biased;
+            Some(frame) = data.recv() => frame,
+            Some(id) = close.recv() => Frame { id, msg: None },
+            else => break,
+        };
+        if send.send(frame).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Read the pooled substream and dispatch the messages to the virtual substreams
+///
+/// On the server side, frames with a new id open a virtual substream on the pipe,
+/// which is sent to `incoming`. On the client side, they are dropped.
+async fn read_loop<In, Out, St>(
+    routes: Routes<In>,
+    recv: St,
+    mut incoming: Option<(Incoming<In, Out>, Pipe<In, Out>)>,
+) where
+    In: RpcMessage,
+    Out: RpcMessage,
+    St: Stream<Item = Result<Frame<In>, anyhow::Error>>,
+{
+    let mut recv = std::pin::pin!(recv);
+    while let Some(frame) = recv.next().await {
+        let Frame { id, msg } = match frame {
+            Ok(frame) => frame,
+            Err(cause) => {
+                let routes = routes.lock().unwrap().take().unwrap_or_default();
+                for route in routes.values() {
+                    route
+                        .try_send(Err(anyhow::anyhow!("pooled substream failed: {cause}")))
+                        .ok();
+                }
+                return;
+            }
+        };
+        let Some(msg) = msg else {
+            if let Some(routes) = routes.lock().unwrap().as_mut() {
+                routes.remove(&id);
+            }
+            continue;
+        };
+        let route = routes
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|r| r.get(&id).cloned());
+        let route = match (route, &mut incoming) {
+            (Some(route), _) => route,
+            // a new virtual substream from the client
+            (None, Some((incoming, pipe))) if id >= pipe.next_id => {
+                pipe.next_id = id + 1;
+                let (channel, route) = pipe.open_id(id);
+                if incoming.send(channel).await.is_err() {
+                    break;
+                }
+                route
+            }
+            (None, _) => {
+                debug!("dropping message for closed substream {id}");
+                continue;
+            }
+        };
+        // the receive side may be gone already
+        route.send(Ok(msg)).await.ok();
+    }
+    // end all virtual receive streams
+    routes.lock().unwrap().take();
+}
+
+/// Send side of a virtual substream
+struct VirtualSendSink<Out: Send + 'static> {
+    id: u64,
+    data: PollSender<Frame<Out>>,
+    close: mpsc::UnboundedSender<u64>,
+}
+
+impl<Out: Send + 'static> Drop for VirtualSendSink<Out> {
+    fn drop(&mut self) {
+        self.close.send(self.id).ok();
+    }
+}
+
+impl<Out: Send + 'static> Sink<Out> for VirtualSendSink<Out> {
+    type Error = anyhow::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        ready!(self.data.poll_reserve(cx)).map_err(|_| pipe_closed())?;
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, msg: Out) -> Result<(), Self::Error> {
+        let id = self.id;
+        self.data
+            .send_item(Frame { id, msg: Some(msg) })
+            .map_err(|_| pipe_closed())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Receive side of a virtual substream
+struct VirtualRecvStream<In> {
+    id: u64,
+    rx: mpsc::Receiver<anyhow::Result<In>>,
+    routes: Routes<In>,
+}
+
+impl<In> Drop for VirtualRecvStream<In> {
+    fn drop(&mut self) {
+        if let Some(routes) = self.routes.lock().unwrap().as_mut() {
+            routes.remove(&self.id);
+        }
+    }
+}
+
+impl<In> Stream for VirtualRecvStream<In> {
+    type Item = anyhow::Result<In>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+fn pipe_closed() -> anyhow::Error {
+    anyhow::anyhow!("pooled substream closed")
+}
+
+/// A connector that runs all substreams over a single pooled substream
+pub struct PipelineConnector<In, Out, C> {
+    inner: C,
+    pipe: Arc<Mutex<Option<Pipe<In, Out>>>>,
+}
+
+impl<In, Out, C> PipelineConnector<In, Out, C>
+where
+    C: Connector<In = Frame<In>, Out = Frame<Out>>,
+{
+    /// Create a new pipeline connector
+    ///
+    /// The pooled substream is opened when the first substream is opened.
+    pub fn new(inner: C) -> Self {
+        Self {
+            inner,
+            pipe: Default::default(),
+        }
+    }
+}
+
+impl<In, Out, C: Clone> Clone for PipelineConnector<In, Out, C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            pipe: self.pipe.clone(),
+        }
+    }
+}
+
+impl<In, Out, C: fmt::Debug> fmt::Debug for PipelineConnector<In, Out, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PipelineConnector")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<In, Out, C> ConnectionErrors for PipelineConnector<In, Out, C>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    C: ConnectionErrors,
+{
+    type SendError = anyhow::Error;
+    type RecvError = anyhow::Error;
+    type OpenError = anyhow::Error;
+    type AcceptError = anyhow::Error;
+}
+
+impl<In, Out, C> StreamTypes for PipelineConnector<In, Out, C>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    C: StreamTypes<In = Frame<In>, Out = Frame<Out>>,
+{
+    type In = In;
+    type Out = Out;
+    type RecvStream = RecvStream<In>;
+    type SendSink = SendSink<Out>;
+}
+
+impl<In, Out, C> Connector for PipelineConnector<In, Out, C>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    C: Connector<In = Frame<In>, Out = Frame<Out>>,
+{
+    async fn open(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        let mut pipe = self.pipe.lock().await;
+        let pipe = match pipe.as_mut() {
+            Some(current) if !current.is_closed() => current,
+            _ => {
+                let (send, recv) = self.inner.open().await.map_err(Into::into)?;
+                let send = send.sink_map_err(Into::<anyhow::Error>::into);
+                let recv = recv.map(|frame| frame.map_err(Into::<anyhow::Error>::into));
+                let new = Pipe::new(send);
+                rt::spawn_detached(read_loop::<In, Out, _>(new.routes.clone(), recv, None));
+                pipe.insert(new)
+            }
+        };
+        Ok(pipe.open())
+    }
+
+    /// Open a virtual substream and drop its receive side
+    ///
+    /// This is not forwarded to the inner connector, since a unidirectional
+    /// substream of its own would cost the round trip that pipelining avoids. The
+    /// server accepts it with [Listener::accept].
+    async fn open_uni(&self) -> Result<Self::SendSink, Self::OpenError> {
+        let (send, _recv) = self.open().await?;
+        Ok(send)
+    }
+
+    /// Open a virtual substream, ignoring the priority
+    ///
+    /// This is not forwarded to the inner connector, since all virtual substreams
+    /// share the priority of the pooled substream.
+    async fn open_with_priority(
+        &self,
+        _priority: i32,
+    ) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        self.open().await
+    }
+
+    async fn send_datagram(&self, msg: Self::Out) -> Result<Option<Self::Out>, Self::SendError> {
+        let frame = Frame {
+            id: 0,
+            msg: Some(msg),
+        };
+        let returned = self.inner.send_datagram(frame).await.map_err(Into::into)?;
+        Ok(returned.and_then(|frame| frame.msg))
+    }
+}
+
+/// A listener that splits up the pooled substreams of [PipelineConnector]s
+pub struct PipelineListener<In: RpcMessage, Out: RpcMessage> {
+    incoming: Arc<Mutex<mpsc::Receiver<Channel<In, Out>>>>,
+    datagrams: Arc<Mutex<mpsc::Receiver<anyhow::Result<In>>>>,
+    local_addr: Vec<LocalAddr>,
+    _tasks: Arc<[rt::Task<()>; 2]>,
+    _p: PhantomData<(In, Out)>,
+}
+
+impl<In: RpcMessage, Out: RpcMessage> PipelineListener<In, Out> {
+    /// Create a new pipeline listener
+    ///
+    /// This spawns tasks that accept pooled substreams and receive datagrams on
+    /// the listener. The tasks are stopped when the listener and all its clones
+    /// are dropped.
+    pub fn new<L>(inner: L) -> Self
+    where
+        L: Listener<In = Frame<In>, Out = Frame<Out>>,
+    {
+        let (tx, rx) = mpsc::channel(BUFFER);
+        let (datagram_tx, datagram_rx) = mpsc::channel(BUFFER);
+        let local_addr = inner.local_addr().to_vec();
+        let datagrams = rt::spawn(Self::datagram_loop(inner.clone(), datagram_tx));
+        let task = rt::spawn(Self::accept_loop(inner, tx));
+        Self {
+            incoming: Arc::new(Mutex::new(rx)),
+            datagrams: Arc::new(Mutex::new(datagram_rx)),
+            local_addr,
+            _tasks: Arc::new([task, datagrams]),
+            _p: PhantomData,
+        }
+    }
+
+    /// Queue the datagrams of the listener, dropping them if the queue is full
+    async fn datagram_loop<L>(inner: L, datagrams: mpsc::Sender<anyhow::Result<In>>)
+    where
+        L: Listener<In = Frame<In>, Out = Frame<Out>>,
+    {
+        loop {
+            match inner.recv_datagram().await {
+                Ok(Frame { msg: Some(msg), .. }) => {
+                    if let Err(mpsc::error::TrySendError::Closed(_)) = datagrams.try_send(Ok(msg)) {
+                        break;
+                    }
+                }
+                Ok(Frame { msg: None, .. }) => {}
+                // errors are not dropped, and waiting for room keeps this from spinning
+                Err(cause) => {
+                    if datagrams.send(Err(cause.into())).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn accept_loop<L>(inner: L, incoming: Incoming<In, Out>)
+    where
+        L: Listener<In = Frame<In>, Out = Frame<Out>>,
+    {
+        loop {
+            let (send, recv) = match inner.accept().await {
+                Ok(channel) => channel,
+                Err(cause) => {
+                    warn!("pipeline accept failed: {cause}");
+                    break;
+                }
+            };
+            let send = send.sink_map_err(Into::<anyhow::Error>::into);
+            let recv = recv.map(|frame| frame.map_err(Into::<anyhow::Error>::into));
+            let pipe = Pipe::new(send);
+            let routes = pipe.routes.clone();
+            rt::spawn_detached(read_loop(routes, recv, Some((incoming.clone(), pipe))));
+        }
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage> Clone for PipelineListener<In, Out> {
+    fn clone(&self) -> Self {
+        Self {
+            incoming: self.incoming.clone(),
+            datagrams: self.datagrams.clone(),
+            local_addr: self.local_addr.clone(),
+            _tasks: self._tasks.clone(),
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage> fmt::Debug for PipelineListener<In, Out> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PipelineListener")
+            .field("local_addr", &self.local_addr)
+            .finish()
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage> ConnectionErrors for PipelineListener<In, Out> {
+    type SendError = anyhow::Error;
+    type RecvError = anyhow::Error;
+    type OpenError = anyhow::Error;
+    type AcceptError = anyhow::Error;
+}
+
+impl<In: RpcMessage, Out: RpcMessage> StreamTypes for PipelineListener<In, Out> {
+    type In = In;
+    type Out = Out;
+    type RecvStream = RecvStream<In>;
+    type SendSink = SendSink<Out>;
+}
+
+impl<In: RpcMessage, Out: RpcMessage> Listener for PipelineListener<In, Out> {
+    async fn accept(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::AcceptError> {
+        self.incoming
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| anyhow::anyhow!("pipeline listener closed"))
+    }
+
+    fn local_addr(&self) -> &[LocalAddr] {
+        &self.local_addr
+    }
+
+    async fn recv_datagram(&self) -> Result<Self::In, Self::RecvError> {
+        self.datagrams
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| anyhow::anyhow!("pipeline listener closed"))?
+    }
+}
+
+#[cfg(all(test, feature = "flume-transport"))]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::{message::RpcMsg, transport::flume, RpcClient, RpcServer, Service};
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Ping(u64);
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Pong(u64);
+
+    #[derive(Debug, Clone)]
+    struct PingService;
+
+    impl Service for PingService {
+        type Req = Ping;
+        type Res = Pong;
+    }
+
+    impl RpcMsg<PingService> for Ping {
+        type Response = Pong;
+    }
+
+    /// Counts the substreams opened on the underlying connector
+    #[derive(Debug, Clone)]
+    struct Counting<C>(C, Arc<AtomicUsize>);
+
+    impl<C: ConnectionErrors> ConnectionErrors for Counting<C> {
+        type SendError = C::SendError;
+        type RecvError = C::RecvError;
+        type OpenError = C::OpenError;
+        type AcceptError = C::AcceptError;
+    }
+
+    impl<C: StreamTypes> StreamTypes for Counting<C> {
+        type In = C::In;
+        type Out = C::Out;
+        type RecvStream = C::RecvStream;
+        type SendSink = C::SendSink;
+    }
+
+    impl<C: Connector> Connector for Counting<C> {
+        async fn open(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+            self.1.fetch_add(1, Ordering::Relaxed);
+            self.0.open().await
+        }
+    }
+
+    #[tokio::test]
+    async fn pipelined_rpc() -> anyhow::Result<()> {
+        let (listener, connector) = flume::channel::<Frame<Ping>, Frame<Pong>>(1);
+        let server = RpcServer::<PingService, _>::new(PipelineListener::new(listener));
+        let _server = tokio::spawn(async move {
+            loop {
+                let (req, chan) = server.accept().await?.read_first().await?;
+                tokio::spawn(chan.rpc(req, (), |_, Ping(x)| async move { Pong(x + 1) }));
+            }
+            #[allow(unreachable_code)]
+            anyhow::Ok(())
+        });
+        let opened = Arc::new(AtomicUsize::new(0));
+        let connector = PipelineConnector::new(Counting(connector, opened.clone()));
+        let client = RpcClient::<PingService, _>::new(connector);
+        for i in 0..10 {
+            let Pong(x) = client.rpc(Ping(i)).await?;
+            assert_eq!(x, i + 1);
+        }
+        // concurrent requests share the pooled substream as well
+        let results =
+            futures_util::future::try_join_all((0..10).map(|i| client.rpc(Ping(i)))).await?;
+        assert_eq!(results.len(), 10);
+        assert_eq!(opened.load(Ordering::Relaxed), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,506 @@
+//! Dialing tcp connections through a SOCKS5 or HTTP CONNECT proxy
+//!
+//! Clients behind corporate proxies often can not reach a server directly. The
+//! [tcp](super::tcp) and [websocket](super::websocket) connectors can be
+//! configured with a [Proxy], which then establishes all their connections.
+//!
+//! Only tcp based transports are supported. Tunneling QUIC through a proxy
+//! would need UDP support from the proxy, like SOCKS5 UDP ASSOCIATE or MASQUE,
+//! which is rarely available where proxies are mandatory.
+use std::{
+    fmt, io,
+    net::{IpAddr, SocketAddr},
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use tracing::trace;
+
+/// Max size of the response header of a HTTP proxy
+const MAX_HTTP_RESPONSE: usize = 8192;
+
+/// The protocol used to talk to a proxy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyKind {
+    /// A SOCKS5 proxy, see RFC 1928
+    Socks5,
+    /// A HTTP proxy that supports the CONNECT method
+    HttpConnect,
+}
+
+/// A proxy to open tcp connections through
+#[derive(Clone)]
+pub struct Proxy {
+    kind: ProxyKind,
+    addr: SocketAddr,
+    auth: Option<(String, String)>,
+}
+
+impl fmt::Debug for Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Proxy")
+            .field("kind", &self.kind)
+            .field("addr", &self.addr)
+            .field("user", &self.auth.as_ref().map(|(user, _)| user))
+            .finish()
+    }
+}
+
+impl Proxy {
+    /// A SOCKS5 proxy at the given address
+    pub fn socks5(addr: SocketAddr) -> Self {
+        Self {
+            kind: ProxyKind::Socks5,
+            addr,
+            auth: None,
+        }
+    }
+
+    /// A HTTP proxy at the given address
+    pub fn http(addr: SocketAddr) -> Self {
+        Self {
+            kind: ProxyKind::HttpConnect,
+            addr,
+            auth: None,
+        }
+    }
+
+    /// Authenticate with a user name and password
+    ///
+    /// For SOCKS5 this uses username/password authentication (RFC 1929), for HTTP
+    /// proxies basic authentication. Note that both send the password in the clear.
+    pub fn with_auth(mut self, user: impl Into<String>, password: impl Into<String>) -> Self {
+        self.auth = Some((user.into(), password.into()));
+        self
+    }
+
+    /// The protocol used to talk to the proxy
+    pub fn kind(&self) -> ProxyKind {
+        self.kind
+    }
+
+    /// The address of the proxy
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Open a tcp connection to `host` and `port` through the proxy
+    ///
+    /// `host` can be a domain name, which is then resolved by the proxy, or an ip address.
+    pub async fn connect(&self, host: &str, port: u16) -> io::Result<TcpStream> {
+        trace!(
+            "connecting to {host}:{port} via {:?} proxy {}",
+            self.kind,
+            self.addr
+        );
+        let mut stream = TcpStream::connect(self.addr).await?;
+        match self.kind {
+            ProxyKind::Socks5 => socks5_handshake(&mut stream, host, port, &self.auth).await?,
+            ProxyKind::HttpConnect => http_handshake(&mut stream, host, port, &self.auth).await?,
+        }
+        Ok(stream)
+    }
+}
+
+fn proxy_error(kind: io::ErrorKind, msg: impl Into<String>) -> io::Error {
+    io::Error::new(kind, msg.into())
+}
+
+async fn socks5_handshake(
+    stream: &mut TcpStream,
+    host: &str,
+    port: u16,
+    auth: &Option<(String, String)>,
+) -> io::Result<()> {
+    // offer no authentication, or username/password if we have credentials
+    match auth {
+        Some(_) => stream.write_all(&[5, 2, 0, 2]).await?,
+        None => stream.write_all(&[5, 1, 0]).await?,
+    }
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice).await?;
+    if choice[0] != 5 {
+        return Err(proxy_error(
+            io::ErrorKind::InvalidData,
+            "not a socks5 proxy",
+        ));
+    }
+    match (choice[1], auth) {
+        (0, _) => {}
+        (2, Some((user, password))) => {
+            if user.len() > 255 || password.len() > 255 {
+                return Err(proxy_error(
+                    io::ErrorKind::InvalidInput,
+                    "socks5 credentials too long",
+                ));
+            }
+            let mut msg = vec![1, user.len() as u8];
+            msg.extend_from_slice(user.as_bytes());
+            msg.push(password.len() as u8);
+            msg.extend_from_slice(password.as_bytes());
+            stream.write_all(&msg).await?;
+            let mut status = [0u8; 2];
+            stream.read_exact(&mut status).await?;
+            if status[1] != 0 {
+                return Err(proxy_error(
+                    io::ErrorKind::PermissionDenied,
+                    "socks5 authentication failed",
+                ));
+            }
+        }
+        _ => {
+            return Err(proxy_error(
+                io::ErrorKind::PermissionDenied,
+                "no acceptable socks5 authentication method",
+            ))
+        }
+    }
+    let mut request = vec![5, 1, 0];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(1);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(4);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            if host.len() > 255 {
+                return Err(proxy_error(
+                    io::ErrorKind::InvalidInput,
+                    "host name too long",
+                ));
+            }
+            request.push(3);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != 0 {
+        let kind = match reply[1] {
+            2 => io::ErrorKind::PermissionDenied,
+            5 => io::ErrorKind::ConnectionRefused,
+            _ => io::ErrorKind::Other,
+        };
+        return Err(proxy_error(
+            kind,
+            format!("socks5 connect failed with reply {}", reply[1]),
+        ));
+    }
+    // skip the bound address, we have no use for it
+    let len = match reply[3] {
+        1 => 4,
+        4 => 16,
+        3 => stream.read_u8().await? as usize,
+        _ => {
+            return Err(proxy_error(
+                io::ErrorKind::InvalidData,
+                "invalid socks5 address type",
+            ))
+        }
+    };
+    let mut bound = vec![0u8; len + 2];
+    stream.read_exact(&mut bound).await?;
+    Ok(())
+}
+
+async fn http_handshake(
+    stream: &mut TcpStream,
+    host: &str,
+    port: u16,
+    auth: &Option<(String, String)>,
+) -> io::Result<()> {
+    let authority = match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) => format!("[{ip}]:{port}"),
+        _ => format!("{host}:{port}"),
+    };
+    let mut request = format!("CONNECT {authority} HTTP/1.1\r\nHost: {authority}\r\n");
+    if let Some((user, password)) = auth {
+        let credentials = base64(format!("{user}:{password}").as_bytes());
+        request.push_str(&format!("Proxy-Authorization: Basic {credentials}\r\n"));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+    // read byte by byte, so we don't consume anything after the header
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() >= MAX_HTTP_RESPONSE {
+            return Err(proxy_error(
+                io::ErrorKind::InvalidData,
+                "http proxy response too large",
+            ));
+        }
+        response.push(stream.read_u8().await?);
+    }
+    let response = String::from_utf8_lossy(&response);
+    let status = response.lines().next().unwrap_or_default();
+    match status.split_whitespace().nth(1) {
+        Some("200") => Ok(()),
+        Some("407") => Err(proxy_error(
+            io::ErrorKind::PermissionDenied,
+            "http proxy authentication required",
+        )),
+        _ => Err(proxy_error(
+            io::ErrorKind::Other,
+            format!("http proxy connect failed: {status}"),
+        )),
+    }
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut res = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = u32::from_be_bytes([0, b[0], b[1], b[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                res.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                res.push('=');
+            }
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn base64_encoding() {
+        // test vectors from RFC 4648, section 10
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foob"), "Zm9vYg==");
+        assert_eq!(base64(b"fooba"), "Zm9vYmE=");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        // the last two characters of the alphabet
+        assert_eq!(base64(&[0xfb, 0xff]), "+/8=");
+        assert_eq!(base64(b"user:pass"), "dXNlcjpwYXNz");
+    }
+
+    /// An echo server, to connect to through the proxies
+    async fn echo_server() -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (mut recv, mut send) = stream.split();
+                    tokio::io::copy(&mut recv, &mut send).await.ok();
+                });
+            }
+        });
+        Ok(addr)
+    }
+
+    /// A minimal SOCKS5 proxy that requires username/password authentication, and
+    /// only connects to IPv4 addresses and host names
+    async fn socks5_proxy(user: &'static str, password: &'static str) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut greeting = [0u8; 2];
+                    stream.read_exact(&mut greeting).await?;
+                    let mut methods = vec![0u8; greeting[1] as usize];
+                    stream.read_exact(&mut methods).await?;
+                    if !methods.contains(&2) {
+                        return stream.write_all(&[5, 0xff]).await;
+                    }
+                    stream.write_all(&[5, 2]).await?;
+                    let mut buf = [0u8; 2];
+                    stream.read_exact(&mut buf).await?;
+                    let mut u = vec![0u8; buf[1] as usize];
+                    stream.read_exact(&mut u).await?;
+                    let mut p = vec![0u8; stream.read_u8().await? as usize];
+                    stream.read_exact(&mut p).await?;
+                    if u != user.as_bytes() || p != password.as_bytes() {
+                        return stream.write_all(&[1, 1]).await;
+                    }
+                    stream.write_all(&[1, 0]).await?;
+                    let mut request = [0u8; 4];
+                    stream.read_exact(&mut request).await?;
+                    let host = match request[3] {
+                        1 => {
+                            let mut ip = [0u8; 4];
+                            stream.read_exact(&mut ip).await?;
+                            IpAddr::from(ip).to_string()
+                        }
+                        3 => {
+                            let mut name = vec![0u8; stream.read_u8().await? as usize];
+                            stream.read_exact(&mut name).await?;
+                            String::from_utf8(name).unwrap()
+                        }
+                        _ => {
+                            // address type not supported
+                            return stream.write_all(&[5, 8, 0, 1, 0, 0, 0, 0, 0, 0]).await;
+                        }
+                    };
+                    let port = stream.read_u16().await?;
+                    let host = if host == "localhost" {
+                        "127.0.0.1".to_string()
+                    } else {
+                        host
+                    };
+                    let mut target = TcpStream::connect((host.as_str(), port)).await?;
+                    stream.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+                    tokio::io::copy_bidirectional(&mut stream, &mut target).await?;
+                    io::Result::Ok(())
+                });
+            }
+        });
+        Ok(addr)
+    }
+
+    /// A minimal HTTP CONNECT proxy that requires basic authentication
+    async fn http_proxy(credentials: &'static str) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut header = Vec::new();
+                    while !header.ends_with(b"\r\n\r\n") {
+                        header.push(stream.read_u8().await?);
+                    }
+                    let header = String::from_utf8(header).unwrap();
+                    let expected = format!(
+                        "Proxy-Authorization: Basic {}\r\n",
+                        base64(credentials.as_bytes())
+                    );
+                    if !header.contains(&expected) {
+                        return stream
+                            .write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+                            .await;
+                    }
+                    let authority = header.split_whitespace().nth(1).unwrap().to_string();
+                    let mut target = TcpStream::connect(authority).await?;
+                    stream
+                        .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                        .await?;
+                    tokio::io::copy_bidirectional(&mut stream, &mut target).await?;
+                    io::Result::Ok(())
+                });
+            }
+        });
+        Ok(addr)
+    }
+
+    async fn check_echo(mut stream: TcpStream) -> io::Result<()> {
+        stream.write_all(b"hello").await?;
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"hello");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn socks5() -> anyhow::Result<()> {
+        let target = echo_server().await?;
+        let proxy = Proxy::socks5(socks5_proxy("user", "pass").await?);
+        let port = target.port();
+        check_echo(
+            proxy
+                .clone()
+                .with_auth("user", "pass")
+                .connect("127.0.0.1", port)
+                .await?,
+        )
+        .await?;
+        check_echo(
+            proxy
+                .clone()
+                .with_auth("user", "pass")
+                .connect("localhost", port)
+                .await?,
+        )
+        .await?;
+        let err = proxy
+            .clone()
+            .with_auth("user", "wrong")
+            .connect("127.0.0.1", port)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        // the proxy does not support IPv6 targets, and replies with an error
+        let err = proxy
+            .clone()
+            .with_auth("user", "pass")
+            .connect("::1", port)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        let err = proxy.connect("127.0.0.1", port).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn http_connect() -> anyhow::Result<()> {
+        let target = echo_server().await?;
+        let proxy = Proxy::http(http_proxy("user:pass").await?);
+        let port = target.port();
+        check_echo(
+            proxy
+                .clone()
+                .with_auth("user", "pass")
+                .connect("127.0.0.1", port)
+                .await?,
+        )
+        .await?;
+        let err = proxy.connect("127.0.0.1", port).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        Ok(())
+    }
+
+    #[cfg(all(feature = "tcp-transport", feature = "testkit"))]
+    #[tokio::test]
+    async fn tcp_connector_via_proxy() -> anyhow::Result<()> {
+        use crate::transport::{tcp, testkit};
+
+        let listener = tcp::TcpListener::serve("127.0.0.1:0".parse()?).await?;
+        let addr = match crate::transport::Listener::local_addr(&listener)[0] {
+            crate::transport::LocalAddr::Socket(addr) => addr,
+            _ => unreachable!(),
+        };
+        let proxy = Proxy::socks5(socks5_proxy("user", "pass").await?).with_auth("user", "pass");
+        let connector = tcp::TcpConnector::new(addr).with_proxy(proxy);
+        testkit::run(connector, listener).await
+    }
+
+    #[cfg(all(feature = "websocket-transport", feature = "testkit"))]
+    #[tokio::test]
+    async fn websocket_connector_via_proxy() -> anyhow::Result<()> {
+        use crate::transport::{testkit, websocket};
+
+        let listener = websocket::WebSocketListener::serve("127.0.0.1:0".parse()?).await?;
+        let addr = match crate::transport::Listener::local_addr(&listener)[0] {
+            crate::transport::LocalAddr::Socket(addr) => addr,
+            _ => unreachable!(),
+        };
+        let proxy = Proxy::http(http_proxy("user:pass").await?).with_auth("user", "pass");
+        let connector =
+            websocket::WebSocketConnector::new(format!("ws://localhost:{}", addr.port()))
+                .with_proxy(proxy);
+        testkit::run(connector, listener).await
+    }
+}
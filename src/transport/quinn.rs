@@ -1,45 +1,56 @@
 //! QUIC transport implementation based on [quinn](https://crates.io/crates/quinn)
 use std::{
+    collections::VecDeque,
     fmt, io,
     marker::PhantomData,
-    net::SocketAddr,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     pin::Pin,
     result,
     sync::Arc,
     task::{Context, Poll},
+    time::Duration,
 };
 
 use futures_lite::{Future, Stream, StreamExt};
 use futures_sink::Sink;
-use futures_util::FutureExt;
+use futures_util::{future::BoxFuture, FutureExt};
 use pin_project::pin_project;
 use serde::{de::DeserializeOwned, Serialize};
-use tokio::sync::oneshot;
+use tokio::sync::{oneshot, watch};
+use tokio_util::task::AbortOnDropHandle;
 use tracing::{debug_span, Instrument};
 
 use super::{
-    util::{FramedPostcardRead, FramedPostcardWrite},
+    stream_limit::{self, Accepted, Incoming, StreamLimit, StreamPermit},
+    util::{
+        self, FrameConfig, FramedCodecRead, FramedCodecWrite, OpenRequest, UniReceivers, UniSenders,
+    },
     StreamTypes,
 };
 use crate::{
-    transport::{ConnectionErrors, Connector, Listener, LocalAddr},
+    codec::{Codec, Framing, PostcardCodec},
+    transport::{
+        ConnectionErrors, ConnectionEvent, ConnectionEvents, Connector, Listener, LocalAddr,
+        PeerIdentity, PeerInfo,
+    },
     RpcMessage,
 };
 
-const MAX_FRAME_LENGTH: usize = 1024 * 1024 * 16;
-
 #[derive(Debug)]
 struct ListenerInner {
-    endpoint: Option<quinn::Endpoint>,
+    endpoints: Vec<quinn::Endpoint>,
     task: Option<tokio::task::JoinHandle<()>>,
-    local_addr: [LocalAddr; 1],
-    receiver: flume::Receiver<SocketInner>,
+    local_addr: Vec<LocalAddr>,
+    receiver: Incoming,
+    /// Datagrams and uni substreams received on any connection, if the listener
+    /// handles connections
+    uni: Option<UniReceivers>,
 }
 
 impl Drop for ListenerInner {
     fn drop(&mut self) {
         tracing::debug!("Dropping listener");
-        if let Some(endpoint) = self.endpoint.take() {
+        for endpoint in self.endpoints.drain(..) {
             endpoint.close(0u32.into(), b"Listener dropped");
 
             if let Ok(handle) = tokio::runtime::Handle::try_current() {
@@ -61,38 +72,55 @@ impl Drop for ListenerInner {
 
 /// A listener using a quinn connection
 #[derive(Debug)]
-pub struct QuinnListener<In: RpcMessage, Out: RpcMessage> {
+pub struct QuinnListener<In: RpcMessage, Out: RpcMessage, C: Codec = PostcardCodec> {
     inner: Arc<ListenerInner>,
-    _p: PhantomData<(In, Out)>,
+    frames: FrameConfig,
+    _p: PhantomData<(In, Out, C)>,
 }
 
 impl<In: RpcMessage, Out: RpcMessage> QuinnListener<In, Out> {
     /// handles RPC requests from a connection
     ///
     /// to cleanly shutdown the handler, drop the receiver side of the sender.
-    async fn connection_handler(connection: quinn::Connection, sender: flume::Sender<SocketInner>) {
+    async fn connection_handler(
+        connection: quinn::Connection,
+        sender: flume::Sender<Accepted>,
+        limit: Option<StreamLimit>,
+    ) {
+        let limit = stream_limit::semaphore(limit);
+        let peer = peer_identity(&connection);
         loop {
             tracing::debug!("Awaiting incoming bidi substream on existing connection...");
-            let bidi_stream = match connection.accept_bi().await {
-                Ok(bidi_stream) => bidi_stream,
-                Err(quinn::ConnectionError::ApplicationClosed(e)) => {
-                    tracing::debug!("Peer closed the connection {:?}", e);
-                    break;
-                }
-                Err(e) => {
-                    tracing::debug!("Error accepting stream: {}", e);
-                    break;
-                }
-            };
+            let (bidi_stream, permit) =
+                match stream_limit::accept_bi(&connection, limit.as_ref()).await {
+                    Ok(bidi_stream) => bidi_stream,
+                    Err(quinn::ConnectionError::ApplicationClosed(e)) => {
+                        tracing::debug!("Peer closed the connection {:?}", e);
+                        break;
+                    }
+                    Err(e) => {
+                        tracing::debug!("Error accepting stream: {}", e);
+                        break;
+                    }
+                };
             tracing::debug!("Sending substream to be handled... {}", bidi_stream.0.id());
-            if sender.send_async(bidi_stream).await.is_err() {
+            if sender
+                .send_async((bidi_stream, permit, peer.clone()))
+                .await
+                .is_err()
+            {
                 tracing::debug!("Receiver dropped");
                 break;
             }
         }
     }
 
-    async fn endpoint_handler(endpoint: quinn::Endpoint, sender: flume::Sender<SocketInner>) {
+    async fn endpoint_handler(
+        endpoint: quinn::Endpoint,
+        sender: flume::Sender<Accepted>,
+        uni: UniSenders,
+        limit: Option<StreamLimit>,
+    ) {
         loop {
             tracing::debug!("Waiting for incoming connection...");
             let connecting = match endpoint.accept().await {
@@ -112,7 +140,8 @@ impl<In: RpcMessage, Out: RpcMessage> QuinnListener<In, Out> {
                 conection.remote_address()
             );
             tracing::debug!("Spawning connection handler...");
-            tokio::spawn(Self::connection_handler(conection, sender.clone()));
+            uni.spawn(&conection, peer_identity(&conection));
+            tokio::spawn(Self::connection_handler(conection, sender.clone(), limit));
         }
     }
 
@@ -123,20 +152,112 @@ impl<In: RpcMessage, Out: RpcMessage> QuinnListener<In, Out> {
     /// The server channel will take care of listening on the endpoint and spawning
     /// handlers for new connections.
     pub fn new(endpoint: quinn::Endpoint) -> io::Result<Self> {
-        let local_addr = endpoint.local_addr()?;
+        Self::builder().build(endpoint)
+    }
+
+    /// Create a builder to configure the listener, e.g. with a [StreamLimit]
+    pub fn builder() -> QuinnListenerBuilder<In, Out> {
+        QuinnListenerBuilder::default()
+    }
+
+    /// Create a new server channel that accepts connections on several quinn endpoints
+    ///
+    /// This is useful to listen on both IPv4 and IPv6, see [bind_server_endpoints].
+    /// The listener reports the local addresses of all endpoints.
+    pub fn from_endpoints(endpoints: Vec<quinn::Endpoint>) -> io::Result<Self> {
+        Self::builder().build_from_endpoints(endpoints)
+    }
+
+    fn new_inner(endpoints: Vec<quinn::Endpoint>, limit: Option<StreamLimit>) -> io::Result<Self> {
+        if endpoints.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "at least one endpoint is required",
+            ));
+        }
+        let local_addr = endpoints
+            .iter()
+            .map(|endpoint| Ok(LocalAddr::Socket(endpoint.local_addr()?)))
+            .collect::<io::Result<Vec<_>>>()?;
         let (sender, receiver) = flume::bounded(16);
-        let task = tokio::spawn(Self::endpoint_handler(endpoint.clone(), sender));
+        let (uni, uni_rx) = util::uni_channels();
+        let handlers = endpoints
+            .iter()
+            .map(|endpoint| {
+                Self::endpoint_handler(endpoint.clone(), sender.clone(), uni.clone(), limit)
+            })
+            .collect::<Vec<_>>();
+        let task = tokio::spawn(async move {
+            futures_util::future::join_all(handlers).await;
+        });
         Ok(Self {
             inner: Arc::new(ListenerInner {
-                endpoint: Some(endpoint),
+                endpoints,
                 task: Some(task),
-                local_addr: [LocalAddr::Socket(local_addr)],
-                receiver,
+                local_addr,
+                receiver: Incoming::Accepted(receiver),
+                uni: Some(uni_rx),
             }),
+            frames: FrameConfig::default(),
             _p: PhantomData,
         })
     }
 
+    /// Create a new server channel that shares an endpoint with other protocols
+    ///
+    /// Only connections that negotiated one of the given ALPN protocols are
+    /// handled. All other connections are passed to the returned receiver, so the
+    /// caller can handle them. Unlike [QuinnListener::new], the endpoint is not
+    /// closed when the listener is dropped, but connections are no longer accepted.
+    pub fn from_shared_endpoint(
+        endpoint: quinn::Endpoint,
+        alpns: Vec<Vec<u8>>,
+    ) -> io::Result<(Self, flume::Receiver<quinn::Connection>)> {
+        Self::builder().build_from_shared_endpoint(endpoint, alpns)
+    }
+
+    fn from_shared_endpoint_inner(
+        endpoint: quinn::Endpoint,
+        alpns: Vec<Vec<u8>>,
+        limit: Option<StreamLimit>,
+    ) -> io::Result<(Self, flume::Receiver<quinn::Connection>)> {
+        let local_addr = endpoint.local_addr()?;
+        let (sender, receiver) = flume::bounded(16);
+        let (unhandled_tx, unhandled_rx) = flume::bounded(16);
+        let (uni, uni_rx) = util::uni_channels();
+        let task = tokio::spawn(async move {
+            while let Some(incoming) = endpoint.accept().await {
+                let connection = match incoming.await {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        tracing::warn!("Error accepting connection: {}", e);
+                        continue;
+                    }
+                };
+                let alpn = get_handshake_data(&connection).and_then(|data| data.protocol);
+                if alpn.is_some_and(|alpn| alpns.contains(&alpn)) {
+                    uni.spawn(&connection, peer_identity(&connection));
+                    tokio::spawn(Self::connection_handler(connection, sender.clone(), limit));
+                } else if unhandled_tx.send_async(connection).await.is_err() {
+                    tracing::debug!("Receiver for unhandled connections dropped");
+                }
+            }
+        });
+        let listener = Self {
+            inner: Arc::new(ListenerInner {
+                // the endpoint is not ours to close
+                endpoints: Vec::new(),
+                task: Some(task),
+                local_addr: vec![LocalAddr::Socket(local_addr)],
+                receiver: Incoming::Accepted(receiver),
+                uni: Some(uni_rx),
+            }),
+            frames: FrameConfig::default(),
+            _p: PhantomData,
+        };
+        Ok((listener, unhandled_rx))
+    }
+
     /// Create a new server channel, given just a source of incoming connections
     ///
     /// This is useful if you want to manage the quinn endpoint yourself,
@@ -144,21 +265,33 @@ impl<In: RpcMessage, Out: RpcMessage> QuinnListener<In, Out> {
     pub fn handle_connections(
         incoming: flume::Receiver<quinn::Connection>,
         local_addr: SocketAddr,
+    ) -> Self {
+        Self::builder().build_from_connections(incoming, local_addr)
+    }
+
+    fn handle_connections_inner(
+        incoming: flume::Receiver<quinn::Connection>,
+        local_addr: SocketAddr,
+        limit: Option<StreamLimit>,
     ) -> Self {
         let (sender, receiver) = flume::bounded(16);
+        let (uni, uni_rx) = util::uni_channels();
         let task = tokio::spawn(async move {
             // just grab all connections and spawn a handler for each one
             while let Ok(connection) = incoming.recv_async().await {
-                tokio::spawn(Self::connection_handler(connection, sender.clone()));
+                uni.spawn(&connection, peer_identity(&connection));
+                tokio::spawn(Self::connection_handler(connection, sender.clone(), limit));
             }
         });
         Self {
             inner: Arc::new(ListenerInner {
-                endpoint: None,
+                endpoints: Vec::new(),
                 task: Some(task),
-                local_addr: [LocalAddr::Socket(local_addr)],
-                receiver,
+                local_addr: vec![LocalAddr::Socket(local_addr)],
+                receiver: Incoming::Accepted(receiver),
+                uni: Some(uni_rx),
             }),
+            frames: FrameConfig::default(),
             _p: PhantomData,
         }
     }
@@ -173,53 +306,178 @@ impl<In: RpcMessage, Out: RpcMessage> QuinnListener<In, Out> {
     ) -> Self {
         Self {
             inner: Arc::new(ListenerInner {
-                endpoint: None,
+                endpoints: Vec::new(),
                 task: None,
-                local_addr: [LocalAddr::Socket(local_addr)],
-                receiver,
+                local_addr: vec![LocalAddr::Socket(local_addr)],
+                receiver: Incoming::External(receiver),
+                uni: None,
             }),
+            frames: FrameConfig::default(),
+            _p: PhantomData,
+        }
+    }
+}
+
+/// Builder for a [QuinnListener]
+///
+/// By default, the number of concurrently accepted substreams per connection is
+/// only limited by the transport config of quinn.
+#[derive(Debug)]
+pub struct QuinnListenerBuilder<In: RpcMessage, Out: RpcMessage> {
+    limit: Option<StreamLimit>,
+    _p: PhantomData<(In, Out)>,
+}
+
+impl<In: RpcMessage, Out: RpcMessage> Default for QuinnListenerBuilder<In, Out> {
+    fn default() -> Self {
+        Self {
+            limit: None,
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage> QuinnListenerBuilder<In, Out> {
+    /// Limit the number of concurrently accepted substreams per connection
+    ///
+    /// See [StreamLimit] for details.
+    pub fn stream_limit(mut self, limit: StreamLimit) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Create the listener, given a quinn endpoint, see [QuinnListener::new]
+    pub fn build(self, endpoint: quinn::Endpoint) -> io::Result<QuinnListener<In, Out>> {
+        QuinnListener::new_inner(vec![endpoint], self.limit)
+    }
+
+    /// Create the listener, given several quinn endpoints, see [QuinnListener::from_endpoints]
+    pub fn build_from_endpoints(
+        self,
+        endpoints: Vec<quinn::Endpoint>,
+    ) -> io::Result<QuinnListener<In, Out>> {
+        QuinnListener::new_inner(endpoints, self.limit)
+    }
+
+    /// Create the listener, given an endpoint that is shared with other protocols,
+    /// see [QuinnListener::from_shared_endpoint]
+    pub fn build_from_shared_endpoint(
+        self,
+        endpoint: quinn::Endpoint,
+        alpns: Vec<Vec<u8>>,
+    ) -> io::Result<(QuinnListener<In, Out>, flume::Receiver<quinn::Connection>)> {
+        QuinnListener::from_shared_endpoint_inner(endpoint, alpns, self.limit)
+    }
+
+    /// Create the listener, given just a source of incoming connections, see
+    /// [QuinnListener::handle_connections]
+    pub fn build_from_connections(
+        self,
+        incoming: flume::Receiver<quinn::Connection>,
+        local_addr: SocketAddr,
+    ) -> QuinnListener<In, Out> {
+        QuinnListener::handle_connections_inner(incoming, local_addr, self.limit)
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage, C: Codec> QuinnListener<In, Out, C> {
+    /// Replace the server config of all endpoints of this listener
+    ///
+    /// This only affects connections that are accepted afterwards. Use it e.g.
+    /// to change the [quinn::TransportConfig], see [TransportSettings].
+    pub fn set_server_config(&self, config: quinn::ServerConfig) {
+        for endpoint in &self.inner.endpoints {
+            endpoint.set_server_config(Some(config.clone()));
+        }
+    }
+
+    /// Set the maximum length of encoded messages that are sent
+    ///
+    /// Sending a larger message fails with [MessageTooLarge](crate::codec::MessageTooLarge). The default is 16 MiB.
+    pub fn max_send_frame_length(mut self, value: usize) -> Self {
+        self.frames.send = value;
+        self
+    }
+
+    /// Set the maximum length of encoded messages that are received
+    ///
+    /// Receiving a larger message fails with [MessageTooLarge](crate::codec::MessageTooLarge), and ends the
+    /// substream. The default is 16 MiB.
+    pub fn max_recv_frame_length(mut self, value: usize) -> Self {
+        self.frames.recv = value;
+        self
+    }
+
+    /// Set how messages are delimited on the substreams
+    ///
+    /// The connectors have to use the same framing.
+    pub fn framing(mut self, value: Framing) -> Self {
+        self.frames.framing = value;
+        self
+    }
+
+    /// Use a different [Codec] for the messages of this listener
+    ///
+    /// The connectors have to use the same codec.
+    pub fn with_codec<C2: Codec>(self) -> QuinnListener<In, Out, C2> {
+        QuinnListener {
+            inner: self.inner,
+            frames: self.frames,
             _p: PhantomData,
         }
     }
 }
 
-impl<In: RpcMessage, Out: RpcMessage> Clone for QuinnListener<In, Out> {
+impl<In: RpcMessage, Out: RpcMessage, C: Codec> Clone for QuinnListener<In, Out, C> {
     fn clone(&self) -> Self {
         Self {
             inner: self.inner.clone(),
+            frames: self.frames,
             _p: PhantomData,
         }
     }
 }
 
-impl<In: RpcMessage, Out: RpcMessage> ConnectionErrors for QuinnListener<In, Out> {
+impl<In: RpcMessage, Out: RpcMessage, C: Codec> ConnectionErrors for QuinnListener<In, Out, C> {
     type SendError = io::Error;
     type RecvError = io::Error;
     type OpenError = quinn::ConnectionError;
     type AcceptError = quinn::ConnectionError;
 }
 
-impl<In: RpcMessage, Out: RpcMessage> StreamTypes for QuinnListener<In, Out> {
+impl<In: RpcMessage, Out: RpcMessage, C: Codec> StreamTypes for QuinnListener<In, Out, C> {
     type In = In;
     type Out = Out;
-    type SendSink = self::SendSink<Out>;
-    type RecvStream = self::RecvStream<In>;
+    type SendSink = self::SendSink<Out, C>;
+    type RecvStream = self::RecvStream<In, C>;
 }
 
-impl<In: RpcMessage, Out: RpcMessage> Listener for QuinnListener<In, Out> {
+impl<In: RpcMessage, Out: RpcMessage, C: Codec> Listener for QuinnListener<In, Out, C> {
     async fn accept(&self) -> Result<(Self::SendSink, Self::RecvStream), AcceptError> {
-        let (send, recv) = self
+        let ((send, recv), permit, peer) = self
             .inner
             .receiver
-            .recv_async()
+            .recv()
             .await
             .map_err(|_| quinn::ConnectionError::LocallyClosed)?;
-        Ok((SendSink::new(send), RecvStream::new(recv)))
+        Ok((
+            SendSink::with_permit(send, self.frames, permit.clone()),
+            RecvStream::accepted(recv, self.frames, permit, peer),
+        ))
     }
 
     fn local_addr(&self) -> &[LocalAddr] {
         &self.inner.local_addr
     }
+
+    async fn accept_uni(&self) -> Result<Self::RecvStream, AcceptError> {
+        let (recv, peer) = util::recv_uni(self.inner.uni.as_ref()).await?;
+        Ok(RecvStream::accepted(recv, self.frames, None, peer))
+    }
+
+    async fn recv_datagram(&self) -> Result<In, io::Error> {
+        util::recv_datagram::<_, C>(self.inner.uni.as_ref()).await
+    }
 }
 
 type SocketInner = (quinn::SendStream, quinn::RecvStream);
@@ -231,9 +489,22 @@ struct ClientConnectionInner {
     /// The task that handles creating new connections
     task: Option<tokio::task::JoinHandle<()>>,
     /// The channel to receive new connections
-    sender: flume::Sender<oneshot::Sender<Result<SocketInner, quinn::ConnectionError>>>,
+    sender: flume::Sender<OpenRequest<quinn::ConnectionError>>,
+    /// The current connection, if any
+    connection: watch::Receiver<Option<quinn::Connection>>,
+    /// Completes when the handshake of the current connection is done, if it
+    /// was established with 0-RTT
+    handshake: watch::Receiver<Option<Handshake>>,
+    /// The most recent lifecycle event of the connection
+    events: watch::Receiver<ConnectionEvent>,
 }
 
+/// Completes when the handshake of a 0-RTT connection is done
+type Handshake = futures_util::future::Shared<futures_util::future::BoxFuture<'static, ()>>;
+
+/// Decides which messages may be sent as 0-RTT data
+type ZeroRttFilter<Out> = Arc<dyn Fn(&Out) -> bool + Send + Sync>;
+
 impl Drop for ClientConnectionInner {
     fn drop(&mut self) {
         tracing::debug!("Dropping client connection");
@@ -260,41 +531,37 @@ impl Drop for ClientConnectionInner {
 }
 
 /// A connection using a quinn connection
-pub struct QuinnConnector<In: RpcMessage, Out: RpcMessage> {
+pub struct QuinnConnector<In: RpcMessage, Out: RpcMessage, C: Codec = PostcardCodec> {
     inner: Arc<ClientConnectionInner>,
-    _p: PhantomData<(In, Out)>,
+    frames: FrameConfig,
+    zero_rtt: Option<ZeroRttFilter<Out>>,
+    _p: PhantomData<(In, Out, C)>,
 }
 
 impl<In: RpcMessage, Out: RpcMessage> QuinnConnector<In, Out> {
     async fn single_connection_handler_inner(
         connection: quinn::Connection,
-        requests: flume::Receiver<oneshot::Sender<Result<SocketInner, quinn::ConnectionError>>>,
+        requests: flume::Receiver<OpenRequest<quinn::ConnectionError>>,
     ) -> result::Result<(), flume::RecvError> {
         loop {
             tracing::debug!("Awaiting request for new bidi substream...");
             let request = requests.recv_async().await?;
-            tracing::debug!("Got request for new bidi substream");
-            match connection.open_bi().await {
-                Ok(pair) => {
-                    tracing::debug!("Bidi substream opened");
-                    if request.send(Ok(pair)).is_err() {
-                        tracing::debug!("requester dropped");
-                    }
-                }
-                Err(e) => {
-                    tracing::warn!("error opening bidi substream: {}", e);
-                    if request.send(Err(e)).is_err() {
-                        tracing::debug!("requester dropped");
-                    }
-                }
+            tracing::debug!("Got request for new substream");
+            if let Err((request, e)) = request.open(&connection).await {
+                tracing::warn!("error opening substream: {}", e);
+                request.fail(e);
             }
         }
     }
 
     async fn single_connection_handler(
         connection: quinn::Connection,
-        requests: flume::Receiver<oneshot::Sender<Result<SocketInner, quinn::ConnectionError>>>,
+        requests: flume::Receiver<OpenRequest<quinn::ConnectionError>>,
+        incoming: Option<flume::Sender<Accepted>>,
+        events: watch::Sender<ConnectionEvent>,
     ) {
+        let _accept = incoming.map(|incoming| accept_substreams(connection.clone(), incoming));
+        let _closed = watch_closed(connection.clone(), events);
         if Self::single_connection_handler_inner(connection, requests)
             .await
             .is_err()
@@ -311,34 +578,37 @@ impl<In: RpcMessage, Out: RpcMessage> QuinnConnector<In, Out> {
     /// All other errors are logged and handled internally.
     /// It will try to keep a connection open at all times.
     async fn reconnect_handler_inner(
-        endpoint: quinn::Endpoint,
-        addr: SocketAddr,
-        name: String,
-        requests: flume::Receiver<oneshot::Sender<Result<SocketInner, quinn::ConnectionError>>>,
+        reconnect: ReconnectHandler,
+        requests: flume::Receiver<OpenRequest<quinn::ConnectionError>>,
+        incoming: Option<flume::Sender<Accepted>>,
+        current: watch::Sender<Option<quinn::Connection>>,
+        handshake: watch::Sender<Option<Handshake>>,
+        events: watch::Sender<ConnectionEvent>,
     ) {
-        let reconnect = ReconnectHandler {
-            endpoint,
-            state: ConnectionState::NotConnected,
-            addr,
-            name,
-        };
         tokio::pin!(reconnect);
 
         let mut receiver = Receiver::new(&requests);
 
-        let mut pending_request: Option<
-            oneshot::Sender<Result<SocketInner, quinn::ConnectionError>>,
-        > = None;
+        let mut pending_request: Option<OpenRequest<quinn::ConnectionError>> = None;
         let mut connection = None;
+        let mut _accept = None;
+        let mut _closed = None;
 
         enum Racer {
             Reconnect(Result<quinn::Connection, ReconnectErr>),
-            Channel(Option<oneshot::Sender<Result<SocketInner, quinn::ConnectionError>>>),
+            Channel(Option<OpenRequest<quinn::ConnectionError>>),
         }
 
         loop {
             let mut conn_result = None;
             let mut chann_result = None;
+            if !reconnect.connected() {
+                events.send_if_modified(|event| {
+                    let connecting = matches!(event, ConnectionEvent::Connecting);
+                    *event = ConnectionEvent::Connecting;
+                    !connecting
+                });
+            }
             if !reconnect.connected() && pending_request.is_none() {
                 match futures_lite::future::race(
                     reconnect.as_mut().map(Racer::Reconnect),
@@ -363,6 +633,23 @@ impl<In: RpcMessage, Out: RpcMessage> QuinnConnector<In, Out> {
                 tracing::trace!("tick: connection result");
                 match conn_result {
                     Ok(new_connection) => {
+                        if let Some(incoming) = &incoming {
+                            _accept =
+                                Some(accept_substreams(new_connection.clone(), incoming.clone()));
+                        }
+                        let accepted = reconnect.accepted.take().map(|accepted| {
+                            async move {
+                                if !accepted.await {
+                                    tracing::debug!("0-RTT rejected by the server");
+                                }
+                            }
+                            .boxed()
+                            .shared()
+                        });
+                        handshake.send_replace(accepted);
+                        current.send_replace(Some(new_connection.clone()));
+                        events.send_replace(ConnectionEvent::Connected);
+                        _closed = Some(watch_closed(new_connection.clone(), events.clone()));
                         connection = Some(new_connection);
                     }
                     Err(e) => {
@@ -373,17 +660,21 @@ impl<In: RpcMessage, Out: RpcMessage> QuinnConnector<In, Out> {
                                 // some ConnectionError since before it was not even reported.
                                 // Maybe adjust the type?
                                 tracing::warn!(%e, "error calling connect");
+                                events.send_replace(ConnectionEvent::Disconnected(Arc::new(
+                                    e.into(),
+                                )));
                                 quinn::ConnectionError::Reset
                             }
                             ReconnectErr::Connection(e) => {
                                 tracing::warn!(%e, "failed to connect");
+                                events.send_replace(ConnectionEvent::Disconnected(Arc::new(
+                                    e.clone().into(),
+                                )));
                                 e
                             }
                         };
                         if let Some(request) = pending_request.take() {
-                            if request.send(Err(connection_err)).is_err() {
-                                tracing::debug!("requester dropped");
-                            }
+                            request.fail(connection_err);
                         }
                     }
                 }
@@ -405,22 +696,14 @@ impl<In: RpcMessage, Out: RpcMessage> QuinnConnector<In, Out> {
 
             if let Some(connection) = connection.as_mut() {
                 if let Some(request) = pending_request.take() {
-                    match connection.open_bi().await {
-                        Ok(pair) => {
-                            tracing::debug!("Bidi substream opened");
-                            if request.send(Ok(pair)).is_err() {
-                                tracing::debug!("requester dropped");
-                            }
-                        }
-                        Err(e) => {
-                            tracing::warn!("error opening bidi substream: {}", e);
-                            tracing::warn!("recreating connection");
-                            // NOTE: the connection might be stale, so we recreate the
-                            // connection and set the request as pending instead of
-                            // sending the error as a response
-                            reconnect.set_not_connected();
-                            pending_request = Some(request);
-                        }
+                    if let Err((request, e)) = request.open(connection).await {
+                        tracing::warn!("error opening substream: {}", e);
+                        tracing::warn!("recreating connection");
+                        // NOTE: the connection might be stale, so we recreate the
+                        // connection and set the request as pending instead of
+                        // sending the error as a response
+                        reconnect.set_not_connected();
+                        pending_request = Some(request);
                     }
                 }
             }
@@ -428,57 +711,618 @@ impl<In: RpcMessage, Out: RpcMessage> QuinnConnector<In, Out> {
     }
 
     async fn reconnect_handler(
-        endpoint: quinn::Endpoint,
-        addr: SocketAddr,
-        name: String,
-        requests: flume::Receiver<oneshot::Sender<Result<SocketInner, quinn::ConnectionError>>>,
+        reconnect: ReconnectHandler,
+        requests: flume::Receiver<OpenRequest<quinn::ConnectionError>>,
+        incoming: Option<flume::Sender<Accepted>>,
+        current: watch::Sender<Option<quinn::Connection>>,
+        handshake: watch::Sender<Option<Handshake>>,
+        events: watch::Sender<ConnectionEvent>,
     ) {
-        Self::reconnect_handler_inner(endpoint, addr, name, requests).await;
+        Self::reconnect_handler_inner(reconnect, requests, incoming, current, handshake, events)
+            .await;
         tracing::info!("Reconnect handler finished");
     }
 
     /// Create a new channel
     pub fn from_connection(connection: quinn::Connection) -> Self {
+        Self::from_connection_inner(connection, None)
+    }
+
+    /// Create a new channel, and a listener for substreams opened by the remote
+    ///
+    /// The listener accepts the substreams that the remote opens on the same
+    /// connection, so the remote can call a service on this side without a
+    /// separate endpoint. The message types of the listener are independent of
+    /// the message types of the connector.
+    ///
+    /// Substreams opened by the remote are queued until they are accepted. The
+    /// listener stops accepting substreams when the connector is dropped.
+    pub fn from_connection_with_listener<In2: RpcMessage, Out2: RpcMessage>(
+        connection: quinn::Connection,
+    ) -> (Self, QuinnListener<In2, Out2>) {
+        let (sender, receiver) = flume::bounded(16);
+        let connector = Self::from_connection_inner(connection, Some(sender));
+        (connector, reverse_listener(receiver, Vec::new()))
+    }
+
+    fn from_connection_inner(
+        connection: quinn::Connection,
+        incoming: Option<flume::Sender<Accepted>>,
+    ) -> Self {
         let (sender, receiver) = flume::bounded(16);
-        let task = tokio::spawn(Self::single_connection_handler(connection, receiver));
+        let (_, current) = watch::channel(Some(connection.clone()));
+        let (_, handshake) = watch::channel(None);
+        let (events_tx, events) = watch::channel(ConnectionEvent::Connected);
+        let task = tokio::spawn(Self::single_connection_handler(
+            connection, receiver, incoming, events_tx,
+        ));
         Self {
             inner: Arc::new(ClientConnectionInner {
                 endpoint: None,
                 task: Some(task),
                 sender,
+                connection: current,
+                handshake,
+                events,
             }),
+            zero_rtt: None,
+            frames: FrameConfig::default(),
             _p: PhantomData,
         }
     }
 
     /// Create a new channel
-    pub fn new(endpoint: quinn::Endpoint, addr: SocketAddr, name: String) -> Self {
+    ///
+    /// `addrs` is either a single address or a list of addresses of the same
+    /// server, e.g. its IPv4 and IPv6 addresses. Connection attempts to the addresses
+    /// are started one after the other, [CONNECT_ATTEMPT_DELAY] apart or as soon as
+    /// the previous attempt failed, and the first connection that is established
+    /// wins. The address that worked is tried first on the next reconnect.
+    ///
+    /// To reach both IPv4 and IPv6 addresses, the endpoint must be bound to a dual
+    /// stack socket, like `[::]:0`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the list of addresses is empty.
+    pub fn new(endpoint: quinn::Endpoint, addrs: impl Into<RemoteAddrs>, name: String) -> Self {
+        Self::builder(endpoint, addrs, name).build()
+    }
+
+    /// Create a builder to configure the channel, e.g. with 0-RTT or a client config
+    ///
+    /// The arguments are the same as for [QuinnConnector::new].
+    pub fn builder(
+        endpoint: quinn::Endpoint,
+        addrs: impl Into<RemoteAddrs>,
+        name: String,
+    ) -> QuinnConnectorBuilder<In, Out> {
+        QuinnConnectorBuilder {
+            endpoint,
+            addrs: addrs.into(),
+            name,
+            config: None,
+            zero_rtt: None,
+            _p: PhantomData,
+        }
+    }
+
+    fn new_inner(
+        endpoint: quinn::Endpoint,
+        config: Option<quinn::ClientConfig>,
+        addrs: RemoteAddrs,
+        name: String,
+        incoming: Option<flume::Sender<Accepted>>,
+        zero_rtt: Option<ZeroRttFilter<Out>>,
+    ) -> Self {
+        assert!(!addrs.0.is_empty(), "no remote address");
         let (sender, receiver) = flume::bounded(16);
+        let (current_tx, current) = watch::channel(None);
+        let (handshake_tx, handshake) = watch::channel(None);
+        let (events_tx, events) = watch::channel(ConnectionEvent::Connecting);
         let task = tokio::spawn(Self::reconnect_handler(
-            endpoint.clone(),
-            addr,
-            name,
+            ReconnectHandler {
+                endpoint: endpoint.clone(),
+                config,
+                zero_rtt: zero_rtt.is_some(),
+                accepted: None,
+                state: ConnectionState::NotConnected,
+                addrs: addrs.0,
+                name,
+            },
             receiver,
+            incoming,
+            current_tx,
+            handshake_tx,
+            events_tx,
         ));
         Self {
             inner: Arc::new(ClientConnectionInner {
                 endpoint: Some(endpoint),
                 task: Some(task),
                 sender,
+                connection: current,
+                handshake,
+                events,
             }),
+            zero_rtt,
+            frames: FrameConfig::default(),
+            _p: PhantomData,
+        }
+    }
+}
+
+/// Builder for a [QuinnConnector]
+///
+/// Created with [QuinnConnector::builder].
+pub struct QuinnConnectorBuilder<In: RpcMessage, Out: RpcMessage> {
+    endpoint: quinn::Endpoint,
+    addrs: RemoteAddrs,
+    name: String,
+    config: Option<quinn::ClientConfig>,
+    zero_rtt: Option<ZeroRttFilter<Out>>,
+    _p: PhantomData<In>,
+}
+
+impl<In: RpcMessage, Out: RpcMessage> fmt::Debug for QuinnConnectorBuilder<In, Out> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QuinnConnectorBuilder")
+            .field("endpoint", &self.endpoint)
+            .field("addrs", &self.addrs)
+            .field("name", &self.name)
+            .field("zero_rtt", &self.zero_rtt.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage> QuinnConnectorBuilder<In, Out> {
+    /// Connect with the given client config, instead of the default client config
+    /// of the endpoint
+    ///
+    /// Use this e.g. to tune the [quinn::TransportConfig] for this connector only,
+    /// see [TransportSettings].
+    pub fn client_config(mut self, config: quinn::ClientConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Send requests in the first flight when reconnecting
+    ///
+    /// After a connection to the server was established once, later connections
+    /// can resume the TLS session and send data before the handshake is complete,
+    /// saving a round trip. This needs early data to be enabled in the rustls
+    /// config of both sides.
+    ///
+    /// 0-RTT data can be replayed by an attacker, so only messages for which
+    /// `safe` returns true, like idempotent requests, are sent before the
+    /// handshake is complete. Other messages wait for the handshake. If the
+    /// server rejects 0-RTT, channels opened before the handshake was complete
+    /// fail with an error.
+    pub fn zero_rtt(mut self, safe: impl Fn(&Out) -> bool + Send + Sync + 'static) -> Self {
+        self.zero_rtt = Some(Arc::new(safe));
+        self
+    }
+
+    /// Create the channel
+    ///
+    /// # Panics
+    ///
+    /// Panics if the list of addresses is empty.
+    pub fn build(self) -> QuinnConnector<In, Out> {
+        QuinnConnector::new_inner(
+            self.endpoint,
+            self.config,
+            self.addrs,
+            self.name,
+            None,
+            self.zero_rtt,
+        )
+    }
+
+    /// Create the channel, and a listener for substreams opened by the remote
+    ///
+    /// Like [QuinnConnector::from_connection_with_listener], but the listener
+    /// follows reconnects. Substreams are accepted on whatever connection is
+    /// currently established.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the list of addresses is empty.
+    pub fn build_with_listener<In2: RpcMessage, Out2: RpcMessage>(
+        self,
+    ) -> (QuinnConnector<In, Out>, QuinnListener<In2, Out2>) {
+        let local_addr = self
+            .endpoint
+            .local_addr()
+            .map(LocalAddr::Socket)
+            .into_iter()
+            .collect();
+        let (sender, receiver) = flume::bounded(16);
+        let connector = QuinnConnector::new_inner(
+            self.endpoint,
+            self.config,
+            self.addrs,
+            self.name,
+            Some(sender),
+            self.zero_rtt,
+        );
+        (connector, reverse_listener(receiver, local_addr))
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage, C: Codec> QuinnConnector<In, Out, C> {
+    /// Set the maximum length of encoded messages that are sent
+    ///
+    /// Sending a larger message fails with [MessageTooLarge](crate::codec::MessageTooLarge). The default is 16 MiB.
+    pub fn max_send_frame_length(mut self, value: usize) -> Self {
+        self.frames.send = value;
+        self
+    }
+
+    /// Set the maximum length of encoded messages that are received
+    ///
+    /// Receiving a larger message fails with [MessageTooLarge](crate::codec::MessageTooLarge), and ends the
+    /// substream. The default is 16 MiB.
+    pub fn max_recv_frame_length(mut self, value: usize) -> Self {
+        self.frames.recv = value;
+        self
+    }
+
+    /// Set how messages are delimited on the substreams
+    ///
+    /// The listener has to use the same framing.
+    pub fn framing(mut self, value: Framing) -> Self {
+        self.frames.framing = value;
+        self
+    }
+
+    /// Use a different [Codec] for the messages of this connector
+    ///
+    /// The listener has to use the same codec.
+    pub fn with_codec<C2: Codec>(self) -> QuinnConnector<In, Out, C2> {
+        QuinnConnector {
+            inner: self.inner,
+            frames: self.frames,
+            zero_rtt: self.zero_rtt,
             _p: PhantomData,
         }
     }
+
+    /// Path events of the connections of this connector
+    ///
+    /// The stream follows reconnects: it yields [PathEvent::Connected] for every new
+    /// connection, followed by the path events of that connection. Quinn does not
+    /// notify about path changes, so the connection is checked every `interval`.
+    ///
+    /// The stream ends when the connector is dropped, or for a connector created
+    /// from a single connection, when that connection is closed.
+    pub fn path_events(
+        &self,
+        interval: Duration,
+    ) -> impl Stream<Item = PathEvent> + Send + Unpin + 'static {
+        connection_path_events(self.inner.connection.clone(), interval)
+    }
+
+    /// Open a raw bidirectional stream on the current connection
+    async fn open_bi(
+        &self,
+    ) -> Result<(quinn::SendStream, quinn::RecvStream), quinn::ConnectionError> {
+        let (sender, receiver) = oneshot::channel();
+        self.inner
+            .sender
+            .send_async(OpenRequest::Bi(sender))
+            .await
+            .map_err(|_| quinn::ConnectionError::LocallyClosed)?;
+        receiver
+            .await
+            .map_err(|_| quinn::ConnectionError::LocallyClosed)?
+    }
+
+    /// Wrap a newly opened send stream, gating 0-RTT data if configured
+    fn send_sink(&self, send: quinn::SendStream) -> SendSink<Out, C> {
+        let mut send = SendSink::new(send, self.frames);
+        if let Some(safe) = &self.zero_rtt {
+            if let Some(handshake) = self.inner.handshake.borrow().clone() {
+                send.2 = Some(ZeroRttGate {
+                    safe: safe.clone(),
+                    handshake,
+                    pending: None,
+                });
+            }
+        }
+        send
+    }
+}
+
+/// A change of the network path of a quinn connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathEvent {
+    /// A new connection was established
+    Connected {
+        /// Address of the remote
+        remote: SocketAddr,
+    },
+    /// The address of the remote changed, e.g. because it migrated to a different network
+    Migrated {
+        /// Previous address of the remote
+        from: SocketAddr,
+        /// New address of the remote
+        to: SocketAddr,
+    },
+    /// The local ip used by the connection changed
+    LocalIpChanged {
+        /// Previous local ip, if known
+        from: Option<IpAddr>,
+        /// New local ip, if known
+        to: Option<IpAddr>,
+    },
+    /// The path MTU changed
+    MtuChanged {
+        /// Previous MTU
+        from: u16,
+        /// New MTU
+        to: u16,
+    },
+    /// The address through which the remote sees this side changed
+    ///
+    /// This requires the address discovery extension to be enabled on both sides.
+    ObservedAddrChanged {
+        /// New observed address, if known
+        addr: Option<SocketAddr>,
+    },
+    /// The connection was closed
+    Closed {
+        /// The reason the connection was closed
+        reason: quinn::ConnectionError,
+    },
+}
+
+/// The parts of a connection that [PathEvent]s are about
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PathState {
+    remote: SocketAddr,
+    local_ip: Option<IpAddr>,
+    mtu: u16,
+    observed: Option<SocketAddr>,
+}
+
+impl PathState {
+    fn new(connection: &quinn::Connection) -> Self {
+        Self {
+            remote: connection.remote_address(),
+            local_ip: connection.local_ip(),
+            mtu: connection.stats().path.current_mtu,
+            observed: *connection.observed_external_addr().borrow(),
+        }
+    }
+
+    /// Update the state, and push an event for every change
+    fn update(&mut self, connection: &quinn::Connection, events: &mut VecDeque<PathEvent>) {
+        let new = Self::new(connection);
+        if new.remote != self.remote {
+            events.push_back(PathEvent::Migrated {
+                from: self.remote,
+                to: new.remote,
+            });
+        }
+        if new.local_ip != self.local_ip {
+            events.push_back(PathEvent::LocalIpChanged {
+                from: self.local_ip,
+                to: new.local_ip,
+            });
+        }
+        if new.mtu != self.mtu {
+            events.push_back(PathEvent::MtuChanged {
+                from: self.mtu,
+                to: new.mtu,
+            });
+        }
+        if new.observed != self.observed {
+            events.push_back(PathEvent::ObservedAddrChanged { addr: new.observed });
+        }
+        *self = new;
+    }
+}
+
+/// Path events of a single quinn connection
+///
+/// Quinn does not notify about path changes, so the connection is checked every
+/// `interval`. The stream ends with [PathEvent::Closed] when the connection is
+/// closed.
+pub fn path_events(
+    connection: quinn::Connection,
+    interval: Duration,
+) -> impl Stream<Item = PathEvent> + Send + Unpin + 'static {
+    let (_, current) = watch::channel(Some(connection));
+    connection_path_events(current, interval)
+}
+
+fn connection_path_events(
+    mut current: watch::Receiver<Option<quinn::Connection>>,
+    interval: Duration,
+) -> impl Stream<Item = PathEvent> + Send + Unpin + 'static {
+    struct State {
+        current: watch::Receiver<Option<quinn::Connection>>,
+        /// Whether the sender of `current` is still alive
+        following: bool,
+        connection: Option<(quinn::Connection, PathState)>,
+        events: VecDeque<PathEvent>,
+        interval: tokio::time::Interval,
+    }
+
+    // a connection that is already established when the stream is created counts as new
+    current.mark_changed();
+    let mut interval = tokio::time::interval(interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let state = State {
+        current,
+        following: true,
+        connection: None,
+        events: VecDeque::new(),
+        interval,
+    };
+    Box::pin(futures_lite::stream::unfold(
+        state,
+        |mut state| async move {
+            loop {
+                if let Some(event) = state.events.pop_front() {
+                    return Some((event, state));
+                }
+                if !state.following && state.connection.is_none() {
+                    return None;
+                }
+                tokio::select! {
+                    res = state.current.changed(), if state.following => {
+                        if res.is_err() {
+                            // the sender is gone, and the last value was already seen
+                            state.following = false;
+                            continue;
+                        }
+                        let Some(connection) = state.current.borrow_and_update().clone() else {
+                            continue;
+                        };
+                        let path = PathState::new(&connection);
+                        state.events.push_back(PathEvent::Connected {
+                            remote: path.remote,
+                        });
+                        state.connection = Some((connection, path));
+                    }
+                    _ = state.interval.tick(), if state.connection.is_some() => {
+                        let (connection, path) = state.connection.as_mut().expect("checked above");
+                        if let Some(reason) = connection.close_reason() {
+                            state.events.push_back(PathEvent::Closed { reason });
+                            state.connection = None;
+                        } else {
+                            path.update(connection, &mut state.events);
+                        }
+                    }
+                }
+            }
+        },
+    ))
+}
+
+/// Accept substreams opened by the remote on a client connection
+///
+/// The task is aborted when the returned handle is dropped, so it does not keep
+/// the connection alive.
+fn accept_substreams(
+    connection: quinn::Connection,
+    incoming: flume::Sender<Accepted>,
+) -> AbortOnDropHandle<()> {
+    AbortOnDropHandle::new(tokio::spawn(QuinnListener::<(), ()>::connection_handler(
+        connection, incoming, None,
+    )))
+}
+
+/// Report [ConnectionEvent::Disconnected] once the connection is closed
+///
+/// The task is aborted when the returned handle is dropped, so a connection that
+/// was already replaced does not report its close.
+fn watch_closed(
+    connection: quinn::Connection,
+    events: watch::Sender<ConnectionEvent>,
+) -> AbortOnDropHandle<()> {
+    AbortOnDropHandle::new(tokio::spawn(async move {
+        let reason = connection.closed().await;
+        events.send_replace(ConnectionEvent::Disconnected(Arc::new(reason.into())));
+    }))
+}
+
+/// A listener for the substreams accepted by [accept_substreams]
+fn reverse_listener<In: RpcMessage, Out: RpcMessage>(
+    receiver: flume::Receiver<Accepted>,
+    local_addr: Vec<LocalAddr>,
+) -> QuinnListener<In, Out> {
+    QuinnListener {
+        inner: Arc::new(ListenerInner {
+            endpoints: Vec::new(),
+            task: None,
+            local_addr,
+            receiver: Incoming::Accepted(receiver),
+            uni: None,
+        }),
+        frames: FrameConfig::default(),
+        _p: PhantomData,
+    }
 }
 
 struct ReconnectHandler {
     endpoint: quinn::Endpoint,
+    /// Client config to use instead of the default client config of the endpoint
+    config: Option<quinn::ClientConfig>,
+    /// Whether to use 0-RTT when possible
+    zero_rtt: bool,
+    /// Resolves when the handshake of a new 0-RTT connection is done
+    accepted: Option<quinn::ZeroRttAccepted>,
     state: ConnectionState,
-    addr: SocketAddr,
+    /// Addresses of the remote, the one that worked last comes first
+    addrs: Vec<SocketAddr>,
     name: String,
 }
 
 impl ReconnectHandler {
+    /// Race connection attempts to all addresses, staggered by [CONNECT_ATTEMPT_DELAY]
+    fn connect(&self) -> BoxFuture<'static, Result<Connected, ReconnectErr>> {
+        let endpoint = self.endpoint.clone();
+        let config = self.config.clone();
+        let addrs = self.addrs.clone();
+        let name = self.name.clone();
+        let zero_rtt = self.zero_rtt;
+        async move {
+            let mut attempts = futures_util::stream::FuturesUnordered::new();
+            let mut addrs = addrs.into_iter();
+            let mut last_err = None;
+            loop {
+                let mut more = false;
+                if let Some(addr) = addrs.next() {
+                    more = true;
+                    let connecting = match &config {
+                        Some(config) => endpoint.connect_with(config.clone(), addr, &name),
+                        None => endpoint.connect(addr, &name),
+                    };
+                    let connecting = match connecting {
+                        Ok(connecting) if zero_rtt => match connecting.into_0rtt() {
+                            Ok((connection, accepted)) => {
+                                tracing::debug!(%addr, "connecting with 0-RTT");
+                                return Ok((connection, addr, Some(accepted)));
+                            }
+                            // no session ticket for the server yet
+                            Err(connecting) => connecting,
+                        },
+                        Ok(connecting) => connecting,
+                        Err(e) => {
+                            tracing::debug!(%addr, %e, "error calling connect");
+                            last_err = Some(ReconnectErr::Connect(e));
+                            continue;
+                        }
+                    };
+                    attempts.push(connecting.map(move |res| (addr, res)));
+                } else if attempts.is_empty() {
+                    return Err(last_err.expect("at least one address"));
+                }
+                let delay = async {
+                    if more {
+                        tokio::time::sleep(CONNECT_ATTEMPT_DELAY).await
+                    } else {
+                        std::future::pending().await
+                    }
+                };
+                tokio::select! {
+                    Some((addr, res)) = attempts.next() => match res {
+                        Ok(connection) => return Ok((connection, addr, None)),
+                        Err(e) => {
+                            tracing::debug!(%addr, %e, "connection attempt failed");
+                            last_err = Some(ReconnectErr::Connection(e));
+                        }
+                    },
+                    _ = delay => {}
+                }
+            }
+        }
+        .boxed()
+    }
+
     pub fn set_not_connected(&mut self) {
         self.state.set_not_connected()
     }
@@ -492,7 +1336,7 @@ enum ConnectionState {
     /// There is no active connection. An attempt to connect will be made.
     NotConnected,
     /// Connecting to the remote.
-    Connecting(quinn::Connecting),
+    Connecting(BoxFuture<'static, Result<Connected, ReconnectErr>>),
     /// A connection is already established. In this state, no more connection attempts are made.
     Connected(quinn::Connection),
     /// Intermediate state while processing.
@@ -509,6 +1353,13 @@ impl ConnectionState {
     }
 }
 
+/// A new connection, the address it was established to, and whether it used 0-RTT
+type Connected = (
+    quinn::Connection,
+    SocketAddr,
+    Option<quinn::ZeroRttAccepted>,
+);
+
 enum ReconnectErr {
     Connect(quinn::ConnectError),
     Connection(quinn::ConnectionError),
@@ -519,26 +1370,23 @@ impl Future for ReconnectHandler {
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         match self.state.poison() {
-            ConnectionState::NotConnected => match self.endpoint.connect(self.addr, &self.name) {
-                Ok(connecting) => {
-                    self.state = ConnectionState::Connecting(connecting);
-                    self.poll(cx)
-                }
-                Err(e) => {
-                    self.state = ConnectionState::NotConnected;
-                    Poll::Ready(Err(ReconnectErr::Connect(e)))
-                }
-            },
-            ConnectionState::Connecting(mut connecting) => match Pin::new(&mut connecting).poll(cx)
-            {
+            ConnectionState::NotConnected => {
+                self.state = ConnectionState::Connecting(self.connect());
+                self.poll(cx)
+            }
+            ConnectionState::Connecting(mut connecting) => match connecting.poll_unpin(cx) {
                 Poll::Ready(res) => match res {
-                    Ok(connection) => {
+                    Ok((connection, addr, accepted)) => {
+                        if let Some(i) = self.addrs.iter().position(|a| *a == addr) {
+                            self.addrs[..=i].rotate_right(1);
+                        }
+                        self.accepted = accepted;
                         self.state = ConnectionState::Connected(connection.clone());
                         Poll::Ready(Ok(connection))
                     }
                     Err(e) => {
                         self.state = ConnectionState::NotConnected;
-                        Poll::Ready(Err(ReconnectErr::Connection(e)))
+                        Poll::Ready(Err(e))
                     }
                 },
                 Poll::Pending => {
@@ -607,7 +1455,7 @@ impl<T> Stream for Receiver<'_, T> {
     }
 }
 
-impl<In: RpcMessage, Out: RpcMessage> fmt::Debug for QuinnConnector<In, Out> {
+impl<In: RpcMessage, Out: RpcMessage, C: Codec> fmt::Debug for QuinnConnector<In, Out, C> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("ClientChannel")
             .field("inner", &self.inner)
@@ -615,65 +1463,136 @@ impl<In: RpcMessage, Out: RpcMessage> fmt::Debug for QuinnConnector<In, Out> {
     }
 }
 
-impl<In: RpcMessage, Out: RpcMessage> Clone for QuinnConnector<In, Out> {
+impl<In: RpcMessage, Out: RpcMessage, C: Codec> Clone for QuinnConnector<In, Out, C> {
     fn clone(&self) -> Self {
         Self {
             inner: self.inner.clone(),
+            zero_rtt: self.zero_rtt.clone(),
+            frames: self.frames,
             _p: PhantomData,
         }
     }
 }
 
-impl<In: RpcMessage, Out: RpcMessage> ConnectionErrors for QuinnConnector<In, Out> {
+impl<In: RpcMessage, Out: RpcMessage, C: Codec> ConnectionErrors for QuinnConnector<In, Out, C> {
     type SendError = io::Error;
     type RecvError = io::Error;
     type OpenError = quinn::ConnectionError;
     type AcceptError = quinn::ConnectionError;
 }
 
-impl<In: RpcMessage, Out: RpcMessage> StreamTypes for QuinnConnector<In, Out> {
+impl<In: RpcMessage, Out: RpcMessage, C: Codec> StreamTypes for QuinnConnector<In, Out, C> {
     type In = In;
     type Out = Out;
-    type SendSink = self::SendSink<Out>;
-    type RecvStream = self::RecvStream<In>;
+    type SendSink = self::SendSink<Out, C>;
+    type RecvStream = self::RecvStream<In, C>;
 }
 
-impl<In: RpcMessage, Out: RpcMessage> Connector for QuinnConnector<In, Out> {
+impl<In: RpcMessage, Out: RpcMessage, C: Codec> Connector for QuinnConnector<In, Out, C> {
     async fn open(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        let (send, recv) = self.open_bi().await?;
+        Ok((self.send_sink(send), RecvStream::new(recv, self.frames)))
+    }
+
+    async fn open_with_priority(
+        &self,
+        priority: i32,
+    ) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        let (send, recv) = self.open_bi().await?;
+        // the stream can only be closed if the connection is lost, which the first
+        // send will report
+        send.set_priority(priority).ok();
+        Ok((self.send_sink(send), RecvStream::new(recv, self.frames)))
+    }
+
+    async fn open_uni(&self) -> Result<Self::SendSink, Self::OpenError> {
         let (sender, receiver) = oneshot::channel();
         self.inner
             .sender
-            .send_async(sender)
+            .send_async(OpenRequest::Uni(sender))
             .await
             .map_err(|_| quinn::ConnectionError::LocallyClosed)?;
-        let (send, recv) = receiver
+        let send = receiver
             .await
             .map_err(|_| quinn::ConnectionError::LocallyClosed)??;
-        Ok((SendSink::new(send), RecvStream::new(recv)))
+        Ok(self.send_sink(send))
+    }
+
+    async fn send_datagram(&self, msg: Out) -> Result<Option<Out>, io::Error> {
+        let connection = self.inner.connection.borrow().clone();
+        Ok(match connection {
+            Some(connection) => util::send_datagram::<_, C>(&connection, msg),
+            None => Some(msg),
+        })
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage, C: Codec> ConnectionEvents for QuinnConnector<In, Out, C> {
+    fn connection_events(&self) -> watch::Receiver<ConnectionEvent> {
+        self.inner.events.clone()
     }
 }
 
-/// A sink that wraps a quinn SendStream with length delimiting and postcard
+/// A sink that wraps a quinn SendStream with length delimiting and a [Codec]
 ///
 /// If you want to send bytes directly, use [SendSink::into_inner] to get the
 /// underlying [quinn::SendStream].
 #[pin_project]
-pub struct SendSink<Out>(#[pin] FramedPostcardWrite<quinn::SendStream, Out>);
+pub struct SendSink<Out, C: Codec = PostcardCodec>(
+    #[pin] FramedCodecWrite<quinn::SendStream, Out, C>,
+    Option<StreamPermit>,
+    Option<ZeroRttGate<Out>>,
+);
+
+/// Holds back messages that are not safe for 0-RTT until the handshake is done
+struct ZeroRttGate<Out> {
+    safe: ZeroRttFilter<Out>,
+    handshake: Handshake,
+    pending: Option<Out>,
+}
 
-impl<Out> fmt::Debug for SendSink<Out> {
+impl<Out, C: Codec> fmt::Debug for SendSink<Out, C> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("SendSink").finish()
     }
 }
 
-impl<Out: Serialize> SendSink<Out> {
-    fn new(inner: quinn::SendStream) -> Self {
-        let inner = FramedPostcardWrite::new(inner, MAX_FRAME_LENGTH);
-        Self(inner)
+impl<Out: Serialize, C: Codec> SendSink<Out, C> {
+    fn new(inner: quinn::SendStream, frames: FrameConfig) -> Self {
+        Self::with_permit(inner, frames, None)
+    }
+
+    fn with_permit(
+        inner: quinn::SendStream,
+        FrameConfig {
+            send: max_frame_length,
+            framing,
+            ..
+        }: FrameConfig,
+        permit: Option<StreamPermit>,
+    ) -> Self {
+        let inner = FramedCodecWrite::new(inner, framing, max_frame_length);
+        Self(inner, permit, None)
+    }
+
+    /// Send the message that waits for the handshake, once it is done
+    fn poll_pending(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut this = self.project();
+        let Some(gate) = this.2.as_mut() else {
+            return Poll::Ready(Ok(()));
+        };
+        if gate.pending.is_some() {
+            futures_lite::ready!(gate.handshake.poll_unpin(cx));
+            futures_lite::ready!(this.0.as_mut().poll_ready(cx))?;
+            this.0.as_mut().start_send(gate.pending.take().unwrap())?;
+            // everything can be sent directly from now on
+            *this.2 = None;
+        }
+        Poll::Ready(Ok(()))
     }
 }
 
-impl<Out> SendSink<Out> {
+impl<Out, C: Codec> SendSink<Out, C> {
     /// Get the underlying [quinn::SendStream], which implements
     /// [tokio::io::AsyncWrite] and can be used to send bytes directly.
     pub fn into_inner(self) -> quinn::SendStream {
@@ -681,64 +1600,110 @@ impl<Out> SendSink<Out> {
     }
 }
 
-impl<Out: Serialize> Sink<Out> for SendSink<Out> {
+impl<Out: Serialize, C: Codec> Sink<Out> for SendSink<Out, C> {
     type Error = io::Error;
 
     fn poll_ready(
-        self: Pin<&mut Self>,
+        mut self: Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Result<(), Self::Error>> {
+        futures_lite::ready!(self.as_mut().poll_pending(cx))?;
         Pin::new(&mut self.project().0).poll_ready(cx)
     }
 
     fn start_send(self: Pin<&mut Self>, item: Out) -> Result<(), Self::Error> {
-        Pin::new(&mut self.project().0).start_send(item)
+        let this = self.project();
+        match this.2 {
+            Some(gate) if !(gate.safe)(&item) => {
+                gate.pending = Some(item);
+                Ok(())
+            }
+            _ => this.0.start_send(item),
+        }
     }
 
     fn poll_flush(
-        self: Pin<&mut Self>,
+        mut self: Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Result<(), Self::Error>> {
+        futures_lite::ready!(self.as_mut().poll_pending(cx))?;
         Pin::new(&mut self.project().0).poll_flush(cx)
     }
 
     fn poll_close(
-        self: Pin<&mut Self>,
+        mut self: Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Result<(), Self::Error>> {
+        futures_lite::ready!(self.as_mut().poll_pending(cx))?;
         Pin::new(&mut self.project().0).poll_close(cx)
     }
 }
 
-/// A stream that wraps a quinn RecvStream with length delimiting and postcard
+/// A stream that wraps a quinn RecvStream with length delimiting and a [Codec]
 ///
 /// If you want to receive bytes directly, use [RecvStream::into_inner] to get
 /// the underlying [quinn::RecvStream].
 #[pin_project]
-pub struct RecvStream<In>(#[pin] FramedPostcardRead<quinn::RecvStream, In>);
+pub struct RecvStream<In, C: Codec = PostcardCodec>(
+    #[pin] FramedCodecRead<quinn::RecvStream, In, C>,
+    Option<StreamPermit>,
+    Option<PeerIdentity>,
+);
 
-impl<In> fmt::Debug for RecvStream<In> {
+impl<In, C: Codec> fmt::Debug for RecvStream<In, C> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("RecvStream").finish()
     }
 }
 
-impl<In: DeserializeOwned> RecvStream<In> {
-    fn new(inner: quinn::RecvStream) -> Self {
-        let inner = FramedPostcardRead::new(inner, MAX_FRAME_LENGTH);
-        Self(inner)
+impl<In: DeserializeOwned, C: Codec> RecvStream<In, C> {
+    fn new(inner: quinn::RecvStream, frames: FrameConfig) -> Self {
+        Self::accepted(inner, frames, None, None)
+    }
+
+    fn accepted(
+        inner: quinn::RecvStream,
+        FrameConfig {
+            recv: max_frame_length,
+            framing,
+            ..
+        }: FrameConfig,
+        permit: Option<StreamPermit>,
+        peer: Option<PeerIdentity>,
+    ) -> Self {
+        let inner = FramedCodecRead::new(inner, framing, max_frame_length);
+        Self(inner, permit, peer)
+    }
+}
+
+impl<In, C: Codec> PeerInfo for RecvStream<In, C> {
+    /// The certificates of the remote, for substreams accepted by a listener
+    fn peer_identity(&self) -> Option<&PeerIdentity> {
+        self.2.as_ref()
     }
 }
 
-impl<In> RecvStream<In> {
+impl<In, C: Codec> RecvStream<In, C> {
     /// Get the underlying [quinn::RecvStream], which implements
     /// [tokio::io::AsyncRead] and can be used to receive bytes directly.
+    ///
+    /// Bytes that were already read from the stream but not yet decoded are lost.
+    /// Use [RecvStream::into_parts] if the remote may write raw bytes right after
+    /// its last message.
     pub fn into_inner(self) -> quinn::RecvStream {
         self.0.into_inner()
     }
+
+    /// Get the underlying [quinn::RecvStream], and the bytes that were already
+    /// read from it
+    ///
+    /// The returned bytes come before whatever is read from the stream.
+    pub fn into_parts(self) -> (quinn::RecvStream, bytes::Bytes) {
+        self.0.into_parts()
+    }
 }
 
-impl<In: DeserializeOwned> Stream for RecvStream<In> {
+impl<In: DeserializeOwned, C: Codec> Stream for RecvStream<In, C> {
     type Item = result::Result<In, io::Error>;
 
     fn poll_next(
@@ -792,6 +1757,263 @@ impl fmt::Display for CreateChannelError {
 
 impl std::error::Error for CreateChannelError {}
 
+/// Congestion controller for quinn connections
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CongestionControl {
+    /// Cubic, the default of quinn
+    Cubic,
+    /// NewReno
+    NewReno,
+    /// BBR, which tends to do better on lossy links with a high bandwidth delay product
+    Bbr,
+}
+
+/// Tuning for quinn connections
+///
+/// This is a builder for the most commonly tuned parts of a
+/// [quinn::TransportConfig]. Everything that is not set keeps the quinn
+/// default. Apply it to the server config of a [QuinnListener], and to the
+/// client config of a [QuinnConnector], see [QuinnConnectorBuilder::client_config].
+#[derive(Debug, Clone, Default)]
+pub struct TransportSettings {
+    keep_alive_interval: Option<Duration>,
+    max_idle_timeout: Option<Duration>,
+    stream_receive_window: Option<u32>,
+    receive_window: Option<u32>,
+    send_window: Option<u64>,
+    max_concurrent_bidi_streams: Option<u32>,
+    max_concurrent_uni_streams: Option<u32>,
+    congestion_control: Option<CongestionControl>,
+}
+
+impl TransportSettings {
+    /// Send keep alive packets at this interval, so idle connections stay open
+    pub fn keep_alive_interval(mut self, value: Duration) -> Self {
+        self.keep_alive_interval = Some(value);
+        self
+    }
+
+    /// Close connections that were idle for this long
+    pub fn max_idle_timeout(mut self, value: Duration) -> Self {
+        self.max_idle_timeout = Some(value);
+        self
+    }
+
+    /// The number of bytes the remote may send on a single substream before it is read
+    pub fn stream_receive_window(mut self, value: u32) -> Self {
+        self.stream_receive_window = Some(value);
+        self
+    }
+
+    /// The number of bytes the remote may send on all substreams of a
+    /// connection before they are read
+    pub fn receive_window(mut self, value: u32) -> Self {
+        self.receive_window = Some(value);
+        self
+    }
+
+    /// The number of bytes to buffer for sending on all substreams of a connection
+    pub fn send_window(mut self, value: u64) -> Self {
+        self.send_window = Some(value);
+        self
+    }
+
+    /// The number of substreams the remote may open concurrently
+    pub fn max_concurrent_bidi_streams(mut self, value: u32) -> Self {
+        self.max_concurrent_bidi_streams = Some(value);
+        self
+    }
+
+    /// The number of unidirectional substreams the remote may open concurrently
+    ///
+    /// Set this to zero if the remote never opens channels with
+    /// [Connector::open_uni].
+    pub fn max_concurrent_uni_streams(mut self, value: u32) -> Self {
+        self.max_concurrent_uni_streams = Some(value);
+        self
+    }
+
+    /// The congestion controller to use
+    pub fn congestion_control(mut self, value: CongestionControl) -> Self {
+        self.congestion_control = Some(value);
+        self
+    }
+
+    /// Build the transport config
+    pub fn transport_config(&self) -> quinn::TransportConfig {
+        let mut config = quinn::TransportConfig::default();
+        if let Some(value) = self.keep_alive_interval {
+            config.keep_alive_interval(Some(value));
+        }
+        if let Some(value) = self.max_idle_timeout {
+            // durations that don't fit are as good as no timeout
+            config.max_idle_timeout(quinn::IdleTimeout::try_from(value).ok());
+        }
+        if let Some(value) = self.stream_receive_window {
+            config.stream_receive_window(value.into());
+        }
+        if let Some(value) = self.receive_window {
+            config.receive_window(value.into());
+        }
+        if let Some(value) = self.send_window {
+            config.send_window(value);
+        }
+        if let Some(value) = self.max_concurrent_bidi_streams {
+            config.max_concurrent_bidi_streams(value.into());
+        }
+        if let Some(value) = self.max_concurrent_uni_streams {
+            config.max_concurrent_uni_streams(value.into());
+        }
+        match self.congestion_control {
+            None => {}
+            Some(CongestionControl::Cubic) => {
+                config.congestion_controller_factory(Arc::new(
+                    quinn::congestion::CubicConfig::default(),
+                ));
+            }
+            Some(CongestionControl::NewReno) => {
+                config.congestion_controller_factory(Arc::new(
+                    quinn::congestion::NewRenoConfig::default(),
+                ));
+            }
+            Some(CongestionControl::Bbr) => {
+                config.congestion_controller_factory(Arc::new(
+                    quinn::congestion::BbrConfig::default(),
+                ));
+            }
+        }
+        config
+    }
+
+    /// Use these settings for the connections accepted with a server config
+    pub fn apply_to_server(&self, config: &mut quinn::ServerConfig) {
+        config.transport_config(Arc::new(self.transport_config()));
+    }
+
+    /// Use these settings for the connections opened with a client config
+    pub fn apply_to_client(&self, config: &mut quinn::ClientConfig) {
+        config.transport_config(Arc::new(self.transport_config()));
+    }
+}
+
+/// Delay before the next connection attempt is started while connecting to
+/// several addresses, see [QuinnConnector::new]
+pub const CONNECT_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// One or more addresses of a remote, in order of preference
+///
+/// Created from a single [SocketAddr], or from a list of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteAddrs(Vec<SocketAddr>);
+
+impl From<SocketAddr> for RemoteAddrs {
+    fn from(addr: SocketAddr) -> Self {
+        Self(vec![addr])
+    }
+}
+
+impl From<Vec<SocketAddr>> for RemoteAddrs {
+    fn from(addrs: Vec<SocketAddr>) -> Self {
+        Self(addrs)
+    }
+}
+
+impl From<&[SocketAddr]> for RemoteAddrs {
+    fn from(addrs: &[SocketAddr]) -> Self {
+        Self(addrs.to_vec())
+    }
+}
+
+impl<const N: usize> From<[SocketAddr; N]> for RemoteAddrs {
+    fn from(addrs: [SocketAddr; N]) -> Self {
+        Self(addrs.to_vec())
+    }
+}
+
+/// The IPv4 and IPv6 wildcard addresses with the given port
+///
+/// Binding both with [bind_server_endpoints] listens on all interfaces for both
+/// address families.
+pub fn dual_stack_addrs(port: u16) -> [SocketAddr; 2] {
+    [
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port),
+        SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port),
+    ]
+}
+
+/// Bind one quinn server endpoint per address
+///
+/// IPv6 sockets are bound with `IPV6_V6ONLY`, so an IPv4 and an IPv6 wildcard
+/// address can be bound on the same port regardless of the platform defaults.
+/// If an address has port 0, it gets the port of the first endpoint that was
+/// bound, so that all endpoints share the port the OS picked.
+///
+/// If `reuse_port` is true, the sockets are bound with `SO_REUSEPORT`, so that
+/// several workers can each bind their own endpoints on the same port and the
+/// OS distributes incoming packets between them. Since the OS distributes
+/// packets by address tuple, a connection that migrates to a new address may
+/// end up on a different worker. This is only supported on unix.
+///
+/// The resulting endpoints can be combined with [QuinnListener::from_endpoints].
+pub fn bind_server_endpoints(
+    addrs: &[SocketAddr],
+    server_config: quinn::ServerConfig,
+    reuse_port: bool,
+) -> io::Result<Vec<quinn::Endpoint>> {
+    let runtime =
+        quinn::default_runtime().ok_or_else(|| io::Error::other("no async runtime found"))?;
+    let mut port = None;
+    let mut endpoints = Vec::with_capacity(addrs.len());
+    for addr in addrs {
+        let mut addr = *addr;
+        if addr.port() == 0 {
+            if let Some(port) = port {
+                addr.set_port(port);
+            }
+        }
+        let socket = bind_udp_socket(addr, reuse_port)?;
+        port.get_or_insert(socket.local_addr()?.port());
+        endpoints.push(quinn::Endpoint::new(
+            quinn::EndpointConfig::default(),
+            Some(server_config.clone()),
+            socket,
+            runtime.clone(),
+        )?);
+    }
+    Ok(endpoints)
+}
+
+fn bind_udp_socket(addr: SocketAddr, reuse_port: bool) -> io::Result<std::net::UdpSocket> {
+    use socket2::{Domain, Protocol, Socket, Type};
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    if reuse_port {
+        #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+        socket.set_reuse_port(true)?;
+        #[cfg(not(all(unix, not(any(target_os = "solaris", target_os = "illumos")))))]
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "SO_REUSEPORT is not supported on this platform",
+        ));
+    }
+    socket.bind(&addr.into())?;
+    Ok(socket.into())
+}
+
+/// Get the certificate chain the remote of a quinn connection that uses rustls
+/// presented, see [PeerIdentity::Certificates]
+pub fn peer_identity(connection: &quinn::Connection) -> Option<PeerIdentity> {
+    let certs = connection
+        .peer_identity()?
+        .downcast::<Vec<quinn::rustls::pki_types::CertificateDer<'static>>>()
+        .ok()?;
+    Some(PeerIdentity::Certificates(
+        certs.iter().map(|cert| cert.to_vec()).collect(),
+    ))
+}
+
 /// Get the handshake data from a quinn connection that uses rustls.
 pub fn get_handshake_data(
     connection: &quinn::Connection,
@@ -866,10 +2088,7 @@ mod quinn_setup_utils {
         let priv_key = rustls::pki_types::PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der());
         let cert_chain = vec![cert_der.clone()];
 
-        let mut server_config = ServerConfig::with_single_cert(cert_chain, priv_key.into())?;
-        Arc::get_mut(&mut server_config.transport)
-            .unwrap()
-            .max_concurrent_uni_streams(0_u8.into());
+        let server_config = ServerConfig::with_single_cert(cert_chain, priv_key.into())?;
 
         Ok((server_config, cert_der.to_vec()))
     }
@@ -0,0 +1,283 @@
+//! Transport that replaces its connector when opening a channel fails
+//!
+//! Some transports reconnect on their own, like the quinn and iroh connectors
+//! created from an endpoint. Others wrap a single connection that is useless once
+//! it is lost, e.g. a [FramedConnection](super::framed::FramedConnection) over a
+//! byte stream, or a connector created from an existing quinn or iroh connection.
+//!
+//! A [ReconnectingConnector] creates such connectors with a factory. All its clones
+//! share the current connector. When opening a channel on it fails, the connector
+//! is replaced with a new one from the factory, and the open is retried once.
+use std::{error, fmt, future::Future, sync::Arc};
+
+use futures_util::future::BoxFuture;
+use tokio::sync::{watch, Mutex};
+
+use super::{ConnectionErrors, Connector, StreamTypes};
+
+type Factory<C> = Arc<dyn Fn() -> BoxFuture<'static, anyhow::Result<C>> + Send + Sync>;
+
+/// The state of the current connector of a [ReconnectingConnector]
+#[derive(Debug, Clone)]
+pub enum ConnectionState {
+    /// No connector is created yet, or the last one failed and will be replaced
+    /// on the next open
+    Disconnected,
+    /// The factory is creating connector number `n`
+    Connecting(u64),
+    /// Connector number `n` is in use
+    Connected(u64),
+    /// Creating connector number `n` failed
+    Failed(u64, Arc<anyhow::Error>),
+}
+
+#[derive(Debug)]
+struct Current<C> {
+    /// The number of the connector, to tell if it was replaced in the meantime
+    generation: u64,
+    connector: Option<C>,
+}
+
+/// A connector that creates a new inner connector when opening a channel fails
+pub struct ReconnectingConnector<C> {
+    factory: Factory<C>,
+    current: Arc<Mutex<Current<C>>>,
+    state: Arc<watch::Sender<ConnectionState>>,
+}
+
+impl<C: Connector> ReconnectingConnector<C> {
+    /// Create a reconnecting connector
+    ///
+    /// This does not call the factory yet. The first connector is created on
+    /// the first open.
+    pub fn new<F, Fut, E>(factory: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<C, E>> + Send + 'static,
+        E: Into<anyhow::Error>,
+    {
+        let factory: Factory<C> = Arc::new(move || {
+            let fut = factory();
+            Box::pin(async move { fut.await.map_err(Into::into) })
+        });
+        Self {
+            factory,
+            current: Arc::new(Mutex::new(Current {
+                generation: 0,
+                connector: None,
+            })),
+            state: Arc::new(watch::channel(ConnectionState::Disconnected).0),
+        }
+    }
+
+    /// Watch the state of the current connector
+    pub fn state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.subscribe()
+    }
+
+    /// The current connector, or a new one from the factory
+    ///
+    /// Concurrent calls wait for the same connector, so that dead remotes do not
+    /// get a connection attempt per open.
+    async fn connector(&self) -> anyhow::Result<(u64, C)> {
+        let mut current = self.current.lock().await;
+        if let Some(connector) = &current.connector {
+            return Ok((current.generation, connector.clone()));
+        }
+        current.generation += 1;
+        let generation = current.generation;
+        self.state
+            .send_replace(ConnectionState::Connecting(generation));
+        match (self.factory)().await {
+            Ok(connector) => {
+                current.connector = Some(connector.clone());
+                self.state
+                    .send_replace(ConnectionState::Connected(generation));
+                Ok((generation, connector))
+            }
+            Err(cause) => {
+                let cause = Arc::new(cause);
+                self.state
+                    .send_replace(ConnectionState::Failed(generation, cause.clone()));
+                Err(anyhow::anyhow!("{cause:#}"))
+            }
+        }
+    }
+
+    /// Drop the connector with the given number, unless it was already replaced
+    async fn invalidate(&self, generation: u64) {
+        let mut current = self.current.lock().await;
+        if current.generation == generation && current.connector.take().is_some() {
+            self.state.send_replace(ConnectionState::Disconnected);
+        }
+    }
+}
+
+impl<C> Clone for ReconnectingConnector<C> {
+    fn clone(&self) -> Self {
+        Self {
+            factory: self.factory.clone(),
+            current: self.current.clone(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<C> fmt::Debug for ReconnectingConnector<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReconnectingConnector")
+            .field("state", &*self.state.borrow())
+            .finish_non_exhaustive()
+    }
+}
+
+/// OpenError for reconnecting connectors
+#[derive(Debug)]
+pub enum OpenError<C: ConnectionErrors> {
+    /// The factory failed to create a connector
+    Connect(anyhow::Error),
+    /// Opening failed, also on a new connector
+    Open(C::OpenError),
+}
+
+impl<C: ConnectionErrors> fmt::Display for OpenError<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl<C: ConnectionErrors> error::Error for OpenError<C> {}
+
+impl<C: ConnectionErrors> ConnectionErrors for ReconnectingConnector<C> {
+    type SendError = C::SendError;
+    type RecvError = C::RecvError;
+    type OpenError = OpenError<C>;
+    type AcceptError = C::AcceptError;
+}
+
+impl<C: StreamTypes> StreamTypes for ReconnectingConnector<C> {
+    type In = C::In;
+    type Out = C::Out;
+    type RecvStream = C::RecvStream;
+    type SendSink = C::SendSink;
+}
+
+impl<C: Connector> ReconnectingConnector<C> {
+    /// Open on the current connector, and once more on a new one if that fails
+    async fn open_by<T, F, Fut>(&self, f: F) -> Result<T, OpenError<C>>
+    where
+        F: Fn(C) -> Fut,
+        Fut: Future<Output = Result<T, C::OpenError>>,
+    {
+        let mut retried = false;
+        loop {
+            let (generation, connector) = self.connector().await.map_err(OpenError::Connect)?;
+            match f(connector).await {
+                Ok(res) => return Ok(res),
+                Err(cause) => {
+                    tracing::debug!("open failed on connector {generation}: {cause}");
+                    self.invalidate(generation).await;
+                    if retried {
+                        return Err(OpenError::Open(cause));
+                    }
+                    retried = true;
+                }
+            }
+        }
+    }
+}
+
+impl<C: Connector> Connector for ReconnectingConnector<C> {
+    async fn open(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        self.open_by(|connector| async move { connector.open().await })
+            .await
+    }
+
+    async fn open_with_priority(
+        &self,
+        priority: i32,
+    ) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        self.open_by(|connector| async move { connector.open_with_priority(priority).await })
+            .await
+    }
+
+    async fn open_uni(&self) -> Result<Self::SendSink, Self::OpenError> {
+        self.open_by(|connector| async move { connector.open_uni().await })
+            .await
+    }
+
+    /// Send a datagram on the current connector
+    ///
+    /// If there is no connector and creating one fails, the message is returned,
+    /// so that it is sent on a channel, and the error surfaces when opening it.
+    async fn send_datagram(&self, msg: Self::Out) -> Result<Option<Self::Out>, Self::SendError> {
+        match self.connector().await {
+            Ok((_, connector)) => connector.send_datagram(msg).await,
+            Err(_) => Ok(Some(msg)),
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "flume-transport")]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::transport::flume;
+
+    #[tokio::test]
+    async fn reconnect_after_open_error() {
+        let (listener, working) = flume::channel::<(), ()>(8);
+        let calls = Arc::new(AtomicUsize::new(0));
+        let connector = ReconnectingConnector::new({
+            let calls = calls.clone();
+            move || {
+                let working = working.clone();
+                // the first connector is dead
+                let first = calls.fetch_add(1, Ordering::SeqCst) == 0;
+                async move {
+                    anyhow::Ok(if first {
+                        flume::channel::<(), ()>(1).1
+                    } else {
+                        working
+                    })
+                }
+            }
+        });
+        assert!(matches!(
+            *connector.state().borrow(),
+            ConnectionState::Disconnected
+        ));
+        connector.open().await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert!(matches!(
+            *connector.state().borrow(),
+            ConnectionState::Connected(2)
+        ));
+        // clones share the working connector
+        for _ in 0..4 {
+            connector.clone().open().await.unwrap();
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // the remote goes away, a new connector does not help
+        drop(listener);
+        let res = connector.open().await;
+        assert!(matches!(res, Err(OpenError::Open(_))));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn factory_error() {
+        let connector = ReconnectingConnector::<flume::FlumeConnector<(), ()>>::new(|| async {
+            anyhow::bail!("no route to host")
+        });
+        let res = connector.open().await;
+        assert!(matches!(res, Err(OpenError::Connect(_))));
+        assert!(matches!(
+            *connector.state().borrow(),
+            ConnectionState::Failed(1, _)
+        ));
+    }
+}
@@ -0,0 +1,132 @@
+//! Transport over the stdin and stdout of a child process
+//!
+//! A parent process spawns a child with [spawn] and talks to it over the child's
+//! stdin and stdout. The child uses [parent] to get the other side of the
+//! connection. Both sides can open and accept substreams, see the
+//! [framed](super::framed) transport.
+//!
+//! Since stdout carries the connection, the child must not write anything else
+//! to it. Use stderr for logging.
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tokio::{
+    io::AsyncWrite,
+    process::{Child, Command},
+};
+
+use super::{
+    framed::{FramedConnection, Role},
+    mux::MuxConfig,
+};
+use crate::RpcMessage;
+
+/// Spawn a child process and connect to its stdin and stdout
+///
+/// The stdin and stdout of the command are replaced by pipes. The connection is
+/// closed when the child exits. Use [Command::kill_on_drop] if the child should
+/// be killed when the returned [Child] is dropped.
+pub fn spawn<In: RpcMessage, Out: RpcMessage>(
+    command: &mut Command,
+) -> io::Result<(FramedConnection<In, Out>, Child)> {
+    spawn_with_config(command, MuxConfig::default())
+}
+
+/// Spawn a child process and connect to its stdin and stdout, with a custom
+/// configuration of the multiplexing
+///
+/// The child has to use the same configuration with [parent_with_config].
+pub fn spawn_with_config<In: RpcMessage, Out: RpcMessage>(
+    command: &mut Command,
+    config: MuxConfig,
+) -> io::Result<(FramedConnection<In, Out>, Child)> {
+    command
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped());
+    let mut child = command.spawn()?;
+    let connection = from_child_with_config(&mut child, config)?;
+    Ok((connection, child))
+}
+
+/// Connect to the stdin and stdout of an already spawned child process
+///
+/// The child must have been spawned with piped stdin and stdout. This takes the
+/// pipes out of the child.
+pub fn from_child<In: RpcMessage, Out: RpcMessage>(
+    child: &mut Child,
+) -> io::Result<FramedConnection<In, Out>> {
+    from_child_with_config(child, MuxConfig::default())
+}
+
+/// Connect to the stdin and stdout of an already spawned child process, with a
+/// custom configuration of the multiplexing
+pub fn from_child_with_config<In: RpcMessage, Out: RpcMessage>(
+    child: &mut Child,
+    config: MuxConfig,
+) -> io::Result<FramedConnection<In, Out>> {
+    let not_piped = |name| io::Error::new(io::ErrorKind::InvalidInput, format!("{name} not piped"));
+    let stdin = child.stdin.take().ok_or_else(|| not_piped("stdin"))?;
+    let stdout = child.stdout.take().ok_or_else(|| not_piped("stdout"))?;
+    Ok(FramedConnection::with_config(
+        tokio::io::join(stdout, ClosingWriter(Some(stdin))),
+        Role::Dialer,
+        config,
+    ))
+}
+
+/// Connect to the parent process, using the stdin and stdout of this process
+///
+/// This must be called at most once per process.
+pub fn parent<In: RpcMessage, Out: RpcMessage>() -> FramedConnection<In, Out> {
+    parent_with_config(MuxConfig::default())
+}
+
+/// Connect to the parent process, with a custom configuration of the multiplexing
+///
+/// This must be called at most once per process.
+pub fn parent_with_config<In: RpcMessage, Out: RpcMessage>(
+    config: MuxConfig,
+) -> FramedConnection<In, Out> {
+    FramedConnection::with_config(
+        tokio::io::join(tokio::io::stdin(), ClosingWriter(Some(tokio::io::stdout()))),
+        Role::Acceptor,
+        config,
+    )
+}
+
+/// A writer that closes the underlying pipe on shutdown
+///
+/// Shutting down a pipe does not close it, so the remote would not see the end
+/// of the stream while the read half of the connection is still alive.
+struct ClosingWriter<W>(Option<W>);
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for ClosingWriter<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.0.as_mut() {
+            Some(inner) => Pin::new(inner).poll_write(cx, buf),
+            None => Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.0.as_mut() {
+            Some(inner) => Pin::new(inner).poll_flush(cx),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let Some(inner) = self.0.as_mut() {
+            futures_lite::ready!(Pin::new(inner).poll_shutdown(cx))?;
+            self.0 = None;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,123 @@
+//! Per connection limits for the quinn and iroh listeners
+//!
+//! QUIC limits the number of streams a peer can open at the same time, but a
+//! stream stops counting against that limit as soon as the listener accepts it.
+//! Since every accepted stream usually gets its own handler task, a single peer
+//! can keep opening streams and spawn an unbounded number of handlers.
+//!
+//! A [StreamLimit] limits the number of accepted substreams per connection that
+//! are still in use, i.e. whose send sink or receive stream has not been dropped.
+use std::sync::Arc;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use super::PeerIdentity;
+
+/// What to do with a substream when the limit is reached
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Stop accepting substreams on the connection until one is dropped
+    ///
+    /// Streams the peer opened stay queued in quinn, so the peer eventually runs
+    /// out of stream credits and has to wait.
+    Backpressure,
+    /// Accept the substream and immediately reset it with the given error code
+    Reject(u32),
+}
+
+/// A limit on the number of concurrently accepted substreams per connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamLimit {
+    max_streams: usize,
+    overflow: Overflow,
+}
+
+impl StreamLimit {
+    /// Create a new limit
+    ///
+    /// Panics if `max_streams` is 0.
+    pub fn new(max_streams: usize, overflow: Overflow) -> Self {
+        assert!(max_streams > 0, "max_streams must be at least 1");
+        Self {
+            max_streams,
+            overflow,
+        }
+    }
+
+    /// The maximum number of concurrently accepted substreams per connection
+    pub fn max_streams(&self) -> usize {
+        self.max_streams
+    }
+
+    /// What happens to substreams that exceed the limit
+    pub fn overflow(&self) -> Overflow {
+        self.overflow
+    }
+}
+
+/// Held by the send sink and receive stream of an accepted substream
+pub(crate) type StreamPermit = Arc<OwnedSemaphorePermit>;
+
+pub(crate) type Substream = (quinn::SendStream, quinn::RecvStream);
+
+/// An accepted substream, with its permit and the identity of the remote
+pub(crate) type Accepted = (Substream, Option<StreamPermit>, Option<PeerIdentity>);
+
+/// Substreams that are waiting to be accepted by a listener
+#[derive(Debug)]
+pub(crate) enum Incoming {
+    /// Substreams accepted by the listener itself, with a permit if it has a limit
+    Accepted(flume::Receiver<Accepted>),
+    /// Substreams accepted by someone else
+    External(flume::Receiver<Substream>),
+}
+
+impl Incoming {
+    pub(crate) async fn recv(&self) -> Result<Accepted, flume::RecvError> {
+        match self {
+            Self::Accepted(receiver) => receiver.recv_async().await,
+            Self::External(receiver) => Ok((receiver.recv_async().await?, None, None)),
+        }
+    }
+}
+
+/// Accept the next bidi substream of a connection, respecting the limit
+pub(crate) async fn accept_bi(
+    connection: &quinn::Connection,
+    limit: Option<&(StreamLimit, Arc<Semaphore>)>,
+) -> Result<(Substream, Option<StreamPermit>), quinn::ConnectionError> {
+    let Some((limit, semaphore)) = limit else {
+        return Ok((connection.accept_bi().await?, None));
+    };
+    match limit.overflow {
+        Overflow::Backpressure => {
+            let permit = semaphore
+                .clone()
+                .acquire_owned()
+                .await
+                .expect("semaphore is never closed");
+            let substream = connection.accept_bi().await?;
+            Ok((substream, Some(Arc::new(permit))))
+        }
+        Overflow::Reject(code) => loop {
+            let (mut send, mut recv) = connection.accept_bi().await?;
+            match semaphore.clone().try_acquire_owned() {
+                Ok(permit) => return Ok(((send, recv), Some(Arc::new(permit)))),
+                Err(_) => {
+                    tracing::debug!(
+                        "Rejecting substream {}, limit of {} reached",
+                        send.id(),
+                        limit.max_streams
+                    );
+                    send.reset(code.into()).ok();
+                    recv.stop(code.into()).ok();
+                }
+            }
+        },
+    }
+}
+
+/// Create the per connection state for a limit
+pub(crate) fn semaphore(limit: Option<StreamLimit>) -> Option<(StreamLimit, Arc<Semaphore>)> {
+    limit.map(|limit| (limit, Arc::new(Semaphore::new(limit.max_streams))))
+}
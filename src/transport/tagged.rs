@@ -0,0 +1,497 @@
+//! Share a single transport between multiple services.
+//!
+//! The underlying transport carries postcard encoded messages as raw bytes.
+//! Each substream starts with a tag that identifies the service, so several
+//! unrelated services with separate [RpcServer](crate::RpcServer)s can share one
+//! listener without merging their request enums.
+//!
+//! On the client side, wrap the connector in a [TaggedConnector] per service. On
+//! the server side, wrap the listener in a [Demux], and create a [TaggedListener]
+//! per service using [Demux::listener].
+//!
+//! Unidirectional substreams start with the tag as well. Datagrams carry the
+//! tag in front of the message.
+use std::{collections::BTreeMap, fmt, future::Future, marker::PhantomData, sync::Arc};
+
+use futures_lite::StreamExt;
+use futures_util::{future, SinkExt, TryStreamExt};
+use tokio::sync::{mpsc, Mutex};
+use tracing::{debug, warn};
+
+use super::{
+    boxed::{RecvStream, SendSink},
+    ConnectionErrors, Connector, Listener, LocalAddr, StreamTypes,
+};
+use crate::{rt, RpcMessage};
+
+/// Raw bytes, used as the message type of the underlying transport
+pub type Frame = Vec<u8>;
+
+type RawChannel = (SendSink<Frame>, RecvStream<Frame>);
+
+fn encode<T: serde::Serialize + ?Sized>(msg: &T) -> anyhow::Result<Frame> {
+    Ok(postcard::to_stdvec(msg)?)
+}
+
+fn decode<T: RpcMessage>(frame: anyhow::Result<Frame>) -> anyhow::Result<T> {
+    Ok(postcard::from_bytes(&frame?)?)
+}
+
+/// Turn a raw channel into a typed channel
+fn typed<In: RpcMessage, Out: RpcMessage>(
+    (send, recv): RawChannel,
+) -> (SendSink<Out>, RecvStream<In>) {
+    let send = send.with(|msg: Out| future::ready(encode(&msg)));
+    let recv = recv.map(decode);
+    (SendSink::boxed(send), RecvStream::boxed(recv))
+}
+
+/// A connector that prefixes each substream with a service tag
+pub struct TaggedConnector<In, Out, C> {
+    inner: C,
+    tag: Arc<str>,
+    _p: PhantomData<(In, Out)>,
+}
+
+impl<In, Out, C> TaggedConnector<In, Out, C>
+where
+    C: Connector<In = Frame, Out = Frame>,
+{
+    /// Create a new tagged connector for the service with the given tag
+    pub fn new(inner: C, tag: impl Into<Arc<str>>) -> Self {
+        Self {
+            inner,
+            tag: tag.into(),
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<In, Out, C: Clone> Clone for TaggedConnector<In, Out, C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            tag: self.tag.clone(),
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<In, Out, C: fmt::Debug> fmt::Debug for TaggedConnector<In, Out, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaggedConnector")
+            .field("inner", &self.inner)
+            .field("tag", &self.tag)
+            .finish()
+    }
+}
+
+impl<In, Out, C> ConnectionErrors for TaggedConnector<In, Out, C>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    C: ConnectionErrors,
+{
+    type SendError = anyhow::Error;
+    type RecvError = anyhow::Error;
+    type OpenError = anyhow::Error;
+    type AcceptError = anyhow::Error;
+}
+
+impl<In, Out, C> StreamTypes for TaggedConnector<In, Out, C>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    C: StreamTypes<In = Frame, Out = Frame>,
+{
+    type In = In;
+    type Out = Out;
+    type RecvStream = RecvStream<In>;
+    type SendSink = SendSink<Out>;
+}
+
+impl<In, Out, C> TaggedConnector<In, Out, C>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    C: Connector<In = Frame, Out = Frame>,
+{
+    /// Set up a channel opened on the inner connector
+    async fn open_by(
+        &self,
+        open: impl Future<Output = Result<(C::SendSink, C::RecvStream), C::OpenError>>,
+    ) -> anyhow::Result<(SendSink<Out>, RecvStream<In>)> {
+        let (mut send, recv) = open.await.map_err(Into::into)?;
+        send.send(encode(&*self.tag)?).await.map_err(Into::into)?;
+        let send = SendSink::boxed(send.sink_map_err(Into::into));
+        let recv = RecvStream::boxed(recv.map_err(Into::into));
+        Ok(typed((send, recv)))
+    }
+}
+
+impl<In, Out, C> Connector for TaggedConnector<In, Out, C>
+where
+    In: RpcMessage,
+    Out: RpcMessage,
+    C: Connector<In = Frame, Out = Frame>,
+{
+    async fn open(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        self.open_by(self.inner.open()).await
+    }
+
+    async fn open_with_priority(
+        &self,
+        priority: i32,
+    ) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        self.open_by(self.inner.open_with_priority(priority)).await
+    }
+
+    async fn open_uni(&self) -> Result<Self::SendSink, Self::OpenError> {
+        let mut send = self.inner.open_uni().await.map_err(Into::into)?;
+        send.send(encode(&*self.tag)?).await.map_err(Into::into)?;
+        let send = send
+            .sink_map_err(Into::into)
+            .with(|msg: Out| future::ready(encode(&msg)));
+        Ok(SendSink::boxed(send))
+    }
+
+    async fn send_datagram(&self, msg: Self::Out) -> Result<Option<Self::Out>, Self::SendError> {
+        let mut frame = encode(&*self.tag)?;
+        frame.extend(encode(&msg)?);
+        let returned = self.inner.send_datagram(frame).await.map_err(Into::into)?;
+        Ok(returned.map(|_| msg))
+    }
+}
+
+/// Where the substreams and datagrams for a tag are queued
+#[derive(Debug, Clone)]
+struct Route {
+    bi: mpsc::Sender<RawChannel>,
+    uni: mpsc::Sender<RecvStream<Frame>>,
+    datagrams: mpsc::Sender<Frame>,
+}
+
+type Routes = Arc<std::sync::Mutex<BTreeMap<String, Route>>>;
+
+/// Read the tag at the start of a substream
+async fn read_tag(recv: &mut RecvStream<Frame>) -> Option<String> {
+    match recv.next().await {
+        Some(Ok(frame)) => match postcard::from_bytes(&frame) {
+            Ok(tag) => Some(tag),
+            Err(cause) => {
+                debug!("invalid service tag: {cause}");
+                None
+            }
+        },
+        _ => None,
+    }
+}
+
+fn route(routes: &Routes, tag: &str) -> Option<Route> {
+    let route = routes.lock().unwrap().get(tag).cloned();
+    if route.is_none() {
+        debug!("unknown service tag {tag}");
+    }
+    route
+}
+
+/// Dispatches incoming substreams of a listener by their service tag
+///
+/// Substreams and datagrams with an unknown tag are dropped.
+pub struct Demux {
+    routes: Routes,
+    local_addr: Vec<LocalAddr>,
+    tasks: Arc<[rt::Task<()>; 3]>,
+}
+
+impl fmt::Debug for Demux {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Demux")
+            .field("tags", &self.routes.lock().unwrap().keys())
+            .finish()
+    }
+}
+
+impl Demux {
+    /// Create a new demultiplexer for the given listener
+    ///
+    /// This spawns tasks that accept substreams and receive datagrams on the
+    /// listener. The tasks are stopped when the demux and all listeners created
+    /// from it are dropped.
+    pub fn new<C>(inner: C) -> Self
+    where
+        C: Listener<In = Frame, Out = Frame>,
+    {
+        let routes: Routes = Default::default();
+        let local_addr = inner.local_addr().to_vec();
+        let tasks = [
+            rt::spawn(Self::accept_loop(inner.clone(), routes.clone())),
+            rt::spawn(Self::accept_uni_loop(inner.clone(), routes.clone())),
+            rt::spawn(Self::datagram_loop(inner, routes.clone())),
+        ];
+        Self {
+            routes,
+            local_addr,
+            tasks: Arc::new(tasks),
+        }
+    }
+
+    async fn accept_loop<C>(inner: C, routes: Routes)
+    where
+        C: Listener<In = Frame, Out = Frame>,
+    {
+        loop {
+            let (send, recv) = match inner.accept().await {
+                Ok(channel) => channel,
+                Err(cause) => {
+                    warn!("demux accept failed: {cause}");
+                    break;
+                }
+            };
+            let routes = routes.clone();
+            // read the tag on a separate task, to not block accepting other substreams
+            rt::spawn_detached(async move {
+                let mut recv = RecvStream::boxed(recv.map_err(Into::into));
+                let Some(route) = read_tag(&mut recv)
+                    .await
+                    .and_then(|tag| route(&routes, &tag))
+                else {
+                    return;
+                };
+                let send = SendSink::boxed(send.sink_map_err(Into::into));
+                route.bi.send((send, recv)).await.ok();
+            });
+        }
+    }
+
+    async fn accept_uni_loop<C>(inner: C, routes: Routes)
+    where
+        C: Listener<In = Frame, Out = Frame>,
+    {
+        loop {
+            let recv = match inner.accept_uni().await {
+                Ok(recv) => recv,
+                Err(cause) => {
+                    warn!("demux accept failed: {cause}");
+                    break;
+                }
+            };
+            let routes = routes.clone();
+            rt::spawn_detached(async move {
+                let mut recv = RecvStream::boxed(recv.map_err(Into::into));
+                let Some(route) = read_tag(&mut recv)
+                    .await
+                    .and_then(|tag| route(&routes, &tag))
+                else {
+                    return;
+                };
+                route.uni.send(recv).await.ok();
+            });
+        }
+    }
+
+    /// Queue the datagrams by their tag, dropping them if the queue is full
+    async fn datagram_loop<C>(inner: C, routes: Routes)
+    where
+        C: Listener<In = Frame, Out = Frame>,
+    {
+        loop {
+            let frame = match inner.recv_datagram().await {
+                Ok(frame) => frame,
+                Err(cause) => {
+                    warn!("demux datagram failed: {cause}");
+                    break;
+                }
+            };
+            let (tag, msg) = match postcard::take_from_bytes::<String>(&frame) {
+                Ok(tagged) => tagged,
+                Err(cause) => {
+                    debug!("invalid service tag: {cause}");
+                    continue;
+                }
+            };
+            if let Some(route) = route(&routes, &tag) {
+                route.datagrams.try_send(msg.to_vec()).ok();
+            }
+        }
+    }
+
+    /// Create a listener for the service with the given tag
+    ///
+    /// Substreams and datagrams for the service are queued until they are
+    /// accepted on the returned listener. Registering the same tag twice replaces
+    /// the previous listener.
+    pub fn listener<In, Out>(&self, tag: impl Into<String>) -> TaggedListener<In, Out> {
+        let (bi, bi_rx) = mpsc::channel(16);
+        let (uni, uni_rx) = mpsc::channel(16);
+        let (datagrams, datagrams_rx) = mpsc::channel(16);
+        let route = Route { bi, uni, datagrams };
+        self.routes.lock().unwrap().insert(tag.into(), route);
+        TaggedListener {
+            rx: Arc::new(Mutex::new(bi_rx)),
+            uni: Arc::new(Mutex::new(uni_rx)),
+            datagrams: Arc::new(Mutex::new(datagrams_rx)),
+            local_addr: self.local_addr.clone(),
+            _tasks: self.tasks.clone(),
+            _p: PhantomData,
+        }
+    }
+}
+
+/// A listener for a single service, created using [Demux::listener]
+pub struct TaggedListener<In, Out> {
+    rx: Arc<Mutex<mpsc::Receiver<RawChannel>>>,
+    uni: Arc<Mutex<mpsc::Receiver<RecvStream<Frame>>>>,
+    datagrams: Arc<Mutex<mpsc::Receiver<Frame>>>,
+    local_addr: Vec<LocalAddr>,
+    _tasks: Arc<[rt::Task<()>; 3]>,
+    _p: PhantomData<(In, Out)>,
+}
+
+impl<In, Out> Clone for TaggedListener<In, Out> {
+    fn clone(&self) -> Self {
+        Self {
+            rx: self.rx.clone(),
+            uni: self.uni.clone(),
+            datagrams: self.datagrams.clone(),
+            local_addr: self.local_addr.clone(),
+            _tasks: self._tasks.clone(),
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<In, Out> fmt::Debug for TaggedListener<In, Out> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaggedListener")
+            .field("local_addr", &self.local_addr)
+            .finish()
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage> ConnectionErrors for TaggedListener<In, Out> {
+    type SendError = anyhow::Error;
+    type RecvError = anyhow::Error;
+    type OpenError = anyhow::Error;
+    type AcceptError = anyhow::Error;
+}
+
+impl<In: RpcMessage, Out: RpcMessage> StreamTypes for TaggedListener<In, Out> {
+    type In = In;
+    type Out = Out;
+    type RecvStream = RecvStream<In>;
+    type SendSink = SendSink<Out>;
+}
+
+impl<In: RpcMessage, Out: RpcMessage> Listener for TaggedListener<In, Out> {
+    async fn accept(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::AcceptError> {
+        let channel = self
+            .rx
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| anyhow::anyhow!("demux closed"))?;
+        Ok(typed(channel))
+    }
+
+    fn local_addr(&self) -> &[LocalAddr] {
+        &self.local_addr
+    }
+
+    async fn accept_uni(&self) -> Result<Self::RecvStream, Self::AcceptError> {
+        let recv = self
+            .uni
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| anyhow::anyhow!("demux closed"))?;
+        Ok(RecvStream::boxed(recv.map(decode)))
+    }
+
+    async fn recv_datagram(&self) -> Result<Self::In, Self::RecvError> {
+        let frame = self
+            .datagrams
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| anyhow::anyhow!("demux closed"))?;
+        decode(Ok(frame))
+    }
+}
+
+#[cfg(all(test, feature = "flume-transport"))]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::{transport::flume, RpcClient, RpcServer, Service};
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Ping(u64);
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Pong(u64);
+
+    #[derive(Debug, Clone)]
+    struct PingService;
+
+    impl Service for PingService {
+        type Req = Ping;
+        type Res = Pong;
+    }
+
+    impl crate::message::RpcMsg<PingService> for Ping {
+        type Response = Pong;
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Echo(String);
+
+    #[derive(Debug, Clone)]
+    struct EchoService;
+
+    impl Service for EchoService {
+        type Req = Echo;
+        type Res = Echo;
+    }
+
+    impl crate::message::RpcMsg<EchoService> for Echo {
+        type Response = Echo;
+    }
+
+    #[tokio::test]
+    async fn two_services() -> anyhow::Result<()> {
+        let (listener, connector) = flume::channel::<Frame, Frame>(1);
+        let demux = Demux::new(listener);
+        let ping = RpcServer::<PingService, _>::new(demux.listener("ping"));
+        let echo = RpcServer::<EchoService, _>::new(demux.listener("echo"));
+        let _ping = tokio::spawn(async move {
+            loop {
+                let (req, chan) = ping.accept().await?.read_first().await?;
+                chan.rpc(req, (), |_, Ping(x)| async move { Pong(x + 1) })
+                    .await?;
+            }
+            #[allow(unreachable_code)]
+            anyhow::Ok(())
+        });
+        let _echo = tokio::spawn(async move {
+            loop {
+                let (req, chan) = echo.accept().await?.read_first().await?;
+                chan.rpc(req, (), |_, req| async move { req }).await?;
+            }
+            #[allow(unreachable_code)]
+            anyhow::Ok(())
+        });
+        let ping =
+            RpcClient::<PingService, _>::new(TaggedConnector::new(connector.clone(), "ping"));
+        let echo = RpcClient::<EchoService, _>::new(TaggedConnector::new(connector, "echo"));
+        let Pong(x) = ping.rpc(Ping(1)).await?;
+        assert_eq!(x, 2);
+        let Echo(s) = echo.rpc(Echo("hello".into())).await?;
+        assert_eq!(s, "hello");
+        Ok(())
+    }
+}
@@ -0,0 +1,400 @@
+//! Debug layer that reports every message of a connection.
+//!
+//! [TapConnector] and [TapListener] wrap any connector or listener, and call a
+//! [Tap] for every message that is sent or received on one of their substreams,
+//! with the name of the enum variant and the message encoded as a postcard frame.
+//! This is the frame the network transports put on the wire, so a tap works like
+//! a `tcpdump` for rpc calls, without a proxy.
+//!
+//! Encoding every message is not free, so this is meant for debugging.
+use std::{
+    fmt,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use futures_lite::Stream;
+use futures_sink::Sink;
+use pin_project::pin_project;
+use serde::Serialize;
+
+use super::{ConnectionErrors, Connector, Listener, LocalAddr, StreamTypes};
+
+/// Direction of a message, as seen from the tapped side
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// The message was sent
+    Sent,
+    /// The message was received
+    Received,
+}
+
+/// A message that was sent or received on a tapped connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TapEvent {
+    /// Id of the substream, unique per [Tap]
+    pub stream: u64,
+    /// Whether the message was sent or received
+    pub direction: Direction,
+    /// Name of the enum variant of the message
+    ///
+    /// `None` if a received message could not be decoded.
+    pub variant: Option<String>,
+    /// The message as a length prefixed postcard frame
+    ///
+    /// Empty if the message could not be encoded or decoded.
+    pub frame: Vec<u8>,
+}
+
+impl TapEvent {
+    /// Size of the frame in bytes
+    pub fn size(&self) -> usize {
+        self.frame.len()
+    }
+
+    fn new<T: fmt::Debug + Serialize>(stream: u64, direction: Direction, msg: &T) -> Self {
+        let frame = match postcard::to_stdvec(msg) {
+            Ok(payload) => {
+                let mut frame = Vec::with_capacity(4 + payload.len());
+                frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+                frame.extend_from_slice(&payload);
+                frame
+            }
+            Err(_) => Vec::new(),
+        };
+        Self {
+            stream,
+            direction,
+            variant: Some(variant_name(msg)),
+            frame,
+        }
+    }
+}
+
+impl fmt::Display for TapEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let arrow = match self.direction {
+            Direction::Sent => "->",
+            Direction::Received => "<-",
+        };
+        let variant = self.variant.as_deref().unwrap_or("<undecodable>");
+        write!(
+            f,
+            "#{} {} {} ({} bytes)",
+            self.stream,
+            arrow,
+            variant,
+            self.size()
+        )
+    }
+}
+
+/// The name of the outermost enum variant or struct, from the debug representation
+fn variant_name<T: fmt::Debug>(msg: &T) -> String {
+    let debug = format!("{msg:?}");
+    let end = debug
+        .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == ':'))
+        .unwrap_or(debug.len());
+    debug[..end].to_string()
+}
+
+/// Receives the events of tapped connections
+///
+/// Clones of a tap share the substream ids.
+#[derive(Clone)]
+pub struct Tap {
+    f: Arc<dyn Fn(TapEvent) + Send + Sync>,
+    next_stream: Arc<AtomicU64>,
+}
+
+impl fmt::Debug for Tap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tap").finish_non_exhaustive()
+    }
+}
+
+impl Tap {
+    /// A tap that calls `f` for every event
+    ///
+    /// `f` is called from the poll functions of the substreams, so it should not block.
+    pub fn new(f: impl Fn(TapEvent) + Send + Sync + 'static) -> Self {
+        Self {
+            f: Arc::new(f),
+            next_stream: Default::default(),
+        }
+    }
+
+    /// A tap that logs every event at debug level
+    pub fn log() -> Self {
+        Self::new(|event| tracing::debug!("{event}"))
+    }
+
+    fn next_stream(&self) -> u64 {
+        self.next_stream.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+/// A send sink that reports every sent message
+#[pin_project]
+#[derive(Debug)]
+pub struct SendSink<S> {
+    #[pin]
+    inner: S,
+    stream: u64,
+    tap: Tap,
+}
+
+impl<S: Sink<T>, T: fmt::Debug + Serialize> Sink<T> for SendSink<S> {
+    type Error = S::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        let this = self.project();
+        let event = TapEvent::new(*this.stream, Direction::Sent, &item);
+        this.inner.start_send(item)?;
+        (this.tap.f)(event);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_close(cx)
+    }
+}
+
+/// A receive stream that reports every received message
+#[pin_project]
+#[derive(Debug)]
+pub struct RecvStream<S> {
+    #[pin]
+    inner: S,
+    stream: u64,
+    tap: Tap,
+}
+
+impl<S: Stream<Item = Result<T, E>>, T: fmt::Debug + Serialize, E> Stream for RecvStream<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let res = this.inner.poll_next(cx);
+        match &res {
+            Poll::Ready(Some(Ok(item))) => {
+                (this.tap.f)(TapEvent::new(*this.stream, Direction::Received, item));
+            }
+            Poll::Ready(Some(Err(_))) => {
+                (this.tap.f)(TapEvent {
+                    stream: *this.stream,
+                    direction: Direction::Received,
+                    variant: None,
+                    frame: Vec::new(),
+                });
+            }
+            _ => {}
+        }
+        res
+    }
+}
+
+fn wrap<S, R>(tap: &Tap, (send, recv): (S, R)) -> (SendSink<S>, RecvStream<R>) {
+    let stream = tap.next_stream();
+    let send = SendSink {
+        inner: send,
+        stream,
+        tap: tap.clone(),
+    };
+    let recv = RecvStream {
+        inner: recv,
+        stream,
+        tap: tap.clone(),
+    };
+    (send, recv)
+}
+
+/// A connector that reports every message on the substreams it opens
+#[derive(Debug, Clone)]
+pub struct TapConnector<C> {
+    inner: C,
+    tap: Tap,
+}
+
+impl<C> TapConnector<C> {
+    /// Wrap a connector
+    pub fn new(inner: C, tap: Tap) -> Self {
+        Self { inner, tap }
+    }
+}
+
+impl<C: ConnectionErrors> ConnectionErrors for TapConnector<C> {
+    type SendError = C::SendError;
+    type RecvError = C::RecvError;
+    type OpenError = C::OpenError;
+    type AcceptError = C::AcceptError;
+}
+
+impl<C: StreamTypes> StreamTypes for TapConnector<C> {
+    type In = C::In;
+    type Out = C::Out;
+    type RecvStream = RecvStream<C::RecvStream>;
+    type SendSink = SendSink<C::SendSink>;
+}
+
+impl<C: Connector> Connector for TapConnector<C> {
+    async fn open(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        Ok(wrap(&self.tap, self.inner.open().await?))
+    }
+
+    async fn open_with_priority(
+        &self,
+        priority: i32,
+    ) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        Ok(wrap(
+            &self.tap,
+            self.inner.open_with_priority(priority).await?,
+        ))
+    }
+
+    async fn open_uni(&self) -> Result<Self::SendSink, Self::OpenError> {
+        Ok(SendSink {
+            inner: self.inner.open_uni().await?,
+            stream: self.tap.next_stream(),
+            tap: self.tap.clone(),
+        })
+    }
+
+    /// Send a datagram on the inner connector
+    ///
+    /// Every datagram that was sent is reported with its own stream id.
+    async fn send_datagram(&self, msg: Self::Out) -> Result<Option<Self::Out>, Self::SendError> {
+        let event = TapEvent::new(self.tap.next_stream(), Direction::Sent, &msg);
+        let res = self.inner.send_datagram(msg).await?;
+        if res.is_none() {
+            (self.tap.f)(event);
+        }
+        Ok(res)
+    }
+}
+
+/// A listener that reports every message on the substreams it accepts
+#[derive(Debug, Clone)]
+pub struct TapListener<C> {
+    inner: C,
+    tap: Tap,
+}
+
+impl<C> TapListener<C> {
+    /// Wrap a listener
+    pub fn new(inner: C, tap: Tap) -> Self {
+        Self { inner, tap }
+    }
+}
+
+impl<C: ConnectionErrors> ConnectionErrors for TapListener<C> {
+    type SendError = C::SendError;
+    type RecvError = C::RecvError;
+    type OpenError = C::OpenError;
+    type AcceptError = C::AcceptError;
+}
+
+impl<C: StreamTypes> StreamTypes for TapListener<C> {
+    type In = C::In;
+    type Out = C::Out;
+    type RecvStream = RecvStream<C::RecvStream>;
+    type SendSink = SendSink<C::SendSink>;
+}
+
+impl<C: Listener> Listener for TapListener<C> {
+    async fn accept(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::AcceptError> {
+        Ok(wrap(&self.tap, self.inner.accept().await?))
+    }
+
+    fn local_addr(&self) -> &[LocalAddr] {
+        self.inner.local_addr()
+    }
+
+    async fn accept_uni(&self) -> Result<Self::RecvStream, Self::AcceptError> {
+        Ok(RecvStream {
+            inner: self.inner.accept_uni().await?,
+            stream: self.tap.next_stream(),
+            tap: self.tap.clone(),
+        })
+    }
+
+    /// Receive a datagram on the inner listener
+    ///
+    /// Every received datagram is reported with its own stream id.
+    async fn recv_datagram(&self) -> Result<Self::In, Self::RecvError> {
+        let msg = self.inner.recv_datagram().await?;
+        (self.tap.f)(TapEvent::new(
+            self.tap.next_stream(),
+            Direction::Received,
+            &msg,
+        ));
+        Ok(msg)
+    }
+}
+
+#[cfg(all(test, feature = "flume-transport"))]
+mod tests {
+    use std::sync::Mutex;
+
+    use futures_lite::StreamExt;
+    use futures_util::SinkExt;
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::transport::flume;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    enum Msg {
+        Ping(u64),
+        Pong,
+    }
+
+    #[tokio::test]
+    async fn reports_messages() -> anyhow::Result<()> {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let tap = Tap::new({
+            let events = events.clone();
+            move |event| events.lock().unwrap().push(event)
+        });
+        let (listener, connector) = flume::channel::<Msg, Msg>(1);
+        let connector = TapConnector::new(connector, tap.clone());
+        let listener = TapListener::new(listener, tap);
+        let (mut send, mut recv) = connector.open().await?;
+        let (mut server_send, mut server_recv) = listener.accept().await?;
+        send.send(Msg::Ping(300)).await?;
+        assert_eq!(server_recv.next().await.transpose()?, Some(Msg::Ping(300)));
+        server_send.send(Msg::Pong).await?;
+        assert_eq!(recv.next().await.transpose()?, Some(Msg::Pong));
+
+        let events = events.lock().unwrap();
+        let summary = events
+            .iter()
+            .map(|e| (e.stream, e.direction, e.variant.as_deref().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                (0, Direction::Sent, "Ping"),
+                (1, Direction::Received, "Ping"),
+                (1, Direction::Sent, "Pong"),
+                (0, Direction::Received, "Pong"),
+            ]
+        );
+        // variant 0, then 300 as a varint
+        assert_eq!(events[0].frame, [0, 0, 0, 3, 0, 0xac, 0x02]);
+        assert_eq!(events[0].to_string(), "#0 -> Ping (7 bytes)");
+        Ok(())
+    }
+}
@@ -0,0 +1,279 @@
+//! Plain tcp transport
+//!
+//! For deployments where QUIC is not an option, e.g. because of restrictive
+//! firewalls. All substreams of a [TcpConnector] share a single tcp connection,
+//! which is established on the first open and reestablished when it is lost.
+//! The substreams are multiplexed like in the [framed](super::framed) transport.
+use std::{error, fmt, io, marker::PhantomData, net::SocketAddr, sync::Arc};
+
+use tokio::net::TcpStream;
+use tokio_util::task::AbortOnDropHandle;
+use tracing::{debug, trace};
+
+pub use super::framed::{RecvError, RecvStream, SendError, SendSink};
+use super::{
+    mux::{Mux, MuxConfig, Role, Substream},
+    proxy::Proxy,
+};
+use crate::{
+    transport::{ConnectionErrors, Connector, Listener, LocalAddr, StreamTypes},
+    RpcMessage,
+};
+
+#[derive(Debug)]
+struct ConnectorInner {
+    addr: SocketAddr,
+    proxy: Option<Proxy>,
+    config: MuxConfig,
+    connection: tokio::sync::Mutex<Option<Arc<Mux>>>,
+}
+
+/// A connector that opens substreams on a tcp connection to a server
+pub struct TcpConnector<In: RpcMessage, Out: RpcMessage> {
+    inner: Arc<ConnectorInner>,
+    _p: PhantomData<(In, Out)>,
+}
+
+impl<In: RpcMessage, Out: RpcMessage> TcpConnector<In, Out> {
+    /// Create a connector for the given server address
+    ///
+    /// This does not connect yet. The connection is established on the first open.
+    pub fn new(addr: SocketAddr) -> Self {
+        Self::new_with_config(addr, MuxConfig::default())
+    }
+
+    /// Create a connector for the given server address, with a custom
+    /// configuration of the multiplexing
+    ///
+    /// The listener should use the same configuration.
+    pub fn new_with_config(addr: SocketAddr, config: MuxConfig) -> Self {
+        Self {
+            inner: Arc::new(ConnectorInner {
+                addr,
+                proxy: None,
+                config,
+                connection: Default::default(),
+            }),
+            _p: PhantomData,
+        }
+    }
+
+    /// Connect through the given proxy instead of directly
+    pub fn with_proxy(self, proxy: Proxy) -> Self {
+        Self {
+            inner: Arc::new(ConnectorInner {
+                addr: self.inner.addr,
+                proxy: Some(proxy),
+                config: self.inner.config,
+                connection: Default::default(),
+            }),
+            _p: PhantomData,
+        }
+    }
+
+    async fn connection(&self) -> io::Result<Arc<Mux>> {
+        let mut connection = self.inner.connection.lock().await;
+        if let Some(mux) = connection.as_ref().filter(|mux| !mux.is_closed()) {
+            return Ok(mux.clone());
+        }
+        trace!("connecting to {}", self.inner.addr);
+        let addr = self.inner.addr;
+        let stream = match &self.inner.proxy {
+            Some(proxy) => proxy.connect(&addr.ip().to_string(), addr.port()).await?,
+            None => TcpStream::connect(addr).await?,
+        };
+        stream.set_nodelay(true)?;
+        let mux = Arc::new(Mux::new(stream, Role::Dialer, self.inner.config));
+        *connection = Some(mux.clone());
+        Ok(mux)
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage> Clone for TcpConnector<In, Out> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage> fmt::Debug for TcpConnector<In, Out> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TcpConnector")
+            .field("addr", &self.inner.addr)
+            .field("proxy", &self.inner.proxy)
+            .finish()
+    }
+}
+
+/// A listener that accepts tcp connections, and the substreams opened on them
+pub struct TcpListener<In: RpcMessage, Out: RpcMessage> {
+    channel: flume::Receiver<Substream>,
+    local_addr: [LocalAddr; 1],
+    _task: Arc<AbortOnDropHandle<()>>,
+    _p: PhantomData<(In, Out)>,
+}
+
+impl<In: RpcMessage, Out: RpcMessage> TcpListener<In, Out> {
+    /// Bind a tcp listener to the given address and accept connections on it
+    pub async fn serve(addr: SocketAddr) -> io::Result<Self> {
+        Self::serve_with_config(addr, MuxConfig::default()).await
+    }
+
+    /// Bind a tcp listener to the given address, with a custom configuration of
+    /// the multiplexing
+    pub async fn serve_with_config(addr: SocketAddr, config: MuxConfig) -> io::Result<Self> {
+        Self::from_listener_with_config(tokio::net::TcpListener::bind(addr).await?, config)
+    }
+
+    /// Accept connections on an existing tcp listener
+    pub fn from_listener(listener: tokio::net::TcpListener) -> io::Result<Self> {
+        Self::from_listener_with_config(listener, MuxConfig::default())
+    }
+
+    /// Accept connections on an existing tcp listener, with a custom
+    /// configuration of the multiplexing
+    pub fn from_listener_with_config(
+        listener: tokio::net::TcpListener,
+        config: MuxConfig,
+    ) -> io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let (sender, channel) = flume::bounded(32);
+        let task = tokio::spawn(accept_connections(listener, config, sender));
+        Ok(Self {
+            channel,
+            local_addr: [LocalAddr::Socket(local_addr)],
+            _task: Arc::new(AbortOnDropHandle::new(task)),
+            _p: PhantomData,
+        })
+    }
+}
+
+async fn accept_connections(
+    listener: tokio::net::TcpListener,
+    config: MuxConfig,
+    sender: flume::Sender<Substream>,
+) {
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(res) => res,
+            Err(cause) => {
+                debug!("error accepting tcp connection: {cause}");
+                continue;
+            }
+        };
+        trace!("accepted tcp connection from {addr}");
+        stream.set_nodelay(true).ok();
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            let mux = Mux::new(stream, Role::Acceptor, config);
+            while let Some(substream) = mux.accept().await {
+                if sender.send_async(substream).await.is_err() {
+                    break;
+                }
+            }
+            trace!("connection from {addr} closed");
+        });
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage> Clone for TcpListener<In, Out> {
+    fn clone(&self) -> Self {
+        Self {
+            channel: self.channel.clone(),
+            local_addr: self.local_addr.clone(),
+            _task: self._task.clone(),
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage> fmt::Debug for TcpListener<In, Out> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TcpListener")
+            .field("local_addr", &self.local_addr)
+            .finish()
+    }
+}
+
+/// Open error for tcp channels
+#[derive(Debug)]
+pub enum OpenError {
+    /// Connecting failed, or the connection was closed
+    Io(io::Error),
+}
+
+impl fmt::Display for OpenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl error::Error for OpenError {}
+
+/// Accept error for tcp channels
+#[derive(Debug)]
+pub enum AcceptError {
+    /// The listener task has stopped
+    RemoteDropped,
+}
+
+impl fmt::Display for AcceptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl error::Error for AcceptError {}
+
+impl<In: RpcMessage, Out: RpcMessage> ConnectionErrors for TcpConnector<In, Out> {
+    type SendError = self::SendError;
+    type RecvError = self::RecvError;
+    type OpenError = OpenError;
+    type AcceptError = AcceptError;
+}
+
+impl<In: RpcMessage, Out: RpcMessage> StreamTypes for TcpConnector<In, Out> {
+    type In = In;
+    type Out = Out;
+    type RecvStream = self::RecvStream<In>;
+    type SendSink = self::SendSink<Out>;
+}
+
+impl<In: RpcMessage, Out: RpcMessage> Connector for TcpConnector<In, Out> {
+    async fn open(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        let mux = self.connection().await.map_err(OpenError::Io)?;
+        let (send, recv) = mux.open().await.map_err(OpenError::Io)?;
+        Ok((SendSink::new(send), RecvStream::new(recv)))
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage> ConnectionErrors for TcpListener<In, Out> {
+    type SendError = self::SendError;
+    type RecvError = self::RecvError;
+    type OpenError = AcceptError;
+    type AcceptError = AcceptError;
+}
+
+impl<In: RpcMessage, Out: RpcMessage> StreamTypes for TcpListener<In, Out> {
+    type In = In;
+    type Out = Out;
+    type RecvStream = self::RecvStream<In>;
+    type SendSink = self::SendSink<Out>;
+}
+
+impl<In: RpcMessage, Out: RpcMessage> Listener for TcpListener<In, Out> {
+    fn local_addr(&self) -> &[LocalAddr] {
+        &self.local_addr
+    }
+
+    async fn accept(&self) -> Result<(Self::SendSink, Self::RecvStream), AcceptError> {
+        let (send, recv) = self
+            .channel
+            .recv_async()
+            .await
+            .map_err(|_| AcceptError::RemoteDropped)?;
+        Ok((SendSink::new(send), RecvStream::new(recv)))
+    }
+}
@@ -0,0 +1,408 @@
+//! Conformance tests for transport implementations
+//!
+//! The checks in this module exercise a [Connector] and [Listener] pair the same
+//! way the built-in transports are tested: rpc calls, all streaming patterns,
+//! closing channels early, many concurrent requests, and large messages.
+//!
+//! They use their own small [TestService], so a transport only has to be generic
+//! over the message types, or be created for [Request] and [Response].
+//!
+//! The client and the server run in the same task, so the checks do not need to
+//! spawn, and work with any runtime.
+//!
+//! # Example
+//! ```
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> anyhow::Result<()> {
+//! use quic_rpc::transport::{flume, testkit};
+//!
+//! let (listener, connector) = flume::channel(8);
+//! testkit::run(connector, listener).await?;
+//! # Ok(())
+//! # }
+//! ```
+use futures_lite::{future, Stream, StreamExt};
+use futures_util::{future::try_join_all, stream, SinkExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    message::{
+        BidiStreaming, BidiStreamingMsg, ClientStreaming, ClientStreamingMsg, Msg, RpcMsg,
+        ServerStreaming, ServerStreamingMsg,
+    },
+    server::{RpcChannel, RpcServerError},
+    Connector, Listener, RpcClient, RpcServer, Service,
+};
+
+/// The size of the payload in [large_messages]
+pub const LARGE_MESSAGE_SIZE: usize = 1024 * 1024;
+
+/// The number of requests in flight at the same time in [concurrent_requests]
+pub const CONCURRENT_REQUESTS: usize = 32;
+
+/// The service used by the checks
+#[derive(Debug, Clone)]
+pub struct TestService;
+
+impl Service for TestService {
+    type Req = Request;
+    type Res = Response;
+}
+
+/// Send back the payload
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Echo(pub Vec<u8>);
+
+/// Response to [Echo]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct EchoResponse(pub Vec<u8>);
+
+/// Stream the numbers from 0 to n
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Count(pub u64);
+
+/// Item of the response stream of [Count]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CountResponse(pub u64);
+
+/// Sum a stream of numbers
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Sum;
+
+/// Update of [Sum]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SumUpdate(pub u64);
+
+/// Response to [Sum]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SumResponse(pub u64);
+
+/// Double every number of a stream
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Double;
+
+/// Update of [Double]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DoubleUpdate(pub u64);
+
+/// Item of the response stream of [Double]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DoubleResponse(pub u64);
+
+/// Requests of [TestService]
+#[allow(missing_docs)]
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Request {
+    Echo(Echo),
+    Count(Count),
+    Sum(Sum),
+    SumUpdate(SumUpdate),
+    Double(Double),
+    DoubleUpdate(DoubleUpdate),
+}
+
+/// Responses of [TestService]
+#[allow(missing_docs, clippy::enum_variant_names)]
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Response {
+    EchoResponse(EchoResponse),
+    CountResponse(CountResponse),
+    SumResponse(SumResponse),
+    DoubleResponse(DoubleResponse),
+}
+
+macro_rules! enum_conversions {
+    ($enum:ident: $($variant:ident),*) => {
+        $(
+            impl From<$variant> for $enum {
+                fn from(value: $variant) -> Self {
+                    Self::$variant(value)
+                }
+            }
+
+            impl TryFrom<$enum> for $variant {
+                type Error = $enum;
+
+                fn try_from(value: $enum) -> Result<Self, Self::Error> {
+                    match value {
+                        $enum::$variant(value) => Ok(value),
+                        #[allow(unreachable_patterns)]
+                        other => Err(other),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+enum_conversions!(Request: Echo, Count, Sum, SumUpdate, Double, DoubleUpdate);
+enum_conversions!(Response: EchoResponse, CountResponse, SumResponse, DoubleResponse);
+
+impl RpcMsg<TestService> for Echo {
+    type Response = EchoResponse;
+}
+
+impl Msg<TestService> for Count {
+    type Pattern = ServerStreaming;
+}
+
+impl ServerStreamingMsg<TestService> for Count {
+    type Response = CountResponse;
+}
+
+impl Msg<TestService> for Sum {
+    type Pattern = ClientStreaming;
+}
+
+impl ClientStreamingMsg<TestService> for Sum {
+    type Update = SumUpdate;
+    type Response = SumResponse;
+}
+
+impl Msg<TestService> for Double {
+    type Pattern = BidiStreaming;
+}
+
+impl BidiStreamingMsg<TestService> for Double {
+    type Update = DoubleUpdate;
+    type Response = DoubleResponse;
+}
+
+impl TestService {
+    async fn echo(self, req: Echo) -> EchoResponse {
+        EchoResponse(req.0)
+    }
+
+    fn count(self, req: Count) -> impl Stream<Item = CountResponse> {
+        stream::iter((0..req.0).map(CountResponse))
+    }
+
+    async fn sum(self, _req: Sum, updates: impl Stream<Item = SumUpdate>) -> SumResponse {
+        SumResponse(updates.fold(0, |sum, SumUpdate(n)| sum + n).await)
+    }
+
+    fn double(
+        self,
+        _req: Double,
+        updates: impl Stream<Item = DoubleUpdate>,
+    ) -> impl Stream<Item = DoubleResponse> {
+        updates.map(|DoubleUpdate(n)| DoubleResponse(n * 2))
+    }
+
+    async fn handle<L: Listener<TestService>>(
+        self,
+        req: Request,
+        chan: RpcChannel<TestService, L>,
+    ) -> Result<(), RpcServerError<L>> {
+        match req {
+            Request::Echo(msg) => chan.rpc(msg, self, Self::echo).await,
+            Request::Count(msg) => chan.server_streaming(msg, self, Self::count).await,
+            Request::Sum(msg) => chan.client_streaming(msg, self, Self::sum).await,
+            Request::Double(msg) => chan.bidi_streaming(msg, self, Self::double).await,
+            Request::SumUpdate(_) | Request::DoubleUpdate(_) => {
+                Err(RpcServerError::UnexpectedStartMessage)
+            }
+        }
+    }
+}
+
+/// Run all checks, with a server for `listener` in the same task
+pub async fn run<C, L>(connector: C, listener: L) -> anyhow::Result<()>
+where
+    C: Connector<TestService>,
+    L: Listener<TestService>,
+{
+    let client = RpcClient::<TestService, C>::new(connector);
+    let checks = async {
+        rpc(&client).await?;
+        server_streaming(&client).await?;
+        client_streaming(&client).await?;
+        bidi_streaming(&client).await?;
+        early_close(&client).await?;
+        concurrent_requests(&client).await?;
+        large_messages(&client).await?;
+        anyhow::Ok(())
+    };
+    let server = async {
+        serve(listener).await?;
+        anyhow::bail!("the listener stopped accepting channels")
+    };
+    future::race(checks, server).await
+}
+
+/// Serve [TestService] on `listener`
+///
+/// Channels are handled concurrently, without spawning. This only returns when
+/// accepting a channel fails.
+pub async fn serve<L: Listener<TestService>>(listener: L) -> anyhow::Result<()> {
+    let server = RpcServer::<TestService, L>::new(listener);
+    let accepted = stream::unfold(server, |server| async move {
+        let accepting = server.accept().await;
+        Some((accepting, server))
+    });
+    let handled = futures_util::StreamExt::buffer_unordered(
+        accepted.map(|accepting| async move {
+            let (req, chan) = accepting?.read_first().await?;
+            tracing::debug!(?req, "testkit request");
+            TestService.handle(req, chan).await
+        }),
+        usize::MAX,
+    );
+    let mut handled = std::pin::pin!(handled);
+    while let Some(res) = handled.next().await {
+        match res {
+            Ok(()) => {}
+            Err(RpcServerError::Accept(cause)) => return Err(cause.into()),
+            // the checks close some channels early on purpose
+            Err(cause) => tracing::debug!("testkit channel failed: {cause}"),
+        }
+    }
+    Ok(())
+}
+
+/// A single rpc call
+pub async fn rpc<C: Connector<TestService>>(
+    client: &RpcClient<TestService, C>,
+) -> anyhow::Result<()> {
+    let res = client.rpc(Echo(b"hello".to_vec())).await?;
+    anyhow::ensure!(res.0 == b"hello", "unexpected rpc response {res:?}");
+    Ok(())
+}
+
+/// A server streaming call that is read to the end
+pub async fn server_streaming<C: Connector<TestService>>(
+    client: &RpcClient<TestService, C>,
+) -> anyhow::Result<()> {
+    let res: Vec<_> = client
+        .server_streaming(Count(100))
+        .await?
+        .map(|item| item.map(|CountResponse(n)| n))
+        .try_collect()
+        .await?;
+    anyhow::ensure!(
+        res == (0..100).collect::<Vec<_>>(),
+        "unexpected items {res:?}"
+    );
+    Ok(())
+}
+
+/// A client streaming call, closing the updates explicitly
+pub async fn client_streaming<C: Connector<TestService>>(
+    client: &RpcClient<TestService, C>,
+) -> anyhow::Result<()> {
+    let (mut send, recv) = client.client_streaming(Sum).await?;
+    let send = async move {
+        for n in 1..=100 {
+            send.send(SumUpdate(n)).await.map_err(anyhow::Error::from)?;
+        }
+        send.close().await.map_err(anyhow::Error::from)
+    };
+    let (res, sent) = future::zip(recv, send).await;
+    sent?;
+    let res = res?;
+    anyhow::ensure!(res == SumResponse(5050), "unexpected response {res:?}");
+    Ok(())
+}
+
+/// A bidi streaming call, reading responses while sending updates
+pub async fn bidi_streaming<C: Connector<TestService>>(
+    client: &RpcClient<TestService, C>,
+) -> anyhow::Result<()> {
+    let (mut send, mut recv) = client.bidi(Double).await?;
+    for n in 1..=100 {
+        send.send(DoubleUpdate(n))
+            .await
+            .map_err(anyhow::Error::from)?;
+        let res = recv.next().await.transpose()?;
+        anyhow::ensure!(
+            matches!(res, Some(DoubleResponse(m)) if m == n * 2),
+            "unexpected item {res:?}"
+        );
+    }
+    drop(send);
+    let res = recv.next().await.transpose()?;
+    anyhow::ensure!(res.is_none(), "unexpected item {res:?} after closing");
+    Ok(())
+}
+
+/// Channels that are closed before they are done
+///
+/// Dropping the update sink ends the updates, and dropping a response stream
+/// must not keep the server busy. The connection must stay usable afterwards.
+pub async fn early_close<C: Connector<TestService>>(
+    client: &RpcClient<TestService, C>,
+) -> anyhow::Result<()> {
+    // stop reading an endless response stream
+    let mut items = client.server_streaming(Count(u64::MAX)).await?;
+    for expected in 0..3 {
+        let item = items.next().await.transpose()?;
+        anyhow::ensure!(
+            matches!(item, Some(CountResponse(n)) if n == expected),
+            "unexpected item {item:?}"
+        );
+    }
+    drop(items);
+
+    // drop the update sink instead of closing it
+    let (mut send, recv) = client.client_streaming(Sum).await?;
+    send.send(SumUpdate(1)).await.map_err(anyhow::Error::from)?;
+    send.send(SumUpdate(2)).await.map_err(anyhow::Error::from)?;
+    drop(send);
+    let res = recv.await?;
+    anyhow::ensure!(res == SumResponse(3), "unexpected response {res:?}");
+
+    // drop both sides of a bidi call without waiting for responses
+    let (mut send, recv) = client.bidi(Double).await?;
+    send.send(DoubleUpdate(1))
+        .await
+        .map_err(anyhow::Error::from)?;
+    drop(send);
+    drop(recv);
+
+    rpc(client).await
+}
+
+/// Many calls in flight at the same time on one connector
+pub async fn concurrent_requests<C: Connector<TestService>>(
+    client: &RpcClient<TestService, C>,
+) -> anyhow::Result<()> {
+    let calls = (0..CONCURRENT_REQUESTS).map(|i| async move {
+        let payload = i.to_be_bytes().to_vec();
+        let res = client.rpc(Echo(payload.clone())).await?;
+        anyhow::ensure!(res.0 == payload, "response to request {i} got mixed up");
+        let items = client
+            .server_streaming(Count(i as u64))
+            .await?
+            .try_collect::<_, _, Vec<_>>()
+            .await?;
+        anyhow::ensure!(items.len() == i, "unexpected stream length for request {i}");
+        anyhow::Ok(())
+    });
+    try_join_all(calls).await?;
+    Ok(())
+}
+
+/// Messages of [LARGE_MESSAGE_SIZE] bytes in both directions
+pub async fn large_messages<C: Connector<TestService>>(
+    client: &RpcClient<TestService, C>,
+) -> anyhow::Result<()> {
+    let payload = (0..LARGE_MESSAGE_SIZE)
+        .map(|i| (i % 251) as u8)
+        .collect::<Vec<_>>();
+    let res = client.rpc(Echo(payload.clone())).await?;
+    anyhow::ensure!(res.0 == payload, "large payload was corrupted");
+    Ok(())
+}
+
+#[cfg(test)]
+#[cfg(feature = "flume-transport")]
+mod tests {
+    use super::*;
+    use crate::transport::flume;
+
+    #[tokio::test]
+    async fn flume_passes() -> anyhow::Result<()> {
+        let (listener, connector) = flume::channel(1);
+        run(connector, listener).await
+    }
+}
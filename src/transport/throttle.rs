@@ -0,0 +1,512 @@
+//! Transport wrapper that limits the rate of substreams and messages.
+//!
+//! A [RateLimit] is a token bucket: it allows bursts of up to `burst` events,
+//! and refills at `per_second` events per second. [ThrottledConnector] and
+//! [ThrottledListener] take one token from a stream limit for every substream
+//! they open or accept, and one token from a message limit for every message
+//! that is sent or received on one of their substreams.
+//!
+//! On a listener, this protects a service from peers that open substreams or
+//! send messages faster than wanted, without a custom accept loop. The limits
+//! are shared by all substreams of the wrapped listener, and clones of a limit
+//! can be shared by several listeners.
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+    time::{Duration, Instant},
+};
+
+use futures_lite::Stream;
+use futures_sink::Sink;
+use pin_project::pin_project;
+
+use super::{ConnectionErrors, Connector, Listener, LocalAddr, StreamTypes};
+
+/// What to do when a rate limit is exhausted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ThrottlePolicy {
+    /// Wait until a token is available
+    ///
+    /// For received messages and accepted substreams, this pushes back on the
+    /// remote, since they are not read from the underlying transport meanwhile.
+    #[default]
+    Wait,
+    /// Fail with [Error::Throttled]
+    ///
+    /// A listener drops substreams that exceed the limit and accepts the next
+    /// one, since an accept error would end the accept loop of the server.
+    Reject,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// A token bucket
+#[derive(Debug)]
+pub struct RateLimit {
+    per_second: f64,
+    burst: f64,
+    policy: ThrottlePolicy,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimit {
+    /// Create a limit of `per_second` events, with bursts of up to `burst` events
+    ///
+    /// The bucket starts full.
+    ///
+    /// # Panics
+    ///
+    /// If `per_second` or `burst` is zero.
+    pub fn new(per_second: u32, burst: u32, policy: ThrottlePolicy) -> Arc<Self> {
+        assert!(per_second > 0, "rate must be positive");
+        assert!(burst > 0, "burst must be positive");
+        Arc::new(Self {
+            per_second: per_second as f64,
+            burst: burst as f64,
+            policy,
+            bucket: Mutex::new(Bucket {
+                tokens: burst as f64,
+                updated: Instant::now(),
+            }),
+        })
+    }
+
+    /// The policy when the limit is exhausted
+    pub fn policy(&self) -> ThrottlePolicy {
+        self.policy
+    }
+
+    /// Take a token, or return how long it takes until one is available
+    fn try_take(&self) -> Result<(), Duration> {
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.per_second).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.per_second,
+            ))
+        }
+    }
+
+    /// Take a token, waiting for it according to the policy
+    async fn take(&self) -> Result<(), Throttled> {
+        loop {
+            match self.try_take() {
+                Ok(()) => return Ok(()),
+                Err(_) if self.policy == ThrottlePolicy::Reject => return Err(Throttled),
+                Err(wait) => crate::rt::sleep(wait).await,
+            }
+        }
+    }
+}
+
+/// The limit was exhausted, and the policy is [ThrottlePolicy::Reject]
+struct Throttled;
+
+/// Error of a throttled connector or listener
+#[derive(Debug)]
+pub enum Error<E> {
+    /// Error from the inner connector or listener
+    Inner(E),
+    /// A rate limit with [ThrottlePolicy::Reject] was exhausted
+    Throttled,
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Inner(e) => write!(f, "{e}"),
+            Self::Throttled => write!(f, "rate limit exceeded"),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for Error<E> {}
+
+impl<E> From<Throttled> for Error<E> {
+    fn from(_: Throttled) -> Self {
+        Self::Throttled
+    }
+}
+
+type Sleep = Pin<Box<dyn Future<Output = ()> + Send + Sync>>;
+
+/// A token of a message limit, taken from within a poll function
+#[derive(Default)]
+struct Permit {
+    limit: Option<Arc<RateLimit>>,
+    sleep: Option<Sleep>,
+    taken: bool,
+}
+
+impl fmt::Debug for Permit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Permit")
+            .field("limit", &self.limit)
+            .field("taken", &self.taken)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Permit {
+    fn new(limit: Option<Arc<RateLimit>>) -> Self {
+        Self {
+            limit,
+            ..Default::default()
+        }
+    }
+
+    /// Take a token unless one is already taken for the next message
+    fn poll_take(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Throttled>> {
+        let Some(limit) = &self.limit else {
+            return Poll::Ready(Ok(()));
+        };
+        while !self.taken {
+            if let Some(sleep) = &mut self.sleep {
+                ready!(sleep.as_mut().poll(cx));
+                self.sleep = None;
+            }
+            match limit.try_take() {
+                Ok(()) => self.taken = true,
+                Err(_) if limit.policy == ThrottlePolicy::Reject => {
+                    return Poll::Ready(Err(Throttled))
+                }
+                Err(wait) => self.sleep = Some(Box::pin(crate::rt::sleep(wait))),
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// The message was sent or received, the next one needs a new token
+    fn used(&mut self) {
+        self.taken = false;
+    }
+}
+
+/// A send sink that limits the rate of sent messages
+#[pin_project]
+#[derive(Debug)]
+pub struct SendSink<S> {
+    #[pin]
+    inner: S,
+    permit: Permit,
+}
+
+impl<S: Sink<T>, T> Sink<T> for SendSink<S> {
+    type Error = Error<S::Error>;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        ready!(this.permit.poll_take(cx))?;
+        this.inner.poll_ready(cx).map_err(Error::Inner)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        let this = self.project();
+        this.permit.used();
+        this.inner.start_send(item).map_err(Error::Inner)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_flush(cx).map_err(Error::Inner)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_close(cx).map_err(Error::Inner)
+    }
+}
+
+/// A receive stream that limits the rate of received messages
+#[pin_project]
+#[derive(Debug)]
+pub struct RecvStream<S> {
+    #[pin]
+    inner: S,
+    permit: Permit,
+}
+
+impl<S: Stream<Item = Result<T, E>>, T, E> Stream for RecvStream<S> {
+    type Item = Result<T, Error<E>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let rejecting =
+            matches!(&this.permit.limit, Some(limit) if limit.policy == ThrottlePolicy::Reject);
+        if !rejecting {
+            // wait for the token before reading, to push back on the remote
+            if let Err(Throttled) = ready!(this.permit.poll_take(cx)) {
+                return Poll::Ready(Some(Err(Error::Throttled)));
+            }
+        }
+        let Some(item) = ready!(this.inner.poll_next(cx)) else {
+            return Poll::Ready(None);
+        };
+        if rejecting {
+            if let Err(Throttled) = ready!(this.permit.poll_take(cx)) {
+                return Poll::Ready(Some(Err(Error::Throttled)));
+            }
+        }
+        this.permit.used();
+        Poll::Ready(Some(item.map_err(Error::Inner)))
+    }
+}
+
+macro_rules! throttled_wrapper {
+    ($name:ident, $what:literal) => {
+        #[doc = concat!("A ", $what, " that limits the rate of substreams and messages")]
+        #[derive(Debug, Clone)]
+        pub struct $name<C> {
+            inner: C,
+            streams: Option<Arc<RateLimit>>,
+            messages: Option<Arc<RateLimit>>,
+        }
+
+        impl<C> $name<C> {
+            #[doc = concat!("Wrap a ", $what, ", without any limits yet")]
+            pub fn new(inner: C) -> Self {
+                Self {
+                    inner,
+                    streams: None,
+                    messages: None,
+                }
+            }
+
+            /// Limit the rate of substreams
+            pub fn with_stream_limit(mut self, limit: Arc<RateLimit>) -> Self {
+                self.streams = Some(limit);
+                self
+            }
+
+            /// Limit the rate of messages, sent and received, across all substreams
+            pub fn with_message_limit(mut self, limit: Arc<RateLimit>) -> Self {
+                self.messages = Some(limit);
+                self
+            }
+
+            fn wrap<S, R>(&self, (send, recv): (S, R)) -> (SendSink<S>, RecvStream<R>) {
+                let send = SendSink {
+                    inner: send,
+                    permit: Permit::new(self.messages.clone()),
+                };
+                let recv = RecvStream {
+                    inner: recv,
+                    permit: Permit::new(self.messages.clone()),
+                };
+                (send, recv)
+            }
+        }
+
+        impl<C: ConnectionErrors> ConnectionErrors for $name<C> {
+            type SendError = Error<C::SendError>;
+            type RecvError = Error<C::RecvError>;
+            type OpenError = Error<C::OpenError>;
+            type AcceptError = C::AcceptError;
+        }
+
+        impl<C: StreamTypes> StreamTypes for $name<C> {
+            type In = C::In;
+            type Out = C::Out;
+            type RecvStream = RecvStream<C::RecvStream>;
+            type SendSink = SendSink<C::SendSink>;
+        }
+    };
+}
+
+throttled_wrapper!(ThrottledConnector, "connector");
+throttled_wrapper!(ThrottledListener, "listener");
+
+impl<C: Connector> Connector for ThrottledConnector<C> {
+    async fn open(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        if let Some(limit) = &self.streams {
+            limit.take().await?;
+        }
+        let channel = self.inner.open().await.map_err(Error::Inner)?;
+        Ok(self.wrap(channel))
+    }
+
+    async fn open_with_priority(
+        &self,
+        priority: i32,
+    ) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        if let Some(limit) = &self.streams {
+            limit.take().await?;
+        }
+        let channel = self
+            .inner
+            .open_with_priority(priority)
+            .await
+            .map_err(Error::Inner)?;
+        Ok(self.wrap(channel))
+    }
+
+    async fn open_uni(&self) -> Result<Self::SendSink, Self::OpenError> {
+        if let Some(limit) = &self.streams {
+            limit.take().await?;
+        }
+        let send = self.inner.open_uni().await.map_err(Error::Inner)?;
+        Ok(SendSink {
+            inner: send,
+            permit: Permit::new(self.messages.clone()),
+        })
+    }
+
+    /// Send a datagram, taking a token from the message limit
+    async fn send_datagram(&self, msg: Self::Out) -> Result<Option<Self::Out>, Self::SendError> {
+        if let Some(limit) = &self.messages {
+            limit.take().await?;
+        }
+        self.inner.send_datagram(msg).await.map_err(Error::Inner)
+    }
+}
+
+impl<C: Listener> ThrottledListener<C> {
+    /// Take a token from `limit` around `f`, waiting for it or dropping what
+    /// `f` returned, depending on the policy
+    async fn throttled<T, E, F, Fut>(&self, limit: Option<&Arc<RateLimit>>, f: F) -> Result<T, E>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        loop {
+            if let Some(limit) = limit.filter(|l| l.policy == ThrottlePolicy::Wait) {
+                // does not fail, since the policy is to wait
+                limit.take().await.ok();
+            }
+            let res = f().await?;
+            if let Some(limit) = limit.filter(|l| l.policy == ThrottlePolicy::Reject) {
+                if limit.try_take().is_err() {
+                    tracing::debug!("dropping, rate limit exceeded");
+                    continue;
+                }
+            }
+            return Ok(res);
+        }
+    }
+}
+
+impl<C: Listener> Listener for ThrottledListener<C> {
+    async fn accept(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::AcceptError> {
+        let channel = self
+            .throttled(self.streams.as_ref(), || self.inner.accept())
+            .await?;
+        Ok(self.wrap(channel))
+    }
+
+    fn local_addr(&self) -> &[LocalAddr] {
+        self.inner.local_addr()
+    }
+
+    async fn accept_uni(&self) -> Result<Self::RecvStream, Self::AcceptError> {
+        let recv = self
+            .throttled(self.streams.as_ref(), || self.inner.accept_uni())
+            .await?;
+        Ok(RecvStream {
+            inner: recv,
+            permit: Permit::new(self.messages.clone()),
+        })
+    }
+
+    /// Receive a datagram, taking a token from the message limit
+    ///
+    /// With [ThrottlePolicy::Reject], datagrams over the limit are dropped.
+    async fn recv_datagram(&self) -> Result<Self::In, Self::RecvError> {
+        self.throttled(self.messages.as_ref(), || self.inner.recv_datagram())
+            .await
+            .map_err(Error::Inner)
+    }
+}
+
+#[cfg(all(test, feature = "flume-transport", feature = "rt-tokio"))]
+mod tests {
+    use futures_lite::StreamExt;
+    use futures_util::SinkExt;
+
+    use super::*;
+    use crate::transport::flume;
+
+    #[test]
+    fn bucket() {
+        let limit = RateLimit::new(10, 3, ThrottlePolicy::Reject);
+        for _ in 0..3 {
+            limit.try_take().unwrap();
+        }
+        let wait = limit.try_take().unwrap_err();
+        assert!(wait <= Duration::from_millis(100));
+        std::thread::sleep(wait);
+        limit.try_take().unwrap();
+    }
+
+    #[tokio::test]
+    async fn reject_streams() -> anyhow::Result<()> {
+        let (_listener, connector) = flume::channel::<u64, u64>(8);
+        let limit = RateLimit::new(1, 2, ThrottlePolicy::Reject);
+        let connector = ThrottledConnector::new(connector).with_stream_limit(limit);
+        let _a = connector.open().await?;
+        let _b = connector.open().await?;
+        assert!(matches!(connector.open().await, Err(Error::Throttled)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn listener_drops_streams() -> anyhow::Result<()> {
+        let (listener, connector) = flume::channel::<u64, u64>(8);
+        let limit = RateLimit::new(1, 1, ThrottlePolicy::Reject);
+        let listener = ThrottledListener::new(listener).with_stream_limit(limit);
+        let (_, _first_recv) = connector.open().await?;
+        let (_, mut second_recv) = connector.open().await?;
+        let _first = listener.accept().await?;
+        let accepting = tokio::spawn(async move { listener.accept().await.map(|_| ()) });
+        // the second substream is dropped while waiting for the next one
+        assert!(second_recv.next().await.is_none());
+        assert!(!accepting.is_finished());
+        accepting.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn wait_for_messages() -> anyhow::Result<()> {
+        let (listener, connector) = flume::channel::<u64, u64>(8);
+        let limit = RateLimit::new(20, 1, ThrottlePolicy::Wait);
+        let connector = ThrottledConnector::new(connector).with_message_limit(limit);
+        let (mut send, _recv) = connector.open().await?;
+        let (_server_send, mut server_recv) = listener.accept().await?;
+        let t0 = Instant::now();
+        for i in 0..3 {
+            send.send(i).await?;
+            assert_eq!(server_recv.next().await.transpose()?, Some(i));
+        }
+        // one token from the burst, then two at 20 per second
+        assert!(t0.elapsed() >= Duration::from_millis(90));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reject_received_messages() -> anyhow::Result<()> {
+        let (listener, connector) = flume::channel::<u64, u64>(8);
+        let limit = RateLimit::new(1, 1, ThrottlePolicy::Reject);
+        let listener = ThrottledListener::new(listener).with_message_limit(limit);
+        let (mut send, _recv) = connector.open().await?;
+        let (_server_send, mut server_recv) = listener.accept().await?;
+        send.send(1).await?;
+        send.send(2).await?;
+        assert_eq!(server_recv.next().await.transpose()?, Some(1));
+        assert!(matches!(
+            server_recv.next().await,
+            Some(Err(Error::Throttled))
+        ));
+        Ok(())
+    }
+}
@@ -1,42 +1,166 @@
 use std::{
+    io,
     pin::Pin,
     task::{self, Poll},
 };
 
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
 use futures_lite::Stream;
 use futures_sink::Sink;
 use pin_project::pin_project;
 use serde::{de::DeserializeOwned, Serialize};
-use tokio::io::{AsyncRead, AsyncWrite};
-use tokio_util::codec::LengthDelimitedCodec;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::oneshot,
+};
+use tokio_util::codec::{Decoder, Encoder, LengthDelimitedCodec, LengthDelimitedCodecError};
+
+use super::{stream_limit::Substream, PeerIdentity};
+use crate::codec::{Codec, Framing, MessageTooLarge};
+
+/// Default maximum length of a frame, in both directions
+pub const MAX_FRAME_LENGTH: usize = 1024 * 1024 * 16;
+
+/// Framing of the substreams of a listener or connector
+#[derive(Debug, Clone, Copy)]
+pub struct FrameConfig {
+    /// Maximum length of sent frames
+    pub send: usize,
+    /// Maximum length of received frames
+    pub recv: usize,
+    /// How frames are delimited
+    pub framing: Framing,
+}
+
+impl Default for FrameConfig {
+    fn default() -> Self {
+        Self {
+            send: MAX_FRAME_LENGTH,
+            recv: MAX_FRAME_LENGTH,
+            framing: Framing::default(),
+        }
+    }
+}
+
+/// Splits a byte stream into frames, according to a [Framing]
+#[derive(Debug)]
+pub enum FrameCodec {
+    Fixed(LengthDelimitedCodec),
+    Varint { max_frame_length: usize },
+}
+
+impl FrameCodec {
+    fn new(framing: Framing, max_frame_length: usize) -> Self {
+        match framing {
+            Framing::Fixed => Self::Fixed(
+                LengthDelimitedCodec::builder()
+                    .max_frame_length(max_frame_length)
+                    .new_codec(),
+            ),
+            Framing::Varint => Self::Varint { max_frame_length },
+        }
+    }
+}
+
+/// Maximum length of a LEB128 encoded u64
+const MAX_VARINT_LENGTH: usize = 10;
+
+impl Decoder for FrameCodec {
+    type Item = BytesMut;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<BytesMut>> {
+        let max = match self {
+            Self::Fixed(codec) => return codec.decode(src),
+            Self::Varint { max_frame_length } => *max_frame_length,
+        };
+        let mut len = 0u64;
+        for (i, byte) in src.iter().take(MAX_VARINT_LENGTH).enumerate() {
+            if i == MAX_VARINT_LENGTH - 1 && *byte > 1 {
+                // does not fit into a u64
+                return Err(MessageTooLarge { size: None, max }.into());
+            }
+            len |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 != 0 {
+                continue;
+            }
+            let size = usize::try_from(len).unwrap_or(usize::MAX);
+            if size > max {
+                return Err(MessageTooLarge {
+                    size: Some(size),
+                    max,
+                }
+                .into());
+            }
+            let header = i + 1;
+            if src.len() < header + size {
+                src.reserve(header + size - src.len());
+                return Ok(None);
+            }
+            src.advance(header);
+            return Ok(Some(src.split_to(size)));
+        }
+        if src.len() >= MAX_VARINT_LENGTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid varint length prefix",
+            ));
+        }
+        Ok(None)
+    }
+}
+
+impl Encoder<Bytes> for FrameCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, data: Bytes, dst: &mut BytesMut) -> io::Result<()> {
+        let max = match self {
+            Self::Fixed(codec) => return codec.encode(data, dst),
+            Self::Varint { max_frame_length } => *max_frame_length,
+        };
+        if data.len() > max {
+            let size = Some(data.len());
+            return Err(MessageTooLarge { size, max }.into());
+        }
+        dst.reserve(MAX_VARINT_LENGTH + data.len());
+        let mut len = data.len() as u64;
+        while len >= 0x80 {
+            dst.put_u8(len as u8 | 0x80);
+            len >>= 7;
+        }
+        dst.put_u8(len as u8);
+        dst.extend_from_slice(&data);
+        Ok(())
+    }
+}
 
 #[pin_project]
-pub struct FramedPostcardRead<T, In>(
+pub struct FramedCodecRead<T, In, C: Codec>(
     #[pin]
     tokio_serde::SymmetricallyFramed<
-        tokio_util::codec::FramedRead<T, tokio_util::codec::LengthDelimitedCodec>,
+        tokio_util::codec::FramedRead<T, FrameCodec>,
         In,
-        tokio_serde_postcard::SymmetricalPostcard<In>,
+        tokio_serde_codec::Symmetrical<C, In>,
     >,
+    usize,
 );
 
-impl<T: AsyncRead, In: DeserializeOwned> FramedPostcardRead<T, In> {
-    /// Wrap a socket in a length delimited codec and postcard encoding
-    pub fn new(inner: T, max_frame_length: usize) -> Self {
-        // configure length delimited codec with max frame length
-        let framing = LengthDelimitedCodec::builder()
-            .max_frame_length(max_frame_length)
-            .new_codec();
+impl<T: AsyncRead, In: DeserializeOwned, C: Codec> FramedCodecRead<T, In, C> {
+    /// Wrap a socket in a length prefixed framing and the encoding of `C`
+    pub fn new(inner: T, framing: Framing, max_frame_length: usize) -> Self {
+        // configure the length prefix with max frame length
+        let framing = FrameCodec::new(framing, max_frame_length);
         // create the actual framing. This turns the AsyncRead/AsyncWrite into a Stream/Sink of Bytes/BytesMut
         let framed = tokio_util::codec::FramedRead::new(inner, framing);
-        let postcard = tokio_serde_postcard::Postcard::new();
+        let codec = tokio_serde_codec::Symmetrical::new(C::default(), max_frame_length);
         // create the actual framing. This turns the Stream/Sink of Bytes/BytesMut into a Stream/Sink of In/Out
-        let framed = tokio_serde::Framed::new(framed, postcard);
-        Self(framed)
+        let framed = tokio_serde::Framed::new(framed, codec);
+        Self(framed, max_frame_length)
     }
 }
 
-impl<T, In> FramedPostcardRead<T, In> {
+impl<T, In, C: Codec> FramedCodecRead<T, In, C> {
     /// Get the underlying binary stream
     ///
     /// This can be useful if you want to drop the framing and use the underlying stream directly
@@ -44,45 +168,62 @@ impl<T, In> FramedPostcardRead<T, In> {
     pub fn into_inner(self) -> T {
         self.0.into_inner().into_inner()
     }
+
+    /// Get the underlying binary stream, and the bytes that were already read
+    /// from it but not yet decoded
+    pub fn into_parts(self) -> (T, Bytes) {
+        let mut framed = self.0.into_inner();
+        let buffer = framed.read_buffer_mut().split().freeze();
+        (framed.into_inner(), buffer)
+    }
 }
 
-impl<T: AsyncRead, In: DeserializeOwned> Stream for FramedPostcardRead<T, In> {
+impl<T: AsyncRead, In: DeserializeOwned, C: Codec> Stream for FramedCodecRead<T, In, C> {
     type Item = Result<In, std::io::Error>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Option<Self::Item>> {
-        Pin::new(&mut self.project().0).poll_next(cx)
+        let this = self.project();
+        let max = *this.1;
+        this.0.poll_next(cx).map_err(|err| {
+            let too_large = err
+                .get_ref()
+                .is_some_and(|inner| inner.is::<LengthDelimitedCodecError>());
+            if too_large {
+                MessageTooLarge { size: None, max }.into()
+            } else {
+                err
+            }
+        })
     }
 }
 
-/// Wrapper that wraps a bidirectional binary stream in a length delimited codec and postcard encoding
-/// to get a bidirectional stream of rpc Messages
+/// Wrapper that wraps a bidirectional binary stream in a length delimited codec and the
+/// encoding of `C` to get a bidirectional stream of rpc Messages
 #[pin_project]
-pub struct FramedPostcardWrite<T, Out>(
+pub struct FramedCodecWrite<T, Out, C: Codec>(
     #[pin]
     tokio_serde::SymmetricallyFramed<
-        tokio_util::codec::FramedWrite<T, tokio_util::codec::LengthDelimitedCodec>,
+        tokio_util::codec::FramedWrite<T, FrameCodec>,
         Out,
-        tokio_serde_postcard::SymmetricalPostcard<Out>,
+        tokio_serde_codec::Symmetrical<C, Out>,
     >,
 );
 
-impl<T: AsyncWrite, Out: Serialize> FramedPostcardWrite<T, Out> {
-    /// Wrap a socket in a length delimited codec and postcard encoding
-    pub fn new(inner: T, max_frame_length: usize) -> Self {
-        // configure length delimited codec with max frame length
-        let framing = LengthDelimitedCodec::builder()
-            .max_frame_length(max_frame_length)
-            .new_codec();
+impl<T: AsyncWrite, Out: Serialize, C: Codec> FramedCodecWrite<T, Out, C> {
+    /// Wrap a socket in a length prefixed framing and the encoding of `C`
+    pub fn new(inner: T, framing: Framing, max_frame_length: usize) -> Self {
+        // configure the length prefix with max frame length
+        let framing = FrameCodec::new(framing, max_frame_length);
         // create the actual framing. This turns the AsyncRead/AsyncWrite into a Stream/Sink of Bytes/BytesMut
         let framed = tokio_util::codec::FramedWrite::new(inner, framing);
-        let postcard = tokio_serde_postcard::SymmetricalPostcard::new();
+        let codec = tokio_serde_codec::Symmetrical::new(C::default(), max_frame_length);
         // create the actual framing. This turns the Stream/Sink of Bytes/BytesMut into a Stream/Sink of In/Out
-        let framed = tokio_serde::SymmetricallyFramed::new(framed, postcard);
+        let framed = tokio_serde::SymmetricallyFramed::new(framed, codec);
         Self(framed)
     }
 }
 
-impl<T, Out> FramedPostcardWrite<T, Out> {
+impl<T, Out, C: Codec> FramedCodecWrite<T, Out, C> {
     /// Get the underlying binary stream
     ///
     /// This can be useful if you want to drop the framing and use the underlying stream directly
@@ -92,7 +233,7 @@ impl<T, Out> FramedPostcardWrite<T, Out> {
     }
 }
 
-impl<T: AsyncWrite, Out: Serialize> Sink<Out> for FramedPostcardWrite<T, Out> {
+impl<T: AsyncWrite, Out: Serialize, C: Codec> Sink<Out> for FramedCodecWrite<T, Out, C> {
     type Error = std::io::Error;
 
     fn poll_ready(
@@ -121,61 +262,253 @@ impl<T: AsyncWrite, Out: Serialize> Sink<Out> for FramedPostcardWrite<T, Out> {
     }
 }
 
-mod tokio_serde_postcard {
+/// Number of received datagrams that are queued until the listener picks them up
+///
+/// Datagrams are dropped when the queue is full, like the QUIC implementation does
+/// when its receive buffer is full.
+const DATAGRAM_QUEUE_SIZE: usize = 256;
+
+/// A unidirectional substream accepted by a listener, and who opened it
+pub type AcceptedUni = (quinn::RecvStream, Option<PeerIdentity>);
+
+/// Senders for the datagrams and unidirectional substreams of the connections
+/// of a listener
+#[derive(Debug, Clone)]
+pub struct UniSenders {
+    datagrams: flume::Sender<Bytes>,
+    streams: flume::Sender<AcceptedUni>,
+}
+
+/// Receivers for what the [UniSenders] of a listener forward
+#[derive(Debug)]
+pub struct UniReceivers {
+    datagrams: flume::Receiver<Bytes>,
+    streams: flume::Receiver<AcceptedUni>,
+}
+
+/// Create the channels for the datagrams and unidirectional substreams of a listener
+pub fn uni_channels() -> (UniSenders, UniReceivers) {
+    let (datagrams, datagrams_rx) = flume::bounded(DATAGRAM_QUEUE_SIZE);
+    let (streams, streams_rx) = flume::bounded(16);
+    let senders = UniSenders { datagrams, streams };
+    let receivers = UniReceivers {
+        datagrams: datagrams_rx,
+        streams: streams_rx,
+    };
+    (senders, receivers)
+}
+
+impl UniSenders {
+    /// Forward the datagrams and unidirectional substreams of a connection,
+    /// until the connection is closed
+    pub fn spawn(&self, connection: &quinn::Connection, peer: Option<PeerIdentity>) {
+        tokio::spawn(read_datagrams(connection.clone(), self.datagrams.clone()));
+        tokio::spawn(accept_uni(connection.clone(), peer, self.streams.clone()));
+    }
+}
+
+/// Send a message as a datagram on a connection
+///
+/// Returns the message if it can not be sent as a datagram, so the caller can
+/// use a substream instead.
+pub fn send_datagram<Out: Serialize, C: Codec>(
+    connection: &quinn::Connection,
+    msg: Out,
+) -> Option<Out> {
+    let Some(max_size) = connection.max_datagram_size() else {
+        return Some(msg);
+    };
+    let mut data = BytesMut::new();
+    let Ok(()) = C::default().serialize(&msg, &mut data) else {
+        // the substream will report the error
+        return Some(msg);
+    };
+    if data.len() > max_size {
+        return Some(msg);
+    }
+    match connection.send_datagram(data.freeze()) {
+        Ok(()) => None,
+        Err(e) => {
+            tracing::debug!(
+                "Unable to send datagram, falling back to a substream: {}",
+                e
+            );
+            Some(msg)
+        }
+    }
+}
+
+async fn read_datagrams(connection: quinn::Connection, sender: flume::Sender<Bytes>) {
+    loop {
+        let datagram = match connection.read_datagram().await {
+            Ok(datagram) => datagram,
+            Err(e) => {
+                tracing::debug!("Stopped reading datagrams: {}", e);
+                break;
+            }
+        };
+        match sender.try_send(datagram) {
+            Ok(()) => {}
+            Err(flume::TrySendError::Full(_)) => tracing::debug!("Datagram queue full, dropping"),
+            Err(flume::TrySendError::Disconnected(_)) => break,
+        }
+    }
+}
+
+async fn accept_uni(
+    connection: quinn::Connection,
+    peer: Option<PeerIdentity>,
+    sender: flume::Sender<AcceptedUni>,
+) {
+    loop {
+        let recv = match connection.accept_uni().await {
+            Ok(recv) => recv,
+            Err(e) => {
+                tracing::debug!("Stopped accepting uni substreams: {}", e);
+                break;
+            }
+        };
+        // while the queue is full, the peer is limited by the uni stream limit of the connection
+        if sender.send_async((recv, peer.clone())).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Receive and decode the next queued datagram
+///
+/// Never completes if the listener does not handle connections itself.
+pub async fn recv_datagram<In: DeserializeOwned, C: Codec>(
+    receivers: Option<&UniReceivers>,
+) -> io::Result<In> {
+    let Some(receivers) = receivers else {
+        return std::future::pending().await;
+    };
+    let datagram = receivers
+        .datagrams
+        .recv_async()
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::NotConnected, "listener closed"))?;
+    C::default().deserialize(&datagram)
+}
+
+/// Receive the next queued unidirectional substream
+///
+/// Never completes if the listener does not handle connections itself.
+pub async fn recv_uni(
+    receivers: Option<&UniReceivers>,
+) -> Result<AcceptedUni, quinn::ConnectionError> {
+    let Some(receivers) = receivers else {
+        return std::future::pending().await;
+    };
+    receivers
+        .streams
+        .recv_async()
+        .await
+        .map_err(|_| quinn::ConnectionError::LocallyClosed)
+}
+
+/// A request to the connection handler of a connector to open a substream
+pub enum OpenRequest<E> {
+    /// Open a bidirectional substream
+    Bi(oneshot::Sender<Result<Substream, E>>),
+    /// Open a unidirectional substream
+    Uni(oneshot::Sender<Result<quinn::SendStream, E>>),
+}
+
+impl<E> OpenRequest<E> {
+    /// Open the substream on a connection and hand it to the requester
+    ///
+    /// On failure, the request is returned so it can be retried or failed.
+    pub async fn open(
+        self,
+        connection: &quinn::Connection,
+    ) -> Result<(), (Self, quinn::ConnectionError)> {
+        let dropped = match self {
+            Self::Bi(tx) => match connection.open_bi().await {
+                Ok(pair) => {
+                    tracing::debug!("Bidi substream opened");
+                    tx.send(Ok(pair)).is_err()
+                }
+                Err(e) => return Err((Self::Bi(tx), e)),
+            },
+            Self::Uni(tx) => match connection.open_uni().await {
+                Ok(send) => {
+                    tracing::debug!("Uni substream opened");
+                    tx.send(Ok(send)).is_err()
+                }
+                Err(e) => return Err((Self::Uni(tx), e)),
+            },
+        };
+        if dropped {
+            tracing::debug!("requester dropped");
+        }
+        Ok(())
+    }
+
+    /// Report an error to the requester
+    pub fn fail(self, e: E) {
+        let dropped = match self {
+            Self::Bi(tx) => tx.send(Err(e)).is_err(),
+            Self::Uni(tx) => tx.send(Err(e)).is_err(),
+        };
+        if dropped {
+            tracing::debug!("requester dropped");
+        }
+    }
+}
+
+mod tokio_serde_codec {
     use std::{io, marker::PhantomData, pin::Pin};
 
-    use bytes::{BufMut as _, Bytes, BytesMut};
+    use bytes::{Bytes, BytesMut};
     use pin_project::pin_project;
-    use serde::{Deserialize, Serialize};
+    use serde::{de::DeserializeOwned, Serialize};
     use tokio_serde::{Deserializer, Serializer};
 
+    use crate::codec::{Codec, MessageTooLarge};
+
+    /// Adapter from a [Codec] to the serializer traits of tokio_serde
     #[pin_project]
-    pub struct Postcard<Item, SinkItem> {
+    pub struct Symmetrical<C, T> {
+        codec: C,
+        max_frame_length: usize,
         #[pin]
         buffer: Box<Option<BytesMut>>,
-        _marker: PhantomData<(Item, SinkItem)>,
-    }
-
-    impl<Item, SinkItem> Default for Postcard<Item, SinkItem> {
-        fn default() -> Self {
-            Self::new()
-        }
+        _marker: PhantomData<T>,
     }
 
-    impl<Item, SinkItem> Postcard<Item, SinkItem> {
-        pub fn new() -> Self {
+    impl<C, T> Symmetrical<C, T> {
+        pub fn new(codec: C, max_frame_length: usize) -> Self {
             Self {
+                codec,
+                max_frame_length,
                 buffer: Box::new(None),
                 _marker: PhantomData,
             }
         }
     }
 
-    pub type SymmetricalPostcard<T> = Postcard<T, T>;
-
-    impl<Item, SinkItem> Deserializer<Item> for Postcard<Item, SinkItem>
-    where
-        for<'a> Item: Deserialize<'a>,
-    {
+    impl<C: Codec, T: DeserializeOwned> Deserializer<T> for Symmetrical<C, T> {
         type Error = io::Error;
 
-        fn deserialize(self: Pin<&mut Self>, src: &BytesMut) -> Result<Item, Self::Error> {
-            postcard::from_bytes(src).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        fn deserialize(self: Pin<&mut Self>, src: &BytesMut) -> Result<T, Self::Error> {
+            self.codec.deserialize(src)
         }
     }
 
-    impl<Item, SinkItem> Serializer<SinkItem> for Postcard<Item, SinkItem>
-    where
-        SinkItem: Serialize,
-    {
+    impl<C: Codec, T: Serialize> Serializer<T> for Symmetrical<C, T> {
         type Error = io::Error;
 
-        fn serialize(self: Pin<&mut Self>, data: &SinkItem) -> Result<Bytes, Self::Error> {
+        fn serialize(self: Pin<&mut Self>, data: &T) -> Result<Bytes, Self::Error> {
             let mut this = self.project();
-            let buffer = this.buffer.take().unwrap_or_default();
-            let mut buffer = postcard::to_io(data, buffer.writer())
-                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
-                .into_inner();
+            let mut buffer = this.buffer.take().unwrap_or_default();
+            this.codec.serialize(data, &mut buffer)?;
+            if buffer.len() > *this.max_frame_length {
+                let size = Some(buffer.len());
+                let max = *this.max_frame_length;
+                return Err(MessageTooLarge { size, max }.into());
+            }
             if buffer.len() <= 1024 {
                 let res = buffer.split().freeze();
                 this.buffer.replace(buffer);
@@ -186,3 +519,52 @@ mod tokio_serde_postcard {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varint() -> FrameCodec {
+        FrameCodec::new(Framing::Varint, 1000)
+    }
+
+    #[test]
+    fn varint_roundtrip() {
+        let mut codec = varint();
+        for len in [0, 1, 127, 128, 300, 1000] {
+            let data = Bytes::from(vec![7u8; len]);
+            let mut buf = BytesMut::new();
+            codec.encode(data.clone(), &mut buf).unwrap();
+            let prefix = if len < 128 { 1 } else { 2 };
+            assert_eq!(buf.len(), prefix + len);
+            // incomplete frames are not decoded
+            let mut partial = BytesMut::from(&buf[..buf.len() - 1]);
+            assert_eq!(codec.decode(&mut partial).unwrap(), None);
+            assert_eq!(codec.decode(&mut buf).unwrap().unwrap(), data);
+            assert!(buf.is_empty());
+        }
+    }
+
+    #[test]
+    fn varint_too_large() {
+        let mut codec = varint();
+        let mut buf = BytesMut::new();
+        let err = codec
+            .encode(Bytes::from(vec![0u8; 1001]), &mut buf)
+            .unwrap_err();
+        assert!(MessageTooLarge::downcast(&err).is_some());
+
+        // 1001 as a varint
+        let mut buf = BytesMut::from(&[0xe9, 0x07][..]);
+        let err = codec.decode(&mut buf).unwrap_err();
+        let expected = MessageTooLarge {
+            size: Some(1001),
+            max: 1000,
+        };
+        assert_eq!(MessageTooLarge::downcast(&err), Some(&expected));
+
+        // prefix longer than any u64
+        let mut buf = BytesMut::from(&[0xff; 11][..]);
+        assert!(codec.decode(&mut buf).is_err());
+    }
+}
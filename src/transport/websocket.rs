@@ -0,0 +1,469 @@
+//! WebSocket transport using [tokio-tungstenite]
+//!
+//! This lets clients that can not speak QUIC, like browsers or clients behind
+//! proxies that only allow http, talk to a quic-rpc server.
+//!
+//! WebSockets have no substreams, so every substream is a WebSocket connection
+//! of its own. Each item is encoded using postcard, like in the quinn transport,
+//! and sent as a binary message. The message boundaries of the WebSocket take the
+//! place of the length prefix. Since WebSockets can not be half closed, the end of
+//! the items in one direction is marked with an empty text message. The
+//! connection is closed once both sides have finished sending.
+//!
+//! [tokio-tungstenite]: https://crates.io/crates/tokio-tungstenite/
+use std::{
+    error, fmt, io, marker::PhantomData, net::SocketAddr, pin::Pin, result, sync::Arc, task::Poll,
+};
+
+use futures_lite::{Stream, StreamExt};
+use futures_sink::Sink;
+use futures_util::SinkExt;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+};
+use tokio_tungstenite::{
+    tungstenite::{self, client::IntoClientRequest, protocol::WebSocketConfig, Message},
+    WebSocketStream,
+};
+use tokio_util::task::AbortOnDropHandle;
+use tracing::{debug, trace};
+
+use super::proxy::Proxy;
+use crate::{
+    transport::{ConnectionErrors, Connector, Listener, LocalAddr, StreamTypes},
+    RpcMessage,
+};
+
+const MAX_MESSAGE_SIZE: usize = 1024 * 1024 * 16;
+
+fn config() -> WebSocketConfig {
+    WebSocketConfig {
+        max_message_size: Some(MAX_MESSAGE_SIZE),
+        max_frame_size: Some(MAX_MESSAGE_SIZE),
+        ..Default::default()
+    }
+}
+
+/// Forward items between the channels of a substream and a WebSocket connection
+async fn forward<S, In: RpcMessage>(
+    ws: WebSocketStream<S>,
+    out_rx: flume::Receiver<Vec<u8>>,
+    in_tx: flume::Sender<result::Result<In, RecvError>>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut sink, mut stream) = futures_util::StreamExt::split(ws);
+    let send = async {
+        while let Ok(data) = out_rx.recv_async().await {
+            sink.send(Message::Binary(data)).await?;
+        }
+        // the send sink was dropped, tell the remote that no more items follow
+        sink.send(Message::Text(String::new())).await
+    };
+    let recv = async {
+        loop {
+            let item = match stream.next().await {
+                Some(Ok(Message::Binary(data))) => {
+                    postcard::from_bytes(&data).map_err(RecvError::DeserializeError)
+                }
+                Some(Ok(Message::Text(text))) if text.is_empty() => break,
+                Some(Ok(Message::Ping(_) | Message::Pong(_))) => continue,
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(Message::Text(_) | Message::Frame(_))) => Err(RecvError::UnexpectedMessage),
+                Some(Err(cause)) => Err(RecvError::WebSocket(cause)),
+            };
+            let done = item.is_err();
+            if in_tx.send_async(item).await.is_err() {
+                trace!("receive stream dropped");
+                break;
+            }
+            if done {
+                break;
+            }
+        }
+        // end the receive stream, even if the remote is still receiving
+        drop(in_tx);
+    };
+    let (send, ()) = tokio::join!(send, recv);
+    match send {
+        Ok(()) => {
+            let mut ws = sink.reunite(stream).expect("halves of the same stream");
+            ws.close(None).await.ok();
+        }
+        Err(cause) => debug!("error sending to websocket: {cause}"),
+    }
+}
+
+/// Spawn a task that drives a WebSocket connection, and return the substream
+fn spawn_substream<S, In: RpcMessage, Out: RpcMessage>(
+    ws: WebSocketStream<S>,
+) -> (SendSink<Out>, RecvStream<In>)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (out_tx, out_rx) = flume::bounded(32);
+    let (in_tx, in_rx) = flume::bounded(32);
+    tokio::spawn(forward(ws, out_rx, in_tx));
+    (SendSink::new(out_tx), RecvStream::new(in_rx))
+}
+
+/// WebSocket based connection to a server
+pub struct WebSocketConnector<In: RpcMessage, Out: RpcMessage> {
+    url: Arc<str>,
+    proxy: Option<Proxy>,
+    _p: PhantomData<(In, Out)>,
+}
+
+impl<In: RpcMessage, Out: RpcMessage> WebSocketConnector<In, Out> {
+    /// Create a connector for a `ws://` or `wss://` url
+    ///
+    /// Every substream opens a new WebSocket connection to the url.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into().into(),
+            proxy: None,
+            _p: PhantomData,
+        }
+    }
+
+    /// Connect through the given proxy instead of directly
+    pub fn with_proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage> Clone for WebSocketConnector<In, Out> {
+    fn clone(&self) -> Self {
+        Self {
+            url: self.url.clone(),
+            proxy: self.proxy.clone(),
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage> fmt::Debug for WebSocketConnector<In, Out> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebSocketConnector")
+            .field("url", &self.url)
+            .field("proxy", &self.proxy)
+            .finish()
+    }
+}
+
+/// A listener that accepts WebSocket connections
+pub struct WebSocketListener<In: RpcMessage, Out: RpcMessage> {
+    channel: flume::Receiver<(SendSink<Out>, RecvStream<In>)>,
+    local_addr: [LocalAddr; 1],
+    _task: Arc<AbortOnDropHandle<()>>,
+}
+
+impl<In: RpcMessage, Out: RpcMessage> WebSocketListener<In, Out> {
+    /// Bind a tcp listener to the given address, and accept WebSocket connections on it
+    pub async fn serve(addr: SocketAddr) -> io::Result<Self> {
+        Self::from_tcp_listener(TcpListener::bind(addr).await?)
+    }
+
+    /// Accept WebSocket connections on an existing tcp listener
+    pub fn from_tcp_listener(listener: TcpListener) -> io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let (sender, channel) = flume::bounded(32);
+        let task = tokio::spawn(accept_connections(listener, sender));
+        Ok(Self {
+            channel,
+            local_addr: [LocalAddr::Socket(local_addr)],
+            _task: Arc::new(AbortOnDropHandle::new(task)),
+        })
+    }
+}
+
+async fn accept_connections<In: RpcMessage, Out: RpcMessage>(
+    listener: TcpListener,
+    sender: flume::Sender<(SendSink<Out>, RecvStream<In>)>,
+) {
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(res) => res,
+            Err(cause) => {
+                debug!("error accepting tcp connection: {cause}");
+                continue;
+            }
+        };
+        trace!("accepted tcp connection from {addr}");
+        stream.set_nodelay(true).ok();
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            let ws = match tokio_tungstenite::accept_async_with_config(stream, Some(config())).await
+            {
+                Ok(ws) => ws,
+                Err(cause) => {
+                    debug!("websocket handshake with {addr} failed: {cause}");
+                    return;
+                }
+            };
+            sender.send_async(spawn_substream(ws)).await.ok();
+        });
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage> Clone for WebSocketListener<In, Out> {
+    fn clone(&self) -> Self {
+        Self {
+            channel: self.channel.clone(),
+            local_addr: self.local_addr.clone(),
+            _task: self._task.clone(),
+        }
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage> fmt::Debug for WebSocketListener<In, Out> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebSocketListener")
+            .field("local_addr", &self.local_addr)
+            .finish()
+    }
+}
+
+/// Receive stream for WebSocket channels
+pub struct RecvStream<In: RpcMessage> {
+    recv: flume::r#async::RecvStream<'static, result::Result<In, RecvError>>,
+}
+
+impl<In: RpcMessage> RecvStream<In> {
+    fn new(recv: flume::Receiver<result::Result<In, RecvError>>) -> Self {
+        Self {
+            recv: recv.into_stream(),
+        }
+    }
+}
+
+impl<In: RpcMessage> fmt::Debug for RecvStream<In> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RecvStream").finish_non_exhaustive()
+    }
+}
+
+impl<In: RpcMessage> Stream for RecvStream<In> {
+    type Item = result::Result<In, RecvError>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.recv).poll_next(cx)
+    }
+}
+
+/// Send sink for WebSocket channels
+pub struct SendSink<Out: RpcMessage> {
+    sink: flume::r#async::SendSink<'static, Vec<u8>>,
+    _p: PhantomData<Out>,
+}
+
+impl<Out: RpcMessage> SendSink<Out> {
+    fn new(sender: flume::Sender<Vec<u8>>) -> Self {
+        Self {
+            sink: sender.into_sink(),
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<Out: RpcMessage> fmt::Debug for SendSink<Out> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SendSink").finish_non_exhaustive()
+    }
+}
+
+impl<Out: RpcMessage> Sink<Out> for SendSink<Out> {
+    type Error = SendError;
+
+    fn poll_ready(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.sink)
+            .poll_ready(cx)
+            .map_err(|_| SendError::ReceiverDropped)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Out) -> Result<(), Self::Error> {
+        let data = postcard::to_stdvec(&item).map_err(SendError::SerializeError)?;
+        if data.len() > MAX_MESSAGE_SIZE {
+            return Err(SendError::SizeError(data.len()));
+        }
+        Pin::new(&mut self.sink)
+            .start_send(data)
+            .map_err(|_| SendError::ReceiverDropped)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.sink)
+            .poll_flush(cx)
+            .map_err(|_| SendError::ReceiverDropped)
+    }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.sink)
+            .poll_close(cx)
+            .map_err(|_| SendError::ReceiverDropped)
+    }
+}
+
+/// Send error for WebSocket channels
+#[derive(Debug)]
+pub enum SendError {
+    /// Error when postcard serializing the message
+    SerializeError(postcard::Error),
+    /// The message is too large to be sent
+    SizeError(usize),
+    /// The connection has been closed
+    ReceiverDropped,
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl error::Error for SendError {}
+
+/// Receive error for WebSocket channels
+#[derive(Debug)]
+pub enum RecvError {
+    /// Error when postcard deserializing the message
+    DeserializeError(postcard::Error),
+    /// The remote sent a message that is not part of the protocol
+    UnexpectedMessage,
+    /// WebSocket error
+    WebSocket(tungstenite::Error),
+}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl error::Error for RecvError {}
+
+/// Open error for WebSocket channels
+#[derive(Debug)]
+pub enum OpenError {
+    /// Connecting or the WebSocket handshake failed
+    WebSocket(tungstenite::Error),
+}
+
+impl fmt::Display for OpenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl error::Error for OpenError {}
+
+/// Accept error for WebSocket channels
+#[derive(Debug)]
+pub enum AcceptError {
+    /// The listener task has stopped
+    RemoteDropped,
+}
+
+impl fmt::Display for AcceptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl error::Error for AcceptError {}
+
+impl<In: RpcMessage, Out: RpcMessage> ConnectionErrors for WebSocketConnector<In, Out> {
+    type SendError = self::SendError;
+    type RecvError = self::RecvError;
+    type OpenError = OpenError;
+    type AcceptError = AcceptError;
+}
+
+impl<In: RpcMessage, Out: RpcMessage> StreamTypes for WebSocketConnector<In, Out> {
+    type In = In;
+    type Out = Out;
+    type RecvStream = self::RecvStream<In>;
+    type SendSink = self::SendSink<Out>;
+}
+
+impl<In: RpcMessage, Out: RpcMessage> Connector for WebSocketConnector<In, Out> {
+    async fn open(&self) -> Result<(Self::SendSink, Self::RecvStream), Self::OpenError> {
+        if let Some(proxy) = &self.proxy {
+            let request = (&*self.url)
+                .into_client_request()
+                .map_err(OpenError::WebSocket)?;
+            let uri = request.uri();
+            let host = uri
+                .host()
+                .ok_or(OpenError::WebSocket(tungstenite::Error::Url(
+                    tungstenite::error::UrlError::NoHostName,
+                )))?;
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            // tls is not enabled for tokio-tungstenite, so wss does not work without a proxy either
+            if uri.scheme_str() != Some("ws") {
+                return Err(OpenError::WebSocket(tungstenite::Error::Url(
+                    tungstenite::error::UrlError::TlsFeatureNotEnabled,
+                )));
+            }
+            let port = uri.port_u16().unwrap_or(80);
+            let stream = proxy
+                .connect(host, port)
+                .await
+                .map_err(|cause| OpenError::WebSocket(tungstenite::Error::Io(cause)))?;
+            stream
+                .set_nodelay(true)
+                .map_err(|cause| OpenError::WebSocket(tungstenite::Error::Io(cause)))?;
+            let (ws, _response) =
+                tokio_tungstenite::client_async_with_config(request, stream, Some(config()))
+                    .await
+                    .map_err(OpenError::WebSocket)?;
+            return Ok(spawn_substream(ws));
+        }
+        let (ws, _response) =
+            tokio_tungstenite::connect_async_with_config(&*self.url, Some(config()), true)
+                .await
+                .map_err(OpenError::WebSocket)?;
+        Ok(spawn_substream(ws))
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage> ConnectionErrors for WebSocketListener<In, Out> {
+    type SendError = self::SendError;
+    type RecvError = self::RecvError;
+    type OpenError = AcceptError;
+    type AcceptError = AcceptError;
+}
+
+impl<In: RpcMessage, Out: RpcMessage> StreamTypes for WebSocketListener<In, Out> {
+    type In = In;
+    type Out = Out;
+    type RecvStream = self::RecvStream<In>;
+    type SendSink = self::SendSink<Out>;
+}
+
+impl<In: RpcMessage, Out: RpcMessage> Listener for WebSocketListener<In, Out> {
+    fn local_addr(&self) -> &[LocalAddr] {
+        &self.local_addr
+    }
+
+    async fn accept(&self) -> Result<(Self::SendSink, Self::RecvStream), AcceptError> {
+        self.channel
+            .recv_async()
+            .await
+            .map_err(|_| AcceptError::RemoteDropped)
+    }
+}
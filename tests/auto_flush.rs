@@ -0,0 +1,109 @@
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll, Wake, Waker},
+    time::Duration,
+};
+
+use futures_sink::Sink;
+use futures_util::SinkExt;
+use quic_rpc::client::{AutoFlush, FlushPolicy};
+
+/// A sink that counts items and flushes
+#[derive(Debug, Default)]
+struct Counting {
+    items: usize,
+    flushed: usize,
+    flushes: usize,
+}
+
+impl Sink<u64> for Counting {
+    type Error = std::convert::Infallible;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, _item: u64) -> Result<(), Self::Error> {
+        self.items += 1;
+        Ok(())
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.flushes += 1;
+        self.flushed = self.items;
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.poll_flush(cx)
+    }
+}
+
+#[tokio::test]
+async fn flush_after_n_items() {
+    let mut sink = AutoFlush::new(Counting::default(), FlushPolicy::default().max_items(4));
+    for i in 0..10 {
+        sink.send(i).await.unwrap();
+    }
+    assert_eq!(sink.unflushed(), 2);
+    sink.close().await.unwrap();
+    let inner = sink.into_inner();
+    assert_eq!(inner.items, 10);
+    assert_eq!(inner.flushed, 10);
+    // two flushes by the policy, one by close
+    assert_eq!(inner.flushes, 3);
+}
+
+#[tokio::test]
+async fn flush_after_delay() {
+    let policy = FlushPolicy::default().max_delay(Duration::from_millis(10));
+    let mut sink = AutoFlush::new(Counting::default(), policy);
+    sink.send(1).await.unwrap();
+    sink.send(2).await.unwrap();
+    assert_eq!(sink.unflushed(), 2);
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    // the overdue items are flushed before the next one is sent
+    sink.send(3).await.unwrap();
+    assert_eq!(sink.unflushed(), 1);
+    let inner = sink.into_inner();
+    assert_eq!(inner.flushed, 2);
+    assert_eq!(inner.flushes, 1);
+}
+
+/// A waker that records that it was woken
+#[derive(Debug, Default)]
+struct Flag(AtomicBool);
+
+impl Wake for Flag {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+#[tokio::test]
+async fn flush_when_idle() {
+    let policy = FlushPolicy::default().max_delay(Duration::from_millis(10));
+    let mut sink = AutoFlush::new(Counting::default(), policy);
+    sink.feed(1).await.unwrap();
+    // polling arms the timer without flushing
+    let flag = Arc::new(Flag::default());
+    let waker = Waker::from(flag.clone());
+    let mut cx = Context::from_waker(&waker);
+    assert!(Pin::new(&mut sink).poll_ready(&mut cx).is_ready());
+    assert_eq!(sink.unflushed(), 1);
+
+    // the timer wakes the idle task, and the next poll flushes
+    while !flag.0.load(Ordering::SeqCst) {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    assert!(Pin::new(&mut sink).poll_ready(&mut cx).is_ready());
+    assert_eq!(sink.unflushed(), 0);
+    assert_eq!(sink.into_inner().flushes, 1);
+}
@@ -101,3 +101,653 @@ async fn flume_channel_smoke() -> anyhow::Result<()> {
     smoke_test(client).await?;
     Ok(())
 }
+
+#[tokio::test]
+async fn flume_server_stats() -> anyhow::Result<()> {
+    use quic_rpc::transport::Connector;
+
+    let (server, client) = flume::channel(1);
+    let server = RpcServer::<ComputeService, _>::new(server);
+    let stats = server.clone();
+    let _server_handle = ComputeService::server(server);
+    let before = stats.stats();
+    let rpc = RpcClient::<ComputeService, _>::new(client.clone());
+    for i in 0..3 {
+        rpc.rpc(Sqr(i)).await?;
+    }
+    // a substream that is closed without sending a request
+    drop(client.open().await?);
+    let stats = loop {
+        let stats = stats.stats();
+        if stats.early_close == 1 && stats.active_handlers == 0 {
+            break stats;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+    };
+    assert_eq!(stats.accepted, 4);
+    assert_eq!(stats.accept_errors, 0);
+    assert_eq!(stats.read_errors, 0);
+    assert!(stats.accepted_per_second(&before) > 0.0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn flume_rpc_timeout() -> anyhow::Result<()> {
+    use std::time::Duration;
+
+    let (server, client) = flume::channel(1);
+    let server = RpcServer::<ComputeService, _>::new(server);
+    let _server_handle =
+        AbortOnDropHandle::new(tokio::spawn(ComputeService::server_unresponsive(server, 1)));
+    let client = RpcClient::<ComputeService, _>::new(client);
+    let res = client.rpc_timeout(Sqr(2), Duration::from_millis(10)).await;
+    assert!(matches!(res, Err(quic_rpc::pattern::rpc::Error::Timeout)));
+    Ok(())
+}
+
+#[tokio::test]
+async fn flume_streaming_timeout() -> anyhow::Result<()> {
+    use std::time::Duration;
+
+    use quic_rpc::pattern::{bidi_streaming, server_streaming};
+
+    let (server, client) = flume::channel(1);
+    let server = RpcServer::<ComputeService, _>::new(server);
+    let _server_handle =
+        AbortOnDropHandle::new(tokio::spawn(ComputeService::server_unresponsive(server, 2)));
+    let client = RpcClient::<ComputeService, _>::new(client);
+    // the request is sent right away, but the first response never arrives
+    let res = client
+        .server_streaming_timeout(Fibonacci(10), Duration::from_millis(10))
+        .await;
+    assert!(matches!(res, Err(server_streaming::Error::Timeout)));
+    let res = client
+        .bidi_timeout(Multiply(2), Duration::from_millis(10))
+        .await;
+    assert!(matches!(res, Err(bidi_streaming::Error::Timeout)));
+    Ok(())
+}
+
+#[tokio::test]
+async fn flume_default_timeout() -> anyhow::Result<()> {
+    use std::time::Duration;
+
+    let (server, client) = flume::channel(1);
+    let server = RpcServer::<ComputeService, _>::new(server);
+    let _server_handle = AbortOnDropHandle::new(tokio::spawn(async move {
+        // respond to the first request after a while, then hang
+        let (req, chan) = server.accept().await?.read_first().await?;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        ComputeService::handle_rpc_request(ComputeService, req, chan).await?;
+        ComputeService::server_unresponsive(server, 1).await
+    }));
+    let client = RpcClient::<ComputeService, _>::builder(client)
+        .default_timeout(Duration::from_millis(10))
+        .build();
+    // the per call timeout overrides the default
+    let SqrResponse(res) = client.rpc_timeout(Sqr(2), Duration::from_secs(10)).await?;
+    assert_eq!(res, 4);
+    let res = client.map::<ComputeService>().rpc(Sqr(3)).await;
+    assert!(matches!(res, Err(quic_rpc::pattern::rpc::Error::Timeout)));
+    Ok(())
+}
+
+#[tokio::test]
+async fn flume_rpc_retry() -> anyhow::Result<()> {
+    use std::time::Duration;
+
+    use quic_rpc::{client::RetryPolicy, message::IdempotentMsg};
+
+    impl IdempotentMsg<ComputeService> for Sqr {}
+
+    let (server, client) = flume::channel(1);
+    let server = RpcServer::<ComputeService, _>::new(server);
+    let _server_handle = AbortOnDropHandle::new(tokio::spawn(async move {
+        // close the first two substreams without a response
+        for _ in 0..2 {
+            let (_req, chan) = server.accept().await?.read_first().await?;
+            drop(chan);
+        }
+        let (req, chan) = server.accept().await?.read_first().await?;
+        ComputeService::handle_rpc_request(ComputeService, req, chan).await?;
+        anyhow::Ok(())
+    }));
+    let policy = RetryPolicy {
+        initial: Duration::from_millis(1),
+        ..Default::default()
+    };
+    let client = RpcClient::<ComputeService, _>::new(client);
+    // early close is not retried by default
+    let res = client.clone().with_retry(policy).rpc_retry(Sqr(2)).await;
+    assert!(matches!(
+        res,
+        Err(quic_rpc::pattern::rpc::Error::EarlyClose)
+    ));
+    let client = client.with_retry(RetryPolicy {
+        retry_early_close: true,
+        ..policy
+    });
+    let SqrResponse(res) = client.rpc_retry(Sqr(2)).await?;
+    assert_eq!(res, 4);
+    Ok(())
+}
+
+#[tokio::test]
+async fn flume_uni_fallback() -> anyhow::Result<()> {
+    use futures_lite::StreamExt;
+    use futures_util::SinkExt;
+    use quic_rpc::transport::{Connector, Listener};
+
+    let (listener, connector) = quic_rpc::transport::flume::channel::<u64, ()>(8);
+    let mut send = connector.open_uni().await?;
+    send.send(1).await?;
+    // without uni substreams, the channel arrives as a regular one
+    let (_, mut recv) = listener.accept().await?;
+    assert_eq!(recv.next().await.transpose()?, Some(1));
+    Ok(())
+}
+
+#[tokio::test]
+async fn flume_does_not_serialize() -> anyhow::Result<()> {
+    use quic_rpc::message::RpcMsg;
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    /// A message that fails to go through serde in any direction
+    #[derive(Debug, PartialEq, Eq)]
+    struct Opaque(u64);
+
+    impl Serialize for Opaque {
+        fn serialize<S: Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+            Err(serde::ser::Error::custom("serialized"))
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Opaque {
+        fn deserialize<D: Deserializer<'de>>(_: D) -> Result<Self, D::Error> {
+            Err(de::Error::custom("deserialized"))
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct OpaqueService;
+    impl Service for OpaqueService {
+        type Req = Opaque;
+        type Res = Opaque;
+    }
+    impl RpcMsg<OpaqueService> for Opaque {
+        type Response = Opaque;
+    }
+
+    let (server, client) = flume::channel(1);
+    let server = RpcServer::<OpaqueService, _>::new(server);
+    let _server_handle = AbortOnDropHandle::new(tokio::spawn(async move {
+        let (req, chan) = server.accept().await?.read_first().await?;
+        chan.rpc(req, (), |_, Opaque(x)| async move { Opaque(x + 1) })
+            .await?;
+        anyhow::Ok(())
+    }));
+    let client = RpcClient::<OpaqueService, _>::new(client);
+    assert_eq!(client.rpc(Opaque(1)).await?, Opaque(2));
+    Ok(())
+}
+
+#[tokio::test]
+async fn flume_server_streaming_cancel() -> anyhow::Result<()> {
+    use std::time::Duration;
+
+    use futures_lite::StreamExt;
+    use tokio_util::sync::CancellationToken;
+
+    let (server, client) = flume::channel(1);
+    let server = RpcServer::<ComputeService, _>::new(server);
+    let (token_tx, token_rx) = tokio::sync::oneshot::channel();
+    let _server_handle = AbortOnDropHandle::new(tokio::spawn(async move {
+        let (req, chan) = server.accept().await?.read_first().await?;
+        let ComputeRequest::Fibonacci(req) = req else {
+            anyhow::bail!("unexpected request");
+        };
+        let res = chan
+            .server_streaming_cancellable(req, (), move |_, _, token: CancellationToken| {
+                token_tx.send(token).ok();
+                futures_lite::stream::repeat_with(|| FibonacciResponse(0))
+            })
+            .await;
+        assert!(res.is_err());
+        anyhow::Ok(())
+    }));
+    let client = RpcClient::<ComputeService, _>::new(client);
+    let mut responses = client.server_streaming(Fibonacci(u64::MAX)).await?;
+    assert!(responses.next().await.is_some());
+    let token = token_rx.await?;
+    assert!(!token.is_cancelled());
+    drop(responses);
+    tokio::time::timeout(Duration::from_secs(1), token.cancelled()).await?;
+    Ok(())
+}
+
+/// A service that sums up uploads, acknowledging every second chunk
+mod acked {
+    use std::num::NonZeroU64;
+
+    use derive_more::{From, TryInto};
+    use futures_lite::StreamExt;
+    use quic_rpc::{
+        message::{AckedClientStreamingMsg, ClientStreaming, ClientStreamingMsg, Msg},
+        transport::flume::FlumeListener,
+        RpcServer, Service,
+    };
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Upload;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Chunk(pub u64);
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Done(pub u64);
+
+    #[derive(Debug, Serialize, Deserialize, From, TryInto)]
+    pub enum UploadRequest {
+        Upload(Upload),
+        Chunk(Chunk),
+    }
+
+    #[derive(Debug, Serialize, Deserialize, From, TryInto)]
+    pub enum UploadResponse {
+        Ack(u64),
+        Done(Done),
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct UploadService;
+
+    impl Service for UploadService {
+        type Req = UploadRequest;
+        type Res = UploadResponse;
+    }
+
+    impl Msg<UploadService> for Upload {
+        type Pattern = ClientStreaming;
+    }
+
+    impl ClientStreamingMsg<UploadService> for Upload {
+        type Update = Chunk;
+        type Response = Done;
+    }
+
+    impl AckedClientStreamingMsg<UploadService> for Upload {
+        fn ack(processed: u64) -> UploadResponse {
+            UploadResponse::Ack(processed)
+        }
+
+        fn processed(res: &UploadResponse) -> Option<u64> {
+            match res {
+                UploadResponse::Ack(processed) => Some(*processed),
+                UploadResponse::Done(_) => None,
+            }
+        }
+
+        fn ack_every() -> Option<NonZeroU64> {
+            NonZeroU64::new(2)
+        }
+    }
+
+    /// Serve a single upload, summing up the chunks
+    pub async fn serve(
+        server: RpcServer<UploadService, FlumeListener<UploadRequest, UploadResponse>>,
+    ) -> anyhow::Result<()> {
+        let (req, chan) = server.accept().await?.read_first().await?;
+        let UploadRequest::Upload(req) = req else {
+            anyhow::bail!("unexpected request");
+        };
+        chan.client_streaming_acked(req, (), |_, _, updates| async move {
+            Done(updates.fold(0, |sum, Chunk(x)| sum + x).await)
+        })
+        .await?;
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn flume_client_streaming_acked() -> anyhow::Result<()> {
+    use acked::*;
+    use futures_util::SinkExt;
+
+    let (server, client) = flume::channel(1);
+    let server = RpcServer::<UploadService, _>::new(server);
+    let _server_handle = AbortOnDropHandle::new(tokio::spawn(acked::serve(server)));
+    let client = RpcClient::<UploadService, _>::new(client);
+    let (mut sink, res) = client.client_streaming_acked(Upload).await?;
+    assert_eq!(sink.acked(), Some(0));
+    for i in 1..=3 {
+        sink.send(Chunk(i)).await?;
+    }
+    // the server asked for the third chunk, so the first two are processed
+    assert_eq!(sink.wait_acked(2).await, Some(2));
+    assert_eq!(sink.unacked(), Some(1));
+    drop(sink);
+    let Done(sum) = res.await?;
+    assert_eq!(sum, 6);
+    Ok(())
+}
+
+#[tokio::test]
+async fn flume_rpc_batch() -> anyhow::Result<()> {
+    let (server, client) = flume::channel(1);
+    let server = RpcServer::<ComputeService, _>::new(server);
+    let _server_handle = AbortOnDropHandle::new(tokio::spawn(ComputeService::server(server)));
+    let client = RpcClient::<ComputeService, _>::new(client);
+    let results = client.rpc_batch((0..100).map(Sqr).collect(), 32).await;
+    for (i, res) in results.into_iter().enumerate() {
+        let SqrResponse(res) = res?;
+        assert_eq!(res, (i * i) as u128);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn flume_concurrency_limit() -> anyhow::Result<()> {
+    let (server, client) = flume::channel(1);
+    let server = RpcServer::<ComputeService, _>::new(server);
+    let _server_handle = AbortOnDropHandle::new(tokio::spawn(ComputeService::server(server)));
+    let client = RpcClient::<ComputeService, _>::new(client).with_concurrency_limit(4, None);
+    // more calls than the limit are queued, not failed
+    let results = client.rpc_batch((0..64).map(Sqr).collect(), 32).await;
+    for (i, res) in results.into_iter().enumerate() {
+        let SqrResponse(res) = res?;
+        assert_eq!(res, (i * i) as u128);
+    }
+    assert_eq!(client.queued(), 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn flume_concurrency_limit_overloaded() -> anyhow::Result<()> {
+    use quic_rpc::{
+        pattern::rpc::Error,
+        transport::limit::{OpenError, Overloaded},
+    };
+
+    let (server, client) = flume::channel(1);
+    let server = RpcServer::<ComputeService, _>::new(server);
+    let _server_handle =
+        AbortOnDropHandle::new(tokio::spawn(ComputeService::server_unresponsive(server, 1)));
+    let client = RpcClient::<ComputeService, _>::new(client).with_concurrency_limit(1, Some(0));
+    let _pending = AbortOnDropHandle::new(tokio::spawn({
+        let client = client.clone();
+        async move { client.rpc(Sqr(2)).await }
+    }));
+    while client.as_ref().available() > 0 {
+        tokio::task::yield_now().await;
+    }
+    // the limit is reached and no call may wait
+    let res = client.rpc(Sqr(3)).await;
+    assert!(matches!(
+        res,
+        Err(Error::Open(OpenError::Overloaded(Overloaded)))
+    ));
+    Ok(())
+}
+
+/// A service that counts up, for credited server streaming
+mod count {
+    use derive_more::{From, TryInto};
+    use futures_lite::StreamExt;
+    use quic_rpc::{
+        message::{CreditedServerStreamingMsg, Msg, ServerStreaming, ServerStreamingMsg},
+        transport::flume::FlumeListener,
+        RpcServer, Service,
+    };
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Count;
+
+    #[derive(Debug, Serialize, Deserialize, From, TryInto)]
+    pub enum CountRequest {
+        Count(Count),
+        Credit(u64),
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct CountResponse(pub u64);
+
+    #[derive(Debug, Clone)]
+    pub struct CountService;
+
+    impl Service for CountService {
+        type Req = CountRequest;
+        type Res = CountResponse;
+    }
+
+    impl Msg<CountService> for Count {
+        type Pattern = ServerStreaming;
+    }
+
+    impl ServerStreamingMsg<CountService> for Count {
+        type Response = CountResponse;
+    }
+
+    impl CreditedServerStreamingMsg<CountService> for Count {
+        fn credit(n: u64) -> CountRequest {
+            CountRequest::Credit(n)
+        }
+
+        fn credits(req: &CountRequest) -> Option<u64> {
+            match req {
+                CountRequest::Credit(n) => Some(*n),
+                CountRequest::Count(_) => None,
+            }
+        }
+    }
+
+    /// Serve a single call, counting up to `len`, or forever
+    pub async fn serve(
+        server: RpcServer<CountService, FlumeListener<CountRequest, CountResponse>>,
+        len: Option<u64>,
+    ) -> anyhow::Result<()> {
+        let (req, chan) = server.accept().await?.read_first().await?;
+        let CountRequest::Count(req) = req else {
+            anyhow::bail!("unexpected request");
+        };
+        chan.server_streaming_credited(req, (), move |_, _| {
+            futures_lite::stream::iter(0..len.unwrap_or(u64::MAX)).map(CountResponse)
+        })
+        .await
+        .ok();
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn flume_server_streaming_credited() -> anyhow::Result<()> {
+    use std::time::Duration;
+
+    use count::*;
+    use futures_lite::StreamExt;
+
+    let (server, client) = flume::channel(1);
+    let server = RpcServer::<CountService, _>::new(server);
+    let _server_handle = AbortOnDropHandle::new(tokio::spawn(count::serve(server, None)));
+    let client = RpcClient::<CountService, _>::new(client);
+    let mut responses = client.server_streaming_credited(Count).await?;
+    responses.request(2).await?;
+    for i in 0..2 {
+        let CountResponse(x) = responses.next().await.transpose()?.unwrap();
+        assert_eq!(x, i);
+    }
+    // nothing is sent without credits
+    let next = tokio::time::timeout(Duration::from_millis(50), responses.next()).await;
+    assert!(next.is_err());
+    responses.request(1).await?;
+    let CountResponse(x) = responses.next().await.transpose()?.unwrap();
+    assert_eq!(x, 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn flume_server_streaming_credited_end() -> anyhow::Result<()> {
+    use std::time::Duration;
+
+    use count::*;
+    use futures_lite::StreamExt;
+
+    let (server, client) = flume::channel(1);
+    let server = RpcServer::<CountService, _>::new(server);
+    let _server_handle = AbortOnDropHandle::new(tokio::spawn(count::serve(server, Some(3))));
+    let client = RpcClient::<CountService, _>::new(client);
+    let mut responses = client.server_streaming_credited(Count).await?;
+    // exactly as many credits as there are responses
+    responses.request(3).await?;
+    let responses = tokio::time::timeout(Duration::from_secs(1), responses.collect::<Vec<_>>())
+        .await
+        .expect("stream ends after the last response");
+    assert_eq!(responses.len(), 3);
+    Ok(())
+}
+
+#[tokio::test]
+async fn flume_client_streaming_rejected() -> anyhow::Result<()> {
+    use futures_util::SinkExt;
+    use quic_rpc::client::UpdateError;
+
+    let (server, client) = flume::channel(1);
+    let server = RpcServer::<ComputeService, _>::new(server);
+    let _server_handle =
+        AbortOnDropHandle::new(tokio::spawn(ComputeService::server_sum_first(server)));
+    let client = RpcClient::<ComputeService, _>::new(client);
+    let (mut send, recv) = client.client_streaming(Sum).await?;
+    send.send(SumUpdate(7)).await?;
+    assert_eq!(recv.await?, SumResponse(7));
+    let res = send.send(SumUpdate(8)).await;
+    assert!(matches!(res, Err(UpdateError::Rejected)));
+    Ok(())
+}
+
+#[tokio::test]
+async fn flume_client_streaming_rejected_before_await() -> anyhow::Result<()> {
+    use futures_util::SinkExt;
+    use quic_rpc::client::UpdateError;
+
+    let (server, client) = flume::channel(1);
+    let server = RpcServer::<ComputeService, _>::new(server);
+    let _server_handle =
+        AbortOnDropHandle::new(tokio::spawn(ComputeService::server_sum_first(server)));
+    let client = RpcClient::<ComputeService, _>::new(client);
+    let (mut send, recv) = client.client_streaming(Sum).await?;
+    // upload everything before looking at the response
+    let mut res = Ok(());
+    for i in 7..10_000 {
+        res = send.send(SumUpdate(i)).await;
+        if res.is_err() {
+            break;
+        }
+    }
+    assert!(matches!(res, Err(UpdateError::Rejected)));
+    assert_eq!(recv.await?, SumResponse(7));
+    Ok(())
+}
+
+/// A service whose uploads end with an explicit marker
+mod upload {
+    use derive_more::{From, TryInto};
+    use futures_lite::StreamExt;
+    use quic_rpc::{
+        message::{ClientStreaming, ClientStreamingMsg, Msg},
+        server::RpcServerError,
+        transport::flume::FlumeListener,
+        RpcServer, Service,
+    };
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Upload;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Chunk(pub u64);
+
+    #[derive(Debug, Serialize, Deserialize, From, TryInto)]
+    pub enum UploadRequest {
+        Upload(Upload),
+        Chunk(Chunk),
+        Done(()),
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+    pub struct UploadResponse(pub u64);
+
+    #[derive(Debug, Clone)]
+    pub struct UploadService;
+
+    impl Service for UploadService {
+        type Req = UploadRequest;
+        type Res = UploadResponse;
+    }
+
+    impl Msg<UploadService> for Upload {
+        type Pattern = ClientStreaming;
+
+        fn end_of_updates() -> Option<UploadRequest> {
+            Some(UploadRequest::Done(()))
+        }
+
+        fn is_end_of_updates(update: &UploadRequest) -> bool {
+            matches!(update, UploadRequest::Done(()))
+        }
+    }
+
+    impl ClientStreamingMsg<UploadService> for Upload {
+        type Update = Chunk;
+        type Response = UploadResponse;
+    }
+
+    type Listener = FlumeListener<UploadRequest, UploadResponse>;
+
+    /// Serve a single upload, summing up the chunks
+    pub async fn serve(
+        server: RpcServer<UploadService, Listener>,
+    ) -> Result<(), RpcServerError<Listener>> {
+        let (req, chan) = server.accept().await?.read_first().await?;
+        let UploadRequest::Upload(req) = req else {
+            return Err(RpcServerError::UnexpectedStartMessage);
+        };
+        chan.client_streaming(req, (), |_, _, updates| async move {
+            UploadResponse(updates.fold(0, |sum, Chunk(n)| sum + n).await)
+        })
+        .await
+    }
+}
+
+#[tokio::test]
+async fn flume_client_streaming_finish() -> anyhow::Result<()> {
+    use futures_util::SinkExt;
+    use upload::*;
+
+    let (server, client) = flume::channel(1);
+    let server = tokio::spawn(upload::serve(RpcServer::new(server)));
+    let client = RpcClient::<UploadService, _>::new(client);
+    let (mut send, recv) = client.client_streaming(Upload).await?;
+    send.send(Chunk(1)).await?;
+    send.send(Chunk(2)).await?;
+    send.finish().await?;
+    assert_eq!(recv.await?, UploadResponse(3));
+    server.await??;
+    Ok(())
+}
+
+#[tokio::test]
+async fn flume_client_streaming_dropped() -> anyhow::Result<()> {
+    use futures_util::SinkExt;
+    use upload::*;
+
+    let (server, client) = flume::channel(1);
+    let server = tokio::spawn(upload::serve(RpcServer::new(server)));
+    let client = RpcClient::<UploadService, _>::new(client);
+    let (mut send, _recv) = client.client_streaming(Upload).await?;
+    send.send(Chunk(1)).await?;
+    // looks like a crash, not like the end of the upload
+    drop(send);
+    assert!(matches!(server.await?, Err(RpcServerError::UpdatesAborted)));
+    Ok(())
+}
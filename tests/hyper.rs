@@ -5,6 +5,8 @@ use std::{assert, net::SocketAddr, result};
 use ::hyper::Uri;
 use derive_more::{From, TryInto};
 use flume::Receiver;
+use futures_lite::StreamExt;
+use futures_util::SinkExt;
 use quic_rpc::{
     declare_rpc,
     server::RpcServerError,
@@ -295,3 +297,231 @@ async fn hyper_channel_errors() -> anyhow::Result<()> {
     server_handle.abort();
     Ok(())
 }
+
+#[tokio::test]
+async fn hyper_channel_long_streams() -> anyhow::Result<()> {
+    let addr: SocketAddr = "127.0.0.1:3003".parse()?;
+    let uri: Uri = "http://127.0.0.1:3003".parse()?;
+    let _server_handle = run_server(&addr);
+    let client = RpcClient::new(HyperConnector::new(uri));
+    let n = 100_000u64;
+
+    // client streaming, the frames are forwarded while the request body is still open
+    let (mut send, recv) = client.client_streaming(Sum).await?;
+    let sender = tokio::spawn(async move {
+        for i in 0..n {
+            send.send(SumUpdate(i)).await?;
+        }
+        anyhow::Ok(())
+    });
+    assert_eq!(recv.await?, SumResponse((n * (n - 1) / 2) as u128));
+    sender.await??;
+
+    // bidi streaming, responses arrive while updates are still being sent
+    let (mut send, mut recv) = client.bidi(Multiply(2)).await?;
+    let sender = tokio::spawn(async move {
+        for i in 0..n {
+            send.send(MultiplyUpdate(i)).await?;
+        }
+        anyhow::Ok(())
+    });
+    let mut count = 0;
+    while let Some(res) = recv.next().await {
+        assert_eq!(res?.0, count as u128 * 2);
+        count += 1;
+    }
+    assert_eq!(count, n);
+    sender.await??;
+    Ok(())
+}
+
+#[tokio::test]
+async fn hyper_channel_max_payload_size() -> anyhow::Result<()> {
+    let addr: SocketAddr = "127.0.0.1:3004".parse()?;
+    let uri: Uri = "http://127.0.0.1:3004".parse()?;
+    let config = hyper::ChannelConfig::default().max_payload_size(1024 * 64)?;
+    let channel = HyperListener::serve_with_config(&addr, config)?;
+    let server = RpcServer::<TestService, _>::new(channel);
+    let handle = tokio::spawn(async move {
+        let res = server.accept().await?.read_first().await;
+        anyhow::Ok(res.map(|_| ()))
+    });
+    let client = RpcClient::<TestService, _>::new(HyperConnector::new(uri));
+    // the client allows this size, but the server must not buffer it
+    let res = client.rpc(BigRequest(vec![0; 1024 * 1024])).await;
+    assert!(res.is_err());
+    let res = handle.await??;
+    assert!(
+        matches!(res, Err(RpcServerError::RecvError(RecvError::SizeError(_)))),
+        "unexpected server result {res:?}"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn hyper_channel_header_read_timeout() -> anyhow::Result<()> {
+    use std::time::{Duration, Instant};
+
+    use tokio::io::AsyncReadExt;
+
+    let addr: SocketAddr = "127.0.0.1:3005".parse()?;
+    let uri: Uri = "http://127.0.0.1:3005".parse()?;
+    let channel = HyperListener::builder()
+        .header_read_timeout(Some(Duration::from_millis(200)))
+        .max_connections(Some(4))
+        .serve(&addr)?;
+    let _server_handle = ComputeService::server(RpcServer::new(channel));
+
+    // a connection that never sends anything is closed by the server
+    let start = Instant::now();
+    let mut socket = tokio::net::TcpStream::connect(addr).await?;
+    let mut buf = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), socket.read_to_end(&mut buf)).await??;
+    assert!(start.elapsed() >= Duration::from_millis(200));
+
+    // a well behaved client is not affected, even after the timeout elapsed
+    let client = RpcClient::<ComputeService, _>::new(HyperConnector::new(uri));
+    assert_eq!(client.rpc(Sqr(3)).await?, SqrResponse(9));
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(client.rpc(Sqr(4)).await?, SqrResponse(16));
+    Ok(())
+}
+
+#[tokio::test]
+async fn hyper_channel_open_does_not_wait_for_response() -> anyhow::Result<()> {
+    use quic_rpc::transport::Connector;
+
+    // nothing listens here, so the request fails
+    let uri: Uri = "http://127.0.0.1:3006".parse()?;
+    let connector = HyperConnector::<ComputeResponse, ComputeRequest>::new(uri);
+    let (_send, mut recv) = connector.open().await?;
+    let res = recv.next().await;
+    assert!(
+        matches!(res, Some(Err(RecvError::NetworkError(_)))),
+        "unexpected result {res:?}"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn hyper_channel_bearer_token() -> anyhow::Result<()> {
+    use ::hyper::{header, StatusCode};
+    use quic_rpc::pattern::rpc::Error;
+
+    let addr: SocketAddr = "127.0.0.1:3007".parse()?;
+    let uri: Uri = "http://127.0.0.1:3007".parse()?;
+    let channel = HyperListener::builder()
+        .authorize(|headers| match headers.get(header::AUTHORIZATION) {
+            Some(value) if value == "Bearer secret" => Ok(()),
+            _ => Err(StatusCode::UNAUTHORIZED),
+        })
+        .serve(&addr)?;
+    let _server_handle = ComputeService::server(RpcServer::new(channel));
+
+    let connector = HyperConnector::new(uri.clone()).with_bearer_token("secret")?;
+    let client = RpcClient::<ComputeService, _>::new(connector);
+    assert_eq!(client.rpc(Sqr(3)).await?, SqrResponse(9));
+
+    // the same header, added for each request by a function
+    let connector = HyperConnector::new(uri.clone()).with_header_fn(|headers| {
+        headers.insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+    });
+    let client = RpcClient::<ComputeService, _>::new(connector);
+    assert_eq!(client.rpc(Sqr(4)).await?, SqrResponse(16));
+
+    let connector = HyperConnector::new(uri).with_bearer_token("wrong")?;
+    let client = RpcClient::<ComputeService, _>::new(connector);
+    let res = client.rpc(Sqr(3)).await;
+    assert!(
+        matches!(
+            res,
+            Err(Error::RecvError(RecvError::Rejected(
+                StatusCode::UNAUTHORIZED
+            )))
+        ),
+        "unexpected result {res:?}"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn hyper_channel_shared_server() -> anyhow::Result<()> {
+    use std::convert::Infallible;
+
+    use ::hyper::{
+        service::{make_service_fn, service_fn},
+        Body, Response, StatusCode,
+    };
+    use quic_rpc::pattern::rpc::Error;
+
+    let (calc, calc_handler) = HyperListener::builder().path("/rpc/calc").handler();
+    let (calc2, calc2_handler) = HyperListener::builder().path("/rpc/calc2").handler();
+    let _calc = ComputeService::server(RpcServer::new(calc));
+    let _calc2 = ComputeService::server(RpcServer::new(calc2));
+
+    // one server with two rpc services and a normal route
+    let addr: SocketAddr = "127.0.0.1:3008".parse()?;
+    let make_service = make_service_fn(move |_| {
+        let calc_handler = calc_handler.clone();
+        let calc2_handler = calc2_handler.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let calc_handler = calc_handler.clone();
+                let calc2_handler = calc2_handler.clone();
+                async move {
+                    let res = match req.uri().path() {
+                        "/rpc/calc" => calc_handler.handle(req).await,
+                        "/rpc/calc2" => calc2_handler.handle(req).await,
+                        "/health" => Response::new(Body::from("ok")),
+                        _ => {
+                            let mut res = Response::new(Body::empty());
+                            *res.status_mut() = StatusCode::NOT_FOUND;
+                            res
+                        }
+                    };
+                    Ok::<_, Infallible>(res)
+                }
+            }))
+        }
+    });
+    let server = ::hyper::Server::bind(&addr)
+        .http2_only(true)
+        .serve(make_service);
+    let _server = AbortOnDropHandle::new(tokio::spawn(server));
+
+    for path in ["/rpc/calc", "/rpc/calc2"] {
+        let uri: Uri = format!("http://127.0.0.1:3008{path}").parse()?;
+        let client = RpcClient::<ComputeService, _>::new(HyperConnector::new(uri));
+        assert_eq!(client.rpc(Sqr(3)).await?, SqrResponse(9));
+    }
+
+    let uri: Uri = "http://127.0.0.1:3008/rpc/unknown".parse()?;
+    let client = RpcClient::<ComputeService, _>::new(HyperConnector::new(uri));
+    let res = client.rpc(Sqr(3)).await;
+    assert!(
+        matches!(
+            res,
+            Err(Error::RecvError(RecvError::Rejected(StatusCode::NOT_FOUND)))
+        ),
+        "unexpected result {res:?}"
+    );
+
+    // a listener with its own server only accepts its own path
+    let addr: SocketAddr = "127.0.0.1:3009".parse()?;
+    let channel = HyperListener::builder().path("/rpc/calc").serve(&addr)?;
+    let _server_handle = ComputeService::server(RpcServer::new(channel));
+    let uri: Uri = "http://127.0.0.1:3009/rpc/calc".parse()?;
+    let client = RpcClient::<ComputeService, _>::new(HyperConnector::new(uri));
+    assert_eq!(client.rpc(Sqr(4)).await?, SqrResponse(16));
+    let uri: Uri = "http://127.0.0.1:3009/".parse()?;
+    let client = RpcClient::<ComputeService, _>::new(HyperConnector::new(uri));
+    let res = client.rpc(Sqr(4)).await;
+    assert!(
+        matches!(
+            res,
+            Err(Error::RecvError(RecvError::Rejected(StatusCode::NOT_FOUND)))
+        ),
+        "unexpected result {res:?}"
+    );
+    Ok(())
+}
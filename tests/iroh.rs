@@ -155,3 +155,203 @@ async fn server_away_and_back() -> TestResult<()> {
     server_handle.abort();
     Ok(())
 }
+
+/// Test that the server can call a service on the client, over the connection
+/// that the client opened.
+#[tokio::test]
+async fn server_opened_streams() -> TestResult<()> {
+    tracing_subscriber::fmt::try_init().ok();
+    let Endpoints {
+        client,
+        server,
+        server_node_addr,
+    } = Endpoints::new().await?;
+    let server_addr = server.bound_sockets().0;
+
+    // the client connects, and serves the compute service on the same connection
+    let (connector, listener) =
+        IrohConnector::new_with_listener(client, server_node_addr, ALPN.into());
+    let _client_server = ComputeService::server(RpcServer::new(listener));
+    let client = RpcClient::<ComputeService, _>::new(connector);
+
+    // the server manages the connection itself, so it can open substreams on it
+    let (connections, incoming) = flume::bounded(1);
+    let _server = ComputeService::server(RpcServer::new(IrohListener::handle_connections(
+        incoming,
+        server_addr,
+    )));
+    let open = tokio::spawn(async move {
+        let connection = server.accept().await.unwrap().await?;
+        connections.send_async(connection.clone()).await?;
+        anyhow::Ok((server, connection))
+    });
+
+    // the client calls the server
+    let SqrResponse(response) = client.rpc(Sqr(4)).await?;
+    assert_eq!(response, 16);
+
+    // the server calls the client, using the same connection
+    let (_server_endpoint, connection) = open.await??;
+    let callback = RpcClient::<ComputeService, _>::new(IrohConnector::from_connection(connection));
+    let SqrResponse(response) = callback.rpc(Sqr(3)).await?;
+    assert_eq!(response, 9);
+    Ok(())
+}
+
+#[tokio::test]
+async fn iroh_prewarm() -> TestResult<()> {
+    tracing_subscriber::fmt::try_init().ok();
+    let Endpoints {
+        client,
+        server,
+        server_node_addr,
+    } = Endpoints::new().await?;
+    let _server_handle = run_server(server);
+
+    // the connector only knows the node id, the addresses are provided by prewarm
+    let connector = IrohConnector::<ComputeResponse, ComputeRequest>::new(
+        client,
+        server_node_addr.node_id,
+        ALPN.into(),
+    );
+    let other = SecretKey::generate().public();
+    assert!(connector.prewarm(other).await.is_err());
+    connector.prewarm(server_node_addr.clone()).await?;
+    // already connected
+    connector.prewarm(server_node_addr.node_id).await?;
+
+    let client = RpcClient::<ComputeService, _>::new(connector);
+    let SqrResponse(response) = client.rpc(Sqr(4)).await?;
+    assert_eq!(response, 16);
+    Ok(())
+}
+
+#[tokio::test]
+async fn peer_node_id() -> TestResult<()> {
+    use quic_rpc::transport::{Listener, PeerIdentity};
+
+    tracing_subscriber::fmt::try_init().ok();
+    let Endpoints {
+        client,
+        server,
+        server_node_addr,
+    } = Endpoints::new().await?;
+    let client_node_id = client.node_id();
+    let server = RpcServer::<ComputeService>::new(IrohListener::new(server)?.boxed());
+    let connector = IrohConnector::<ComputeResponse, ComputeRequest>::new(
+        client,
+        server_node_addr,
+        ALPN.into(),
+    );
+    let client = RpcClient::<ComputeService, _>::new(connector);
+    let request = tokio::spawn(async move { client.rpc(Sqr(4)).await });
+    let (_, chan) = server.accept().await?.read_first().await?;
+    let Some(PeerIdentity::Node { node_id, alpn }) = chan.peer_identity() else {
+        panic!("no node id");
+    };
+    assert_eq!(*node_id, client_node_id);
+    assert_eq!(alpn, ALPN);
+    drop(chan);
+    request.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn multiple_alpns() -> TestResult<()> {
+    use quic_rpc::transport::{iroh::AccessControl, PeerIdentity};
+
+    tracing_subscriber::fmt::try_init().ok();
+    const V1: &[u8] = b"quic-rpc/iroh/test/v1";
+    const V2: &[u8] = b"quic-rpc/iroh/test/v2";
+    let server = make_endpoint(SecretKey::generate(), ALPN).await?;
+    let server_node_addr = server.node_addr().await?;
+    let listener = IrohListener::<ComputeRequest, ComputeResponse>::new_with_alpns(
+        server,
+        vec![V1.to_vec(), V2.to_vec()],
+        AccessControl::Unrestricted,
+    )?;
+    let server = RpcServer::<ComputeService, _>::new(listener);
+    for alpn in [V1, V2] {
+        let client = make_endpoint(SecretKey::generate(), ALPN).await?;
+        let connector = IrohConnector::<ComputeResponse, ComputeRequest>::new(
+            client,
+            server_node_addr.clone(),
+            alpn.to_vec(),
+        );
+        let client = RpcClient::<ComputeService, _>::new(connector);
+        let request = tokio::spawn(async move { client.rpc(Sqr(4)).await });
+        let (_, chan) = server.accept().await?.read_first().await?;
+        assert_eq!(chan.recv.alpn(), Some(alpn));
+        assert!(matches!(
+            chan.peer_identity(),
+            Some(PeerIdentity::Node { alpn: negotiated, .. }) if negotiated == alpn
+        ));
+        request.abort();
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn router_protocol_handler() -> TestResult<()> {
+    use std::sync::Arc;
+
+    use iroh::protocol::Router;
+
+    tracing_subscriber::fmt::try_init().ok();
+    let server = iroh::Endpoint::builder()
+        .secret_key(SecretKey::generate())
+        .bind()
+        .await?;
+    let server_node_addr = server.node_addr().await?;
+    let (listener, handler) =
+        IrohListener::<ComputeRequest, ComputeResponse>::protocol_handler(&server);
+    let _server_handle = ComputeService::server(RpcServer::new(listener));
+    let router = Router::builder(server)
+        .accept(ALPN, Arc::new(handler))
+        .spawn()
+        .await?;
+
+    let client = make_endpoint(SecretKey::generate(), ALPN).await?;
+    let connector = IrohConnector::new(client, server_node_addr, ALPN.to_vec());
+    let client = RpcClient::<ComputeService, _>::new(connector);
+    assert_eq!(client.rpc(Sqr(4)).await?, SqrResponse(16));
+    router.shutdown().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn connection_events() -> TestResult<()> {
+    use std::time::Duration;
+
+    use futures_lite::StreamExt;
+    use quic_rpc::transport::{ConnectionEvent, ConnectionEvents};
+
+    tracing_subscriber::fmt::try_init().ok();
+    let Endpoints {
+        client,
+        server,
+        server_node_addr,
+    } = Endpoints::new().await?;
+    let server_handle = run_server(server);
+    let connector = IrohConnector::<ComputeResponse, ComputeRequest>::new(
+        client,
+        server_node_addr,
+        ALPN.into(),
+    );
+    let mut events = connector.connection_event_stream();
+    let client = RpcClient::<ComputeService, _>::new(connector.clone());
+    let SqrResponse(response) = client.rpc(Sqr(4)).await?;
+    assert_eq!(response, 16);
+    assert!(matches!(
+        *connector.connection_events().borrow(),
+        ConnectionEvent::Connected | ConnectionEvent::Relayed
+    ));
+
+    // stopping the server closes the connection
+    drop(server_handle);
+    let disconnected = events.find(|e| matches!(e, ConnectionEvent::Disconnected(_)));
+    tokio::time::timeout(Duration::from_secs(10), disconnected)
+        .await?
+        .expect("disconnected event");
+    Ok(())
+}
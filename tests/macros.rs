@@ -0,0 +1,55 @@
+#![cfg(all(feature = "macros", feature = "flume-transport"))]
+use quic_rpc::{
+    declare_rpc, declare_server_streaming, declare_service, transport::flume, RpcClient, RpcServer,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Add(u64, u64);
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Countdown(u64);
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Value(u64);
+
+declare_service!(
+    /// A service that does math
+    pub MathService,
+    MathRequest { Add, Countdown },
+    MathResponse { Value },
+);
+declare_rpc!(MathService, Add, Value);
+declare_server_streaming!(MathService, Countdown, Value);
+
+#[tokio::test]
+async fn declare_service() -> anyhow::Result<()> {
+    let (server, client) = flume::channel(1);
+    let server = RpcServer::<MathService, _>::new(server);
+    let _server = server.spawn_accept_loop(|req, chan| async move {
+        match req {
+            MathRequest::Add(req) => {
+                chan.rpc(req, (), |_, Add(a, b)| async move { Value(a + b) })
+                    .await
+            }
+            MathRequest::Countdown(req) => {
+                chan.server_streaming(req, (), |_, Countdown(n)| {
+                    futures_lite::stream::iter((0..n).rev().map(Value))
+                })
+                .await
+            }
+        }
+    });
+    let client = RpcClient::<MathService, _>::new(client);
+    assert_eq!(client.rpc(Add(1, 2)).await?, Value(3));
+    let items =
+        futures_lite::StreamExt::collect::<Vec<_>>(client.server_streaming(Countdown(3)).await?)
+            .await;
+    let items = items.into_iter().collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(items, vec![Value(2), Value(1), Value(0)]);
+    assert!(matches!(
+        Add::try_from(MathRequest::from(Countdown(1))),
+        Err(MathRequest::Countdown(_))
+    ));
+    Ok(())
+}
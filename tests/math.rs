@@ -29,7 +29,7 @@ use thousands::Separable;
 use tokio_util::task::AbortOnDropHandle;
 
 /// compute the square of a number
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Sqr(pub u64);
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
@@ -191,6 +191,35 @@ impl ComputeService {
         Ok(())
     }
 
+    /// Accepts `count` requests, but never responds to them.
+    pub async fn server_unresponsive<C: Listener<ComputeService>>(
+        server: RpcServer<ComputeService, C>,
+        count: usize,
+    ) -> result::Result<(), RpcServerError<C>> {
+        let mut chans = Vec::with_capacity(count);
+        for _ in 0..count {
+            let (_req, chan) = server.accept().await?.read_first().await?;
+            chans.push(chan);
+        }
+        std::future::pending::<()>().await;
+        Ok(())
+    }
+
+    /// Serves a single sum, responding after the first update without reading the rest.
+    pub async fn server_sum_first<C: Listener<ComputeService>>(
+        server: RpcServer<ComputeService, C>,
+    ) -> result::Result<(), RpcServerError<C>> {
+        let (req, chan) = server.accept().await?.read_first().await?;
+        let ComputeRequest::Sum(req) = req else {
+            return Err(RpcServerError::UnexpectedStartMessage);
+        };
+        chan.client_streaming(req, ComputeService, |_, _, mut updates| async move {
+            let SumUpdate(first) = updates.next().await.unwrap();
+            SumResponse(first as u128)
+        })
+        .await
+    }
+
     /// Runs the service until `count` requests have been received.
     pub async fn server_bounded<C: Listener<ComputeService>>(
         server: RpcServer<ComputeService, C>,
@@ -279,7 +308,7 @@ pub async fn smoke_test<C: Connector<ComputeService>>(client: C) -> anyhow::Resu
         for i in 1..=3 {
             send.send(SumUpdate(i)).await?;
         }
-        Ok::<_, C::SendError>(())
+        Ok::<_, quic_rpc::client::UpdateError<C>>(())
     });
     let res = recv.await?;
     tracing::debug!("got response {:?}", res);
@@ -299,7 +328,7 @@ pub async fn smoke_test<C: Connector<ComputeService>>(client: C) -> anyhow::Resu
         for i in 1..=3 {
             send.send(MultiplyUpdate(i)).await?;
         }
-        Ok::<_, C::SendError>(())
+        Ok::<_, quic_rpc::client::UpdateError<C>>(())
     });
     let res: Vec<_> = recv.map(|x| x.map(|x| x.0)).try_collect().await?;
     tracing::debug!("got response {:?}", res);
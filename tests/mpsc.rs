@@ -0,0 +1,43 @@
+#![cfg(feature = "mpsc-transport")]
+#![allow(non_local_definitions)]
+mod math;
+use math::*;
+use quic_rpc::{transport::mpsc, RpcClient, RpcServer};
+use tokio_util::task::AbortOnDropHandle;
+
+#[tokio::test]
+async fn mpsc_channel_bench() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::try_init().ok();
+    let (server, client) = mpsc::channel(1);
+
+    let server = RpcServer::<ComputeService, _>::new(server);
+    let _server_handle = AbortOnDropHandle::new(tokio::spawn(ComputeService::server(server)));
+    let client = RpcClient::<ComputeService, _>::new(client);
+    bench(client, 100000).await?;
+    Ok(())
+}
+
+/// simple happy path test for all 4 patterns
+#[tokio::test]
+async fn mpsc_channel_smoke() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::try_init().ok();
+    let (server, client) = mpsc::channel(1);
+
+    let server = RpcServer::<ComputeService, _>::new(server);
+    let _server_handle = AbortOnDropHandle::new(tokio::spawn(ComputeService::server(server)));
+    smoke_test(client).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn mpsc_listener_dropped() -> anyhow::Result<()> {
+    use quic_rpc::transport::Connector;
+
+    let (server, client) = mpsc::channel::<ComputeRequest, ComputeResponse>(1);
+    drop(server);
+    assert!(matches!(
+        client.open().await,
+        Err(mpsc::OpenError::RemoteDropped)
+    ));
+    Ok(())
+}
@@ -0,0 +1,159 @@
+#![cfg(feature = "flume-transport")]
+use derive_more::{From, TryInto};
+use futures_lite::{Stream, StreamExt};
+use quic_rpc::{
+    message::Msg,
+    pattern::multi::{Error, Mode, Multi, MultiMsg, Request},
+    server::RpcServerError,
+    transport::flume,
+    RpcClient, RpcServer, Service,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone)]
+struct MultiService;
+
+impl Service for MultiService {
+    type Req = MultiRequest;
+    type Res = MultiResponse;
+}
+
+/// List the numbers below n, either all at once or one by one
+#[derive(Debug, Serialize, Deserialize)]
+pub struct List {
+    n: u64,
+}
+
+impl Msg<MultiService> for Request<List> {
+    type Pattern = Multi;
+}
+
+impl MultiMsg<MultiService> for List {
+    type Response = Vec<u64>;
+    type Item = u64;
+}
+
+/// Only supported in the rpc mode by the server
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Count;
+
+impl Msg<MultiService> for Request<Count> {
+    type Pattern = Multi;
+}
+
+impl MultiMsg<MultiService> for Count {
+    type Response = u64;
+    type Item = u64;
+
+    fn unsupported(supported: &[Mode]) -> Option<MultiResponse> {
+        Some(MultiResponse::Unsupported(supported.to_vec()))
+    }
+
+    fn is_unsupported(res: &MultiResponse) -> Option<Vec<Mode>> {
+        match res {
+            MultiResponse::Unsupported(supported) => Some(supported.clone()),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, From, TryInto)]
+pub enum MultiRequest {
+    List(Request<List>),
+    Count(Request<Count>),
+}
+
+#[derive(Debug, Serialize, Deserialize, From, TryInto)]
+pub enum MultiResponse {
+    All(Vec<u64>),
+    One(u64),
+    Unsupported(Vec<Mode>),
+}
+
+#[derive(Clone)]
+struct Handler;
+
+impl Handler {
+    async fn list(self, req: List) -> Vec<u64> {
+        (0..req.n).collect()
+    }
+
+    fn list_stream(self, req: List) -> impl Stream<Item = u64> {
+        futures_lite::stream::iter(0..req.n)
+    }
+
+    async fn count(self, _req: Count) -> u64 {
+        42
+    }
+
+    fn count_stream(self, _req: Count) -> impl Stream<Item = u64> {
+        futures_lite::stream::empty()
+    }
+}
+
+#[tokio::test]
+async fn multi_pattern() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::try_init().ok();
+    let (server, client) = flume::channel(1);
+
+    let server = RpcServer::<MultiService, _>::new(server);
+    let server_handle = tokio::task::spawn(async move {
+        loop {
+            let (req, chan) = server.accept().await?.read_first().await?;
+            let handler = Handler;
+            match req {
+                MultiRequest::List(req) => {
+                    let supported = [Mode::Rpc, Mode::ServerStreaming];
+                    chan.multi(
+                        req,
+                        &supported,
+                        handler,
+                        Handler::list,
+                        Handler::list_stream,
+                    )
+                    .await?;
+                }
+                MultiRequest::Count(req) => {
+                    chan.multi(
+                        req,
+                        &[Mode::Rpc],
+                        handler,
+                        Handler::count,
+                        Handler::count_stream,
+                    )
+                    .await?;
+                }
+            }
+        }
+        #[allow(unreachable_code)]
+        Ok(())
+    });
+    let client = RpcClient::<MultiService, _>::new(client);
+    let all = client.multi_rpc(List { n: 4 }).await?;
+    assert_eq!(all, vec![0, 1, 2, 3]);
+    let items = client
+        .multi_server_streaming(List { n: 4 })
+        .await?
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(items, vec![0, 1, 2, 3]);
+    let count = client.multi_rpc(Count).await?;
+    assert_eq!(count, 42);
+    // the server advertises the modes it supports
+    match client.multi_server_streaming(Count).await {
+        Err(Error::Unsupported {
+            mode: Mode::ServerStreaming,
+            supported,
+        }) => assert_eq!(supported, vec![Mode::Rpc]),
+        res => panic!("unexpected result {:?}", res.map(|_| ())),
+    }
+    drop(client);
+    // dropping the client will cause the server to terminate
+    match server_handle.await? {
+        Err(RpcServerError::Accept(_)) => {}
+        e => panic!("unexpected termination result {e:?}"),
+    }
+    Ok(())
+}
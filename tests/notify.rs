@@ -0,0 +1,225 @@
+#![cfg(feature = "flume-transport")]
+use derive_more::{From, TryInto};
+use quic_rpc::{
+    message::Msg,
+    pattern::notify::{Notify, NotifyMsg},
+    transport::{flume, Listener},
+    RpcClient, RpcServer, Service,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone)]
+struct TelemetryService;
+
+impl Service for TelemetryService {
+    type Req = TelemetryRequest;
+    type Res = ();
+}
+
+/// A tick, sent as a datagram if possible
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Tick(u64);
+
+impl Msg<TelemetryService> for Tick {
+    type Pattern = Notify;
+    const DATAGRAM: bool = true;
+}
+
+impl NotifyMsg<TelemetryService> for Tick {}
+
+/// A log line, always sent on a substream
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Log(String);
+
+impl Msg<TelemetryService> for Log {
+    type Pattern = Notify;
+}
+
+impl NotifyMsg<TelemetryService> for Log {}
+
+#[derive(Debug, Serialize, Deserialize, From, TryInto, PartialEq, Eq)]
+pub enum TelemetryRequest {
+    Tick(Tick),
+    Log(Log),
+}
+
+/// Accept a substream and handle the notification on it
+async fn recv_on_substream<C: Listener<In = TelemetryRequest, Out = ()>>(
+    server: &RpcServer<TelemetryService, C>,
+) -> anyhow::Result<TelemetryRequest> {
+    let (req, chan) = server.accept().await?.read_first().await?;
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    match req {
+        TelemetryRequest::Tick(tick) => {
+            chan.notify(tick, tx, |tx, tick| async move {
+                tx.send(TelemetryRequest::from(tick)).ok();
+            })
+            .await?
+        }
+        TelemetryRequest::Log(log) => {
+            chan.notify(log, tx, |tx, log| async move {
+                tx.send(TelemetryRequest::from(log)).ok();
+            })
+            .await?
+        }
+    }
+    Ok(rx.await?)
+}
+
+#[tokio::test]
+async fn notify_flume() -> anyhow::Result<()> {
+    let (listener, connector) = flume::channel(8);
+    let server = RpcServer::<TelemetryService, _>::new(listener);
+    let client = RpcClient::<TelemetryService, _>::new(connector);
+
+    // flume has no datagrams, so both go on a substream
+    client.notify(Tick(1)).await?;
+    assert_eq!(recv_on_substream(&server).await?, Tick(1).into());
+    client.notify(Log("hello".into())).await?;
+    assert_eq!(
+        recv_on_substream(&server).await?,
+        Log("hello".into()).into()
+    );
+    Ok(())
+}
+
+#[cfg(all(feature = "quinn-transport", feature = "test-utils"))]
+#[tokio::test]
+async fn notify_quinn_datagrams() -> anyhow::Result<()> {
+    use std::{
+        net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+        time::Duration,
+    };
+
+    use quic_rpc::transport::{
+        quinn::{make_client_endpoint, make_server_endpoint, QuinnConnector, QuinnListener},
+        Connector,
+    };
+
+    let (server, server_certs) =
+        make_server_endpoint(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0).into())?;
+    let server_addr: SocketAddr = server.local_addr()?;
+    let client = make_client_endpoint("0.0.0.0:0".parse()?, &[&server_certs])?;
+    let server = RpcServer::<TelemetryService, _>::new(QuinnListener::new(server)?);
+    let connector = QuinnConnector::new(client, server_addr, "localhost".into());
+    let client = RpcClient::<TelemetryService, _>::new(connector.clone());
+
+    // there is no connection yet, so the first tick establishes one on a substream
+    client.notify(Tick(0)).await?;
+    assert_eq!(server.recv_notification().await?, Tick(0).into());
+
+    // now ticks go as datagrams
+    for i in 1..10 {
+        client.notify(Tick(i)).await?;
+        let req = tokio::time::timeout(Duration::from_secs(5), server.recv_datagram()).await??;
+        assert_eq!(req, Tick(i).into());
+    }
+
+    // messages that don't opt in use a unidirectional substream
+    client.notify(Log("hello".into())).await?;
+    assert_eq!(
+        server.recv_notification().await?,
+        Log("hello".into()).into()
+    );
+
+    // too large for a datagram
+    let large = Log("x".repeat(10_000)).into();
+    assert!(connector.send_datagram(large).await?.is_some());
+    Ok(())
+}
+
+#[cfg(all(feature = "quinn-transport", feature = "test-utils"))]
+#[tokio::test]
+async fn notify_quinn_wrapped() -> anyhow::Result<()> {
+    use std::{
+        net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+        time::Duration,
+    };
+
+    use quic_rpc::{
+        middleware::{Rejection, ServerMiddleware},
+        server::RpcServerError,
+        transport::{
+            backpressure::{BackpressureConnector, BackpressureListener},
+            limit::LimitedConnector,
+            quinn::{make_client_endpoint, make_server_endpoint, QuinnConnector, QuinnListener},
+        },
+    };
+
+    /// Rejects log lines
+    #[derive(Debug)]
+    struct NoLogs;
+
+    impl ServerMiddleware<TelemetryService> for NoLogs {
+        fn on_request(&self, req: &mut TelemetryRequest) -> Result<(), Rejection<()>> {
+            match req {
+                TelemetryRequest::Log(_) => Err(Rejection::new("no logs", ())),
+                TelemetryRequest::Tick(_) => Ok(()),
+            }
+        }
+    }
+
+    let (server, server_certs) =
+        make_server_endpoint(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0).into())?;
+    let server_addr: SocketAddr = server.local_addr()?;
+    let client = make_client_endpoint("0.0.0.0:0".parse()?, &[&server_certs])?;
+    let listener = BackpressureListener::new(QuinnListener::new(server)?);
+    let server = RpcServer::<TelemetryService, _>::new(listener).with_middleware(NoLogs);
+    let connector = QuinnConnector::new(client, server_addr, "localhost".into());
+    let connector = LimitedConnector::new(BackpressureConnector::new(connector), 4);
+    let client = RpcClient::<TelemetryService, _>::new(connector);
+
+    // the wrappers pass unidirectional substreams and datagrams through
+    client.notify(Tick(0)).await?;
+    assert_eq!(server.recv_notification().await?, Tick(0).into());
+    client.notify(Tick(1)).await?;
+    let req = tokio::time::timeout(Duration::from_secs(5), server.recv_datagram()).await??;
+    assert_eq!(req, Tick(1).into());
+
+    // and the middleware sees the notifications
+    client.notify(Log("hello".into())).await?;
+    assert!(matches!(
+        server.recv_notification().await,
+        Err(RpcServerError::Rejected(reason)) if reason == "no logs"
+    ));
+    Ok(())
+}
+
+#[cfg(all(feature = "quinn-transport", feature = "test-utils"))]
+#[tokio::test]
+async fn notify_quinn_accept_loop() -> anyhow::Result<()> {
+    use std::{
+        net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+        time::Duration,
+    };
+
+    use quic_rpc::transport::quinn::{
+        make_client_endpoint, make_server_endpoint, QuinnConnector, QuinnListener,
+    };
+
+    let (server, server_certs) =
+        make_server_endpoint(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0).into())?;
+    let server_addr: SocketAddr = server.local_addr()?;
+    let client = make_client_endpoint("0.0.0.0:0".parse()?, &[&server_certs])?;
+    let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+    let _server = RpcServer::<TelemetryService, _>::new(QuinnListener::new(server)?)
+        .with_notification_handler(move |req| {
+            let tx = tx.clone();
+            async move {
+                tx.send(req).await.ok();
+            }
+        })
+        .spawn_accept_loop(|_req, _chan| async { anyhow::Ok(()) });
+    let connector = QuinnConnector::new(client, server_addr, "localhost".into());
+    let client = RpcClient::<TelemetryService, _>::new(connector);
+
+    // the first notification establishes the connection on a substream
+    client.notify(Log("hello".into())).await?;
+    let req = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await?;
+    assert_eq!(req, Some(Log("hello".into()).into()));
+    // and this one goes as a datagram
+    client.notify(Tick(1)).await?;
+    let req = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await?;
+    assert_eq!(req, Some(Tick(1).into()));
+    Ok(())
+}
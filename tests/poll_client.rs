@@ -0,0 +1,50 @@
+#![cfg(feature = "flume-transport")]
+use std::{future::poll_fn, pin::pin};
+
+mod math;
+use math::*;
+use quic_rpc::{transport::flume, RpcClient, RpcServer};
+
+#[tokio::test]
+async fn poll_client() -> anyhow::Result<()> {
+    let (server, client) = flume::channel(1);
+    let server = RpcServer::<ComputeService, _>::new(server);
+    let _server_handle = ComputeService::server(server);
+    let mut client = pin!(RpcClient::<ComputeService, _>::new(client).into_poll());
+
+    // rpc
+    let mut chan = poll_fn(|cx| client.as_mut().poll_open(cx)).await?;
+    let mut msg = Some(ComputeRequest::from(Sqr(3)));
+    poll_fn(|cx| chan.poll_send(cx, &mut msg)).await?;
+    assert!(msg.is_none());
+    let res = poll_fn(|cx| chan.poll_recv(cx)).await.unwrap()?;
+    assert!(matches!(res, ComputeResponse::SqrResponse(SqrResponse(9))));
+
+    // server streaming
+    let mut chan = poll_fn(|cx| client.as_mut().poll_open(cx)).await?;
+    let mut msg = Some(ComputeRequest::from(Fibonacci(5)));
+    poll_fn(|cx| chan.poll_send(cx, &mut msg)).await?;
+    let mut items = Vec::new();
+    while let Some(res) = poll_fn(|cx| chan.poll_recv(cx)).await {
+        match res? {
+            ComputeResponse::FibonacciResponse(FibonacciResponse(x)) => items.push(x),
+            res => panic!("unexpected response {res:?}"),
+        }
+    }
+    assert_eq!(items, vec![0, 1, 1, 2, 3]);
+
+    // client streaming, finished by closing the send side
+    let mut chan = poll_fn(|cx| client.as_mut().poll_open(cx)).await?;
+    for msg in [
+        ComputeRequest::from(Sum),
+        SumUpdate(1).into(),
+        SumUpdate(2).into(),
+    ] {
+        let mut msg = Some(msg);
+        poll_fn(|cx| chan.poll_send(cx, &mut msg)).await?;
+    }
+    poll_fn(|cx| chan.poll_close(cx)).await?;
+    let res = poll_fn(|cx| chan.poll_recv(cx)).await.unwrap()?;
+    assert!(matches!(res, ComputeResponse::SumResponse(SumResponse(3))));
+    Ok(())
+}
@@ -26,9 +26,10 @@ pub struct Endpoints {
     server_addr: SocketAddr,
 }
 
-pub fn make_endpoints(port: u16) -> anyhow::Result<Endpoints> {
-    let server_addr: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, port));
-    let (server, server_certs) = make_server_endpoint(server_addr)?;
+pub fn make_endpoints() -> anyhow::Result<Endpoints> {
+    let (server, server_certs) =
+        make_server_endpoint(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0).into())?;
+    let server_addr = server.local_addr()?;
     let client = make_client_endpoint("0.0.0.0:0".parse()?, &[&server_certs])?;
     Ok(Endpoints {
         client,
@@ -37,6 +38,14 @@ pub fn make_endpoints(port: u16) -> anyhow::Result<Endpoints> {
     })
 }
 
+/// A free local address, for servers that are started after the client
+///
+/// The port is free when this returns, but may be taken by the time the server binds it.
+fn free_addr() -> anyhow::Result<SocketAddr> {
+    let socket = std::net::UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))?;
+    Ok(socket.local_addr()?)
+}
+
 fn run_server(server: quinn::Endpoint) -> AbortOnDropHandle<()> {
     let listener = QuinnListener::new(server).unwrap();
     let listener = RpcServer::new(listener);
@@ -51,7 +60,7 @@ async fn quinn_channel_bench() -> anyhow::Result<()> {
         client,
         server,
         server_addr,
-    } = make_endpoints(12345)?;
+    } = make_endpoints()?;
     tracing::debug!("Starting server");
     let _server_handle = run_server(server);
     tracing::debug!("Starting client");
@@ -69,7 +78,7 @@ async fn quinn_channel_smoke() -> anyhow::Result<()> {
         client,
         server,
         server_addr,
-    } = make_endpoints(12346)?;
+    } = make_endpoints()?;
     let _server_handle = run_server(server);
     let client_connection =
         transport::quinn::QuinnConnector::new(client, server_addr, "localhost".into());
@@ -86,7 +95,7 @@ async fn server_away_and_back() -> TestResult<()> {
     tracing_subscriber::fmt::try_init().ok();
     tracing::info!("Creating endpoints");
 
-    let server_addr: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 12347));
+    let server_addr = free_addr()?;
     let (server_config, server_cert) = configure_server()?;
 
     // create the RPC client
@@ -125,3 +134,768 @@ async fn server_away_and_back() -> TestResult<()> {
     server_handle.abort();
     Ok(())
 }
+
+/// Test that the server can call a service on the client, over the connection
+/// that the client opened.
+#[tokio::test]
+async fn server_opened_streams() -> TestResult<()> {
+    tracing_subscriber::fmt::try_init().ok();
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+
+    // the client connects, and serves the compute service on the same connection
+    let (connector, listener) =
+        QuinnConnector::builder(client, server_addr, "localhost".into()).build_with_listener();
+    let _client_server = ComputeService::server(RpcServer::new(listener));
+    let client = RpcClient::<ComputeService, _>::new(connector);
+
+    // the server manages the connection itself, so it can open substreams on it
+    let (connections, incoming) = flume::bounded(1);
+    let _server = ComputeService::server(RpcServer::new(QuinnListener::handle_connections(
+        incoming,
+        server_addr,
+    )));
+    let open = tokio::spawn(async move {
+        let connection = server.accept().await.unwrap().await?;
+        connections.send_async(connection.clone()).await?;
+        anyhow::Ok((server, connection))
+    });
+
+    // the client calls the server
+    let SqrResponse(response) = client.rpc(Sqr(4)).await?;
+    assert_eq!(response, 16);
+
+    // the server calls the client, using the same connection
+    let (_server_endpoint, connection) = open.await??;
+    let callback = RpcClient::<ComputeService, _>::new(QuinnConnector::from_connection(connection));
+    let SqrResponse(response) = callback.rpc(Sqr(3)).await?;
+    assert_eq!(response, 9);
+    Ok(())
+}
+
+#[tokio::test]
+async fn stream_limit_reject() -> TestResult<()> {
+    use futures_lite::StreamExt;
+    use futures_util::SinkExt;
+    use quic_rpc::transport::{
+        stream_limit::{Overflow, StreamLimit},
+        Connector, Listener,
+    };
+
+    tracing_subscriber::fmt::try_init().ok();
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let listener = QuinnListener::<u64, u64>::builder()
+        .stream_limit(StreamLimit::new(2, Overflow::Reject(7)))
+        .build(server)?;
+    let connector = QuinnConnector::<u64, u64>::new(client, server_addr, "localhost".into());
+
+    // substreams only become visible to the server once the client sends something
+    let mut opened = Vec::new();
+    let mut accepted = Vec::new();
+    for i in 0..2 {
+        let (mut send, recv) = connector.open().await?;
+        send.send(i).await?;
+        opened.push((send, recv));
+        accepted.push(listener.accept().await?);
+    }
+
+    // the third substream is rejected
+    let (mut send, mut recv) = connector.open().await?;
+    send.send(2).await?;
+    assert!(matches!(recv.next().await, Some(Err(_)) | None));
+
+    // once a substream is dropped, new substreams are accepted again
+    accepted.pop();
+    let (mut send, _recv) = connector.open().await?;
+    send.send(3).await?;
+    let (_send, mut recv) = listener.accept().await?;
+    assert_eq!(recv.next().await.transpose()?, Some(3));
+    Ok(())
+}
+
+#[tokio::test]
+async fn stream_limit_backpressure() -> TestResult<()> {
+    use std::time::Duration;
+
+    use futures_lite::StreamExt;
+    use futures_util::SinkExt;
+    use quic_rpc::transport::{
+        stream_limit::{Overflow, StreamLimit},
+        Connector, Listener,
+    };
+
+    tracing_subscriber::fmt::try_init().ok();
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let listener = QuinnListener::<u64, u64>::builder()
+        .stream_limit(StreamLimit::new(1, Overflow::Backpressure))
+        .build(server)?;
+    let connector = QuinnConnector::<u64, u64>::new(client, server_addr, "localhost".into());
+
+    let (mut send1, _recv1) = connector.open().await?;
+    send1.send(1).await?;
+    let first = listener.accept().await?;
+
+    // the second substream is not accepted while the first one is in use
+    let (mut send2, _recv2) = connector.open().await?;
+    send2.send(2).await?;
+    assert!(
+        tokio::time::timeout(Duration::from_millis(200), listener.accept())
+            .await
+            .is_err()
+    );
+
+    // but it is accepted once the first one is dropped
+    drop(first);
+    let (_send, mut recv) = listener.accept().await?;
+    assert_eq!(recv.next().await.transpose()?, Some(2));
+    Ok(())
+}
+
+#[tokio::test]
+async fn path_events() -> TestResult<()> {
+    use std::time::Duration;
+
+    use futures_lite::StreamExt;
+    use quic_rpc::transport::quinn::PathEvent;
+
+    tracing_subscriber::fmt::try_init().ok();
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let server_handle = run_server(server);
+    let connector = QuinnConnector::new(client, server_addr, "localhost".into());
+    let events = connector.path_events(Duration::from_millis(10));
+    // the mtu changes while the connection discovers the path mtu
+    let mut events = events.filter(|e| !matches!(e, PathEvent::MtuChanged { .. }));
+    let client = RpcClient::<ComputeService, _>::new(connector);
+    let SqrResponse(response) = client.rpc(Sqr(4)).await?;
+    assert_eq!(response, 16);
+    assert_eq!(
+        events.next().await,
+        Some(PathEvent::Connected {
+            remote: server_addr
+        })
+    );
+
+    // stopping the server closes the connection
+    drop(server_handle);
+    let event = tokio::time::timeout(Duration::from_secs(5), events.next()).await?;
+    assert!(
+        matches!(event, Some(PathEvent::Closed { .. })),
+        "unexpected event {event:?}"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn dual_stack_listener() -> TestResult<()> {
+    use std::net::{IpAddr, Ipv6Addr};
+
+    use quic_rpc::transport::{
+        quinn::{bind_server_endpoints, dual_stack_addrs},
+        Listener, LocalAddr,
+    };
+
+    tracing_subscriber::fmt::try_init().ok();
+    let (server_config, server_cert) = configure_server()?;
+    let endpoints = bind_server_endpoints(&dual_stack_addrs(0), server_config, false)?;
+    let listener = QuinnListener::from_endpoints(endpoints)?;
+    let addrs = listener
+        .local_addr()
+        .iter()
+        .map(|addr| match addr {
+            LocalAddr::Socket(addr) => *addr,
+            other => panic!("unexpected local addr {other}"),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(addrs.len(), 2);
+    assert!(addrs[0].is_ipv4() && addrs[1].is_ipv6());
+    let port = addrs[0].port();
+    assert_eq!(addrs[1].port(), port);
+    let _server_handle = ComputeService::server(RpcServer::new(listener));
+
+    for (bind, ip) in [
+        ("0.0.0.0:0", IpAddr::V4(Ipv4Addr::LOCALHOST)),
+        ("[::]:0", IpAddr::V6(Ipv6Addr::LOCALHOST)),
+    ] {
+        let client = make_client_endpoint(bind.parse()?, &[&server_cert])?;
+        let connector = QuinnConnector::new(client, SocketAddr::new(ip, port), "localhost".into());
+        let client = RpcClient::<ComputeService, _>::new(connector);
+        let SqrResponse(response) = client.rpc(Sqr(4)).await?;
+        assert_eq!(response, 16);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn transport_settings() -> TestResult<()> {
+    use std::time::Duration;
+
+    use quic_rpc::transport::quinn::{configure_client, CongestionControl, TransportSettings};
+
+    tracing_subscriber::fmt::try_init().ok();
+    let settings = TransportSettings::default()
+        .keep_alive_interval(Duration::from_secs(1))
+        .max_idle_timeout(Duration::from_secs(10))
+        .stream_receive_window(1024 * 1024)
+        .congestion_control(CongestionControl::Bbr);
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let listener = QuinnListener::new(server)?;
+    let (mut server_config, server_cert) = configure_server()?;
+    settings.apply_to_server(&mut server_config);
+    listener.set_server_config(server_config);
+    let _server_handle = ComputeService::server(RpcServer::new(listener));
+
+    let mut client_config = configure_client(&[&server_cert])?;
+    settings.apply_to_client(&mut client_config);
+    let connector = QuinnConnector::builder(client, server_addr, "localhost".into())
+        .client_config(client_config)
+        .build();
+    smoke_test(connector).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn zero_rtt() -> TestResult<()> {
+    use std::sync::Arc;
+
+    use futures_util::SinkExt;
+    use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+
+    tracing_subscriber::fmt::try_init().ok();
+    let server_addr = free_addr()?;
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()])?;
+    let cert_der = cert.cert.der().clone();
+    let key = rustls::pki_types::PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der());
+    let mut server_crypto = rustls::ServerConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .with_no_client_auth()
+        .with_single_cert(vec![cert_der.clone()], key.into())?;
+    server_crypto.max_early_data_size = u32::MAX;
+    let server_config =
+        quinn::ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(server_crypto)?));
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(cert_der)?;
+    let mut client_crypto = rustls::ClientConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    client_crypto.enable_early_data = true;
+    let client_config =
+        quinn::ClientConfig::new(Arc::new(QuicClientConfig::try_from(client_crypto)?));
+    let mut client = Endpoint::client("127.0.0.1:0".parse()?)?;
+    client.set_default_client_config(client_config);
+    // only squaring is marked as safe
+    let connector = QuinnConnector::builder(client, server_addr, "localhost".into())
+        .zero_rtt(|msg: &ComputeRequest| matches!(msg, ComputeRequest::Sqr(_)))
+        .build();
+    let client = RpcClient::<ComputeService, _>::new(connector);
+
+    // the first connection gets a session ticket
+    let server = Endpoint::server(server_config.clone(), server_addr)?;
+    let server = RpcServer::new(QuinnListener::new(server)?);
+    let server_handle = tokio::task::spawn(ComputeService::server_bounded(server, 1));
+    let SqrResponse(response) = client.rpc(Sqr(4)).await?;
+    assert_eq!(response, 16);
+    drop(server_handle.await??);
+    tokio::time::sleep(tokio::time::Duration::from_millis(300)).await;
+
+    // the second connection can use 0-RTT
+    let server = Endpoint::server(server_config, server_addr)?;
+    let server = RpcServer::new(QuinnListener::new(server)?);
+    let server_handle = tokio::task::spawn(ComputeService::server_bounded(server, 5));
+    let SqrResponse(response) = client.rpc(Sqr(3)).await?;
+    assert_eq!(response, 9);
+    // updates are not safe, so they wait for the handshake
+    let (mut send, recv) = client.client_streaming(Sum).await?;
+    for i in 1..=3 {
+        send.send(SumUpdate(i)).await?;
+    }
+    drop(send);
+    assert_eq!(recv.await?, SumResponse(6));
+    server_handle.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn multiple_addrs() -> TestResult<()> {
+    use std::{
+        net::{IpAddr, Ipv6Addr},
+        time::Duration,
+    };
+
+    tracing_subscriber::fmt::try_init().ok();
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let _server_handle = run_server(server);
+    let addrs = vec![
+        // can not be used from an IPv4 endpoint, connect fails right away
+        SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), server_addr.port()),
+        // nobody listening, the attempt only fails after the idle timeout
+        free_addr()?,
+        server_addr,
+    ];
+    let connector = QuinnConnector::new(client, addrs, "localhost".into());
+    let client = RpcClient::<ComputeService, _>::new(connector);
+    let SqrResponse(response) =
+        tokio::time::timeout(Duration::from_secs(5), client.rpc(Sqr(4))).await??;
+    assert_eq!(response, 16);
+    Ok(())
+}
+
+#[tokio::test]
+async fn client_certificate() -> TestResult<()> {
+    use std::sync::Arc;
+
+    use quic_rpc::transport::{Listener, PeerIdentity};
+    use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+
+    tracing_subscriber::fmt::try_init().ok();
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let server_cert = rcgen::generate_simple_self_signed(vec!["localhost".into()])?;
+    let client_cert = rcgen::generate_simple_self_signed(vec!["client".into()])?;
+    let server_key =
+        rustls::pki_types::PrivatePkcs8KeyDer::from(server_cert.key_pair.serialize_der());
+    let client_key =
+        rustls::pki_types::PrivatePkcs8KeyDer::from(client_cert.key_pair.serialize_der());
+
+    // the server only accepts clients with a certificate it trusts
+    let mut client_roots = rustls::RootCertStore::empty();
+    client_roots.add(client_cert.cert.der().clone())?;
+    let verifier = rustls::server::WebPkiClientVerifier::builder_with_provider(
+        Arc::new(client_roots),
+        provider.clone(),
+    )
+    .build()?;
+    let server_crypto = rustls::ServerConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(vec![server_cert.cert.der().clone()], server_key.into())?;
+    let server_config =
+        quinn::ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(server_crypto)?));
+
+    let mut server_roots = rustls::RootCertStore::empty();
+    server_roots.add(server_cert.cert.der().clone())?;
+    let client_crypto = rustls::ClientConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .with_root_certificates(server_roots)
+        .with_client_auth_cert(vec![client_cert.cert.der().clone()], client_key.into())?;
+    let mut client = Endpoint::client("127.0.0.1:0".parse()?)?;
+    client.set_default_client_config(quinn::ClientConfig::new(Arc::new(
+        QuicClientConfig::try_from(client_crypto)?,
+    )));
+
+    let server = Endpoint::server(
+        server_config,
+        SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0).into(),
+    )?;
+    let server_addr = server.local_addr()?;
+    let server = RpcServer::<ComputeService>::new(QuinnListener::new(server)?.boxed());
+    let connector = QuinnConnector::<ComputeResponse, ComputeRequest>::new(
+        client,
+        server_addr,
+        "localhost".into(),
+    );
+    let client = RpcClient::<ComputeService, _>::new(connector);
+    let request = tokio::spawn(async move { client.rpc(Sqr(4)).await });
+    let (_, chan) = server.accept().await?.read_first().await?;
+    let Some(PeerIdentity::Certificates(certs)) = chan.peer_identity() else {
+        panic!("no client certificate");
+    };
+    assert_eq!(certs[0], client_cert.cert.der().to_vec());
+    drop(chan);
+    request.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn uni_streams() -> TestResult<()> {
+    use futures_lite::StreamExt;
+    use futures_util::SinkExt;
+    use quic_rpc::transport::{Connector, Listener};
+
+    tracing_subscriber::fmt::try_init().ok();
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let listener = QuinnListener::<u64, ()>::new(server)?.boxed();
+    let connector = QuinnConnector::<(), u64>::new(client, server_addr, "localhost".into()).boxed();
+
+    let mut send = connector.open_uni().await?;
+    send.send(1).await?;
+    send.send(2).await?;
+    send.close().await?;
+    let recv = listener.accept_uni().await?;
+    assert_eq!(recv.try_collect::<_, _, Vec<_>>().await?, vec![1, 2]);
+
+    // bidi substreams are still accepted separately
+    let (mut send, _recv) = connector.open().await?;
+    send.send(3).await?;
+    let (_, mut recv) = listener.accept().await?;
+    assert_eq!(recv.next().await.transpose()?, Some(3));
+    Ok(())
+}
+
+#[tokio::test]
+async fn rpc_then_raw_streams() -> TestResult<()> {
+    use quic_rpc::server::RpcChannel;
+
+    tracing_subscriber::fmt::try_init().ok();
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let server = RpcServer::<ComputeService, _>::new(QuinnListener::new(server)?);
+    let client = RpcClient::<ComputeService, _>::new(QuinnConnector::new(
+        client,
+        server_addr,
+        "localhost".into(),
+    ));
+    let server_task = tokio::spawn(async move {
+        let (req, chan): (_, RpcChannel<ComputeService, _>) =
+            server.accept().await?.read_first().await?;
+        let ComputeRequest::Sqr(req) = req else {
+            anyhow::bail!("unexpected request {req:?}");
+        };
+        let (send, _recv) = chan
+            .rpc_raw(req, (), |_, Sqr(x)| async move {
+                SqrResponse(x as u128 * x as u128)
+            })
+            .await?;
+        // the response and the raw bytes may arrive in the same packet
+        let mut send = send.into_inner();
+        send.write_all(b"raw bytes").await?;
+        send.finish()?;
+        send.stopped().await?;
+        anyhow::Ok(())
+    });
+    let (SqrResponse(response), _send, recv) = client.rpc_raw(Sqr(4)).await?;
+    assert_eq!(response, 16);
+    let (mut recv, buffered) = recv.into_parts();
+    let mut data = buffered.to_vec();
+    data.extend(recv.read_to_end(1024).await?);
+    assert_eq!(data, b"raw bytes");
+    server_task.await??;
+    Ok(())
+}
+
+type ClientConfigFn = Box<dyn Fn(&[&[u8]]) -> anyhow::Result<quinn::ClientConfig>>;
+
+/// Make a server endpoint that offers the given ALPNs, and a function to make
+/// client configs that offer some ALPNs
+fn make_alpn_endpoint(alpns: &[&[u8]]) -> anyhow::Result<(Endpoint, SocketAddr, ClientConfigFn)> {
+    use std::sync::Arc;
+
+    use quinn::{
+        crypto::rustls::{QuicClientConfig, QuicServerConfig},
+        rustls, ClientConfig, ServerConfig,
+    };
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()])?;
+    let cert_der = cert.cert.der().clone();
+    let key = rustls::pki_types::PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der());
+    let mut server_crypto = rustls::ServerConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .with_no_client_auth()
+        .with_single_cert(vec![cert_der.clone()], key.into())?;
+    server_crypto.alpn_protocols = alpns.iter().map(|alpn| alpn.to_vec()).collect();
+    let server_config =
+        ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(server_crypto)?));
+    let server = Endpoint::server(
+        server_config,
+        SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0).into(),
+    )?;
+    let server_addr = server.local_addr()?;
+
+    let client_config = move |alpns: &[&[u8]]| -> anyhow::Result<ClientConfig> {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(cert_der.clone())?;
+        let mut crypto = rustls::ClientConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(&[&rustls::version::TLS13])?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        crypto.alpn_protocols = alpns.iter().map(|alpn| alpn.to_vec()).collect();
+        Ok(ClientConfig::new(Arc::new(QuicClientConfig::try_from(
+            crypto,
+        )?)))
+    };
+    Ok((server, server_addr, Box::new(client_config)))
+}
+
+/// Run quic-rpc on an endpoint that also serves another protocol
+#[tokio::test]
+async fn shared_endpoint_alpn() -> TestResult<()> {
+    let (server, server_addr, client_config) = make_alpn_endpoint(&[b"rpc", b"other"])?;
+
+    let (listener, unhandled) =
+        QuinnListener::from_shared_endpoint(server.clone(), vec![b"rpc".to_vec()])?;
+    let _server_handle = ComputeService::server(RpcServer::new(listener));
+    // the other protocol just echoes a single stream
+    let _other_handle = AbortOnDropHandle::new(tokio::spawn(async move {
+        while let Ok(connection) = unhandled.recv_async().await {
+            let (mut send, mut recv) = connection.accept_bi().await?;
+            let data = recv.read_to_end(1024).await?;
+            send.write_all(&data).await?;
+            send.finish()?;
+            send.stopped().await?;
+        }
+        anyhow::Ok(())
+    }));
+
+    let mut client = Endpoint::client("0.0.0.0:0".parse()?)?;
+    client.set_default_client_config(client_config(&[b"rpc"])?);
+    let connector = QuinnConnector::new(client.clone(), server_addr, "localhost".into());
+    let rpc = RpcClient::<ComputeService, _>::new(connector);
+    assert_eq!(rpc.rpc(Sqr(3)).await?, SqrResponse(9));
+
+    let other = client
+        .connect_with(client_config(&[b"other"])?, server_addr, "localhost")?
+        .await?;
+    let (mut send, mut recv) = other.open_bi().await?;
+    send.write_all(b"hello").await?;
+    send.finish()?;
+    assert_eq!(recv.read_to_end(1024).await?, b"hello");
+    Ok(())
+}
+
+/// A codec that non-rust peers can implement easily
+#[derive(Debug, Default, Clone, Copy)]
+struct JsonCodec;
+
+impl quic_rpc::codec::Codec for JsonCodec {
+    fn serialize<T: serde::Serialize>(
+        &self,
+        item: &T,
+        dst: &mut bytes::BytesMut,
+    ) -> std::io::Result<()> {
+        use bytes::BufMut;
+        Ok(serde_json::to_writer(dst.writer(), item)?)
+    }
+
+    fn deserialize<T: serde::de::DeserializeOwned>(&self, src: &[u8]) -> std::io::Result<T> {
+        Ok(serde_json::from_slice(src)?)
+    }
+}
+
+#[tokio::test]
+async fn custom_codec() -> TestResult<()> {
+    use futures_util::SinkExt;
+    use quic_rpc::transport::{Connector, Listener};
+
+    tracing_subscriber::fmt::try_init().ok();
+    // messages are length prefixed json
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let listener = QuinnListener::<u64, ()>::new(server)?.with_codec::<JsonCodec>();
+    let connector = QuinnConnector::<(), u64>::new(client, server_addr, "localhost".into())
+        .with_codec::<JsonCodec>();
+    let (mut send, _recv) = connector.open().await?;
+    send.send(1234).await?;
+    send.close().await?;
+    let (_send, recv) = listener.accept().await?;
+    let data = recv.into_inner().read_to_end(1024).await?;
+    assert_eq!(data, b"\0\0\0\x041234");
+
+    // and the patterns work as usual
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let listener = QuinnListener::new(server)?.with_codec::<JsonCodec>();
+    let _server_handle = ComputeService::server(RpcServer::new(listener));
+    let connector =
+        QuinnConnector::new(client, server_addr, "localhost".into()).with_codec::<JsonCodec>();
+    smoke_test(connector).await?;
+    Ok(())
+}
+
+/// Serve a new codec next to the old one, selected by ALPN
+#[tokio::test]
+async fn codec_by_alpn() -> TestResult<()> {
+    tracing_subscriber::fmt::try_init().ok();
+    let (server, server_addr, client_config) = make_alpn_endpoint(&[b"compute/json", b"compute"])?;
+    let (listener, unhandled) =
+        QuinnListener::from_shared_endpoint(server.clone(), vec![b"compute/json".to_vec()])?;
+    let _json_handle = ComputeService::server(RpcServer::new(listener.with_codec::<JsonCodec>()));
+    // peers that predate the json codec use the old ALPN
+    let listener = QuinnListener::handle_connections(unhandled, server_addr);
+    let _postcard_handle = ComputeService::server(RpcServer::new(listener));
+
+    let mut client = Endpoint::client("0.0.0.0:0".parse()?)?;
+    client.set_default_client_config(client_config(&[b"compute/json"])?);
+    let connector = QuinnConnector::new(client, server_addr, "localhost".into());
+    smoke_test(connector.with_codec::<JsonCodec>()).await?;
+
+    let mut client = Endpoint::client("0.0.0.0:0".parse()?)?;
+    client.set_default_client_config(client_config(&[b"compute"])?);
+    smoke_test(QuinnConnector::new(client, server_addr, "localhost".into())).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn max_frame_length() -> TestResult<()> {
+    use futures_lite::StreamExt;
+    use futures_util::SinkExt;
+    use quic_rpc::{
+        codec::MessageTooLarge,
+        transport::{Connector, Listener},
+    };
+
+    tracing_subscriber::fmt::try_init().ok();
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let listener = QuinnListener::<Vec<u8>, ()>::new(server)?.max_recv_frame_length(1000);
+    let connector = QuinnConnector::<(), Vec<u8>>::new(client, server_addr, "localhost".into())
+        .max_send_frame_length(2000);
+
+    // too large to send, but the substream can still be used
+    let (mut send, _recv) = connector.open().await?;
+    let err = send.send(vec![0u8; 3000]).await.unwrap_err();
+    let err = MessageTooLarge::downcast(&err).expect("message too large");
+    assert_eq!(err.max, 2000);
+    assert!(err.size.unwrap() > 3000);
+    send.send(vec![1u8; 10]).await?;
+    let (_send, mut recv) = listener.accept().await?;
+    assert_eq!(recv.next().await.transpose()?, Some(vec![1u8; 10]));
+
+    // too large to receive
+    send.send(vec![2u8; 1500]).await?;
+    let err = recv.next().await.expect("an item").unwrap_err();
+    assert_eq!(
+        MessageTooLarge::downcast(&err),
+        Some(&MessageTooLarge {
+            size: None,
+            max: 1000
+        })
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn varint_framing() -> TestResult<()> {
+    use futures_util::SinkExt;
+    use quic_rpc::{
+        codec::Framing,
+        transport::{Connector, Listener},
+    };
+
+    tracing_subscriber::fmt::try_init().ok();
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let listener = QuinnListener::<u64, ()>::new(server)?.framing(Framing::Varint);
+    let connector = QuinnConnector::<(), u64>::new(client, server_addr, "localhost".into())
+        .framing(Framing::Varint);
+    let (mut send, _recv) = connector.open().await?;
+    send.send(1).await?;
+    send.close().await?;
+    let (_send, recv) = listener.accept().await?;
+    // a single byte prefix, and a single byte postcard varint
+    assert_eq!(recv.into_inner().read_to_end(1024).await?, [1, 1]);
+
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let listener = QuinnListener::new(server)?.framing(Framing::Varint);
+    let _server_handle = ComputeService::server(RpcServer::new(listener));
+    let connector =
+        QuinnConnector::new(client, server_addr, "localhost".into()).framing(Framing::Varint);
+    smoke_test(connector).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn rpc_priority() -> TestResult<()> {
+    use quic_rpc::transport::Connector;
+
+    tracing_subscriber::fmt::try_init().ok();
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let _server_handle = run_server(server);
+    let connector = QuinnConnector::<ComputeResponse, ComputeRequest>::new(
+        client,
+        server_addr,
+        "localhost".into(),
+    );
+    // the priority is set on the underlying QUIC stream
+    let (send, _recv) = connector.open_with_priority(7).await?;
+    assert_eq!(send.into_inner().priority()?, 7);
+
+    let client = RpcClient::<ComputeService, _>::new(connector);
+    let SqrResponse(x) = client.rpc_with_priority(Sqr(4), 1).await?;
+    assert_eq!(x, 16);
+    Ok(())
+}
+
+#[tokio::test]
+async fn connection_events() -> TestResult<()> {
+    use std::time::Duration;
+
+    use futures_lite::StreamExt;
+    use quic_rpc::transport::{ConnectionEvent, ConnectionEvents};
+
+    tracing_subscriber::fmt::try_init().ok();
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let server_handle = run_server(server);
+    let connector = QuinnConnector::new(client, server_addr, "localhost".into());
+    let mut events = connector.connection_event_stream();
+    let client = RpcClient::<ComputeService, _>::new(connector.clone());
+    let SqrResponse(response) = client.rpc(Sqr(4)).await?;
+    assert_eq!(response, 16);
+    assert!(matches!(
+        *connector.connection_events().borrow(),
+        ConnectionEvent::Connected
+    ));
+
+    // stopping the server closes the connection
+    drop(server_handle);
+    let disconnected = events.find(|e| matches!(e, ConnectionEvent::Disconnected(_)));
+    tokio::time::timeout(Duration::from_secs(5), disconnected)
+        .await?
+        .expect("disconnected event");
+    Ok(())
+}
@@ -0,0 +1,102 @@
+//! Run a server and client on a runtime other than tokio
+#![cfg(all(
+    feature = "flume-transport",
+    not(feature = "rt-tokio"),
+    any(feature = "rt-smol", feature = "rt-async-std")
+))]
+use derive_more::{From, TryInto};
+use futures_lite::StreamExt;
+use quic_rpc::{
+    message::{Msg, RpcMsg},
+    pattern::server_streaming::{ServerStreaming, ServerStreamingMsg},
+    server::RpcChannel,
+    transport::flume,
+    Listener, RpcClient, RpcServer, Service,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone)]
+struct CountService;
+
+impl Service for CountService {
+    type Req = CountRequest;
+    type Res = CountResponse;
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Add(u64, u64);
+
+impl RpcMsg<CountService> for Add {
+    type Response = u64;
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Count(u64);
+
+impl Msg<CountService> for Count {
+    type Pattern = ServerStreaming;
+}
+
+impl ServerStreamingMsg<CountService> for Count {
+    type Response = u64;
+}
+
+#[derive(Debug, Serialize, Deserialize, From, TryInto)]
+enum CountRequest {
+    Add(Add),
+    Count(Count),
+}
+
+#[derive(Debug, Serialize, Deserialize, From, TryInto)]
+enum CountResponse {
+    Value(u64),
+}
+
+async fn handle<C: Listener<CountService>>(
+    req: CountRequest,
+    chan: RpcChannel<CountService, C>,
+) -> anyhow::Result<()> {
+    match req {
+        CountRequest::Add(req) => chan.rpc(req, (), |_, Add(a, b)| async move { a + b }).await,
+        CountRequest::Count(req) => {
+            chan.server_streaming(req, (), |_, Count(n)| futures_lite::stream::iter(0..n))
+                .await
+        }
+    }?;
+    Ok(())
+}
+
+async fn roundtrip() -> anyhow::Result<()> {
+    let (server, client) = flume::channel(1);
+    let server = RpcServer::<CountService, _>::new(server);
+    let server = futures_util::FutureExt::boxed(server.accept_loop(handle));
+    let client = RpcClient::<CountService, _>::new(client);
+    let test = async move {
+        assert_eq!(client.rpc(Add(1, 2)).await?, 3);
+        let items: Vec<u64> = client
+            .server_streaming(Count(10))
+            .await?
+            .map(|x| x.unwrap())
+            .collect()
+            .await;
+        assert_eq!(items, (0..10).collect::<Vec<_>>());
+        anyhow::Ok(())
+    };
+    futures_lite::future::or(test, async move {
+        server.await;
+        anyhow::bail!("accept loop stopped")
+    })
+    .await
+}
+
+#[cfg(feature = "rt-smol")]
+#[test]
+fn smol() -> anyhow::Result<()> {
+    smol::block_on(roundtrip())
+}
+
+#[cfg(all(not(feature = "rt-smol"), feature = "rt-async-std"))]
+#[test]
+fn async_std() -> anyhow::Result<()> {
+    async_std::task::block_on(roundtrip())
+}
@@ -0,0 +1,78 @@
+#![cfg(feature = "flume-transport")]
+use derive_more::{From, TryInto};
+use futures_lite::StreamExt;
+use quic_rpc::{
+    message::{Msg, ServerStreaming, ServerStreamingMsg},
+    session::Session,
+    transport::flume,
+    RpcClient, RpcServer, Service,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone)]
+struct CountService;
+
+impl Service for CountService {
+    type Req = CountRequest;
+    type Res = CountResponse;
+}
+
+/// Count up from start, but only ever send 3 items per stream
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Count {
+    start: u64,
+}
+
+impl Msg<CountService> for Count {
+    type Pattern = ServerStreaming;
+}
+
+impl ServerStreamingMsg<CountService> for Count {
+    type Response = u64;
+}
+
+#[derive(Debug, Serialize, Deserialize, From, TryInto)]
+pub enum CountRequest {
+    Count(Count),
+}
+
+#[derive(Debug, Serialize, Deserialize, From, TryInto)]
+pub enum CountResponse {
+    Count(u64),
+}
+
+#[tokio::test]
+async fn subscription_resumes() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::try_init().ok();
+    let (server, client) = flume::channel(1);
+    let server = RpcServer::<CountService, _>::new(server);
+    let _server = tokio::spawn(async move {
+        loop {
+            let (req, chan) = server.accept().await?.read_first().await?;
+            match req {
+                CountRequest::Count(req) => {
+                    chan.server_streaming(req, (), |_, req| {
+                        futures_lite::stream::iter(req.start..req.start + 3)
+                    })
+                    .await?;
+                }
+            }
+        }
+        #[allow(unreachable_code)]
+        anyhow::Ok(())
+    });
+    let client = RpcClient::<CountService, _>::new(client);
+    let session = Session::new(client);
+    let mut subscription = session.subscribe(|last: Option<&u64>| {
+        // resume after the last item we got
+        let start = last.map(|x| x + 1).unwrap_or(0);
+        (start < 8).then_some(Count { start })
+    });
+    let mut items = Vec::new();
+    while let Some(item) = subscription.next().await {
+        items.push(item);
+    }
+    assert_eq!(items, (0..9).collect::<Vec<_>>());
+    assert_eq!(subscription.resumed(), 2);
+    Ok(())
+}
@@ -0,0 +1,190 @@
+#![cfg(feature = "flume-transport")]
+use std::time::Duration;
+
+use derive_more::{From, TryInto};
+use futures_lite::StreamExt;
+use quic_rpc::{
+    message::Msg,
+    pattern::server_streaming::{ServerStreaming, ServerStreamingMsg, SlowConsumer},
+    server::RpcServerError,
+    transport::flume,
+    RpcClient, RpcServer, Service,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+/// Number of items the handler produces, much more than the flume transport buffers
+const N: u64 = 1000;
+
+#[derive(Debug, Clone)]
+struct SlowService;
+
+impl Service for SlowService {
+    type Req = SlowRequest;
+    type Res = SlowResponse;
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Blocking;
+
+impl Msg<SlowService> for Blocking {
+    type Pattern = ServerStreaming;
+}
+
+impl ServerStreamingMsg<SlowService> for Blocking {
+    type Response = u64;
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Tick {
+    Item(u64),
+    Lagged(u64),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Dropping;
+
+impl Msg<SlowService> for Dropping {
+    type Pattern = ServerStreaming;
+}
+
+impl ServerStreamingMsg<SlowService> for Dropping {
+    type Response = Tick;
+
+    fn slow_consumer() -> SlowConsumer<Tick> {
+        SlowConsumer::DropOldest {
+            buffer: 4,
+            lagged: Tick::Lagged,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TooSlow;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Terminating;
+
+impl Msg<SlowService> for Terminating {
+    type Pattern = ServerStreaming;
+}
+
+impl ServerStreamingMsg<SlowService> for Terminating {
+    type Response = Result<u64, TooSlow>;
+
+    fn slow_consumer() -> SlowConsumer<Self::Response> {
+        SlowConsumer::Terminate {
+            buffer: 4,
+            too_slow: || Err(TooSlow),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, From, TryInto)]
+pub enum SlowRequest {
+    Blocking(Blocking),
+    Dropping(Dropping),
+    Terminating(Terminating),
+}
+
+#[derive(Debug, Serialize, Deserialize, From, TryInto)]
+pub enum SlowResponse {
+    Item(u64),
+    Tick(Tick),
+    Terminating(Result<u64, TooSlow>),
+}
+
+type Outcome = Result<(), RpcServerError<flume::FlumeListener<SlowRequest, SlowResponse>>>;
+
+/// Run a server, reporting the outcome of every request
+fn spawn_server() -> (
+    RpcClient<SlowService, flume::FlumeConnector<SlowResponse, SlowRequest>>,
+    mpsc::UnboundedReceiver<Outcome>,
+) {
+    let (server, client) = flume::channel(1);
+    let server = RpcServer::<SlowService, _>::new(server);
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Ok(accepting) = server.accept().await {
+            let (req, chan) = accepting.read_first().await?;
+            let items = || futures_lite::stream::iter(0..N);
+            let res = match req {
+                SlowRequest::Blocking(req) => {
+                    chan.server_streaming(req, (), move |_, _| items()).await
+                }
+                SlowRequest::Dropping(req) => {
+                    chan.server_streaming(req, (), move |_, _| items().map(Tick::Item))
+                        .await
+                }
+                SlowRequest::Terminating(req) => {
+                    chan.server_streaming(req, (), move |_, _| items().map(Ok))
+                        .await
+                }
+            };
+            tx.send(res).ok();
+        }
+        anyhow::Ok(())
+    });
+    (RpcClient::new(client), rx)
+}
+
+/// Give the handler time to produce all items while the client does not read
+async fn stall() {
+    tokio::time::sleep(Duration::from_millis(50)).await;
+}
+
+#[tokio::test]
+async fn block() -> anyhow::Result<()> {
+    let (client, mut outcomes) = spawn_server();
+    let mut items = client.server_streaming(Blocking).await?;
+    stall().await;
+    let mut received = Vec::new();
+    while let Some(item) = items.next().await {
+        received.push(item?);
+    }
+    assert_eq!(received, (0..N).collect::<Vec<_>>());
+    assert!(outcomes.recv().await.unwrap().is_ok());
+    Ok(())
+}
+
+#[tokio::test]
+async fn drop_oldest() -> anyhow::Result<()> {
+    let (client, mut outcomes) = spawn_server();
+    let mut items = client.server_streaming(Dropping).await?;
+    stall().await;
+    let mut received = Vec::new();
+    let mut lagged = 0;
+    while let Some(item) = items.next().await {
+        match item? {
+            Tick::Item(i) => received.push(i),
+            Tick::Lagged(n) => {
+                assert!(n > 0);
+                lagged += n;
+            }
+        }
+    }
+    assert!(lagged > 0);
+    assert_eq!(received.len() as u64 + lagged, N);
+    assert!(received.windows(2).all(|w| w[0] < w[1]));
+    assert_eq!(received.last(), Some(&(N - 1)));
+    assert!(outcomes.recv().await.unwrap().is_ok());
+    Ok(())
+}
+
+#[tokio::test]
+async fn terminate() -> anyhow::Result<()> {
+    let (client, mut outcomes) = spawn_server();
+    let mut items = client.server_streaming(Terminating).await?;
+    stall().await;
+    let mut received = Vec::new();
+    while let Some(item) = items.next().await {
+        received.push(item?);
+    }
+    assert_eq!(received.last(), Some(&Err(TooSlow)));
+    assert!((received.len() as u64) < N);
+    assert!(matches!(
+        outcomes.recv().await.unwrap(),
+        Err(RpcServerError::SlowConsumer)
+    ));
+    Ok(())
+}
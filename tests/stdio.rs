@@ -0,0 +1,44 @@
+//! The test spawns itself as the child process, so it uses a custom main
+use quic_rpc::{transport::stdio, RpcServer};
+use tokio::process::Command;
+
+mod math;
+use math::*;
+mod util;
+
+const CHILD_ENV: &str = "QUIC_RPC_STDIO_CHILD";
+
+/// Serve the compute service on stdio until the parent closes the connection
+async fn child() -> anyhow::Result<()> {
+    let server = RpcServer::<ComputeService, _>::new(stdio::parent());
+    while let Ok(accepting) = server.accept().await {
+        let (req, chan) = accepting.read_first().await?;
+        tokio::spawn(ComputeService.handle_rpc_request(req, chan));
+    }
+    Ok(())
+}
+
+async fn parent() -> anyhow::Result<()> {
+    let mut command = Command::new(std::env::current_exe()?);
+    command.env(CHILD_ENV, "1").kill_on_drop(true);
+    let (connection, mut child) = stdio::spawn(&mut command)?;
+    smoke_test(connection).await?;
+    // closing the connection makes the child exit
+    let status = child.wait().await?;
+    assert!(status.success(), "child failed: {status}");
+    println!("stdio smoke test ok");
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .try_init()
+        .ok();
+    if std::env::var_os(CHILD_ENV).is_some() {
+        child().await
+    } else {
+        parent().await
+    }
+}
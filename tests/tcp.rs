@@ -0,0 +1,63 @@
+#![cfg(feature = "tcp-transport")]
+use std::net::SocketAddr;
+
+use quic_rpc::{
+    transport::{
+        tcp::{TcpConnector, TcpListener},
+        Connector, Listener, LocalAddr,
+    },
+    RpcClient, RpcServer,
+};
+use tokio_util::task::AbortOnDropHandle;
+
+mod math;
+use math::*;
+mod util;
+
+async fn run_server() -> anyhow::Result<(AbortOnDropHandle<()>, SocketAddr)> {
+    let listener = TcpListener::serve("127.0.0.1:0".parse()?).await?;
+    let LocalAddr::Socket(addr) = listener.local_addr()[0] else {
+        anyhow::bail!("unexpected local addr");
+    };
+    let server = RpcServer::new(listener);
+    Ok((ComputeService::server(server), addr))
+}
+
+#[tokio::test]
+async fn tcp_channel_smoke() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::try_init().ok();
+    let (_server_handle, addr) = run_server().await?;
+    let client = TcpConnector::new(addr);
+    smoke_test(client).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn tcp_channel_boxed() -> anyhow::Result<()> {
+    let listener = TcpListener::serve("127.0.0.1:0".parse()?).await?;
+    let LocalAddr::Socket(addr) = listener.local_addr()[0] else {
+        anyhow::bail!("unexpected local addr");
+    };
+    let _server_handle = ComputeService::server(RpcServer::new(listener.boxed()));
+    smoke_test(TcpConnector::new(addr).boxed()).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn tcp_channel_bench() -> anyhow::Result<()> {
+    let (_server_handle, addr) = run_server().await?;
+    let client = TcpConnector::new(addr);
+    let client = RpcClient::new(client);
+    bench(client, 50000).await?;
+    Ok(())
+}
+
+#[cfg(feature = "testkit")]
+#[tokio::test]
+async fn tcp_channel_testkit() -> anyhow::Result<()> {
+    let listener = TcpListener::serve("127.0.0.1:0".parse()?).await?;
+    let LocalAddr::Socket(addr) = listener.local_addr()[0] else {
+        anyhow::bail!("unexpected local addr");
+    };
+    quic_rpc::transport::testkit::run(TcpConnector::new(addr), listener).await
+}
@@ -2,8 +2,11 @@
 use derive_more::{From, TryInto};
 use futures_lite::{Stream, StreamExt};
 use quic_rpc::{
-    message::Msg,
-    pattern::try_server_streaming::{StreamCreated, TryServerStreaming, TryServerStreamingMsg},
+    message::{Msg, RpcMsg},
+    pattern::{
+        rpc::TryError,
+        try_server_streaming::{StreamCreated, TryServerStreaming, TryServerStreamingMsg},
+    },
     server::RpcServerError,
     transport::flume,
     RpcClient, RpcServer, Service,
@@ -33,16 +36,31 @@ impl TryServerStreamingMsg<TryService> for StreamN {
     type CreateError = String;
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Half {
+    n: u64,
+}
+
+/// application error, serialized and sent to the client
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct OddError(u64);
+
+impl RpcMsg<TryService> for Half {
+    type Response = std::result::Result<u64, OddError>;
+}
+
 /// request enum
 #[derive(Debug, Serialize, Deserialize, From, TryInto)]
 pub enum TryRequest {
     StreamN(StreamN),
+    Half(Half),
 }
 
 #[derive(Debug, Serialize, Deserialize, From, TryInto, Clone)]
 pub enum TryResponse {
     StreamN(std::result::Result<u64, String>),
     StreamNError(std::result::Result<StreamCreated, String>),
+    Half(std::result::Result<u64, OddError>),
 }
 
 #[derive(Clone)]
@@ -67,6 +85,13 @@ impl Handler {
         };
         Ok(stream)
     }
+
+    async fn half(self, req: Half) -> std::result::Result<u64, OddError> {
+        if req.n % 2 != 0 {
+            return Err(OddError(req.n));
+        }
+        Ok(req.n / 2)
+    }
 }
 
 #[tokio::test]
@@ -84,6 +109,9 @@ async fn try_server_streaming() -> anyhow::Result<()> {
                     chan.try_server_streaming(req, handler, Handler::try_stream_n)
                         .await?;
                 }
+                TryRequest::Half(req) => {
+                    chan.rpc(req, handler, Handler::half).await?;
+                }
             }
         }
         #[allow(unreachable_code)]
@@ -93,6 +121,10 @@ async fn try_server_streaming() -> anyhow::Result<()> {
     let stream_n = client.try_server_streaming(StreamN { n: 10 }).await?;
     let items: Vec<_> = stream_n.collect().await;
     println!("{:?}", items);
+    let res = client.try_rpc(Half { n: 10 }).await?;
+    assert_eq!(res, 5);
+    let res = client.try_rpc(Half { n: 3 }).await;
+    assert!(matches!(res, Err(TryError::Application(OddError(3)))));
     drop(client);
     // dropping the client will cause the server to terminate
     match server_handle.await? {
@@ -101,3 +133,49 @@ async fn try_server_streaming() -> anyhow::Result<()> {
     }
     Ok(())
 }
+
+#[tokio::test]
+async fn try_server_streaming_default_timeout() -> anyhow::Result<()> {
+    use std::time::Duration;
+
+    use quic_rpc::pattern::try_server_streaming::Error;
+
+    let (server, client) = flume::channel(1);
+    let server = RpcServer::<TryService, _>::new(server);
+    let server_handle = tokio::task::spawn(async move {
+        // accept the request, but never create the stream
+        let (_req, _chan) = server.accept().await?.read_first().await?;
+        std::future::pending::<()>().await;
+        anyhow::Ok(())
+    });
+    let client = RpcClient::<TryService, _>::builder(client)
+        .default_timeout(Duration::from_millis(10))
+        .build();
+    let res = client.try_server_streaming(StreamN { n: 10 }).await;
+    assert!(matches!(res, Err(Error::Timeout)));
+    server_handle.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn try_server_streaming_timeout() -> anyhow::Result<()> {
+    use std::time::Duration;
+
+    use quic_rpc::pattern::try_server_streaming::Error;
+
+    let (server, client) = flume::channel(1);
+    let server = RpcServer::<TryService, _>::new(server);
+    let server_handle = tokio::task::spawn(async move {
+        // accept the request, but never create the stream
+        let (_req, _chan) = server.accept().await?.read_first().await?;
+        std::future::pending::<()>().await;
+        anyhow::Ok(())
+    });
+    let client = RpcClient::<TryService, _>::new(client);
+    let res = client
+        .try_server_streaming_timeout(StreamN { n: 10 }, Duration::from_millis(10))
+        .await;
+    assert!(matches!(res, Err(Error::Timeout)));
+    server_handle.abort();
+    Ok(())
+}
@@ -0,0 +1,54 @@
+#![cfg(feature = "websocket-transport")]
+use std::net::SocketAddr;
+
+use quic_rpc::{
+    transport::{
+        websocket::{WebSocketConnector, WebSocketListener},
+        Listener, LocalAddr,
+    },
+    RpcClient, RpcServer,
+};
+use tokio_util::task::AbortOnDropHandle;
+
+mod math;
+use math::*;
+mod util;
+
+async fn run_server() -> anyhow::Result<(AbortOnDropHandle<()>, SocketAddr)> {
+    let listener = WebSocketListener::serve("127.0.0.1:0".parse()?).await?;
+    let LocalAddr::Socket(addr) = listener.local_addr()[0] else {
+        anyhow::bail!("unexpected local addr");
+    };
+    let server = RpcServer::new(listener);
+    Ok((ComputeService::server(server), addr))
+}
+
+#[tokio::test]
+async fn websocket_channel_smoke() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::try_init().ok();
+    let (_server_handle, addr) = run_server().await?;
+    let client = WebSocketConnector::new(format!("ws://{addr}"));
+    smoke_test(client).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn websocket_channel_bench() -> anyhow::Result<()> {
+    let (_server_handle, addr) = run_server().await?;
+    let client = WebSocketConnector::new(format!("ws://{addr}"));
+    let client = RpcClient::new(client);
+    // every rpc is a new connection, so keep this small
+    bench(client, 1000).await?;
+    Ok(())
+}
+
+#[cfg(feature = "testkit")]
+#[tokio::test]
+async fn websocket_channel_testkit() -> anyhow::Result<()> {
+    let listener = WebSocketListener::serve("127.0.0.1:0".parse()?).await?;
+    let LocalAddr::Socket(addr) = listener.local_addr()[0] else {
+        anyhow::bail!("unexpected local addr");
+    };
+    let connector = WebSocketConnector::new(format!("ws://{addr}"));
+    quic_rpc::transport::testkit::run(connector, listener).await
+}